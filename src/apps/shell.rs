@@ -1,18 +1,34 @@
 // Simple interactive shell for file operations
 
-use crate::system::fs::filesystem::SimpleFilesystem;
+use crate::system::archive::{self, tar, zip};
+use crate::system::fs::{self, vfs};
 use crate::kernel::uart_write_string;
 use crate::gui::widgets::console;
+use crate::kernel::executor::{self, TaskId};
+use core::future::Future;
 extern crate alloc;
 
 const MAX_COMMAND_LEN: usize = 128;
+/// Sectors per read in `diskspeed` (1 MB)
+const DISKSPEED_CHUNK_SECTORS: u64 = 2048;
+
+/// What a passphrase typed at the shell is for
+enum PassphrasePrompt {
+    /// Unlock the encrypted device and mount it
+    Unlock(usize),
+    /// Encrypt the device; asked twice
+    Format(usize),
+    Confirm(usize, alloc::string::String),
+}
 
 pub struct Shell {
     command_buffer: [u8; MAX_COMMAND_LEN],
     cursor_pos: usize,
-    pub filesystem: Option<SimpleFilesystem>,
-    pub device_index: Option<usize>,
+    cwd: alloc::string::String, // Current directory, an absolute VFS path
     console_id: usize, // ID of the console instance for this shell
+    job: Option<TaskId>, // Background network command; holds the prompt until done
+    passphrase: Option<PassphrasePrompt>, // Input is a passphrase, echoed as '*'
+    remote: bool, // Output also goes to the host over the shell port
 }
 
 impl Shell {
@@ -20,9 +36,11 @@ impl Shell {
         Shell {
             command_buffer: [0; MAX_COMMAND_LEN],
             cursor_pos: 0,
-            filesystem: None,
-            device_index: None,
+            cwd: vfs::home(),
             console_id,
+            job: None,
+            passphrase: None,
+            remote: false,
         }
     }
 
@@ -30,26 +48,51 @@ impl Shell {
     fn write_output(&self, s: &str) {
         uart_write_string(s); // Keep UART for debugging
         console::write_string(self.console_id, s); // Display in GUI
+        if self.remote {
+            crate::kernel::ports::shell_output(s.as_bytes());
+        }
     }
 
-    pub fn set_filesystem(&mut self, fs: SimpleFilesystem, device_idx: usize) {
-        self.filesystem = Some(fs);
-        self.device_index = Some(device_idx);
+    /// Absolute VFS path of a command argument
+    fn path(&self, arg: &str) -> alloc::string::String {
+        vfs::join(&self.cwd, arg)
     }
 
     pub fn show_prompt(&self) {
-        self.write_output("> ");
+        self.write_output(&alloc::format!("{}> ", self.cwd));
+    }
+
+    /// Run a network or disk command as an executor task. Keystrokes are ignored and
+    /// the prompt held back until it finishes, but the GUI keeps running.
+    fn spawn_job(&mut self, job: impl Future<Output = ()> + 'static) {
+        let console_id = self.console_id;
+        self.job = Some(executor::spawn(async move {
+            job.await;
+            if let Some(shell) = shell_for_console(console_id) {
+                shell.job = None;
+                shell.show_prompt();
+            }
+        }));
     }
 
     pub fn handle_char(&mut self, ch: u8) {
+        if self.job.is_some() {
+            return;
+        }
+
         match ch {
             b'\n' | b'\r' => {
                 // Execute command
                 self.write_output("\r\n");
-                self.execute_command();
+                match self.passphrase.take() {
+                    Some(prompt) => self.answer_passphrase(prompt),
+                    None => self.execute_command(),
+                }
                 self.cursor_pos = 0;
                 self.command_buffer = [0; MAX_COMMAND_LEN];
-                self.show_prompt();
+                if self.job.is_none() && self.passphrase.is_none() {
+                    self.show_prompt();
+                }
             }
             8 | 127 => {
                 // Backspace
@@ -65,10 +108,14 @@ impl Shell {
                     self.command_buffer[self.cursor_pos] = ch;
                     self.cursor_pos += 1;
                     // Echo the character to UART and GUI
+                    let echo = if self.passphrase.is_some() { b'*' } else { ch };
                     unsafe {
-                        core::ptr::write_volatile(0x09000000 as *mut u8, ch);
+                        core::ptr::write_volatile(0x09000000 as *mut u8, echo);
+                    }
+                    console::write_char(self.console_id, echo);
+                    if self.remote {
+                        crate::kernel::ports::shell_output(&[echo]);
                     }
-                    console::write_char(self.console_id, ch);
                 }
             }
         }
@@ -94,15 +141,40 @@ impl Shell {
 
         match parts[0] {
             "help" => self.cmd_help(),
-            "ls" => self.cmd_ls(),
+            "ls" => self.cmd_ls(&parts),
+            "stat" => self.cmd_stat(&parts),
+            "attrib" => self.cmd_attrib(&parts),
+            "lsblk" => self.cmd_lsblk(),
+            "cd" => self.cmd_cd(&parts),
+            "pwd" => self.cmd_pwd(),
+            "mount" => self.cmd_mount(&parts),
+            "umount" => self.cmd_umount(&parts),
+            "fsck" => self.cmd_fsck(&parts),
+            "df" => self.cmd_df(),
+            "defrag" => self.cmd_defrag(&parts),
+            "snapshot" => self.cmd_snapshot(&parts),
+            "crypt" => self.cmd_crypt(&parts),
+            "sync" => self.cmd_sync(),
+            "cache" => self.cmd_cache(&parts),
+            "diskspeed" => self.cmd_diskspeed(&parts),
             "cat" => self.cmd_cat(&parts),
             "create" => self.cmd_create(&parts),
+            "mkdir" => self.cmd_mkdir(&parts),
             "rm" => self.cmd_rm(&parts),
             "rename" | "mv" => self.cmd_rename(&parts),
-            "write" => self.cmd_write(&parts),
+            "write" | "append" => self.cmd_write(&parts),
+            "truncate" => self.cmd_truncate(&parts),
+            "hexdump" => self.cmd_hexdump(&parts),
             "edit" => self.cmd_edit(&parts),
+            "tar" => self.cmd_tar(&parts),
+            "gunzip" => self.cmd_gunzip(&parts),
+            "unzip" => self.cmd_unzip(&parts),
             "clear" => self.cmd_clear(),
             "setfont" => self.cmd_setfont(&parts),
+            "date" => self.cmd_date(&parts),
+            "tz" => self.cmd_tz(&parts),
+            "locale" => self.cmd_locale(&parts),
+            "uptime" => self.cmd_uptime(),
             "ifconfig" => self.cmd_ifconfig(),
             "ping" => self.cmd_ping(&parts),
             "arp" => self.cmd_arp(),
@@ -119,15 +191,47 @@ impl Shell {
 
     fn cmd_help(&self) {
         self.write_output("Available commands:\r\n");
-        self.write_output("  ls                    - List files\r\n");
+        self.write_output("  ls [-l] [-a] [dir]    - List files (-l details, -a hidden files too)\r\n");
+        self.write_output("  stat <path>           - Show size, type, times and attributes\r\n");
+        self.write_output("  attrib [+-rhs] <path> - Show or change read-only/hidden/system\r\n");
+        self.write_output("  cd [dir]              - Change directory (volumes are /disk0, /disk1, ...; also /tmp, /proc, /dev)\r\n");
+        self.write_output("  pwd                   - Show current directory\r\n");
+        self.write_output("  lsblk                 - List disks and partitions\r\n");
+        self.write_output("  mount [device]        - List mounts or mount a device (asks for the passphrase if encrypted)\r\n");
+        self.write_output("  crypt format <device> - Encrypt a device (erases it) and put a new filesystem on it\r\n");
+        self.write_output("  crypt open <device>   - Unlock an encrypted device and mount it\r\n");
+        self.write_output("  umount <volume>       - Unmount a volume\r\n");
+        self.write_output("  fsck [-n] <volume>    - Check and repair a volume (-n: check only)\r\n");
+        self.write_output("  df                    - Show free space and fragmentation per volume\r\n");
+        self.write_output("  defrag <volume>       - Make files and free space contiguous\r\n");
+        self.write_output("  snapshot list <volume> - List a volume's snapshots (browse them in /<volume>/.snapshots)\r\n");
+        self.write_output("  snapshot create|restore|delete <volume> <name> - Take, roll back to or remove a snapshot\r\n");
+        self.write_output("  sync                  - Write cached disk blocks to disk\r\n");
+        self.write_output("  cache [size <KB>]     - Show block cache stats or resize it\r\n");
+        self.write_output("  diskspeed <disk> [MB] - Time raw reads from a disk, bypassing the cache (default 16 MB)\r\n");
         self.write_output("  cat <filename>        - Show file contents\r\n");
         self.write_output("  create <name> <size>  - Create a file\r\n");
+        self.write_output("  mkdir <dir>           - Create a directory\r\n");
         self.write_output("  rm <filename>         - Delete a file\r\n");
         self.write_output("  rename <old> <new>    - Rename a file\r\n");
         self.write_output("  write <file> <text>   - Write text to file\r\n");
+        self.write_output("  append <file> <text>  - Add text to the end of a file\r\n");
+        self.write_output("  truncate <file> <n>   - Resize a file to n bytes\r\n");
+        self.write_output("  hexdump <file> [off]  - Show 256 bytes of a file in hex (negative off: from the end)\r\n");
         self.write_output("  edit <filename>       - Open file in editor\r\n");
+        self.write_output("  tar -c <tar> <paths>  - Pack files and directories into a .tar\r\n");
+        self.write_output("  tar -x <tar> [dir]    - Unpack a .tar/.tar.gz/.tgz here or into dir\r\n");
+        self.write_output("  tar -t <tar>          - List what a .tar/.tar.gz/.tgz holds\r\n");
+        self.write_output("  gunzip <file> [dest]  - Decompress a .gz file, keeping the original\r\n");
+        self.write_output("  unzip <zip> [dir]     - Extract a .zip here or into dir\r\n");
+        self.write_output("  unzip -l <zip>        - List what a .zip holds\r\n");
         self.write_output("  clear                 - Clear screen\r\n");
         self.write_output("  setfont <mode>        - Set font (ttf, bitmap, auto)\r\n");
+        self.write_output("  date [set <time>]     - Show or set date/time (UTC, YYYY-MM-DD HH:MM:SS)\r\n");
+        self.write_output("  date adjust <+/-s>    - Step the clock by N seconds\r\n");
+        self.write_output("  tz [zone|list]        - Show or set timezone (name or POSIX TZ rule)\r\n");
+        self.write_output("  locale [name|list]    - Show or set date/time format locale\r\n");
+        self.write_output("  uptime                - Time since boot\r\n");
         self.write_output("\r\nNetwork commands:\r\n");
         self.write_output("  ifconfig              - Show network configuration\r\n");
         self.write_output("  ping <ip>             - Ping a host (e.g. ping 8.8.8.8)\r\n");
@@ -138,69 +242,579 @@ impl Shell {
         self.write_output("  help                  - Show this help\r\n");
     }
 
-    fn cmd_ls(&mut self) {
-        if let Some(ref fs) = self.filesystem {
-            let files = fs.list_files();
-            if files.is_empty() {
-                self.write_output("No files\r\n");
-            } else {
+    fn cmd_ls(&mut self, parts: &[&str]) {
+        let (flags, args): (alloc::vec::Vec<&str>, alloc::vec::Vec<&str>) =
+            parts[1..].iter().partition(|part| part.starts_with('-'));
+        let long = flags.iter().any(|flag| flag.contains('l'));
+        let all = flags.iter().any(|flag| flag.contains('a'));
+        let dir = self.path(args.first().copied().unwrap_or(""));
+
+        match vfs::list_dir(&dir) {
+            Ok(files) => {
+                let files: alloc::vec::Vec<_> = files
+                    .into_iter()
+                    .filter(|file| all || file.attributes & fs::ATTR_HIDDEN == 0)
+                    .collect();
+                if files.is_empty() {
+                    self.write_output("No files\r\n");
+                    return;
+                }
                 self.write_output(&alloc::format!("{} file(s):\r\n", files.len()));
                 for file in files {
-                    self.write_output(&alloc::format!(
-                        "  {} - {} bytes\r\n",
-                        file.get_name(),
-                        file.get_size_bytes()
-                    ));
+                    let name = if file.is_dir { alloc::format!("{}/", file.name) } else { file.name.clone() };
+                    if long {
+                        let size = if file.is_dir { alloc::string::String::from("<DIR>") } else { alloc::format!("{}", file.size) };
+                        self.write_output(&alloc::format!(
+                            "  {} {:>10} {:>12}  {}\r\n",
+                            file.attribute_string(), size, format_timestamp(file.modified), name
+                        ));
+                    } else if file.is_dir {
+                        self.write_output(&alloc::format!("  {} - <DIR>\r\n", name));
+                    } else {
+                        self.write_output(&alloc::format!("  {} - {} bytes\r\n", name, file.size));
+                    }
+                }
+            }
+            Err(e) => self.write_output(&alloc::format!("Error: {}\r\n", e)),
+        }
+    }
+
+    fn cmd_stat(&mut self, parts: &[&str]) {
+        if parts.len() < 2 {
+            self.write_output("Usage: stat <path>\r\n");
+            return;
+        }
+
+        let file = match vfs::stat(&self.path(parts[1])) {
+            Ok(file) => file,
+            Err(e) => {
+                self.write_output(&alloc::format!("Error: {}\r\n", e));
+                return;
+            }
+        };
+        let file_type = fs::mime::file_type(file.file_type);
+        let datetime = |timestamp: i64| {
+            use crate::kernel::clock;
+            if timestamp == 0 {
+                alloc::string::String::from("-")
+            } else {
+                clock::locale::current().format_datetime(&clock::to_local(timestamp))
+            }
+        };
+
+        self.write_output(&alloc::format!("  Name:       {}\r\n", file.name));
+        self.write_output(&alloc::format!("  Type:       {} ({})\r\n", file_type.label, file_type.mime));
+        if !file.is_dir {
+            self.write_output(&alloc::format!("  Size:       {} bytes\r\n", file.size));
+        }
+        self.write_output(&alloc::format!("  Attributes: {}\r\n", file.attribute_string()));
+        self.write_output(&alloc::format!("  Created:    {}\r\n", datetime(file.created)));
+        self.write_output(&alloc::format!("  Modified:   {}\r\n", datetime(file.modified)));
+        self.write_output(&alloc::format!("  Accessed:   {}\r\n", datetime(file.accessed)));
+    }
+
+    fn cmd_attrib(&mut self, parts: &[&str]) {
+        let usage = "Usage: attrib [+r|-r] [+h|-h] [+s|-s] <path>\r\n";
+        if parts.len() < 2 {
+            self.write_output(usage);
+            return;
+        }
+
+        let path = self.path(parts[parts.len() - 1]);
+        let mut attributes = match vfs::stat(&path) {
+            Ok(file) => file.attributes,
+            Err(e) => {
+                self.write_output(&alloc::format!("Error: {}\r\n", e));
+                return;
+            }
+        };
+        let original = attributes;
+
+        for change in &parts[1..parts.len() - 1] {
+            let bit = match change.get(1..) {
+                Some("r") => fs::ATTR_READ_ONLY,
+                Some("h") => fs::ATTR_HIDDEN,
+                Some("s") => fs::ATTR_SYSTEM,
+                _ => {
+                    self.write_output(usage);
+                    return;
+                }
+            };
+            match change.as_bytes()[0] {
+                b'+' => attributes |= bit,
+                b'-' => attributes &= !bit,
+                _ => {
+                    self.write_output(usage);
+                    return;
+                }
+            }
+        }
+
+        if attributes != original {
+            if let Err(e) = vfs::set_attributes(&path, attributes) {
+                self.write_output(&alloc::format!("Error: {}\r\n", e));
+                return;
+            }
+            crate::gui::widgets::file_explorer::refresh_all_explorers();
+        }
+        self.write_output(&alloc::format!("{}  {}\r\n", fs::format_attributes(attributes), parts[parts.len() - 1]));
+    }
+
+    fn cmd_cd(&mut self, parts: &[&str]) {
+        let dir = match parts.get(1) {
+            Some(arg) => self.path(arg),
+            None => vfs::home(),
+        };
+
+        match vfs::stat(&dir) {
+            Ok(file) if file.is_dir => self.cwd = dir,
+            Ok(_) => self.write_output("Not a directory\r\n"),
+            Err(e) => self.write_output(&alloc::format!("Error: {}\r\n", e)),
+        }
+    }
+
+    fn cmd_pwd(&self) {
+        self.write_output(&alloc::format!("{}\r\n", self.cwd));
+    }
+
+    fn cmd_mount(&mut self, parts: &[&str]) {
+        if parts.len() < 2 {
+            let mounts = vfs::mounts();
+            if mounts.is_empty() {
+                self.write_output("Nothing mounted\r\n");
+            }
+            for mount in mounts {
+                self.write_output(&alloc::format!(
+                    "/{:<8} {:<8} {:<9} {}\r\n",
+                    mount.name,
+                    mount.source(),
+                    mount.fs_type(),
+                    mount.label()
+                ));
+            }
+            return;
+        }
+
+        let idx = match crate::system::block::registry::find(parts[1]) {
+            Some(idx) => idx,
+            None => {
+                self.write_output(&alloc::format!("No such device: {}\r\n", parts[1]));
+                return;
+            }
+        };
+        if crate::system::block::registry::is_locked(idx) {
+            self.ask_passphrase(PassphrasePrompt::Unlock(idx));
+            return;
+        }
+        // An encrypted device that is already unlocked mounts its contents
+        let idx = crate::system::block::registry::unlocked_volume(idx).unwrap_or(idx);
+        match vfs::mount(idx) {
+            Ok(name) => {
+                self.write_output(&alloc::format!("Mounted {} at /{}\r\n", parts[1], name));
+                crate::gui::widgets::file_explorer::refresh_all_explorers();
+            }
+            Err(e) => self.write_output(&alloc::format!("Mount failed: {}\r\n", e)),
+        }
+    }
+
+    fn cmd_umount(&mut self, parts: &[&str]) {
+        if parts.len() < 2 {
+            self.write_output("Usage: umount <volume>\r\n");
+            return;
+        }
+
+        match vfs::unmount(parts[1]) {
+            Ok(()) => {
+                self.write_output(&alloc::format!("Unmounted {}\r\n", parts[1]));
+                crate::gui::widgets::file_explorer::refresh_all_explorers();
+            }
+            Err(e) => self.write_output(&alloc::format!("Error: {}\r\n", e)),
+        }
+    }
+
+    fn cmd_fsck(&mut self, parts: &[&str]) {
+        let dry_run = parts[1..].contains(&"-n");
+        let volume = match parts[1..].iter().find(|part| !part.starts_with('-')) {
+            Some(volume) => *volume,
+            None => {
+                self.write_output("Usage: fsck [-n] <volume>\r\n");
+                return;
+            }
+        };
+
+        match vfs::check(volume, !dry_run) {
+            Ok(problems) if problems.is_empty() => self.write_output(&alloc::format!("{}: clean\r\n", volume)),
+            Ok(problems) => {
+                for problem in &problems {
+                    self.write_output(&alloc::format!("  {}\r\n", problem));
+                }
+                if dry_run {
+                    self.write_output(&alloc::format!("{} problem(s) found, run without -n to repair\r\n", problems.len()));
+                } else {
+                    self.write_output(&alloc::format!("{} problem(s) repaired\r\n", problems.len()));
+                    crate::gui::widgets::file_explorer::refresh_all_explorers();
+                }
+            }
+            Err(e) => self.write_output(&alloc::format!("fsck: {}\r\n", e)),
+        }
+    }
+
+    fn cmd_df(&mut self) {
+        self.write_output("VOLUME    TYPE         SIZE      USED      FREE  USE%\r\n");
+        for mount in vfs::mounts() {
+            let usage = match vfs::usage(&mount.name) {
+                Ok(usage) => usage,
+                Err(_) => {
+                    self.write_output(&alloc::format!("/{:<8} {:<10}  -\r\n", mount.name, mount.fs_type()));
+                    continue;
                 }
+            };
+            let used = usage.total_bytes - usage.free_bytes;
+            let percent = if usage.total_bytes > 0 { used * 100 / usage.total_bytes } else { 0 };
+            self.write_output(&alloc::format!(
+                "/{:<8} {:<10} {:>6} {:>9} {:>9}  {:>3}%\r\n",
+                mount.name,
+                mount.fs_type(),
+                format_bytes(usage.total_bytes),
+                format_bytes(used),
+                format_bytes(usage.free_bytes),
+                percent
+            ));
+            if let Some(line) = fragmentation(&usage) {
+                self.write_output(&alloc::format!("          {}\r\n", line));
             }
+        }
+    }
+
+    fn cmd_defrag(&mut self, parts: &[&str]) {
+        if parts.len() < 2 {
+            self.write_output("Usage: defrag <volume>\r\n");
+            return;
+        }
+
+        if let Some(line) = vfs::usage(parts[1]).ok().as_ref().and_then(fragmentation) {
+            self.write_output(&alloc::format!("Before: {}\r\n", line));
+        }
+        match vfs::defrag(parts[1]) {
+            Ok(moved) => {
+                self.write_output(&alloc::format!("Moved {} file(s)\r\n", moved));
+                if let Some(line) = vfs::usage(parts[1]).ok().as_ref().and_then(fragmentation) {
+                    self.write_output(&alloc::format!("After:  {}\r\n", line));
+                }
+            }
+            Err(e) => self.write_output(&alloc::format!("defrag: {}\r\n", e)),
+        }
+    }
+
+    fn cmd_snapshot(&mut self, parts: &[&str]) {
+        match parts.get(1..) {
+            Some(["list", volume]) => match vfs::snapshots(volume) {
+                Ok(snapshots) if snapshots.is_empty() => self.write_output("No snapshots\r\n"),
+                Ok(snapshots) => {
+                    for snapshot in snapshots {
+                        self.write_output(&alloc::format!("{}  {}\r\n", format_timestamp(snapshot.created), snapshot.name));
+                    }
+                }
+                Err(e) => self.write_output(&alloc::format!("snapshot: {}\r\n", e)),
+            },
+            Some([command @ ("create" | "restore" | "delete"), volume, name]) => {
+                let (result, done) = match *command {
+                    "create" => (vfs::create_snapshot(volume, name), "Created"),
+                    "restore" => (vfs::restore_snapshot(volume, name), "Restored"),
+                    _ => (vfs::delete_snapshot(volume, name), "Deleted"),
+                };
+                match result {
+                    Ok(()) => {
+                        self.write_output(&alloc::format!("{} snapshot {}\r\n", done, name));
+                        crate::gui::widgets::file_explorer::refresh_all_explorers();
+                    }
+                    Err(e) => self.write_output(&alloc::format!("snapshot: {}\r\n", e)),
+                }
+            }
+            _ => self.write_output("Usage: snapshot list <volume> | snapshot create|restore|delete <volume> <name>\r\n"),
+        }
+    }
+
+    fn cmd_crypt(&mut self, parts: &[&str]) {
+        use crate::system::block::registry;
+
+        let (action, device) = match parts {
+            [_, action @ ("format" | "open"), device] => (*action, *device),
+            _ => {
+                self.write_output("Usage: crypt format|open <device>\r\n");
+                return;
+            }
+        };
+        let idx = match registry::find(device) {
+            Some(idx) => idx,
+            None => {
+                self.write_output(&alloc::format!("No such device: {}\r\n", device));
+                return;
+            }
+        };
+
+        if action == "open" {
+            if registry::is_locked(idx) {
+                self.ask_passphrase(PassphrasePrompt::Unlock(idx));
+            } else {
+                self.write_output(&alloc::format!("{} is not a locked encrypted device\r\n", device));
+            }
+            return;
+        }
+
+        let entry = &registry::devices()[idx];
+        let in_use = if vfs::mounts().iter().any(|m| m.device == Some(idx)) {
+            Some("it is mounted")
+        } else if entry.table.is_some() {
+            Some("it has a partition table - encrypt a partition instead")
+        } else if entry.bad_table {
+            Some("its partition table can't be read")
+        } else if registry::unlocked_volume(idx).is_some() {
+            Some("it is unlocked")
+        } else if entry.device.is_read_only() {
+            Some("it is read-only")
         } else {
-            self.write_output("Filesystem not mounted\r\n");
+            None
+        };
+        if let Some(reason) = in_use {
+            self.write_output(&alloc::format!("Can't encrypt {}: {}\r\n", device, reason));
+            return;
+        }
+        self.write_output(&alloc::format!("This erases everything on {}.\r\n", device));
+        self.ask_passphrase(PassphrasePrompt::Format(idx));
+    }
+
+    /// Ask for a passphrase; the next line typed answers `prompt`
+    fn ask_passphrase(&mut self, prompt: PassphrasePrompt) {
+        let question = match prompt {
+            PassphrasePrompt::Unlock(idx) => alloc::format!(
+                "Passphrase for {} (empty to cancel): ",
+                crate::system::block::device_name(idx).unwrap_or("?")
+            ),
+            PassphrasePrompt::Format(_) => alloc::string::String::from("New passphrase (empty to cancel): "),
+            PassphrasePrompt::Confirm(..) => alloc::string::String::from("Repeat passphrase: "),
+        };
+        self.write_output(&question);
+        self.passphrase = Some(prompt);
+    }
+
+    fn answer_passphrase(&mut self, prompt: PassphrasePrompt) {
+        let passphrase = core::str::from_utf8(&self.command_buffer[..self.cursor_pos]).unwrap_or("");
+        if passphrase.is_empty() {
+            self.write_output("Cancelled\r\n");
+            return;
+        }
+
+        match prompt {
+            PassphrasePrompt::Unlock(idx) => match crate::kernel::unlock::unlock(idx, passphrase) {
+                Ok(name) => {
+                    self.write_output(&alloc::format!("Unlocked and mounted at /{}\r\n", name));
+                    // A shell opened at boot before the root volume was unlocked
+                    if self.cwd == "/" {
+                        self.cwd = vfs::home();
+                    }
+                }
+                Err("Wrong passphrase") => {
+                    self.write_output("Wrong passphrase\r\n");
+                    self.ask_passphrase(PassphrasePrompt::Unlock(idx));
+                }
+                Err(e) => self.write_output(&alloc::format!("Unlock failed: {}\r\n", e)),
+            },
+            PassphrasePrompt::Format(idx) => {
+                let passphrase = alloc::string::String::from(passphrase);
+                self.ask_passphrase(PassphrasePrompt::Confirm(idx, passphrase));
+            }
+            PassphrasePrompt::Confirm(idx, first) if first == passphrase => {
+                match format_encrypted(idx, &first) {
+                    Ok(name) => {
+                        self.write_output(&alloc::format!("Encrypted volume mounted at /{}\r\n", name));
+                        crate::gui::widgets::file_explorer::refresh_all_explorers();
+                    }
+                    Err(e) => self.write_output(&alloc::format!("crypt format failed: {}\r\n", e)),
+                }
+            }
+            PassphrasePrompt::Confirm(..) => self.write_output("Passphrases don't match\r\n"),
+        }
+    }
+
+    fn cmd_lsblk(&mut self) {
+        use crate::system::block::partition_table::TableKind;
+
+        let devices = crate::system::block::registry::devices();
+        if devices.is_empty() {
+            self.write_output("No block devices\r\n");
+            return;
+        }
+
+        self.write_output("NAME     SIZE       RO TYPE  TABLE/FSTYPE      LABEL\r\n");
+        for entry in devices {
+            let size = format_blocks(entry.device.block_count());
+            let ro = if entry.device.is_read_only() { 1 } else { 0 };
+            let line = match entry.partition {
+                _ if entry.unlocked_from.is_some() => alloc::format!(
+                    "  {:<6} {:<10} {}  crypt\r\n",
+                    entry.name, size, ro
+                ),
+                Some((_, ref info)) => alloc::format!(
+                    "  {:<6} {:<10} {}  part  {:<17} {}\r\n",
+                    entry.name, size, ro, info.type_name(), info.name
+                ),
+                None => {
+                    let table = match entry.table {
+                        Some(TableKind::Gpt) => "gpt",
+                        Some(TableKind::Mbr) => "mbr",
+                        None if entry.bad_table => "bad",
+                        None => "-",
+                    };
+                    let mut line = alloc::format!("{:<8} {:<10} {}  disk  {}\r\n", entry.name, size, ro, table);
+                    if let Some(disk) = virtio_disk(&entry.name) {
+                        line.push_str(&format_disk_info(&disk.info()));
+                    }
+                    line
+                }
+            };
+            self.write_output(&line);
         }
     }
 
+    fn cmd_diskspeed(&mut self, parts: &[&str]) {
+        if parts.len() < 2 {
+            self.write_output("Usage: diskspeed <disk> [MB]  (e.g. diskspeed vda)\r\n");
+            return;
+        }
+        let Some(disk) = virtio_disk(parts[1]) else {
+            self.write_output("No such disk\r\n");
+            return;
+        };
+        let megabytes = match parts.get(2).map(|mb| mb.parse::<u64>()) {
+            Some(Ok(mb)) if mb > 0 => mb,
+            Some(_) => {
+                self.write_output("Invalid size\r\n");
+                return;
+            }
+            None => 16,
+        };
+
+        let sectors = (megabytes * 2048).min(disk.info().capacity);
+        self.write_output(&alloc::format!("Reading {} KB from {}...\r\n", sectors / 2, parts[1]));
+        let console_id = self.console_id;
+        self.spawn_job(async move {
+            let start = crate::kernel::clock::monotonic_ms();
+            let mut done = 0;
+            while done < sectors {
+                let count = (sectors - done).min(DISKSPEED_CHUNK_SECTORS);
+                if let Err(e) = disk.read(done, count as usize).await {
+                    job_output(console_id, &alloc::format!("Read failed at sector {}: {}\r\n", done, e));
+                    return;
+                }
+                done += count;
+            }
+            let ms = (crate::kernel::clock::monotonic_ms() - start).max(1);
+            job_output(console_id, &alloc::format!(
+                "Read {} KB in {} ms ({} KB/s)\r\n",
+                done / 2, ms, done / 2 * 1000 / ms
+            ));
+        });
+    }
+
+    fn cmd_sync(&mut self) {
+        let dirty = crate::system::block::cache::cache().dirty_blocks();
+        match crate::system::block::cache::sync() {
+            Ok(()) => self.write_output(&alloc::format!("Wrote back {} block(s)\r\n", dirty)),
+            Err(e) => self.write_output(&alloc::format!("sync failed: {}\r\n", e)),
+        }
+    }
+
+    fn cmd_cache(&mut self, parts: &[&str]) {
+        let cache = crate::system::block::cache::cache();
+
+        if parts.len() >= 2 {
+            if parts[1] != "size" || parts.len() < 3 {
+                self.write_output("Usage: cache [size <KB>]\r\n");
+                return;
+            }
+            let kb: usize = match parts[2].parse() {
+                Ok(kb) => kb,
+                Err(_) => {
+                    self.write_output("Invalid size\r\n");
+                    return;
+                }
+            };
+            let blocks = kb * 1024 / crate::system::block::BLOCK_SIZE;
+            match cache.set_capacity(blocks) {
+                Ok(()) => self.write_output(&alloc::format!("Cache size set to {} KB\r\n", kb)),
+                Err(e) => self.write_output(&alloc::format!("Resize failed: {}\r\n", e)),
+            }
+            return;
+        }
+
+        let stats = cache.stats();
+        let lookups = stats.hits + stats.misses;
+        let hit_rate = if lookups > 0 { stats.hits * 100 / lookups } else { 0 };
+        self.write_output(&alloc::format!(
+            "Size:      {} / {} blocks ({} dirty)\r\n",
+            cache.cached_blocks(), cache.capacity(), cache.dirty_blocks()
+        ));
+        self.write_output(&alloc::format!(
+            "Hits:      {} ({}%)\r\nMisses:    {}\r\nBypassed:  {}\r\n",
+            stats.hits, hit_rate, stats.misses, stats.bypassed
+        ));
+        self.write_output(&alloc::format!(
+            "Evictions: {}\r\nWritten:   {}\r\n",
+            stats.evictions, stats.writebacks
+        ));
+    }
+
     fn cmd_cat(&mut self, parts: &[&str]) {
         if parts.len() < 2 {
             self.write_output("Usage: cat <filename>\r\n");
             return;
         }
 
-        if let (Some(ref fs), Some(idx)) = (&self.filesystem, self.device_index) {
-            unsafe {
-                if let Some(ref mut devices) = crate::kernel::BLOCK_DEVICES {
-                    if let Some(device) = devices.get_mut(idx) {
-                        let filename = parts[1];
-
-                        // Get file size
-                        let files = fs.list_files();
-                        let file = files.iter().find(|f| f.get_name() == filename);
-
-                        if let Some(file) = file {
-                            let size = file.get_size_bytes() as usize;
-                            let mut buffer = alloc::vec![0u8; size];
-
-                            match fs.read_file(device, filename, &mut buffer) {
-                                Ok(bytes_read) => {
-                                    if let Ok(text) = core::str::from_utf8(&buffer[..bytes_read]) {
-                                        self.write_output(text);
-                                        self.write_output("\r\n");
-                                    } else {
-                                        self.write_output("(binary file)\r\n");
-                                    }
-                                }
-                                Err(e) => self.write_output(&alloc::format!("Error: {}\r\n", e)),
-                            }
-                        } else {
-                            self.write_output("File not found\r\n");
-                        }
-                    } else {
-                        self.write_output("Block device not available\r\n");
-                    }
-                } else {
-                    self.write_output("Block devices not initialized\r\n");
+        // Read in chunks, so device files bigger than memory can be shown too
+        let fd = match vfs::open(&self.path(parts[1]), vfs::O_READ) {
+            Ok(fd) => fd,
+            Err(e) => {
+                self.write_output(&alloc::format!("Error: {}\r\n", e));
+                return;
+            }
+        };
+        let size = vfs::fstat(fd).map(|info| info.size);
+        let mut chunk = alloc::vec![0u8; 4096];
+        let mut pending = alloc::vec::Vec::new(); // Text not shown yet, ending mid-character
+        let mut binary = false;
+        let result = loop {
+            let count = match vfs::read(fd, &mut chunk) {
+                Ok(0) => {
+                    binary = !pending.is_empty();
+                    break Ok(());
                 }
+                Ok(count) => count,
+                Err(e) => break Err(e),
+            };
+            pending.extend_from_slice(&chunk[..count]);
+            let valid = match core::str::from_utf8(&pending) {
+                Ok(text) => text.len(),
+                Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                Err(_) => {
+                    binary = true;
+                    break Ok(());
+                }
+            };
+            if let Ok(text) = core::str::from_utf8(&pending[..valid]) {
+                self.write_output(text);
             }
-        } else {
-            self.write_output("Filesystem not mounted\r\n");
+            pending.drain(..valid);
+        };
+        let _ = vfs::close(fd);
+
+        match result {
+            Ok(()) if binary => match size {
+                Ok(size) => self.write_output(&alloc::format!("(binary file, {} bytes)\r\n", size)),
+                Err(_) => self.write_output("(binary file)\r\n"),
+            },
+            Ok(()) => self.write_output("\r\n"),
+            Err(e) => self.write_output(&alloc::format!("Error: {}\r\n", e)),
         }
     }
 
@@ -210,35 +824,35 @@ impl Shell {
             return;
         }
 
-        if let (Some(ref mut fs), Some(idx)) = (&mut self.filesystem, self.device_index) {
-            unsafe {
-                if let Some(ref mut devices) = crate::kernel::BLOCK_DEVICES {
-                    if let Some(device) = devices.get_mut(idx) {
-                        let filename = parts[1];
-
-                        if let Ok(size) = parts[2].parse::<u32>() {
-                            match fs.create_file(device, filename, size) {
-                                Ok(()) => {
-                                    self.write_output(&alloc::format!(
-                                        "Created '{}' ({} bytes)\r\n", filename, size
-                                    ));
-                                    // Refresh all open file explorers to show the new file
-                                    crate::gui::widgets::file_explorer::refresh_all_explorers();
-                                }
-                                Err(e) => self.write_output(&alloc::format!("Error: {}\r\n", e)),
-                            }
-                        } else {
-                            self.write_output("Invalid size\r\n");
-                        }
-                    } else {
-                        self.write_output("Block device not available\r\n");
-                    }
-                } else {
-                    self.write_output("Block devices not initialized\r\n");
+        let filename = parts[1];
+        if let Ok(size) = parts[2].parse::<u32>() {
+            match vfs::create_file(&self.path(filename), size) {
+                Ok(()) => {
+                    self.write_output(&alloc::format!(
+                        "Created '{}' ({} bytes)\r\n", filename, size
+                    ));
+                    // Refresh all open file explorers to show the new file
+                    crate::gui::widgets::file_explorer::refresh_all_explorers();
                 }
+                Err(e) => self.write_output(&alloc::format!("Error: {}\r\n", e)),
             }
         } else {
-            self.write_output("Filesystem not mounted\r\n");
+            self.write_output("Invalid size\r\n");
+        }
+    }
+
+    fn cmd_mkdir(&mut self, parts: &[&str]) {
+        if parts.len() < 2 {
+            self.write_output("Usage: mkdir <dir>\r\n");
+            return;
+        }
+
+        match vfs::create_dir(&self.path(parts[1])) {
+            Ok(()) => {
+                self.write_output(&alloc::format!("Created directory '{}'\r\n", parts[1]));
+                crate::gui::widgets::file_explorer::refresh_all_explorers();
+            }
+            Err(e) => self.write_output(&alloc::format!("Error: {}\r\n", e)),
         }
     }
 
@@ -248,29 +862,14 @@ impl Shell {
             return;
         }
 
-        if let (Some(ref mut fs), Some(idx)) = (&mut self.filesystem, self.device_index) {
-            unsafe {
-                if let Some(ref mut devices) = crate::kernel::BLOCK_DEVICES {
-                    if let Some(device) = devices.get_mut(idx) {
-                        let filename = parts[1];
-
-                        match fs.delete_file(device, filename) {
-                            Ok(()) => {
-                                self.write_output(&alloc::format!("Deleted '{}'\r\n", filename));
-                                // Refresh all open file explorers to remove the deleted file
-                                crate::gui::widgets::file_explorer::refresh_all_explorers();
-                            }
-                            Err(e) => self.write_output(&alloc::format!("Error: {}\r\n", e)),
-                        }
-                    } else {
-                        self.write_output("Block device not available\r\n");
-                    }
-                } else {
-                    self.write_output("Block devices not initialized\r\n");
-                }
+        let filename = parts[1];
+        match vfs::delete(&self.path(filename)) {
+            Ok(()) => {
+                self.write_output(&alloc::format!("Deleted '{}'\r\n", filename));
+                // Refresh all open file explorers to remove the deleted file
+                crate::gui::widgets::file_explorer::refresh_all_explorers();
             }
-        } else {
-            self.write_output("Filesystem not mounted\r\n");
+            Err(e) => self.write_output(&alloc::format!("Error: {}\r\n", e)),
         }
     }
 
@@ -280,67 +879,267 @@ impl Shell {
             return;
         }
 
-        if let (Some(ref mut fs), Some(idx)) = (&mut self.filesystem, self.device_index) {
-            unsafe {
-                if let Some(ref mut devices) = crate::kernel::BLOCK_DEVICES {
-                    if let Some(device) = devices.get_mut(idx) {
-                        let old_name = parts[1];
-                        let new_name = parts[2];
-
-                        match fs.rename_file(device, old_name, new_name) {
-                            Ok(()) => {
-                                self.write_output(&alloc::format!(
-                                    "Renamed '{}' to '{}'\r\n", old_name, new_name
-                                ));
-                                // Refresh all open file explorers to show the renamed file
-                                crate::gui::widgets::file_explorer::refresh_all_explorers();
-                            }
-                            Err(e) => self.write_output(&alloc::format!("Error: {}\r\n", e)),
-                        }
-                    } else {
-                        self.write_output("Block device not available\r\n");
-                    }
-                } else {
-                    self.write_output("Block devices not initialized\r\n");
-                }
+        let old_name = parts[1];
+        let new_name = parts[2];
+        match vfs::rename(&self.path(old_name), &self.path(new_name)) {
+            Ok(()) => {
+                self.write_output(&alloc::format!(
+                    "Renamed '{}' to '{}'\r\n", old_name, new_name
+                ));
+                // Refresh all open file explorers to show the renamed file
+                crate::gui::widgets::file_explorer::refresh_all_explorers();
             }
-        } else {
-            self.write_output("Filesystem not mounted\r\n");
+            Err(e) => self.write_output(&alloc::format!("Error: {}\r\n", e)),
         }
     }
 
     fn cmd_write(&mut self, parts: &[&str]) {
         if parts.len() < 3 {
-            self.write_output("Usage: write <filename> <text...>\r\n");
-            return;
-        }
-
-        if let (Some(ref mut fs), Some(idx)) = (&mut self.filesystem, self.device_index) {
-            unsafe {
-                if let Some(ref mut devices) = crate::kernel::BLOCK_DEVICES {
-                    if let Some(device) = devices.get_mut(idx) {
-                        let filename = parts[1];
-                        let text = parts[2..].join(" ");
-
-                        match fs.write_file(device, filename, text.as_bytes()) {
-                            Ok(()) => {
-                                self.write_output(&alloc::format!(
-                                    "Wrote {} bytes to '{}'\r\n", text.len(), filename
-                                ));
-                                // Refresh all open file explorers to update file sizes
-                                crate::gui::widgets::file_explorer::refresh_all_explorers();
-                            }
-                            Err(e) => self.write_output(&alloc::format!("Error: {}\r\n", e)),
-                        }
-                    } else {
-                        self.write_output("Block device not available\r\n");
+            self.write_output(&alloc::format!("Usage: {} <filename> <text...>\r\n", parts[0]));
+            return;
+        }
+
+        let append = parts[0] == "append";
+        let flags = vfs::O_WRITE | vfs::O_CREATE | if append { vfs::O_APPEND } else { vfs::O_TRUNC };
+        let filename = parts[1];
+        let text = parts[2..].join(" ");
+        let result = vfs::open(&self.path(filename), flags).and_then(|fd| {
+            let written = vfs::write(fd, text.as_bytes());
+            vfs::close(fd)?;
+            written
+        });
+
+        match result {
+            Ok(written) => {
+                self.write_output(&alloc::format!(
+                    "Wrote {} bytes to '{}'\r\n", written, filename
+                ));
+                // Refresh all open file explorers to update file sizes
+                crate::gui::widgets::file_explorer::refresh_all_explorers();
+            }
+            Err(e) => self.write_output(&alloc::format!("Error: {}\r\n", e)),
+        }
+    }
+
+    fn cmd_truncate(&mut self, parts: &[&str]) {
+        let size = match parts.get(2).and_then(|size| size.parse::<u32>().ok()) {
+            Some(size) if parts.len() == 3 => size,
+            _ => {
+                self.write_output("Usage: truncate <filename> <size>\r\n");
+                return;
+            }
+        };
+
+        match vfs::truncate(&self.path(parts[1]), size) {
+            Ok(()) => {
+                self.write_output(&alloc::format!("'{}' is now {} bytes\r\n", parts[1], size));
+                crate::gui::widgets::file_explorer::refresh_all_explorers();
+            }
+            Err(e) => self.write_output(&alloc::format!("Error: {}\r\n", e)),
+        }
+    }
+
+    fn cmd_hexdump(&mut self, parts: &[&str]) {
+        if parts.len() < 2 {
+            self.write_output("Usage: hexdump <filename> [offset]  (a negative offset counts from the end)\r\n");
+            return;
+        }
+        let from = match parts.get(2).map(|offset| offset.parse::<i64>()) {
+            Some(Ok(offset)) if offset < 0 => vfs::SeekFrom::End(offset),
+            Some(Ok(offset)) => vfs::SeekFrom::Start(offset as u64),
+            Some(Err(_)) => {
+                self.write_output("Invalid offset\r\n");
+                return;
+            }
+            None => vfs::SeekFrom::Start(0),
+        };
+
+        // Show 256 bytes from the offset
+        let mut buffer = [0u8; 256];
+        let result = vfs::open(&self.path(parts[1]), vfs::O_READ).and_then(|fd| {
+            let read = vfs::seek(fd, from).and_then(|offset| Ok((offset, vfs::read(fd, &mut buffer)?)));
+            vfs::close(fd)?;
+            read
+        });
+
+        match result {
+            Ok((offset, count)) => {
+                for (i, line) in buffer[..count].chunks(16).enumerate() {
+                    let mut text = alloc::format!("{:08x} ", offset + i as u64 * 16);
+                    for byte in line {
+                        text.push_str(&alloc::format!(" {:02x}", byte));
                     }
-                } else {
-                    self.write_output("Block devices not initialized\r\n");
+                    for _ in line.len()..16 {
+                        text.push_str("   ");
+                    }
+                    text.push_str("  ");
+                    text.extend(line.iter().map(|&b| if (0x20..0x7F).contains(&b) { b as char } else { '.' }));
+                    text.push_str("\r\n");
+                    self.write_output(&text);
                 }
             }
-        } else {
-            self.write_output("Filesystem not mounted\r\n");
+            Err(e) => self.write_output(&alloc::format!("Error: {}\r\n", e)),
+        }
+    }
+
+    fn cmd_tar(&mut self, parts: &[&str]) {
+        let (flags, args): (alloc::vec::Vec<&str>, alloc::vec::Vec<&str>) =
+            parts[1..].iter().partition(|part| part.starts_with('-'));
+        // The f, v and z of habit are accepted and ignored
+        let mode = flags.iter().flat_map(|flag| flag.chars()).find(|c| matches!(c, 'c' | 'x' | 't'));
+        match (mode, args.as_slice()) {
+            (Some('t'), [archive]) => self.tar_list(archive),
+            (Some('x'), [archive]) => self.tar_extract(archive, "."),
+            (Some('x'), [archive, dir]) => self.tar_extract(archive, dir),
+            (Some('c'), [archive, paths @ ..]) if !paths.is_empty() => self.tar_create(archive, paths),
+            _ => self.write_output("Usage: tar -c <archive> <path>... | tar -x <archive> [dir] | tar -t <archive>\r\n"),
+        }
+    }
+
+    fn tar_list(&mut self, archive: &str) {
+        let (_, contents) = match vfs::read_file(&self.path(archive)).and_then(archive::unpack_tar) {
+            Ok(unpacked) => unpacked,
+            Err(e) => {
+                self.write_output(&alloc::format!("tar: {}\r\n", e));
+                return;
+            }
+        };
+        for entry in &contents.entries {
+            let suffix = if entry.is_dir { "/" } else { "" };
+            self.write_output(&alloc::format!("{:>10}  {}{}\r\n", entry.size, entry.path, suffix));
+        }
+        self.write_output(&alloc::format!("{} entries\r\n", contents.entries.len()));
+    }
+
+    fn tar_extract(&mut self, archive: &str, dir: &str) {
+        let dest = self.path(dir);
+        let (data, contents) = match vfs::read_file(&self.path(archive)).and_then(archive::unpack_tar) {
+            Ok(unpacked) => unpacked,
+            Err(e) => {
+                self.write_output(&alloc::format!("tar: {}\r\n", e));
+                return;
+            }
+        };
+
+        let mut files = 0;
+        for entry in &contents.entries {
+            let path = vfs::join(&dest, &entry.path);
+            let result = if entry.is_dir {
+                vfs::create_dir_all(&path)
+            } else {
+                vfs::create_dir_all(&vfs::join(&path, "..")).and_then(|()| vfs::write_file(&path, &data[entry.offset..entry.offset + entry.size]))
+            };
+            if let Err(e) = result {
+                self.write_output(&alloc::format!("tar: {}: {}\r\n", entry.path, e));
+                break;
+            }
+            if !entry.is_dir {
+                files += 1;
+            }
+        }
+
+        self.write_output(&alloc::format!("Extracted {} file(s) to {}\r\n", files, dest));
+        if contents.skipped > 0 {
+            self.write_output(&alloc::format!("Skipped {} link(s), device(s) or unsafe path(s)\r\n", contents.skipped));
+        }
+        crate::gui::widgets::file_explorer::refresh_all_explorers();
+    }
+
+    fn tar_create(&mut self, archive: &str, paths: &[&str]) {
+        if archive::gzip::uncompressed_name(archive).is_some() {
+            self.write_output("tar: can only write uncompressed .tar archives\r\n");
+            return;
+        }
+
+        let mut builder = tar::Builder::new();
+        let mut files = 0;
+        for path in paths {
+            let full = self.path(path);
+            let name = full.rsplit('/').next().unwrap_or("");
+            if let Err(e) = add_to_tar(&mut builder, &full, name, &mut files) {
+                self.write_output(&alloc::format!("tar: {}: {}\r\n", path, e));
+                return;
+            }
+        }
+
+        let data = builder.finish();
+        match vfs::write_file(&self.path(archive), &data) {
+            Ok(()) => {
+                self.write_output(&alloc::format!("Packed {} file(s) into {} ({})\r\n", files, archive, format_bytes(data.len() as u64)));
+                crate::gui::widgets::file_explorer::refresh_all_explorers();
+            }
+            Err(e) => self.write_output(&alloc::format!("tar: {}\r\n", e)),
+        }
+    }
+
+    fn cmd_gunzip(&mut self, parts: &[&str]) {
+        if parts.len() < 2 {
+            self.write_output("Usage: gunzip <file.gz> [dest]\r\n");
+            return;
+        }
+
+        let source = self.path(parts[1]);
+        let dest = match parts.get(2) {
+            Some(dest) => self.path(dest),
+            None => match archive::gzip::uncompressed_name(&source) {
+                Some(dest) => dest,
+                None => {
+                    self.write_output("gunzip: name doesn't end in .gz or .tgz, give a destination\r\n");
+                    return;
+                }
+            },
+        };
+
+        let result = vfs::read_file(&source)
+            .and_then(|data| archive::gzip::decompress(&data, archive::MAX_DECOMPRESSED))
+            .and_then(|out| vfs::write_file(&dest, &out.data).map(|()| out.data.len()));
+        match result {
+            Ok(size) => {
+                self.write_output(&alloc::format!("Wrote {} ({} bytes)\r\n", dest, size));
+                crate::gui::widgets::file_explorer::refresh_all_explorers();
+            }
+            Err(e) => self.write_output(&alloc::format!("gunzip: {}\r\n", e)),
+        }
+    }
+
+    fn cmd_unzip(&mut self, parts: &[&str]) {
+        match parts[1..] {
+            ["-l", archive] => self.unzip_list(archive),
+            [archive] => self.unzip_extract(archive, "."),
+            [archive, dir] if !archive.starts_with('-') => self.unzip_extract(archive, dir),
+            _ => self.write_output("Usage: unzip <zip> [dir] | unzip -l <zip>\r\n"),
+        }
+    }
+
+    fn unzip_list(&mut self, archive: &str) {
+        let contents = match vfs::read_file(&self.path(archive)).and_then(|data| zip::parse(&data)) {
+            Ok(contents) => contents,
+            Err(e) => {
+                self.write_output(&alloc::format!("unzip: {}\r\n", e));
+                return;
+            }
+        };
+        for entry in &contents.entries {
+            let suffix = if entry.is_dir { "/" } else { "" };
+            let note = if entry.encrypted { "  (encrypted)" } else { "" };
+            self.write_output(&alloc::format!("{:>10}  {}{}{}\r\n", entry.size, entry.path, suffix, note));
+        }
+        self.write_output(&alloc::format!("{} entries\r\n", contents.entries.len()));
+    }
+
+    fn unzip_extract(&mut self, archive: &str, dir: &str) {
+        let source = self.path(archive);
+        if !source.to_ascii_lowercase().ends_with(".zip") {
+            self.write_output("unzip: name doesn't end in .zip\r\n");
+            return;
+        }
+        let dest = self.path(dir);
+        match vfs::extract(&source, &dest) {
+            Ok(files) => {
+                self.write_output(&alloc::format!("Extracted {} file(s) to {}\r\n", files, dest));
+                crate::gui::widgets::file_explorer::refresh_all_explorers();
+            }
+            Err(e) => self.write_output(&alloc::format!("unzip: {}\r\n", e)),
         }
     }
 
@@ -350,68 +1149,37 @@ impl Shell {
             return;
         }
 
-        let filename = parts[1];
-
         // Check if the editor window already exists
         if crate::gui::window_manager::has_focused_editor() {
             self.write_output("Editor window is already open\r\n");
             return;
         }
 
-        if let (Some(ref fs), Some(idx)) = (&self.filesystem, self.device_index) {
-            unsafe {
-                if let Some(ref mut devices) = crate::kernel::BLOCK_DEVICES {
-                    if let Some(device) = devices.get_mut(idx) {
-                        // Check if file exists
-                        let files = fs.list_files();
-                        let file = files.iter().find(|f| f.get_name() == filename);
-
-                        if let Some(file) = file {
-                            let size = file.get_size_bytes() as usize;
-                            let mut buffer = alloc::vec![0u8; size];
-
-                            match fs.read_file(device, filename, &mut buffer) {
-                                Ok(bytes_read) => {
-                                    // Find the actual content length (stop at first null byte or end)
-                                    let actual_len = buffer[..bytes_read].iter()
-                                        .position(|&b| b == 0)
-                                        .unwrap_or(bytes_read);
-
-                                    if let Ok(text) = core::str::from_utf8(&buffer[..actual_len]) {
-                                        // Create editor instance with file content
-                                        let editor_id = crate::gui::widgets::editor::create_editor_with_content(
-                                            filename,
-                                            text
-                                        );
-
-                                        // Open editor window
-                                        let window = crate::gui::window_manager::Window::new(
-                                            0, 0, 640, 480,
-                                            &alloc::format!("Text Editor - {}", filename),
-                                            crate::gui::window_manager::WindowContent::Editor,
-                                            editor_id
-                                        );
-                                        crate::gui::window_manager::add_window(window);
-
-                                        self.write_output(&alloc::format!("Opened '{}' in editor\r\n", filename));
-                                    } else {
-                                        self.write_output("Cannot edit binary file\r\n");
-                                    }
-                                }
-                                Err(e) => self.write_output(&alloc::format!("Error: {}\r\n", e)),
-                            }
-                        } else {
-                            self.write_output("File not found\r\n");
-                        }
-                    } else {
-                        self.write_output("Block device not available\r\n");
-                    }
+        let path = self.path(parts[1]);
+        match vfs::read_file(&path) {
+            Ok(buffer) => {
+                // Find the actual content length (stop at first null byte or end)
+                let actual_len = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+
+                if let Ok(text) = core::str::from_utf8(&buffer[..actual_len]) {
+                    // Create editor instance with file content
+                    let editor_id = crate::gui::widgets::editor::create_editor_with_content(&path, text);
+
+                    // Open editor window
+                    let window = crate::gui::window_manager::Window::new(
+                        0, 0, 640, 480,
+                        &alloc::format!("Text Editor - {}", path),
+                        crate::gui::window_manager::WindowContent::Editor,
+                        editor_id
+                    );
+                    crate::gui::window_manager::add_window(window);
+
+                    self.write_output(&alloc::format!("Opened '{}' in editor\r\n", path));
                 } else {
-                    self.write_output("Block devices not initialized\r\n");
+                    self.write_output("Cannot edit binary file\r\n");
                 }
             }
-        } else {
-            self.write_output("Filesystem not mounted\r\n");
+            Err(e) => self.write_output(&alloc::format!("Error: {}\r\n", e)),
         }
     }
 
@@ -420,6 +1188,9 @@ impl Shell {
         uart_write_string("\x1b[2J\x1b[H");
         // Clear GUI console
         console::clear(self.console_id);
+        if self.remote {
+            crate::kernel::ports::shell_output(b"\x1b[2J\x1b[H");
+        }
     }
 
     fn cmd_setfont(&self, parts: &[&str]) {
@@ -450,6 +1221,102 @@ impl Shell {
         }
     }
 
+    fn cmd_date(&self, parts: &[&str]) {
+        use crate::kernel::clock;
+
+        if parts.len() >= 3 && parts[1] == "set" {
+            let spec = parts[2..].join(" ");
+            // Accept either a Unix timestamp or "YYYY-MM-DD HH:MM[:SS]" (UTC)
+            let timestamp = spec.parse::<i64>().ok().or_else(|| clock::parse_datetime(&spec));
+            match timestamp {
+                Some(ts) => match clock::set_realtime(ts) {
+                    Ok(()) => self.write_output(&alloc::format!("Clock set to {}\r\n", clock::format_iso8601(ts))),
+                    Err(e) => self.write_output(&alloc::format!("Error: {}\r\n", e)),
+                },
+                None => self.write_output("Usage: date set <YYYY-MM-DD HH:MM[:SS]> | <unix seconds>\r\n"),
+            }
+            return;
+        }
+
+        if parts.len() >= 3 && parts[1] == "adjust" {
+            // Step the clock by a signed number of seconds
+            match parts[2].trim_start_matches('+').parse::<i64>() {
+                Ok(delta) => {
+                    clock::adjust_realtime(delta * 1_000_000_000);
+                    self.write_output(&alloc::format!("Clock stepped by {}s\r\n", delta));
+                }
+                Err(_) => self.write_output("Usage: date adjust <+/-seconds>\r\n"),
+            }
+            return;
+        }
+
+        let now = clock::realtime_secs();
+        let local = clock::to_local(now);
+        self.write_output(&alloc::format!("{}\r\n", clock::locale::current().format_long(&local)));
+        self.write_output(&alloc::format!("UTC: {}  ({})\r\n",
+            clock::format_iso8601(now), now));
+    }
+
+    fn cmd_tz(&self, parts: &[&str]) {
+        use crate::kernel::clock::tz;
+
+        if parts.len() < 2 {
+            let zone = tz::current();
+            let local = crate::kernel::clock::now_local();
+            self.write_output(&alloc::format!("Timezone: {} ({}, UTC{}{})\r\n",
+                zone.name,
+                local.abbrev.as_str(),
+                tz::format_offset(local.utc_offset),
+                if local.is_dst { ", DST" } else { "" }));
+            self.write_output("Usage: tz <zone> | tz list\r\n");
+            return;
+        }
+
+        if parts[1] == "list" {
+            for name in tz::zone_names() {
+                self.write_output(&alloc::format!("  {}\r\n", name));
+            }
+            return;
+        }
+
+        match tz::set_timezone(parts[1]) {
+            Ok(zone) => self.write_output(&alloc::format!("Timezone set to {}\r\n", zone.name)),
+            Err(e) => self.write_output(&alloc::format!("Error: {}\r\n", e)),
+        }
+    }
+
+    fn cmd_locale(&self, parts: &[&str]) {
+        use crate::kernel::clock::locale;
+
+        if parts.len() < 2 {
+            let current = locale::current();
+            let local = crate::kernel::clock::now_local();
+            self.write_output(&alloc::format!("Locale: {} ({})\r\n",
+                current.name, current.format_datetime(&local)));
+            self.write_output("Usage: locale <name> | locale list\r\n");
+            return;
+        }
+
+        if parts[1] == "list" {
+            for name in locale::locale_names() {
+                self.write_output(&alloc::format!("  {}\r\n", name));
+            }
+            return;
+        }
+
+        match locale::set_locale(parts[1]) {
+            Ok(l) => self.write_output(&alloc::format!("Locale set to {}\r\n", l.name)),
+            Err(e) => self.write_output(&alloc::format!("Error: {}\r\n", e)),
+        }
+    }
+
+    fn cmd_uptime(&self) {
+        use crate::kernel::clock::{clock_gettime, ClockId};
+        let secs = clock_gettime(ClockId::Monotonic).sec;
+        self.write_output(&alloc::format!("up {}:{:02}:{:02}\r\n",
+            secs / 3600, (secs / 60) % 60, secs % 60));
+    }
+
     fn cmd_ifconfig(&self) {
         unsafe {
             if let Some(ref devices) = crate::kernel::NET_DEVICES {
@@ -524,27 +1391,24 @@ impl Shell {
             return;
         }
 
-        let domain = parts[1];
+        let domain = alloc::string::String::from(parts[1]);
         self.write_output(&alloc::format!("Resolving {} ...\r\n", domain));
 
-        unsafe {
-            if let Some(ref mut stack) = crate::kernel::NETWORK_STACK.as_mut() {
-                match crate::system::net::helpers::dns_lookup(stack, domain, 5000) {
-                    Ok(addresses) => {
-                        self.write_output(&alloc::format!("Resolved {} to:\r\n", domain));
-                        for addr in addresses {
-                            self.write_output(&alloc::format!("  {}.{}.{}.{}\r\n",
-                                addr[0], addr[1], addr[2], addr[3]));
-                        }
-                    }
-                    Err(e) => {
-                        self.write_output(&alloc::format!("DNS lookup failed: {}\r\n", e));
+        let console_id = self.console_id;
+        self.spawn_job(async move {
+            match crate::system::net::dns::resolve(&domain).await {
+                Ok(addresses) => {
+                    job_output(console_id, &alloc::format!("Resolved {} to:\r\n", domain));
+                    for addr in addresses {
+                        job_output(console_id, &alloc::format!("  {}.{}.{}.{}\r\n",
+                            addr[0], addr[1], addr[2], addr[3]));
                     }
                 }
-            } else {
-                self.write_output("Network stack not initialized\r\n");
+                Err(e) => {
+                    job_output(console_id, &alloc::format!("DNS lookup failed: {}\r\n", e));
+                }
             }
-        }
+        });
     }
 
     fn cmd_http(&mut self, parts: &[&str]) {
@@ -554,30 +1418,26 @@ impl Shell {
             return;
         }
 
-        let domain = parts[1];
+        let domain = alloc::string::String::from(parts[1]);
         self.write_output(&alloc::format!("HTTP GET http://{}/\r\n", domain));
 
-        unsafe {
-            if let Some(ref mut stack) = crate::kernel::NETWORK_STACK.as_mut() {
-                // Use smoltcp http_get helper
-                match crate::system::net::helpers::http_get(stack, domain, "/", 80, 10000) {
-                    Ok(response_data) => {
-                        self.write_output("---\r\n");
-                        if let Ok(text) = core::str::from_utf8(&response_data) {
-                            self.write_output(text);
-                        } else {
-                            self.write_output(&alloc::format!("Received {} bytes (binary data)\r\n", response_data.len()));
-                        }
-                        self.write_output("\r\n---\r\n");
-                    }
-                    Err(e) => {
-                        self.write_output(&alloc::format!("HTTP request failed: {}\r\n", e));
+        let console_id = self.console_id;
+        self.spawn_job(async move {
+            match crate::system::net::http::get(&domain, 80, "/").await {
+                Ok(response_data) => {
+                    job_output(console_id, "---\r\n");
+                    if let Ok(text) = core::str::from_utf8(&response_data) {
+                        job_output(console_id, text);
+                    } else {
+                        job_output(console_id, &alloc::format!("Received {} bytes (binary data)\r\n", response_data.len()));
                     }
+                    job_output(console_id, "\r\n---\r\n");
+                }
+                Err(e) => {
+                    job_output(console_id, &alloc::format!("HTTP request failed: {}\r\n", e));
                 }
-            } else {
-                self.write_output("Network stack not initialized\r\n");
             }
-        }
+        });
     }
 
     fn cmd_download(&mut self, parts: &[&str]) {
@@ -614,7 +1474,7 @@ impl Shell {
         };
 
         // Extract filename from path
-        let mut filename = if let Some(last_slash) = path.rfind('/') {
+        let filename = if let Some(last_slash) = path.rfind('/') {
             &path[last_slash + 1..]
         } else {
             path
@@ -624,107 +1484,49 @@ impl Shell {
             self.write_output("Cannot extract filename from URL\r\n");
             return;
         }
-
-        // Normalize common long extensions to shorter versions
-        let filename = if filename.ends_with(".jpeg") {
-            alloc::format!("{}.jpg", &filename[..filename.len()-5])
-        } else {
-            alloc::string::String::from(filename)
-        };
-        let filename = filename.as_str();
-
-        // SimpleFS has 8-character filename limit - truncate if needed
-        let final_filename = if filename.len() > 8 {
-            // Try to preserve extension
-            if let Some(dot_pos) = filename.rfind('.') {
-                let ext = &filename[dot_pos..]; // includes the dot
-                let name = &filename[..dot_pos];
-
-                // If extension is reasonable length (<=4 chars including dot), keep it
-                if ext.len() <= 4 && ext.len() < filename.len() {
-                    let max_name_len = 8 - ext.len();
-                    if name.len() > max_name_len {
-                        alloc::format!("{}{}", &name[..max_name_len], ext)
-                    } else {
-                        alloc::string::String::from(filename)
-                    }
-                } else {
-                    // Extension too long, just truncate whole filename
-                    alloc::string::String::from(&filename[..8])
-                }
-            } else {
-                // No extension, just truncate
-                alloc::string::String::from(&filename[..8])
-            }
-        } else {
-            alloc::string::String::from(filename)
-        };
-
-        if final_filename != filename {
-            self.write_output(&alloc::format!("Note: Filename truncated to '{}' (8 char limit)\r\n", final_filename));
-        }
+        let final_filename = self.path(filename);
 
         self.write_output(&alloc::format!("Downloading http://{}:{}{}\r\n", host, port, path));
         self.write_output(&alloc::format!("Saving to: {}\r\n", final_filename));
 
-        // Download file using smoltcp
-        let response_data = unsafe {
-            if let Some(ref mut stack) = crate::kernel::NETWORK_STACK.as_mut() {
-                match crate::system::net::helpers::http_get(stack, host, path, port, 30000) {
-                    Ok(response_data) => {
-                        self.write_output(&alloc::format!("Downloaded {} bytes (with headers)\r\n", response_data.len()));
-                        response_data
-                    }
-                    Err(e) => {
-                        self.write_output(&alloc::format!("Download failed: {}\r\n", e));
-                        return;
-                    }
+        let console_id = self.console_id;
+        let host = alloc::string::String::from(host);
+        let path = alloc::string::String::from(path);
+        self.spawn_job(async move {
+            let response_data = match crate::system::net::http::get(&host, port, &path).await {
+                Ok(response_data) => {
+                    job_output(console_id, &alloc::format!("Downloaded {} bytes (with headers)\r\n", response_data.len()));
+                    response_data
                 }
+                Err(e) => {
+                    job_output(console_id, &alloc::format!("Download failed: {}\r\n", e));
+                    return;
+                }
+            };
+
+            let data = crate::system::net::http::body(&response_data);
+            if data.len() == response_data.len() {
+                job_output(console_id, "Warning: Could not find HTTP header separator, saving entire response\r\n");
             } else {
-                self.write_output("Network stack not initialized\r\n");
-                return;
+                job_output(console_id, &alloc::format!("Body size: {} bytes\r\n", data.len()));
             }
-        };
 
-        // Extract body from HTTP response (strip headers)
-        let data = if let Some(body_start) = response_data.windows(4).position(|w| w == b"\r\n\r\n") {
-            let body = &response_data[body_start + 4..];
-            self.write_output(&alloc::format!("Body size: {} bytes\r\n", body.len()));
-            body
-        } else {
-            self.write_output("Warning: Could not find HTTP header separator, saving entire response\r\n");
-            &response_data[..]
-        };
+            // The window may have been closed while we were downloading
+            if let Some(shell) = shell_for_console(console_id) {
+                shell.save_download(&final_filename, data);
+            }
+        });
+    }
 
-        // Save to filesystem
-        let result = if let (Some(ref mut fs), Some(idx)) = (&mut self.filesystem, self.device_index) {
-            unsafe {
-                if let Some(ref mut devices) = crate::kernel::BLOCK_DEVICES {
-                    if let Some(device) = devices.get_mut(idx) {
-                        // Create file with appropriate size
-                        match fs.create_file(device, &final_filename, data.len() as u32) {
-                            Ok(()) => {
-                                // Write data to file
-                                match fs.write_file(device, &final_filename, &data) {
-                                    Ok(()) => {
-                                        // Refresh file explorers
-                                        crate::gui::widgets::file_explorer::refresh_all_explorers();
-                                        Ok(data.len())
-                                    }
-                                    Err(e) => Err(alloc::format!("Failed to write file: {}", e))
-                                }
-                            }
-                            Err(e) => Err(alloc::format!("Failed to create file: {}", e))
-                        }
-                    } else {
-                        Err(alloc::string::String::from("Block device not available"))
-                    }
-                } else {
-                    Err(alloc::string::String::from("Block devices not initialized"))
-                }
+    /// Write a finished download to the filesystem and report the result
+    fn save_download(&mut self, final_filename: &str, data: &[u8]) {
+        let result = match vfs::write_file(final_filename, data) {
+            Ok(()) => {
+                // Refresh file explorers
+                crate::gui::widgets::file_explorer::refresh_all_explorers();
+                Ok(data.len())
             }
-        } else {
-            Err(alloc::string::String::from("Filesystem not mounted"))
+            Err(e) => Err(alloc::format!("Failed to save file: {}", e)),
         };
 
         // Output results
@@ -748,29 +1550,33 @@ static mut SHELLS: alloc::vec::Vec<Shell> = alloc::vec::Vec::new();
 
 /// Create a new shell instance for a console
 pub fn create_shell(console_id: usize) {
+    unsafe {
+        let shell = Shell::new(console_id);
+        shell.show_prompt();
+        SHELLS.push(shell);
+    }
+}
+
+/// Create a shell that starts by asking for the passphrase of encrypted
+/// device `device`
+pub fn create_unlock_shell(console_id: usize, device: usize) {
     unsafe {
         let mut shell = Shell::new(console_id);
+        shell.ask_passphrase(PassphrasePrompt::Unlock(device));
+        SHELLS.push(shell);
+    }
+}
 
-        // Initialize filesystem if block device is available
-        if let Some(ref mut devices) = crate::kernel::BLOCK_DEVICES {
-            if !devices.is_empty() {
-                let device_idx = 0;
-                if let Some(device) = devices.get_mut(device_idx) {
-                    match crate::system::fs::filesystem::SimpleFilesystem::mount(device) {
-                        Ok(fs) => {
-                            shell.set_filesystem(fs, device_idx);
-                            shell.write_output("Filesystem mounted\r\n");
-                        }
-                        Err(e) => {
-                            shell.write_output(&alloc::format!("Failed to mount: {}\r\n", e));
-                        }
-                    }
-                }
-            }
+/// The shell for the host end of the shell port, created on first use. Its
+/// console has no window, so output only shows on the host.
+pub fn remote_shell() -> &'static mut Shell {
+    unsafe {
+        if !SHELLS.iter().any(|shell| shell.remote) {
+            let mut shell = Shell::new(console::create_console());
+            shell.remote = true;
+            SHELLS.push(shell);
         }
-
-        shell.show_prompt();
-        SHELLS.push(shell);
+        SHELLS.iter_mut().find(|shell| shell.remote).unwrap()
     }
 }
 
@@ -778,6 +1584,9 @@ pub fn create_shell(console_id: usize) {
 pub fn remove_shell(id: usize) {
     unsafe {
         if id < SHELLS.len() {
+            if let Some(job) = SHELLS[id].job {
+                executor::cancel(job);
+            }
             SHELLS.remove(id);
         }
     }
@@ -789,3 +1598,110 @@ pub fn get_shell(id: usize) -> Option<&'static mut Shell> {
         SHELLS.get_mut(id)
     }
 }
+
+/// Find the shell attached to a console. Background jobs look their shell up
+/// again when they finish, since it may have been closed in the meantime.
+fn shell_for_console(console_id: usize) -> Option<&'static mut Shell> {
+    unsafe {
+        SHELLS.iter_mut().find(|shell| shell.console_id == console_id)
+    }
+}
+
+/// The virtio disk behind a disk name: vda, vdb, ... in the order found at boot
+fn virtio_disk(name: &str) -> Option<crate::kernel::drivers::virtio::blk::VirtioBlkDevice> {
+    match name.as_bytes() {
+        &[b'v', b'd', letter] if letter.is_ascii_lowercase() => {
+            crate::kernel::drivers::virtio::blk::disk((letter - b'a') as usize)
+        }
+        _ => None,
+    }
+}
+
+/// What `lsblk` shows under a virtio disk: block size, geometry and features
+fn format_disk_info(info: &crate::kernel::drivers::virtio::blk::DiskInfo) -> alloc::string::String {
+    let mut text = alloc::format!("         {}-byte blocks", info.block_size);
+    if let Some((cylinders, heads, sectors)) = info.geometry {
+        text.push_str(&alloc::format!(", CHS {}/{}/{}", cylinders, heads, sectors));
+    }
+    for (supported, feature) in [
+        (info.flush, "flush"),
+        (info.max_discard_sectors.is_some(), "discard"),
+        (info.max_write_zeroes_sectors.is_some(), "write-zeroes"),
+    ] {
+        if supported {
+            text.push_str(", ");
+            text.push_str(feature);
+        }
+    }
+    text.push_str("\r\n");
+    text
+}
+
+/// Write output from a background job to its shell (dropped if it was closed)
+fn job_output(console_id: usize, s: &str) {
+    if let Some(shell) = shell_for_console(console_id) {
+        shell.write_output(s);
+    }
+}
+
+/// File time for `ls -l`, or "-" if the filesystem didn't record one
+fn format_timestamp(timestamp: i64) -> alloc::string::String {
+    if timestamp == 0 {
+        alloc::string::String::from("-")
+    } else {
+        crate::kernel::clock::locale::format_file_timestamp(timestamp)
+    }
+}
+
+/// Encrypt device `idx` with `passphrase`, unlock it and put a fresh SimpleFS
+/// on it. Returns the mount point.
+fn format_encrypted(idx: usize, passphrase: &str) -> Result<alloc::string::String, &'static str> {
+    use crate::system::block::{self, crypt, registry};
+
+    let device = block::device(idx).ok_or("No such block device")?;
+    crypt::format(device, passphrase.as_bytes(), crypt::DEFAULT_ITERATIONS)?;
+    let volume = registry::unlock(idx, passphrase)?;
+    fs::SimpleFilesystem::format(block::device(volume).ok_or("No such block device")?)?;
+    vfs::mount(volume)
+}
+
+/// Block count as a human readable size
+fn format_blocks(blocks: u64) -> alloc::string::String {
+    format_bytes(blocks * crate::system::block::BLOCK_SIZE as u64)
+}
+
+/// Add the file or directory tree at `path` to a tar as `name`, counting files
+fn add_to_tar(builder: &mut tar::Builder, path: &str, name: &str, files: &mut usize) -> Result<(), &'static str> {
+    let info = vfs::stat(path)?;
+    if !info.is_dir {
+        builder.add_file(name, &vfs::read_file(path)?, info.modified);
+        *files += 1;
+        return Ok(());
+    }
+    builder.add_dir(name, info.modified);
+    for child in vfs::list_dir(path)? {
+        add_to_tar(builder, &vfs::join(path, &child.name), &alloc::format!("{}/{}", name, child.name), files)?;
+    }
+    Ok(())
+}
+
+/// Byte count as a human readable size
+fn format_bytes(bytes: u64) -> alloc::string::String {
+    if bytes >= 1024 * 1024 * 1024 {
+        alloc::format!("{} GB", bytes / (1024 * 1024 * 1024))
+    } else if bytes >= 1024 * 1024 {
+        alloc::format!("{} MB", bytes / (1024 * 1024))
+    } else {
+        alloc::format!("{} KB", bytes / 1024)
+    }
+}
+
+/// How broken up a volume's free space and files are, if it keeps track
+fn fragmentation(usage: &crate::system::fs::Usage) -> Option<alloc::string::String> {
+    let (runs, largest) = usage.free_runs?;
+    let mut line = alloc::format!("free space in {} piece(s), largest {}", runs, format_bytes(largest));
+    if let Some((fragmented, files)) = usage.fragmented_files {
+        line.push_str(&alloc::format!("; {} of {} file(s) fragmented", fragmented, files));
+    }
+    Some(line)
+}
@@ -1,5 +1,6 @@
 use alloc::vec::Vec;
 use crate::kernel::drivers::timer::get_time_us;
+use crate::kernel::random::random_range;
 extern crate alloc;
 
 const GRID_SIZE: usize = 20; // 20x20 grid
@@ -30,8 +31,6 @@ pub struct SnakeGame {
     game_over: bool,
     score: u32,
     last_update: u64,
-    // Random seed for food generation
-    rng_state: u64,
 }
 
 impl SnakeGame {
@@ -44,7 +43,6 @@ impl SnakeGame {
             game_over: false,
             score: 0,
             last_update: get_time_us(),
-            rng_state: get_time_us(),
         };
 
         // Initialize snake in the middle
@@ -136,13 +134,9 @@ impl SnakeGame {
     }
 
     fn spawn_food(&mut self) {
-        // Simple LCG random number generator
         loop {
-            self.rng_state = self.rng_state.wrapping_mul(1103515245).wrapping_add(12345);
-            let x = ((self.rng_state >> 16) % GRID_SIZE as u64) as i32;
-
-            self.rng_state = self.rng_state.wrapping_mul(1103515245).wrapping_add(12345);
-            let y = ((self.rng_state >> 16) % GRID_SIZE as u64) as i32;
+            let x = random_range(GRID_SIZE as u32) as i32;
+            let y = random_range(GRID_SIZE as u32) as i32;
 
             let pos = Position { x, y };
 
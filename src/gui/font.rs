@@ -25,61 +25,40 @@ fn try_load_font() {
 
         crate::kernel::uart_write_string("[FONT] Attempting to load i24.ttf from filesystem...\r\n");
 
-        // Access global block devices and mount filesystem directly
-        if let Some(ref mut devices) = crate::kernel::BLOCK_DEVICES {
-            crate::kernel::uart_write_string("[FONT] Block devices available\r\n");
-
-            if devices.is_empty() {
-                crate::kernel::uart_write_string("[FONT] ✗ No block devices found\r\n");
+        // Fonts live in the top directory of the root volume
+        let dir = crate::system::fs::vfs::home();
+        let files = match crate::system::fs::vfs::list_dir(&dir) {
+            Ok(files) => files,
+            Err(e) => {
+                crate::kernel::uart_write_string(&alloc::format!("[FONT] ✗ Can't list {}: {}\r\n", dir, e));
                 return;
             }
+        };
+        crate::kernel::uart_write_string(&alloc::format!("[FONT] Found {} files in {}\r\n", files.len(), dir));
+
+        if let Some(file) = files.iter().find(|f| f.name.eq_ignore_ascii_case("i24.ttf")) {
+            crate::kernel::uart_write_string(&alloc::format!("[FONT] i24.ttf found! Size: {} bytes\r\n", file.size));
+
+            match crate::system::fs::vfs::read_file(&crate::system::fs::vfs::join(&dir, &file.name)) {
+                Ok(buffer) => {
+                    crate::kernel::uart_write_string("[FONT] File read successfully\r\n");
 
-            let device_idx = 0;
-            let device = &mut devices[device_idx];
-
-            // Mount the filesystem directly
-            match crate::system::fs::filesystem::SimpleFilesystem::mount(device) {
-                Ok(fs) => {
-                    crate::kernel::uart_write_string("[FONT] Filesystem mounted successfully\r\n");
-
-                    // Get file list
-                    let files = fs.list_files();
-                    crate::kernel::uart_write_string(&alloc::format!("[FONT] Found {} files in filesystem\r\n", files.len()));
-
-                    if let Some(file) = files.iter().find(|f| f.get_name() == "i24.ttf") {
-                        let size = file.get_size_bytes() as usize;
-                        crate::kernel::uart_write_string(&alloc::format!("[FONT] i24.ttf found! Size: {} bytes\r\n", size));
-
-                        let mut buffer = alloc::vec![0u8; size];
-
-                        match fs.read_file(device, "i24.ttf", &mut buffer) {
-                            Ok(_) => {
-                                crate::kernel::uart_write_string("[FONT] File read successfully\r\n");
-
-                                match Font::from_bytes(buffer, fontdue::FontSettings::default()) {
-                                    Ok(font) => {
-                                        FONT = Some(font);
-                                        crate::kernel::uart_write_string("[FONT] ✓ TrueType font loaded successfully!\r\n");
-                                    }
-                                    Err(_) => {
-                                        crate::kernel::uart_write_string("[FONT] ✗ Failed to parse TTF font\r\n");
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                crate::kernel::uart_write_string(&alloc::format!("[FONT] ✗ Failed to read i24.ttf: {}\r\n", e));
-                            }
+                    match Font::from_bytes(buffer, fontdue::FontSettings::default()) {
+                        Ok(font) => {
+                            FONT = Some(font);
+                            crate::kernel::uart_write_string("[FONT] ✓ TrueType font loaded successfully!\r\n");
+                        }
+                        Err(_) => {
+                            crate::kernel::uart_write_string("[FONT] ✗ Failed to parse TTF font\r\n");
                         }
-                    } else {
-                        crate::kernel::uart_write_string("[FONT] ✗ i24.ttf not found in filesystem\r\n");
                     }
                 }
                 Err(e) => {
-                    crate::kernel::uart_write_string(&alloc::format!("[FONT] ✗ Failed to mount filesystem: {}\r\n", e));
+                    crate::kernel::uart_write_string(&alloc::format!("[FONT] ✗ Failed to read i24.ttf: {}\r\n", e));
                 }
             }
         } else {
-            crate::kernel::uart_write_string("[FONT] ✗ No block devices available\r\n");
+            crate::kernel::uart_write_string("[FONT] ✗ i24.ttf not found in filesystem\r\n");
         }
     }
 }
@@ -171,12 +171,11 @@ pub fn extract_css_urls(node: &Node, base_url: &str) -> Vec<String> {
                     if rel.to_lowercase().contains("stylesheet") {
                         if let Some(href) = elem.attributes.get("href") {
                             // Resolve relative URLs
-                            let css_url = if href.starts_with("http://") || href.starts_with("https://") {
+                            let css_url = if super::http::is_absolute(href) {
                                 href.clone()
                             } else if href.starts_with('/') {
                                 // Absolute path - use current host
-                                let (host, port, _) = super::http::parse_url(base_url);
-                                alloc::format!("http://{}:{}{}", host, port, href)
+                                super::http::resolve_path(base_url, href)
                             } else {
                                 // Relative path - append to current URL's directory
                                 let base = if let Some(last_slash) = base_url.rfind('/') {
@@ -707,12 +706,11 @@ pub fn layout_element(
                 crate::kernel::uart_write_string(&alloc::format!("layout_element: Found <img src=\"{}\">\r\n", src));
 
                 // Parse the image URL (resolve relative URLs)
-                let img_url = if src.starts_with("http://") || src.starts_with("https://") {
+                let img_url = if super::http::is_absolute(src) {
                     src.clone()
                 } else if src.starts_with('/') {
                     // Absolute path - use current host
-                    let (host, port, _) = super::http::parse_url(&browser.url);
-                    alloc::format!("http://{}:{}{}", host, port, src)
+                    super::http::resolve_path(&browser.url, src)
                 } else {
                     // Relative path - append to current URL's directory
                     let base_url = if let Some(last_slash) = browser.url.rfind('/') {
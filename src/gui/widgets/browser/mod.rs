@@ -20,11 +20,14 @@ use crate::gui::bmp_decoder::BmpImage;
 use crate::gui::bmp_decoder::decode_bmp;
 use crate::gui::png_decoder::decode_png;
 use crate::gui::jpeg_decoder::decode_jpeg;
-use smoltcp::iface::SocketHandle;
+use crate::kernel::executor::{self, TaskId};
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 use alloc::format;
 
+/// Resource loads (stylesheets + images) in flight per browser
+const MAX_CONCURRENT_LOADS: usize = 4;
+
 /// Global list of browser instances
 static mut BROWSERS: Vec<Browser> = Vec::new();
 
@@ -42,20 +45,19 @@ pub struct Browser {
     pub page_title: Option<String>,
     pub instance_id: usize, // ID for updating window title
 
-    // Async HTTP state
-    http_state: HttpState,
+    // Executor tasks for the page and its resources (cancelled on navigation)
+    tasks: Vec<TaskId>,
+    needs_redraw: bool,
 
-    // Async image loading
+    // Resources queued by layout, and those currently being fetched
     pending_images: Vec<PendingImage>,
-    image_load_state: ImageLoadState,
+    pending_css: Vec<PendingCss>,
+    loading_urls: Vec<String>,
+    active_loads: usize,
 
     // Image cache to prevent re-downloading on layout reflow
     image_cache: alloc::collections::BTreeMap<String, BmpImage>,
 
-    // Async CSS loading
-    pending_css: Vec<PendingCss>,
-    css_load_state: CssLoadState,
-
     // Loaded stylesheets
     pub stylesheets: Vec<Stylesheet>,
 
@@ -78,620 +80,200 @@ impl Browser {
             loading: false,
             page_title: None,
             instance_id,
-            http_state: HttpState::Idle,
+            tasks: Vec::new(),
+            needs_redraw: false,
             pending_images: Vec::new(),
-            image_load_state: ImageLoadState::Idle,
-            image_cache: alloc::collections::BTreeMap::new(),
             pending_css: Vec::new(),
-            css_load_state: CssLoadState::Idle,
+            loading_urls: Vec::new(),
+            active_loads: 0,
+            image_cache: alloc::collections::BTreeMap::new(),
             stylesheets: Vec::new(),
             last_window_width: 0,
         }
     }
 
-    /// Poll async HTTP request state machine (called each frame)
+    /// Start queued stylesheet and image loads (called each frame)
     /// Returns true if display needs redraw
-    pub fn poll_http(&mut self) -> bool {
-        // Move state out temporarily to avoid borrow checker issues
-        let current_state = core::mem::replace(&mut self.http_state, HttpState::Idle);
-
-        let mut needs_redraw = false;
-
-        self.http_state = match current_state {
-            HttpState::Idle => HttpState::Idle,
-
-            HttpState::ResolvingDns { host, path, port, start_time } => {
-                // Try to resolve DNS
-                unsafe {
-                    if let Some(ref mut stack) = crate::kernel::NETWORK_STACK {
-                        // Check if host is already an IP (instant)
-                        let server_ip = if let Some(ip) = crate::system::net::network::parse_ip(&host) {
-                            Some(smoltcp::wire::IpAddress::Ipv4(smoltcp::wire::Ipv4Address::new(ip[0], ip[1], ip[2], ip[3])))
-                        } else {
-                            // Need DNS - use blocking dns_lookup for now (we'll make it async later)
-                            match crate::system::net::helpers::dns_lookup(stack, &host, 5000) {
-                                Ok(addresses) => {
-                                    if addresses.is_empty() {
-                                        None
-                                    } else {
-                                        let ip = addresses[0];
-                                        Some(smoltcp::wire::IpAddress::Ipv4(smoltcp::wire::Ipv4Address::new(ip[0], ip[1], ip[2], ip[3])))
-                                    }
-                                }
-                                Err(_) => None,
-                            }
-                        };
-
-                        if let Some(server_ip) = server_ip {
-                                crate::kernel::uart_write_string(&alloc::format!("DNS resolved, connecting...\r\n"));
-
-                                // Create TCP socket
-                                let tcp_handle = stack.create_tcp_socket();
-
-                                // Generate dynamic local port
-                                static mut LOCAL_PORT_COUNTER: u16 = 49152;
-                                let local_port = unsafe {
-                                    let port = LOCAL_PORT_COUNTER;
-                                    LOCAL_PORT_COUNTER = if LOCAL_PORT_COUNTER >= 65000 { 49152 } else { LOCAL_PORT_COUNTER + 1 };
-                                    port
-                                };
-
-                                // Initiate connection
-                                let remote_endpoint = smoltcp::wire::IpEndpoint::new(server_ip, port);
-                                if let Err(_) = stack.tcp_connect(tcp_handle, remote_endpoint, local_port) {
-                                    stack.remove_socket(tcp_handle);
-                                    HttpState::Error {
-                                        message: "Failed to initiate TCP connection".to_string(),
-                                    }
-                                } else {
-                                    // Build HTTP request
-                                    let http_request = alloc::format!(
-                                        "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
-                                        path, host
-                                    );
-
-                                    HttpState::Connecting {
-                                        socket_handle: tcp_handle,
-                                        http_request,
-                                        start_time: crate::kernel::drivers::timer::get_time_ms(),
-                                    }
-                                }
-                            } else {
-                                // DNS still resolving, stay in this state
-                                HttpState::ResolvingDns { host, path, port, start_time }
-                            }
-                        } else {
-                            HttpState::Error {
-                                message: "No network stack available".to_string(),
-                            }
-                        }
-                    }
+    pub fn poll_loads(&mut self) -> bool {
+        // Stylesheets first - they trigger a reflow, which can move images
+        while self.active_loads < MAX_CONCURRENT_LOADS && !self.pending_css.is_empty() {
+            let pending = self.pending_css.remove(0);
+            if !self.loading_urls.contains(&pending.url) {
+                self.spawn_css_load(pending.url);
             }
+        }
 
-            HttpState::Connecting { socket_handle, http_request, start_time } => {
-                // Check timeout (10 seconds for connection)
-                if crate::kernel::drivers::timer::get_time_ms() - start_time > 10000 {
-                    unsafe {
-                        if let Some(ref mut stack) = crate::kernel::NETWORK_STACK {
-                            stack.remove_socket(socket_handle);
-                        }
-                    }
-                    HttpState::Error {
-                        message: "Connection timeout".to_string(),
-                    }
-                } else {
-                    unsafe {
-                        if let Some(ref mut stack) = crate::kernel::NETWORK_STACK {
-                            let is_connected = stack.with_tcp_socket(socket_handle, |socket| {
-                                socket.may_send() && socket.may_recv()
-                            });
-
-                            if is_connected {
-                                crate::kernel::uart_write_string("Connected, sending request...\r\n");
-
-                                // Send HTTP request
-                                stack.with_tcp_socket(socket_handle, |socket| {
-                                    socket.send_slice(http_request.as_bytes()).ok();
-                                });
-
-                                HttpState::ReceivingResponse {
-                                    socket_handle,
-                                    response_data: Vec::new(),
-                                    last_recv_time: crate::kernel::drivers::timer::get_time_ms(),
-                                }
-                            } else {
-                                // Still connecting
-                                HttpState::Connecting { socket_handle, http_request, start_time }
-                            }
-                        } else {
-                            HttpState::Error {
-                                message: "Network stack disappeared".to_string(),
-                            }
-                        }
-                    }
-                }
+        // Images in document order, so the top of the page fills in first
+        while self.active_loads < MAX_CONCURRENT_LOADS && !self.pending_images.is_empty() {
+            let pending = self.pending_images.remove(0);
+            if !self.loading_urls.contains(&pending.url) && !self.image_cache.contains_key(&pending.url) {
+                self.spawn_image_load(pending);
             }
+        }
 
-            HttpState::ReceivingResponse { socket_handle, mut response_data, last_recv_time } => {
-                unsafe {
-                    if let Some(ref mut stack) = crate::kernel::NETWORK_STACK {
-                        let mut received_data = false;
-                        let mut connection_closed = false;
-
-                        stack.with_tcp_socket(socket_handle, |socket| {
-                            // Receive data
-                            while socket.can_recv() {
-                                if let Ok(_) = socket.recv(|buffer| {
-                                    let len = buffer.len();
-                                    if len > 0 {
-                                        response_data.extend_from_slice(buffer);
-                                        received_data = true;
-                                    }
-                                    (len, ())
-                                }) {}
-                            }
-
-                            // Check if connection closed
-                            if !socket.may_recv() {
-                                connection_closed = true;
-                            }
-                        });
-
-                        let new_last_recv_time = if received_data {
-                            crate::kernel::drivers::timer::get_time_ms()
-                        } else {
-                            last_recv_time
-                        };
-
-                        if connection_closed {
-                            crate::kernel::uart_write_string(&alloc::format!("Received {} bytes total\r\n", response_data.len()));
-                            stack.remove_socket(socket_handle);
-
-                            // Parse HTTP response
-                            if let Ok(response) = core::str::from_utf8(&response_data) {
-                                // Find the blank line that separates headers from body
-                                let html = if let Some(body_start) = response.find("\r\n\r\n") {
-                                    response[body_start + 4..].to_string()
-                                } else if let Some(body_start) = response.find("\n\n") {
-                                    response[body_start + 2..].to_string()
-                                } else {
-                                    response.to_string()
-                                };
-
-                                HttpState::Complete { html }
-                            } else {
-                                HttpState::Error {
-                                    message: "Invalid UTF-8 in response".to_string(),
-                                }
-                            }
-                        } else {
-                            // Check timeout (30 seconds of no data)
-                            if crate::kernel::drivers::timer::get_time_ms() - new_last_recv_time > 30000 {
-                                stack.remove_socket(socket_handle);
-                                HttpState::Error {
-                                    message: "Receive timeout".to_string(),
-                                }
-                            } else {
-                                HttpState::ReceivingResponse {
-                                    socket_handle,
-                                    response_data,
-                                    last_recv_time: new_last_recv_time,
-                                }
-                            }
-                        }
-                    } else {
-                        HttpState::Error {
-                            message: "Network stack disappeared".to_string(),
-                        }
-                    }
-                }
+        core::mem::replace(&mut self.needs_redraw, false)
+    }
+
+    /// Cancel every in-flight load (page, stylesheets and images)
+    pub(crate) fn cancel_loads(&mut self) {
+        for task in self.tasks.drain(..) {
+            executor::cancel(task);
+        }
+        self.pending_images.clear();
+        self.pending_css.clear();
+        self.loading_urls.clear();
+        self.active_loads = 0;
+    }
+
+    /// Fetch a page in the background; the result replaces the current DOM
+    pub(crate) fn spawn_page_load(&mut self, url: String) {
+        let browser_id = self.instance_id;
+        let task = executor::spawn(async move {
+            let result = http::fetch(&url).await;
+            if let Some(browser) = get_browser(browser_id) {
+                browser.finish_page_load(result);
             }
+        });
+        self.tasks.push(task);
+    }
 
-            HttpState::Complete { html } => {
-                crate::kernel::uart_write_string(&alloc::format!("Page loaded, {} bytes\r\n", html.len()));
+    fn finish_page_load(&mut self, result: Result<Vec<u8>, &'static str>) {
+        let html = result.and_then(|body| String::from_utf8(body).map_err(|_| "Invalid UTF-8 in response"));
+        match html {
+            Ok(html) => {
+                crate::kernel::uart_write_string(&format!("Page loaded, {} bytes\r\n", html.len()));
                 layout::load_html(self, html);
-                self.loading = false;
-                needs_redraw = true; // Trigger redraw!
-                HttpState::Idle
             }
+            Err(message) => {
+                crate::kernel::uart_write_string(&format!("HTTP error: {}\r\n", message));
+                navigation::load_error_page(self, message);
+            }
+        }
+        self.loading = false;
+        self.needs_redraw = true;
+    }
+
+    fn spawn_css_load(&mut self, url: String) {
+        crate::kernel::uart_write_string(&format!("Starting async CSS load: {}\r\n", url));
 
-            HttpState::Error { message } => {
-                crate::kernel::uart_write_string(&alloc::format!("HTTP error: {}\r\n", message));
-                navigation::load_error_page(self, &message);
-                self.loading = false;
-                needs_redraw = true; // Trigger redraw!
-                HttpState::Idle
+        let browser_id = self.instance_id;
+        self.loading_urls.push(url.clone());
+        self.active_loads += 1;
+        let task = executor::spawn(async move {
+            let result = http::fetch(&url).await;
+            if let Some(browser) = get_browser(browser_id) {
+                browser.finish_css_load(&url, result);
             }
-        };
+        });
+        self.tasks.push(task);
+    }
 
-        // Also poll async image loading
-        if let ImageLoadState::Idle = self.image_load_state {
-            // Start next pending image load
-            if let Some(pending) = self.pending_images.pop() {
-                crate::kernel::uart_write_string(&alloc::format!("Starting async image load: {}\r\n", pending.url));
-
-                // Parse URL and initiate TCP connection
-                let (host, port, path) = http::parse_url(&pending.url);
-                let format = if pending.url.ends_with(".png") {
-                    ImageFormat::Png
-                } else if pending.url.ends_with(".jpg") || pending.url.ends_with(".jpeg") {
-                    ImageFormat::Jpeg
-                } else {
-                    ImageFormat::Bmp
-                };
-
-                unsafe {
-                    if let Some(ref mut stack) = crate::kernel::NETWORK_STACK {
-                        // Resolve DNS
-                        let server_ip = if let Some(ip) = crate::system::net::network::parse_ip(&host) {
-                            Some(smoltcp::wire::IpAddress::Ipv4(smoltcp::wire::Ipv4Address::new(ip[0], ip[1], ip[2], ip[3])))
-                        } else {
-                            match crate::system::net::helpers::dns_lookup(stack, &host, 5000) {
-                                Ok(addresses) => {
-                                    if !addresses.is_empty() {
-                                        let ip = addresses[0];
-                                        Some(smoltcp::wire::IpAddress::Ipv4(smoltcp::wire::Ipv4Address::new(ip[0], ip[1], ip[2], ip[3])))
-                                    } else {
-                                        None
-                                    }
-                                }
-                                Err(_) => None,
-                            }
-                        };
-
-                        if let Some(server_ip) = server_ip {
-                            let tcp_handle = stack.create_tcp_socket();
-
-                            static mut IMG_LOCAL_PORT: u16 = 50000;
-                            let local_port = unsafe {
-                                let port = IMG_LOCAL_PORT;
-                                IMG_LOCAL_PORT = if IMG_LOCAL_PORT >= 60000 { 50000 } else { IMG_LOCAL_PORT + 1 };
-                                port
-                            };
-
-                            let remote_endpoint = smoltcp::wire::IpEndpoint::new(server_ip, port);
-                            if stack.tcp_connect(tcp_handle, remote_endpoint, local_port).is_ok() {
-                                // Prepare HTTP request
-                                let http_request = alloc::format!(
-                                    "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
-                                    path, host
-                                );
-
-                                self.image_load_state = ImageLoadState::Connecting {
-                                    socket_handle: tcp_handle,
-                                    http_request,
-                                    start_time: crate::kernel::drivers::timer::get_time_ms(),
-                                    layout_box_index: pending.layout_box_index,
-                                    format,
-                                    url: pending.url.clone(),
-                                };
-                            }
-                        }
-                    }
-                }
+    fn finish_css_load(&mut self, url: &str, result: Result<Vec<u8>, &'static str>) {
+        self.loading_urls.retain(|u| u != url);
+        self.active_loads = self.active_loads.saturating_sub(1);
+
+        let css_data = match result {
+            Ok(data) => data,
+            Err(e) => {
+                crate::kernel::uart_write_string(&format!("CSS load failed: {}: {}\r\n", url, e));
+                return;
             }
-        } else {
-            // Poll current image load
-            let current_state = core::mem::replace(&mut self.image_load_state, ImageLoadState::Idle);
-
-            self.image_load_state = match current_state {
-                ImageLoadState::Idle => ImageLoadState::Idle,
-
-                ImageLoadState::Connecting { socket_handle, http_request, start_time, layout_box_index, format, url } => {
-                    unsafe {
-                        if let Some(ref mut stack) = crate::kernel::NETWORK_STACK {
-                            let connected = stack.with_tcp_socket(socket_handle, |socket| {
-                                socket.may_send() && socket.may_recv()
-                            });
-
-                            if connected {
-                                // Send HTTP request
-                                stack.with_tcp_socket(socket_handle, |socket| {
-                                    socket.send_slice(http_request.as_bytes()).ok();
-                                });
-
-                                ImageLoadState::Loading {
-                                    socket_handle,
-                                    response_data: Vec::new(),
-                                    last_recv_time: crate::kernel::drivers::timer::get_time_ms(),
-                                    layout_box_index,
-                                    format,
-                                    url,
-                                }
-                            } else if crate::kernel::drivers::timer::get_time_ms() - start_time > 10000 {
-                                stack.remove_socket(socket_handle);
-                                ImageLoadState::Idle
-                            } else {
-                                ImageLoadState::Connecting { socket_handle, http_request, start_time, layout_box_index, format, url }
-                            }
-                        } else {
-                            ImageLoadState::Idle
-                        }
-                    }
-                }
+        };
 
-                ImageLoadState::Loading { socket_handle, mut response_data, last_recv_time, layout_box_index, format, url } => {
-                    unsafe {
-                        if let Some(ref mut stack) = crate::kernel::NETWORK_STACK {
-                            let mut received_data = false;
-                            let mut connection_closed = false;
-
-                            stack.with_tcp_socket(socket_handle, |socket| {
-                                while socket.can_recv() {
-                                    if let Ok(_) = socket.recv(|buffer| {
-                                        let len = buffer.len();
-                                        if len > 0 {
-                                            response_data.extend_from_slice(buffer);
-                                            received_data = true;
-                                        }
-                                        (len, ())
-                                    }) {}
-                                }
-
-                                if !socket.may_recv() {
-                                    connection_closed = true;
-                                }
-                            });
-
-                            let new_last_recv_time = if received_data {
-                                crate::kernel::drivers::timer::get_time_ms()
-                            } else {
-                                last_recv_time
-                            };
-
-                            if connection_closed {
-                                stack.remove_socket(socket_handle);
-
-                                // Parse HTTP response and decode image
-                                if let Some(body_start) = response_data.windows(4).position(|w| w == b"\r\n\r\n") {
-                                    let image_data = &response_data[body_start + 4..];
-
-                                    let decoded_image = match format {
-                                        ImageFormat::Png => decode_png(image_data),
-                                        ImageFormat::Jpeg => decode_jpeg(image_data),
-                                        ImageFormat::Bmp => decode_bmp(image_data),
-                                    };
-
-                                    if let Some(img) = decoded_image {
-                                        crate::kernel::uart_write_string(&alloc::format!("Image loaded: {}x{}\r\n", img.width, img.height));
-
-                                        // Cache the loaded image
-                                        self.image_cache.insert(url.clone(), img.clone());
-
-                                        // Check if dimensions will change (need reflow)
-                                        // Reflow if placeholder was 0x0 (no size specified in HTML)
-                                        let needs_reflow = if layout_box_index < self.layout.len() {
-                                            self.layout[layout_box_index].width == 0 &&
-                                            self.layout[layout_box_index].height == 0
-                                        } else {
-                                            false
-                                        };
-
-                                        if needs_reflow {
-                                            // Dimensions changed - need full reflow
-                                            crate::kernel::uart_write_string("Image dimensions changed, reflowing layout\r\n");
-                                            if let Some(ref dom) = self.dom.clone() {
-                                                self.layout.clear();
-                                                let layout_width = if self.last_window_width > 0 {
-                                                    self.last_window_width
-                                                } else {
-                                                    1280
-                                                };
-                                                layout::find_and_layout_body(self, &dom, 0, 0, layout_width);
-                                            }
-                                            needs_redraw = true;
-                                        } else {
-                                            // Just update the image data in place
-                                            if layout_box_index < self.layout.len() {
-                                                self.layout[layout_box_index].image_data = Some(img.clone());
-                                                self.layout[layout_box_index].text = String::new();
-                                                needs_redraw = true;
-                                            }
-                                        }
-                                    }
-                                }
-
-                                ImageLoadState::Idle
-                            } else if crate::kernel::drivers::timer::get_time_ms() - new_last_recv_time > 30000 {
-                                stack.remove_socket(socket_handle);
-                                ImageLoadState::Idle
-                            } else {
-                                ImageLoadState::Loading {
-                                    socket_handle,
-                                    response_data,
-                                    last_recv_time: new_last_recv_time,
-                                    layout_box_index,
-                                    format,
-                                    url,
-                                }
-                            }
-                        } else {
-                            ImageLoadState::Idle
-                        }
-                    }
-                }
-            };
+        if let Ok(css_text) = core::str::from_utf8(&css_data) {
+            crate::kernel::uart_write_string(&format!("CSS loaded: {} bytes\r\n", css_text.len()));
+
+            // Parse the CSS
+            let stylesheet = crate::gui::css_parser::Stylesheet::parse(css_text);
+            crate::kernel::uart_write_string(&format!("Parsed {} CSS rules\r\n", stylesheet.rules.len()));
+
+            // Add to stylesheets and reflow to apply them
+            self.stylesheets.push(stylesheet);
+            self.reflow();
+            self.needs_redraw = true;
         }
+    }
 
-        // Poll async CSS loading
-        if let CssLoadState::Idle = self.css_load_state {
-            // Start next pending CSS load
-            if let Some(pending) = self.pending_css.pop() {
-                crate::kernel::uart_write_string(&alloc::format!("Starting async CSS load: {}\r\n", pending.url));
-
-                // Parse URL and initiate TCP connection
-                let (host, port, path) = http::parse_url(&pending.url);
-
-                unsafe {
-                    if let Some(ref mut stack) = crate::kernel::NETWORK_STACK {
-                        // Resolve DNS
-                        let server_ip = if let Some(ip) = crate::system::net::network::parse_ip(&host) {
-                            Some(smoltcp::wire::IpAddress::Ipv4(smoltcp::wire::Ipv4Address::new(ip[0], ip[1], ip[2], ip[3])))
-                        } else {
-                            match crate::system::net::helpers::dns_lookup(stack, &host, 5000) {
-                                Ok(addresses) => {
-                                    if !addresses.is_empty() {
-                                        let ip = addresses[0];
-                                        Some(smoltcp::wire::IpAddress::Ipv4(smoltcp::wire::Ipv4Address::new(ip[0], ip[1], ip[2], ip[3])))
-                                    } else {
-                                        None
-                                    }
-                                }
-                                Err(_) => None,
-                            }
-                        };
-
-                        if let Some(server_ip) = server_ip {
-                            let tcp_handle = stack.create_tcp_socket();
-
-                            static mut CSS_LOCAL_PORT: u16 = 51000;
-                            let local_port = unsafe {
-                                let port = CSS_LOCAL_PORT;
-                                CSS_LOCAL_PORT = if CSS_LOCAL_PORT >= 61000 { 51000 } else { CSS_LOCAL_PORT + 1 };
-                                port
-                            };
-
-                            let remote_endpoint = smoltcp::wire::IpEndpoint::new(server_ip, port);
-                            if stack.tcp_connect(tcp_handle, remote_endpoint, local_port).is_ok() {
-                                // Prepare HTTP request
-                                let http_request = alloc::format!(
-                                    "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
-                                    path, host
-                                );
-
-                                self.css_load_state = CssLoadState::Connecting {
-                                    socket_handle: tcp_handle,
-                                    http_request,
-                                    start_time: crate::kernel::drivers::timer::get_time_ms(),
-                                    url: pending.url,
-                                };
-                            }
-                        }
-                    }
-                }
-            }
+    fn spawn_image_load(&mut self, pending: PendingImage) {
+        crate::kernel::uart_write_string(&format!("Starting async image load: {}\r\n", pending.url));
+
+        let format = if pending.url.ends_with(".png") {
+            ImageFormat::Png
+        } else if pending.url.ends_with(".jpg") || pending.url.ends_with(".jpeg") {
+            ImageFormat::Jpeg
         } else {
-            // Poll current CSS load
-            let current_state = core::mem::replace(&mut self.css_load_state, CssLoadState::Idle);
-
-            self.css_load_state = match current_state {
-                CssLoadState::Idle => CssLoadState::Idle,
-
-                CssLoadState::Connecting { socket_handle, http_request, start_time, url } => {
-                    unsafe {
-                        if let Some(ref mut stack) = crate::kernel::NETWORK_STACK {
-                            let connected = stack.with_tcp_socket(socket_handle, |socket| {
-                                socket.may_send() && socket.may_recv()
-                            });
-
-                            if connected {
-                                // Send HTTP request
-                                stack.with_tcp_socket(socket_handle, |socket| {
-                                    socket.send_slice(http_request.as_bytes()).ok();
-                                });
-
-                                CssLoadState::Loading {
-                                    socket_handle,
-                                    response_data: Vec::new(),
-                                    last_recv_time: crate::kernel::drivers::timer::get_time_ms(),
-                                    url,
-                                }
-                            } else if crate::kernel::drivers::timer::get_time_ms() - start_time > 10000 {
-                                stack.remove_socket(socket_handle);
-                                CssLoadState::Idle
-                            } else {
-                                CssLoadState::Connecting { socket_handle, http_request, start_time, url }
-                            }
-                        } else {
-                            CssLoadState::Idle
-                        }
-                    }
-                }
+            ImageFormat::Bmp
+        };
 
-                CssLoadState::Loading { socket_handle, mut response_data, last_recv_time, url } => {
-                    unsafe {
-                        if let Some(ref mut stack) = crate::kernel::NETWORK_STACK {
-                            let mut received_data = false;
-                            let mut connection_closed = false;
-
-                            stack.with_tcp_socket(socket_handle, |socket| {
-                                while socket.can_recv() {
-                                    if let Ok(_) = socket.recv(|buffer| {
-                                        let len = buffer.len();
-                                        if len > 0 {
-                                            response_data.extend_from_slice(buffer);
-                                            received_data = true;
-                                        }
-                                        (len, ())
-                                    }) {}
-                                }
-
-                                if !socket.may_recv() {
-                                    connection_closed = true;
-                                }
-                            });
-
-                            let new_last_recv_time = if received_data {
-                                crate::kernel::drivers::timer::get_time_ms()
-                            } else {
-                                last_recv_time
-                            };
-
-                            if connection_closed {
-                                stack.remove_socket(socket_handle);
-
-                                // Parse HTTP response and extract CSS
-                                if let Some(body_start) = response_data.windows(4).position(|w| w == b"\r\n\r\n") {
-                                    let css_data = &response_data[body_start + 4..];
-
-                                    if let Ok(css_text) = core::str::from_utf8(css_data) {
-                                        crate::kernel::uart_write_string(&alloc::format!("CSS loaded: {} bytes\r\n", css_text.len()));
-
-                                        // Parse the CSS
-                                        let stylesheet = crate::gui::css_parser::Stylesheet::parse(css_text);
-                                        crate::kernel::uart_write_string(&alloc::format!("Parsed {} CSS rules\r\n", stylesheet.rules.len()));
-
-                                        // Add to stylesheets
-                                        self.stylesheets.push(stylesheet);
-
-                                        // Trigger reflow to apply styles
-                                        if let Some(ref dom) = self.dom.clone() {
-                                            self.layout.clear();
-                                            // Use find_and_layout_body with actual window width
-                                            // Start at (0, 0) with full width - CSS controls margins/padding
-                                            let layout_width = if self.last_window_width > 0 {
-                                                self.last_window_width
-                                            } else {
-                                                1280
-                                            };
-                                            layout::find_and_layout_body(self, &dom, 0, 0, layout_width);
-                                        }
-
-                                        needs_redraw = true;
-                                    }
-                                }
-
-                                CssLoadState::Idle
-                            } else if crate::kernel::drivers::timer::get_time_ms() - new_last_recv_time > 30000 {
-                                stack.remove_socket(socket_handle);
-                                CssLoadState::Idle
-                            } else {
-                                CssLoadState::Loading {
-                                    socket_handle,
-                                    response_data,
-                                    last_recv_time: new_last_recv_time,
-                                    url,
-                                }
-                            }
-                        } else {
-                            CssLoadState::Idle
-                        }
-                    }
-                }
+        let browser_id = self.instance_id;
+        self.loading_urls.push(pending.url.clone());
+        self.active_loads += 1;
+        let task = executor::spawn(async move {
+            let result = http::fetch(&pending.url).await;
+            if let Some(browser) = get_browser(browser_id) {
+                browser.finish_image_load(&pending.url, pending.layout_box_index, format, result);
+            }
+        });
+        self.tasks.push(task);
+    }
+
+    fn finish_image_load(&mut self, url: &str, layout_box_index: usize, format: ImageFormat, result: Result<Vec<u8>, &'static str>) {
+        self.loading_urls.retain(|u| u != url);
+        self.active_loads = self.active_loads.saturating_sub(1);
+
+        let image_data = match result {
+            Ok(data) => data,
+            Err(e) => {
+                crate::kernel::uart_write_string(&format!("Image load failed: {}: {}\r\n", url, e));
+                return;
+            }
+        };
+
+        let decoded_image = match format {
+            ImageFormat::Png => decode_png(&image_data),
+            ImageFormat::Jpeg => decode_jpeg(&image_data),
+            ImageFormat::Bmp => decode_bmp(&image_data),
+        };
+
+        if let Some(img) = decoded_image {
+            crate::kernel::uart_write_string(&format!("Image loaded: {}x{}\r\n", img.width, img.height));
+
+            // Cache the loaded image
+            self.image_cache.insert(url.to_string(), img.clone());
+
+            // Reflow if placeholder was 0x0 (no size specified in HTML)
+            let needs_reflow = if layout_box_index < self.layout.len() {
+                self.layout[layout_box_index].width == 0 &&
+                self.layout[layout_box_index].height == 0
+            } else {
+                false
             };
+
+            if needs_reflow {
+                crate::kernel::uart_write_string("Image dimensions changed, reflowing layout\r\n");
+                self.reflow();
+                self.needs_redraw = true;
+            } else if layout_box_index < self.layout.len() {
+                // Just update the image data in place
+                self.layout[layout_box_index].image_data = Some(img);
+                self.layout[layout_box_index].text = String::new();
+                self.needs_redraw = true;
+            }
         }
+    }
 
-        needs_redraw
+    /// Re-run layout for the current DOM at the last known window width
+    fn reflow(&mut self) {
+        if let Some(ref dom) = self.dom.clone() {
+            self.layout.clear();
+            // Start at (0, 0) with full width - CSS controls margins/padding
+            let layout_width = if self.last_window_width > 0 {
+                self.last_window_width
+            } else {
+                1280
+            };
+            layout::find_and_layout_body(self, &dom, 0, 0, layout_width);
+        }
     }
 
     /// Handle mouse click
@@ -769,12 +351,11 @@ impl Browser {
                     }
 
                     // Handle relative URLs
-                    let url = if layout_box.link_url.starts_with("http://") || layout_box.link_url.starts_with("https://") {
+                    let url = if http::is_absolute(&layout_box.link_url) {
                         layout_box.link_url.clone()
                     } else if layout_box.link_url.starts_with('/') {
                         // Absolute path - use current host
-                        let (host, port, _) = http::parse_url(&self.url);
-                        alloc::format!("http://{}:{}{}", host, port, layout_box.link_url)
+                        http::resolve_path(&self.url, &layout_box.link_url)
                     } else {
                         // Relative path - append to current URL's directory
                         alloc::format!("{}/{}", self.url.trim_end_matches('/'), layout_box.link_url)
@@ -920,13 +501,13 @@ pub fn create_browser() -> usize {
     }
 }
 
-/// Poll all browsers' async HTTP state machines (call from main loop)
-/// Returns true if any browser needs redraw
+/// Start queued resource loads and collect redraw requests from finished ones
+/// (call from main loop). Returns true if any browser needs redraw
 pub fn poll_all_browsers() -> bool {
     unsafe {
         let mut needs_redraw = false;
         for browser in BROWSERS.iter_mut() {
-            if browser.poll_http() {
+            if browser.poll_loads() {
                 needs_redraw = true;
             }
         }
@@ -943,7 +524,7 @@ pub fn render_at(instance_id: usize, x: usize, y: usize, width: usize, height: u
             // Check if window width changed - trigger reflow if needed
             // Also reflow on first render (last_window_width == 0) if we have a DOM
             if browser.last_window_width != width {
-                crate::kernel::uart_write_string(&alloc::format!(
+                crate::kernel::uart_write_string(&format!(
                     "Browser: Window resized from {} to {} - reflowing layout\r\n",
                     browser.last_window_width, width
                 ));
@@ -1015,7 +596,12 @@ pub fn handle_mouse_up(instance_id: usize) {
 }
 
 /// Remove a browser instance (when window is closed)
-pub fn remove_browser(_instance_id: usize) {
+pub fn remove_browser(instance_id: usize) {
+    // Stop any page or resource fetches for the closed window
+    if let Some(browser) = get_browser(instance_id) {
+        browser.cancel_loads();
+    }
+
     // For now, we don't actually remove browsers from the vector
     // They'll just remain unused. In a real implementation, we'd need to
     // handle this properly with Option<Browser> or Vec::remove
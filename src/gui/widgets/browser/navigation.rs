@@ -1,5 +1,3 @@
-use super::types::{HttpState};
-use super::http::parse_url;
 use super::layout::load_html;
 use alloc::string::{String, ToString};
 use alloc::format;
@@ -24,6 +22,9 @@ pub fn load_url(browser: &mut super::Browser, url: String) {
     browser.scroll_offset = 0;
     browser.loading = true;
 
+    // Abandon whatever the previous page was still fetching
+    browser.cancel_loads();
+
     // Handle special URLs
     if url.starts_with("about:") {
         load_about_page(browser, &url);
@@ -36,20 +37,8 @@ pub fn load_url(browser: &mut super::Browser, url: String) {
 
     crate::kernel::uart_write_string(&format!("Browser: Async loading {}\r\n", url));
 
-    // Parse URL to get host, port, path
-    let (host, port, path) = parse_url(&url);
-
-    crate::kernel::uart_write_string(&format!("Browser: Host={}, Port={}, Path={}\r\n", host, port, path));
-
-    // Start async HTTP request - just initiate DNS resolution
-    browser.http_state = HttpState::ResolvingDns {
-        host,
-        path,
-        port,
-        start_time: crate::kernel::drivers::timer::get_time_ms(),
-    };
-
-    // Returns immediately - poll_http() will advance the state machine
+    // Fetch in the background - the task swaps in the page when it arrives
+    browser.spawn_page_load(url);
 }
 
 /// Go back in history
@@ -97,6 +86,7 @@ pub fn load_about_page(browser: &mut super::Browser, url: &str) {
             <li>Text layout engine</li>\
             <li>Clickable hyperlinks</li>\
             <li>Address bar navigation</li>\
+            <li>Local pages with file:// URLs, e.g. file:///host/index.html</li>\
             <li>Keyboard shortcuts Ctrl+L</li>\
             </ul>\
             <h2>Current Limitations</h2>\
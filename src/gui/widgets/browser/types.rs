@@ -1,34 +1,5 @@
 use crate::gui::bmp_decoder::BmpImage;
-use smoltcp::iface::SocketHandle;
 use alloc::string::String;
-use alloc::vec::Vec;
-
-/// Async HTTP request state machine
-pub enum HttpState {
-    Idle,
-    ResolvingDns {
-        host: String,
-        path: String,
-        port: u16,
-        start_time: u64,
-    },
-    Connecting {
-        socket_handle: SocketHandle,
-        http_request: String,
-        start_time: u64,
-    },
-    ReceivingResponse {
-        socket_handle: SocketHandle,
-        response_data: Vec<u8>,
-        last_recv_time: u64,
-    },
-    Complete {
-        html: String,
-    },
-    Error {
-        message: String,
-    },
-}
 
 /// Pending image load request
 pub struct PendingImage {
@@ -44,49 +15,11 @@ pub enum ImageFormat {
     Jpeg,
 }
 
-/// Image loading state machine
-pub enum ImageLoadState {
-    Idle,
-    Connecting {
-        socket_handle: SocketHandle,
-        http_request: String,
-        start_time: u64,
-        layout_box_index: usize,
-        format: ImageFormat,
-        url: String, // For caching
-    },
-    Loading {
-        socket_handle: SocketHandle,
-        response_data: Vec<u8>,
-        last_recv_time: u64,
-        layout_box_index: usize,
-        format: ImageFormat,
-        url: String, // For caching
-    },
-}
-
 /// Pending CSS load request
 pub struct PendingCss {
     pub url: String,
 }
 
-/// CSS loading state machine
-pub enum CssLoadState {
-    Idle,
-    Connecting {
-        socket_handle: SocketHandle,
-        http_request: String,
-        start_time: u64,
-        url: String,
-    },
-    Loading {
-        socket_handle: SocketHandle,
-        response_data: Vec<u8>,
-        last_recv_time: u64,
-        url: String,
-    },
-}
-
 /// Simple color structure
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Color {
@@ -1,7 +1,11 @@
-// File Explorer - Visual file manager for SimpleFS
+// File Explorer - Visual file manager for mounted volumes
+//
+// Tar and zip archives open like folders, read-only, and Extract copies an
+// archive, or the selected entry inside one, out next to the archive.
 
 use crate::gui::framebuffer;
-use crate::system::fs::filesystem::SimpleFilesystem;
+use crate::system::archive;
+use crate::system::fs::{mime, vfs, FileInfo, ATTR_HIDDEN};
 extern crate alloc;
 use alloc::string::String;
 use alloc::vec::Vec;
@@ -13,6 +17,14 @@ const FILE_ITEM_HEIGHT: u32 = LINE_HEIGHT;
 const BUTTON_HEIGHT: u32 = 28;
 const BUTTON_SPACING: u32 = 8;
 const TOOLBAR_HEIGHT: u32 = BUTTON_HEIGHT + BUTTON_SPACING * 2;
+const HEADER_HEIGHT: u32 = LINE_HEIGHT;
+const LIST_TOP: u32 = TOOLBAR_HEIGHT + HEADER_HEIGHT;
+
+// Detail columns, right to left; each is dropped when the name gets too narrow
+const SIZE_COLUMN_WIDTH: u32 = 96;
+const DATE_COLUMN_WIDTH: u32 = 192;
+const TYPE_COLUMN_WIDTH: u32 = 144;
+const MIN_NAME_WIDTH: u32 = 160;
 
 // Colors
 const COLOR_TEXT: u32 = 0xFFFFFFFF;           // White text
@@ -21,11 +33,16 @@ const COLOR_HOVER: u32 = 0xFF5588DD;          // Lighter blue hover
 const COLOR_BUTTON: u32 = 0xFF3D3D3D;         // Button background
 const COLOR_BUTTON_HOVER: u32 = 0xFF5D5D5D;   // Button hover
 const COLOR_BUTTON_BORDER: u32 = 0xFF555555;  // Button border
-
-#[derive(Clone)]
-struct FileInfo {
-    name: String,
-    size: usize,
+const COLOR_HEADER: u32 = 0xFFAAAAAA;         // Column headings
+const COLOR_DETAIL: u32 = 0xFFCCCCCC;         // Type and date columns
+
+/// Column the file list is sorted by
+#[derive(Clone, Copy, PartialEq)]
+pub enum SortKey {
+    Name,
+    Type,
+    Modified,
+    Size,
 }
 
 pub struct FileExplorer {
@@ -35,8 +52,11 @@ pub struct FileExplorer {
     visible_height: usize,
     last_click_time: u64,       // For double-click detection
     last_click_index: Option<usize>,
-    pub filesystem: Option<SimpleFilesystem>,
-    pub device_index: Option<usize>,
+    current_dir: String, // VFS path of the directory being shown
+    archive: Option<String>, // Archive file that current_dir is inside, if any
+    sort_key: SortKey,
+    sort_descending: bool,
+    width: u32, // Width at the last render, for hit-testing the column headings
 }
 
 impl FileExplorer {
@@ -48,74 +68,43 @@ impl FileExplorer {
             visible_height: 20, // Default
             last_click_time: 0,
             last_click_index: None,
-            filesystem: None,
-            device_index: None,
+            current_dir: vfs::home(),
+            archive: None,
+            sort_key: SortKey::Name,
+            sort_descending: false,
+            width: 0,
         };
 
-        // Initialize filesystem if block device is available
-        unsafe {
-            if let Some(ref mut devices) = crate::kernel::BLOCK_DEVICES {
-                if !devices.is_empty() {
-                    let device_idx = 0;
-                    let device = &mut devices[device_idx];
-
-                    match SimpleFilesystem::mount(device) {
-                        Ok(fs) => {
-                            explorer.filesystem = Some(fs);
-                            explorer.device_index = Some(device_idx);
-                            explorer.refresh_files();
-
-                            // Auto-select first file if any exist
-                            if !explorer.files.is_empty() {
-                                explorer.selected_index = Some(0);
-                            }
-                        },
-                        Err(_) => {
-                            // Filesystem not available
-                        }
-                    }
-                }
-            }
+        explorer.refresh_files();
+
+        // Auto-select first file if any exist
+        if !explorer.files.is_empty() {
+            explorer.selected_index = Some(0);
         }
 
         explorer
     }
 
-    /// Refresh file list from filesystem (remounts to get latest changes from disk)
+    /// Refresh file list from the filesystem
     pub fn refresh_files(&mut self) {
         self.files.clear();
 
-        // Remount filesystem from disk to get latest changes (e.g., from terminal)
-        if let Some(device_idx) = self.device_index {
-            unsafe {
-                if let Some(ref mut devices) = crate::kernel::BLOCK_DEVICES {
-                    if let Some(device) = devices.get_mut(device_idx) {
-                        match SimpleFilesystem::mount(device) {
-                            Ok(fs) => {
-                                // Update with fresh filesystem from disk
-                                self.filesystem = Some(fs);
-                            }
-                            Err(_) => {
-                                // Keep existing filesystem if remount fails
-                            }
-                        }
-                    }
-                }
+        let listing = match vfs::list_dir(&self.current_dir) {
+            Ok(listing) => listing,
+            Err(_) => {
+                // Directory vanished (e.g. deleted from a terminal or unmounted)
+                self.current_dir = String::from("/");
+                vfs::list_dir("/").unwrap_or_default()
             }
-        }
-
-        // Get filesystem instance
-        if let Some(ref fs) = self.filesystem {
-            // List all files
-            let file_list = fs.list_files();
+        };
 
-            for entry in file_list {
-                self.files.push(FileInfo {
-                    name: String::from(entry.get_name()),
-                    size: entry.get_size_bytes() as usize,
-                });
-            }
+        // List the current directory, with a ".." entry below the top level
+        if self.current_dir != "/" {
+            self.files.push(FileInfo::new(String::from(".."), 0, true));
         }
+        self.files.extend(listing.into_iter().filter(|file| file.attributes & ATTR_HIDDEN == 0));
+        self.sort_files();
+        self.archive = vfs::archive_of(&self.current_dir);
 
         // Clear selection if it's out of bounds
         if let Some(idx) = self.selected_index {
@@ -132,13 +121,14 @@ impl FileExplorer {
             return self.handle_toolbar_click(x, y);
         }
 
-        // Calculate which file was clicked (adjust for toolbar)
-        let click_y = y - TOOLBAR_HEIGHT as i32;
-        if click_y < 0 {
-            return FileExplorerAction::None;
+        if y < LIST_TOP as i32 {
+            return self.handle_header_click(x);
         }
 
-        let visible_items = ((content_height - TOOLBAR_HEIGHT) / FILE_ITEM_HEIGHT) as usize;
+        // Calculate which file was clicked (adjust for toolbar and headings)
+        let click_y = y - LIST_TOP as i32;
+
+        let visible_items = (content_height.saturating_sub(LIST_TOP) / FILE_ITEM_HEIGHT) as usize;
         self.visible_height = visible_items;
 
         let clicked_index = (click_y as u32 / FILE_ITEM_HEIGHT) as usize + self.scroll_offset;
@@ -156,9 +146,8 @@ impl FileExplorer {
             self.last_click_time = current_time;
 
             if is_double_click {
-                // Double-click: open file in editor
-                let filename = self.files[clicked_index].name.clone();
-                return FileExplorerAction::OpenFile(filename);
+                // Double-click: enter directory or open file in editor
+                return self.open_index(clicked_index);
             } else {
                 // Single click: select file
                 self.selected_index = Some(clicked_index);
@@ -200,11 +189,67 @@ impl FileExplorer {
             if x >= current_x as i32 && x < (current_x + rename_width) as i32 {
                 return FileExplorerAction::RenameFile;
             }
+            current_x += rename_width + BUTTON_SPACING;
+
+            // Extract button (only for archives and what's in them)
+            if self.extract_target().is_some() {
+                let extract_width = 7 * CHAR_WIDTH + 16; // "Extract" + padding
+                if x >= current_x as i32 && x < (current_x + extract_width) as i32 {
+                    return FileExplorerAction::Extract;
+                }
+            }
         }
 
         FileExplorerAction::None
     }
 
+    /// Sort by the clicked column heading, or reverse the order if it's
+    /// already the sort column
+    fn handle_header_click(&mut self, x: i32) -> FileExplorerAction {
+        let (type_x, date_x, size_x) = column_layout(self.width);
+        let x = x.max(0) as u32;
+        let key = if x >= size_x {
+            SortKey::Size
+        } else if date_x.map_or(false, |date_x| x >= date_x) {
+            SortKey::Modified
+        } else if type_x.map_or(false, |type_x| x >= type_x) {
+            SortKey::Type
+        } else {
+            SortKey::Name
+        };
+
+        if key == self.sort_key {
+            self.sort_descending = !self.sort_descending;
+        } else {
+            self.sort_key = key;
+            // Newest and largest first is the useful default for those columns
+            self.sort_descending = matches!(key, SortKey::Modified | SortKey::Size);
+        }
+
+        let selected = self.selected_index.map(|idx| self.files[idx].name.clone());
+        self.sort_files();
+        self.selected_index = selected.and_then(|name| self.files.iter().position(|file| file.name == name));
+        FileExplorerAction::Redraw
+    }
+
+    /// Order the list by the sort column. ".." stays first and folders come before files.
+    fn sort_files(&mut self) {
+        let (key, descending) = (self.sort_key, self.sort_descending);
+        self.files.sort_by(|a, b| {
+            let pinned = (b.name == "..").cmp(&(a.name == "..")).then(b.is_dir.cmp(&a.is_dir));
+            pinned.then_with(|| {
+                let order = match key {
+                    SortKey::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+                    SortKey::Type => mime::file_type(a.file_type).label.cmp(mime::file_type(b.file_type).label),
+                    SortKey::Modified => a.modified.cmp(&b.modified),
+                    SortKey::Size => a.size.cmp(&b.size),
+                };
+                let order = order.then_with(|| a.name.cmp(&b.name));
+                if descending { order.reverse() } else { order }
+            })
+        });
+    }
+
     /// Scroll the file list
     pub fn scroll(&mut self, lines: i32) {
         if lines > 0 {
@@ -217,9 +262,11 @@ impl FileExplorer {
         }
     }
 
-    /// Get selected filename
+    /// Get selected filename (None for the ".." entry, which can't be deleted or renamed)
     pub fn get_selected_filename(&self) -> Option<String> {
-        self.selected_index.map(|idx| self.files[idx].name.clone())
+        self.selected_index
+            .map(|idx| self.files[idx].name.clone())
+            .filter(|name| name != "..")
     }
 
     /// Move selection up (arrow up key)
@@ -267,36 +314,72 @@ impl FileExplorer {
     }
 
     /// Open selected file (Enter key)
-    pub fn open_selected(&self) -> FileExplorerAction {
+    pub fn open_selected(&mut self) -> FileExplorerAction {
         if let Some(idx) = self.selected_index {
             if idx < self.files.len() {
-                let filename = self.files[idx].name.clone();
-                return FileExplorerAction::OpenFile(filename);
+                return self.open_index(idx);
             }
         }
         FileExplorerAction::None
     }
 
+    /// Enter a directory or archive, or ask for a file to be opened
+    fn open_index(&mut self, idx: usize) -> FileExplorerAction {
+        let file = &self.files[idx];
+        if !file.is_dir && !archive::is_archive_name(&file.name) {
+            return FileExplorerAction::OpenFile(self.path_of(&file.name));
+        }
+
+        self.current_dir = self.path_of(&file.name);
+        self.selected_index = None;
+        self.scroll_offset = 0;
+        self.last_click_index = None;
+        self.refresh_files();
+        FileExplorerAction::Redraw
+    }
+
+    /// Full VFS path of an entry in the current directory
+    pub fn path_of(&self, name: &str) -> String {
+        vfs::join(&self.current_dir, name)
+    }
+
+    /// What Extract would copy out and where to: a selected archive goes into
+    /// a folder named after it, an entry inside an archive goes next to the
+    /// archive file
+    fn extract_target(&self) -> Option<(String, String)> {
+        let name = self.get_selected_filename()?;
+        let from = self.path_of(&name);
+        match &self.archive {
+            Some(archive) => Some((from, vfs::join(archive, &alloc::format!("../{}", name)))),
+            None if !self.files[self.selected_index?].is_dir => {
+                let stem = archive::archive_stem(&name)?;
+                Some((from, self.path_of(stem)))
+            }
+            None => None,
+        }
+    }
+
+    /// Extract the selected archive or archive entry. Returns how many files
+    /// were written and where.
+    pub fn extract_selected(&mut self) -> Result<(usize, String), &'static str> {
+        let (from, dest) = self.extract_target().ok_or("Nothing to extract")?;
+        let files = vfs::extract(&from, &dest)?;
+        refresh_all_explorers();
+        Ok((files, dest))
+    }
+
     /// Delete selected file
     pub fn delete_selected(&mut self) -> bool {
         if let Some(idx) = self.selected_index {
-            if idx < self.files.len() {
-                let filename = self.files[idx].name.clone();
+            if idx < self.files.len() && self.files[idx].name != ".." {
+                let filename = self.path_of(&self.files[idx].name);
 
                 // Delete from filesystem
-                if let (Some(ref mut fs), Some(device_idx)) = (&mut self.filesystem, self.device_index) {
-                    unsafe {
-                        if let Some(ref mut devices) = crate::kernel::BLOCK_DEVICES {
-                            if let Some(device) = devices.get_mut(device_idx) {
-                                if fs.delete_file(device, &filename).is_ok() {
-                                    // Remove from our list
-                                    self.files.remove(idx);
-                                    self.selected_index = None;
-                                    return true;
-                                }
-                            }
-                        }
-                    }
+                if vfs::delete(&filename).is_ok() {
+                    // Remove from our list
+                    self.files.remove(idx);
+                    self.selected_index = None;
+                    return true;
                 }
             }
         }
@@ -306,14 +389,17 @@ impl FileExplorer {
     /// Render the file explorer
     pub fn render_at(&mut self, offset_x: i32, offset_y: i32, width: u32, height: u32, cursor_x: i32, cursor_y: i32) {
         // Calculate visible items
-        let visible_items = ((height - TOOLBAR_HEIGHT) / FILE_ITEM_HEIGHT) as usize;
+        let visible_items = (height.saturating_sub(LIST_TOP) / FILE_ITEM_HEIGHT) as usize;
         self.visible_height = visible_items;
+        self.width = width;
 
         // Draw toolbar
         self.draw_toolbar(offset_x, offset_y, width, cursor_x, cursor_y);
+        self.draw_headings(offset_x, offset_y + TOOLBAR_HEIGHT as i32, width);
+        let (type_x, date_x, size_x) = column_layout(width);
 
-        // Draw file list (below toolbar)
-        let list_y = offset_y + TOOLBAR_HEIGHT as i32;
+        // Draw file list (below toolbar and headings)
+        let list_y = offset_y + LIST_TOP as i32;
         let visible_end = (self.scroll_offset + visible_items).min(self.files.len());
 
         for (idx, file_idx) in (self.scroll_offset..visible_end).enumerate() {
@@ -349,19 +435,39 @@ impl FileExplorer {
             }
 
             // Draw file icon (simple folder/file emoji)
-            let icon = "\u{1F4C4}"; // 📄 document icon
+            let icon = if file.is_dir {
+                "\u{1F4C1}" // 📁 folder icon
+            } else {
+                "\u{1F4C4}" // 📄 document icon
+            };
             framebuffer::draw_string((offset_x + 8) as u32, y as u32 + 4, icon, COLOR_TEXT);
 
             // Draw filename
             let name_x = offset_x + 32; // After icon
             framebuffer::draw_string(name_x as u32, y as u32 + 4, &file.name, COLOR_TEXT);
 
+            let text_y = y as u32 + 4;
+            if file.name != ".." {
+                if let Some(type_x) = type_x {
+                    let label = mime::file_type(file.file_type).label;
+                    framebuffer::draw_string((offset_x + type_x as i32) as u32, text_y, label, COLOR_DETAIL);
+                }
+                if let Some(date_x) = date_x {
+                    let date = if file.modified == 0 {
+                        String::from("-")
+                    } else {
+                        crate::kernel::clock::locale::format_file_timestamp(file.modified)
+                    };
+                    framebuffer::draw_string((offset_x + date_x as i32) as u32, text_y, &date, COLOR_DETAIL);
+                }
+            }
+
             // Draw file size (right-aligned)
-            let size_str = format_size(file.size);
-            let size_width = size_str.len() as u32 * CHAR_WIDTH;
-            let size_x = offset_x + width as i32 - size_width as i32 - 8;
-            if size_x > name_x {
-                framebuffer::draw_string(size_x as u32, y as u32 + 4, &size_str, COLOR_TEXT);
+            let size_str = if file.is_dir { String::from("<DIR>") } else { format_size(file.size as usize) };
+            let size_width = framebuffer::measure_string(&size_str);
+            let size_right = offset_x + (size_x + SIZE_COLUMN_WIDTH) as i32;
+            if size_right - (size_width as i32) > name_x {
+                framebuffer::draw_string((size_right - size_width as i32) as u32, text_y, &size_str, COLOR_TEXT);
             }
         }
 
@@ -432,6 +538,64 @@ impl FileExplorer {
                 cursor_x,
                 cursor_y
             );
+            current_x += rename_width + BUTTON_SPACING;
+
+            // Extract button (only for archives and what's in them)
+            if self.extract_target().is_some() {
+                let extract_width = 7 * CHAR_WIDTH + 16; // "Extract" + padding
+                self.draw_button(
+                    offset_x + current_x as i32,
+                    offset_y + BUTTON_SPACING as i32,
+                    extract_width,
+                    BUTTON_HEIGHT,
+                    "Extract",
+                    cursor_x,
+                    cursor_y
+                );
+                current_x += extract_width + BUTTON_SPACING;
+            }
+        }
+
+        // Current directory, right-aligned
+        let label_width = framebuffer::measure_string(&self.current_dir);
+        if current_x + label_width + BUTTON_SPACING <= width {
+            let label_x = offset_x + (width - label_width - BUTTON_SPACING) as i32;
+            let label_y = offset_y + (BUTTON_SPACING + (BUTTON_HEIGHT - framebuffer::get_char_height()) / 2) as i32;
+            framebuffer::draw_string(label_x as u32, label_y as u32, &self.current_dir, COLOR_TEXT);
+        }
+    }
+
+    /// Draw the column headings, marking the sort column with an arrow
+    fn draw_headings(&self, offset_x: i32, y: i32, width: u32) {
+        let (type_x, date_x, size_x) = column_layout(width);
+        let arrow = if self.sort_descending { " \u{25BC}" } else { " \u{25B2}" };
+        let heading = |title: &str, key: SortKey| {
+            let mut text = String::from(title);
+            if key == self.sort_key {
+                text.push_str(arrow);
+            }
+            text
+        };
+        let text_y = (y + 4) as u32;
+
+        framebuffer::draw_string((offset_x + 32) as u32, text_y, &heading("Name", SortKey::Name), COLOR_HEADER);
+        if let Some(type_x) = type_x {
+            framebuffer::draw_string((offset_x + type_x as i32) as u32, text_y, &heading("Type", SortKey::Type), COLOR_HEADER);
+        }
+        if let Some(date_x) = date_x {
+            framebuffer::draw_string((offset_x + date_x as i32) as u32, text_y, &heading("Modified", SortKey::Modified), COLOR_HEADER);
+        }
+        let size = heading("Size", SortKey::Size);
+        let size_right = offset_x + (size_x + SIZE_COLUMN_WIDTH) as i32;
+        framebuffer::draw_string((size_right - framebuffer::measure_string(&size) as i32) as u32, text_y, &size, COLOR_HEADER);
+
+        // Rule under the headings
+        for dx in 0..width {
+            let px = offset_x + dx as i32;
+            let py = y + HEADER_HEIGHT as i32 - 1;
+            if px >= 0 && py >= 0 {
+                framebuffer::draw_pixel(px as u32, py as u32, COLOR_BUTTON_BORDER);
+            }
         }
     }
 
@@ -482,9 +646,19 @@ pub enum FileExplorerAction {
     NewFile,
     DeleteFile,
     RenameFile,
+    Extract,
     OpenFile(String),
 }
 
+/// X offsets of the type, modified and size columns in a list `width` pixels
+/// wide. Type and modified are None when there isn't room for them.
+fn column_layout(width: u32) -> (Option<u32>, Option<u32>, u32) {
+    let size_x = width.saturating_sub(SIZE_COLUMN_WIDTH + 8);
+    let date_x = size_x.checked_sub(DATE_COLUMN_WIDTH).filter(|&x| x >= 32 + MIN_NAME_WIDTH);
+    let type_x = date_x.and_then(|x| x.checked_sub(TYPE_COLUMN_WIDTH)).filter(|&x| x >= 32 + MIN_NAME_WIDTH);
+    (type_x, date_x, size_x)
+}
+
 /// Format file size in human-readable format
 fn format_size(bytes: usize) -> String {
     if bytes < 1024 {
@@ -203,13 +203,14 @@ pub fn create_image_viewer() -> usize {
     }
 }
 
-/// Create an image viewer with loaded image data
-pub fn create_image_viewer_with_data(filename: &str, data: &[u8]) -> usize {
+/// Create an image viewer showing the image file at VFS path `path`
+pub fn create_image_viewer_from_file(path: &str) -> Result<usize, &'static str> {
+    let data = crate::system::fs::vfs::read_file(path)?;
     unsafe {
         let mut viewer = ImageViewer::new();
-        viewer.load_image(filename, data);
+        viewer.load_image(path, &data);
         IMAGE_VIEWERS.push(viewer);
-        IMAGE_VIEWERS.len() - 1
+        Ok(IMAGE_VIEWERS.len() - 1)
     }
 }
 
@@ -357,8 +357,8 @@ impl WindowManager {
         let centered_text_y = MENU_START_Y + (MENU_ITEM_HEIGHT - text_height) / 2;
 
         // Draw time in top right corner
-        let datetime = crate::kernel::drivers::rtc::get_datetime();
-        let time_str = datetime.format_time();
+        let local_time = crate::kernel::clock::now_local();
+        let time_str = crate::kernel::clock::locale::format_clock(&local_time);
         let time_width = framebuffer::measure_string(&time_str);
         let time_x = self.screen_width.saturating_sub(time_width + 8); // 8px padding from right edge
         framebuffer::draw_string(time_x, centered_text_y, &time_str, COLOR_TEXT);
@@ -678,59 +678,13 @@ impl WindowManager {
                                 if self.windows.len() >= 4 {
                                     crate::kernel::drivers::input_events::set_menu_status("Cannot open: 4 windows max");
                                 } else {
-                                    // Check if file is an image (BMP or PNG)
-                                    let lower = filename.to_lowercase();
-                                    let is_image = lower.ends_with(".bmp") || lower.ends_with(".png");
-
-                                    // Get filesystem from file explorer
-                                    if let Some(explorer) = crate::gui::widgets::file_explorer::get_file_explorer(instance_id) {
-                                        if let (Some(ref fs), Some(device_idx)) = (&explorer.filesystem, explorer.device_index) {
-                                        // Get file info by listing all files
-                                        let file_list = fs.list_files();
-                                        let file_entry = file_list.iter().find(|e| e.get_name() == filename);
-
-                                        if let Some(file) = file_entry {
-                                            let size = file.get_size_bytes() as usize;
-                                            let mut buffer = alloc::vec![0u8; size];
-
-                                            unsafe {
-                                                if let Some(ref mut devices) = crate::kernel::BLOCK_DEVICES {
-                                                    if let Some(device) = devices.get_mut(device_idx) {
-                                                        if let Ok(bytes_read) = fs.read_file(device, &filename, &mut buffer) {
-                                                            if is_image {
-                                                                // Open in image viewer
-                                                                let viewer_id = crate::gui::widgets::image_viewer::create_image_viewer_with_data(
-                                                                    &filename,
-                                                                    &buffer[..bytes_read]
-                                                                );
-                                                                let title = alloc::format!("Image - {}", filename);
-                                                                let window = Window::new(0, 0, 800, 600, &title, WindowContent::ImageViewer, viewer_id);
-                                                                self.add_window(window);
-                                                            } else {
-                                                                // Open in text editor
-                                                                // Find the actual content length (for text files)
-                                                                let actual_len = buffer[..bytes_read].iter()
-                                                                    .position(|&b| b == 0)
-                                                                    .unwrap_or(bytes_read);
-
-                                                                if let Ok(text) = core::str::from_utf8(&buffer[..actual_len]) {
-                                                                    let editor_id = crate::gui::widgets::editor::create_editor_with_content(
-                                                                        &filename,
-                                                                        text
-                                                                    );
-                                                                    let title = alloc::format!("Editor - {}", filename);
-                                                                    let window = Window::new(0, 0, 640, 480, &title, WindowContent::Editor, editor_id);
-                                                                    self.add_window(window);
-                                                                }
-                                                            }
-                                                        }
-                                                    }
-                                                }
-                                            }
+                                    match window_for_file(&filename) {
+                                        Ok(window) => {
+                                            self.add_window(window);
                                         }
+                                        Err(e) => crate::kernel::drivers::input_events::set_menu_status(e),
                                     }
                                 }
-                                }
                             },
                             FileExplorerAction::Refresh => {
                                 crate::gui::widgets::file_explorer::refresh(instance_id);
@@ -755,6 +709,16 @@ impl WindowManager {
                                     }
                                 }
                             },
+                            FileExplorerAction::Extract => {
+                                if let Some(explorer) = crate::gui::widgets::file_explorer::get_file_explorer(instance_id) {
+                                    match explorer.extract_selected() {
+                                        Ok((files, dest)) => crate::kernel::drivers::input_events::set_menu_status(
+                                            &alloc::format!("Extracted {} file(s) to {}", files, dest)
+                                        ),
+                                        Err(e) => crate::kernel::drivers::input_events::set_menu_status(e),
+                                    }
+                                }
+                            },
                             FileExplorerAction::Redraw => {
                                 // Just need to redraw
                             },
@@ -984,6 +948,27 @@ pub fn init() {
     }
 }
 
+/// Build a window showing a file: images open in the image viewer, anything
+/// else in the text editor
+pub fn window_for_file(path: &str) -> Result<Window, &'static str> {
+    let lower = path.to_lowercase();
+    let is_image = lower.ends_with(".bmp") || lower.ends_with(".png") || lower.ends_with(".jpg") || lower.ends_with(".jpeg");
+
+    if is_image {
+        let viewer_id = crate::gui::widgets::image_viewer::create_image_viewer_from_file(path)?;
+        let title = alloc::format!("Image - {}", path);
+        return Ok(Window::new(0, 0, 800, 600, &title, WindowContent::ImageViewer, viewer_id));
+    }
+
+    let buffer = crate::system::fs::vfs::read_file(path)?;
+    // Find the actual content length (for text files)
+    let actual_len = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    let text = core::str::from_utf8(&buffer[..actual_len]).map_err(|_| "Cannot edit binary file")?;
+    let editor_id = crate::gui::widgets::editor::create_editor_with_content(path, text);
+    let title = alloc::format!("Editor - {}", path);
+    Ok(Window::new(0, 0, 640, 480, &title, WindowContent::Editor, editor_id))
+}
+
 pub fn add_window(window: Window) -> usize {
     unsafe {
         if let Some(ref mut wm) = WINDOW_MANAGER {
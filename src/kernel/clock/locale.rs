@@ -0,0 +1,207 @@
+// Locale-aware date and time formatting
+//
+// A locale only covers what the desktop displays: field order, separators,
+// 12/24-hour clock and month/weekday names. Numbers are always ASCII digits.
+
+extern crate alloc;
+use alloc::string::String;
+use spin::Mutex;
+use super::LocalTime;
+
+/// Order of the day, month and year fields in a numeric date
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DateOrder {
+    YearMonthDay,
+    DayMonthYear,
+    MonthDayYear,
+}
+
+#[derive(Debug)]
+pub struct Locale {
+    pub name: &'static str,
+    pub date_order: DateOrder,
+    pub date_separator: char,
+    pub hour12: bool,
+    pub am_pm: [&'static str; 2],
+    pub months: [&'static str; 12],   // abbreviated month names
+    pub weekdays: [&'static str; 7],  // abbreviated weekday names, Sunday first
+}
+
+const ENGLISH_MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+const ENGLISH_WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+const LOCALES: &[Locale] = &[
+    Locale {
+        name: "C",
+        date_order: DateOrder::YearMonthDay,
+        date_separator: '-',
+        hour12: false,
+        am_pm: ["AM", "PM"],
+        months: ENGLISH_MONTHS,
+        weekdays: ENGLISH_WEEKDAYS,
+    },
+    Locale {
+        name: "en_US",
+        date_order: DateOrder::MonthDayYear,
+        date_separator: '/',
+        hour12: true,
+        am_pm: ["AM", "PM"],
+        months: ENGLISH_MONTHS,
+        weekdays: ENGLISH_WEEKDAYS,
+    },
+    Locale {
+        name: "en_GB",
+        date_order: DateOrder::DayMonthYear,
+        date_separator: '/',
+        hour12: false,
+        am_pm: ["am", "pm"],
+        months: ENGLISH_MONTHS,
+        weekdays: ENGLISH_WEEKDAYS,
+    },
+    Locale {
+        name: "de_DE",
+        date_order: DateOrder::DayMonthYear,
+        date_separator: '.',
+        hour12: false,
+        am_pm: ["AM", "PM"],
+        months: ["Jan", "Feb", "Mär", "Apr", "Mai", "Jun", "Jul", "Aug", "Sep", "Okt", "Nov", "Dez"],
+        weekdays: ["So", "Mo", "Di", "Mi", "Do", "Fr", "Sa"],
+    },
+    Locale {
+        name: "fr_FR",
+        date_order: DateOrder::DayMonthYear,
+        date_separator: '/',
+        hour12: false,
+        am_pm: ["AM", "PM"],
+        months: ["janv", "févr", "mars", "avr", "mai", "juin", "juil", "août", "sept", "oct", "nov", "déc"],
+        weekdays: ["dim", "lun", "mar", "mer", "jeu", "ven", "sam"],
+    },
+    Locale {
+        name: "nb_NO",
+        date_order: DateOrder::DayMonthYear,
+        date_separator: '.',
+        hour12: false,
+        am_pm: ["AM", "PM"],
+        months: ["jan", "feb", "mar", "apr", "mai", "jun", "jul", "aug", "sep", "okt", "nov", "des"],
+        weekdays: ["søn", "man", "tir", "ons", "tor", "fre", "lør"],
+    },
+    Locale {
+        name: "ja_JP",
+        date_order: DateOrder::YearMonthDay,
+        date_separator: '/',
+        hour12: false,
+        am_pm: ["午前", "午後"],
+        months: ["1月", "2月", "3月", "4月", "5月", "6月", "7月", "8月", "9月", "10月", "11月", "12月"],
+        weekdays: ["日", "月", "火", "水", "木", "金", "土"],
+    },
+];
+
+/// Compare locale names ignoring case, '-' vs '_' and any ".charset" suffix
+fn name_matches(locale_name: &str, query: &str) -> bool {
+    let query = query.split('.').next().unwrap_or(query);
+    locale_name.len() == query.len()
+        && locale_name.bytes().zip(query.bytes()).all(|(a, b)| {
+            let norm = |c: u8| if c == b'-' { b'_' } else { c.to_ascii_lowercase() };
+            norm(a) == norm(b)
+        })
+}
+
+/// The active locale
+static CURRENT_LOCALE: Mutex<&'static Locale> = Mutex::new(&LOCALES[0]);
+
+impl Locale {
+    /// Find a locale by name ("de_DE", "de-DE" and "de_DE.UTF-8" all match)
+    pub fn by_name(name: &str) -> Option<&'static Locale> {
+        LOCALES.iter().find(|l| name_matches(l.name, name))
+    }
+
+    /// "14:05" or "2:05 PM"
+    pub fn format_time(&self, t: &LocalTime) -> String {
+        let dt = &t.datetime;
+        if self.hour12 {
+            let hour = match dt.hour % 12 { 0 => 12, h => h };
+            alloc::format!("{}:{:02} {}", hour, dt.minute, self.am_pm[(dt.hour >= 12) as usize])
+        } else {
+            alloc::format!("{:02}:{:02}", dt.hour, dt.minute)
+        }
+    }
+
+    /// "14:05:09" or "2:05:09 PM"
+    pub fn format_time_seconds(&self, t: &LocalTime) -> String {
+        let dt = &t.datetime;
+        if self.hour12 {
+            let hour = match dt.hour % 12 { 0 => 12, h => h };
+            alloc::format!("{}:{:02}:{:02} {}", hour, dt.minute, dt.second, self.am_pm[(dt.hour >= 12) as usize])
+        } else {
+            alloc::format!("{:02}:{:02}:{:02}", dt.hour, dt.minute, dt.second)
+        }
+    }
+
+    /// Numeric date in locale order: "2025-01-31", "31.01.2025", "01/31/2025"
+    pub fn format_date(&self, t: &LocalTime) -> String {
+        let dt = &t.datetime;
+        let sep = self.date_separator;
+        match self.date_order {
+            DateOrder::YearMonthDay => alloc::format!("{:04}{}{:02}{}{:02}", dt.year, sep, dt.month, sep, dt.day),
+            DateOrder::DayMonthYear => alloc::format!("{:02}{}{:02}{}{:04}", dt.day, sep, dt.month, sep, dt.year),
+            DateOrder::MonthDayYear => alloc::format!("{:02}{}{:02}{}{:04}", dt.month, sep, dt.day, sep, dt.year),
+        }
+    }
+
+    /// Date and time: "31.01.2025 14:05"
+    pub fn format_datetime(&self, t: &LocalTime) -> String {
+        alloc::format!("{} {}", self.format_date(t), self.format_time(t))
+    }
+
+    /// Long form for `date`: "Fri 31 Jan 2025 14:05:09 CET"
+    pub fn format_long(&self, t: &LocalTime) -> String {
+        let dt = &t.datetime;
+        let weekday = self.weekdays[dt.weekday as usize % 7];
+        let month = self.months[(dt.month as usize + 11) % 12];
+        let time = self.format_time_seconds(t);
+        match self.date_order {
+            DateOrder::MonthDayYear => alloc::format!("{} {} {} {} {} {}", weekday, month, dt.day, dt.year, time, t.abbrev.as_str()),
+            _ => alloc::format!("{} {} {} {} {} {}", weekday, dt.day, month, dt.year, time, t.abbrev.as_str()),
+        }
+    }
+
+    /// Compact file timestamp in the style of `ls -l`: time for recent files, year otherwise
+    pub fn format_file_time(&self, t: &LocalTime, now: &LocalTime) -> String {
+        let dt = &t.datetime;
+        let month = self.months[(dt.month as usize + 11) % 12];
+        let recent = dt.year == now.datetime.year;
+        let tail = if recent { self.format_time(t) } else { alloc::format!("{}", dt.year) };
+        match self.date_order {
+            DateOrder::MonthDayYear => alloc::format!("{} {:>2} {}", month, dt.day, tail),
+            _ => alloc::format!("{:>2} {} {}", dt.day, month, tail),
+        }
+    }
+}
+
+/// The active locale
+pub fn current() -> &'static Locale {
+    *CURRENT_LOCALE.lock()
+}
+
+/// Switch the active locale by name
+pub fn set_locale(name: &str) -> Result<&'static Locale, &'static str> {
+    let locale = Locale::by_name(name).ok_or("Unknown locale")?;
+    *CURRENT_LOCALE.lock() = locale;
+    Ok(locale)
+}
+
+/// Names of all available locales
+pub fn locale_names() -> impl Iterator<Item = &'static str> {
+    LOCALES.iter().map(|l| l.name)
+}
+
+/// Format the current time for the menu bar clock
+pub fn format_clock(t: &LocalTime) -> String {
+    current().format_time(t)
+}
+
+/// Format a file timestamp (Unix seconds) for listings
+pub fn format_file_timestamp(timestamp: i64) -> String {
+    let now = super::now_local();
+    current().format_file_time(&super::to_local(timestamp), &now)
+}
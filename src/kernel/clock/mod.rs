@@ -0,0 +1,275 @@
+// Unified clock subsystem
+//
+// Two clocks, modelled on POSIX clock_gettime():
+// - CLOCK_MONOTONIC: ARM Generic Timer counter (CNTPCT_EL0), never goes backwards
+// - CLOCK_REALTIME:  wall clock, seeded from the PL031 RTC at boot and adjustable
+//
+// Realtime is kept as a signed nanosecond offset on top of the monotonic clock,
+// so reading it never touches the RTC and setting it is a single atomic store.
+// Local time is derived from realtime using the active timezone (see tz.rs),
+// and formatted for display using the active locale (see locale.rs).
+
+pub mod tz;
+pub mod locale;
+
+extern crate alloc;
+use core::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use crate::kernel::drivers::{rtc, timer};
+
+const NANOS_PER_SEC: i64 = 1_000_000_000;
+const SECONDS_PER_DAY: i64 = 86400;
+
+/// Clock identifiers (same meaning as the POSIX constants)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockId {
+    Monotonic,
+    Realtime,
+}
+
+/// A point in time as seconds + nanoseconds
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timespec {
+    pub sec: i64,
+    pub nsec: u32,
+}
+
+impl Timespec {
+    pub fn from_nanos(nanos: i64) -> Self {
+        Timespec {
+            sec: nanos.div_euclid(NANOS_PER_SEC),
+            nsec: nanos.rem_euclid(NANOS_PER_SEC) as u32,
+        }
+    }
+
+    pub fn as_nanos(&self) -> i64 {
+        self.sec * NANOS_PER_SEC + self.nsec as i64
+    }
+}
+
+/// Offset (ns) added to the monotonic clock to obtain realtime
+static REALTIME_OFFSET_NS: AtomicI64 = AtomicI64::new(0);
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Initialize the clock subsystem: enable the RTC and seed CLOCK_REALTIME from it
+pub fn init() {
+    rtc::init();
+    let rtc_secs = rtc::read_time() as i64;
+    REALTIME_OFFSET_NS.store(rtc_secs * NANOS_PER_SEC - monotonic_ns() as i64, Ordering::SeqCst);
+    INITIALIZED.store(true, Ordering::SeqCst);
+
+    tz::init();
+
+    crate::kernel::uart_write_string(&alloc::format!(
+        "Clock: realtime seeded from PL031 ({}), timezone {}\r\n",
+        format_iso8601(realtime_secs()),
+        tz::current().name
+    ));
+}
+
+/// Nanoseconds since boot (CLOCK_MONOTONIC)
+pub fn monotonic_ns() -> u64 {
+    let counter = timer::read_counter() as u128;
+    let frequency = timer::counter_frequency() as u128;
+    if frequency == 0 {
+        return 0;
+    }
+    // u128 intermediate: counter * 1e9 overflows u64 after a few seconds at 62.5MHz
+    (counter * NANOS_PER_SEC as u128 / frequency) as u64
+}
+
+/// Microseconds since boot (CLOCK_MONOTONIC)
+pub fn monotonic_us() -> u64 {
+    monotonic_ns() / 1000
+}
+
+/// Milliseconds since boot (CLOCK_MONOTONIC)
+pub fn monotonic_ms() -> u64 {
+    monotonic_ns() / 1_000_000
+}
+
+/// Nanoseconds since the Unix epoch (CLOCK_REALTIME)
+pub fn realtime_ns() -> i64 {
+    if !INITIALIZED.load(Ordering::Relaxed) {
+        // Before init() we can still answer from the RTC directly
+        return rtc::read_time() as i64 * NANOS_PER_SEC;
+    }
+    monotonic_ns() as i64 + REALTIME_OFFSET_NS.load(Ordering::Relaxed)
+}
+
+/// Seconds since the Unix epoch (CLOCK_REALTIME)
+pub fn realtime_secs() -> i64 {
+    realtime_ns().div_euclid(NANOS_PER_SEC)
+}
+
+/// Read a clock (POSIX clock_gettime)
+pub fn clock_gettime(clock: ClockId) -> Timespec {
+    match clock {
+        ClockId::Monotonic => Timespec::from_nanos(monotonic_ns() as i64),
+        ClockId::Realtime => Timespec::from_nanos(realtime_ns()),
+    }
+}
+
+/// Set a clock (POSIX clock_settime). Only CLOCK_REALTIME can be set.
+/// The new time is also written back to the PL031 so it survives a warm reboot.
+pub fn clock_settime(clock: ClockId, time: Timespec) -> Result<(), &'static str> {
+    if clock != ClockId::Realtime {
+        return Err("CLOCK_MONOTONIC cannot be set");
+    }
+    if time.sec < 0 || time.sec > u32::MAX as i64 {
+        return Err("Time out of RTC range (1970-2106)");
+    }
+
+    REALTIME_OFFSET_NS.store(time.as_nanos() - monotonic_ns() as i64, Ordering::SeqCst);
+    INITIALIZED.store(true, Ordering::SeqCst);
+    rtc::set_time(time.sec as u32);
+    Ok(())
+}
+
+/// Set CLOCK_REALTIME to a Unix timestamp (seconds)
+pub fn set_realtime(secs: i64) -> Result<(), &'static str> {
+    clock_settime(ClockId::Realtime, Timespec { sec: secs, nsec: 0 })
+}
+
+/// Step CLOCK_REALTIME forwards or backwards by `delta_ns` (like adjtime, but immediate)
+pub fn adjust_realtime(delta_ns: i64) {
+    REALTIME_OFFSET_NS.fetch_add(delta_ns, Ordering::SeqCst);
+}
+
+/// Broken-down calendar time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: i32,
+    pub month: u8,   // 1-12
+    pub day: u8,     // 1-31
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub weekday: u8, // 0 = Sunday
+}
+
+/// Local time: calendar fields plus the zone information that produced them
+#[derive(Debug, Clone, Copy)]
+pub struct LocalTime {
+    pub datetime: DateTime,
+    pub utc_offset: i32, // seconds east of UTC
+    pub is_dst: bool,
+    pub abbrev: tz::Abbrev,
+}
+
+/// Check if a year is a leap year
+pub fn is_leap_year(year: i32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || (year % 400 == 0)
+}
+
+/// Number of days in a month (1-12)
+pub fn days_in_month(year: i32, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 for a civil date (proleptic Gregorian)
+pub fn days_from_civil(year: i32, month: u8, day: u8) -> i64 {
+    // Howard Hinnant's algorithm: shift the year to start in March so the leap day is last
+    let y = if month <= 2 { year as i64 - 1 } else { year as i64 };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Civil date for a day count since 1970-01-01
+fn civil_from_days(days: i64) -> (i32, u8, u8) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u8;
+    let year = (yoe + era * 400 + if month <= 2 { 1 } else { 0 }) as i32;
+    (year, month, day)
+}
+
+/// Day of week for a day count since 1970-01-01 (0 = Sunday)
+pub fn weekday_from_days(days: i64) -> u8 {
+    // 1970-01-01 was a Thursday
+    (days + 4).rem_euclid(7) as u8
+}
+
+/// Convert a Unix timestamp to a UTC calendar time
+pub fn timestamp_to_datetime(timestamp: i64) -> DateTime {
+    let days = timestamp.div_euclid(SECONDS_PER_DAY);
+    let secs_of_day = timestamp.rem_euclid(SECONDS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+
+    DateTime {
+        year,
+        month,
+        day,
+        hour: (secs_of_day / 3600) as u8,
+        minute: ((secs_of_day % 3600) / 60) as u8,
+        second: (secs_of_day % 60) as u8,
+        weekday: weekday_from_days(days),
+    }
+}
+
+/// Convert a UTC calendar time to a Unix timestamp
+pub fn datetime_to_timestamp(dt: &DateTime) -> i64 {
+    days_from_civil(dt.year, dt.month, dt.day) * SECONDS_PER_DAY
+        + dt.hour as i64 * 3600
+        + dt.minute as i64 * 60
+        + dt.second as i64
+}
+
+/// Convert a Unix timestamp to local time in the active timezone
+pub fn to_local(timestamp: i64) -> LocalTime {
+    tz::current().to_local(timestamp)
+}
+
+/// Current local time in the active timezone
+pub fn now_local() -> LocalTime {
+    to_local(realtime_secs())
+}
+
+/// Format a Unix timestamp as ISO 8601 UTC ("2025-01-31T13:45:00Z")
+pub fn format_iso8601(timestamp: i64) -> alloc::string::String {
+    let dt = timestamp_to_datetime(timestamp);
+    alloc::format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        dt.year, dt.month, dt.day, dt.hour, dt.minute, dt.second
+    )
+}
+
+/// Parse "YYYY-MM-DD HH:MM[:SS]" (or with a 'T' separator) as a UTC timestamp
+pub fn parse_datetime(s: &str) -> Option<i64> {
+    let s = s.trim().trim_end_matches('Z');
+    let (date, time) = match s.find(|c| c == ' ' || c == 'T') {
+        Some(pos) => (&s[..pos], s[pos + 1..].trim()),
+        None => (s, "00:00:00"),
+    };
+
+    let mut date_parts = date.split('-');
+    let year = date_parts.next()?.parse::<i32>().ok()?;
+    let month = date_parts.next()?.parse::<u8>().ok()?;
+    let day = date_parts.next()?.parse::<u8>().ok()?;
+    if date_parts.next().is_some() || !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+
+    let mut time_parts = time.split(':');
+    let hour = time_parts.next()?.parse::<u8>().ok()?;
+    let minute = time_parts.next()?.parse::<u8>().ok()?;
+    let second = time_parts.next().map(|s| s.parse::<u8>().ok()).unwrap_or(Some(0))?;
+    if hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+
+    Some(datetime_to_timestamp(&DateTime { year, month, day, hour, minute, second, weekday: 0 }))
+}
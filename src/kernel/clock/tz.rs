@@ -0,0 +1,483 @@
+// Timezones and daylight saving time
+//
+// Zones are described with POSIX TZ strings (the same format as the footer of
+// a TZif file, e.g. "CET-1CEST,M3.5.0,M10.5.0/3"). That keeps the built-in
+// table compact - one line per zone - and lets the user enter any other rule
+// set at runtime without new code.
+
+extern crate alloc;
+use alloc::boxed::Box;
+use alloc::string::String;
+use spin::Mutex;
+use super::{days_from_civil, is_leap_year, timestamp_to_datetime, weekday_from_days, days_in_month, LocalTime};
+
+/// Zone used until the user picks one (replaces the old fixed CET offset)
+const DEFAULT_ZONE: &str = "Europe/Berlin";
+
+/// Built-in timezone table: (IANA name, POSIX TZ rule)
+const ZONE_TABLE: &[(&str, &str)] = &[
+    ("UTC",                 "UTC0"),
+    ("Europe/London",       "GMT0BST,M3.5.0/1,M10.5.0"),
+    ("Europe/Dublin",       "GMT0IST,M3.5.0/1,M10.5.0"),
+    ("Europe/Lisbon",       "WET0WEST,M3.5.0/1,M10.5.0"),
+    ("Europe/Paris",        "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Berlin",       "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Amsterdam",    "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Madrid",       "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Rome",         "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Oslo",         "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Stockholm",    "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Warsaw",       "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Helsinki",     "EET-2EEST,M3.5.0/3,M10.5.0/4"),
+    ("Europe/Athens",       "EET-2EEST,M3.5.0/3,M10.5.0/4"),
+    ("Europe/Istanbul",     "<+03>-3"),
+    ("Europe/Moscow",       "MSK-3"),
+    ("Africa/Cairo",        "EET-2EEST,M4.5.5/0,M10.5.4/24"),
+    ("Africa/Johannesburg", "SAST-2"),
+    ("Africa/Lagos",        "WAT-1"),
+    ("Asia/Dubai",          "<+04>-4"),
+    ("Asia/Karachi",        "PKT-5"),
+    ("Asia/Kolkata",        "IST-5:30"),
+    ("Asia/Kathmandu",      "<+0545>-5:45"),
+    ("Asia/Bangkok",        "<+07>-7"),
+    ("Asia/Shanghai",       "CST-8"),
+    ("Asia/Singapore",      "<+08>-8"),
+    ("Asia/Hong_Kong",      "HKT-8"),
+    ("Asia/Seoul",          "KST-9"),
+    ("Asia/Tokyo",          "JST-9"),
+    ("Australia/Perth",     "AWST-8"),
+    ("Australia/Adelaide",  "ACST-9:30ACDT,M10.1.0,M4.1.0/3"),
+    ("Australia/Brisbane",  "AEST-10"),
+    ("Australia/Sydney",    "AEST-10AEDT,M10.1.0,M4.1.0/3"),
+    ("Pacific/Auckland",    "NZST-12NZDT,M9.5.0,M4.1.0/3"),
+    ("Pacific/Honolulu",    "HST10"),
+    ("America/Anchorage",   "AKST9AKDT,M3.2.0,M11.1.0"),
+    ("America/Los_Angeles", "PST8PDT,M3.2.0,M11.1.0"),
+    ("America/Denver",      "MST7MDT,M3.2.0,M11.1.0"),
+    ("America/Phoenix",     "MST7"),
+    ("America/Chicago",     "CST6CDT,M3.2.0,M11.1.0"),
+    ("America/New_York",    "EST5EDT,M3.2.0,M11.1.0"),
+    ("America/Halifax",     "AST4ADT,M3.2.0,M11.1.0"),
+    ("America/St_Johns",    "NST3:30NDT,M3.2.0,M11.1.0"),
+    ("America/Sao_Paulo",   "<-03>3"),
+    ("America/Mexico_City", "CST6"),
+];
+
+/// Short zone abbreviation ("CEST", "+0545"), stored inline so `Zone` stays `Copy`
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Abbrev {
+    bytes: [u8; 8],
+    len: u8,
+}
+
+impl Abbrev {
+    fn new(s: &str) -> Self {
+        let mut bytes = [0u8; 8];
+        let len = s.len().min(8);
+        bytes[..len].copy_from_slice(&s.as_bytes()[..len]);
+        Abbrev { bytes, len: len as u8 }
+    }
+
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.bytes[..self.len as usize]).unwrap_or("")
+    }
+}
+
+impl core::fmt::Debug for Abbrev {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Day of year on which a DST transition happens
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RuleDate {
+    /// Mm.w.d - day `weekday` (0 = Sunday) of week `week` (1-5, 5 = last) of `month`
+    MonthWeekDay { month: u8, week: u8, weekday: u8 },
+    /// Jn - Julian day 1-365, February 29 is never counted
+    Julian(u16),
+    /// n - zero-based day of year 0-365, February 29 is counted
+    DayOfYear(u16),
+}
+
+/// A DST transition: a date and a local wall-clock time (seconds, may exceed 24h)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Transition {
+    pub date: RuleDate,
+    pub time: i32,
+}
+
+/// Daylight saving time part of a zone
+#[derive(Clone, Copy, Debug)]
+pub struct DstRule {
+    pub abbrev: Abbrev,
+    pub offset: i32, // seconds east of UTC while DST is in effect
+    pub start: Transition,
+    pub end: Transition,
+}
+
+/// A timezone: standard offset plus optional DST rule
+#[derive(Clone, Copy, Debug)]
+pub struct Zone {
+    pub name: &'static str,
+    pub std_abbrev: Abbrev,
+    pub std_offset: i32, // seconds east of UTC
+    pub dst: Option<DstRule>,
+}
+
+impl Zone {
+    /// Look up a zone in the built-in table (case-insensitive)
+    pub fn by_name(name: &str) -> Option<Zone> {
+        ZONE_TABLE
+            .iter()
+            .find(|(zone_name, _)| zone_name.eq_ignore_ascii_case(name))
+            .and_then(|(zone_name, rule)| Zone::parse_posix(zone_name, rule))
+    }
+
+    /// Parse a POSIX TZ string such as "EST5EDT,M3.2.0,M11.1.0"
+    pub fn parse_posix(name: &'static str, spec: &str) -> Option<Zone> {
+        let mut p = PosixParser { s: spec.as_bytes(), pos: 0 };
+
+        let std_abbrev = p.abbrev()?;
+        // POSIX offsets are hours *west* of UTC; we store seconds east
+        let std_offset = -p.offset()?;
+
+        if p.eof() {
+            return Some(Zone { name, std_abbrev, std_offset, dst: None });
+        }
+
+        let dst_abbrev = p.abbrev()?;
+        let dst_offset = if p.eof() || p.peek() == Some(b',') {
+            std_offset + 3600
+        } else {
+            -p.offset()?
+        };
+
+        let (start, end) = if p.eof() {
+            // No rule given: POSIX leaves this implementation-defined; use US rules like glibc
+            (
+                Transition { date: RuleDate::MonthWeekDay { month: 3, week: 2, weekday: 0 }, time: 7200 },
+                Transition { date: RuleDate::MonthWeekDay { month: 11, week: 1, weekday: 0 }, time: 7200 },
+            )
+        } else {
+            p.expect(b',')?;
+            let start = p.transition()?;
+            p.expect(b',')?;
+            let end = p.transition()?;
+            (start, end)
+        };
+
+        if !p.eof() {
+            return None;
+        }
+
+        Some(Zone {
+            name,
+            std_abbrev,
+            std_offset,
+            dst: Some(DstRule { abbrev: dst_abbrev, offset: dst_offset, start, end }),
+        })
+    }
+
+    /// Is DST in effect at Unix time `timestamp`?
+    pub fn is_dst(&self, timestamp: i64) -> bool {
+        let dst = match self.dst {
+            Some(ref dst) => dst,
+            None => return false,
+        };
+
+        let year = timestamp_to_datetime(timestamp + self.std_offset as i64).year;
+        // Start is given in standard local time, end in daylight local time
+        let start = transition_local_seconds(year, &dst.start) - self.std_offset as i64;
+        let end = transition_local_seconds(year, &dst.end) - dst.offset as i64;
+
+        if start < end {
+            // Northern hemisphere: DST in the middle of the year
+            timestamp >= start && timestamp < end
+        } else {
+            // Southern hemisphere: DST spans the new year
+            !(timestamp >= end && timestamp < start)
+        }
+    }
+
+    /// Convert a Unix timestamp to local time in this zone
+    pub fn to_local(&self, timestamp: i64) -> LocalTime {
+        let (offset, abbrev, is_dst) = match self.dst {
+            Some(ref dst) if self.is_dst(timestamp) => (dst.offset, dst.abbrev, true),
+            _ => (self.std_offset, self.std_abbrev, false),
+        };
+
+        LocalTime {
+            datetime: timestamp_to_datetime(timestamp + offset as i64),
+            utc_offset: offset,
+            is_dst,
+            abbrev,
+        }
+    }
+}
+
+/// Seconds since the epoch, in local wall-clock time, at which `transition` happens in `year`
+fn transition_local_seconds(year: i32, transition: &Transition) -> i64 {
+    let jan1 = days_from_civil(year, 1, 1);
+    let day = match transition.date {
+        RuleDate::MonthWeekDay { month, week, weekday } => {
+            let first = days_from_civil(year, month, 1);
+            let first_weekday = weekday_from_days(first);
+            let mut day = first + ((weekday as i64 - first_weekday as i64).rem_euclid(7)) + (week as i64 - 1) * 7;
+            // Week 5 means "last": step back until we are inside the month
+            let month_end = first + days_in_month(year, month) as i64;
+            while day >= month_end {
+                day -= 7;
+            }
+            day
+        }
+        RuleDate::Julian(n) => {
+            let n = n as i64;
+            let leap_shift = if is_leap_year(year) && n >= 60 { 1 } else { 0 };
+            jan1 + n - 1 + leap_shift
+        }
+        RuleDate::DayOfYear(n) => jan1 + n as i64,
+    };
+    day * 86400 + transition.time as i64
+}
+
+/// Minimal cursor over a POSIX TZ string
+struct PosixParser<'a> {
+    s: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PosixParser<'a> {
+    fn eof(&self) -> bool {
+        self.pos >= self.s.len()
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.s.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, c: u8) -> Option<()> {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    /// Zone abbreviation: 3+ letters, or anything inside <...>
+    fn abbrev(&mut self) -> Option<Abbrev> {
+        let start;
+        let end;
+        if self.peek() == Some(b'<') {
+            self.pos += 1;
+            start = self.pos;
+            while self.peek()? != b'>' {
+                self.pos += 1;
+            }
+            end = self.pos;
+            self.pos += 1;
+        } else {
+            start = self.pos;
+            while matches!(self.peek(), Some(c) if c.is_ascii_alphabetic()) {
+                self.pos += 1;
+            }
+            end = self.pos;
+        }
+        if end - start < 3 {
+            return None;
+        }
+        Some(Abbrev::new(core::str::from_utf8(&self.s[start..end]).ok()?))
+    }
+
+    fn number(&mut self) -> Option<i32> {
+        let start = self.pos;
+        let mut value: i32 = 0;
+        while let Some(c) = self.peek() {
+            if !c.is_ascii_digit() {
+                break;
+            }
+            value = value.checked_mul(10)?.checked_add((c - b'0') as i32)?;
+            self.pos += 1;
+        }
+        if self.pos == start { None } else { Some(value) }
+    }
+
+    /// [+-]hh[:mm[:ss]] in seconds
+    fn offset(&mut self) -> Option<i32> {
+        let sign = match self.peek() {
+            Some(b'-') => { self.pos += 1; -1 }
+            Some(b'+') => { self.pos += 1; 1 }
+            _ => 1,
+        };
+        let mut seconds = self.number()? * 3600;
+        if self.expect(b':').is_some() {
+            seconds += self.number()? * 60;
+            if self.expect(b':').is_some() {
+                seconds += self.number()?;
+            }
+        }
+        Some(sign * seconds)
+    }
+
+    /// date[/time]
+    fn transition(&mut self) -> Option<Transition> {
+        let date = match self.peek()? {
+            b'M' => {
+                self.pos += 1;
+                let month = self.number()?;
+                self.expect(b'.')?;
+                let week = self.number()?;
+                self.expect(b'.')?;
+                let weekday = self.number()?;
+                if !(1..=12).contains(&month) || !(1..=5).contains(&week) || weekday > 6 {
+                    return None;
+                }
+                RuleDate::MonthWeekDay { month: month as u8, week: week as u8, weekday: weekday as u8 }
+            }
+            b'J' => {
+                self.pos += 1;
+                let n = self.number()?;
+                if !(1..=365).contains(&n) {
+                    return None;
+                }
+                RuleDate::Julian(n as u16)
+            }
+            _ => {
+                let n = self.number()?;
+                if n > 365 {
+                    return None;
+                }
+                RuleDate::DayOfYear(n as u16)
+            }
+        };
+        let time = if self.expect(b'/').is_some() { self.offset()? } else { 7200 };
+        Some(Transition { date, time })
+    }
+}
+
+static CURRENT_ZONE: Mutex<Option<Zone>> = Mutex::new(None);
+
+/// Install the default zone
+pub fn init() {
+    let mut zone = CURRENT_ZONE.lock();
+    if zone.is_none() {
+        *zone = Zone::by_name(DEFAULT_ZONE);
+    }
+}
+
+/// The active timezone (UTC if none has been set)
+pub fn current() -> Zone {
+    CURRENT_ZONE.lock().unwrap_or(Zone {
+        name: "UTC",
+        std_abbrev: Abbrev::new("UTC"),
+        std_offset: 0,
+        dst: None,
+    })
+}
+
+/// Switch timezone by table name ("Europe/Paris") or POSIX TZ string ("CET-1CEST,M3.5.0,M10.5.0/3")
+pub fn set_timezone(spec: &str) -> Result<Zone, &'static str> {
+    let zone = match Zone::by_name(spec) {
+        Some(zone) => zone,
+        None => {
+            let zone = Zone::parse_posix("", spec).ok_or("Unknown timezone or invalid POSIX TZ string")?;
+            // Custom rule: keep the spec as the zone name. Only valid specs get
+            // here, and setting the current one again reuses its name, so the
+            // leak stays as small as the number of rules actually used.
+            let name = match *CURRENT_ZONE.lock() {
+                Some(current) if current.name == spec => current.name,
+                _ => Box::leak(String::from(spec).into_boxed_str()),
+            };
+            Zone { name, ..zone }
+        }
+    };
+    *CURRENT_ZONE.lock() = Some(zone);
+    Ok(zone)
+}
+
+/// Names of all built-in zones
+pub fn zone_names() -> impl Iterator<Item = &'static str> {
+    ZONE_TABLE.iter().map(|(name, _)| *name)
+}
+
+/// Format a UTC offset as "+01:00"
+pub fn format_offset(offset: i32) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    let abs = offset.abs();
+    alloc::format!("{}{:02}:{:02}", sign, abs / 3600, (abs % 3600) / 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kernel::clock::parse_datetime;
+
+    fn utc(s: &str) -> i64 {
+        parse_datetime(s).unwrap()
+    }
+
+    #[test]
+    fn test_parse_fixed_offset() {
+        let zone = Zone::parse_posix("test", "IST-5:30").unwrap();
+        assert_eq!(zone.std_offset, 5 * 3600 + 30 * 60);
+        assert_eq!(zone.std_abbrev.as_str(), "IST");
+        assert!(zone.dst.is_none());
+
+        let zone = Zone::parse_posix("test", "<-03>3").unwrap();
+        assert_eq!(zone.std_offset, -3 * 3600);
+        assert_eq!(zone.std_abbrev.as_str(), "-03");
+    }
+
+    #[test]
+    fn test_eu_transitions() {
+        let zone = Zone::by_name("Europe/Berlin").unwrap();
+        // 2024: DST from 2024-03-31 01:00 UTC to 2024-10-27 01:00 UTC
+        assert!(!zone.is_dst(utc("2024-03-31 00:59:59")));
+        assert!(zone.is_dst(utc("2024-03-31 01:00:00")));
+        assert!(zone.is_dst(utc("2024-10-27 00:59:59")));
+        assert!(!zone.is_dst(utc("2024-10-27 01:00:00")));
+
+        let local = zone.to_local(utc("2024-07-01 12:00:00"));
+        assert_eq!(local.datetime.hour, 14);
+        assert_eq!(local.abbrev.as_str(), "CEST");
+    }
+
+    #[test]
+    fn test_us_transitions() {
+        let zone = Zone::by_name("america/new_york").unwrap();
+        // 2024: DST from 2024-03-10 07:00 UTC to 2024-11-03 06:00 UTC
+        assert!(!zone.is_dst(utc("2024-03-10 06:59:59")));
+        assert!(zone.is_dst(utc("2024-03-10 07:00:00")));
+        assert!(!zone.is_dst(utc("2024-11-03 06:00:00")));
+    }
+
+    #[test]
+    fn test_southern_hemisphere() {
+        let zone = Zone::by_name("Australia/Sydney").unwrap();
+        assert!(zone.is_dst(utc("2024-01-15 00:00:00")));
+        assert!(!zone.is_dst(utc("2024-07-15 00:00:00")));
+        assert_eq!(zone.to_local(utc("2024-01-15 00:00:00")).utc_offset, 11 * 3600);
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        assert!(Zone::parse_posix("test", "X1").is_none());
+        assert!(Zone::parse_posix("test", "CET-1CEST,M13.5.0,M10.5.0").is_none());
+        assert!(Zone::parse_posix("test", "CET-1CEST,M3.5.0").is_none());
+    }
+
+    #[test]
+    fn test_set_timezone() {
+        assert_eq!(set_timezone("Europe/Paris").unwrap().name, "Europe/Paris");
+        assert!(set_timezone("CET-1CEST,M13.5.0,M10.5.0").is_err());
+        assert_eq!(current().name, "Europe/Paris");
+
+        let spec = "AAA-3BBB,M3.5.0,M10.5.0/3";
+        let zone = set_timezone(spec).unwrap();
+        assert_eq!(zone.name, spec);
+        // Setting the same rule again reuses its name instead of leaking another
+        assert!(core::ptr::eq(set_timezone(spec).unwrap().name, zone.name));
+        assert_eq!(current().std_offset, 3 * 3600);
+    }
+}
@@ -258,6 +258,10 @@ impl UsbHidDevice {
 
 /// Add an input event to the global event queue
 pub fn queue_input_event(event: InputEvent) {
+    // Keystroke and mouse timing is unpredictable; feed it to the entropy pool
+    let timestamp = crate::kernel::drivers::timer::read_counter();
+    crate::kernel::random::add_entropy(&timestamp.to_le_bytes()[..4]);
+
     unsafe {
         if let Some(ref mut queue) = INPUT_EVENT_QUEUE {
             queue.push_back(event);
@@ -639,66 +643,13 @@ pub fn test_input_events() -> (bool, bool) {
                                     if crate::gui::window_manager::get_window_count() >= 4 {
                                         set_menu_status("Cannot open: 4 windows max");
                                     } else {
-                                        // Check if file is an image (BMP, PNG, or JPEG)
-                                        let lower = filename.to_lowercase();
-                                        let is_image = lower.ends_with(".bmp") || lower.ends_with(".png") || lower.ends_with(".jpg") || lower.ends_with(".jpeg");
-
-                                        if let Some(explorer) = crate::gui::widgets::file_explorer::get_file_explorer(explorer_id) {
-                                        if let (Some(ref fs), Some(device_idx)) = (&explorer.filesystem, explorer.device_index) {
-                                            // Get file info by listing all files
-                                            let file_list = fs.list_files();
-                                            let file_entry = file_list.iter().find(|e| e.get_name() == filename);
-
-                                            if let Some(file) = file_entry {
-                                                let size = file.get_size_bytes() as usize;
-                                                let mut buffer = alloc::vec![0u8; size];
-
-                                                unsafe {
-                                                    if let Some(ref mut devices) = crate::kernel::BLOCK_DEVICES {
-                                                        if let Some(device) = devices.get_mut(device_idx) {
-                                                            if let Ok(bytes_read) = fs.read_file(device, &filename, &mut buffer) {
-                                                                if is_image {
-                                                                    // Open in image viewer
-                                                                    let viewer_id = crate::gui::widgets::image_viewer::create_image_viewer_with_data(
-                                                                        &filename,
-                                                                        &buffer[..bytes_read]
-                                                                    );
-                                                                    let title = alloc::format!("Image - {}", filename);
-                                                                    let window = crate::gui::window_manager::Window::new(
-                                                                        0, 0, 800, 600, &title,
-                                                                        crate::gui::window_manager::WindowContent::ImageViewer,
-                                                                        viewer_id
-                                                                    );
-                                                                    crate::gui::window_manager::add_window(window);
-                                                                } else {
-                                                                    // Open in text editor
-                                                                    // Find the actual content length (for text files)
-                                                                    let actual_len = buffer[..bytes_read].iter()
-                                                                        .position(|&b| b == 0)
-                                                                        .unwrap_or(bytes_read);
-
-                                                                    if let Ok(text) = core::str::from_utf8(&buffer[..actual_len]) {
-                                                                        let editor_id = crate::gui::widgets::editor::create_editor_with_content(
-                                                                            &filename,
-                                                                            text
-                                                                        );
-                                                                        let title = alloc::format!("Editor - {}", filename);
-                                                                        let window = crate::gui::window_manager::Window::new(
-                                                                            0, 0, 640, 480, &title,
-                                                                            crate::gui::window_manager::WindowContent::Editor,
-                                                                            editor_id
-                                                                        );
-                                                                        crate::gui::window_manager::add_window(window);
-                                                                    }
-                                                                }
-                                                            }
-                                                        }
-                                                    }
-                                                }
+                                        match crate::gui::window_manager::window_for_file(&filename) {
+                                            Ok(window) => {
+                                                crate::gui::window_manager::add_window(window);
                                             }
+                                            Err(e) => set_menu_status(e),
                                         }
                                     }
-                                    }
                                 }
                                 _ => {}
                             }
@@ -1030,7 +981,9 @@ pub fn finish_filename_prompt(editor: &mut crate::gui::widgets::editor::TextEdit
     unsafe {
         if let Some(filename) = FILENAME_PROMPT.take() {
             if !filename.is_empty() {
-                editor.set_filename(&filename);
+                // Names without a directory go on the root volume
+                let path = crate::system::fs::vfs::join(&crate::system::fs::vfs::home(), &filename);
+                editor.set_filename(&path);
                 save_editor_file_internal(editor);
             } else {
                 editor.set_status("Save cancelled - no filename provided");
@@ -1054,25 +1007,13 @@ pub fn finish_filename_prompt_for_file_explorer() {
                 // Get the focused file explorer
                 if let Some(explorer_id) = crate::gui::window_manager::get_focused_file_explorer_id() {
                     if let Some(explorer) = crate::gui::widgets::file_explorer::get_file_explorer(explorer_id) {
-                        if let (Some(ref mut fs), Some(device_idx)) = (&mut explorer.filesystem, explorer.device_index) {
-                            if let Some(ref mut devices) = crate::kernel::BLOCK_DEVICES {
-                                if let Some(device) = devices.get_mut(device_idx) {
-                                    // Create the file (1KB default size)
-                                    match fs.create_file(device, &filename, 1024) {
-                                        Ok(()) => {
-                                            // Write some initial content
-                                            let initial_content = b"";
-                                            let _ = fs.write_file(device, &filename, initial_content);
-
-                                            // Refresh the file list
-                                            crate::gui::widgets::file_explorer::refresh(explorer_id);
-                                        }
-                                        Err(_e) => {
-                                            // File creation failed (maybe duplicate name)
-                                        }
-                                    }
-                                }
+                        let path = explorer.path_of(&filename);
+                        match crate::system::fs::vfs::create_file(&path, 0) {
+                            Ok(()) => {
+                                // Refresh the file list
+                                crate::gui::widgets::file_explorer::refresh(explorer_id);
                             }
+                            Err(e) => set_menu_status(e),
                         }
                     }
                 }
@@ -1103,22 +1044,15 @@ pub fn finish_rename_prompt_for_file_explorer() {
                     // Get the focused file explorer
                     if let Some(explorer_id) = crate::gui::window_manager::get_focused_file_explorer_id() {
                         if let Some(explorer) = crate::gui::widgets::file_explorer::get_file_explorer(explorer_id) {
-                            if let (Some(ref mut fs), Some(device_idx)) = (&mut explorer.filesystem, explorer.device_index) {
-                                if let Some(ref mut devices) = crate::kernel::BLOCK_DEVICES {
-                                    if let Some(device) = devices.get_mut(device_idx) {
-                                        // Rename the file
-                                        match fs.rename_file(device, &old_filename, &new_filename) {
-                                            Ok(()) => {
-                                                // Refresh the file list and re-select the renamed file
-                                                crate::gui::widgets::file_explorer::refresh(explorer_id);
-                                                crate::gui::widgets::file_explorer::select_file_by_name(explorer_id, &new_filename);
-                                            }
-                                            Err(_e) => {
-                                                // Rename failed
-                                            }
-                                        }
-                                    }
+                            let old_path = explorer.path_of(&old_filename);
+                            let new_path = explorer.path_of(&new_filename);
+                            match crate::system::fs::vfs::rename(&old_path, &new_path) {
+                                Ok(()) => {
+                                    // Refresh the file list and re-select the renamed file
+                                    crate::gui::widgets::file_explorer::refresh(explorer_id);
+                                    crate::gui::widgets::file_explorer::select_file_by_name(explorer_id, &new_filename);
                                 }
+                                Err(e) => set_menu_status(e),
                             }
                         }
                     }
@@ -1225,92 +1159,22 @@ fn save_editor_file_internal(editor: &mut crate::gui::widgets::editor::TextEdito
     let content = editor.get_text();
     let content_bytes = content.as_bytes();
 
-    // Access filesystem directly
-    unsafe {
-        if let Some(ref mut devices) = crate::kernel::BLOCK_DEVICES {
-            if let Some(device) = devices.get_mut(0) { // Use first block device
-                // Mount filesystem
-                let mut fs = match crate::system::fs::filesystem::SimpleFilesystem::mount(device) {
-                    Ok(fs) => fs,
-                    Err(_) => {
-                        editor.set_status("Failed to mount filesystem");
-                        return;
-                    }
-                };
-                // Check if file exists and get its size
-                let files = fs.list_files();
-                let existing_file = files.iter().find(|f| f.get_name() == filename);
-
-                let required_size = ((content_bytes.len() + 511) / 512) * 512; // Round up to sector
-
-                // If file doesn't exist or is too small, (re)create it
-                if let Some(file) = existing_file {
-                    let current_size = file.get_size_bytes() as usize;
-                    if content_bytes.len() > current_size {
-                        // File exists but is too small, delete and recreate
-                        match fs.delete_file(device, &filename) {
-                            Ok(()) => {
-                                uart_write_string(&alloc::format!("Resizing '{}' from {} to {} bytes\r\n",
-                                    filename, current_size, required_size));
-                            }
-                            Err(e) => {
-                                let msg = alloc::format!("Error deleting file for resize: {}", e);
-                                set_menu_status(&msg);
-                                uart_write_string(&alloc::format!("{}\r\n", msg));
-                                return;
-                            }
-                        }
-                        // Create new larger file
-                        match fs.create_file(device, &filename, required_size as u32) {
-                            Ok(()) => {
-                                uart_write_string(&alloc::format!("Created larger file '{}'\r\n", filename));
-                            }
-                            Err(e) => {
-                                let msg = alloc::format!("Error creating resized file: {}", e);
-                                set_menu_status(&msg);
-                                uart_write_string(&alloc::format!("{}\r\n", msg));
-                                return;
-                            }
-                        }
-                    }
-                } else {
-                    // File doesn't exist, create it
-                    match fs.create_file(device, &filename, required_size as u32) {
-                        Ok(()) => {
-                            uart_write_string(&alloc::format!("Created file '{}'\r\n", filename));
-                        }
-                        Err(e) => {
-                            let msg = alloc::format!("Error creating file: {}", e);
-                            set_menu_status(&msg);
-                            uart_write_string(&alloc::format!("{}\r\n", msg));
-                            return;
-                        }
-                    }
-                }
-
-                // Write content to file
-                match fs.write_file(device, &filename, content_bytes) {
-                    Ok(()) => {
-                        editor.mark_saved();
-                        let msg = alloc::format!("Saved {} bytes to '{}'", content_bytes.len(), filename);
-                        set_menu_status(&msg);
-                        uart_write_string(&alloc::format!("{}\r\n", msg));
-
-                        // Update editor window title to show filename
-                        let window_title = alloc::format!("Text Editor - {}", filename);
-                        crate::gui::window_manager::set_editor_window_title(&window_title);
-                    }
-                    Err(e) => {
-                        let msg = alloc::format!("Error saving: {}", e);
-                        set_menu_status(&msg);
-                        uart_write_string(&alloc::format!("{}\r\n", msg));
-                    }
-                }
-            } else {
-                editor.set_status("Block device not available");
-            }
-        } else {
-            editor.set_status("Block device not available");
+    match crate::system::fs::vfs::write_file(&filename, content_bytes) {
+        Ok(()) => {
+            editor.mark_saved();
+            let msg = alloc::format!("Saved {} bytes to '{}'", content_bytes.len(), filename);
+            set_menu_status(&msg);
+            uart_write_string(&alloc::format!("{}\r\n", msg));
+
+            // Update editor window title to show filename
+            let window_title = alloc::format!("Text Editor - {}", filename);
+            crate::gui::window_manager::set_editor_window_title(&window_title);
+            crate::gui::widgets::file_explorer::refresh_all_explorers();
+        }
+        Err(e) => {
+            let msg = alloc::format!("Error saving: {}", e);
+            set_menu_status(&msg);
+            uart_write_string(&alloc::format!("{}\r\n", msg));
         }
     }
 }
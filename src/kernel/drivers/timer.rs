@@ -1,33 +1,36 @@
 // ARM Generic Timer for hardware-independent timing
+//
+// Conversions to wall-clock units live in kernel::clock; this module only
+// exposes the raw counter plus the busy-wait helpers built on top of it.
 
 use core::arch::asm;
 
-/// Get current time in microseconds using ARM Generic Timer
-pub fn get_time_us() -> u64 {
+/// Read the physical counter (CNTPCT_EL0)
+pub fn read_counter() -> u64 {
     let counter: u64;
-    let frequency: u64;
-
     unsafe {
-        // Read the counter value (CNTPCT_EL0)
         asm!("mrs {}, cntpct_el0", out(reg) counter);
+    }
+    counter
+}
 
-        // Read the counter frequency (CNTFRQ_EL0)
+/// Read the counter frequency in Hz (CNTFRQ_EL0)
+pub fn counter_frequency() -> u64 {
+    let frequency: u64;
+    unsafe {
         asm!("mrs {}, cntfrq_el0", out(reg) frequency);
     }
+    frequency
+}
 
-    // Convert counter ticks to microseconds
-    // Formula: (counter * 1_000_000) / frequency
-    // To avoid overflow, we can do: counter / (frequency / 1_000_000)
-    if frequency > 0 {
-        (counter * 1_000_000) / frequency
-    } else {
-        0
-    }
+/// Get current time in microseconds (CLOCK_MONOTONIC)
+pub fn get_time_us() -> u64 {
+    crate::kernel::clock::monotonic_us()
 }
 
-/// Get current time in milliseconds
+/// Get current time in milliseconds (CLOCK_MONOTONIC)
 pub fn get_time_ms() -> u64 {
-    get_time_us() / 1000
+    crate::kernel::clock::monotonic_ms()
 }
 
 /// Simple delay in microseconds (busy wait)
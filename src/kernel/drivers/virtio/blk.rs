@@ -1,8 +1,25 @@
 // VirtIO Block Device Driver
 // Based on VirtIO 1.0 specification and Stephen Brennan's implementation
+//
+// A request covers any sector range: its data is split over a chain of
+// descriptors between the header and status descriptors, and many requests can
+// be in flight at once up to the queue depth. Headers and status bytes are
+// allocated per request. Completions are collected by poll_completions() on the
+// main loop, which wakes the futures returned by read() (used by executor tasks
+// such as the shell's diskspeed); the blocking read_blocks/write_blocks queue
+// their requests the same way and spin until they finish.
+//
+// Device state lives in QUEUES and a VirtioBlkDevice is a handle to its entry,
+// so in-flight requests stay valid however the handle is moved or boxed.
 
 use crate::kernel::drivers::pci::{PciConfig, PciDevice};
+use crate::system::block::{check_range, check_request, zero_fill, BlockDevice};
+use core::cell::RefCell;
+use core::future::poll_fn;
 use core::ptr;
+use core::task::{Poll, Waker};
+use alloc::boxed::Box;
+use alloc::rc::Rc;
 use alloc::vec::Vec;
 
 // VirtIO Device IDs
@@ -15,21 +32,40 @@ const VIRTIO_STATUS_ACKNOWLEDGE: u8 = 1;
 const VIRTIO_STATUS_DRIVER: u8 = 2;
 const VIRTIO_STATUS_FEATURES_OK: u8 = 8;
 const VIRTIO_STATUS_DRIVER_OK: u8 = 4;
-const VIRTIO_STATUS_FAILED: u8 = 128;
 
 // VirtIO PCI Capability Types
 const VIRTIO_PCI_CAP_COMMON_CFG: u8 = 1;
 const VIRTIO_PCI_CAP_NOTIFY_CFG: u8 = 2;
-const VIRTIO_PCI_CAP_ISR_CFG: u8 = 3;
 const VIRTIO_PCI_CAP_DEVICE_CFG: u8 = 4;
 
 // Virtqueue descriptor flags
 const VIRTQ_DESC_F_NEXT: u16 = 1;
 const VIRTQ_DESC_F_WRITE: u16 = 2;
 
+// VirtIO Block Feature Bits
+const VIRTIO_BLK_F_GEOMETRY: u32 = 1 << 4;
+const VIRTIO_BLK_F_RO: u32 = 1 << 5;
+const VIRTIO_BLK_F_BLK_SIZE: u32 = 1 << 6;
+const VIRTIO_BLK_F_FLUSH: u32 = 1 << 9;
+const VIRTIO_BLK_F_DISCARD: u32 = 1 << 13;
+const VIRTIO_BLK_F_WRITE_ZEROES: u32 = 1 << 14;
+
+// VirtIO Generic Feature Bits (bits 32+)
+const VIRTIO_F_VERSION_1: u32 = 1 << 0;  // Bit 32 in features[1]
+
 // VirtIO Block Request Types
 const VIRTIO_BLK_T_IN: u32 = 0;  // Read
 const VIRTIO_BLK_T_OUT: u32 = 1; // Write
+const VIRTIO_BLK_T_FLUSH: u32 = 4;
+const VIRTIO_BLK_T_DISCARD: u32 = 11;
+const VIRTIO_BLK_T_WRITE_ZEROES: u32 = 13;
+
+// Offsets into the device-specific config space (struct virtio_blk_config)
+const BLK_CFG_CAPACITY: u64 = 0;
+const BLK_CFG_GEOMETRY: u64 = 16;
+const BLK_CFG_BLK_SIZE: u64 = 20;
+const BLK_CFG_MAX_DISCARD_SECTORS: u64 = 36;
+const BLK_CFG_MAX_WRITE_ZEROES_SECTORS: u64 = 48;
 
 // VirtIO Block Status
 const VIRTIO_BLK_S_OK: u8 = 0;
@@ -39,6 +75,11 @@ const VIRTIO_BLK_S_UNSUPP: u8 = 2;
 const SECTOR_SIZE: usize = 512;
 const QUEUE_SIZE: u16 = 128;
 
+/// Largest buffer described by a single descriptor
+const MAX_SEGMENT_BYTES: usize = 64 * 1024;
+/// Larger transfers are split into several requests (18 descriptors each)
+const MAX_REQUEST_BYTES: usize = 1024 * 1024;
+
 // Memory barrier
 #[inline(always)]
 fn mb() {
@@ -114,6 +155,66 @@ struct VirtioBlkReqHeader {
     sector: u64,
 }
 
+/// Disk properties read from the device config at init
+#[derive(Clone, Copy, Debug)]
+pub struct DiskInfo {
+    /// Size in 512-byte sectors
+    pub capacity: u64,
+    /// Preferred I/O size in bytes (requests are still in 512-byte sectors)
+    pub block_size: u32,
+    /// Legacy CHS geometry: (cylinders, heads, sectors per track)
+    pub geometry: Option<(u16, u8, u8)>,
+    pub read_only: bool,
+    pub flush: bool,
+    /// Largest discard / write-zeroes request in sectors (None if unsupported)
+    pub max_discard_sectors: Option<u32>,
+    pub max_write_zeroes_sectors: Option<u32>,
+}
+
+/// Per-request memory the device reads the header from and writes the status to
+#[repr(C)]
+struct RequestBlock {
+    header: VirtioBlkReqHeader,
+    status: u8,
+}
+
+/// Result of a request, filled in by poll_completions()
+struct Completion {
+    result: Option<Result<(), &'static str>>,
+    /// Data buffer handed back to an async request
+    buffer: Option<Vec<u8>>,
+    waker: Option<Waker>,
+}
+
+type CompletionSlot = Rc<RefCell<Completion>>;
+
+fn new_slot() -> CompletionSlot {
+    Rc::new(RefCell::new(Completion { result: None, buffer: None, waker: None }))
+}
+
+/// A request waiting to be queued
+struct Request {
+    req_type: u32,
+    sector: u64,
+    data: *mut u8,
+    len: usize,
+    /// Keeps an async request's buffer alive until the device is done with it
+    buffer: Option<Vec<u8>>,
+    slot: CompletionSlot,
+}
+
+/// A request the device owns until it shows up in the used ring
+struct InFlight {
+    block: Box<RequestBlock>,
+    buffer: Option<Vec<u8>>,
+    slot: CompletionSlot,
+}
+
+/// Descriptors used by a request with `len` data bytes
+fn descriptors_needed(len: usize) -> u16 {
+    (2 + (len + MAX_SEGMENT_BYTES - 1) / MAX_SEGMENT_BYTES) as u16
+}
+
 /// Virtqueue structure
 struct Virtqueue {
     // Physical address of queue memory
@@ -124,6 +225,8 @@ struct Virtqueue {
     last_seen_used: u16,
     // Next free descriptor
     free_desc: u16,
+    // Number of descriptors on the free list
+    num_free: u16,
 
     // Pointers to queue structures (virtual addresses)
     desc: *mut VirtqDesc,
@@ -131,9 +234,6 @@ struct Virtqueue {
     avail_ring: *mut u16,
     used: *mut VirtqUsed,
     used_ring: *mut VirtqUsedElem,
-
-    // Track virtual addresses for each descriptor
-    desc_virt_addrs: [u64; QUEUE_SIZE as usize],
 }
 
 impl Virtqueue {
@@ -182,25 +282,26 @@ impl Virtqueue {
             size,
             last_seen_used: 0,
             free_desc: 0,
+            num_free: size,
             desc,
             avail,
             avail_ring,
             used,
             used_ring,
-            desc_virt_addrs: [0; QUEUE_SIZE as usize],
         })
     }
 
-    /// Allocate a descriptor and associate it with a virtual address
-    unsafe fn alloc_desc(&mut self, virt_addr: u64) -> Option<u16> {
+    /// Take a descriptor off the free list
+    unsafe fn alloc_desc(&mut self) -> Option<u16> {
+        if self.num_free == 0 {
+            return None;
+        }
         let idx = self.free_desc;
         let desc_ptr = self.desc.add(idx as usize);
 
         // Update free list
         self.free_desc = (*desc_ptr).next;
-
-        // Store virtual address for this descriptor
-        self.desc_virt_addrs[idx as usize] = virt_addr;
+        self.num_free -= 1;
 
         Some(idx)
     }
@@ -211,17 +312,186 @@ impl Virtqueue {
         (*desc_ptr).next = self.free_desc;
         (*desc_ptr).flags = 0;
         self.free_desc = idx;
-        self.desc_virt_addrs[idx as usize] = 0;
+        self.num_free += 1;
+    }
+
+    /// Free every descriptor of the chain starting at `head`
+    unsafe fn free_chain(&mut self, head: u16) {
+        let mut idx = head;
+        loop {
+            let desc = *self.desc.add(idx as usize);
+            self.free_desc(idx);
+            if desc.flags & VIRTQ_DESC_F_NEXT == 0 {
+                break;
+            }
+            idx = desc.next;
+        }
+    }
+
+    unsafe fn set_desc(&mut self, idx: u16, addr: u64, len: usize, flags: u16) {
+        let desc_ptr = self.desc.add(idx as usize);
+        (*desc_ptr).addr = addr;
+        (*desc_ptr).len = len as u32;
+        (*desc_ptr).flags = flags;
+        (*desc_ptr).next = 0;
     }
 }
 
-/// VirtIO Block Device
-pub struct VirtioBlkDevice {
-    pci_device: PciDevice,
-    common_cfg: *mut VirtioPciCommonCfg,
-    notify_base: u64,
-    notify_off_multiplier: u32,
+/// Driver state of one disk
+struct BlkQueue {
+    notify_addr: u64,
     virtq: Virtqueue,
+    info: DiskInfo,
+    /// Requests in flight, indexed by head descriptor
+    in_flight: Vec<Option<InFlight>>,
+    /// Tasks waiting for descriptors to free up
+    space_waiters: Vec<Waker>,
+}
+
+static mut QUEUES: Vec<BlkQueue> = Vec::new();
+
+fn queue(index: usize) -> &'static mut BlkQueue {
+    unsafe { &mut QUEUES[index] }
+}
+
+impl BlkQueue {
+    /// Put a request on the available ring, or hand it back if there aren't
+    /// enough free descriptors for it right now
+    unsafe fn submit(&mut self, request: Request) -> Result<(), Request> {
+        let needed = descriptors_needed(request.len);
+        if needed > self.virtq.num_free {
+            return Err(request);
+        }
+
+        let mut block = Box::new(RequestBlock {
+            header: VirtioBlkReqHeader { req_type: request.req_type, reserved: 0, sector: request.sector },
+            status: 0xFF, // Overwritten by the device
+        });
+        let header_addr = ptr::addr_of!(block.header) as u64;
+        let status_addr = ptr::addr_of_mut!(block.status) as u64;
+
+        let chain: Vec<u16> = (0..needed).map(|_| self.virtq.alloc_desc().unwrap()).collect();
+
+        // Header (read-only for device)
+        self.virtq.set_desc(chain[0], header_addr, core::mem::size_of::<VirtioBlkReqHeader>(), VIRTQ_DESC_F_NEXT);
+
+        // Data segments: the device writes into them on reads and reads from them on writes
+        let data_flags = if request.req_type == VIRTIO_BLK_T_IN { VIRTQ_DESC_F_WRITE } else { 0 };
+        for (i, offset) in (0..request.len).step_by(MAX_SEGMENT_BYTES).enumerate() {
+            let len = core::cmp::min(MAX_SEGMENT_BYTES, request.len - offset);
+            self.virtq.set_desc(chain[1 + i], request.data as u64 + offset as u64, len, data_flags | VIRTQ_DESC_F_NEXT);
+        }
+
+        // Status byte (write for device)
+        self.virtq.set_desc(chain[needed as usize - 1], status_addr, 1, VIRTQ_DESC_F_WRITE);
+
+        for pair in chain.windows(2) {
+            (*self.virtq.desc.add(pair[0] as usize)).next = pair[1];
+        }
+
+        let head = chain[0];
+        self.in_flight[head as usize] = Some(InFlight { block, buffer: request.buffer, slot: request.slot });
+
+        // Add to available ring
+        let avail_idx = ptr::read_volatile(ptr::addr_of!((*self.virtq.avail).idx));
+        ptr::write_volatile(self.virtq.avail_ring.add(avail_idx as usize % self.virtq.size as usize), head);
+        mb();
+        ptr::write_volatile(ptr::addr_of_mut!((*self.virtq.avail).idx), avail_idx.wrapping_add(1));
+        mb();
+
+        // Notify device
+        ptr::write_volatile(self.notify_addr as *mut u16, 0);
+        mb();
+
+        Ok(())
+    }
+
+    /// Retire finished requests and wake whoever is waiting on them
+    unsafe fn poll(&mut self) -> usize {
+        let mut completed = 0;
+
+        loop {
+            let used_idx = ptr::read_volatile(ptr::addr_of!((*self.virtq.used).idx));
+            if used_idx == self.virtq.last_seen_used {
+                break;
+            }
+            mb();
+
+            let slot = self.virtq.last_seen_used as usize % self.virtq.size as usize;
+            let elem = ptr::read_volatile(self.virtq.used_ring.add(slot));
+            self.virtq.last_seen_used = self.virtq.last_seen_used.wrapping_add(1);
+
+            let head = elem.id as u16;
+            self.virtq.free_chain(head);
+            completed += 1;
+
+            let request = match self.in_flight[head as usize].take() {
+                Some(request) => request,
+                None => continue,
+            };
+
+            let result = match ptr::read_volatile(&request.block.status) {
+                VIRTIO_BLK_S_OK => Ok(()),
+                VIRTIO_BLK_S_IOERR => Err("Disk I/O error"),
+                VIRTIO_BLK_S_UNSUPP => Err("Disk request not supported"),
+                _ => Err("Disk request failed"),
+            };
+            if let Err(e) = result {
+                let sector = ptr::read_unaligned(ptr::addr_of!(request.block.header.sector));
+                crate::kernel::uart_write_string(&alloc::format!(
+                    "ERROR: Block request at sector {} failed: {}\r\n", sector, e
+                ));
+            }
+
+            let mut completion = request.slot.borrow_mut();
+            completion.result = Some(result);
+            completion.buffer = request.buffer;
+            if let Some(waker) = completion.waker.take() {
+                waker.wake();
+            }
+        }
+
+        if completed > 0 {
+            for waker in self.space_waiters.drain(..) {
+                waker.wake();
+            }
+        }
+
+        completed
+    }
+}
+
+/// Collect finished requests on every disk and wake their tasks.
+/// Call from the main loop alongside the network poll.
+pub fn poll_completions() {
+    unsafe {
+        for queue in QUEUES.iter_mut() {
+            queue.poll();
+        }
+    }
+}
+
+/// Handle to the `index`th disk found at boot, for async I/O from tasks
+pub fn disk(index: usize) -> Option<VirtioBlkDevice> {
+    unsafe { QUEUES.get(index).map(|queue| VirtioBlkDevice { queue: index, capacity: queue.info.capacity }) }
+}
+
+/// Spin until a request completes (blocking callers only)
+fn wait_for(queue: &mut BlkQueue, slot: &CompletionSlot) -> Result<(), &'static str> {
+    loop {
+        if let Some(result) = slot.borrow_mut().result.take() {
+            return result;
+        }
+        if unsafe { queue.poll() } == 0 {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// VirtIO Block Device (handle to its QUEUES entry)
+#[derive(Clone, Copy)]
+pub struct VirtioBlkDevice {
+    queue: usize,
     capacity: u64,
 }
 
@@ -269,7 +539,7 @@ impl VirtioBlkDevice {
         pci_dev.enable_bus_mastering();
 
         // Parse PCI capabilities to find VirtIO structures
-        let (common_cfg_addr, notify_addr, notify_off_mult) =
+        let (common_cfg_addr, notify_addr, notify_off_mult, device_cfg_addr) =
             Self::parse_capabilities(&pci_dev, mmio_base)?;
 
         let common_cfg = common_cfg_addr as *mut VirtioPciCommonCfg;
@@ -291,9 +561,28 @@ impl VirtioBlkDevice {
 
         crate::kernel::uart_write_string("Device acknowledged, driver bit set\r\n");
 
-        // 4. Feature negotiation (we don't need any special features for now)
+        // 4. Feature negotiation - read what device offers
+        ptr::write_volatile(&mut (*common_cfg).device_feature_select, 0);
+        mb();
+        let device_features = ptr::read_volatile(&(*common_cfg).device_feature);
+        ptr::write_volatile(&mut (*common_cfg).device_feature_select, 1);
+        mb();
+        let device_features_high = ptr::read_volatile(&(*common_cfg).device_feature);
+
+        let our_features = VIRTIO_BLK_F_GEOMETRY | VIRTIO_BLK_F_RO | VIRTIO_BLK_F_BLK_SIZE |
+            VIRTIO_BLK_F_FLUSH | VIRTIO_BLK_F_DISCARD | VIRTIO_BLK_F_WRITE_ZEROES;
+        let negotiated = device_features & our_features;
+        let negotiated_high = device_features_high & VIRTIO_F_VERSION_1;
+
+        crate::kernel::uart_write_string(&alloc::format!(
+            "Device features: 0x{:08x}, negotiating 0x{:08x}\r\n", device_features, negotiated
+        ));
+
         ptr::write_volatile(&mut (*common_cfg).driver_feature_select, 0);
-        ptr::write_volatile(&mut (*common_cfg).driver_feature, 0);
+        ptr::write_volatile(&mut (*common_cfg).driver_feature, negotiated);
+        mb();
+        ptr::write_volatile(&mut (*common_cfg).driver_feature_select, 1);
+        ptr::write_volatile(&mut (*common_cfg).driver_feature, negotiated_high);
         mb();
 
         // 5. Set FEATURES_OK
@@ -311,7 +600,7 @@ impl VirtioBlkDevice {
         crate::kernel::uart_write_string("Features negotiated successfully\r\n");
 
         // 7. Set up virtqueue
-        let mut virtq = Virtqueue::new(QUEUE_SIZE)?;
+        let virtq = Virtqueue::new(QUEUE_SIZE)?;
 
         // Select queue 0 (block devices only have one queue)
         ptr::write_volatile(&mut (*common_cfg).queue_select, 0);
@@ -360,24 +649,77 @@ impl VirtioBlkDevice {
 
         crate::kernel::uart_write_string("Device ready!\r\n");
 
-        // Read device capacity (from device-specific config space)
-        // For now we'll skip this and just report success
+        let info = Self::read_config(device_cfg_addr, negotiated);
+        crate::kernel::uart_write_string(&alloc::format!(
+            "Capacity: {} sectors ({} MB), block size {}{}{}{}{}\r\n",
+            info.capacity, info.capacity * SECTOR_SIZE as u64 / (1024 * 1024), info.block_size,
+            if info.read_only { ", read-only" } else { "" },
+            if info.flush { ", flush" } else { "" },
+            if info.max_discard_sectors.is_some() { ", discard" } else { "" },
+            if info.max_write_zeroes_sectors.is_some() { ", write-zeroes" } else { "" },
+        ));
 
-        Some(VirtioBlkDevice {
-            pci_device: pci_dev,
-            common_cfg,
-            notify_base: notify_addr,
-            notify_off_multiplier: notify_off_mult,
+        let queue_notify_off = ptr::read_volatile(&(*common_cfg).queue_notify_off);
+        let notify_addr = notify_addr + queue_notify_off as u64 * notify_off_mult as u64;
+
+        let index = QUEUES.len();
+        QUEUES.push(BlkQueue {
+            notify_addr,
             virtq,
-            capacity: 0,
-        })
+            info,
+            in_flight: (0..QUEUE_SIZE).map(|_| None).collect(),
+            space_waiters: Vec::new(),
+        });
+
+        Some(VirtioBlkDevice { queue: index, capacity: info.capacity })
+    }
+
+    /// Read the device-specific config fields covered by `features`
+    unsafe fn read_config(cfg: u64, features: u32) -> DiskInfo {
+        let read_u32 = |offset: u64| ptr::read_volatile((cfg + offset) as *const u32);
+
+        let capacity = read_u32(BLK_CFG_CAPACITY) as u64 | (read_u32(BLK_CFG_CAPACITY + 4) as u64) << 32;
+
+        let block_size = match read_u32(BLK_CFG_BLK_SIZE) {
+            size if features & VIRTIO_BLK_F_BLK_SIZE != 0 && size >= SECTOR_SIZE as u32 => size,
+            _ => SECTOR_SIZE as u32,
+        };
+
+        let geometry = if features & VIRTIO_BLK_F_GEOMETRY != 0 {
+            let cylinders = ptr::read_volatile((cfg + BLK_CFG_GEOMETRY) as *const u16);
+            let heads = ptr::read_volatile((cfg + BLK_CFG_GEOMETRY + 2) as *const u8);
+            let sectors = ptr::read_volatile((cfg + BLK_CFG_GEOMETRY + 3) as *const u8);
+            Some((cylinders, heads, sectors))
+        } else {
+            None
+        };
+
+        // A limit of 0 would make the command unusable
+        let limit = |feature: u32, offset: u64| {
+            if features & feature != 0 {
+                Some(read_u32(offset)).filter(|&max| max > 0)
+            } else {
+                None
+            }
+        };
+
+        DiskInfo {
+            capacity,
+            block_size,
+            geometry,
+            read_only: features & VIRTIO_BLK_F_RO != 0,
+            flush: features & VIRTIO_BLK_F_FLUSH != 0,
+            max_discard_sectors: limit(VIRTIO_BLK_F_DISCARD, BLK_CFG_MAX_DISCARD_SECTORS),
+            max_write_zeroes_sectors: limit(VIRTIO_BLK_F_WRITE_ZEROES, BLK_CFG_MAX_WRITE_ZEROES_SECTORS),
+        }
     }
 
     /// Parse PCI capabilities to find VirtIO structures
-    unsafe fn parse_capabilities(pci_dev: &PciDevice, mmio_base: u64) -> Option<(u64, u64, u32)> {
+    unsafe fn parse_capabilities(pci_dev: &PciDevice, mmio_base: u64) -> Option<(u64, u64, u32, u64)> {
         let mut cap_ptr = pci_dev.get_capabilities_ptr()? as u16;
         let mut common_cfg_addr = None;
         let mut notify_addr = None;
+        let mut device_cfg_addr = None;
         let mut notify_off_mult = 0u32;
 
         // Read and program BAR4 (where VirtIO capabilities point)
@@ -421,6 +763,9 @@ impl VirtioBlkDevice {
                                 "Found notify at 0x{:x} (mult={})\r\n", addr, notify_off_mult
                             ));
                         }
+                        VIRTIO_PCI_CAP_DEVICE_CFG => {
+                            device_cfg_addr = Some(addr);
+                        }
                         _ => {}
                     }
                 }
@@ -429,182 +774,187 @@ impl VirtioBlkDevice {
             cap_ptr = pci_dev.read_config_u8((cap_ptr + 1) as u8) as u16;
         }
 
-        Some((common_cfg_addr?, notify_addr?, notify_off_mult))
+        Some((common_cfg_addr?, notify_addr?, notify_off_mult, device_cfg_addr?))
     }
 
-    /// Read a sector from the block device
-    pub fn read_sector(&mut self, sector: u64, buffer: &mut [u8; SECTOR_SIZE]) -> Result<(), &'static str> {
-        unsafe {
-            // Allocate memory for request header
-            let header_phys = 0x50100000u64;
-            let header = header_phys as *mut VirtioBlkReqHeader;
-
-            // Allocate memory for status byte
-            let status_phys = 0x50100200u64;
-            let status_ptr = status_phys as *mut u8;
-
-            // Fill in request header
-            ptr::write_volatile(ptr::addr_of_mut!((*header).req_type), VIRTIO_BLK_T_IN);
-            ptr::write_volatile(ptr::addr_of_mut!((*header).reserved), 0);
-            ptr::write_volatile(ptr::addr_of_mut!((*header).sector), sector);
-
-            // Initialize status to 0xFF (will be overwritten by device)
-            ptr::write_volatile(status_ptr, 0xFF);
-
-            // Build 3-descriptor chain
-            let d1 = self.virtq.alloc_desc(header as u64).ok_or("No descriptors available")?;
-            let d2 = self.virtq.alloc_desc(buffer.as_ptr() as u64).ok_or("No descriptors available")?;
-            let d3 = self.virtq.alloc_desc(status_phys).ok_or("No descriptors available")?;
-
-            // Descriptor 1: Request header (read-only for device)
-            (*self.virtq.desc.add(d1 as usize)).addr = header_phys;
-            (*self.virtq.desc.add(d1 as usize)).len = core::mem::size_of::<VirtioBlkReqHeader>() as u32;
-            (*self.virtq.desc.add(d1 as usize)).flags = VIRTQ_DESC_F_NEXT;
-            (*self.virtq.desc.add(d1 as usize)).next = d2;
-
-            // Descriptor 2: Data buffer (write for device on read)
-            (*self.virtq.desc.add(d2 as usize)).addr = buffer.as_ptr() as u64;
-            (*self.virtq.desc.add(d2 as usize)).len = SECTOR_SIZE as u32;
-            (*self.virtq.desc.add(d2 as usize)).flags = VIRTQ_DESC_F_WRITE | VIRTQ_DESC_F_NEXT;
-            (*self.virtq.desc.add(d2 as usize)).next = d3;
-
-            // Descriptor 3: Status byte (write for device)
-            (*self.virtq.desc.add(d3 as usize)).addr = status_phys;
-            (*self.virtq.desc.add(d3 as usize)).len = 1;
-            (*self.virtq.desc.add(d3 as usize)).flags = VIRTQ_DESC_F_WRITE;
-            (*self.virtq.desc.add(d3 as usize)).next = 0;
-
-            // Add to available ring
-            let avail_idx = ptr::read_volatile(ptr::addr_of!((*self.virtq.avail).idx));
-            ptr::write_volatile(self.virtq.avail_ring.add(avail_idx as usize % QUEUE_SIZE as usize), d1);
-            mb();
-            ptr::write_volatile(ptr::addr_of_mut!((*self.virtq.avail).idx), avail_idx.wrapping_add(1));
-            mb();
-
-            // Notify device
-            let queue_notify_off = ptr::read_volatile(&(*self.common_cfg).queue_notify_off);
-            let notify_addr = self.notify_base + (queue_notify_off as u64 * self.notify_off_multiplier as u64);
-            ptr::write_volatile(notify_addr as *mut u16, 0);
-            mb();
+    /// Disk properties negotiated with the device
+    pub fn info(&self) -> DiskInfo {
+        queue(self.queue).info
+    }
 
-            // Poll for completion (busy wait)
-            let start_used_idx = self.virtq.last_seen_used;
-            loop {
-                let used_idx = ptr::read_volatile(ptr::addr_of!((*self.virtq.used).idx));
-                if used_idx != start_used_idx {
-                    break;
-                }
-                // Small delay
-                for _ in 0..1000 {
-                    core::arch::asm!("nop");
+    /// Queue `requests` and wait for all of them. They go on the ring as fast
+    /// as descriptors allow, so several are in flight at once.
+    fn run(&mut self, requests: Vec<Request>) -> Result<(), &'static str> {
+        let queue = queue(self.queue);
+        let mut slots = Vec::new();
+
+        for mut request in requests {
+            slots.push(request.slot.clone());
+            // Queue full: wait for earlier requests to free descriptors
+            while let Err(returned) = unsafe { queue.submit(request) } {
+                request = returned;
+                if unsafe { queue.poll() } == 0 {
+                    core::hint::spin_loop();
                 }
             }
+        }
 
-            // Check status
-            let final_status = ptr::read_volatile(status_ptr);
-            if final_status != VIRTIO_BLK_S_OK {
-                crate::kernel::uart_write_string(&alloc::format!(
-                    "ERROR: Read failed with status {}\r\n", final_status
-                ));
-                return Err("Read failed");
+        // The device may still be using the caller's buffer, so wait for every
+        // request even after one fails
+        let mut result = Ok(());
+        for slot in &slots {
+            if let Err(e) = wait_for(queue, slot) {
+                result = Err(e);
             }
-
-            // Free descriptors
-            self.virtq.free_desc(d1);
-            self.virtq.free_desc(d2);
-            self.virtq.free_desc(d3);
-
-            self.virtq.last_seen_used = ptr::read_volatile(ptr::addr_of!((*self.virtq.used).idx));
-
-            Ok(())
         }
+        result
     }
 
-    /// Write a sector to the block device
-    pub fn write_sector(&mut self, sector: u64, buffer: &[u8; SECTOR_SIZE]) -> Result<(), &'static str> {
-        unsafe {
-            // Allocate memory for request header
-            let header_phys = 0x50100000u64;
-            let header = header_phys as *mut VirtioBlkReqHeader;
-
-            // Allocate memory for status byte
-            let status_phys = 0x50100200u64;
-            let status_ptr = status_phys as *mut u8;
-
-            // Fill in request header (WRITE this time!)
-            ptr::write_volatile(ptr::addr_of_mut!((*header).req_type), VIRTIO_BLK_T_OUT);
-            ptr::write_volatile(ptr::addr_of_mut!((*header).reserved), 0);
-            ptr::write_volatile(ptr::addr_of_mut!((*header).sector), sector);
-
-            // Initialize status to 0xFF (will be overwritten by device)
-            ptr::write_volatile(status_ptr, 0xFF);
-
-            // Build 3-descriptor chain
-            let d1 = self.virtq.alloc_desc(header as u64).ok_or("No descriptors available")?;
-            let d2 = self.virtq.alloc_desc(buffer.as_ptr() as u64).ok_or("No descriptors available")?;
-            let d3 = self.virtq.alloc_desc(status_phys).ok_or("No descriptors available")?;
-
-            // Descriptor 1: Request header (read-only for device)
-            (*self.virtq.desc.add(d1 as usize)).addr = header_phys;
-            (*self.virtq.desc.add(d1 as usize)).len = core::mem::size_of::<VirtioBlkReqHeader>() as u32;
-            (*self.virtq.desc.add(d1 as usize)).flags = VIRTQ_DESC_F_NEXT;
-            (*self.virtq.desc.add(d1 as usize)).next = d2;
-
-            // Descriptor 2: Data buffer (READ-ONLY for device on write - device reads from it!)
-            (*self.virtq.desc.add(d2 as usize)).addr = buffer.as_ptr() as u64;
-            (*self.virtq.desc.add(d2 as usize)).len = SECTOR_SIZE as u32;
-            (*self.virtq.desc.add(d2 as usize)).flags = VIRTQ_DESC_F_NEXT;  // NO WRITE flag!
-            (*self.virtq.desc.add(d2 as usize)).next = d3;
-
-            // Descriptor 3: Status byte (write for device)
-            (*self.virtq.desc.add(d3 as usize)).addr = status_phys;
-            (*self.virtq.desc.add(d3 as usize)).len = 1;
-            (*self.virtq.desc.add(d3 as usize)).flags = VIRTQ_DESC_F_WRITE;
-            (*self.virtq.desc.add(d3 as usize)).next = 0;
-
-            // Add to available ring
-            let avail_idx = ptr::read_volatile(ptr::addr_of!((*self.virtq.avail).idx));
-            ptr::write_volatile(self.virtq.avail_ring.add(avail_idx as usize % QUEUE_SIZE as usize), d1);
-            mb();
-            ptr::write_volatile(ptr::addr_of_mut!((*self.virtq.avail).idx), avail_idx.wrapping_add(1));
-            mb();
+    /// Read or write `len` bytes at `data`, split into MAX_REQUEST_BYTES requests
+    fn transfer(&mut self, req_type: u32, start: u64, data: *mut u8, len: usize) -> Result<(), &'static str> {
+        let requests = (0..len)
+            .step_by(MAX_REQUEST_BYTES)
+            .map(|offset| Request {
+                req_type,
+                sector: start + (offset / SECTOR_SIZE) as u64,
+                data: unsafe { data.add(offset) },
+                len: core::cmp::min(MAX_REQUEST_BYTES, len - offset),
+                buffer: None,
+                slot: new_slot(),
+            })
+            .collect();
+        self.run(requests)
+    }
 
-            // Notify device
-            let queue_notify_off = ptr::read_volatile(&(*self.common_cfg).queue_notify_off);
-            let notify_addr = self.notify_base + (queue_notify_off as u64 * self.notify_off_multiplier as u64);
-            ptr::write_volatile(notify_addr as *mut u16, 0);
-            mb();
+    /// Discard or zero a sector range, at most `max_sectors` per request
+    fn range_command(&mut self, req_type: u32, start: u64, count: u64, max_sectors: u32) -> Result<(), &'static str> {
+        let mut requests = Vec::new();
+        let mut done = 0;
+        while done < count {
+            let sectors = core::cmp::min(count - done, max_sectors as u64);
+            // struct virtio_blk_discard_write_zeroes: le64 sector, le32 num_sectors, le32 flags
+            let mut segment = Vec::with_capacity(16);
+            segment.extend_from_slice(&(start + done).to_le_bytes());
+            segment.extend_from_slice(&(sectors as u32).to_le_bytes());
+            segment.extend_from_slice(&0u32.to_le_bytes());
+            requests.push(Request {
+                req_type,
+                sector: 0,
+                data: segment.as_mut_ptr(),
+                len: segment.len(),
+                buffer: Some(segment),
+                slot: new_slot(),
+            });
+            done += sectors;
+        }
+        self.run(requests)
+    }
 
-            // Poll for completion (busy wait)
-            let start_used_idx = self.virtq.last_seen_used;
-            loop {
-                let used_idx = ptr::read_volatile(ptr::addr_of!((*self.virtq.used).idx));
-                if used_idx != start_used_idx {
-                    break;
-                }
-                // Small delay
-                for _ in 0..1000 {
-                    core::arch::asm!("nop");
+    /// Queue one request that owns its buffer and wait for it without blocking
+    async fn submit_owned(self, req_type: u32, sector: u64, mut buffer: Vec<u8>, offset: usize, len: usize) -> Result<Vec<u8>, &'static str> {
+        let slot = new_slot();
+        let data = unsafe { buffer.as_mut_ptr().add(offset) };
+        let mut pending = Some(Request { req_type, sector, data, len, buffer: Some(buffer), slot: slot.clone() });
+
+        poll_fn(|cx| {
+            if let Some(request) = pending.take() {
+                let queue = queue(self.queue);
+                if let Err(request) = unsafe { queue.submit(request) } {
+                    pending = Some(request);
+                    queue.space_waiters.push(cx.waker().clone());
+                    return Poll::Pending;
                 }
             }
 
-            // Check status
-            let final_status = ptr::read_volatile(status_ptr);
-            if final_status != VIRTIO_BLK_S_OK {
-                crate::kernel::uart_write_string(&alloc::format!(
-                    "ERROR: Write failed with status {}\r\n", final_status
-                ));
-                return Err("Write failed");
+            let mut completion = slot.borrow_mut();
+            match completion.result.take() {
+                Some(result) => Poll::Ready(result.map(|()| completion.buffer.take().unwrap_or_default())),
+                None => {
+                    completion.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
             }
+        })
+        .await
+    }
+
+    async fn transfer_owned(self, req_type: u32, start: u64, mut buffer: Vec<u8>) -> Result<Vec<u8>, &'static str> {
+        check_request(self.capacity, start, buffer.len())?;
+        let len = buffer.len();
+        for offset in (0..len).step_by(MAX_REQUEST_BYTES) {
+            let chunk = core::cmp::min(MAX_REQUEST_BYTES, len - offset);
+            let sector = start + (offset / SECTOR_SIZE) as u64;
+            buffer = self.submit_owned(req_type, sector, buffer, offset, chunk).await?;
+        }
+        Ok(buffer)
+    }
 
-            // Free descriptors
-            self.virtq.free_desc(d1);
-            self.virtq.free_desc(d2);
-            self.virtq.free_desc(d3);
+    /// Read `count` sectors starting at `start` (for executor tasks)
+    pub async fn read(self, start: u64, count: usize) -> Result<Vec<u8>, &'static str> {
+        self.transfer_owned(VIRTIO_BLK_T_IN, start, alloc::vec![0u8; count * SECTOR_SIZE]).await
+    }
+}
+
+impl BlockDevice for VirtioBlkDevice {
+    fn block_count(&self) -> u64 {
+        self.capacity
+    }
+
+    fn read_blocks(&mut self, start: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+        check_request(self.capacity, start, buffer.len())?;
+        self.transfer(VIRTIO_BLK_T_IN, start, buffer.as_mut_ptr(), buffer.len())
+    }
 
-            self.virtq.last_seen_used = ptr::read_volatile(ptr::addr_of!((*self.virtq.used).idx));
+    fn write_blocks(&mut self, start: u64, buffer: &[u8]) -> Result<(), &'static str> {
+        if self.is_read_only() {
+            return Err("Device is read-only");
+        }
+        check_request(self.capacity, start, buffer.len())?;
+        // The device only reads from the buffer on writes
+        self.transfer(VIRTIO_BLK_T_OUT, start, buffer.as_ptr() as *mut u8, buffer.len())
+    }
 
-            Ok(())
+    fn flush(&mut self) -> Result<(), &'static str> {
+        // Without VIRTIO_BLK_F_FLUSH the device has no volatile write cache
+        if !self.info().flush {
+            return Ok(());
+        }
+        let flush = Request {
+            req_type: VIRTIO_BLK_T_FLUSH,
+            sector: 0,
+            data: ptr::null_mut(),
+            len: 0,
+            buffer: None,
+            slot: new_slot(),
+        };
+        self.run(alloc::vec![flush])
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.info().read_only
+    }
+
+    fn discard(&mut self, start: u64, count: u64) -> Result<(), &'static str> {
+        if self.is_read_only() {
+            return Err("Device is read-only");
+        }
+        check_range(self.capacity, start, count)?;
+        match self.info().max_discard_sectors {
+            Some(max) => self.range_command(VIRTIO_BLK_T_DISCARD, start, count, max),
+            None => Ok(()),
+        }
+    }
+
+    fn write_zeroes(&mut self, start: u64, count: u64) -> Result<(), &'static str> {
+        if self.is_read_only() {
+            return Err("Device is read-only");
+        }
+        match self.info().max_write_zeroes_sectors {
+            Some(max) => {
+                check_range(self.capacity, start, count)?;
+                self.range_command(VIRTIO_BLK_T_WRITE_ZEROES, start, count, max)
+            }
+            None => zero_fill(self, start, count),
         }
     }
 }
@@ -0,0 +1,547 @@
+// VirtIO Console Driver (virtio-console) with multiport support
+// Based on VirtIO 1.3 specification, section 5.3
+//
+// With VIRTIO_CONSOLE_F_MULTIPORT the device carries several ports, each a
+// byte stream to something on the host (QEMU's virtserialport, usually a
+// socket). Port n has a receive and a transmit queue: queues 0/1 for port 0,
+// 4/5 for port 1, 6/7 for port 2 and so on. Queues 2/3 carry control messages
+// that add ports, name them and say whether the host end is connected.
+//
+// Everything is polled. Receive queues keep a buffer on every descriptor and
+// get them back as the host fills them; sends wait for the device to take the
+// data. Ports past MAX_PORTS are refused.
+
+use crate::kernel::drivers::pci::{PciConfig, PciDevice};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ptr;
+
+// VirtIO Device IDs
+const VIRTIO_VENDOR_ID: u16 = 0x1AF4;
+const VIRTIO_CONSOLE_DEVICE_ID_LEGACY: u16 = 0x1003;
+const VIRTIO_CONSOLE_DEVICE_ID_MODERN: u16 = 0x1043;
+
+// VirtIO Status Register Bits
+const VIRTIO_STATUS_ACKNOWLEDGE: u8 = 1;
+const VIRTIO_STATUS_DRIVER: u8 = 2;
+const VIRTIO_STATUS_FEATURES_OK: u8 = 8;
+const VIRTIO_STATUS_DRIVER_OK: u8 = 4;
+
+// VirtIO PCI Capability Types
+const VIRTIO_PCI_CAP_COMMON_CFG: u8 = 1;
+const VIRTIO_PCI_CAP_NOTIFY_CFG: u8 = 2;
+
+// Virtqueue descriptor flags
+const VIRTQ_DESC_F_WRITE: u16 = 2;
+
+// VirtIO Console Feature Bits
+const VIRTIO_CONSOLE_F_MULTIPORT: u32 = 1 << 1;
+
+// VirtIO Generic Feature Bits (bits 32+)
+const VIRTIO_F_VERSION_1: u32 = 1 << 0;  // Bit 32 in features[1]
+
+// Control message events
+const VIRTIO_CONSOLE_DEVICE_READY: u16 = 0;
+const VIRTIO_CONSOLE_DEVICE_ADD: u16 = 1;
+const VIRTIO_CONSOLE_DEVICE_REMOVE: u16 = 2;
+const VIRTIO_CONSOLE_PORT_READY: u16 = 3;
+const VIRTIO_CONSOLE_PORT_OPEN: u16 = 6;
+const VIRTIO_CONSOLE_PORT_NAME: u16 = 7;
+
+// Queues for the control channel
+const CONTROL_RX_QUEUE: u16 = 2;
+const CONTROL_TX_QUEUE: u16 = 3;
+
+/// Ports with queues set up; QEMU numbers virtserialports from 1
+const MAX_PORTS: u32 = 8;
+const QUEUE_SIZE: u16 = 8;
+/// Size of each receive buffer; a queue has QUEUE_SIZE of them
+const RX_BUFFER_SIZE: usize = 2048;
+/// Largest single send; bigger writes are split
+const TX_BUFFER_SIZE: usize = 4096;
+/// How long the host gets to take data before it is dropped
+const TX_TIMEOUT_MS: u64 = 1000;
+
+// Memory barrier
+#[inline(always)]
+fn mb() {
+    unsafe {
+        core::arch::asm!("dsb sy", options(nostack, preserves_flags));
+    }
+}
+
+/// VirtIO PCI Common Configuration (mapped via BAR)
+#[repr(C)]
+struct VirtioPciCommonCfg {
+    device_feature_select: u32,
+    device_feature: u32,
+    driver_feature_select: u32,
+    driver_feature: u32,
+    msix_config: u16,
+    num_queues: u16,
+    device_status: u8,
+    config_generation: u8,
+    queue_select: u16,
+    queue_size: u16,
+    queue_msix_vector: u16,
+    queue_enable: u16,
+    queue_notify_off: u16,
+    queue_desc_lo: u32,
+    queue_desc_hi: u32,
+    queue_avail_lo: u32,
+    queue_avail_hi: u32,
+    queue_used_lo: u32,
+    queue_used_hi: u32,
+}
+
+/// Virtqueue Descriptor
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct VirtqDesc {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+/// Virtqueue Used Element
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct VirtqUsedElem {
+    id: u32,
+    len: u32,
+}
+
+/// One virtqueue: its rings, in a page of their own, and its buffers
+struct Queue {
+    size: u16,
+    desc_phys: u64,
+    avail_phys: u64,
+    used_phys: u64,
+    buffers_phys: u64,
+    notify_addr: u64,
+    avail_idx: u16,
+    last_seen_used: u16,
+    /// A send timed out and the device still has the transmit buffer
+    busy: bool,
+}
+
+impl Queue {
+    /// Set up queue `index`; receive queues get a buffer per descriptor
+    unsafe fn new(common_cfg: *mut VirtioPciCommonCfg, notify_base: u64, notify_off_mult: u32, index: u16, receive: bool) -> Option<Self> {
+        ptr::write_volatile(&mut (*common_cfg).queue_select, index);
+        mb();
+
+        let size = ptr::read_volatile(&(*common_cfg).queue_size);
+        if size == 0 || size == 0xFFFF {
+            return None;
+        }
+        let size = size.min(QUEUE_SIZE);
+        ptr::write_volatile(&mut (*common_cfg).queue_size, size);
+
+        let ring_phys = crate::kernel::memory::allocate_pages(1)?;
+        ptr::write_bytes(ring_phys as *mut u8, 0, 4096);
+        let buffer_bytes = if receive { size as usize * RX_BUFFER_SIZE } else { TX_BUFFER_SIZE };
+        let buffers_phys = crate::kernel::memory::allocate_pages(buffer_bytes.div_ceil(4096))?;
+
+        let desc_phys = ring_phys;
+        let avail_phys = desc_phys + size as u64 * 16;
+        let used_phys = (avail_phys + 6 + 2 * size as u64 + 3) & !3;
+
+        ptr::write_volatile(&mut (*common_cfg).queue_desc_lo, desc_phys as u32);
+        ptr::write_volatile(&mut (*common_cfg).queue_desc_hi, (desc_phys >> 32) as u32);
+        ptr::write_volatile(&mut (*common_cfg).queue_avail_lo, avail_phys as u32);
+        ptr::write_volatile(&mut (*common_cfg).queue_avail_hi, (avail_phys >> 32) as u32);
+        ptr::write_volatile(&mut (*common_cfg).queue_used_lo, used_phys as u32);
+        ptr::write_volatile(&mut (*common_cfg).queue_used_hi, (used_phys >> 32) as u32);
+        mb();
+
+        let queue_notify_off = ptr::read_volatile(&(*common_cfg).queue_notify_off);
+        ptr::write_volatile(&mut (*common_cfg).queue_enable, 1);
+        mb();
+
+        Some(Queue {
+            size,
+            desc_phys,
+            avail_phys,
+            used_phys,
+            buffers_phys,
+            notify_addr: notify_base + queue_notify_off as u64 * notify_off_mult as u64,
+            avail_idx: 0,
+            last_seen_used: 0,
+            busy: false,
+        })
+    }
+
+    /// Make descriptor `id` available to the device
+    unsafe fn offer(&mut self, id: u16) {
+        let avail_ring = (self.avail_phys + 4) as *mut u16;
+        ptr::write_volatile(avail_ring.add((self.avail_idx % self.size) as usize), id);
+        mb();
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        ptr::write_volatile((self.avail_phys + 2) as *mut u16, self.avail_idx);
+        mb();
+    }
+
+    unsafe fn notify(&self, index: u16) {
+        ptr::write_volatile(self.notify_addr as *mut u16, index);
+        mb();
+    }
+
+    /// Next buffer the device is done with, as (descriptor id, length)
+    unsafe fn next_used(&mut self) -> Option<(u16, usize)> {
+        if ptr::read_volatile((self.used_phys + 2) as *const u16) == self.last_seen_used {
+            return None;
+        }
+        mb();
+        let used_ring = (self.used_phys + 4) as *const VirtqUsedElem;
+        let elem = ptr::read_volatile(used_ring.add((self.last_seen_used % self.size) as usize));
+        self.last_seen_used = self.last_seen_used.wrapping_add(1);
+        Some((elem.id as u16, elem.len as usize))
+    }
+
+    /// Give every receive buffer to the device
+    unsafe fn fill(&mut self, index: u16) {
+        for id in 0..self.size {
+            ptr::write_volatile((self.desc_phys as *mut VirtqDesc).add(id as usize), VirtqDesc {
+                addr: self.buffers_phys + id as u64 * RX_BUFFER_SIZE as u64,
+                len: RX_BUFFER_SIZE as u32,
+                flags: VIRTQ_DESC_F_WRITE,
+                next: 0,
+            });
+            self.offer(id);
+        }
+        self.notify(index);
+    }
+
+    /// Take the data the device has put in receive buffers, handing each
+    /// buffer straight back
+    unsafe fn receive(&mut self, index: u16) -> Vec<Vec<u8>> {
+        let mut received = Vec::new();
+        while let Some((id, len)) = self.next_used() {
+            let buffer = self.buffers_phys + (id as u64 % self.size as u64) * RX_BUFFER_SIZE as u64;
+            received.push(core::slice::from_raw_parts(buffer as *const u8, len.min(RX_BUFFER_SIZE)).to_vec());
+            self.offer(id % self.size);
+        }
+        if !received.is_empty() {
+            self.notify(index);
+        }
+        received
+    }
+
+    /// Send `data` on a transmit queue and wait for the device to take it
+    unsafe fn send(&mut self, index: u16, data: &[u8]) -> Result<(), &'static str> {
+        // The buffer can't be reused until the device gives it back
+        if self.busy {
+            if self.next_used().is_none() {
+                return Err("Console port busy");
+            }
+            self.busy = false;
+        }
+        for chunk in data.chunks(TX_BUFFER_SIZE) {
+            ptr::copy_nonoverlapping(chunk.as_ptr(), self.buffers_phys as *mut u8, chunk.len());
+            // Always slot 0, since only one send is in flight
+            ptr::write_volatile(self.desc_phys as *mut VirtqDesc, VirtqDesc {
+                addr: self.buffers_phys,
+                len: chunk.len() as u32,
+                flags: 0,
+                next: 0,
+            });
+            self.offer(0);
+            self.notify(index);
+
+            let deadline = crate::kernel::clock::monotonic_ms() + TX_TIMEOUT_MS;
+            while self.next_used().is_none() {
+                if crate::kernel::clock::monotonic_ms() > deadline {
+                    self.busy = true;
+                    return Err("Console port timeout");
+                }
+                core::hint::spin_loop();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Queue index of port `id`'s receive queue; transmit is the next one
+fn port_rx_queue(id: u32) -> u16 {
+    if id == 0 { 0 } else { 2 + 2 * id as u16 }
+}
+
+/// A port the device has added
+pub struct Port {
+    pub id: u32,
+    /// Name given on the host (virtserialport's name=), empty if none
+    pub name: String,
+    /// Whether something is connected at the host end
+    pub host_connected: bool,
+    rx: Queue,
+    tx: Queue,
+}
+
+/// Something that happened on a port
+pub enum PortEvent {
+    /// The host end connected or disconnected
+    Connected(u32, bool),
+    Data(u32, Vec<u8>),
+}
+
+/// VirtIO Console Device
+pub struct VirtioConsoleDevice {
+    control_rx: Queue,
+    control_tx: Queue,
+    /// Queues set up for ports the device hasn't added yet, by port id
+    spare: Vec<Option<(Queue, Queue)>>,
+    pub ports: Vec<Port>,
+}
+
+impl VirtioConsoleDevice {
+    /// Find and initialize the first VirtIO console device
+    pub fn find_and_init(ecam_base: u64, mmio_base: u64) -> Option<VirtioConsoleDevice> {
+        let config = PciConfig::with_base_addr(ecam_base);
+
+        crate::kernel::uart_write_string("Scanning for VirtIO console device...\r\n");
+
+        for device_num in 0..32 {
+            if let Some(pci_dev) = PciDevice::new(0, device_num, 0, &config) {
+                if pci_dev.vendor_id == VIRTIO_VENDOR_ID &&
+                   (pci_dev.device_id == VIRTIO_CONSOLE_DEVICE_ID_MODERN ||
+                    pci_dev.device_id == VIRTIO_CONSOLE_DEVICE_ID_LEGACY) {
+
+                    crate::kernel::uart_write_string(&alloc::format!(
+                        "Found VirtIO console device at 0:{}:0 (device_id=0x{:x})\r\n",
+                        device_num, pci_dev.device_id
+                    ));
+
+                    if let Some(console_dev) = unsafe { Self::init_device(pci_dev, mmio_base) } {
+                        return Some(console_dev);
+                    }
+                }
+            }
+        }
+
+        crate::kernel::uart_write_string("No VirtIO console device found\r\n");
+        None
+    }
+
+    /// Initialize a VirtIO console device
+    unsafe fn init_device(pci_dev: PciDevice, mmio_base: u64) -> Option<Self> {
+        pci_dev.enable_bus_mastering();
+
+        let (common_cfg_addr, notify_base, notify_off_mult) =
+            Self::parse_capabilities(&pci_dev, mmio_base)?;
+        let common_cfg = common_cfg_addr as *mut VirtioPciCommonCfg;
+
+        // Device initialization sequence (VirtIO spec 3.1)
+        ptr::write_volatile(&mut (*common_cfg).device_status, 0);
+        mb();
+        ptr::write_volatile(&mut (*common_cfg).device_status, VIRTIO_STATUS_ACKNOWLEDGE);
+        mb();
+        let status = ptr::read_volatile(&(*common_cfg).device_status);
+        ptr::write_volatile(&mut (*common_cfg).device_status, status | VIRTIO_STATUS_DRIVER);
+        mb();
+
+        // Only multiport, plus VERSION_1 for modern devices
+        ptr::write_volatile(&mut (*common_cfg).device_feature_select, 0);
+        mb();
+        let device_features_low = ptr::read_volatile(&(*common_cfg).device_feature);
+        ptr::write_volatile(&mut (*common_cfg).device_feature_select, 1);
+        mb();
+        let device_features_high = ptr::read_volatile(&(*common_cfg).device_feature);
+
+        // Ports are told apart by name, which needs multiport
+        if device_features_low & VIRTIO_CONSOLE_F_MULTIPORT == 0 {
+            crate::kernel::uart_write_string("ERROR: Console device has no multiport support\r\n");
+            return None;
+        }
+
+        ptr::write_volatile(&mut (*common_cfg).driver_feature_select, 0);
+        ptr::write_volatile(&mut (*common_cfg).driver_feature, VIRTIO_CONSOLE_F_MULTIPORT);
+        ptr::write_volatile(&mut (*common_cfg).driver_feature_select, 1);
+        ptr::write_volatile(&mut (*common_cfg).driver_feature, device_features_high & VIRTIO_F_VERSION_1);
+        mb();
+
+        let status = ptr::read_volatile(&(*common_cfg).device_status);
+        ptr::write_volatile(&mut (*common_cfg).device_status, status | VIRTIO_STATUS_FEATURES_OK);
+        mb();
+
+        let status = ptr::read_volatile(&(*common_cfg).device_status);
+        if (status & VIRTIO_STATUS_FEATURES_OK) == 0 {
+            crate::kernel::uart_write_string("ERROR: Console device rejected our features\r\n");
+            return None;
+        }
+
+        // Control queues, then a queue pair for each port the device can have
+        let control_rx = Queue::new(common_cfg, notify_base, notify_off_mult, CONTROL_RX_QUEUE, true)?;
+        let control_tx = Queue::new(common_cfg, notify_base, notify_off_mult, CONTROL_TX_QUEUE, false)?;
+        let num_queues = ptr::read_volatile(&(*common_cfg).num_queues);
+        let mut spare = Vec::new();
+        for id in 0..MAX_PORTS {
+            let rx_index = port_rx_queue(id);
+            if rx_index + 1 >= num_queues {
+                break;
+            }
+            let rx = Queue::new(common_cfg, notify_base, notify_off_mult, rx_index, true);
+            let tx = Queue::new(common_cfg, notify_base, notify_off_mult, rx_index + 1, false);
+            spare.push(rx.zip(tx));
+        }
+
+        let status = ptr::read_volatile(&(*common_cfg).device_status);
+        ptr::write_volatile(&mut (*common_cfg).device_status, status | VIRTIO_STATUS_DRIVER_OK);
+        mb();
+
+        let mut device = VirtioConsoleDevice { control_rx, control_tx, spare, ports: Vec::new() };
+        device.control_rx.fill(CONTROL_RX_QUEUE);
+        // The device answers with DEVICE_ADD for each port, handled in poll()
+        device.send_control(0, VIRTIO_CONSOLE_DEVICE_READY, 1);
+
+        crate::kernel::uart_write_string("VirtIO console device ready!\r\n");
+        Some(device)
+    }
+
+    /// Parse PCI capabilities to find the common config and notify structures
+    unsafe fn parse_capabilities(pci_dev: &PciDevice, mmio_base: u64) -> Option<(u64, u64, u32)> {
+        let mut cap_ptr = pci_dev.get_capabilities_ptr()? as u16;
+        let mut common_cfg_addr = None;
+        let mut notify_addr = None;
+        let mut notify_off_mult = 0u32;
+
+        // Program BAR4 above the 9P devices (0x800000+)
+        let bar4_size = pci_dev.get_bar_size(4)?;
+        let bar4_addr = mmio_base + 0xC00000;
+
+        pci_dev.write_config_u32(0x20, bar4_addr as u32);
+        pci_dev.write_config_u32(0x24, (bar4_addr >> 32) as u32);
+
+        crate::kernel::uart_write_string(&alloc::format!(
+            "BAR4: size=0x{:x}, allocated at 0x{:x}\r\n", bar4_size, bar4_addr
+        ));
+
+        while cap_ptr != 0 && cap_ptr < 0xFF {
+            let cap_id = pci_dev.read_config_u8(cap_ptr as u8);
+
+            if cap_id == 0x09 { // Vendor-specific capability
+                let cfg_type = pci_dev.read_config_u8((cap_ptr + 3) as u8);
+                let bar = pci_dev.read_config_u8((cap_ptr + 4) as u8);
+                let offset = pci_dev.read_config_u32((cap_ptr + 8) as u8);
+
+                if bar == 4 {
+                    let addr = bar4_addr + offset as u64;
+                    match cfg_type {
+                        VIRTIO_PCI_CAP_COMMON_CFG => common_cfg_addr = Some(addr),
+                        VIRTIO_PCI_CAP_NOTIFY_CFG => {
+                            notify_addr = Some(addr);
+                            notify_off_mult = pci_dev.read_config_u32((cap_ptr + 16) as u8);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            cap_ptr = pci_dev.read_config_u8((cap_ptr + 1) as u8) as u16;
+        }
+
+        Some((common_cfg_addr?, notify_addr?, notify_off_mult))
+    }
+
+    /// Send a control message (struct virtio_console_control)
+    fn send_control(&mut self, id: u32, event: u16, value: u16) {
+        let mut message = [0u8; 8];
+        message[0..4].copy_from_slice(&id.to_le_bytes());
+        message[4..6].copy_from_slice(&event.to_le_bytes());
+        message[6..8].copy_from_slice(&value.to_le_bytes());
+        if unsafe { self.control_tx.send(CONTROL_TX_QUEUE, &message) }.is_err() {
+            crate::kernel::uart_write_string("VirtIO console: control message not taken\r\n");
+        }
+    }
+
+    fn handle_control(&mut self, message: &[u8], events: &mut Vec<PortEvent>) {
+        if message.len() < 8 {
+            return;
+        }
+        let id = u32::from_le_bytes(message[0..4].try_into().unwrap());
+        let event = u16::from_le_bytes(message[4..6].try_into().unwrap());
+        let value = u16::from_le_bytes(message[6..8].try_into().unwrap());
+
+        match event {
+            VIRTIO_CONSOLE_DEVICE_ADD => {
+                let queues = self.spare.get_mut(id as usize).and_then(|queues| queues.take());
+                match queues {
+                    Some((mut rx, tx)) => {
+                        unsafe { rx.fill(port_rx_queue(id)) };
+                        self.ports.push(Port { id, name: String::new(), host_connected: false, rx, tx });
+                        self.send_control(id, VIRTIO_CONSOLE_PORT_READY, 1);
+                        // We always listen, so open it straight away
+                        self.send_control(id, VIRTIO_CONSOLE_PORT_OPEN, 1);
+                    }
+                    None => {
+                        crate::kernel::uart_write_string(&alloc::format!(
+                            "VirtIO console: no queues for port {}\r\n", id
+                        ));
+                        self.send_control(id, VIRTIO_CONSOLE_PORT_READY, 0);
+                    }
+                }
+            }
+            VIRTIO_CONSOLE_DEVICE_REMOVE => {
+                if let Some(index) = self.ports.iter().position(|port| port.id == id) {
+                    let port = self.ports.remove(index);
+                    if port.host_connected {
+                        events.push(PortEvent::Connected(id, false));
+                    }
+                    if let Some(slot) = self.spare.get_mut(id as usize) {
+                        *slot = Some((port.rx, port.tx));
+                    }
+                }
+            }
+            VIRTIO_CONSOLE_PORT_NAME => {
+                if let Some(port) = self.port_mut(id) {
+                    port.name = String::from_utf8_lossy(&message[8..]).trim_end_matches('\0').into();
+                    crate::kernel::uart_write_string(&alloc::format!(
+                        "VirtIO console: port {} is '{}'\r\n", id, port.name
+                    ));
+                }
+            }
+            VIRTIO_CONSOLE_PORT_OPEN => {
+                if let Some(port) = self.port_mut(id) {
+                    port.host_connected = value != 0;
+                    events.push(PortEvent::Connected(id, value != 0));
+                }
+            }
+            // Console port and resize events don't matter without a tty
+            _ => {}
+        }
+    }
+
+    fn port_mut(&mut self, id: u32) -> Option<&mut Port> {
+        self.ports.iter_mut().find(|port| port.id == id)
+    }
+
+    /// The port the host named `name`
+    pub fn port_named(&self, name: &str) -> Option<&Port> {
+        self.ports.iter().find(|port| port.name == name)
+    }
+
+    /// Handle control messages and collect data from every port
+    pub fn poll(&mut self) -> Vec<PortEvent> {
+        let mut events = Vec::new();
+        for message in unsafe { self.control_rx.receive(CONTROL_RX_QUEUE) } {
+            self.handle_control(&message, &mut events);
+        }
+        for port in self.ports.iter_mut() {
+            for data in unsafe { port.rx.receive(port_rx_queue(port.id)) } {
+                events.push(PortEvent::Data(port.id, data));
+            }
+        }
+        events
+    }
+
+    /// Send `data` to the host end of port `id`. Nothing is sent while the
+    /// host end isn't connected, as the device would only drop it.
+    pub fn write(&mut self, id: u32, data: &[u8]) -> Result<(), &'static str> {
+        let port = self.port_mut(id).ok_or("No such console port")?;
+        if !port.host_connected {
+            return Ok(());
+        }
+        unsafe { port.tx.send(port_rx_queue(id) + 1, data) }
+    }
+}
@@ -3,4 +3,7 @@
 pub mod gpu;
 pub mod input;
 pub mod blk;
+pub mod console;
 pub mod net;
+pub mod p9;
+pub mod rng;
@@ -0,0 +1,366 @@
+// VirtIO 9P Transport Driver (virtio-9p)
+// Based on VirtIO 1.3 specification, section 5.6
+//
+// The device carries 9P messages to a server on the host, which exports a
+// directory (QEMU's -virtfs). It has a single virtqueue (requestq): each
+// request is a device-readable buffer holding the T-message chained to a
+// device-writable buffer for the R-message. The device config holds the mount
+// tag that names the share. The 9P protocol itself is in system::fs::ninep.
+
+use crate::kernel::drivers::pci::{PciConfig, PciDevice};
+use crate::system::fs::ninep::Transport;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ptr;
+
+// VirtIO Device IDs
+const VIRTIO_VENDOR_ID: u16 = 0x1AF4;
+const VIRTIO_9P_DEVICE_ID_LEGACY: u16 = 0x1009;
+const VIRTIO_9P_DEVICE_ID_MODERN: u16 = 0x1049;
+
+// VirtIO Status Register Bits
+const VIRTIO_STATUS_ACKNOWLEDGE: u8 = 1;
+const VIRTIO_STATUS_DRIVER: u8 = 2;
+const VIRTIO_STATUS_FEATURES_OK: u8 = 8;
+const VIRTIO_STATUS_DRIVER_OK: u8 = 4;
+
+// VirtIO PCI Capability Types
+const VIRTIO_PCI_CAP_COMMON_CFG: u8 = 1;
+const VIRTIO_PCI_CAP_NOTIFY_CFG: u8 = 2;
+const VIRTIO_PCI_CAP_DEVICE_CFG: u8 = 4;
+
+// Virtqueue descriptor flags
+const VIRTQ_DESC_F_NEXT: u16 = 1;
+const VIRTQ_DESC_F_WRITE: u16 = 2;
+
+// VirtIO 9P Feature Bits
+const VIRTIO_9P_F_MOUNT_TAG: u32 = 1 << 0;
+
+// VirtIO Generic Feature Bits (bits 32+)
+const VIRTIO_F_VERSION_1: u32 = 1 << 0;  // Bit 32 in features[1]
+
+// One request in flight at a time, so a tiny queue is plenty
+const QUEUE_SIZE: u16 = 8;
+// Largest message either way; a buffer of this size is kept for each direction
+const MESSAGE_PAGES: usize = 16;
+const MAX_MESSAGE: usize = MESSAGE_PAGES * 4096;
+// How long the host gets to answer one request
+const REQUEST_TIMEOUT_MS: u64 = 5000;
+
+// Memory barrier
+#[inline(always)]
+fn mb() {
+    unsafe {
+        core::arch::asm!("dsb sy", options(nostack, preserves_flags));
+    }
+}
+
+/// VirtIO PCI Common Configuration (mapped via BAR)
+#[repr(C)]
+struct VirtioPciCommonCfg {
+    device_feature_select: u32,
+    device_feature: u32,
+    driver_feature_select: u32,
+    driver_feature: u32,
+    msix_config: u16,
+    num_queues: u16,
+    device_status: u8,
+    config_generation: u8,
+    queue_select: u16,
+    queue_size: u16,
+    queue_msix_vector: u16,
+    queue_enable: u16,
+    queue_notify_off: u16,
+    queue_desc_lo: u32,
+    queue_desc_hi: u32,
+    queue_avail_lo: u32,
+    queue_avail_hi: u32,
+    queue_used_lo: u32,
+    queue_used_hi: u32,
+}
+
+/// Virtqueue Descriptor
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct VirtqDesc {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+/// Virtqueue Used Element
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct VirtqUsedElem {
+    id: u32,
+    len: u32,
+}
+
+/// VirtIO 9P Transport Device
+pub struct Virtio9pDevice {
+    common_cfg: *mut VirtioPciCommonCfg,
+    notify_addr: u64,
+    queue_phys: u64,
+    request_phys: u64,
+    reply_phys: u64,
+    avail_idx: u16,
+    last_seen_used: u16,
+    /// Name the host gave the share
+    pub tag: String,
+}
+
+impl Virtio9pDevice {
+    /// Find and initialize all VirtIO 9P devices, one per shared folder
+    pub fn find_and_init(ecam_base: u64, mmio_base: u64) -> Vec<Virtio9pDevice> {
+        let mut devices = Vec::new();
+        let config = PciConfig::with_base_addr(ecam_base);
+
+        crate::kernel::uart_write_string("Scanning for VirtIO 9P devices...\r\n");
+
+        for device_num in 0..32 {
+            if let Some(pci_dev) = PciDevice::new(0, device_num, 0, &config) {
+                if pci_dev.vendor_id == VIRTIO_VENDOR_ID &&
+                   (pci_dev.device_id == VIRTIO_9P_DEVICE_ID_MODERN ||
+                    pci_dev.device_id == VIRTIO_9P_DEVICE_ID_LEGACY) {
+
+                    crate::kernel::uart_write_string(&alloc::format!(
+                        "Found VirtIO 9P device at 0:{}:0 (device_id=0x{:x})\r\n",
+                        device_num, pci_dev.device_id
+                    ));
+
+                    if let Some(p9_dev) = unsafe { Self::init_device(pci_dev, mmio_base) } {
+                        devices.push(p9_dev);
+                    }
+                }
+            }
+        }
+
+        crate::kernel::uart_write_string(&alloc::format!(
+            "Found {} VirtIO 9P device(s)\r\n", devices.len()
+        ));
+
+        devices
+    }
+
+    /// Initialize a VirtIO 9P device
+    unsafe fn init_device(pci_dev: PciDevice, mmio_base: u64) -> Option<Self> {
+        pci_dev.enable_bus_mastering();
+
+        let (common_cfg_addr, notify_base, notify_off_mult, device_cfg_addr) =
+            Self::parse_capabilities(&pci_dev, mmio_base)?;
+        let common_cfg = common_cfg_addr as *mut VirtioPciCommonCfg;
+
+        // Device initialization sequence (VirtIO spec 3.1)
+        ptr::write_volatile(&mut (*common_cfg).device_status, 0);
+        mb();
+        ptr::write_volatile(&mut (*common_cfg).device_status, VIRTIO_STATUS_ACKNOWLEDGE);
+        mb();
+        let status = ptr::read_volatile(&(*common_cfg).device_status);
+        ptr::write_volatile(&mut (*common_cfg).device_status, status | VIRTIO_STATUS_DRIVER);
+        mb();
+
+        // The mount tag is all we need, plus VERSION_1 for modern devices
+        ptr::write_volatile(&mut (*common_cfg).device_feature_select, 0);
+        mb();
+        let device_features_low = ptr::read_volatile(&(*common_cfg).device_feature);
+        ptr::write_volatile(&mut (*common_cfg).device_feature_select, 1);
+        mb();
+        let device_features_high = ptr::read_volatile(&(*common_cfg).device_feature);
+
+        ptr::write_volatile(&mut (*common_cfg).driver_feature_select, 0);
+        ptr::write_volatile(&mut (*common_cfg).driver_feature, device_features_low & VIRTIO_9P_F_MOUNT_TAG);
+        ptr::write_volatile(&mut (*common_cfg).driver_feature_select, 1);
+        ptr::write_volatile(&mut (*common_cfg).driver_feature, device_features_high & VIRTIO_F_VERSION_1);
+        mb();
+
+        let status = ptr::read_volatile(&(*common_cfg).device_status);
+        ptr::write_volatile(&mut (*common_cfg).device_status, status | VIRTIO_STATUS_FEATURES_OK);
+        mb();
+
+        let status = ptr::read_volatile(&(*common_cfg).device_status);
+        if (status & VIRTIO_STATUS_FEATURES_OK) == 0 {
+            crate::kernel::uart_write_string("ERROR: 9P device rejected our features\r\n");
+            return None;
+        }
+
+        // Without a tag there is nothing to call the share; QEMU always sets one
+        if device_features_low & VIRTIO_9P_F_MOUNT_TAG == 0 {
+            crate::kernel::uart_write_string("ERROR: 9P device has no mount tag\r\n");
+            return None;
+        }
+        let tag_len = ptr::read_volatile(device_cfg_addr as *const u16);
+        let tag: Vec<u8> = (0..tag_len as u64)
+            .map(|i| ptr::read_volatile((device_cfg_addr + 2 + i) as *const u8))
+            .collect();
+        let tag = String::from_utf8_lossy(&tag).into_owned();
+
+        // Queue gets a page; the request and reply buffers MAX_MESSAGE each
+        let queue_phys = crate::kernel::memory::allocate_pages(1)?;
+        let request_phys = crate::kernel::memory::allocate_pages(MESSAGE_PAGES)?;
+        let reply_phys = crate::kernel::memory::allocate_pages(MESSAGE_PAGES)?;
+        ptr::write_bytes(queue_phys as *mut u8, 0, 4096);
+
+        // Set up requestq (queue 0)
+        ptr::write_volatile(&mut (*common_cfg).queue_select, 0);
+        mb();
+
+        let queue_size = ptr::read_volatile(&(*common_cfg).queue_size);
+        if queue_size < 2 || queue_size == 0xFFFF {
+            crate::kernel::uart_write_string(&alloc::format!(
+                "Invalid/broken queue size: {} - REJECTING DEVICE\r\n", queue_size
+            ));
+            return None;
+        }
+        let queue_size = queue_size.min(QUEUE_SIZE);
+        ptr::write_volatile(&mut (*common_cfg).queue_size, queue_size);
+
+        let desc_phys = queue_phys;
+        let avail_phys = desc_phys + queue_size as u64 * 16;
+        let used_phys = (avail_phys + 6 + 2 * queue_size as u64 + 3) & !3;
+
+        ptr::write_volatile(&mut (*common_cfg).queue_desc_lo, desc_phys as u32);
+        ptr::write_volatile(&mut (*common_cfg).queue_desc_hi, (desc_phys >> 32) as u32);
+        ptr::write_volatile(&mut (*common_cfg).queue_avail_lo, avail_phys as u32);
+        ptr::write_volatile(&mut (*common_cfg).queue_avail_hi, (avail_phys >> 32) as u32);
+        ptr::write_volatile(&mut (*common_cfg).queue_used_lo, used_phys as u32);
+        ptr::write_volatile(&mut (*common_cfg).queue_used_hi, (used_phys >> 32) as u32);
+        mb();
+
+        let queue_notify_off = ptr::read_volatile(&(*common_cfg).queue_notify_off);
+        ptr::write_volatile(&mut (*common_cfg).queue_enable, 1);
+        mb();
+
+        let status = ptr::read_volatile(&(*common_cfg).device_status);
+        ptr::write_volatile(&mut (*common_cfg).device_status, status | VIRTIO_STATUS_DRIVER_OK);
+        mb();
+
+        crate::kernel::uart_write_string(&alloc::format!("VirtIO 9P device '{}' ready!\r\n", tag));
+
+        Some(Virtio9pDevice {
+            common_cfg,
+            notify_addr: notify_base + queue_notify_off as u64 * notify_off_mult as u64,
+            queue_phys: desc_phys,
+            request_phys,
+            reply_phys,
+            avail_idx: 0,
+            last_seen_used: 0,
+            tag,
+        })
+    }
+
+    /// Parse PCI capabilities to find the common config, notify and device
+    /// config structures
+    unsafe fn parse_capabilities(pci_dev: &PciDevice, mmio_base: u64) -> Option<(u64, u64, u32, u64)> {
+        let mut cap_ptr = pci_dev.get_capabilities_ptr()? as u16;
+        let mut common_cfg_addr = None;
+        let mut notify_addr = None;
+        let mut device_cfg_addr = None;
+        let mut notify_off_mult = 0u32;
+
+        // Program BAR4 above the entropy device (0x700000), 1MB per share
+        let bar4_size = pci_dev.get_bar_size(4)?;
+        static mut NEXT_9P_BAR_OFFSET: u64 = 0x800000;
+        let bar4_addr = mmio_base + NEXT_9P_BAR_OFFSET;
+        NEXT_9P_BAR_OFFSET += 0x100000;
+
+        pci_dev.write_config_u32(0x20, bar4_addr as u32);
+        pci_dev.write_config_u32(0x24, (bar4_addr >> 32) as u32);
+
+        crate::kernel::uart_write_string(&alloc::format!(
+            "BAR4: size=0x{:x}, allocated at 0x{:x}\r\n", bar4_size, bar4_addr
+        ));
+
+        while cap_ptr != 0 && cap_ptr < 0xFF {
+            let cap_id = pci_dev.read_config_u8(cap_ptr as u8);
+
+            if cap_id == 0x09 { // Vendor-specific capability
+                let cfg_type = pci_dev.read_config_u8((cap_ptr + 3) as u8);
+                let bar = pci_dev.read_config_u8((cap_ptr + 4) as u8);
+                let offset = pci_dev.read_config_u32((cap_ptr + 8) as u8);
+
+                if bar == 4 {
+                    let addr = bar4_addr + offset as u64;
+                    match cfg_type {
+                        VIRTIO_PCI_CAP_COMMON_CFG => common_cfg_addr = Some(addr),
+                        VIRTIO_PCI_CAP_NOTIFY_CFG => {
+                            notify_addr = Some(addr);
+                            notify_off_mult = pci_dev.read_config_u32((cap_ptr + 16) as u8);
+                        }
+                        VIRTIO_PCI_CAP_DEVICE_CFG => device_cfg_addr = Some(addr),
+                        _ => {}
+                    }
+                }
+            }
+
+            cap_ptr = pci_dev.read_config_u8((cap_ptr + 1) as u8) as u16;
+        }
+
+        Some((common_cfg_addr?, notify_addr?, notify_off_mult, device_cfg_addr?))
+    }
+}
+
+impl Transport for Virtio9pDevice {
+    /// Post the request chained to the reply buffer and wait for the host
+    fn transact(&mut self, request: &[u8], reply: &mut [u8]) -> Result<usize, &'static str> {
+        if request.len() > MAX_MESSAGE {
+            return Err("9P request too large");
+        }
+        let reply_len = reply.len().min(MAX_MESSAGE);
+
+        unsafe {
+            let queue_size = ptr::read_volatile(&(*self.common_cfg).queue_size);
+            let desc = self.queue_phys as *mut VirtqDesc;
+            let avail = self.queue_phys + queue_size as u64 * 16;
+            let avail_ring = (avail + 4) as *mut u16;
+            let used = (avail + 6 + 2 * queue_size as u64 + 3) & !3;
+            let used_ring = (used + 4) as *const VirtqUsedElem;
+
+            ptr::copy_nonoverlapping(request.as_ptr(), self.request_phys as *mut u8, request.len());
+
+            // Always descriptors 0 and 1, since only one request is in flight
+            ptr::write_volatile(desc, VirtqDesc {
+                addr: self.request_phys,
+                len: request.len() as u32,
+                flags: VIRTQ_DESC_F_NEXT,
+                next: 1,
+            });
+            ptr::write_volatile(desc.add(1), VirtqDesc {
+                addr: self.reply_phys,
+                len: reply_len as u32,
+                flags: VIRTQ_DESC_F_WRITE,
+                next: 0,
+            });
+
+            ptr::write_volatile(avail_ring.add((self.avail_idx % queue_size) as usize), 0);
+            mb();
+            self.avail_idx = self.avail_idx.wrapping_add(1);
+            ptr::write_volatile((avail + 2) as *mut u16, self.avail_idx);
+            mb();
+
+            ptr::write_volatile(self.notify_addr as *mut u16, 0);
+            mb();
+
+            // Poll for completion; the host answers once its own file I/O is done
+            let deadline = crate::kernel::clock::monotonic_ms() + REQUEST_TIMEOUT_MS;
+            while ptr::read_volatile((used + 2) as *const u16) == self.last_seen_used {
+                if crate::kernel::clock::monotonic_ms() > deadline {
+                    return Err("9P device timeout");
+                }
+                core::hint::spin_loop();
+            }
+            mb();
+
+            let elem = ptr::read_volatile(used_ring.add((self.last_seen_used % queue_size) as usize));
+            self.last_seen_used = self.last_seen_used.wrapping_add(1);
+
+            let len = (elem.len as usize).min(reply_len);
+            ptr::copy_nonoverlapping(self.reply_phys as *const u8, reply.as_mut_ptr(), len);
+            Ok(len)
+        }
+    }
+
+    fn max_message(&self) -> usize {
+        MAX_MESSAGE
+    }
+}
@@ -0,0 +1,323 @@
+// VirtIO Entropy Device Driver (virtio-rng)
+// Based on VirtIO 1.3 specification, section 5.4
+//
+// The device has a single virtqueue (requestq). The driver posts device-writable
+// buffers and the device fills them with random bytes from the host.
+
+use crate::kernel::drivers::pci::{PciConfig, PciDevice};
+use core::ptr;
+
+// VirtIO Device IDs
+const VIRTIO_VENDOR_ID: u16 = 0x1AF4;
+const VIRTIO_RNG_DEVICE_ID_LEGACY: u16 = 0x1005;
+const VIRTIO_RNG_DEVICE_ID_MODERN: u16 = 0x1044;
+
+// VirtIO Status Register Bits
+const VIRTIO_STATUS_ACKNOWLEDGE: u8 = 1;
+const VIRTIO_STATUS_DRIVER: u8 = 2;
+const VIRTIO_STATUS_FEATURES_OK: u8 = 8;
+const VIRTIO_STATUS_DRIVER_OK: u8 = 4;
+
+// VirtIO PCI Capability Types
+const VIRTIO_PCI_CAP_COMMON_CFG: u8 = 1;
+const VIRTIO_PCI_CAP_NOTIFY_CFG: u8 = 2;
+
+// Virtqueue descriptor flags
+const VIRTQ_DESC_F_WRITE: u16 = 2;
+
+// VirtIO Generic Feature Bits (bits 32+)
+const VIRTIO_F_VERSION_1: u32 = 1 << 0;  // Bit 32 in features[1]
+
+// One request in flight at a time, so a tiny queue is plenty
+const QUEUE_SIZE: u16 = 8;
+// Largest single request; bigger reads are split
+const MAX_REQUEST: usize = 256;
+
+// Memory barrier
+#[inline(always)]
+fn mb() {
+    unsafe {
+        core::arch::asm!("dsb sy", options(nostack, preserves_flags));
+    }
+}
+
+/// VirtIO PCI Common Configuration (mapped via BAR)
+#[repr(C)]
+struct VirtioPciCommonCfg {
+    device_feature_select: u32,
+    device_feature: u32,
+    driver_feature_select: u32,
+    driver_feature: u32,
+    msix_config: u16,
+    num_queues: u16,
+    device_status: u8,
+    config_generation: u8,
+    queue_select: u16,
+    queue_size: u16,
+    queue_msix_vector: u16,
+    queue_enable: u16,
+    queue_notify_off: u16,
+    queue_desc_lo: u32,
+    queue_desc_hi: u32,
+    queue_avail_lo: u32,
+    queue_avail_hi: u32,
+    queue_used_lo: u32,
+    queue_used_hi: u32,
+}
+
+/// Virtqueue Descriptor
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct VirtqDesc {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+/// Virtqueue Used Element
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct VirtqUsedElem {
+    id: u32,
+    len: u32,
+}
+
+/// VirtIO Entropy Device
+pub struct VirtioRngDevice {
+    common_cfg: *mut VirtioPciCommonCfg,
+    notify_addr: u64,
+    queue_phys: u64,
+    buffer_phys: u64,
+    avail_idx: u16,
+    last_seen_used: u16,
+}
+
+impl VirtioRngDevice {
+    /// Find and initialize the first VirtIO entropy device
+    pub fn find_and_init(ecam_base: u64, mmio_base: u64) -> Option<VirtioRngDevice> {
+        let config = PciConfig::with_base_addr(ecam_base);
+
+        crate::kernel::uart_write_string("Scanning for VirtIO entropy device...\r\n");
+
+        for device_num in 0..32 {
+            if let Some(pci_dev) = PciDevice::new(0, device_num, 0, &config) {
+                if pci_dev.vendor_id == VIRTIO_VENDOR_ID &&
+                   (pci_dev.device_id == VIRTIO_RNG_DEVICE_ID_MODERN ||
+                    pci_dev.device_id == VIRTIO_RNG_DEVICE_ID_LEGACY) {
+
+                    crate::kernel::uart_write_string(&alloc::format!(
+                        "Found VirtIO entropy device at 0:{}:0 (device_id=0x{:x})\r\n",
+                        device_num, pci_dev.device_id
+                    ));
+
+                    if let Some(rng_dev) = unsafe { Self::init_device(pci_dev, mmio_base) } {
+                        return Some(rng_dev);
+                    }
+                }
+            }
+        }
+
+        crate::kernel::uart_write_string("No VirtIO entropy device found\r\n");
+        None
+    }
+
+    /// Initialize a VirtIO entropy device
+    unsafe fn init_device(pci_dev: PciDevice, mmio_base: u64) -> Option<Self> {
+        pci_dev.enable_bus_mastering();
+
+        let (common_cfg_addr, notify_base, notify_off_mult) =
+            Self::parse_capabilities(&pci_dev, mmio_base)?;
+        let common_cfg = common_cfg_addr as *mut VirtioPciCommonCfg;
+
+        // Device initialization sequence (VirtIO spec 3.1)
+        ptr::write_volatile(&mut (*common_cfg).device_status, 0);
+        mb();
+        ptr::write_volatile(&mut (*common_cfg).device_status, VIRTIO_STATUS_ACKNOWLEDGE);
+        mb();
+        let status = ptr::read_volatile(&(*common_cfg).device_status);
+        ptr::write_volatile(&mut (*common_cfg).device_status, status | VIRTIO_STATUS_DRIVER);
+        mb();
+
+        // No device-specific features; only VERSION_1 for modern devices
+        ptr::write_volatile(&mut (*common_cfg).device_feature_select, 1);
+        mb();
+        let device_features_high = ptr::read_volatile(&(*common_cfg).device_feature);
+
+        ptr::write_volatile(&mut (*common_cfg).driver_feature_select, 0);
+        ptr::write_volatile(&mut (*common_cfg).driver_feature, 0);
+        ptr::write_volatile(&mut (*common_cfg).driver_feature_select, 1);
+        ptr::write_volatile(&mut (*common_cfg).driver_feature, device_features_high & VIRTIO_F_VERSION_1);
+        mb();
+
+        let status = ptr::read_volatile(&(*common_cfg).device_status);
+        ptr::write_volatile(&mut (*common_cfg).device_status, status | VIRTIO_STATUS_FEATURES_OK);
+        mb();
+
+        let status = ptr::read_volatile(&(*common_cfg).device_status);
+        if (status & VIRTIO_STATUS_FEATURES_OK) == 0 {
+            crate::kernel::uart_write_string("ERROR: Entropy device rejected our features\r\n");
+            return None;
+        }
+
+        // Queue and request buffer each get their own page
+        let queue_phys = crate::kernel::memory::allocate_pages(1)?;
+        let buffer_phys = crate::kernel::memory::allocate_pages(1)?;
+        ptr::write_bytes(queue_phys as *mut u8, 0, 4096);
+
+        // Set up requestq (queue 0)
+        ptr::write_volatile(&mut (*common_cfg).queue_select, 0);
+        mb();
+
+        let queue_size = ptr::read_volatile(&(*common_cfg).queue_size);
+        if queue_size == 0 || queue_size == 0xFFFF {
+            crate::kernel::uart_write_string(&alloc::format!(
+                "Invalid/broken queue size: {} - REJECTING DEVICE\r\n", queue_size
+            ));
+            return None;
+        }
+        let queue_size = queue_size.min(QUEUE_SIZE);
+        ptr::write_volatile(&mut (*common_cfg).queue_size, queue_size);
+
+        let desc_phys = queue_phys;
+        let avail_phys = desc_phys + queue_size as u64 * 16;
+        let used_phys = (avail_phys + 6 + 2 * queue_size as u64 + 3) & !3;
+
+        ptr::write_volatile(&mut (*common_cfg).queue_desc_lo, desc_phys as u32);
+        ptr::write_volatile(&mut (*common_cfg).queue_desc_hi, (desc_phys >> 32) as u32);
+        ptr::write_volatile(&mut (*common_cfg).queue_avail_lo, avail_phys as u32);
+        ptr::write_volatile(&mut (*common_cfg).queue_avail_hi, (avail_phys >> 32) as u32);
+        ptr::write_volatile(&mut (*common_cfg).queue_used_lo, used_phys as u32);
+        ptr::write_volatile(&mut (*common_cfg).queue_used_hi, (used_phys >> 32) as u32);
+        mb();
+
+        let queue_notify_off = ptr::read_volatile(&(*common_cfg).queue_notify_off);
+        ptr::write_volatile(&mut (*common_cfg).queue_enable, 1);
+        mb();
+
+        let status = ptr::read_volatile(&(*common_cfg).device_status);
+        ptr::write_volatile(&mut (*common_cfg).device_status, status | VIRTIO_STATUS_DRIVER_OK);
+        mb();
+
+        crate::kernel::uart_write_string("VirtIO entropy device ready!\r\n");
+
+        Some(VirtioRngDevice {
+            common_cfg,
+            notify_addr: notify_base + queue_notify_off as u64 * notify_off_mult as u64,
+            queue_phys: desc_phys,
+            buffer_phys,
+            avail_idx: 0,
+            last_seen_used: 0,
+        })
+    }
+
+    /// Parse PCI capabilities to find the common config and notify structures
+    unsafe fn parse_capabilities(pci_dev: &PciDevice, mmio_base: u64) -> Option<(u64, u64, u32)> {
+        let mut cap_ptr = pci_dev.get_capabilities_ptr()? as u16;
+        let mut common_cfg_addr = None;
+        let mut notify_addr = None;
+        let mut notify_off_mult = 0u32;
+
+        // Program BAR4 above the block (0x300000+) and network BARs
+        let bar4_size = pci_dev.get_bar_size(4)?;
+        let bar4_addr = mmio_base + 0x700000;
+
+        pci_dev.write_config_u32(0x20, bar4_addr as u32);
+        pci_dev.write_config_u32(0x24, (bar4_addr >> 32) as u32);
+
+        crate::kernel::uart_write_string(&alloc::format!(
+            "BAR4: size=0x{:x}, allocated at 0x{:x}\r\n", bar4_size, bar4_addr
+        ));
+
+        while cap_ptr != 0 && cap_ptr < 0xFF {
+            let cap_id = pci_dev.read_config_u8(cap_ptr as u8);
+
+            if cap_id == 0x09 { // Vendor-specific capability
+                let cfg_type = pci_dev.read_config_u8((cap_ptr + 3) as u8);
+                let bar = pci_dev.read_config_u8((cap_ptr + 4) as u8);
+                let offset = pci_dev.read_config_u32((cap_ptr + 8) as u8);
+
+                if bar == 4 {
+                    let addr = bar4_addr + offset as u64;
+                    match cfg_type {
+                        VIRTIO_PCI_CAP_COMMON_CFG => common_cfg_addr = Some(addr),
+                        VIRTIO_PCI_CAP_NOTIFY_CFG => {
+                            notify_addr = Some(addr);
+                            notify_off_mult = pci_dev.read_config_u32((cap_ptr + 16) as u8);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            cap_ptr = pci_dev.read_config_u8((cap_ptr + 1) as u8) as u16;
+        }
+
+        Some((common_cfg_addr?, notify_addr?, notify_off_mult))
+    }
+
+    /// Fill `buf` with random bytes from the host. Returns the number of bytes
+    /// written, which can be less than requested if the device runs dry.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, &'static str> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            let want = (buf.len() - filled).min(MAX_REQUEST);
+            let got = self.request(&mut buf[filled..filled + want])?;
+            if got == 0 {
+                break;
+            }
+            filled += got;
+        }
+        Ok(filled)
+    }
+
+    /// Post a single device-writable buffer and wait for the device to fill it
+    fn request(&mut self, out: &mut [u8]) -> Result<usize, &'static str> {
+        unsafe {
+            let queue_size = ptr::read_volatile(&(*self.common_cfg).queue_size);
+            let desc = self.queue_phys as *mut VirtqDesc;
+            let avail = self.queue_phys + queue_size as u64 * 16;
+            let avail_ring = (avail + 4) as *mut u16;
+            let used = (avail + 6 + 2 * queue_size as u64 + 3) & !3;
+            let used_ring = (used + 4) as *const VirtqUsedElem;
+
+            // Single descriptor, always slot 0 since only one request is in flight
+            ptr::write_volatile(desc, VirtqDesc {
+                addr: self.buffer_phys,
+                len: out.len() as u32,
+                flags: VIRTQ_DESC_F_WRITE,
+                next: 0,
+            });
+
+            ptr::write_volatile(avail_ring.add((self.avail_idx % queue_size) as usize), 0);
+            mb();
+            self.avail_idx = self.avail_idx.wrapping_add(1);
+            ptr::write_volatile((avail + 2) as *mut u16, self.avail_idx);
+            mb();
+
+            ptr::write_volatile(self.notify_addr as *mut u16, 0);
+            mb();
+
+            // Poll for completion (the host answers immediately unless rate limited)
+            let mut completed = false;
+            for _ in 0..1_000_000 {
+                let used_idx = ptr::read_volatile((used + 2) as *const u16);
+                if used_idx != self.last_seen_used {
+                    completed = true;
+                    break;
+                }
+                core::arch::asm!("nop");
+            }
+            if !completed {
+                return Err("Entropy device timeout");
+            }
+
+            let elem = ptr::read_volatile(used_ring.add((self.last_seen_used % queue_size) as usize));
+            self.last_seen_used = self.last_seen_used.wrapping_add(1);
+
+            let len = (elem.len as usize).min(out.len());
+            ptr::copy_nonoverlapping(self.buffer_phys as *const u8, out.as_mut_ptr(), len);
+            Ok(len)
+        }
+    }
+}
@@ -0,0 +1,121 @@
+// Async executor for kernel tasks
+//
+// Runs futures cooperatively on the kernel main loop, next to input polling and
+// rendering. A task is only polled after something wakes it: a timer (see
+// timer.rs), a smoltcp socket waker fired by NetworkStack::poll() or a finished
+// virtio-blk request. Idle tasks therefore cost nothing per frame, and many
+// network and disk operations can be in flight at once without blocking the GUI.
+//
+// spawn/cancel/run_ready must only be called from the main loop. Wakers are
+// Send + Sync and may be invoked from anywhere.
+
+pub mod timer;
+
+pub use timer::timeout;
+
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::task::{Context, Waker};
+use spin::Mutex;
+
+/// Handle to a spawned task, used to cancel it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TaskId(u64);
+
+struct Task {
+    future: Pin<Box<dyn Future<Output = ()>>>,
+    waker: Arc<TaskWaker>,
+}
+
+/// Waker that pushes its task onto the ready queue (at most once until polled)
+struct TaskWaker {
+    id: TaskId,
+    queued: AtomicBool,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if !self.queued.swap(true, Ordering::AcqRel) {
+            READY_QUEUE.lock().push_back(self.id);
+        }
+    }
+}
+
+static NEXT_TASK_ID: AtomicU64 = AtomicU64::new(1);
+static READY_QUEUE: Mutex<VecDeque<TaskId>> = Mutex::new(VecDeque::new());
+
+// Main-loop only state
+static mut TASKS: BTreeMap<TaskId, Task> = BTreeMap::new();
+static mut RUNNING: Option<TaskId> = None;
+static mut CANCEL_RUNNING: bool = false;
+
+/// Spawn a task. It is first polled on the next run_ready().
+pub fn spawn(future: impl Future<Output = ()> + 'static) -> TaskId {
+    let id = TaskId(NEXT_TASK_ID.fetch_add(1, Ordering::Relaxed));
+    let waker = Arc::new(TaskWaker { id, queued: AtomicBool::new(false) });
+    unsafe {
+        TASKS.insert(id, Task { future: Box::pin(future), waker: waker.clone() });
+    }
+    waker.wake_by_ref();
+    id
+}
+
+/// Cancel a task, dropping its future (and any sockets or timers it owns).
+/// Cancelling a finished or unknown task is a no-op.
+pub fn cancel(id: TaskId) {
+    unsafe {
+        if RUNNING == Some(id) {
+            // A task cancelling itself: drop it once its poll returns
+            CANCEL_RUNNING = true;
+        } else {
+            TASKS.remove(&id);
+        }
+    }
+}
+
+/// Fire expired timers and poll every task that has been woken.
+/// Returns true if any task was polled (call from the main loop every frame).
+pub fn run_ready() -> bool {
+    timer::fire_expired();
+
+    // Only run tasks woken before this call. A task that wakes itself again
+    // waits for the next frame, so it can't starve input and rendering.
+    let batch = core::mem::take(&mut *READY_QUEUE.lock());
+    let mut ran = false;
+
+    for id in batch {
+        let mut task = match unsafe { TASKS.remove(&id) } {
+            Some(task) => task,
+            None => continue, // Cancelled or already finished
+        };
+
+        task.waker.queued.store(false, Ordering::Release);
+        let waker = Waker::from(task.waker.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        unsafe {
+            RUNNING = Some(id);
+            CANCEL_RUNNING = false;
+        }
+        let result = task.future.as_mut().poll(&mut cx);
+        ran = true;
+
+        unsafe {
+            RUNNING = None;
+            if result.is_pending() && !CANCEL_RUNNING {
+                TASKS.insert(id, task);
+            }
+        }
+    }
+
+    ran
+}
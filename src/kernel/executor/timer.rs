@@ -0,0 +1,92 @@
+// Executor timers: sleep_ms() and timeout()
+//
+// Pending sleeps register their waker in a deadline-ordered map; run_ready()
+// wakes everything whose deadline has passed. Deadlines are CLOCK_MONOTONIC
+// milliseconds.
+
+use alloc::collections::BTreeMap;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use spin::Mutex;
+use crate::kernel::clock;
+
+/// (deadline_ms, sequence) -> waker; the sequence keeps equal deadlines distinct
+static TIMERS: Mutex<BTreeMap<(u64, u64), Waker>> = Mutex::new(BTreeMap::new());
+static NEXT_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// Wake every timer whose deadline has passed
+pub fn fire_expired() {
+    let now = clock::monotonic_ms();
+    let mut timers = TIMERS.lock();
+    while let Some(entry) = timers.first_entry() {
+        if entry.key().0 > now {
+            break;
+        }
+        entry.remove().wake();
+    }
+}
+
+/// Future that completes once a deadline has passed
+pub struct Sleep {
+    deadline: u64,
+    key: Option<(u64, u64)>,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if clock::monotonic_ms() >= self.deadline {
+            if let Some(key) = self.key.take() {
+                TIMERS.lock().remove(&key);
+            }
+            return Poll::Ready(());
+        }
+
+        let key = match self.key {
+            Some(key) => key,
+            None => {
+                let key = (self.deadline, NEXT_SEQUENCE.fetch_add(1, Ordering::Relaxed));
+                self.key = Some(key);
+                key
+            }
+        };
+        // (Re)register: the entry is gone if the timer already fired spuriously
+        TIMERS.lock().insert(key, cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            TIMERS.lock().remove(&key);
+        }
+    }
+}
+
+/// Sleep for `ms` milliseconds
+pub fn sleep_ms(ms: u64) -> Sleep {
+    Sleep {
+        deadline: clock::monotonic_ms() + ms,
+        key: None,
+    }
+}
+
+/// Run `future`, giving up after `ms` milliseconds
+pub async fn timeout<F: Future>(ms: u64, future: F) -> Result<F::Output, &'static str> {
+    let mut future = core::pin::pin!(future);
+    let mut sleep = sleep_ms(ms);
+    core::future::poll_fn(|cx| {
+        if let Poll::Ready(output) = future.as_mut().poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        if Pin::new(&mut sleep).poll(cx).is_ready() {
+            return Poll::Ready(Err("Timed out"));
+        }
+        Poll::Pending
+    })
+    .await
+}
@@ -249,6 +249,9 @@ fn handle_timer_interrupt() {
         CNTP_TVAL_EL0.set(freq / 100); // 10ms intervals
         CNTP_CTL_EL0.write(CNTP_CTL_EL0::ENABLE::SET);
 
+        // Interrupt arrival time is a cheap entropy source
+        crate::kernel::random::add_interrupt_jitter();
+
         // Preemptive multitasking - preempt every tick (10ms time slices)
         static mut TICK_COUNT: u64 = 0;
         const PREEMPT_TICKS: u64 = 1; // Preempt every 10ms
@@ -0,0 +1,90 @@
+// Kernel message log
+//
+// Everything written to the UART is also kept in a fixed ring buffer, so the
+// boot messages can be read back later (/proc/log) without a serial console.
+// The buffer is static so logging works before the heap is set up; once it
+// fills, the oldest messages are overwritten. The uptime each line started at
+// is kept alongside, for readers that want log records rather than text.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const LOG_SIZE: usize = 64 * 1024;
+/// Recent lines whose start is remembered
+const LINE_STARTS: usize = 1024;
+
+static mut LOG: [u8; LOG_SIZE] = [0; LOG_SIZE];
+/// Total bytes ever logged; the next byte goes at LOG_WRITTEN % LOG_SIZE
+static mut LOG_WRITTEN: usize = 0;
+/// Where each recent line starts (in LOG_WRITTEN terms) and the uptime in ms
+/// it was logged at; line n is at n % LINE_STARTS
+static mut LINES: [(usize, u64); LINE_STARTS] = [(0, 0); LINE_STARTS];
+/// Total lines ever started
+static mut LINES_STARTED: usize = 0;
+
+/// A complete line of the log
+pub struct Line {
+    /// Lines logged before this one since boot
+    pub number: usize,
+    pub uptime_ms: u64,
+    /// Text without the line ending
+    pub text: String,
+}
+
+/// Append a message to the log
+pub fn record(s: &str) {
+    unsafe {
+        for &byte in s.as_bytes() {
+            if LOG_WRITTEN == 0 || LOG[(LOG_WRITTEN - 1) % LOG_SIZE] == b'\n' {
+                LINES[LINES_STARTED % LINE_STARTS] = (LOG_WRITTEN, crate::kernel::clock::monotonic_ms());
+                LINES_STARTED += 1;
+            }
+            LOG[LOG_WRITTEN % LOG_SIZE] = byte;
+            LOG_WRITTEN += 1;
+        }
+    }
+}
+
+/// The messages still in the buffer, oldest first. After the buffer wraps,
+/// the partial line at the start is dropped.
+pub fn contents() -> Vec<u8> {
+    unsafe {
+        if LOG_WRITTEN <= LOG_SIZE {
+            return LOG[..LOG_WRITTEN].to_vec();
+        }
+        let start = LOG_WRITTEN % LOG_SIZE;
+        let mut log = LOG[start..].to_vec();
+        log.extend_from_slice(&LOG[..start]);
+        match log.iter().position(|&byte| byte == b'\n') {
+            Some(newline) => log.split_off(newline + 1),
+            None => log,
+        }
+    }
+}
+
+/// Complete lines from line number `from` on, as far as they are still in the
+/// buffer. The line being written is left until its newline arrives.
+pub fn lines_from(from: usize) -> Vec<Line> {
+    let mut lines = Vec::new();
+    unsafe {
+        let oldest = LINES_STARTED.saturating_sub(LINE_STARTS);
+        for number in from.max(oldest)..LINES_STARTED {
+            let (start, uptime_ms) = LINES[number % LINE_STARTS];
+            let end = if number + 1 < LINES_STARTED {
+                LINES[(number + 1) % LINE_STARTS].0
+            } else if LOG[(LOG_WRITTEN - 1) % LOG_SIZE] == b'\n' {
+                LOG_WRITTEN
+            } else {
+                break;
+            };
+            // Overwritten since
+            if start + LOG_SIZE < LOG_WRITTEN {
+                continue;
+            }
+            let bytes: Vec<u8> = (start..end).map(|i| LOG[i % LOG_SIZE]).collect();
+            let text = String::from_utf8_lossy(&bytes).trim_end_matches(['\r', '\n']).into();
+            lines.push(Line { number, uptime_ms, text });
+        }
+    }
+    lines
+}
@@ -32,9 +32,36 @@ pub enum MemoryType {
     PersistentMemory = 14,
 }
 
+impl MemoryType {
+    /// Name of a UEFI memory type number
+    pub fn name(typ: u32) -> &'static str {
+        match typ {
+            0 => "Reserved",
+            1 => "LoaderCode",
+            2 => "LoaderData",
+            3 => "BootServicesCode",
+            4 => "BootServicesData",
+            5 => "RuntimeServicesCode",
+            6 => "RuntimeServicesData",
+            7 => "Conventional",
+            8 => "Unusable",
+            9 => "AcpiReclaim",
+            10 => "AcpiNvs",
+            11 => "MemoryMappedIo",
+            12 => "MemoryMappedIoPortSpace",
+            13 => "PalCode",
+            14 => "PersistentMemory",
+            _ => "Unknown",
+        }
+    }
+}
+
 /// Physical memory allocator state
 static mut PHYS_MEM_ALLOCATOR: PhysicalMemoryAllocator = PhysicalMemoryAllocator::new();
 
+/// Memory map handed over by the bootloader (may be empty)
+static mut MEMORY_MAP: &[MemoryDescriptor] = &[];
+
 struct PhysicalMemoryAllocator {
     initialized: bool,
     next_free_page: u64,
@@ -85,8 +112,9 @@ impl PhysicalMemoryAllocator {
 }
 
 /// Initialize physical memory management
-pub fn init_physical_memory(memory_map: &[MemoryDescriptor]) {
+pub fn init_physical_memory(memory_map: &'static [MemoryDescriptor]) {
     unsafe {
+        MEMORY_MAP = memory_map;
         if memory_map.is_empty() {
             // If no memory map provided, use a default range for QEMU ARM64
             // QEMU ARM virt machine has RAM starting at 0x40000000
@@ -100,6 +128,16 @@ pub fn init_physical_memory(memory_map: &[MemoryDescriptor]) {
     }
 }
 
+/// The bootloader's memory map
+pub fn memory_map() -> &'static [MemoryDescriptor] {
+    unsafe { MEMORY_MAP }
+}
+
+/// Range of physical memory still available to allocate_pages(), as (next free, end)
+pub fn free_physical_range() -> (u64, u64) {
+    unsafe { (PHYS_MEM_ALLOCATOR.next_free_page, PHYS_MEM_ALLOCATOR.memory_end) }
+}
+
 /// Allocate a physical page (4KB)
 pub fn alloc_physical_page() -> Option<u64> {
     unsafe {
@@ -16,6 +16,12 @@ pub mod dtb;
 pub mod drivers;
 pub mod thread;
 pub mod scheduler;
+pub mod clock;
+pub mod random;
+pub mod executor;
+pub mod log;
+pub mod ports;
+pub mod unlock;
 
 /// Information passed from UEFI bootloader to kernel
 #[repr(C)]
@@ -25,12 +31,18 @@ pub struct BootInfo {
     pub acpi_rsdp: Option<u64>,
 }
 
-// Static storage for block devices (needs to outlive local scope for shell access)
-pub static mut BLOCK_DEVICES: Option<alloc::vec::Vec<drivers::virtio::blk::VirtioBlkDevice>> = None;
+// How often dirty blocks in the block cache are written back to disk
+const CACHE_WRITEBACK_INTERVAL_MS: u64 = 5000;
+
+// Most file data /tmp may hold (it lives on the kernel heap)
+const TMPFS_CAPACITY: usize = 8 * 1024 * 1024;
 
 // Static storage for network devices (deprecated - use NETWORK_STACK instead)
 pub static mut NET_DEVICES: Option<alloc::vec::Vec<drivers::virtio::net::VirtioNetDevice>> = None;
 
+// Static storage for the entropy device (read by kernel::random when reseeding)
+pub static mut RNG_DEVICE: Option<drivers::virtio::rng::VirtioRngDevice> = None;
+
 // Static storage for smoltcp-based network stack
 pub static mut NETWORK_STACK: Option<crate::system::net::NetworkStack> = None;
 
@@ -48,6 +60,7 @@ static mut SCREEN_HEIGHT: u32 = 0;
 // Basic UART output for debugging
 pub fn uart_write_string(s: &str) {
     const UART_BASE: u64 = 0x09000000; // QEMU ARM virt machine UART address
+    log::record(s);
     for byte in s.bytes() {
         unsafe {
             core::ptr::write_volatile(UART_BASE as *mut u8, byte);
@@ -55,6 +68,20 @@ pub fn uart_write_string(s: &str) {
     }
 }
 
+/// Next byte received on the UART, if one is waiting
+pub fn uart_read_byte() -> Option<u8> {
+    const UART_DR: u64 = 0x09000000;
+    const UART_FR: u64 = 0x09000018;
+    const FR_RXFE: u32 = 1 << 4; // Receive FIFO empty
+    unsafe {
+        if core::ptr::read_volatile(UART_FR as *const u32) & FR_RXFE != 0 {
+            None
+        } else {
+            Some(core::ptr::read_volatile(UART_DR as *const u32) as u8)
+        }
+    }
+}
+
 // Handle mouse movement and update hardware cursor
 pub fn handle_mouse_movement(x_delta: i32, y_delta: i32) {
     unsafe {
@@ -76,7 +103,7 @@ pub fn handle_mouse_movement(x_delta: i32, y_delta: i32) {
 
 // Get current time in milliseconds (for double-click detection, etc.)
 pub fn get_time_ms() -> u64 {
-    drivers::timer::get_time_ms()
+    clock::monotonic_ms()
 }
 
 // Flush a partial region of the framebuffer to GPU (for menu bar updates, etc.)
@@ -125,6 +152,12 @@ pub extern "C" fn kernel_main(boot_info: &'static BootInfo) -> ! {
     uart_write_string("Initializing physical memory...\r\n");
     memory::init_physical_memory(&boot_info.memory_map);
     uart_write_string("Physical memory initialized\r\n");
+
+    // Initialize clocks (monotonic from the generic timer, realtime seeded from the PL031)
+    clock::init();
+
+    // Seed the kernel CSPRNG from timer jitter (virtio-rng is added after PCI probing)
+    random::init();
     
     // Now initialize VirtIO-GPU for graphics
     uart_write_string("Trying to initialize VirtIO-GPU...\r\n");
@@ -324,10 +357,6 @@ pub extern "C" fn kernel_main(boot_info: &'static BootInfo) -> ! {
         uart_write_string("Window manager initialized!\r\n");
         uart_write_string("Click menu bar to open windows\r\n");
 
-        // Initialize RTC
-        drivers::rtc::init();
-        uart_write_string("RTC initialized!\r\n");
-
         // Initialize text editor
         crate::gui::widgets::editor::init();
         uart_write_string("Text editor initialized!\r\n");
@@ -372,6 +401,8 @@ pub extern "C" fn kernel_main(boot_info: &'static BootInfo) -> ! {
     // Parse Device Tree Blob to get correct PCI controller addresses
     uart_write_string("Parsing Device Tree Blob...\r\n");
     let pci_info = dtb::parse_dtb();
+    // Host folders shared over 9P, mounted with the other diskless filesystems
+    let mut shares = alloc::vec::Vec::new();
 
     if let Some(info) = pci_info {
         uart_write_string("DTB parsing successful!\r\n");
@@ -389,82 +420,49 @@ pub extern "C" fn kernel_main(boot_info: &'static BootInfo) -> ! {
         drivers::virtio::input::init_virtio_input_with_pci_base(info.ecam_base, info.mmio_base);
         uart_write_string("VirtIO input devices ready!\r\n");
 
-        // Initialize VirtIO block devices
-        uart_write_string("Initializing VirtIO block devices...\r\n");
+        // Initialize VirtIO entropy device and reseed the CSPRNG from it
         unsafe {
-            BLOCK_DEVICES = Some(drivers::virtio::blk::VirtioBlkDevice::find_and_init(info.ecam_base, info.mmio_base));
+            RNG_DEVICE = drivers::virtio::rng::VirtioRngDevice::find_and_init(info.ecam_base, info.mmio_base);
         }
+        random::reseed_from_hardware();
 
-        // Create a local reference for easier access (must borrow from static)
-        let blk_devices = unsafe { BLOCK_DEVICES.as_mut().unwrap() };
-
-        if !blk_devices.is_empty() {
-            uart_write_string("VirtIO block device initialized! Running read/write tests...\r\n");
+        shares = drivers::virtio::p9::Virtio9pDevice::find_and_init(info.ecam_base, info.mmio_base);
 
-            // Test 1: Write a pattern to sector 1000 (high sector to avoid filesystem collision)
-            uart_write_string("\nTest 1: Writing pattern to sector 1000...\r\n");
-            let mut write_buffer = [0u8; 512];
-            // Fill with a recognizable pattern
-            for i in 0..512 {
-                write_buffer[i] = (i % 256) as u8;
-            }
-
-            match blk_devices[0].write_sector(1000, &write_buffer) {
-                Ok(()) => {
-                    uart_write_string("Write successful! Pattern written to sector 1000.\r\n");
-                }
-                Err(e) => {
-                    uart_write_string("ERROR: Failed to write sector: ");
-                    uart_write_string(e);
-                    uart_write_string("\r\n");
-                }
-            }
+        // Host channels: remote shell, log and file transfer
+        if let Some(console) = drivers::virtio::console::VirtioConsoleDevice::find_and_init(info.ecam_base, info.mmio_base) {
+            ports::init(console);
+        }
 
-            // Test 2: Read it back and verify
-            uart_write_string("\nTest 2: Reading back sector 1000 to verify...\r\n");
-            let mut read_buffer = [0u8; 512];
-            match blk_devices[0].read_sector(1000, &mut read_buffer) {
-                Ok(()) => {
-                    uart_write_string("Read successful! Verifying data...\r\n");
+        // Initialize VirtIO block devices
+        uart_write_string("Initializing VirtIO block devices...\r\n");
+        // Register each disk (vda, vdb, ...) along with its partitions
+        let mut disk_indices = alloc::vec::Vec::new();
+        for (i, blk_device) in drivers::virtio::blk::VirtioBlkDevice::find_and_init(info.ecam_base, info.mmio_base).into_iter().enumerate() {
+            let name = alloc::format!("vd{}", (b'a' + i as u8) as char);
+            disk_indices.push(crate::system::block::registry::register_disk(&name, alloc::boxed::Box::new(blk_device)));
+        }
 
-                    // Verify the data matches
-                    let mut errors = 0;
-                    for i in 0..512 {
-                        if read_buffer[i] != write_buffer[i] {
-                            errors += 1;
-                        }
-                    }
-
-                    if errors == 0 {
-                        uart_write_string("✓ VERIFICATION SUCCESS! All 512 bytes match!\r\n");
-                        uart_write_string("First 16 bytes: ");
-                        for i in 0..16 {
-                            let byte = read_buffer[i];
-                            let hex_chars = b"0123456789ABCDEF";
-                            unsafe {
-                                core::ptr::write_volatile(0x09000000 as *mut u8, hex_chars[(byte >> 4) as usize]);
-                                core::ptr::write_volatile(0x09000000 as *mut u8, hex_chars[(byte & 0x0F) as usize]);
-                                core::ptr::write_volatile(0x09000000 as *mut u8, b' ');
-                            }
-                        }
-                        uart_write_string("\r\n");
-                    } else {
-                        uart_write_string(&alloc::format!(
-                            "✗ VERIFICATION FAILED! {} bytes don't match!\r\n", errors
-                        ));
+        // Write back dirty cached blocks every few seconds
+        executor::spawn(async {
+            loop {
+                executor::timer::sleep_ms(CACHE_WRITEBACK_INTERVAL_MS).await;
+                let cache = crate::system::block::cache::cache();
+                if cache.dirty_blocks() > 0 {
+                    if let Err(e) = cache.sync() {
+                        uart_write_string(&alloc::format!("Cache write-back failed: {}\r\n", e));
                     }
                 }
-                Err(e) => {
-                    uart_write_string("ERROR: Failed to read sector: ");
-                    uart_write_string(e);
-                    uart_write_string("\r\n");
-                }
             }
+        });
+
+        if !disk_indices.is_empty() {
+            uart_write_string("VirtIO block device initialized! Running read test...\r\n");
+            let first_disk = crate::system::block::device(disk_indices[0]).unwrap();
 
-            // Test 3: Read sector 0 (boot sector)
-            uart_write_string("\nTest 3: Reading sector 0 (boot sector)...\r\n");
+            // Read sector 0 (boot sector); the disks are never written here
+            uart_write_string("\nReading sector 0 (boot sector)...\r\n");
             let mut buffer = [0u8; 512];
-            match blk_devices[0].read_sector(0, &mut buffer) {
+            match first_disk.read_blocks(0, &mut buffer) {
                 Ok(()) => {
                     uart_write_string("Sector 0 read successfully! First 16 bytes:\r\n");
                     for i in 0..16 {
@@ -538,235 +536,264 @@ pub extern "C" fn kernel_main(boot_info: &'static BootInfo) -> ! {
             }
 
             // Test filesystem
-            uart_write_string("\n=== Testing SimpleFS Filesystem ===\r\n");
+            uart_write_string("\n=== Testing Filesystem ===\r\n");
 
             // Determine which device to use for persistent storage
-            // Strategy: Use the last device in the array (most likely to be the data disk)
-            let fs_device_idx = blk_devices.len() - 1;
-
-            if blk_devices.len() >= 2 {
-                uart_write_string(&alloc::format!(
-                    "Found {} block devices - using device {} for persistent storage\r\n",
-                    blk_devices.len(), fs_device_idx
-                ));
+            // Strategy: Use the last disk (most likely to be the data disk). On a
+            // partitioned disk use the first partition holding a filesystem we
+            // know; a partitioned disk is never formatted as a whole, and neither
+            // is one whose partition table couldn't be read. An encrypted disk
+            // waits for its passphrase and becomes the root once unlocked.
+            let data_disk = disk_indices[disk_indices.len() - 1];
+            let data_partitions = crate::system::block::registry::partitions_of(data_disk);
+            let root_volume = if crate::system::block::registry::is_bare(data_disk) {
+                Some(data_disk).filter(|&idx| !crate::system::block::registry::is_locked(idx))
             } else {
+                data_partitions.iter().copied().find(|&idx| {
+                    crate::system::fs::probe(crate::system::block::device(idx).unwrap())
+                })
+            };
+
+            if let Some(fs_device_idx) = root_volume {
+                crate::system::block::registry::set_root_volume(fs_device_idx);
+                let fs_device = crate::system::block::device(fs_device_idx).unwrap();
                 uart_write_string(&alloc::format!(
-                    "Found only 1 block device - using device {} (assuming persistent)\r\n",
-                    fs_device_idx
+                    "Found {} disk(s) - using {} for persistent storage\r\n",
+                    disk_indices.len(), crate::system::block::device_name(fs_device_idx).unwrap_or("?")
                 ));
-            }
 
-            // Try to mount existing filesystem first
-            uart_write_string("\nTrying to mount existing filesystem...\r\n");
-            let mut fs_result = crate::system::fs::SimpleFilesystem::mount(&mut blk_devices[fs_device_idx]);
-
-            if fs_result.is_err() {
-                // No existing filesystem, format and mount
-                uart_write_string("No existing filesystem found. Formatting disk...\r\n");
-                match crate::system::fs::SimpleFilesystem::format(&mut blk_devices[fs_device_idx], 20480) {
-                    Ok(()) => {
-                        uart_write_string("✓ Disk formatted successfully!\r\n");
-                    }
-                    Err(e) => {
-                        uart_write_string("✗ Format failed: ");
-                        uart_write_string(e);
-                        uart_write_string("\r\n");
+                // Only a blank disk is formatted: a volume we recognise (even one we
+                // can't mount), or data we don't recognise at all, is left alone
+                if !crate::system::fs::probe(fs_device) {
+                    if fs_device.is_read_only() {
+                        uart_write_string("No existing filesystem found and the disk is read-only - not formatting\r\n");
+                    } else if !crate::system::fs::is_blank(fs_device) {
+                        uart_write_string("No filesystem we know, but the disk isn't blank - not formatting\r\n");
+                    } else {
+                        uart_write_string("No existing filesystem found. Formatting disk...\r\n");
+                        match crate::system::fs::SimpleFilesystem::format(fs_device) {
+                            Ok(()) => {
+                                uart_write_string("✓ Disk formatted successfully!\r\n");
+                            }
+                            Err(e) => {
+                                uart_write_string("✗ Format failed: ");
+                                uart_write_string(e);
+                                uart_write_string("\r\n");
+                            }
+                        }
                     }
                 }
 
-                // Mount the freshly formatted filesystem
+                // Mount it once, in the VFS, where everything else finds it
                 uart_write_string("\nMounting filesystem...\r\n");
-                fs_result = crate::system::fs::SimpleFilesystem::mount(&mut blk_devices[fs_device_idx]);
-            }
+                let fs_result = crate::system::fs::vfs::mount(fs_device_idx);
 
-            match fs_result {
-                Ok(mut fs) => {
-                    uart_write_string(&alloc::format!(
-                        "✓ Filesystem mounted! {} files found\r\n",
-                        fs.file_count()
-                    ));
+                match fs_result {
+                    Ok(volume) => {
+                        use crate::system::fs::vfs;
+                        let path = |name: &str| alloc::format!("/{}/{}", volume, name);
 
-                    // List files
-                    let files = fs.list_files();
-                    let file_count = fs.file_count();
-
-                    // Skip initialization tests if filesystem already has files
-                    if file_count > 0 {
+                        // List files
+                        let files = vfs::list_dir(&path("")).unwrap_or_default();
+                        let file_count = files.len();
                         uart_write_string(&alloc::format!(
-                            "Existing filesystem with {} file entries - skipping initialization tests\r\n",
+                            "✓ Filesystem mounted at /{}! {} files found\r\n",
+                            volume,
                             file_count
                         ));
 
-                        if !files.is_empty() {
+                        // Skip initialization tests if filesystem already has files
+                        if file_count > 0 {
+                            uart_write_string(&alloc::format!(
+                                "Existing filesystem with {} file entries - skipping initialization tests\r\n",
+                                file_count
+                            ));
+
                             uart_write_string("Visible files:\r\n");
                             for file in &files {
                                 uart_write_string(&alloc::format!(
                                     "  - {} ({} bytes)\r\n",
-                                    file.get_name(),
-                                    file.get_size_bytes()
+                                    file.name,
+                                    file.size
                                 ));
                             }
+                        } else if fs_device.is_read_only() {
+                            uart_write_string("Empty read-only filesystem - skipping initialization tests\r\n");
                         } else {
-                            uart_write_string("Warning: file_count > 0 but list_files() returned empty (corruption?)\r\n");
-                        }
-                    } else {
-                        // Fresh filesystem - run initialization tests
-                        uart_write_string("\n--- Testing File Operations ---\r\n");
-                        let is_empty = files.is_empty();
-                        if is_empty {
-                            uart_write_string("✓ File list is empty (as expected on fresh format)\r\n");
-                        }
+                            // Fresh filesystem - run initialization tests
+                            uart_write_string("\n--- Testing File Operations ---\r\n");
+                            let is_empty = files.is_empty();
+                            if is_empty {
+                                uart_write_string("✓ File list is empty (as expected on fresh format)\r\n");
+                            }
 
-                        // Create a welcome file on fresh filesystem
-                        if is_empty {
-                        uart_write_string("\nCreating welcome file...\r\n");
-                        match fs.create_file(&mut blk_devices[fs_device_idx], "welcome", 256) {
-                            Ok(()) => {
-                                uart_write_string("✓ Created 'welcome' file\r\n");
-                                // Write welcome message
-                                let welcome_msg = b"Welcome to rOSt!\n\nThis is a Rust ARM64 Operating System.\n\nTry opening the Files menu to browse files,\nor use the Terminal to run shell commands.";
-                                match fs.write_file(&mut blk_devices[fs_device_idx], "welcome", welcome_msg) {
-                                    Ok(()) => uart_write_string("✓ Wrote welcome message\r\n"),
-                                    Err(e) => uart_write_string(&alloc::format!("✗ Failed to write: {}\r\n", e)),
+                            // Create a welcome file on fresh filesystem
+                            if is_empty {
+                            uart_write_string("\nCreating welcome file...\r\n");
+                            match vfs::create_file(&path("welcome"), 256) {
+                                Ok(()) => {
+                                    uart_write_string("✓ Created 'welcome' file\r\n");
+                                    // Write welcome message
+                                    let welcome_msg = b"Welcome to rOSt!\n\nThis is a Rust ARM64 Operating System.\n\nTry opening the Files menu to browse files,\nor use the Terminal to run shell commands.";
+                                    match vfs::write_file(&path("welcome"), welcome_msg) {
+                                        Ok(()) => uart_write_string("✓ Wrote welcome message\r\n"),
+                                        Err(e) => uart_write_string(&alloc::format!("✗ Failed to write: {}\r\n", e)),
+                                    }
                                 }
+                                Err(e) => uart_write_string(&alloc::format!("✗ Failed: {}\r\n", e)),
                             }
-                            Err(e) => uart_write_string(&alloc::format!("✗ Failed: {}\r\n", e)),
                         }
-                    }
 
-                    // List files
-                    uart_write_string("\nListing files...\r\n");
-                    let files = fs.list_files();
-                    uart_write_string(&alloc::format!("✓ Found {} file(s):\r\n", files.len()));
-                    for file in &files {
-                        uart_write_string(&alloc::format!(
-                            "  - '{}': {} bytes ({} sectors) at sector {}\r\n",
-                            file.get_name(),
-                            file.get_size_bytes(),
-                            file.get_size_sectors(),
-                            file.get_start_sector()
-                        ));
-                    }
-
-                    // Filesystem tests removed - OS is ready for use!
-                    if false { // Disabled filesystem tests
-                        // Test duplicate file creation (should fail)
-                        uart_write_string("\nTrying to create duplicate file...\r\n");
-                        match fs.create_file(&mut blk_devices[fs_device_idx], "hello", 50) {
-                            Ok(()) => uart_write_string("✗ Should have failed!\r\n"),
-                            Err(e) => uart_write_string(&alloc::format!("✓ Correctly rejected: {}\r\n", e)),
-                        }
-
-                        // Delete a file
-                        uart_write_string("\nDeleting 'test' file...\r\n");
-                        match fs.delete_file(&mut blk_devices[fs_device_idx], "test") {
-                            Ok(()) => uart_write_string("✓ File deleted\r\n"),
-                            Err(e) => uart_write_string(&alloc::format!("✗ Failed: {}\r\n", e)),
-                        }
-
-                        // List files again
-                        uart_write_string("\nListing files after deletion...\r\n");
-                        let files = fs.list_files();
+                        // List files
+                        uart_write_string("\nListing files...\r\n");
+                        let files = vfs::list_dir(&path("")).unwrap_or_default();
                         uart_write_string(&alloc::format!("✓ Found {} file(s):\r\n", files.len()));
                         for file in &files {
                             uart_write_string(&alloc::format!(
                                 "  - '{}': {} bytes\r\n",
-                                file.get_name(),
-                                file.get_size_bytes()
+                                file.name,
+                                file.size
                             ));
                         }
 
-                        // Try to delete non-existent file
-                        uart_write_string("\nTrying to delete non-existent file...\r\n");
-                        match fs.delete_file(&mut blk_devices[fs_device_idx], "missing") {
-                            Ok(()) => uart_write_string("✗ Should have failed!\r\n"),
-                            Err(e) => uart_write_string(&alloc::format!("✓ Correctly rejected: {}\r\n", e)),
-                        }
+                        // Filesystem tests removed - OS is ready for use!
+                        if false { // Disabled filesystem tests
+                            // Test duplicate file creation (should fail)
+                            uart_write_string("\nTrying to create duplicate file...\r\n");
+                            match vfs::create_file(&path("hello"), 50) {
+                                Ok(()) => uart_write_string("✗ Should have failed!\r\n"),
+                                Err(e) => uart_write_string(&alloc::format!("✓ Correctly rejected: {}\r\n", e)),
+                            }
 
-                        // Test file read/write
-                        uart_write_string("\n--- Testing File Read/Write ---\r\n");
+                            // Delete a file
+                            uart_write_string("\nDeleting 'test' file...\r\n");
+                            match vfs::delete(&path("test")) {
+                                Ok(()) => uart_write_string("✓ File deleted\r\n"),
+                                Err(e) => uart_write_string(&alloc::format!("✗ Failed: {}\r\n", e)),
+                            }
 
-                        // Write data to 'hello' file
-                        uart_write_string("\nWriting data to 'hello' file...\r\n");
-                        let test_data = b"Hello, World! This is a test message.";
-                        match fs.write_file(&mut blk_devices[fs_device_idx], "hello", test_data) {
-                            Ok(()) => uart_write_string(&alloc::format!("✓ Wrote {} bytes\r\n", test_data.len())),
-                            Err(e) => uart_write_string(&alloc::format!("✗ Failed: {}\r\n", e)),
-                        }
+                            // List files again
+                            uart_write_string("\nListing files after deletion...\r\n");
+                            let files = vfs::list_dir(&path("")).unwrap_or_default();
+                            uart_write_string(&alloc::format!("✓ Found {} file(s):\r\n", files.len()));
+                            for file in &files {
+                                uart_write_string(&alloc::format!(
+                                    "  - '{}': {} bytes\r\n",
+                                    file.name,
+                                    file.size
+                                ));
+                            }
 
-                        // Read data back from 'hello' file
-                        uart_write_string("\nReading data from 'hello' file...\r\n");
-                        let mut read_buffer = [0u8; 100];
-                        match fs.read_file(&mut blk_devices[fs_device_idx], "hello", &mut read_buffer) {
-                        Ok(bytes_read) => {
-                            uart_write_string(&alloc::format!("✓ Read {} bytes\r\n", bytes_read));
-
-                            // Verify data
-                            let matches = read_buffer[..test_data.len()] == test_data[..];
-                            if matches {
-                                uart_write_string("✓ Data verification SUCCESS! Content matches:\r\n");
-                                uart_write_string("  \"");
-                                uart_write_string(core::str::from_utf8(&read_buffer[..test_data.len()]).unwrap_or("???"));
-                                uart_write_string("\"\r\n");
-                            } else {
-                                uart_write_string("✗ Data verification FAILED!\r\n");
+                            // Try to delete non-existent file
+                            uart_write_string("\nTrying to delete non-existent file...\r\n");
+                            match vfs::delete(&path("missing")) {
+                                Ok(()) => uart_write_string("✗ Should have failed!\r\n"),
+                                Err(e) => uart_write_string(&alloc::format!("✓ Correctly rejected: {}\r\n", e)),
                             }
-                        }
-                        Err(e) => uart_write_string(&alloc::format!("✗ Failed: {}\r\n", e)),
-                    }
 
-                        // Write to 'data' file (multiple sectors)
-                        uart_write_string("\nWriting 400 bytes to 'data' file...\r\n");
-                        let mut big_data = [0u8; 400];
-                        for i in 0..400 {
-                            big_data[i] = (i % 256) as u8;
-                        }
-                        match fs.write_file(&mut blk_devices[fs_device_idx], "data", &big_data) {
-                            Ok(()) => uart_write_string("✓ Wrote 400 bytes\r\n"),
+                            // Test file read/write
+                            uart_write_string("\n--- Testing File Read/Write ---\r\n");
+
+                            // Write data to 'hello' file
+                            uart_write_string("\nWriting data to 'hello' file...\r\n");
+                            let test_data = b"Hello, World! This is a test message.";
+                            match vfs::write_file(&path("hello"), test_data) {
+                                Ok(()) => uart_write_string(&alloc::format!("✓ Wrote {} bytes\r\n", test_data.len())),
+                                Err(e) => uart_write_string(&alloc::format!("✗ Failed: {}\r\n", e)),
+                            }
+
+                            // Read data back from 'hello' file
+                            uart_write_string("\nReading data from 'hello' file...\r\n");
+                            let mut read_buffer = [0u8; 100];
+                            match vfs::open(&path("hello"), vfs::O_READ).and_then(|fd| { let read = vfs::read(fd, &mut read_buffer); vfs::close(fd)?; read }) {
+                            Ok(bytes_read) => {
+                                uart_write_string(&alloc::format!("✓ Read {} bytes\r\n", bytes_read));
+
+                                // Verify data
+                                let matches = read_buffer[..test_data.len()] == test_data[..];
+                                if matches {
+                                    uart_write_string("✓ Data verification SUCCESS! Content matches:\r\n");
+                                    uart_write_string("  \"");
+                                    uart_write_string(core::str::from_utf8(&read_buffer[..test_data.len()]).unwrap_or("???"));
+                                    uart_write_string("\"\r\n");
+                                } else {
+                                    uart_write_string("✗ Data verification FAILED!\r\n");
+                                }
+                            }
                             Err(e) => uart_write_string(&alloc::format!("✗ Failed: {}\r\n", e)),
                         }
 
-                        // Read it back
-                        uart_write_string("\nReading 400 bytes from 'data' file...\r\n");
-                        let mut big_read_buffer = [0u8; 512];
-                        match fs.read_file(&mut blk_devices[fs_device_idx], "data", &mut big_read_buffer) {
-                        Ok(bytes_read) => {
-                            uart_write_string(&alloc::format!("✓ Read {} bytes\r\n", bytes_read));
-
-                            // Verify
-                            let matches = big_read_buffer[..400] == big_data[..];
-                            if matches {
-                                uart_write_string("✓ Data verification SUCCESS! All 400 bytes match!\r\n");
-                                uart_write_string("  First 16 bytes: ");
-                                for i in 0..16 {
-                                    let byte = big_read_buffer[i];
-                                    let hex_chars = b"0123456789ABCDEF";
-                                    unsafe {
-                                        core::ptr::write_volatile(0x09000000 as *mut u8, hex_chars[(byte >> 4) as usize]);
-                                        core::ptr::write_volatile(0x09000000 as *mut u8, hex_chars[(byte & 0x0F) as usize]);
-                                        core::ptr::write_volatile(0x09000000 as *mut u8, b' ');
+                            // Write to 'data' file (multiple sectors)
+                            uart_write_string("\nWriting 400 bytes to 'data' file...\r\n");
+                            let mut big_data = [0u8; 400];
+                            for i in 0..400 {
+                                big_data[i] = (i % 256) as u8;
+                            }
+                            match vfs::write_file(&path("data"), &big_data) {
+                                Ok(()) => uart_write_string("✓ Wrote 400 bytes\r\n"),
+                                Err(e) => uart_write_string(&alloc::format!("✗ Failed: {}\r\n", e)),
+                            }
+
+                            // Read it back
+                            uart_write_string("\nReading 400 bytes from 'data' file...\r\n");
+                            let mut big_read_buffer = [0u8; 512];
+                            match vfs::open(&path("data"), vfs::O_READ).and_then(|fd| { let read = vfs::read(fd, &mut big_read_buffer); vfs::close(fd)?; read }) {
+                            Ok(bytes_read) => {
+                                uart_write_string(&alloc::format!("✓ Read {} bytes\r\n", bytes_read));
+
+                                // Verify
+                                let matches = big_read_buffer[..400] == big_data[..];
+                                if matches {
+                                    uart_write_string("✓ Data verification SUCCESS! All 400 bytes match!\r\n");
+                                    uart_write_string("  First 16 bytes: ");
+                                    for i in 0..16 {
+                                        let byte = big_read_buffer[i];
+                                        let hex_chars = b"0123456789ABCDEF";
+                                        unsafe {
+                                            core::ptr::write_volatile(0x09000000 as *mut u8, hex_chars[(byte >> 4) as usize]);
+                                            core::ptr::write_volatile(0x09000000 as *mut u8, hex_chars[(byte & 0x0F) as usize]);
+                                            core::ptr::write_volatile(0x09000000 as *mut u8, b' ');
+                                        }
                                     }
+                                    uart_write_string("\r\n");
+                                } else {
+                                    uart_write_string("✗ Data verification FAILED!\r\n");
                                 }
-                                uart_write_string("\r\n");
-                            } else {
-                                uart_write_string("✗ Data verification FAILED!\r\n");
-                            }
+                                }
+                                Err(e) => uart_write_string(&alloc::format!("✗ Failed: {}\r\n", e)),
                             }
-                            Err(e) => uart_write_string(&alloc::format!("✗ Failed: {}\r\n", e)),
-                        }
-                        } // End of is_empty check
-                    } // End of fresh filesystem tests / else block for file_count check
+                            } // End of is_empty check
+                        } // End of fresh filesystem tests / else block for file_count check
 
-                    // Filesystem mounted successfully
-                    // Shells will be created when terminal windows are opened
-                    uart_write_string("Filesystem ready!\r\n");
+                        // Filesystem mounted successfully
+                        // Shells will be created when terminal windows are opened
+                        uart_write_string("Filesystem ready!\r\n");
+                    }
+                    Err(e) => {
+                        uart_write_string("✗ Mount failed: ");
+                        uart_write_string(e);
+                        uart_write_string("\r\n");
+                    }
                 }
-                Err(e) => {
-                    uart_write_string("✗ Mount failed: ");
-                    uart_write_string(e);
-                    uart_write_string("\r\n");
+            } else {
+                uart_write_string("No mountable volume on the data disk - leaving it untouched\r\n");
+            }
+
+            // Every other volume with a filesystem we know goes at the next /diskN.
+            // Disks with a partition table are reached through their partitions.
+            for idx in 0..crate::system::block::registry::devices().len() {
+                if Some(idx) != root_volume && crate::system::block::registry::is_bare(idx) {
+                    let _ = crate::system::fs::vfs::mount(idx);
                 }
             }
+
+            // Encrypted volumes are mounted once someone types the passphrase
+            let locked: alloc::vec::Vec<usize> = (0..crate::system::block::registry::devices().len())
+                .filter(|&idx| crate::system::block::registry::is_locked(idx))
+                .collect();
+            if !locked.is_empty() {
+                unlock::prompt_at_boot(locked);
+            }
         } else {
             uart_write_string("No VirtIO block devices found\r\n");
         }
@@ -777,6 +804,24 @@ pub extern "C" fn kernel_main(boot_info: &'static BootInfo) -> ! {
         drivers::virtio::input::init_virtio_input();
     }
 
+    // Filesystems that don't need a disk
+    {
+        use crate::system::fs::{devfs::DevFs, procfs::ProcFs, vfs, TmpFs};
+        let _ = vfs::mount_virtual("tmp", alloc::boxed::Box::new(TmpFs::new(TMPFS_CAPACITY)));
+        let _ = vfs::mount_virtual("proc", alloc::boxed::Box::new(ProcFs));
+        let _ = vfs::mount_virtual("dev", alloc::boxed::Box::new(DevFs));
+
+        // Each share goes at /<mount tag>
+        for share in shares {
+            let tag = share.tag.clone();
+            let result = crate::system::fs::NinePFs::mount(alloc::boxed::Box::new(share))
+                .and_then(|fs| vfs::mount_virtual(&tag, alloc::boxed::Box::new(fs)));
+            if let Err(e) = result {
+                uart_write_string(&alloc::format!("Couldn't mount host folder '{}': {}\r\n", tag, e));
+            }
+        }
+    }
+
     uart_write_string("\r\n");
     uart_write_string("================================\r\n");
     uart_write_string("  Rust OS - Interactive Shell  \r\n");
@@ -787,13 +832,13 @@ pub extern "C" fn kernel_main(boot_info: &'static BootInfo) -> ! {
     uart_write_string("Kernel ready! Open a terminal window from the menu.\r\n");
 
     let mut needs_full_render = true; // Force initial render
-    let mut last_minute = drivers::rtc::get_datetime().minute; // Track last rendered minute
+    let mut last_minute = clock::now_local().datetime.minute; // Track last rendered minute
 
     loop {
         // Worker threads scheduled via timer preemption only (every 10ms)
 
         // Check if minute has changed - redraw clock every minute
-        let current_minute = drivers::rtc::get_datetime().minute;
+        let current_minute = clock::now_local().datetime.minute;
         if current_minute != last_minute {
             last_minute = current_minute;
             needs_full_render = true;
@@ -809,7 +854,16 @@ pub extern "C" fn kernel_main(boot_info: &'static BootInfo) -> ! {
             }
         }
 
-        // Poll browser async HTTP state machines
+        // Retire finished disk requests (wakes tasks waiting on them)
+        drivers::virtio::blk::poll_completions();
+
+        // Run async tasks woken by timers, socket activity or disk I/O. Tasks may
+        // write to consoles or swap in browser pages, so redraw whenever one ran
+        if executor::run_ready() {
+            needs_full_render = true;
+        }
+
+        // Start queued browser resource loads
         if crate::gui::widgets::browser::poll_all_browsers() {
             needs_full_render = true;
         }
@@ -820,6 +874,16 @@ pub extern "C" fn kernel_main(boot_info: &'static BootInfo) -> ! {
             needs_full_render = true;
         }
 
+        // Passphrases for encrypted volumes typed on the serial console
+        if unlock::poll_serial() {
+            needs_full_render = true;
+        }
+
+        // Remote shell, log and file transfer over virtio-console ports
+        if ports::poll() {
+            needs_full_render = true;
+        }
+
         // Update snake games and only render if any game changed state
         if !crate::gui::window_manager::get_all_snakes().is_empty() {
             if crate::apps::snake::update_all_games() {
@@ -0,0 +1,179 @@
+// Host channels over virtio-console ports
+//
+// Each virtserialport is given its job by the name the host gave it:
+//
+//   rost.shell  a shell, as in a terminal window, for whoever connects
+//   rost.log    the kernel log, one JSON record per line
+//   rost.files  copying files to and from the guest (system::transfer)
+//
+// tools/rostlink.py is the host side; `make run` creates the ports with
+// sockets under target/. Ports are polled from the main loop. Log records
+// look like {"seq":12,"ms":4031,"level":"info","msg":"VirtIO console device ready!"}
+// with the level guessed from the message, since the log itself is plain text.
+
+use super::drivers::virtio::console::{PortEvent, VirtioConsoleDevice};
+use super::log;
+use crate::system::fs::vfs;
+use crate::system::transfer::{self, Request};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+pub const SHELL_PORT: &str = "rost.shell";
+pub const LOG_PORT: &str = "rost.log";
+pub const FILES_PORT: &str = "rost.files";
+
+static mut DEVICE: Option<VirtioConsoleDevice> = None;
+/// Next log line to send on the log port
+static mut LOG_NEXT: usize = 0;
+static mut TRANSFER: transfer::Parser = transfer::Parser::new();
+/// Last byte from the shell port, so "\r\n" is one Enter rather than two
+static mut SHELL_LAST: u8 = 0;
+
+pub fn init(device: VirtioConsoleDevice) {
+    unsafe {
+        DEVICE = Some(device);
+    }
+}
+
+/// Name of port `id`
+fn port_name(id: u32) -> Option<String> {
+    unsafe { DEVICE.as_ref()?.ports.iter().find(|port| port.id == id).map(|port| port.name.clone()) }
+}
+
+/// Send to the host end of the named port, if it exists and is connected
+fn send(name: &str, data: &[u8]) -> Result<(), &'static str> {
+    unsafe {
+        let device = DEVICE.as_mut().ok_or("No console device")?;
+        let id = device.port_named(name).ok_or("No such console port")?.id;
+        device.write(id, data)
+    }
+}
+
+/// Output of the remote shell
+pub fn shell_output(data: &[u8]) {
+    let _ = send(SHELL_PORT, data);
+}
+
+/// Handle whatever arrived on the ports and send new log lines. Returns
+/// whether something may have changed on screen.
+pub fn poll() -> bool {
+    let events = match unsafe { DEVICE.as_mut() } {
+        Some(device) => device.poll(),
+        None => return false,
+    };
+
+    let mut changed = false;
+    let mut received_files = false;
+    for event in events {
+        match event {
+            PortEvent::Connected(id, connected) => match port_name(id).as_deref() {
+                Some(SHELL_PORT) if connected => {
+                    shell_output(b"rOSt remote shell - type 'help' for commands\r\n");
+                    crate::apps::shell::remote_shell().show_prompt();
+                }
+                // A new listener gets everything still in the log
+                Some(LOG_PORT) if connected => unsafe { LOG_NEXT = 0 },
+                // A new connection starts a fresh request stream
+                Some(FILES_PORT) => unsafe { TRANSFER = transfer::Parser::new() },
+                _ => {}
+            },
+            PortEvent::Data(id, data) => match port_name(id).as_deref() {
+                Some(SHELL_PORT) => {
+                    let shell = crate::apps::shell::remote_shell();
+                    for byte in data {
+                        let last = unsafe { core::mem::replace(&mut SHELL_LAST, byte) };
+                        if !(byte == b'\n' && last == b'\r') {
+                            shell.handle_char(byte);
+                        }
+                    }
+                    changed = true;
+                }
+                Some(FILES_PORT) => {
+                    for request in unsafe { TRANSFER.feed(&data) } {
+                        let put = matches!(request, Ok(Request::Put { .. }));
+                        let result = request.and_then(handle_request);
+                        received_files |= put && result.is_ok();
+                        let _ = send(FILES_PORT, &transfer::reply(result));
+                    }
+                }
+                // Nothing is read from the log port
+                _ => {}
+            },
+        }
+    }
+    if received_files {
+        crate::gui::widgets::file_explorer::refresh_all_explorers();
+        changed = true;
+    }
+
+    send_log();
+    changed
+}
+
+/// Carry out a file transfer request, returning the reply data
+fn handle_request(request: Request) -> Result<Vec<u8>, &'static str> {
+    match request {
+        Request::Put { path, data } => {
+            vfs::write_file(&path, &data)?;
+            super::uart_write_string(&alloc::format!("Received {} ({} bytes) from the host\r\n", path, data.len()));
+            Ok(Vec::new())
+        }
+        Request::Get { path } => vfs::read_file(&path),
+        Request::List { path } => {
+            let mut listing = String::new();
+            for file in vfs::list_dir(&path)? {
+                listing.push_str(&transfer::list_entry(&file.name, file.size, file.is_dir));
+            }
+            Ok(listing.into_bytes())
+        }
+    }
+}
+
+/// Send log lines the log port hasn't had yet, if someone is listening
+fn send_log() {
+    let listening = unsafe { DEVICE.as_ref() }
+        .and_then(|device| device.port_named(LOG_PORT))
+        .is_some_and(|port| port.host_connected);
+    if !listening {
+        return;
+    }
+    for line in log::lines_from(unsafe { LOG_NEXT }) {
+        if !line.text.trim().is_empty() {
+            let record = alloc::format!(
+                "{{\"seq\":{},\"ms\":{},\"level\":\"{}\",\"msg\":\"{}\"}}\n",
+                line.number, line.uptime_ms, level(&line.text), json_escape(line.text.trim())
+            );
+            // Try again next time
+            if send(LOG_PORT, record.as_bytes()).is_err() {
+                return;
+            }
+        }
+        unsafe { LOG_NEXT = line.number + 1 };
+    }
+}
+
+/// Severity of a log message, going by how the kernel words them
+fn level(text: &str) -> &'static str {
+    let text = text.trim_start().to_ascii_lowercase();
+    if text.starts_with("error") || text.contains("failed") || text.starts_with('✗') {
+        "error"
+    } else if text.starts_with("warning") {
+        "warn"
+    } else {
+        "info"
+    }
+}
+
+fn json_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => escaped.push_str(&alloc::format!("\\u{:04x}", ch as u32)),
+            ch => escaped.push(ch),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,316 @@
+// Kernel entropy pool and CSPRNG
+//
+// Entropy sources (virtio-rng, timer jitter, the RTC) are XORed into a 64-byte
+// pool which is stirred with the ChaCha20 block function. The pool keys a
+// ChaCha20 generator that serves getrandom(). The generator replaces its own key
+// after every request (fast key erasure, so earlier output can't be recovered
+// from a later state) and reseeds from the pool every RESEED_INTERVAL bytes.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use crate::kernel::drivers::timer;
+
+/// Bytes of output between reseeds from the pool
+const RESEED_INTERVAL: usize = 64 * 1024;
+/// Bytes requested from virtio-rng per reseed
+const HARDWARE_SEED_BYTES: usize = 32;
+
+const CHACHA_CONSTANTS: [u32; 4] = [0x61707865, 0x3320646e, 0x79622d32, 0x6b206574];
+
+/// Counter samples folded in by the timer interrupt (lock-free, IRQ safe)
+static INTERRUPT_JITTER: AtomicU64 = AtomicU64::new(0);
+
+#[inline(always)]
+fn quarter_round(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    s[a] = s[a].wrapping_add(s[b]); s[d] ^= s[a]; s[d] = s[d].rotate_left(16);
+    s[c] = s[c].wrapping_add(s[d]); s[b] ^= s[c]; s[b] = s[b].rotate_left(12);
+    s[a] = s[a].wrapping_add(s[b]); s[d] ^= s[a]; s[d] = s[d].rotate_left(8);
+    s[c] = s[c].wrapping_add(s[d]); s[b] ^= s[c]; s[b] = s[b].rotate_left(7);
+}
+
+/// ChaCha20 block function (RFC 8439 section 2.3)
+fn chacha20_block(key: &[u32; 8], counter: u32, nonce: &[u32; 3]) -> [u32; 16] {
+    let mut input = [0u32; 16];
+    input[..4].copy_from_slice(&CHACHA_CONSTANTS);
+    input[4..12].copy_from_slice(key);
+    input[12] = counter;
+    input[13..].copy_from_slice(nonce);
+
+    let mut state = input;
+    for _ in 0..10 {
+        // Column rounds
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        // Diagonal rounds
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+
+    for (word, original) in state.iter_mut().zip(input.iter()) {
+        *word = word.wrapping_add(*original);
+    }
+    state
+}
+
+/// ChaCha20 keystream generator
+struct ChaCha20 {
+    key: [u32; 8],
+    nonce: [u32; 3],
+    counter: u32,
+}
+
+impl ChaCha20 {
+    const fn new() -> Self {
+        ChaCha20 { key: [0; 8], nonce: [0; 3], counter: 0 }
+    }
+
+    fn next_block(&mut self) -> [u32; 16] {
+        let block = chacha20_block(&self.key, self.counter, &self.nonce);
+        self.counter = self.counter.wrapping_add(1);
+        if self.counter == 0 {
+            // 256GB of output under one nonce; move to the next one
+            self.nonce[0] = self.nonce[0].wrapping_add(1);
+        }
+        block
+    }
+
+    /// Fill `out` with keystream, then replace the key so this output can't be
+    /// reconstructed from any later state
+    fn fill(&mut self, out: &mut [u8]) {
+        for chunk in out.chunks_mut(64) {
+            let block = self.next_block();
+            for (i, byte) in chunk.iter_mut().enumerate() {
+                *byte = (block[i / 4] >> ((i % 4) * 8)) as u8;
+            }
+        }
+        self.rekey();
+    }
+
+    fn rekey(&mut self) {
+        let block = self.next_block();
+        self.key.copy_from_slice(&block[..8]);
+    }
+}
+
+/// Entropy accumulator
+struct EntropyPool {
+    state: [u32; 16],
+    position: usize,
+    stirs: u32,
+}
+
+impl EntropyPool {
+    const fn new() -> Self {
+        EntropyPool { state: [0; 16], position: 0, stirs: 0 }
+    }
+
+    /// XOR bytes into the pool, stirring each time it wraps around
+    fn mix(&mut self, data: &[u8]) {
+        for &byte in data {
+            let word = self.position / 4;
+            self.state[word] ^= (byte as u32) << ((self.position % 4) * 8);
+            self.position += 1;
+            if self.position == 64 {
+                self.stir();
+            }
+        }
+    }
+
+    fn mix_u64(&mut self, value: u64) {
+        self.mix(&value.to_le_bytes());
+    }
+
+    /// Run the pool through the ChaCha20 block function so every input bit
+    /// affects every pool bit
+    fn stir(&mut self) {
+        let mut key = [0u32; 8];
+        key.copy_from_slice(&self.state[..8]);
+        let nonce = [self.state[13], self.state[14], self.state[15]];
+        let block = chacha20_block(&key, self.state[12] ^ self.stirs, &nonce);
+        for (word, mixed) in self.state.iter_mut().zip(block.iter()) {
+            *word ^= *mixed;
+        }
+        self.stirs = self.stirs.wrapping_add(1);
+        self.position = 0;
+    }
+}
+
+struct Rng {
+    pool: EntropyPool,
+    chacha: ChaCha20,
+    bytes_since_reseed: usize,
+    seeded: bool,
+}
+
+impl Rng {
+    /// Fold fresh entropy into the generator key
+    fn reseed(&mut self) {
+        // Always mix something that changed since the last reseed
+        self.pool.mix_u64(timer::read_counter());
+        self.pool.mix_u64(INTERRUPT_JITTER.load(Ordering::Relaxed));
+
+        // Pull from the host entropy device when we have one
+        let mut hw = [0u8; HARDWARE_SEED_BYTES];
+        let hw_len = unsafe {
+            match crate::kernel::RNG_DEVICE.as_mut() {
+                Some(device) => device.read(&mut hw).unwrap_or(0),
+                None => 0,
+            }
+        };
+        self.pool.mix(&hw[..hw_len]);
+        self.pool.stir();
+
+        // New key = old key XOR pool, so a weak reseed never makes things worse
+        for i in 0..8 {
+            self.chacha.key[i] ^= self.pool.state[i];
+        }
+        self.chacha.nonce = [self.pool.state[8], self.pool.state[9], self.pool.state[10]];
+        self.chacha.counter = 0;
+        self.chacha.rekey();
+
+        // Stir again so the pool no longer contains the key material we just used
+        self.pool.stir();
+        self.bytes_since_reseed = 0;
+        self.seeded = true;
+    }
+}
+
+static RNG: Mutex<Rng> = Mutex::new(Rng {
+    pool: EntropyPool::new(),
+    chacha: ChaCha20::new(),
+    bytes_since_reseed: 0,
+    seeded: false,
+});
+
+/// Measure timing jitter of a short busy loop. Each sample contributes its
+/// low bits, which depend on caches, host scheduling and interrupts.
+fn collect_timer_jitter(pool: &mut EntropyPool, samples: usize) {
+    let mut previous = timer::read_counter();
+    for i in 0..samples {
+        let mut acc = i as u64;
+        for j in 0..(16 + (previous & 0x3F)) {
+            acc = core::hint::black_box(acc.wrapping_mul(6364136223846793005).wrapping_add(j));
+        }
+        let now = timer::read_counter();
+        let delta = now.wrapping_sub(previous);
+        pool.mix(&[delta as u8, (delta >> 8) as u8, acc as u8]);
+        previous = now;
+    }
+}
+
+/// Seed the pool from the RTC and timer jitter. Call once the clocks are up;
+/// `reseed_from_hardware` adds virtio-rng output once PCI devices are probed.
+pub fn init() {
+    let mut rng = RNG.lock();
+    rng.pool.mix_u64(crate::kernel::clock::realtime_ns() as u64);
+    collect_timer_jitter(&mut rng.pool, 256);
+    rng.reseed();
+    drop(rng);
+
+    crate::kernel::uart_write_string("Random: entropy pool seeded from timer jitter\r\n");
+}
+
+/// Force a reseed now that the entropy device is available
+pub fn reseed_from_hardware() {
+    RNG.lock().reseed();
+    if unsafe { crate::kernel::RNG_DEVICE.is_some() } {
+        crate::kernel::uart_write_string("Random: reseeded from virtio-rng\r\n");
+    }
+}
+
+/// Called from the timer interrupt: fold the counter value at interrupt entry
+/// into a lock-free accumulator that the next reseed picks up
+pub fn add_interrupt_jitter() {
+    let sample = timer::read_counter();
+    let previous = INTERRUPT_JITTER.load(Ordering::Relaxed);
+    INTERRUPT_JITTER.store(previous.rotate_left(7) ^ sample, Ordering::Relaxed);
+}
+
+/// Mix caller-provided data into the pool (not credited, just stirred in)
+pub fn add_entropy(data: &[u8]) {
+    RNG.lock().pool.mix(data);
+}
+
+/// Fill `buf` with cryptographically secure random bytes
+pub fn getrandom(buf: &mut [u8]) {
+    let mut rng = RNG.lock();
+    if !rng.seeded || rng.bytes_since_reseed >= RESEED_INTERVAL {
+        rng.reseed();
+    }
+    rng.chacha.fill(buf);
+    rng.bytes_since_reseed += buf.len();
+}
+
+pub fn random_u16() -> u16 {
+    let mut bytes = [0u8; 2];
+    getrandom(&mut bytes);
+    u16::from_le_bytes(bytes)
+}
+
+pub fn random_u32() -> u32 {
+    let mut bytes = [0u8; 4];
+    getrandom(&mut bytes);
+    u32::from_le_bytes(bytes)
+}
+
+pub fn random_u64() -> u64 {
+    let mut bytes = [0u8; 8];
+    getrandom(&mut bytes);
+    u64::from_le_bytes(bytes)
+}
+
+/// Uniform random number in 0..bound (bound must be non-zero)
+pub fn random_range(bound: u32) -> u32 {
+    // Reject the top partial range so every value is equally likely
+    let zone = u32::MAX - (u32::MAX % bound);
+    loop {
+        let value = random_u32();
+        if value < zone {
+            return value % bound;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chacha20_block_rfc8439() {
+        // RFC 8439 section 2.3.2 test vector
+        let mut key = [0u32; 8];
+        for (i, word) in key.iter_mut().enumerate() {
+            let b = (i * 4) as u32;
+            *word = u32::from_le_bytes([b as u8, (b + 1) as u8, (b + 2) as u8, (b + 3) as u8]);
+        }
+        let nonce = [0x09000000, 0x4a000000, 0x00000000];
+        let block = chacha20_block(&key, 1, &nonce);
+        assert_eq!(block[0], 0xe4e7f110);
+        assert_eq!(block[1], 0x15593bd1);
+        assert_eq!(block[15], 0x4e3c50a2);
+    }
+
+    #[test]
+    fn test_fill_rekeys() {
+        let mut a = ChaCha20::new();
+        let mut first = [0u8; 32];
+        let mut second = [0u8; 32];
+        a.fill(&mut first);
+        a.fill(&mut second);
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_pool_mixing_changes_state() {
+        let mut pool = EntropyPool::new();
+        pool.mix(&[1u8; 64]);
+        let stirred = pool.state;
+        pool.mix(&[1u8; 64]);
+        assert_ne!(stirred, pool.state);
+        assert_ne!(stirred, [0x01010101; 16]);
+    }
+}
@@ -64,7 +64,6 @@ pub struct Thread {
     pub context: ThreadContext,
     pub state: ThreadState,
     pub stack: Box<[u8]>,
-    pub kernel_thread: bool, // true = kernel thread, false = user thread (future)
 }
 
 impl Thread {
@@ -85,7 +84,6 @@ impl Thread {
             context,
             state: ThreadState::Ready,
             stack,
-            kernel_thread: true,
         }
     }
 }
@@ -0,0 +1,114 @@
+// Passphrase prompts for encrypted volumes
+//
+// Encrypted volumes found at boot stay locked until someone types their
+// passphrase, but boot doesn't wait for it: each one gets a terminal window
+// asking, and the serial console asks as well. Whichever is answered first
+// unlocks the volume and mounts the filesystem inside; the other then has
+// nothing left to do. Serial input is polled from the main loop.
+
+use super::{uart_read_byte, uart_write_string};
+use crate::system::block::registry;
+use crate::system::fs::vfs;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Locked devices the serial console still has to ask about, current first
+static mut SERIAL_QUEUE: Vec<usize> = Vec::new();
+static mut SERIAL_INPUT: String = String::new();
+/// Whether the serial prompt for the current device has been printed
+static mut SERIAL_ASKED: bool = false;
+
+/// Unlock the encrypted volume on device `device` and mount what it holds.
+/// If boot found no root volume, this one becomes it. Returns the mount point.
+pub fn unlock(device: usize, passphrase: &str) -> Result<String, &'static str> {
+    let volume = registry::unlock(device, passphrase)?;
+    let name = vfs::mount(volume)?;
+    if registry::root_volume().is_none() {
+        registry::set_root_volume(volume);
+    }
+    crate::gui::widgets::file_explorer::refresh_all_explorers();
+    Ok(name)
+}
+
+/// Ask for the passphrases of the locked `devices`, in a terminal window each
+/// and one after another on the serial console
+pub fn prompt_at_boot(devices: Vec<usize>) {
+    use crate::gui::window_manager::{self, Window, WindowContent};
+
+    for &device in &devices {
+        let id = crate::gui::widgets::console::create_console();
+        crate::apps::shell::create_unlock_shell(id, device);
+        window_manager::add_window(Window::new(0, 0, 640, 480, "Terminal", WindowContent::Terminal, id));
+    }
+    // The serial prompt is printed from the main loop, after the boot messages
+    unsafe {
+        SERIAL_QUEUE = devices;
+    }
+}
+
+/// Print the serial prompt for the next device that is still locked
+fn ask_serial() {
+    unsafe {
+        SERIAL_QUEUE.retain(|&device| registry::is_locked(device));
+        if let Some(&device) = SERIAL_QUEUE.first() {
+            uart_write_string(&alloc::format!(
+                "Passphrase for {} (empty to skip): ",
+                registry::device_name(device).unwrap_or("?")
+            ));
+            SERIAL_ASKED = true;
+        }
+    }
+}
+
+/// Handle serial console input while a passphrase is wanted. Returns whether
+/// a volume was mounted, so the screen needs redrawing.
+pub fn poll_serial() -> bool {
+    let mut mounted = false;
+    unsafe {
+        if !SERIAL_ASKED {
+            ask_serial();
+        }
+        while let Some(&device) = SERIAL_QUEUE.first() {
+            let byte = match uart_read_byte() {
+                Some(byte) => byte,
+                None => break,
+            };
+            match byte {
+                b'\r' | b'\n' => {
+                    uart_write_string("\r\n");
+                    let passphrase = core::mem::take(&mut SERIAL_INPUT);
+                    if passphrase.is_empty() || !registry::is_locked(device) {
+                        SERIAL_QUEUE.remove(0);
+                    } else {
+                        match unlock(device, &passphrase) {
+                            Ok(name) => {
+                                uart_write_string(&alloc::format!("Mounted at /{}\r\n", name));
+                                SERIAL_QUEUE.remove(0);
+                                mounted = true;
+                            }
+                            // Ask again
+                            Err("Wrong passphrase") => uart_write_string("Wrong passphrase\r\n"),
+                            Err(e) => {
+                                uart_write_string(&alloc::format!("Unlock failed: {}\r\n", e));
+                                SERIAL_QUEUE.remove(0);
+                            }
+                        }
+                    }
+                    SERIAL_ASKED = false;
+                    ask_serial();
+                }
+                8 | 127 => {
+                    if SERIAL_INPUT.pop().is_some() {
+                        uart_write_string("\x08 \x08");
+                    }
+                }
+                0x20..=0x7e => {
+                    SERIAL_INPUT.push(byte as char);
+                    uart_write_string("*");
+                }
+                _ => {}
+            }
+        }
+    }
+    mounted
+}
@@ -0,0 +1,311 @@
+// AES-256 and XTS mode
+//
+// The block cipher (FIPS-197) for encrypted volumes, with the usual 32-bit
+// lookup tables built at compile time from the S-box. Table lookups depend on
+// the data, so this is not hardened against cache-timing attacks from code
+// running on the same machine.
+//
+// XTS (IEEE 1619) encrypts each disk block on its own with a tweak made from
+// the block number, so identical blocks at different places encrypt
+// differently and any block can be read or written without its neighbours.
+
+/// Multiply by x in GF(2^8)
+const fn xtime(b: u8) -> u8 {
+    (b << 1) ^ if b & 0x80 != 0 { 0x1b } else { 0 }
+}
+
+const fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        a = xtime(a);
+        b >>= 1;
+    }
+    product
+}
+
+const fn make_sbox() -> [u8; 256] {
+    let mut sbox = [0u8; 256];
+    let mut x = 0;
+    while x < 256 {
+        // Inverse as x^254, then the affine transform
+        let mut inverse = 1u8;
+        let mut i = 0;
+        while i < 254 {
+            inverse = gf_mul(inverse, x as u8);
+            i += 1;
+        }
+        if x == 0 {
+            inverse = 0;
+        }
+        sbox[x] = inverse
+            ^ inverse.rotate_left(1)
+            ^ inverse.rotate_left(2)
+            ^ inverse.rotate_left(3)
+            ^ inverse.rotate_left(4)
+            ^ 0x63;
+        x += 1;
+    }
+    sbox
+}
+
+const fn invert(sbox: &[u8; 256]) -> [u8; 256] {
+    let mut inverse = [0u8; 256];
+    let mut x = 0;
+    while x < 256 {
+        inverse[sbox[x] as usize] = x as u8;
+        x += 1;
+    }
+    inverse
+}
+
+/// One column of MixColumns (or its inverse) applied to each S-box output,
+/// as a big-endian word
+const fn make_table(sbox: &[u8; 256], coefficients: [u8; 4]) -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut x = 0;
+    while x < 256 {
+        let s = sbox[x];
+        table[x] = (gf_mul(s, coefficients[0]) as u32) << 24
+            | (gf_mul(s, coefficients[1]) as u32) << 16
+            | (gf_mul(s, coefficients[2]) as u32) << 8
+            | gf_mul(s, coefficients[3]) as u32;
+        x += 1;
+    }
+    table
+}
+
+static SBOX: [u8; 256] = make_sbox();
+static INV_SBOX: [u8; 256] = invert(&make_sbox());
+static TE: [u32; 256] = make_table(&make_sbox(), [2, 1, 1, 3]);
+static TD: [u32; 256] = make_table(&invert(&make_sbox()), [14, 9, 13, 11]);
+
+const ROUNDS: usize = 14;
+const ROUND_KEY_WORDS: usize = 4 * (ROUNDS + 1);
+
+fn sub_word(word: u32) -> u32 {
+    u32::from_be_bytes(word.to_be_bytes().map(|b| SBOX[b as usize]))
+}
+
+/// Look up the four bytes `a`, `b`, `c`, `d` (taken from the top byte down)
+/// in `table`, rotated to their column
+fn round_column(table: &[u32; 256], a: u32, b: u32, c: u32, d: u32) -> u32 {
+    table[(a >> 24) as usize]
+        ^ table[((b >> 16) & 0xff) as usize].rotate_right(8)
+        ^ table[((c >> 8) & 0xff) as usize].rotate_right(16)
+        ^ table[(d & 0xff) as usize].rotate_right(24)
+}
+
+fn final_column(sbox: &[u8; 256], a: u32, b: u32, c: u32, d: u32) -> u32 {
+    (sbox[(a >> 24) as usize] as u32) << 24
+        | (sbox[((b >> 16) & 0xff) as usize] as u32) << 16
+        | (sbox[((c >> 8) & 0xff) as usize] as u32) << 8
+        | sbox[(d & 0xff) as usize] as u32
+}
+
+fn load(block: &[u8]) -> [u32; 4] {
+    core::array::from_fn(|i| u32::from_be_bytes([block[4 * i], block[4 * i + 1], block[4 * i + 2], block[4 * i + 3]]))
+}
+
+fn store(state: [u32; 4], block: &mut [u8]) {
+    for (i, word) in state.iter().enumerate() {
+        block[4 * i..4 * i + 4].copy_from_slice(&word.to_be_bytes());
+    }
+}
+
+/// Overwrite key material with zeros. Volatile writes, because the compiler
+/// may drop ordinary stores to memory that is about to be freed.
+pub fn zeroize<T: Copy + Default>(values: &mut [T]) {
+    for value in values.iter_mut() {
+        unsafe { core::ptr::write_volatile(value, T::default()) };
+    }
+    core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+}
+
+/// An AES-256 key expanded for encryption and decryption. The round keys
+/// are wiped when it is dropped.
+pub struct Aes256 {
+    encrypt_keys: [u32; ROUND_KEY_WORDS],
+    /// Round keys in reverse order with InvMixColumns applied, for the
+    /// equivalent inverse cipher
+    decrypt_keys: [u32; ROUND_KEY_WORDS],
+}
+
+impl Aes256 {
+    pub fn new(key: &[u8; 32]) -> Self {
+        let mut w = [0u32; ROUND_KEY_WORDS];
+        for i in 0..8 {
+            w[i] = u32::from_be_bytes([key[4 * i], key[4 * i + 1], key[4 * i + 2], key[4 * i + 3]]);
+        }
+        let mut rcon = 1u8;
+        for i in 8..ROUND_KEY_WORDS {
+            let mut temp = w[i - 1];
+            if i % 8 == 0 {
+                temp = sub_word(temp.rotate_left(8)) ^ (rcon as u32) << 24;
+                rcon = xtime(rcon);
+            } else if i % 8 == 4 {
+                temp = sub_word(temp);
+            }
+            w[i] = w[i - 8] ^ temp;
+        }
+
+        let mut d = [0u32; ROUND_KEY_WORDS];
+        for round in 0..=ROUNDS {
+            for column in 0..4 {
+                let word = w[4 * (ROUNDS - round) + column];
+                d[4 * round + column] = if round == 0 || round == ROUNDS {
+                    word
+                } else {
+                    // TD includes the inverse S-box, which SBOX cancels out
+                    let s = sub_word(word);
+                    round_column(&TD, s, s, s, s)
+                };
+            }
+        }
+        Aes256 { encrypt_keys: w, decrypt_keys: d }
+    }
+
+    /// Encrypt one 16-byte block in place
+    pub fn encrypt_block(&self, block: &mut [u8]) {
+        let k = &self.encrypt_keys;
+        let mut s = load(block);
+        for (i, word) in s.iter_mut().enumerate() {
+            *word ^= k[i];
+        }
+        for round in 1..ROUNDS {
+            let r = &k[4 * round..4 * round + 4];
+            s = [
+                round_column(&TE, s[0], s[1], s[2], s[3]) ^ r[0],
+                round_column(&TE, s[1], s[2], s[3], s[0]) ^ r[1],
+                round_column(&TE, s[2], s[3], s[0], s[1]) ^ r[2],
+                round_column(&TE, s[3], s[0], s[1], s[2]) ^ r[3],
+            ];
+        }
+        let r = &k[4 * ROUNDS..];
+        s = [
+            final_column(&SBOX, s[0], s[1], s[2], s[3]) ^ r[0],
+            final_column(&SBOX, s[1], s[2], s[3], s[0]) ^ r[1],
+            final_column(&SBOX, s[2], s[3], s[0], s[1]) ^ r[2],
+            final_column(&SBOX, s[3], s[0], s[1], s[2]) ^ r[3],
+        ];
+        store(s, block);
+    }
+
+    /// Decrypt one 16-byte block in place
+    pub fn decrypt_block(&self, block: &mut [u8]) {
+        let k = &self.decrypt_keys;
+        let mut s = load(block);
+        for (i, word) in s.iter_mut().enumerate() {
+            *word ^= k[i];
+        }
+        for round in 1..ROUNDS {
+            let r = &k[4 * round..4 * round + 4];
+            s = [
+                round_column(&TD, s[0], s[3], s[2], s[1]) ^ r[0],
+                round_column(&TD, s[1], s[0], s[3], s[2]) ^ r[1],
+                round_column(&TD, s[2], s[1], s[0], s[3]) ^ r[2],
+                round_column(&TD, s[3], s[2], s[1], s[0]) ^ r[3],
+            ];
+        }
+        let r = &k[4 * ROUNDS..];
+        s = [
+            final_column(&INV_SBOX, s[0], s[3], s[2], s[1]) ^ r[0],
+            final_column(&INV_SBOX, s[1], s[0], s[3], s[2]) ^ r[1],
+            final_column(&INV_SBOX, s[2], s[1], s[0], s[3]) ^ r[2],
+            final_column(&INV_SBOX, s[3], s[2], s[1], s[0]) ^ r[3],
+        ];
+        store(s, block);
+    }
+}
+
+impl Drop for Aes256 {
+    fn drop(&mut self) {
+        zeroize(&mut self.encrypt_keys);
+        zeroize(&mut self.decrypt_keys);
+    }
+}
+
+/// Size of the blocks XTS works on
+pub const AES_BLOCK: usize = 16;
+
+/// XTS-AES-256: a data key and a tweak key, 64 bytes in all
+pub struct Xts {
+    data: Aes256,
+    tweak: Aes256,
+}
+
+impl Xts {
+    pub fn new(key: &[u8; 64]) -> Self {
+        let (data, tweak) = key.split_at(32);
+        Xts { data: Aes256::new(data.try_into().unwrap()), tweak: Aes256::new(tweak.try_into().unwrap()) }
+    }
+
+    /// Encrypt one data unit in place. `data` must be a whole number of AES
+    /// blocks; `unit` is its number, usually the disk block.
+    pub fn encrypt(&self, unit: u64, data: &mut [u8]) {
+        self.process(unit, data, |block| self.data.encrypt_block(block));
+    }
+
+    pub fn decrypt(&self, unit: u64, data: &mut [u8]) {
+        self.process(unit, data, |block| self.data.decrypt_block(block));
+    }
+
+    fn process(&self, unit: u64, data: &mut [u8], cipher: impl Fn(&mut [u8])) {
+        debug_assert!(data.len() % AES_BLOCK == 0);
+        let mut tweak = [0u8; AES_BLOCK];
+        tweak[..8].copy_from_slice(&unit.to_le_bytes());
+        self.tweak.encrypt_block(&mut tweak);
+
+        for block in data.chunks_exact_mut(AES_BLOCK) {
+            for (b, t) in block.iter_mut().zip(&tweak) {
+                *b ^= t;
+            }
+            cipher(block);
+            for (b, t) in block.iter_mut().zip(&tweak) {
+                *b ^= t;
+            }
+            // Next tweak: multiply by x in GF(2^128), little-endian
+            let carry = tweak[15] >> 7;
+            for i in (1..AES_BLOCK).rev() {
+                tweak[i] = tweak[i] << 1 | tweak[i - 1] >> 7;
+            }
+            tweak[0] = tweak[0] << 1 ^ if carry != 0 { 0x87 } else { 0 };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> alloc::string::String {
+        bytes.iter().map(|b| alloc::format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn test_aes256_block() {
+        // FIPS-197 appendix C.3
+        let key: [u8; 32] = core::array::from_fn(|i| i as u8);
+        let aes = Aes256::new(&key);
+        let mut block = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff];
+        aes.encrypt_block(&mut block);
+        assert_eq!(hex(&block), "8ea2b7ca516745bfeafc49904b496089");
+        aes.decrypt_block(&mut block);
+        assert_eq!(hex(&block), "00112233445566778899aabbccddeeff");
+    }
+
+    #[test]
+    fn test_xts_sector() {
+        let key: [u8; 64] = core::array::from_fn(|i| (i * 7 + 3) as u8);
+        let plain: [u8; 512] = core::array::from_fn(|i| (i * 13) as u8);
+        let xts = Xts::new(&key);
+        let mut data = plain;
+        xts.encrypt(1234567, &mut data);
+        assert_eq!(hex(&data[..32]), "69713f04e7970d6c0cf3ed29a7188843be91cdd50095a0014f2f42523251156f");
+        assert_eq!(hex(&data[496..]), "14551e7850b19aaa02056f58e8bc2455");
+        xts.decrypt(1234567, &mut data);
+        assert_eq!(data, plain);
+    }
+}
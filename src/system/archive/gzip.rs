@@ -0,0 +1,152 @@
+// gzip container (RFC 1952)
+//
+// A header with an optional original file name, a deflate stream, then the
+// CRC-32 and length of the data. Files made by concatenating several gzip
+// members decompress to the members' data joined together, as with gunzip.
+
+use super::inflate::inflate;
+use crate::system::crc32;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const MAGIC: [u8; 2] = [0x1f, 0x8b];
+const METHOD_DEFLATE: u8 = 8;
+
+// Header flags
+const FLAG_HCRC: u8 = 0x02;
+const FLAG_EXTRA: u8 = 0x04;
+const FLAG_NAME: u8 = 0x08;
+const FLAG_COMMENT: u8 = 0x10;
+const FLAG_RESERVED: u8 = 0xE0;
+
+pub struct Decompressed {
+    pub data: Vec<u8>,
+    /// File name stored by the compressor, if any
+    pub name: Option<String>,
+    /// Modification time of the original file (Unix seconds, 0 if unknown)
+    pub modified: i64,
+}
+
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// Decompress a gzip file, failing if the result would pass `limit` bytes
+pub fn decompress(data: &[u8], limit: usize) -> Result<Decompressed, &'static str> {
+    let mut result = Decompressed { data: Vec::new(), name: None, modified: 0 };
+    let mut pos = 0;
+    while pos < data.len() {
+        let member = &data[pos..];
+        // Some tools pad the file with zeros after the last member
+        if pos > 0 && !is_gzip(member) && member.iter().all(|&b| b == 0) {
+            break;
+        }
+
+        let (header_len, name, modified) = header(member)?;
+        let (out, used) = inflate(&member[header_len..], limit - result.data.len())?;
+        let trailer = member.get(header_len + used..header_len + used + 8).ok_or("gzip data is truncated")?;
+        let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+        if crc != crc32::update(0, &out) {
+            return Err("gzip CRC mismatch");
+        }
+        if size != out.len() as u32 {
+            return Err("gzip length mismatch");
+        }
+
+        if pos == 0 {
+            result.name = name;
+            result.modified = modified;
+        }
+        result.data.extend_from_slice(&out);
+        pos += header_len + used + 8;
+    }
+    Ok(result)
+}
+
+/// Parse a member header, returning its length, the stored name and the time
+fn header(data: &[u8]) -> Result<(usize, Option<String>, i64), &'static str> {
+    if data.len() < 10 || !is_gzip(data) {
+        return Err("Not a gzip file");
+    }
+    if data[2] != METHOD_DEFLATE {
+        return Err("Unsupported gzip compression method");
+    }
+    let flags = data[3];
+    if flags & FLAG_RESERVED != 0 {
+        return Err("Unsupported gzip flags");
+    }
+    let modified = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as i64;
+
+    let mut pos = 10;
+    if flags & FLAG_EXTRA != 0 {
+        let len = data.get(pos..pos + 2).ok_or("gzip header is truncated")?;
+        pos += 2 + u16::from_le_bytes([len[0], len[1]]) as usize;
+    }
+    let mut name = None;
+    if flags & FLAG_NAME != 0 {
+        let (text, len) = zero_terminated(data, pos)?;
+        // Latin-1, which maps straight onto the first 256 code points
+        name = Some(text.iter().map(|&b| b as char).collect());
+        pos += len;
+    }
+    if flags & FLAG_COMMENT != 0 {
+        pos += zero_terminated(data, pos)?.1;
+    }
+    if flags & FLAG_HCRC != 0 {
+        pos += 2;
+    }
+    if pos > data.len() {
+        return Err("gzip header is truncated");
+    }
+    Ok((pos, name, modified))
+}
+
+/// The string at `pos` and how many bytes it takes with its terminator
+fn zero_terminated(data: &[u8], pos: usize) -> Result<(&[u8], usize), &'static str> {
+    let rest = data.get(pos..).ok_or("gzip header is truncated")?;
+    let end = rest.iter().position(|&b| b == 0).ok_or("gzip header is truncated")?;
+    Ok((&rest[..end], end + 1))
+}
+
+/// Name for the decompressed copy of `name`: a.txt.gz -> a.txt, b.tgz -> b.tar
+pub fn uncompressed_name(name: &str) -> Option<String> {
+    let lower = name.to_ascii_lowercase();
+    if lower.ends_with(".tgz") {
+        return Some(alloc::format!("{}.tar", &name[..name.len() - 4]));
+    }
+    if lower.ends_with(".gz") && name.len() > 3 {
+        return Some(String::from(&name[..name.len() - 3]));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decompress_members() {
+        // "hello\n" compressed by gzip with the name "hello.txt"
+        let member = [
+            0x1f, 0x8b, 0x08, 0x08, 0x00, 0xf1, 0x53, 0x65, 0x02, 0xff, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x2e, 0x74, 0x78,
+            0x74, 0x00, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0xe7, 0x02, 0x00, 0x20, 0x30, 0x3a, 0x36, 0x06, 0x00, 0x00, 0x00,
+        ];
+        let result = decompress(&member, 100).unwrap();
+        assert_eq!(result.data, b"hello\n");
+        assert_eq!(result.name.as_deref(), Some("hello.txt"));
+        assert_eq!(result.modified, 1_700_000_000);
+
+        let mut twice = member.to_vec();
+        twice.extend_from_slice(&member);
+        assert_eq!(decompress(&twice, 100).unwrap().data, b"hello\nhello\n");
+
+        let mut corrupt = member;
+        corrupt[28] ^= 1;
+        assert!(decompress(&corrupt, 100).is_err());
+
+        assert_eq!(uncompressed_name("site.tgz").as_deref(), Some("site.tar"));
+        assert_eq!(uncompressed_name("a.txt.GZ").as_deref(), Some("a.txt"));
+        assert_eq!(uncompressed_name("a.txt"), None);
+    }
+}
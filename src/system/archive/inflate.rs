@@ -0,0 +1,280 @@
+// DEFLATE decompression (RFC 1951)
+//
+// Decodes a whole stream into memory. Huffman codes are decoded a bit at a
+// time from the canonical code counts, which keeps the tables small and the
+// code short at some cost in speed.
+
+use alloc::vec::Vec;
+
+const MAX_BITS: usize = 15;
+const MAX_LITLEN_CODES: usize = 288;
+const MAX_DIST_CODES: usize = 30;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097,
+    6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/// Order the code length code lengths are sent in
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// Decompress the deflate stream at the start of `data`, stopping with an
+/// error if the output would pass `limit` bytes. Returns the output and how
+/// many bytes of `data` the stream took up.
+pub fn inflate(data: &[u8], limit: usize) -> Result<(Vec<u8>, usize), &'static str> {
+    let mut bits = Bits { data, pos: 0, buffer: 0, count: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = bits.take(1)? == 1;
+        match bits.take(2)? {
+            0 => stored(&mut bits, &mut out, limit)?,
+            1 => {
+                let (litlen, dist) = fixed_codes();
+                codes(&mut bits, &mut out, limit, &litlen, &dist)?;
+            }
+            2 => {
+                let (litlen, dist) = dynamic_codes(&mut bits)?;
+                codes(&mut bits, &mut out, limit, &litlen, &dist)?;
+            }
+            _ => return Err("Invalid deflate block type"),
+        }
+        if last {
+            // Whatever is left in the bit buffer is padding in the last byte
+            return Ok((out, bits.pos));
+        }
+    }
+}
+
+/// LSB-first bit reader
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl Bits<'_> {
+    fn take(&mut self, n: u32) -> Result<u32, &'static str> {
+        while self.count < n {
+            let byte = *self.data.get(self.pos).ok_or("Deflate stream is truncated")?;
+            self.pos += 1;
+            self.buffer |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1u32 << n) - 1);
+        self.buffer >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    /// Drop the bits left in the current byte
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+/// Canonical Huffman code: how many codes there are of each length, and the
+/// symbols in code order
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: [u16; MAX_LITLEN_CODES],
+}
+
+impl Huffman {
+    /// Build from a code length per symbol (0 = unused). Incomplete codes are
+    /// allowed, as the format permits for a single distance code; reading an
+    /// unassigned code is then an error.
+    fn new(lengths: &[u8]) -> Result<Self, &'static str> {
+        let mut code = Huffman { counts: [0; MAX_BITS + 1], symbols: [0; MAX_LITLEN_CODES] };
+        for &len in lengths {
+            code.counts[len as usize] += 1;
+        }
+
+        let mut left: i32 = 1;
+        for len in 1..=MAX_BITS {
+            left = (left << 1) - code.counts[len] as i32;
+            if left < 0 {
+                return Err("Invalid Huffman code lengths");
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 1];
+        for len in 1..MAX_BITS {
+            offsets[len + 1] = offsets[len] + code.counts[len];
+        }
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                code.symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Ok(code)
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16, &'static str> {
+        let mut code: i32 = 0; // Bits read so far
+        let mut first: i32 = 0; // First code of the current length
+        let mut index: i32 = 0; // Where that code's symbols start
+        for len in 1..=MAX_BITS {
+            code |= bits.take(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("Invalid Huffman code")
+    }
+}
+
+fn stored(bits: &mut Bits, out: &mut Vec<u8>, limit: usize) -> Result<(), &'static str> {
+    bits.align();
+    let header = bits.data.get(bits.pos..bits.pos + 4).ok_or("Deflate stream is truncated")?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    let nlen = u16::from_le_bytes([header[2], header[3]]);
+    if len != !nlen {
+        return Err("Corrupt stored block length");
+    }
+    let start = bits.pos + 4;
+    let block = bits.data.get(start..start + len as usize).ok_or("Deflate stream is truncated")?;
+    if out.len() + block.len() > limit {
+        return Err("Decompressed data too large");
+    }
+    out.extend_from_slice(block);
+    bits.pos = start + len as usize;
+    Ok(())
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; MAX_LITLEN_CODES];
+    for (symbol, len) in lengths.iter_mut().enumerate() {
+        *len = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    // The fixed lengths are a complete code, so building can't fail
+    let litlen = Huffman::new(&lengths).unwrap();
+    let dist = Huffman::new(&[5; MAX_DIST_CODES]).unwrap();
+    (litlen, dist)
+}
+
+fn dynamic_codes(bits: &mut Bits) -> Result<(Huffman, Huffman), &'static str> {
+    let litlen_count = bits.take(5)? as usize + 257;
+    let dist_count = bits.take(5)? as usize + 1;
+    let length_count = bits.take(4)? as usize + 4;
+    if litlen_count > 286 || dist_count > MAX_DIST_CODES {
+        return Err("Too many Huffman codes");
+    }
+
+    let mut length_lengths = [0u8; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..length_count] {
+        length_lengths[symbol] = bits.take(3)? as u8;
+    }
+    let length_code = Huffman::new(&length_lengths)?;
+
+    // Literal/length and distance code lengths, run-length coded as one list
+    let mut lengths = [0u8; MAX_LITLEN_CODES + MAX_DIST_CODES];
+    let total = litlen_count + dist_count;
+    let mut i = 0;
+    while i < total {
+        let symbol = length_code.decode(bits)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *i.checked_sub(1).map(|p| &lengths[p]).ok_or("Repeat with no previous length")?;
+                (previous, 3 + bits.take(2)? as usize)
+            }
+            17 => (0, 3 + bits.take(3)? as usize),
+            _ => (0, 11 + bits.take(7)? as usize),
+        };
+        if i + repeat > total {
+            return Err("Too many code lengths");
+        }
+        lengths[i..i + repeat].fill(value);
+        i += repeat;
+    }
+    if lengths[256] == 0 {
+        return Err("No end-of-block code");
+    }
+
+    Ok((Huffman::new(&lengths[..litlen_count])?, Huffman::new(&lengths[litlen_count..total])?))
+}
+
+fn codes(bits: &mut Bits, out: &mut Vec<u8>, limit: usize, litlen: &Huffman, dist: &Huffman) -> Result<(), &'static str> {
+    loop {
+        let symbol = litlen.decode(bits)? as usize;
+        if symbol < 256 {
+            if out.len() >= limit {
+                return Err("Decompressed data too large");
+            }
+            out.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+
+        let symbol = symbol - 257;
+        if symbol >= LENGTH_BASE.len() {
+            return Err("Invalid length code");
+        }
+        let len = LENGTH_BASE[symbol] as usize + bits.take(LENGTH_EXTRA[symbol] as u32)? as usize;
+        let symbol = dist.decode(bits)? as usize;
+        if symbol >= DIST_BASE.len() {
+            return Err("Invalid distance code");
+        }
+        let distance = DIST_BASE[symbol] as usize + bits.take(DIST_EXTRA[symbol] as u32)? as usize;
+        if distance > out.len() {
+            return Err("Distance too far back");
+        }
+        if out.len() + len > limit {
+            return Err("Decompressed data too large");
+        }
+        // The copy may overlap what it is producing, so go a byte at a time
+        let start = out.len() - distance;
+        for i in 0..len {
+            let byte = out[start + i];
+            out.push(byte);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inflate_block_types() {
+        // Stored block
+        let stored = [0x01, 0x05, 0x00, 0xfa, 0xff, b'h', b'e', b'l', b'l', b'o'];
+        assert_eq!(inflate(&stored, 100).unwrap(), (b"hello".to_vec(), 10));
+
+        // Fixed codes with back-references: "abcabcabcabc"
+        let fixed = [0x4b, 0x4c, 0x4a, 0x4e, 0x84, 0x21, 0x00];
+        assert_eq!(inflate(&fixed, 100).unwrap().0, b"abcabcabcabc");
+
+        // Dynamic codes, followed by bytes that aren't part of the stream
+        let dynamic = [
+            0x25, 0x8a, 0xc1, 0x11, 0x00, 0x00, 0x0c, 0xc1, 0x66, 0x45, 0xf7, 0x9f, 0xa1, 0xd2, 0x7a, 0x90, 0xcb,
+            0x91, 0x93, 0xa8, 0x69, 0x59, 0x47, 0x03, 0x56, 0x9a, 0x69, 0x0f, 0xd2, 0x2c, 0xe6, 0x5f, 0x46, 0x2d,
+            0xff, 0xff,
+        ];
+        let (text, used) = inflate(&dynamic, 100).unwrap();
+        assert_eq!(text, b"abcccaaaacaabacaaaadcaabccabaabcabadaaaabbadabaababacaabaaab");
+        assert_eq!(used, dynamic.len() - 2);
+
+        assert!(inflate(&fixed[..3], 100).is_err());
+        assert!(inflate(&fixed, 5).is_err());
+    }
+}
@@ -0,0 +1,57 @@
+// Archive and compression formats
+//
+// Pure data transformations with no kernel dependencies: inflate decodes
+// deflate streams (for gzip here, and anything else that carries them, like
+// HTTP content encoding), gzip wraps it, tar reads and writes archives and
+// zip reads them. The shell's tar/gunzip/unzip commands and the vfs archive
+// browsing sit on top.
+
+pub mod gzip;
+pub mod inflate;
+pub mod tar;
+pub mod zip;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Largest output decompressed into memory at once
+pub const MAX_DECOMPRESSED: usize = 64 * 1024 * 1024;
+
+const EXTENSIONS: [&str; 4] = [".tar.gz", ".tgz", ".tar", ".zip"];
+
+/// Whether a file name marks an archive (.tar, .tar.gz, .tgz or .zip)
+pub fn is_archive_name(name: &str) -> bool {
+    archive_stem(name).is_some()
+}
+
+/// Name without its archive extension, as the folder to extract it into:
+/// site.tar.gz -> site
+pub fn archive_stem(name: &str) -> Option<&str> {
+    let lower = name.to_ascii_lowercase();
+    EXTENSIONS
+        .iter()
+        .find(|ext| lower.len() > ext.len() && lower.ends_with(*ext))
+        .map(|ext| &name[..name.len() - ext.len()])
+}
+
+/// Path with "." and empty components dropped, or None if it climbs out
+/// of the extraction directory with ".."
+pub fn clean_path(path: &str) -> Option<String> {
+    let mut parts = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => return None,
+            _ => parts.push(part),
+        }
+    }
+    Some(parts.join("/"))
+}
+
+/// Parse a tar archive, gunzipping it first if it is compressed. Returns the
+/// tar data, which the entries' offsets point into, with the entries.
+pub fn unpack_tar(data: Vec<u8>) -> Result<(Vec<u8>, tar::Archive), &'static str> {
+    let data = if gzip::is_gzip(&data) { gzip::decompress(&data, MAX_DECOMPRESSED)?.data } else { data };
+    let archive = tar::parse(&data)?;
+    Ok((data, archive))
+}
@@ -0,0 +1,283 @@
+// tar archives (POSIX ustar)
+//
+// A run of 512-byte headers, each followed by the entry's data padded to a
+// whole block, ending with two zero blocks. Names longer than the 100-byte
+// name field are read from the ustar prefix field, a GNU long name record or
+// a pax "path" record, and written with whichever of the first two fits.
+// Only files and directories are kept; links and device nodes are skipped.
+
+use super::clean_path;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+pub const BLOCK_SIZE: usize = 512;
+
+// Entry types
+const TYPE_FILE: u8 = b'0';
+const TYPE_OLD_FILE: u8 = 0;
+const TYPE_CONTIGUOUS: u8 = b'7';
+const TYPE_DIR: u8 = b'5';
+const TYPE_GNU_LONG_NAME: u8 = b'L';
+const TYPE_PAX: u8 = b'x';
+const TYPE_PAX_GLOBAL: u8 = b'g';
+
+const MAGIC: &[u8] = b"ustar";
+const NAME_LEN: usize = 100;
+const PREFIX_LEN: usize = 155;
+
+#[derive(Clone, Debug)]
+pub struct Entry {
+    /// Path inside the archive, without a leading "./" or a trailing '/'
+    pub path: String,
+    pub is_dir: bool,
+    pub size: usize,
+    /// Unix permission bits
+    pub mode: u32,
+    /// Unix seconds
+    pub modified: i64,
+    /// Where the entry's data starts in the archive
+    pub offset: usize,
+}
+
+pub struct Archive {
+    pub entries: Vec<Entry>,
+    /// Links, devices and entries with unsafe paths ("../x") that were left out
+    pub skipped: usize,
+}
+
+/// Read the table of contents of a tar archive
+pub fn parse(data: &[u8]) -> Result<Archive, &'static str> {
+    let mut archive = Archive { entries: Vec::new(), skipped: 0 };
+    let mut long_name: Option<String> = None;
+    let mut pos = 0;
+    while pos + BLOCK_SIZE <= data.len() {
+        let header = &data[pos..pos + BLOCK_SIZE];
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        if number(&header[148..156])? != checksum(header) as u64 {
+            return Err("Bad tar header checksum");
+        }
+
+        let size = number(&header[124..136])? as usize;
+        let start = pos + BLOCK_SIZE;
+        let end = start.checked_add(size).filter(|&end| end <= data.len()).ok_or("Tar entry runs past the end")?;
+        pos = start + size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+
+        let kind = header[156];
+        match kind {
+            TYPE_GNU_LONG_NAME => {
+                long_name = Some(text(&data[start..end]));
+                continue;
+            }
+            TYPE_PAX => {
+                if let Some(path) = pax_path(&data[start..end]) {
+                    long_name = Some(path);
+                }
+                continue;
+            }
+            TYPE_PAX_GLOBAL => continue,
+            _ => {}
+        }
+
+        let name = long_name.take().unwrap_or_else(|| header_name(header));
+        // Old archives mark directories only with a trailing slash
+        let is_dir = kind == TYPE_DIR || (kind == TYPE_OLD_FILE && name.ends_with('/'));
+        if !is_dir && !matches!(kind, TYPE_FILE | TYPE_OLD_FILE | TYPE_CONTIGUOUS) {
+            archive.skipped += 1;
+            continue;
+        }
+        match clean_path(&name) {
+            // "./" itself, as archives made with `tar -C dir .` start with
+            Some(path) if path.is_empty() => {}
+            Some(path) => archive.entries.push(Entry {
+                path,
+                is_dir,
+                size: if is_dir { 0 } else { size },
+                mode: number(&header[100..108])? as u32,
+                modified: number(&header[136..148])? as i64,
+                offset: start,
+            }),
+            None => archive.skipped += 1,
+        }
+    }
+    Ok(archive)
+}
+
+/// Name from the name and (for ustar) prefix fields
+fn header_name(header: &[u8]) -> String {
+    let name = text(&header[0..NAME_LEN]);
+    if &header[257..262] != MAGIC {
+        return name;
+    }
+    let prefix = text(&header[345..345 + PREFIX_LEN]);
+    if prefix.is_empty() { name } else { alloc::format!("{}/{}", prefix, name) }
+}
+
+/// Bytes up to the first NUL as text
+fn text(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// A numeric field: octal text, or big-endian binary when the top bit is set
+fn number(field: &[u8]) -> Result<u64, &'static str> {
+    if field[0] & 0x80 != 0 {
+        return Ok(field[1..].iter().fold((field[0] & 0x7F) as u64, |n, &b| (n << 8) | b as u64));
+    }
+    let mut value: u64 = 0;
+    for &b in field.iter().skip_while(|&&b| b == b' ') {
+        match b {
+            b'0'..=b'7' => value = value.checked_mul(8).ok_or("Bad number in tar header")? + (b - b'0') as u64,
+            0 | b' ' => break,
+            _ => return Err("Bad number in tar header"),
+        }
+    }
+    Ok(value)
+}
+
+/// Sum of the header bytes, counting the checksum field as spaces
+fn checksum(header: &[u8]) -> u32 {
+    header.iter().enumerate().map(|(i, &b)| if (148..156).contains(&i) { b' ' as u32 } else { b as u32 }).sum()
+}
+
+/// The "path" value from pax extended header records ("<len> path=<value>\n")
+fn pax_path(mut records: &[u8]) -> Option<String> {
+    let mut path = None;
+    while let Some(space) = records.iter().position(|&b| b == b' ') {
+        let len: usize = core::str::from_utf8(&records[..space]).ok()?.parse().ok()?;
+        if len <= space || len > records.len() {
+            break;
+        }
+        let record = &records[space + 1..len];
+        let record = record.strip_suffix(b"\n").unwrap_or(record);
+        if let Some(value) = record.strip_prefix(b"path=") {
+            path = Some(String::from_utf8_lossy(value).into_owned());
+        }
+        records = &records[len..];
+    }
+    path
+}
+
+/// Writes a tar archive in memory
+pub struct Builder {
+    data: Vec<u8>,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Builder { data: Vec::new() }
+    }
+
+    pub fn add_dir(&mut self, path: &str, modified: i64) {
+        let path = alloc::format!("{}/", path.trim_end_matches('/'));
+        self.add_header(&path, TYPE_DIR, 0o755, 0, modified);
+    }
+
+    pub fn add_file(&mut self, path: &str, data: &[u8], modified: i64) {
+        self.add_header(path, TYPE_FILE, 0o644, data.len(), modified);
+        self.add_data(data);
+    }
+
+    /// The archive, with its end-of-archive marker
+    pub fn finish(mut self) -> Vec<u8> {
+        self.data.resize(self.data.len() + 2 * BLOCK_SIZE, 0);
+        self.data
+    }
+
+    fn add_header(&mut self, path: &str, kind: u8, mode: u32, size: usize, modified: i64) {
+        let mut header = [0u8; BLOCK_SIZE];
+        let path = path.as_bytes();
+        if path.len() <= NAME_LEN {
+            header[..path.len()].copy_from_slice(path);
+        } else if let Some(split) = prefix_split(path) {
+            header[..path.len() - split - 1].copy_from_slice(&path[split + 1..]);
+            header[345..345 + split].copy_from_slice(&path[..split]);
+        } else {
+            // Too long for ustar: a GNU long name record first, with the
+            // name field truncated
+            let mut name = path.to_vec();
+            name.push(0);
+            self.add_header("././@LongLink", TYPE_GNU_LONG_NAME, 0, name.len(), 0);
+            self.add_data(&name);
+            header[..NAME_LEN].copy_from_slice(&path[..NAME_LEN]);
+        }
+
+        write_octal(&mut header[100..108], mode as u64);
+        write_octal(&mut header[108..116], 0); // uid
+        write_octal(&mut header[116..124], 0); // gid
+        write_octal(&mut header[124..136], size as u64);
+        write_octal(&mut header[136..148], modified.max(0) as u64);
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        let sum = checksum(&header);
+        write_octal(&mut header[148..155], sum as u64);
+        header[155] = b' ';
+        self.data.extend_from_slice(&header);
+    }
+
+    fn add_data(&mut self, data: &[u8]) {
+        self.data.extend_from_slice(data);
+        self.data.resize(self.data.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, 0);
+    }
+}
+
+/// Where to split a long path between the prefix and name fields: the
+/// slash that leaves the longest prefix that fits
+fn prefix_split(path: &[u8]) -> Option<usize> {
+    (1..=core::cmp::min(PREFIX_LEN, path.len() - 1))
+        .rev()
+        .find(|&i| path[i] == b'/' && path.len() - i - 1 <= NAME_LEN && path.len() - i > 1)
+}
+
+/// Zero-padded octal filling all but the last byte of `field`, which is NUL
+fn write_octal(field: &mut [u8], mut value: u64) {
+    let digits = field.len() - 1;
+    for i in (0..digits).rev() {
+        field[i] = b'0' + (value & 7) as u8;
+        value >>= 3;
+    }
+    field[digits] = 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_and_parse() {
+        let deep = "site/assets/fonts/a-rather-long-directory-name-for-testing/and-another-one-below-it";
+        let long_file = alloc::format!("{}/{}.ttf", deep, "f".repeat(60));
+        let very_long = alloc::format!("{}/{}", "d".repeat(200), "g".repeat(120));
+
+        let mut builder = Builder::new();
+        builder.add_dir("site", 1_700_000_000);
+        builder.add_file("site/index.html", b"<p>hi</p>", 1_700_000_100);
+        builder.add_file(&long_file, b"font", 0);
+        builder.add_file(&very_long, b"long", 0);
+        let data = builder.finish();
+        assert_eq!(data.len() % BLOCK_SIZE, 0);
+
+        let archive = parse(&data).unwrap();
+        let paths: Vec<&str> = archive.entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["site", "site/index.html", long_file.as_str(), very_long.as_str()]);
+        let index = &archive.entries[1];
+        assert!(archive.entries[0].is_dir && !index.is_dir);
+        assert_eq!(&data[index.offset..index.offset + index.size], b"<p>hi</p>");
+        assert_eq!((index.mode, index.modified), (0o644, 1_700_000_100));
+        assert_eq!(&data[archive.entries[3].offset..][..4], b"long");
+
+        // Paths that climb out are skipped, "./" prefixes are dropped
+        let mut builder = Builder::new();
+        builder.add_file("../evil", b"x", 0);
+        builder.add_file("./ok", b"y", 0);
+        let archive = parse(&builder.finish()).unwrap();
+        assert_eq!(archive.skipped, 1);
+        assert_eq!(archive.entries[0].path, "ok");
+
+        let mut corrupt = data.clone();
+        corrupt[0] ^= 1;
+        assert!(parse(&corrupt).is_err());
+    }
+}
@@ -0,0 +1,361 @@
+// zip archives (PKWARE APPNOTE)
+//
+// Read through the central directory at the end of the file, which lists
+// every entry with its sizes and the offset of its local header; the data
+// follows that header. Entries are stored or deflated. ZIP64 records and
+// extra fields are followed for archives with more than 65535 entries or
+// sizes and offsets past 4 GB. Encrypted entries are listed but can't be read.
+
+use super::{clean_path, inflate::inflate};
+use crate::system::crc32;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const END_OF_DIRECTORY: u32 = 0x06054b50;
+const ZIP64_END_OF_DIRECTORY: u32 = 0x06064b50;
+const ZIP64_LOCATOR: u32 = 0x07064b50;
+
+const END_SIZE: usize = 22;
+const CENTRAL_SIZE: usize = 46;
+const LOCAL_SIZE: usize = 30;
+const MAX_COMMENT: usize = 0xFFFF;
+
+pub const METHOD_STORED: u16 = 0;
+pub const METHOD_DEFLATE: u16 = 8;
+
+const FLAG_ENCRYPTED: u16 = 0x0001;
+/// Names are UTF-8 rather than code page 437
+const FLAG_UTF8: u16 = 0x0800;
+
+// Extra field IDs
+const EXTRA_ZIP64: u16 = 0x0001;
+const EXTRA_TIMESTAMP: u16 = 0x5455;
+
+/// "Made by" system whose external attributes hold a Unix mode
+const SYSTEM_UNIX: u8 = 3;
+const DOS_DIRECTORY: u32 = 0x10;
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+
+#[derive(Clone, Debug)]
+pub struct Entry {
+    /// Path inside the archive, without a leading "./" or a trailing '/'
+    pub path: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub compressed_size: u64,
+    pub method: u16,
+    pub crc: u32,
+    pub encrypted: bool,
+    /// MS-DOS (date, time) of the last change, in the archiver's local time
+    pub dos_time: (u16, u16),
+    /// Unix time from an extended timestamp field, where there is one
+    pub modified: Option<i64>,
+    /// Unix permission bits, for archives made on Unix
+    pub mode: Option<u32>,
+    header_offset: u64,
+}
+
+pub struct Archive {
+    pub entries: Vec<Entry>,
+    /// Links and entries with unsafe paths ("../x") that were left out
+    pub skipped: usize,
+}
+
+/// Whether `data` starts like a zip file (an entry, or the end record of an
+/// empty archive)
+pub fn is_zip(data: &[u8]) -> bool {
+    data.len() >= 4 && matches!(u32_at(data, 0), LOCAL_HEADER | END_OF_DIRECTORY)
+}
+
+fn u16_at(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([data[pos], data[pos + 1]])
+}
+
+fn u32_at(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+}
+
+fn u64_at(data: &[u8], pos: usize) -> u64 {
+    u32_at(data, pos) as u64 | (u32_at(data, pos + 4) as u64) << 32
+}
+
+/// Read the central directory
+pub fn parse(data: &[u8]) -> Result<Archive, &'static str> {
+    let (mut count, mut offset, size) = end_of_directory(data)?;
+    let end = offset.checked_add(size).filter(|&end| end <= data.len() as u64).ok_or("Zip directory runs past the end")?;
+
+    let mut archive = Archive { entries: Vec::new(), skipped: 0 };
+    while count > 0 {
+        let pos = offset as usize;
+        if offset + CENTRAL_SIZE as u64 > end || u32_at(data, pos) != CENTRAL_HEADER {
+            return Err("Corrupt zip directory");
+        }
+        let name_len = u16_at(data, pos + 28) as usize;
+        let extra_len = u16_at(data, pos + 30) as usize;
+        let comment_len = u16_at(data, pos + 32) as usize;
+        let record_end = pos + CENTRAL_SIZE + name_len + extra_len + comment_len;
+        if record_end as u64 > end {
+            return Err("Corrupt zip directory");
+        }
+        if let Some(entry) = central_entry(data, pos, name_len, extra_len) {
+            archive.entries.push(entry);
+        } else {
+            archive.skipped += 1;
+        }
+        offset = record_end as u64;
+        count -= 1;
+    }
+    Ok(archive)
+}
+
+/// Entry count, offset and size of the central directory
+fn end_of_directory(data: &[u8]) -> Result<(u64, u64, u64), &'static str> {
+    if data.len() < END_SIZE {
+        return Err("Not a zip file");
+    }
+    // The end record sits before a comment of up to 64 KB
+    let lowest = data.len().saturating_sub(END_SIZE + MAX_COMMENT);
+    let pos = (lowest..=data.len() - END_SIZE)
+        .rev()
+        .find(|&pos| u32_at(data, pos) == END_OF_DIRECTORY)
+        .ok_or("Not a zip file")?;
+
+    let count = u16_at(data, pos + 10) as u64;
+    let size = u32_at(data, pos + 12) as u64;
+    let offset = u32_at(data, pos + 16) as u64;
+    if count != 0xFFFF && size != 0xFFFF_FFFF && offset != 0xFFFF_FFFF {
+        return Ok((count, offset, size));
+    }
+
+    // ZIP64: a locator just before the end record points at the real one
+    let locator = pos.checked_sub(20).filter(|&at| u32_at(data, at) == ZIP64_LOCATOR).ok_or("Missing ZIP64 locator")?;
+    let record = u64_at(data, locator + 8) as usize;
+    if record.checked_add(56).map_or(true, |end| end > data.len()) || u32_at(data, record) != ZIP64_END_OF_DIRECTORY {
+        return Err("Corrupt ZIP64 end record");
+    }
+    Ok((u64_at(data, record + 32), u64_at(data, record + 48), u64_at(data, record + 40)))
+}
+
+/// A central directory record, or None for entries that are left out
+fn central_entry(data: &[u8], pos: usize, name_len: usize, extra_len: usize) -> Option<Entry> {
+    let flags = u16_at(data, pos + 8);
+    let name = &data[pos + CENTRAL_SIZE..pos + CENTRAL_SIZE + name_len];
+    let name = if flags & FLAG_UTF8 != 0 {
+        String::from_utf8_lossy(name).into_owned()
+    } else {
+        // Code page 437 matches ASCII, which covers nearly every name seen
+        name.iter().map(|&b| if b < 0x80 { b as char } else { '?' }).collect()
+    };
+
+    let mut entry = Entry {
+        path: String::new(),
+        is_dir: name.ends_with('/'),
+        size: u32_at(data, pos + 24) as u64,
+        compressed_size: u32_at(data, pos + 20) as u64,
+        method: u16_at(data, pos + 10),
+        crc: u32_at(data, pos + 16),
+        encrypted: flags & FLAG_ENCRYPTED != 0,
+        dos_time: (u16_at(data, pos + 14), u16_at(data, pos + 12)),
+        modified: None,
+        mode: None,
+        header_offset: u32_at(data, pos + 42) as u64,
+    };
+
+    let external = u32_at(data, pos + 38);
+    if data[pos + 5] == SYSTEM_UNIX && external >> 16 != 0 {
+        let mode = external >> 16;
+        match mode & S_IFMT {
+            S_IFDIR => entry.is_dir = true,
+            // Links and the like have nothing to extract
+            S_IFREG | 0 => {}
+            _ => return None,
+        }
+        entry.mode = Some(mode & 0o7777);
+    } else if external & DOS_DIRECTORY != 0 {
+        entry.is_dir = true;
+    }
+
+    let extra_start = pos + CENTRAL_SIZE + name_len;
+    read_extra(&data[extra_start..extra_start + extra_len], &mut entry);
+    if entry.is_dir {
+        entry.size = 0;
+    }
+
+    clean_path(&name).filter(|path| !path.is_empty()).map(|path| Entry { path, ..entry })
+}
+
+/// Apply the ZIP64 and extended timestamp extra fields
+fn read_extra(mut extra: &[u8], entry: &mut Entry) {
+    while extra.len() >= 4 {
+        let id = u16_at(extra, 0);
+        let len = core::cmp::min(u16_at(extra, 2) as usize, extra.len() - 4);
+        let field = &extra[4..4 + len];
+        match id {
+            EXTRA_ZIP64 => {
+                // 64-bit values follow for exactly the fields that are 0xFFFFFFFF
+                let mut values = field.chunks_exact(8).map(|value| u64_at(value, 0));
+                for target in [&mut entry.size, &mut entry.compressed_size, &mut entry.header_offset] {
+                    if *target == 0xFFFF_FFFF {
+                        if let Some(value) = values.next() {
+                            *target = value;
+                        }
+                    }
+                }
+            }
+            EXTRA_TIMESTAMP if field.len() >= 5 && field[0] & 1 != 0 => {
+                entry.modified = Some(u32_at(field, 1) as i32 as i64);
+            }
+            _ => {}
+        }
+        extra = &extra[4 + len..];
+    }
+}
+
+/// Decompress an entry's data, which must be no bigger than `limit`
+pub fn read(data: &[u8], entry: &Entry, limit: usize) -> Result<Vec<u8>, &'static str> {
+    if entry.encrypted {
+        return Err("Encrypted zip entries aren't supported");
+    }
+    if entry.size > limit as u64 {
+        return Err("Zip entry too large");
+    }
+    let pos = entry.header_offset as usize;
+    if entry.header_offset.checked_add(LOCAL_SIZE as u64).map_or(true, |end| end > data.len() as u64)
+        || u32_at(data, pos) != LOCAL_HEADER
+    {
+        return Err("Corrupt zip entry header");
+    }
+    // The local header's name and extra field can differ from the central ones
+    let start = pos + LOCAL_SIZE + u16_at(data, pos + 26) as usize + u16_at(data, pos + 28) as usize;
+    let compressed = (start as u64)
+        .checked_add(entry.compressed_size)
+        .filter(|&end| end <= data.len() as u64)
+        .map(|end| &data[start..end as usize])
+        .ok_or("Zip entry runs past the end")?;
+
+    let out = match entry.method {
+        METHOD_STORED => compressed.to_vec(),
+        METHOD_DEFLATE => inflate(compressed, entry.size as usize)?.0,
+        _ => return Err("Unsupported zip compression method"),
+    };
+    if out.len() as u64 != entry.size {
+        return Err("Zip entry has the wrong size");
+    }
+    if crc32::update(0, &out) != entry.crc {
+        return Err("Zip entry CRC mismatch");
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A zip of (name, method, data, uncompressed contents) entries. With
+    /// `zip64` the sizes and offsets go in ZIP64 fields and records.
+    fn build(entries: &[(&str, u16, &[u8], &[u8])], zip64: bool) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut directory = Vec::new();
+        for &(name, method, stored, contents) in entries {
+            let offset = data.len() as u64;
+            let crc = crc32::update(0, contents);
+            let put = |out: &mut Vec<u8>, value: u64| out.extend_from_slice(&value.to_le_bytes()[..4]);
+            let small = |value: u64| if zip64 { 0xFFFF_FFFF } else { value };
+
+            data.extend_from_slice(&LOCAL_HEADER.to_le_bytes());
+            data.extend_from_slice(&[20, 0, 0, 0]);
+            data.extend_from_slice(&method.to_le_bytes());
+            data.extend_from_slice(&[0, 0, 0x21, 0]); // 1980-01-01 00:00
+            put(&mut data, crc as u64);
+            put(&mut data, stored.len() as u64);
+            put(&mut data, contents.len() as u64);
+            data.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            data.extend_from_slice(&[0, 0]);
+            data.extend_from_slice(name.as_bytes());
+            data.extend_from_slice(stored);
+
+            let mut extra = Vec::new();
+            if zip64 {
+                extra.extend_from_slice(&[0x01, 0x00, 24, 0]);
+                for value in [contents.len() as u64, stored.len() as u64, offset] {
+                    extra.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+            directory.extend_from_slice(&CENTRAL_HEADER.to_le_bytes());
+            directory.extend_from_slice(&[20, SYSTEM_UNIX, 20, 0, 0, 0]);
+            directory.extend_from_slice(&method.to_le_bytes());
+            directory.extend_from_slice(&[0, 0, 0x21, 0]);
+            put(&mut directory, crc as u64);
+            put(&mut directory, small(stored.len() as u64));
+            put(&mut directory, small(contents.len() as u64));
+            directory.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            directory.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+            directory.extend_from_slice(&[0; 6]); // Comment length, disk, internal attributes
+            let mode = if name.ends_with('/') { 0o040755u32 } else { 0o100644 };
+            put(&mut directory, (mode << 16) as u64);
+            put(&mut directory, small(offset));
+            directory.extend_from_slice(name.as_bytes());
+            directory.extend_from_slice(&extra);
+        }
+
+        let (offset, size, count) = (data.len() as u64, directory.len() as u64, entries.len() as u64);
+        data.extend_from_slice(&directory);
+        if zip64 {
+            let record = data.len() as u64;
+            data.extend_from_slice(&ZIP64_END_OF_DIRECTORY.to_le_bytes());
+            data.extend_from_slice(&44u64.to_le_bytes());
+            data.extend_from_slice(&[45, 0, 45, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            for value in [count, count, size, offset] {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+            data.extend_from_slice(&ZIP64_LOCATOR.to_le_bytes());
+            data.extend_from_slice(&[0; 4]);
+            data.extend_from_slice(&record.to_le_bytes());
+            data.extend_from_slice(&[1, 0, 0, 0]);
+        }
+        let (count, size, offset) = if zip64 { (0xFFFF, 0xFFFF_FFFF, 0xFFFF_FFFF) } else { (count, size, offset) };
+        data.extend_from_slice(&END_OF_DIRECTORY.to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&(count as u16).to_le_bytes());
+        data.extend_from_slice(&(count as u16).to_le_bytes());
+        data.extend_from_slice(&(size as u32).to_le_bytes());
+        data.extend_from_slice(&(offset as u32).to_le_bytes());
+        data.extend_from_slice(b"\x05\x00hello"); // Comment
+        data
+    }
+
+    #[test]
+    fn test_read_entries() {
+        // "abcabcabcabc" deflated with fixed codes
+        let deflated: &[u8] = &[0x4b, 0x4c, 0x4a, 0x4e, 0x84, 0x21, 0x00];
+        let entries: &[(&str, u16, &[u8], &[u8])] = &[
+            ("docs/", METHOD_STORED, b"", b""),
+            ("docs/a.txt", METHOD_STORED, b"stored", b"stored"),
+            ("./docs/b.txt", METHOD_DEFLATE, deflated, b"abcabcabcabc"),
+            ("../evil", METHOD_STORED, b"x", b"x"),
+        ];
+
+        for zip64 in [false, true] {
+            let data = build(entries, zip64);
+            assert!(is_zip(&data));
+            let archive = parse(&data).unwrap();
+            assert_eq!(archive.skipped, 1);
+            let paths: Vec<&str> = archive.entries.iter().map(|e| e.path.as_str()).collect();
+            assert_eq!(paths, ["docs", "docs/a.txt", "docs/b.txt"]);
+            assert!(archive.entries[0].is_dir);
+            assert_eq!(archive.entries[1].mode, Some(0o644));
+            assert_eq!(read(&data, &archive.entries[1], 100).unwrap(), b"stored");
+            assert_eq!(read(&data, &archive.entries[2], 100).unwrap(), b"abcabcabcabc");
+            assert!(read(&data, &archive.entries[2], 5).is_err());
+        }
+
+        let mut corrupt = build(entries, false);
+        corrupt[30 + 5 + 2] ^= 1; // First byte of "stored"
+        let archive = parse(&corrupt).unwrap();
+        assert!(read(&corrupt, &archive.entries[1], 100).is_err());
+    }
+}
@@ -0,0 +1,423 @@
+// Write-back block cache
+//
+// Disks registered at boot are attached to one global cache shared by all of
+// them, so a single size limit covers every disk. Reads are served from cached
+// blocks where possible; writes only dirty the cached copy until the block is
+// evicted (least recently used first), the device is flushed, or sync() runs.
+// Transfers larger than a quarter of the cache bypass it so one big file read
+// doesn't throw out the filesystem metadata everyone else is using.
+
+use super::{check_range, check_request, BlockDevice, BLOCK_SIZE};
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Default cache size: 4MB
+pub const DEFAULT_CAPACITY_BLOCKS: usize = 8192;
+/// Longest run of dirty blocks written back in one request
+const MAX_WRITEBACK_BLOCKS: usize = 256;
+
+/// (device index in the cache, block number)
+type BlockKey = (usize, u64);
+
+struct CacheEntry {
+    data: Box<[u8]>,
+    dirty: bool,
+    /// Position in the LRU order
+    tick: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Blocks read or written around the cache by large transfers
+    pub bypassed: u64,
+    pub evictions: u64,
+    /// Dirty blocks written back to their device
+    pub writebacks: u64,
+}
+
+pub struct BlockCache {
+    devices: Vec<Box<dyn BlockDevice>>,
+    capacity: usize,
+    entries: BTreeMap<BlockKey, CacheEntry>,
+    /// tick -> block, oldest first
+    lru: BTreeMap<u64, BlockKey>,
+    next_tick: u64,
+    stats: CacheStats,
+}
+
+impl BlockCache {
+    pub const fn new(capacity: usize) -> Self {
+        BlockCache {
+            devices: Vec::new(),
+            capacity,
+            entries: BTreeMap::new(),
+            lru: BTreeMap::new(),
+            next_tick: 0,
+            stats: CacheStats { hits: 0, misses: 0, bypassed: 0, evictions: 0, writebacks: 0 },
+        }
+    }
+
+    /// Put a device behind the cache. Returns its index for the other calls.
+    pub fn attach(&mut self, device: Box<dyn BlockDevice>) -> usize {
+        self.devices.push(device);
+        self.devices.len() - 1
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Change the cache size, writing back whatever no longer fits
+    pub fn set_capacity(&mut self, blocks: usize) -> Result<(), &'static str> {
+        self.capacity = blocks;
+        while self.entries.len() > self.capacity {
+            self.evict_one()?;
+        }
+        Ok(())
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    pub fn cached_blocks(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn dirty_blocks(&self) -> usize {
+        self.entries.values().filter(|entry| entry.dirty).count()
+    }
+
+    fn bypass(&self, count: u64) -> bool {
+        count > (self.capacity / 4) as u64
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.next_tick += 1;
+        self.next_tick
+    }
+
+    /// Mark a cached block as most recently used
+    fn touch(&mut self, key: BlockKey) {
+        let tick = self.next_tick();
+        if let Some(entry) = self.entries.get_mut(&key) {
+            self.lru.remove(&entry.tick);
+            entry.tick = tick;
+            self.lru.insert(tick, key);
+        }
+    }
+
+    /// Drop the least recently used block, writing it back first if dirty
+    fn evict_one(&mut self) -> Result<(), &'static str> {
+        let (&tick, &key) = match self.lru.iter().next() {
+            Some(oldest) => oldest,
+            None => return Ok(()),
+        };
+        if self.entries[&key].dirty {
+            self.devices[key.0].write_blocks(key.1, &self.entries[&key].data)?;
+            self.stats.writebacks += 1;
+        }
+        self.lru.remove(&tick);
+        self.entries.remove(&key);
+        self.stats.evictions += 1;
+        Ok(())
+    }
+
+    /// Cache a copy of `data` for `key`, making room if needed
+    fn insert(&mut self, key: BlockKey, data: &[u8], dirty: bool) -> Result<(), &'static str> {
+        if let Some(entry) = self.entries.get_mut(&key) {
+            entry.data.copy_from_slice(data);
+            entry.dirty |= dirty;
+            self.touch(key);
+            return Ok(());
+        }
+
+        if self.capacity == 0 {
+            return Ok(());
+        }
+        while self.entries.len() >= self.capacity {
+            self.evict_one()?;
+        }
+        let tick = self.next_tick();
+        self.entries.insert(key, CacheEntry { data: data.into(), dirty, tick });
+        self.lru.insert(tick, key);
+        Ok(())
+    }
+
+    pub fn block_count(&self, device: usize) -> u64 {
+        self.devices[device].block_count()
+    }
+
+    pub fn is_read_only(&self, device: usize) -> bool {
+        self.devices[device].is_read_only()
+    }
+
+    pub fn read_blocks(&mut self, device: usize, start: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+        let count = check_request(self.block_count(device), start, buffer.len())?;
+
+        if self.bypass(count) {
+            self.devices[device].read_blocks(start, buffer)?;
+            // Cached dirty blocks are newer than what the device returned
+            for (&(_, block), entry) in self.entries.range((device, start)..(device, start + count)) {
+                if entry.dirty {
+                    let offset = (block - start) as usize * BLOCK_SIZE;
+                    buffer[offset..offset + BLOCK_SIZE].copy_from_slice(&entry.data);
+                }
+            }
+            self.stats.bypassed += count;
+            return Ok(());
+        }
+
+        let mut i = 0;
+        while i < count {
+            let key = (device, start + i);
+            let offset = i as usize * BLOCK_SIZE;
+            if let Some(entry) = self.entries.get(&key) {
+                buffer[offset..offset + BLOCK_SIZE].copy_from_slice(&entry.data);
+                self.touch(key);
+                self.stats.hits += 1;
+                i += 1;
+                continue;
+            }
+
+            // Read the whole run of missing blocks in one request
+            let mut end = i + 1;
+            while end < count && !self.entries.contains_key(&(device, start + end)) {
+                end += 1;
+            }
+            let run = &mut buffer[offset..end as usize * BLOCK_SIZE];
+            self.devices[device].read_blocks(start + i, run)?;
+            for (j, block) in run.chunks_exact(BLOCK_SIZE).enumerate() {
+                self.insert((device, start + i + j as u64), block, false)?;
+            }
+            self.stats.misses += end - i;
+            i = end;
+        }
+        Ok(())
+    }
+
+    pub fn write_blocks(&mut self, device: usize, start: u64, buffer: &[u8]) -> Result<(), &'static str> {
+        if self.is_read_only(device) {
+            return Err("Device is read-only");
+        }
+        let count = check_request(self.block_count(device), start, buffer.len())?;
+
+        if self.bypass(count) {
+            self.devices[device].write_blocks(start, buffer)?;
+            // Keep cached copies in step with what is now on the device
+            for (&(_, block), entry) in self.entries.range_mut((device, start)..(device, start + count)) {
+                let offset = (block - start) as usize * BLOCK_SIZE;
+                entry.data.copy_from_slice(&buffer[offset..offset + BLOCK_SIZE]);
+                entry.dirty = false;
+            }
+            self.stats.bypassed += count;
+            return Ok(());
+        }
+
+        for (i, block) in buffer.chunks_exact(BLOCK_SIZE).enumerate() {
+            self.insert((device, start + i as u64), block, true)?;
+        }
+        Ok(())
+    }
+
+    /// Write back every dirty block of `device`, coalescing adjacent blocks
+    fn write_back(&mut self, device: usize) -> Result<(), &'static str> {
+        let dirty: Vec<u64> = self.entries
+            .range((device, 0)..=(device, u64::MAX))
+            .filter(|(_, entry)| entry.dirty)
+            .map(|(&(_, block), _)| block)
+            .collect();
+
+        let mut i = 0;
+        while i < dirty.len() {
+            let mut end = i + 1;
+            while end < dirty.len() && dirty[end] == dirty[end - 1] + 1 && end - i < MAX_WRITEBACK_BLOCKS {
+                end += 1;
+            }
+
+            let mut run = vec![0u8; (end - i) * BLOCK_SIZE];
+            for (j, block) in dirty[i..end].iter().enumerate() {
+                run[j * BLOCK_SIZE..(j + 1) * BLOCK_SIZE].copy_from_slice(&self.entries[&(device, *block)].data);
+            }
+            self.devices[device].write_blocks(dirty[i], &run)?;
+
+            for block in &dirty[i..end] {
+                if let Some(entry) = self.entries.get_mut(&(device, *block)) {
+                    entry.dirty = false;
+                }
+            }
+            self.stats.writebacks += (end - i) as u64;
+            i = end;
+        }
+        Ok(())
+    }
+
+    /// Write back `device`'s dirty blocks and flush the device itself
+    pub fn flush(&mut self, device: usize) -> Result<(), &'static str> {
+        self.write_back(device)?;
+        self.devices[device].flush()
+    }
+
+    /// Flush every device, returning the first error
+    pub fn sync(&mut self) -> Result<(), &'static str> {
+        let mut result = Ok(());
+        for device in 0..self.devices.len() {
+            if let Err(e) = self.flush(device) {
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+
+    /// Forget cached blocks in a range (their contents are being replaced)
+    fn invalidate(&mut self, device: usize, start: u64, count: u64) {
+        let keys: Vec<BlockKey> = self.entries
+            .range((device, start)..(device, start + count))
+            .map(|(&key, _)| key)
+            .collect();
+        for key in keys {
+            if let Some(entry) = self.entries.remove(&key) {
+                self.lru.remove(&entry.tick);
+            }
+        }
+    }
+
+    pub fn discard(&mut self, device: usize, start: u64, count: u64) -> Result<(), &'static str> {
+        check_range(self.block_count(device), start, count)?;
+        self.invalidate(device, start, count);
+        self.devices[device].discard(start, count)
+    }
+
+    pub fn write_zeroes(&mut self, device: usize, start: u64, count: u64) -> Result<(), &'static str> {
+        check_range(self.block_count(device), start, count)?;
+        self.invalidate(device, start, count);
+        self.devices[device].write_zeroes(start, count)
+    }
+}
+
+static mut CACHE: BlockCache = BlockCache::new(DEFAULT_CAPACITY_BLOCKS);
+
+/// The global cache in front of every registered disk
+pub fn cache() -> &'static mut BlockCache {
+    unsafe { &mut *core::ptr::addr_of_mut!(CACHE) }
+}
+
+/// Write back all dirty blocks on every disk
+pub fn sync() -> Result<(), &'static str> {
+    cache().sync()
+}
+
+/// A device accessed through the global cache
+pub struct CachedDevice(usize);
+
+impl CachedDevice {
+    /// Put `device` behind the global cache
+    pub fn new(device: Box<dyn BlockDevice>) -> Self {
+        CachedDevice(cache().attach(device))
+    }
+}
+
+impl BlockDevice for CachedDevice {
+    fn block_count(&self) -> u64 {
+        cache().block_count(self.0)
+    }
+
+    fn read_blocks(&mut self, start: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+        cache().read_blocks(self.0, start, buffer)
+    }
+
+    fn write_blocks(&mut self, start: u64, buffer: &[u8]) -> Result<(), &'static str> {
+        cache().write_blocks(self.0, start, buffer)
+    }
+
+    fn flush(&mut self) -> Result<(), &'static str> {
+        cache().flush(self.0)
+    }
+
+    fn is_read_only(&self) -> bool {
+        cache().is_read_only(self.0)
+    }
+
+    fn discard(&mut self, start: u64, count: u64) -> Result<(), &'static str> {
+        cache().discard(self.0, start, count)
+    }
+
+    fn write_zeroes(&mut self, start: u64, count: u64) -> Result<(), &'static str> {
+        cache().write_zeroes(self.0, start, count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::block::RamDisk;
+
+    #[test]
+    fn test_hits_after_first_read() {
+        let mut cache = BlockCache::new(16);
+        let dev = cache.attach(Box::new(RamDisk::new(64)));
+        let mut block = [0u8; BLOCK_SIZE];
+
+        cache.read_blocks(dev, 3, &mut block).unwrap();
+        cache.read_blocks(dev, 3, &mut block).unwrap();
+        assert_eq!(cache.stats().misses, 1);
+        assert_eq!(cache.stats().hits, 1);
+    }
+
+    #[test]
+    fn test_write_back_on_sync() {
+        let mut cache = BlockCache::new(16);
+        let dev = cache.attach(Box::new(RamDisk::new(64)));
+
+        cache.write_blocks(dev, 5, &[0x42u8; BLOCK_SIZE * 2]).unwrap();
+        assert_eq!(cache.dirty_blocks(), 2);
+
+        // Still readable through the cache before it reaches the disk
+        let mut block = [0u8; BLOCK_SIZE];
+        cache.read_blocks(dev, 6, &mut block).unwrap();
+        assert!(block.iter().all(|&b| b == 0x42));
+
+        cache.sync().unwrap();
+        assert_eq!(cache.dirty_blocks(), 0);
+        assert_eq!(cache.stats().writebacks, 2);
+    }
+
+    #[test]
+    fn test_lru_eviction_writes_back() {
+        let mut cache = BlockCache::new(4);
+        let dev = cache.attach(Box::new(RamDisk::new(64)));
+
+        cache.write_blocks(dev, 0, &[1u8; BLOCK_SIZE]).unwrap();
+        let mut block = [0u8; BLOCK_SIZE];
+        for b in 1..4 {
+            cache.read_blocks(dev, b, &mut block).unwrap();
+        }
+        // Block 0 is the oldest, so reading a fifth block evicts (and writes) it
+        cache.read_blocks(dev, 10, &mut block).unwrap();
+        assert_eq!(cache.stats().evictions, 1);
+        assert_eq!(cache.stats().writebacks, 1);
+        assert_eq!(cache.cached_blocks(), 4);
+
+        cache.read_blocks(dev, 0, &mut block).unwrap();
+        assert!(block.iter().all(|&b| b == 1));
+    }
+
+    #[test]
+    fn test_large_read_bypasses_but_sees_dirty_blocks() {
+        let mut cache = BlockCache::new(8);
+        let dev = cache.attach(Box::new(RamDisk::new(64)));
+        cache.write_blocks(dev, 4, &[9u8; BLOCK_SIZE]).unwrap();
+
+        let mut buffer = vec![0u8; 16 * BLOCK_SIZE];
+        cache.read_blocks(dev, 0, &mut buffer).unwrap();
+        assert_eq!(cache.stats().bypassed, 16);
+        assert!(buffer[4 * BLOCK_SIZE..5 * BLOCK_SIZE].iter().all(|&b| b == 9));
+        assert!(buffer[..4 * BLOCK_SIZE].iter().all(|&b| b == 0));
+    }
+}
@@ -0,0 +1,219 @@
+// Encrypted block device
+//
+// An encrypted volume starts with HEADER_BLOCKS blocks of header, then the
+// data, each block encrypted with AES-256-XTS using its number within the data
+// area as the tweak. The 64-byte volume key is random; the header holds it
+// encrypted with a key derived from the passphrase by PBKDF2-HMAC-SHA256, and
+// an HMAC of a fixed label under the volume key so a wrong passphrase is
+// recognised. Header block 0:
+//
+//   0   magic, version, PBKDF2 iterations (u32 each), 4 bytes reserved
+//   16  salt (32 bytes)
+//   48  volume key, XTS-encrypted with the derived key as data unit 0 (64 bytes)
+//   112 key check value (32 bytes)
+//
+// The header isn't authenticated until the passphrase has been checked, so the
+// iteration count read from it must lie in MIN_ITERATIONS..=MAX_ITERATIONS.
+// Keys are wiped from memory once used, and the volume key schedule when the
+// unlocked volume is dropped.
+//
+// The unlocked volume is a BlockDevice like any other, so any filesystem can go
+// on it. Discards are dropped rather than passed down, so the image doesn't
+// show which blocks are in use.
+
+use super::{check_request, BlockDevice, BLOCK_SIZE};
+use crate::system::aes::{zeroize, Xts};
+use crate::system::sha256::{hmac_sha256, pbkdf2};
+use alloc::vec;
+
+const CRYPT_MAGIC: u32 = 0x52435259; // "RCRY" in ASCII
+const CRYPT_VERSION: u32 = 1;
+/// Blocks before the encrypted data: the header, padded to 4 KiB
+pub const HEADER_BLOCKS: u64 = 8;
+/// PBKDF2 iterations for new volumes, about a second on QEMU
+pub const DEFAULT_ITERATIONS: u32 = 100_000;
+/// Fewest PBKDF2 iterations a volume may use
+pub const MIN_ITERATIONS: u32 = 1_000;
+/// Most PBKDF2 iterations a volume may use, about 20 seconds on QEMU. A
+/// tampered header can't make unlocking hang for longer.
+pub const MAX_ITERATIONS: u32 = 2_000_000;
+const KEY_CHECK_LABEL: &[u8] = b"rOSt volume key check";
+/// Blocks encrypted per write to the parent
+const CHUNK_BLOCKS: usize = 32;
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// Whether `device` starts with an encrypted volume header
+pub fn is_encrypted(device: &mut dyn BlockDevice) -> bool {
+    let mut header = [0u8; BLOCK_SIZE];
+    device.read_blocks(0, &mut header).is_ok() && read_u32(&header, 0) == CRYPT_MAGIC
+}
+
+/// Key that unlocks the volume key, from the passphrase
+fn passphrase_key(passphrase: &[u8], salt: &[u8], iterations: u32) -> Result<Xts, &'static str> {
+    if !(MIN_ITERATIONS..=MAX_ITERATIONS).contains(&iterations) {
+        return Err("PBKDF2 iteration count out of range");
+    }
+    let mut key = [0u8; 64];
+    pbkdf2(passphrase, salt, iterations, &mut key);
+    let xts = Xts::new(&key);
+    zeroize(&mut key);
+    Ok(xts)
+}
+
+/// Make `device` an empty encrypted volume with a random volume key. The old
+/// contents are zeroed, so nothing stays readable in the clear.
+pub fn format(device: &mut dyn BlockDevice, passphrase: &[u8], iterations: u32) -> Result<(), &'static str> {
+    if device.is_read_only() {
+        return Err("Device is read-only");
+    }
+    if device.block_count() <= HEADER_BLOCKS {
+        return Err("Device too small");
+    }
+    if passphrase.is_empty() {
+        return Err("Empty passphrase");
+    }
+    let mut salt = [0u8; 32];
+    crate::kernel::random::getrandom(&mut salt);
+    let key = passphrase_key(passphrase, &salt, iterations)?;
+
+    let mut header = vec![0u8; HEADER_BLOCKS as usize * BLOCK_SIZE];
+    header[0..4].copy_from_slice(&CRYPT_MAGIC.to_le_bytes());
+    header[4..8].copy_from_slice(&CRYPT_VERSION.to_le_bytes());
+    header[8..12].copy_from_slice(&iterations.to_le_bytes());
+    header[16..48].copy_from_slice(&salt);
+
+    let mut volume_key = [0u8; 64];
+    crate::kernel::random::getrandom(&mut volume_key);
+    header[112..144].copy_from_slice(&hmac_sha256(&volume_key, KEY_CHECK_LABEL));
+    key.encrypt(0, &mut volume_key);
+    header[48..112].copy_from_slice(&volume_key);
+
+    device.write_zeroes(HEADER_BLOCKS, device.block_count() - HEADER_BLOCKS)?;
+    device.write_blocks(0, &header)?;
+    device.flush()
+}
+
+/// An unlocked encrypted volume on `parent`
+pub struct CryptDevice<D: BlockDevice> {
+    parent: D,
+    xts: Xts,
+    block_count: u64,
+}
+
+impl<D: BlockDevice> CryptDevice<D> {
+    /// Check `passphrase` against the header and open the volume
+    pub fn unlock(mut parent: D, passphrase: &[u8]) -> Result<Self, &'static str> {
+        let mut header = [0u8; BLOCK_SIZE];
+        parent.read_blocks(0, &mut header)?;
+        if read_u32(&header, 0) != CRYPT_MAGIC {
+            return Err("Not an encrypted volume");
+        }
+        if read_u32(&header, 4) != CRYPT_VERSION {
+            return Err("Unsupported encrypted volume version");
+        }
+        if parent.block_count() <= HEADER_BLOCKS {
+            return Err("Encrypted volume is truncated");
+        }
+
+        let key = passphrase_key(passphrase, &header[16..48], read_u32(&header, 8))?;
+        let mut volume_key: [u8; 64] = header[48..112].try_into().unwrap();
+        key.decrypt(0, &mut volume_key);
+        let matches = hmac_sha256(&volume_key, KEY_CHECK_LABEL)[..] == header[112..144];
+        let xts = Xts::new(&volume_key);
+        zeroize(&mut volume_key);
+        if !matches {
+            return Err("Wrong passphrase");
+        }
+        let block_count = parent.block_count() - HEADER_BLOCKS;
+        Ok(CryptDevice { parent, xts, block_count })
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for CryptDevice<D> {
+    fn block_count(&self) -> u64 {
+        self.block_count
+    }
+
+    fn read_blocks(&mut self, start: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+        check_request(self.block_count, start, buffer.len())?;
+        self.parent.read_blocks(HEADER_BLOCKS + start, buffer)?;
+        for (i, block) in buffer.chunks_exact_mut(BLOCK_SIZE).enumerate() {
+            self.xts.decrypt(start + i as u64, block);
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, start: u64, buffer: &[u8]) -> Result<(), &'static str> {
+        check_request(self.block_count, start, buffer.len())?;
+        let mut chunk = vec![0u8; core::cmp::min(buffer.len(), CHUNK_BLOCKS * BLOCK_SIZE)];
+        for (n, data) in buffer.chunks(CHUNK_BLOCKS * BLOCK_SIZE).enumerate() {
+            let first = start + (n * CHUNK_BLOCKS) as u64;
+            let chunk = &mut chunk[..data.len()];
+            chunk.copy_from_slice(data);
+            for (i, block) in chunk.chunks_exact_mut(BLOCK_SIZE).enumerate() {
+                self.xts.encrypt(first + i as u64, block);
+            }
+            self.parent.write_blocks(HEADER_BLOCKS + first, chunk)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), &'static str> {
+        self.parent.flush()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.parent.is_read_only()
+    }
+
+    // discard() keeps the default (checks the range, does nothing) and
+    // write_zeroes() the default zero_fill(): zeroes on the parent would
+    // decrypt to noise
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::block::RamDisk;
+
+    #[test]
+    fn test_encrypted_volume() {
+        let mut disk = RamDisk::new(64);
+        assert!(!is_encrypted(&mut disk));
+        disk.write_blocks(20, &[0xaa; BLOCK_SIZE]).unwrap();
+        assert!(format(&mut disk, b"correct horse", MIN_ITERATIONS - 1).is_err());
+        format(&mut disk, b"correct horse", MIN_ITERATIONS).unwrap();
+        assert!(is_encrypted(&mut disk));
+        assert!(disk.as_bytes()[20 * BLOCK_SIZE..21 * BLOCK_SIZE].iter().all(|&b| b == 0));
+        assert_eq!(CryptDevice::unlock(&mut disk, b"wrong horse").err(), Some("Wrong passphrase"));
+
+        // A tampered iteration count is refused before any PBKDF2 work
+        let mut header = [0u8; BLOCK_SIZE];
+        disk.read_blocks(0, &mut header).unwrap();
+        let mut tampered = RamDisk::from_image(disk.as_bytes().to_vec());
+        header[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        tampered.write_blocks(0, &header).unwrap();
+        assert_eq!(CryptDevice::unlock(&mut tampered, b"correct horse").err(), Some("PBKDF2 iteration count out of range"));
+
+        let mut volume = CryptDevice::unlock(&mut disk, b"correct horse").unwrap();
+        assert_eq!(volume.block_count(), 64 - HEADER_BLOCKS);
+        let data: alloc::vec::Vec<u8> = (0..3 * BLOCK_SIZE).map(|i| (i % 251) as u8).collect();
+        volume.write_blocks(5, &data).unwrap();
+        volume.write_zeroes(10, 2).unwrap();
+        assert!(volume.read_blocks(55, &mut [0u8; 2 * BLOCK_SIZE]).is_err());
+
+        // Nothing readable on the disk itself
+        let raw = &disk.as_bytes()[(HEADER_BLOCKS as usize + 5) * BLOCK_SIZE..][..data.len()];
+        assert_ne!(raw, &data[..]);
+
+        let mut volume = CryptDevice::unlock(&mut disk, b"correct horse").unwrap();
+        let mut buffer = vec![0u8; 3 * BLOCK_SIZE];
+        volume.read_blocks(5, &mut buffer).unwrap();
+        assert_eq!(buffer, data);
+        volume.read_blocks(10, &mut buffer[..2 * BLOCK_SIZE]).unwrap();
+        assert!(buffer[..2 * BLOCK_SIZE].iter().all(|&b| b == 0));
+    }
+}
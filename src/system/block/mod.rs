@@ -0,0 +1,123 @@
+// Block device layer
+//
+// Filesystems talk to storage through the BlockDevice trait, so the same code
+// runs on a virtio disk, a single Partition of one, or in unit tests an
+// in-memory RamDisk. All devices use 512-byte blocks. Devices in use are
+// listed in the registry; registered disks sit behind the write-back cache.
+// An encrypted volume, once unlocked, is a CryptDevice stacked on its disk or
+// partition.
+
+pub mod cache;
+pub mod crypt;
+#[cfg(test)]
+pub mod ramdisk;
+pub mod partition;
+pub mod partition_table;
+pub mod registry;
+
+#[cfg(test)]
+pub use ramdisk::RamDisk;
+pub use partition::Partition;
+pub use registry::{device, device_name, root_volume};
+
+/// Size of one block in bytes
+pub const BLOCK_SIZE: usize = 512;
+
+/// A random-access device addressed in BLOCK_SIZE blocks
+pub trait BlockDevice {
+    /// Number of blocks on the device
+    fn block_count(&self) -> u64;
+
+    /// Read `buffer.len() / BLOCK_SIZE` blocks starting at block `start`.
+    /// The buffer length must be a multiple of BLOCK_SIZE.
+    fn read_blocks(&mut self, start: u64, buffer: &mut [u8]) -> Result<(), &'static str>;
+
+    /// Write `buffer.len() / BLOCK_SIZE` blocks starting at block `start`
+    fn write_blocks(&mut self, start: u64, buffer: &[u8]) -> Result<(), &'static str>;
+
+    /// Make previous writes durable
+    fn flush(&mut self) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Hint that blocks `start..start + count` no longer hold useful data.
+    /// Devices that can't reclaim space ignore it.
+    fn discard(&mut self, start: u64, count: u64) -> Result<(), &'static str> {
+        check_range(self.block_count(), start, count)
+    }
+
+    /// Set blocks `start..start + count` to zero
+    fn write_zeroes(&mut self, start: u64, count: u64) -> Result<(), &'static str> {
+        zero_fill(self, start, count)
+    }
+}
+
+/// Check that blocks `start..start + count` lie inside a device of
+/// `block_count` blocks
+pub fn check_range(block_count: u64, start: u64, count: u64) -> Result<(), &'static str> {
+    match start.checked_add(count) {
+        Some(end) if end <= block_count => Ok(()),
+        _ => Err("Block out of range"),
+    }
+}
+
+/// Zero blocks by writing zero-filled buffers (for devices without a
+/// dedicated write-zeroes command)
+pub fn zero_fill<D: BlockDevice + ?Sized>(device: &mut D, start: u64, count: u64) -> Result<(), &'static str> {
+    check_range(device.block_count(), start, count)?;
+    const CHUNK_BLOCKS: u64 = 16;
+    static ZEROES: [u8; CHUNK_BLOCKS as usize * BLOCK_SIZE] = [0; CHUNK_BLOCKS as usize * BLOCK_SIZE];
+
+    let mut done = 0;
+    while done < count {
+        let blocks = core::cmp::min(CHUNK_BLOCKS, count - done);
+        device.write_blocks(start + done, &ZEROES[..blocks as usize * BLOCK_SIZE])?;
+        done += blocks;
+    }
+    Ok(())
+}
+
+/// Check that a request covers whole blocks inside a device of `block_count`
+/// blocks. Returns the number of blocks requested.
+pub fn check_request(block_count: u64, start: u64, len: usize) -> Result<u64, &'static str> {
+    if len % BLOCK_SIZE != 0 {
+        return Err("Buffer is not a multiple of the block size");
+    }
+    let count = (len / BLOCK_SIZE) as u64;
+    check_range(block_count, start, count)?;
+    Ok(count)
+}
+
+impl<D: BlockDevice + ?Sized> BlockDevice for &mut D {
+    fn block_count(&self) -> u64 {
+        (**self).block_count()
+    }
+
+    fn read_blocks(&mut self, start: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+        (**self).read_blocks(start, buffer)
+    }
+
+    fn write_blocks(&mut self, start: u64, buffer: &[u8]) -> Result<(), &'static str> {
+        (**self).write_blocks(start, buffer)
+    }
+
+    fn flush(&mut self) -> Result<(), &'static str> {
+        (**self).flush()
+    }
+
+    fn is_read_only(&self) -> bool {
+        (**self).is_read_only()
+    }
+
+    fn discard(&mut self, start: u64, count: u64) -> Result<(), &'static str> {
+        (**self).discard(start, count)
+    }
+
+    fn write_zeroes(&mut self, start: u64, count: u64) -> Result<(), &'static str> {
+        (**self).write_zeroes(start, count)
+    }
+}
@@ -0,0 +1,112 @@
+// Partition view of a block device
+//
+// Exposes a contiguous range of a parent device's blocks as a device of its
+// own, with block 0 at the start of the range. Requests past the end of the
+// partition are rejected rather than spilling into the next one.
+
+use super::{check_range, check_request, BlockDevice};
+
+pub struct Partition<D: BlockDevice> {
+    parent: D,
+    first_block: u64,
+    block_count: u64,
+    read_only: bool,
+}
+
+impl<D: BlockDevice> Partition<D> {
+    /// Wrap blocks `first_block..first_block + block_count` of `parent`
+    pub fn new(parent: D, first_block: u64, block_count: u64) -> Result<Self, &'static str> {
+        match first_block.checked_add(block_count) {
+            Some(end) if end <= parent.block_count() => {}
+            _ => return Err("Partition extends past end of device"),
+        }
+        let read_only = parent.is_read_only();
+        Ok(Partition { parent, first_block, block_count, read_only })
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for Partition<D> {
+    fn block_count(&self) -> u64 {
+        self.block_count
+    }
+
+    fn read_blocks(&mut self, start: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+        check_request(self.block_count, start, buffer.len())?;
+        self.parent.read_blocks(self.first_block + start, buffer)
+    }
+
+    fn write_blocks(&mut self, start: u64, buffer: &[u8]) -> Result<(), &'static str> {
+        if self.read_only {
+            return Err("Device is read-only");
+        }
+        check_request(self.block_count, start, buffer.len())?;
+        self.parent.write_blocks(self.first_block + start, buffer)
+    }
+
+    fn flush(&mut self) -> Result<(), &'static str> {
+        self.parent.flush()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn discard(&mut self, start: u64, count: u64) -> Result<(), &'static str> {
+        if self.read_only {
+            return Err("Device is read-only");
+        }
+        check_range(self.block_count, start, count)?;
+        self.parent.discard(self.first_block + start, count)
+    }
+
+    fn write_zeroes(&mut self, start: u64, count: u64) -> Result<(), &'static str> {
+        if self.read_only {
+            return Err("Device is read-only");
+        }
+        check_range(self.block_count, start, count)?;
+        self.parent.write_zeroes(self.first_block + start, count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::block::{RamDisk, BLOCK_SIZE};
+
+    #[test]
+    fn test_offsets_into_parent() {
+        let mut disk = RamDisk::new(16);
+        {
+            let mut part = Partition::new(&mut disk, 4, 8).unwrap();
+            part.write_blocks(0, &[0x5Au8; BLOCK_SIZE]).unwrap();
+        }
+        let mut block = [0u8; BLOCK_SIZE];
+        disk.read_blocks(4, &mut block).unwrap();
+        assert!(block.iter().all(|&b| b == 0x5A));
+    }
+
+    #[test]
+    fn test_bounds() {
+        let mut disk = RamDisk::new(16);
+        assert!(Partition::new(&mut disk, 10, 8).is_err());
+
+        let mut part = Partition::new(&mut disk, 4, 8).unwrap();
+        let mut block = [0u8; BLOCK_SIZE];
+        assert!(part.read_blocks(7, &mut block).is_ok());
+        assert!(part.read_blocks(8, &mut block).is_err());
+    }
+
+    #[test]
+    fn test_write_zeroes() {
+        let mut disk = RamDisk::from_image(alloc::vec![0xFFu8; 16 * BLOCK_SIZE]);
+        {
+            let mut part = Partition::new(&mut disk, 4, 8).unwrap();
+            part.write_zeroes(1, 2).unwrap();
+            assert!(part.write_zeroes(7, 2).is_err());
+        }
+        let bytes = disk.as_bytes();
+        assert!(bytes[5 * BLOCK_SIZE..7 * BLOCK_SIZE].iter().all(|&b| b == 0));
+        assert!(bytes[..5 * BLOCK_SIZE].iter().all(|&b| b == 0xFF));
+        assert!(bytes[7 * BLOCK_SIZE..].iter().all(|&b| b == 0xFF));
+    }
+}
@@ -0,0 +1,495 @@
+// Partition table parsing: GPT (behind a protective MBR) and classic MBR
+//
+// GPT headers and entry arrays are CRC32-checked; if the primary copy at LBA 1
+// is damaged the backup at the end of the disk is used instead. MBR support
+// covers the four primary slots plus logical partitions in an extended
+// partition's EBR chain (numbered from 5, as Linux does).
+
+use super::{BlockDevice, BLOCK_SIZE};
+use crate::system::crc32::crc32;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+const MBR_PARTITION_OFFSET: usize = 446;
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xEE;
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_MIN_HEADER_SIZE: usize = 92;
+/// Refuse entry arrays larger than this (the usual size is 16KB)
+const GPT_MAX_ENTRIES_BYTES: usize = 1024 * 1024;
+/// Guard against EBR chains that loop back on themselves
+const MAX_LOGICAL_PARTITIONS: u32 = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TableKind {
+    Gpt,
+    Mbr,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PartitionType {
+    Gpt([u8; 16]),
+    Mbr(u8),
+}
+
+/// One partition, in blocks of the device the table was read from
+#[derive(Clone, Debug)]
+pub struct PartitionInfo {
+    pub number: u32,
+    pub first_block: u64,
+    pub block_count: u64,
+    pub partition_type: PartitionType,
+    /// GPT partition name (empty for MBR partitions)
+    pub name: String,
+}
+
+impl PartitionInfo {
+    /// Human readable partition type
+    pub fn type_name(&self) -> String {
+        match self.partition_type {
+            PartitionType::Gpt(guid) => {
+                let guid = format_guid(&guid);
+                GPT_TYPES
+                    .iter()
+                    .find(|(known, _)| *known == guid)
+                    .map(|(_, name)| String::from(*name))
+                    .unwrap_or(guid)
+            }
+            PartitionType::Mbr(id) => match mbr_type_name(id) {
+                Some(name) => String::from(name),
+                None => alloc::format!("0x{:02x}", id),
+            },
+        }
+    }
+}
+
+pub struct PartitionTable {
+    pub kind: TableKind,
+    pub partitions: Vec<PartitionInfo>,
+}
+
+const GPT_TYPES: &[(&str, &str)] = &[
+    ("C12A7328-F81F-11D2-BA4B-00A0C93EC93B", "EFI System"),
+    ("21686148-6449-6E6F-744E-656564454649", "BIOS boot"),
+    ("EBD0A0A2-B9E5-4433-87C0-68B6B72699C7", "Basic data"),
+    ("0FC63DAF-8483-4772-8E79-3D69D8477DE4", "Linux filesystem"),
+    ("0657FD6D-A4AB-43C4-84E5-0933C84B4F4F", "Linux swap"),
+    ("E6D6D379-F507-44C2-A23C-238F2A3DF928", "Linux LVM"),
+    ("48465300-0000-11AA-AA11-00306543ECAC", "Apple HFS+"),
+];
+
+fn mbr_type_name(id: u8) -> Option<&'static str> {
+    Some(match id {
+        0x01 => "FAT12",
+        0x04 | 0x06 | 0x0E => "FAT16",
+        0x0B | 0x0C => "FAT32",
+        0x07 => "NTFS/exFAT",
+        0x82 => "Linux swap",
+        0x83 => "Linux",
+        0x8E => "Linux LVM",
+        0xEF => "EFI System",
+        _ => return None,
+    })
+}
+
+fn is_extended(id: u8) -> bool {
+    matches!(id, 0x05 | 0x0F | 0x85)
+}
+
+/// Format a GPT GUID (first three fields are little-endian on disk)
+pub fn format_guid(guid: &[u8; 16]) -> String {
+    alloc::format!(
+        "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+        u32::from_le_bytes([guid[0], guid[1], guid[2], guid[3]]),
+        u16::from_le_bytes([guid[4], guid[5]]),
+        u16::from_le_bytes([guid[6], guid[7]]),
+        guid[8], guid[9], guid[10], guid[11], guid[12], guid[13], guid[14], guid[15]
+    )
+}
+
+fn le_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn le_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// Read the partition table of `device`. Returns Ok(None) for a disk without
+/// one (e.g. a filesystem written straight to sector 0).
+pub fn read_partition_table(device: &mut dyn BlockDevice) -> Result<Option<PartitionTable>, &'static str> {
+    if device.block_count() < 2 {
+        return Ok(None);
+    }
+
+    let mut mbr = [0u8; BLOCK_SIZE];
+    device.read_blocks(0, &mut mbr)?;
+
+    match parse_mbr_entries(&mbr, device.block_count()) {
+        Some(entries) if entries.iter().any(|e| e.type_id == MBR_TYPE_GPT_PROTECTIVE) => {
+            let partitions = read_gpt(device)?.ok_or("Protective MBR but no valid GPT")?;
+            Ok(Some(PartitionTable { kind: TableKind::Gpt, partitions }))
+        }
+        Some(entries) => {
+            let partitions = read_mbr(device, &entries)?;
+            Ok(Some(PartitionTable { kind: TableKind::Mbr, partitions }))
+        }
+        // Some tools write a GPT without a protective MBR
+        None => Ok(read_gpt(device)?.map(|partitions| PartitionTable { kind: TableKind::Gpt, partitions })),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// MBR
+
+struct MbrEntry {
+    slot: u32,
+    type_id: u8,
+    first_lba: u64,
+    sectors: u64,
+}
+
+/// Decode the four primary entries, or None if this sector isn't an MBR
+fn parse_mbr_entries(sector: &[u8], block_count: u64) -> Option<Vec<MbrEntry>> {
+    if sector[510] != 0x55 || sector[511] != 0xAA {
+        return None;
+    }
+
+    let mut entries = Vec::new();
+    for slot in 0..4 {
+        let entry = &sector[MBR_PARTITION_OFFSET + slot * 16..MBR_PARTITION_OFFSET + (slot + 1) * 16];
+        // Boot code (e.g. a FAT boot sector) rarely has valid status bytes
+        if entry[0] != 0x00 && entry[0] != 0x80 {
+            return None;
+        }
+        let type_id = entry[4];
+        let first_lba = le_u32(entry, 8) as u64;
+        let sectors = le_u32(entry, 12) as u64;
+        if type_id == 0 || sectors == 0 {
+            continue;
+        }
+        // The protective entry may claim 0xFFFFFFFF sectors on large disks
+        if type_id != MBR_TYPE_GPT_PROTECTIVE && (first_lba == 0 || first_lba + sectors > block_count) {
+            return None;
+        }
+        entries.push(MbrEntry { slot: slot as u32, type_id, first_lba, sectors });
+    }
+
+    if entries.is_empty() { None } else { Some(entries) }
+}
+
+fn read_mbr(device: &mut dyn BlockDevice, entries: &[MbrEntry]) -> Result<Vec<PartitionInfo>, &'static str> {
+    let mut partitions = Vec::new();
+    let mut extended_start = None;
+
+    for entry in entries {
+        // The extended partition is only a container for the logical ones
+        if is_extended(entry.type_id) {
+            extended_start = Some(entry.first_lba);
+            continue;
+        }
+        partitions.push(PartitionInfo {
+            number: entry.slot + 1,
+            first_block: entry.first_lba,
+            block_count: entry.sectors,
+            partition_type: PartitionType::Mbr(entry.type_id),
+            name: String::new(),
+        });
+    }
+
+    if let Some(extended_start) = extended_start {
+        read_logical_partitions(device, extended_start, &mut partitions)?;
+    }
+    Ok(partitions)
+}
+
+/// Walk the EBR chain of an extended partition. Each EBR holds one logical
+/// partition (relative to the EBR) and a link to the next EBR (relative to the
+/// start of the extended partition).
+fn read_logical_partitions(
+    device: &mut dyn BlockDevice,
+    extended_start: u64,
+    partitions: &mut Vec<PartitionInfo>,
+) -> Result<(), &'static str> {
+    let block_count = device.block_count();
+    let mut ebr_lba = extended_start;
+    let mut sector = [0u8; BLOCK_SIZE];
+
+    for number in 5..5 + MAX_LOGICAL_PARTITIONS {
+        device.read_blocks(ebr_lba, &mut sector)?;
+        if sector[510] != 0x55 || sector[511] != 0xAA {
+            break;
+        }
+
+        let logical = &sector[MBR_PARTITION_OFFSET..MBR_PARTITION_OFFSET + 16];
+        let type_id = logical[4];
+        let first_block = ebr_lba + le_u32(logical, 8) as u64;
+        let sectors = le_u32(logical, 12) as u64;
+        if type_id != 0 && sectors != 0 && first_block + sectors <= block_count {
+            partitions.push(PartitionInfo {
+                number,
+                first_block,
+                block_count: sectors,
+                partition_type: PartitionType::Mbr(type_id),
+                name: String::new(),
+            });
+        }
+
+        let link = &sector[MBR_PARTITION_OFFSET + 16..MBR_PARTITION_OFFSET + 32];
+        let next = le_u32(link, 8) as u64;
+        if link[4] == 0 || next == 0 || extended_start + next >= block_count {
+            break;
+        }
+        ebr_lba = extended_start + next;
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// GPT
+
+struct GptHeader {
+    alternate_lba: u64,
+    entries_lba: u64,
+    entry_count: usize,
+    entry_size: usize,
+    entries_crc: u32,
+}
+
+/// Read and verify the GPT header at `lba`
+fn read_gpt_header(device: &mut dyn BlockDevice, lba: u64) -> Result<Option<GptHeader>, &'static str> {
+    let mut sector = [0u8; BLOCK_SIZE];
+    device.read_blocks(lba, &mut sector)?;
+
+    if &sector[0..8] != GPT_SIGNATURE {
+        return Ok(None);
+    }
+    let header_size = le_u32(&sector, 12) as usize;
+    if header_size < GPT_MIN_HEADER_SIZE || header_size > BLOCK_SIZE {
+        return Ok(None);
+    }
+
+    // The header CRC is computed with its own field zeroed
+    let stored_crc = le_u32(&sector, 16);
+    sector[16..20].fill(0);
+    if crc32(&sector[..header_size]) != stored_crc || le_u64(&sector, 24) != lba {
+        return Ok(None);
+    }
+
+    let entry_count = le_u32(&sector, 80) as usize;
+    let entry_size = le_u32(&sector, 84) as usize;
+    if entry_size < 128 || entry_size % 8 != 0 || entry_count * entry_size > GPT_MAX_ENTRIES_BYTES {
+        return Ok(None);
+    }
+
+    Ok(Some(GptHeader {
+        alternate_lba: le_u64(&sector, 32),
+        entries_lba: le_u64(&sector, 72),
+        entry_count,
+        entry_size,
+        entries_crc: le_u32(&sector, 88),
+    }))
+}
+
+/// Read and verify the entry array a header points to
+fn read_gpt_entries(device: &mut dyn BlockDevice, header: &GptHeader) -> Result<Option<Vec<PartitionInfo>>, &'static str> {
+    let bytes = header.entry_count * header.entry_size;
+    let blocks = (bytes + BLOCK_SIZE - 1) / BLOCK_SIZE;
+    if header.entries_lba + blocks as u64 > device.block_count() {
+        return Ok(None);
+    }
+
+    let mut data = vec![0u8; blocks * BLOCK_SIZE];
+    device.read_blocks(header.entries_lba, &mut data)?;
+    if crc32(&data[..bytes]) != header.entries_crc {
+        return Ok(None);
+    }
+
+    let mut partitions = Vec::new();
+    for (index, entry) in data[..bytes].chunks_exact(header.entry_size).enumerate() {
+        let type_guid: [u8; 16] = entry[0..16].try_into().unwrap();
+        if type_guid == [0; 16] {
+            continue; // Unused slot
+        }
+        let first_lba = le_u64(entry, 32);
+        let last_lba = le_u64(entry, 40);
+        if last_lba < first_lba || last_lba >= device.block_count() {
+            continue;
+        }
+
+        // Name: up to 36 UTF-16LE code units, NUL terminated
+        let units = entry[56..128]
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .take_while(|&unit| unit != 0);
+        let name = char::decode_utf16(units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+
+        partitions.push(PartitionInfo {
+            number: index as u32 + 1,
+            first_block: first_lba,
+            block_count: last_lba - first_lba + 1,
+            partition_type: PartitionType::Gpt(type_guid),
+            name,
+        });
+    }
+    Ok(Some(partitions))
+}
+
+/// Parse the GPT, falling back to the backup copy at the end of the disk.
+/// Ok(None) means there is no GPT; a GPT with both copies damaged is an error.
+fn read_gpt(device: &mut dyn BlockDevice) -> Result<Option<Vec<PartitionInfo>>, &'static str> {
+    let primary = read_gpt_header(device, 1)?;
+    let backup_lba = match &primary {
+        Some(header) if header.alternate_lba < device.block_count() => header.alternate_lba,
+        _ => device.block_count() - 1,
+    };
+
+    if let Some(header) = &primary {
+        if let Some(partitions) = read_gpt_entries(device, header)? {
+            return Ok(Some(partitions));
+        }
+    }
+
+    let backup = read_gpt_header(device, backup_lba)?;
+    if let Some(header) = &backup {
+        if let Some(partitions) = read_gpt_entries(device, header)? {
+            crate::kernel::uart_write_string("GPT: primary table damaged, using backup\r\n");
+            return Ok(Some(partitions));
+        }
+    }
+
+    if primary.is_some() || backup.is_some() {
+        Err("GPT checksum mismatch")
+    } else {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::block::RamDisk;
+
+    const LINUX_FS: [u8; 16] = [
+        0xAF, 0x3D, 0xC6, 0x0F, 0x83, 0x84, 0x72, 0x47, 0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47, 0x7D, 0xE4,
+    ];
+
+    fn mbr_entry(sector: &mut [u8], slot: usize, type_id: u8, first: u32, count: u32) {
+        let entry = &mut sector[MBR_PARTITION_OFFSET + slot * 16..MBR_PARTITION_OFFSET + (slot + 1) * 16];
+        entry[4] = type_id;
+        entry[8..12].copy_from_slice(&first.to_le_bytes());
+        entry[12..16].copy_from_slice(&count.to_le_bytes());
+    }
+
+    fn write_gpt_header(disk: &mut RamDisk, lba: u64, alternate: u64, entries_lba: u64, entries_crc: u32) {
+        let mut header = [0u8; BLOCK_SIZE];
+        header[0..8].copy_from_slice(GPT_SIGNATURE);
+        header[8..12].copy_from_slice(&0x00010000u32.to_le_bytes());
+        header[12..16].copy_from_slice(&92u32.to_le_bytes());
+        header[24..32].copy_from_slice(&lba.to_le_bytes());
+        header[32..40].copy_from_slice(&alternate.to_le_bytes());
+        header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+        header[80..84].copy_from_slice(&128u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+        let crc = crc32(&header[..92]);
+        header[16..20].copy_from_slice(&crc.to_le_bytes());
+        disk.write_blocks(lba, &header).unwrap();
+    }
+
+    /// 4096-block disk with one "Linux filesystem" partition named "data"
+    fn gpt_disk() -> RamDisk {
+        let mut disk = RamDisk::new(4096);
+        let last = disk.block_count() - 1;
+
+        let mut mbr = [0u8; BLOCK_SIZE];
+        mbr_entry(&mut mbr, 0, MBR_TYPE_GPT_PROTECTIVE, 1, last as u32);
+        mbr[510] = 0x55;
+        mbr[511] = 0xAA;
+        disk.write_blocks(0, &mbr).unwrap();
+
+        let mut entries = vec![0u8; 128 * 128];
+        entries[0..16].copy_from_slice(&LINUX_FS);
+        entries[32..40].copy_from_slice(&2048u64.to_le_bytes());
+        entries[40..48].copy_from_slice(&3071u64.to_le_bytes());
+        for (i, unit) in "data".encode_utf16().enumerate() {
+            entries[56 + i * 2..58 + i * 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        let entries_crc = crc32(&entries);
+
+        disk.write_blocks(2, &entries).unwrap();
+        disk.write_blocks(last - 32, &entries).unwrap();
+        write_gpt_header(&mut disk, 1, last, 2, entries_crc);
+        write_gpt_header(&mut disk, last, 1, last - 32, entries_crc);
+        disk
+    }
+
+    #[test]
+    fn test_gpt() {
+        let mut disk = gpt_disk();
+        let table = read_partition_table(&mut disk).unwrap().unwrap();
+        assert_eq!(table.kind, TableKind::Gpt);
+        assert_eq!(table.partitions.len(), 1);
+
+        let part = &table.partitions[0];
+        assert_eq!((part.number, part.first_block, part.block_count), (1, 2048, 1024));
+        assert_eq!(part.name, "data");
+        assert_eq!(part.type_name(), "Linux filesystem");
+    }
+
+    #[test]
+    fn test_gpt_falls_back_to_backup() {
+        let mut disk = gpt_disk();
+        // Corrupt one byte of the primary entry array
+        let mut block = [0u8; BLOCK_SIZE];
+        disk.read_blocks(2, &mut block).unwrap();
+        block[40] ^= 0xFF;
+        disk.write_blocks(2, &block).unwrap();
+
+        let table = read_partition_table(&mut disk).unwrap().unwrap();
+        assert_eq!(table.partitions[0].block_count, 1024);
+
+        // With the backup damaged too, the table is rejected
+        let last = disk.block_count() - 1;
+        disk.write_blocks(last, &[0u8; BLOCK_SIZE]).unwrap();
+        assert!(read_partition_table(&mut disk).is_err());
+    }
+
+    #[test]
+    fn test_mbr_with_logical_partition() {
+        let mut disk = RamDisk::new(4096);
+        let mut mbr = [0u8; BLOCK_SIZE];
+        mbr_entry(&mut mbr, 0, 0x0C, 64, 1000);
+        mbr_entry(&mut mbr, 1, 0x05, 2048, 2048);
+        mbr[510] = 0x55;
+        mbr[511] = 0xAA;
+        disk.write_blocks(0, &mbr).unwrap();
+
+        // EBR at the start of the extended partition: logical 2048+63..+500
+        let mut ebr = [0u8; BLOCK_SIZE];
+        mbr_entry(&mut ebr, 0, 0x83, 63, 500);
+        ebr[510] = 0x55;
+        ebr[511] = 0xAA;
+        disk.write_blocks(2048, &ebr).unwrap();
+
+        let table = read_partition_table(&mut disk).unwrap().unwrap();
+        assert_eq!(table.kind, TableKind::Mbr);
+        let numbers: Vec<u32> = table.partitions.iter().map(|p| p.number).collect();
+        assert_eq!(numbers, vec![1, 5]);
+        assert_eq!(table.partitions[0].type_name(), "FAT32");
+        assert_eq!(table.partitions[1].first_block, 2111);
+    }
+
+    #[test]
+    fn test_unpartitioned_disk() {
+        let mut disk = RamDisk::new(64);
+        assert!(read_partition_table(&mut disk).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_format_guid() {
+        assert_eq!(format_guid(&LINUX_FS), "0FC63DAF-8483-4772-8E79-3D69D8477DE4");
+    }
+}
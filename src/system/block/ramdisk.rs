@@ -0,0 +1,100 @@
+// In-memory block device
+//
+// Backs a BlockDevice with a heap buffer, for exercising filesystem code
+// without a disk. Only built for tests.
+
+use super::{check_request, BlockDevice, BLOCK_SIZE};
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub struct RamDisk {
+    data: Vec<u8>,
+    read_only: bool,
+}
+
+impl RamDisk {
+    /// Create a zero-filled disk of `blocks` blocks
+    pub fn new(blocks: u64) -> Self {
+        RamDisk {
+            data: vec![0; blocks as usize * BLOCK_SIZE],
+            read_only: false,
+        }
+    }
+
+    /// Wrap an existing disk image (padded with zeros to a whole block)
+    pub fn from_image(mut image: Vec<u8>) -> Self {
+        let padded = (image.len() + BLOCK_SIZE - 1) / BLOCK_SIZE * BLOCK_SIZE;
+        image.resize(padded, 0);
+        RamDisk { data: image, read_only: false }
+    }
+
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    /// Raw contents of the disk
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn block_count(&self) -> u64 {
+        (self.data.len() / BLOCK_SIZE) as u64
+    }
+
+    fn read_blocks(&mut self, start: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+        check_request(self.block_count(), start, buffer.len())?;
+        let offset = start as usize * BLOCK_SIZE;
+        buffer.copy_from_slice(&self.data[offset..offset + buffer.len()]);
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, start: u64, buffer: &[u8]) -> Result<(), &'static str> {
+        if self.read_only {
+            return Err("Device is read-only");
+        }
+        check_request(self.block_count(), start, buffer.len())?;
+        let offset = start as usize * BLOCK_SIZE;
+        self.data[offset..offset + buffer.len()].copy_from_slice(buffer);
+        Ok(())
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_write_roundtrip() {
+        let mut disk = RamDisk::new(8);
+        let data = [0xABu8; BLOCK_SIZE * 2];
+        disk.write_blocks(3, &data).unwrap();
+
+        let mut out = [0u8; BLOCK_SIZE * 2];
+        disk.read_blocks(3, &mut out).unwrap();
+        assert_eq!(out, data);
+        assert!(disk.as_bytes()[..3 * BLOCK_SIZE].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_rejects_bad_requests() {
+        let mut disk = RamDisk::new(4);
+        let mut block = [0u8; BLOCK_SIZE];
+        assert!(disk.read_blocks(4, &mut block).is_err());
+        assert!(disk.read_blocks(0, &mut block[..100]).is_err());
+
+        disk.set_read_only(true);
+        assert!(disk.write_blocks(0, &block).is_err());
+    }
+
+    #[test]
+    fn test_from_image_pads_to_block() {
+        let disk = RamDisk::from_image(vec![1u8; 700]);
+        assert_eq!(disk.block_count(), 2);
+    }
+}
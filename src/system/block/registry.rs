@@ -0,0 +1,202 @@
+// Block device registry
+//
+// Disks found at boot are registered under a name (vda, vdb, ...) and their
+// partition tables scanned. Each partition becomes a device of its own (vda1,
+// vda2, ...) that forwards to its parent disk, so filesystems can be mounted on
+// a partition exactly as on a whole disk. Disks are put behind the block cache
+// before anything reads them. Unlocking an encrypted disk or partition adds the
+// decrypted volume as another device ("vdb_crypt"). Devices are addressed by
+// registry index; the root volume is the one holding the system SimpleFS.
+
+use super::cache::CachedDevice;
+use super::crypt::{self, CryptDevice};
+use super::partition_table::{read_partition_table, PartitionInfo, TableKind};
+use super::{BlockDevice, Partition};
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+pub struct BlockDeviceEntry {
+    pub name: String,
+    pub device: Box<dyn BlockDevice>,
+    /// Partition table type, for disks that have one
+    pub table: Option<TableKind>,
+    /// The disk looks partitioned but its table couldn't be read, so nothing
+    /// may treat it as a bare disk
+    pub bad_table: bool,
+    /// For partitions: registry index of the parent disk and the table entry
+    pub partition: Option<(usize, PartitionInfo)>,
+    /// For unlocked encrypted volumes: registry index of the device holding it
+    pub unlocked_from: Option<usize>,
+}
+
+static mut DEVICES: Vec<BlockDeviceEntry> = Vec::new();
+static mut ROOT_VOLUME: Option<usize> = None;
+
+/// Handle to a registered device, used as the parent of partitions and
+/// unlocked volumes
+struct DeviceRef(usize);
+
+impl DeviceRef {
+    fn disk(&self) -> &'static mut dyn BlockDevice {
+        unsafe { &mut *DEVICES[self.0].device }
+    }
+}
+
+impl BlockDevice for DeviceRef {
+    fn block_count(&self) -> u64 {
+        self.disk().block_count()
+    }
+
+    fn read_blocks(&mut self, start: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+        self.disk().read_blocks(start, buffer)
+    }
+
+    fn write_blocks(&mut self, start: u64, buffer: &[u8]) -> Result<(), &'static str> {
+        self.disk().write_blocks(start, buffer)
+    }
+
+    fn flush(&mut self) -> Result<(), &'static str> {
+        self.disk().flush()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.disk().is_read_only()
+    }
+
+    fn discard(&mut self, start: u64, count: u64) -> Result<(), &'static str> {
+        self.disk().discard(start, count)
+    }
+
+    fn write_zeroes(&mut self, start: u64, count: u64) -> Result<(), &'static str> {
+        self.disk().write_zeroes(start, count)
+    }
+}
+
+/// Register a disk and any partitions on it. Returns the disk's index.
+pub fn register_disk(name: &str, device: Box<dyn BlockDevice>) -> usize {
+    let mut device: Box<dyn BlockDevice> = Box::new(CachedDevice::new(device));
+    let (table, bad_table) = match read_partition_table(device.as_mut()) {
+        Ok(table) => (table, false),
+        Err(e) => {
+            crate::kernel::uart_write_string(&alloc::format!("{}: bad partition table: {}\r\n", name, e));
+            (None, true)
+        }
+    };
+
+    unsafe {
+        let disk_index = DEVICES.len();
+        DEVICES.push(BlockDeviceEntry {
+            name: String::from(name),
+            device,
+            table: table.as_ref().map(|t| t.kind),
+            bad_table,
+            partition: None,
+            unlocked_from: None,
+        });
+
+        for info in table.map(|t| t.partitions).unwrap_or_default() {
+            let partition = match Partition::new(DeviceRef(disk_index), info.first_block, info.block_count) {
+                Ok(partition) => partition,
+                Err(_) => continue,
+            };
+            let part_name = alloc::format!("{}{}", name, info.number);
+            crate::kernel::uart_write_string(&alloc::format!(
+                "{}: {} blocks at {} ({})\r\n",
+                part_name, info.block_count, info.first_block, info.type_name()
+            ));
+            DEVICES.push(BlockDeviceEntry {
+                name: part_name,
+                device: Box::new(partition),
+                table: None,
+                bad_table: false,
+                partition: Some((disk_index, info)),
+                unlocked_from: None,
+            });
+        }
+
+        disk_index
+    }
+}
+
+/// All registered devices: disks followed by their partitions, then any
+/// unlocked volumes in the order they were unlocked
+pub fn devices() -> &'static [BlockDeviceEntry] {
+    unsafe { &DEVICES }
+}
+
+pub fn device(index: usize) -> Option<&'static mut dyn BlockDevice> {
+    unsafe { DEVICES.get_mut(index).map(|entry| &mut *entry.device) }
+}
+
+pub fn device_name(index: usize) -> Option<&'static str> {
+    devices().get(index).map(|entry| entry.name.as_str())
+}
+
+/// Whether device `index` can be used as a whole: it is not a disk with a
+/// partition table, readable or not
+pub fn is_bare(index: usize) -> bool {
+    devices().get(index).map_or(false, |entry| entry.table.is_none() && !entry.bad_table)
+}
+
+/// Index of the device called `name` (e.g. "vdb1")
+pub fn find(name: &str) -> Option<usize> {
+    devices().iter().position(|entry| entry.name == name)
+}
+
+/// Indices of the partitions on disk `disk_index`
+pub fn partitions_of(disk_index: usize) -> Vec<usize> {
+    devices()
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| matches!(entry.partition, Some((parent, _)) if parent == disk_index))
+        .map(|(index, _)| index)
+        .collect()
+}
+
+/// Registry index of the unlocked volume on device `index`, if any
+pub fn unlocked_volume(index: usize) -> Option<usize> {
+    devices().iter().position(|entry| entry.unlocked_from == Some(index))
+}
+
+/// Whether device `index` holds an encrypted volume that isn't unlocked yet
+pub fn is_locked(index: usize) -> bool {
+    match device(index) {
+        Some(device) => unlocked_volume(index).is_none() && crypt::is_encrypted(device),
+        None => false,
+    }
+}
+
+/// Unlock the encrypted volume on device `index` with `passphrase` and register
+/// it as a device of its own. Returns the new device's index.
+pub fn unlock(index: usize, passphrase: &str) -> Result<usize, &'static str> {
+    let name = alloc::format!("{}_crypt", device_name(index).ok_or("No such block device")?);
+    if unlocked_volume(index).is_some() {
+        return Err("Already unlocked");
+    }
+    let volume = CryptDevice::unlock(DeviceRef(index), passphrase.as_bytes())?;
+    crate::kernel::uart_write_string(&alloc::format!(
+        "{}: unlocked, {} blocks\r\n", name, volume.block_count()
+    ));
+
+    unsafe {
+        DEVICES.push(BlockDeviceEntry {
+            name,
+            device: Box::new(volume),
+            table: None,
+            bad_table: false,
+            partition: None,
+            unlocked_from: Some(index),
+        });
+        Ok(DEVICES.len() - 1)
+    }
+}
+
+pub fn set_root_volume(index: usize) {
+    unsafe { ROOT_VOLUME = Some(index) }
+}
+
+/// Registry index of the volume holding the system filesystem
+pub fn root_volume() -> Option<usize> {
+    unsafe { ROOT_VOLUME }
+}
@@ -0,0 +1,204 @@
+// archivefs - a tar or zip archive as a read-only filesystem
+//
+// Holds a whole archive in memory and serves its entries as files and
+// directories. Tar archives are decompressed up front if they are gzipped;
+// zip entries are inflated one at a time as they are read. Directories that
+// the archive only implies, by having entries under them, are listed like
+// real ones, and when an archive holds a path more than once the last copy
+// wins, as when extracting. vfs uses this to browse into archive files.
+
+use super::{fat::fat_to_unix, split_path, FileInfo, FileSystem, ATTR_READ_ONLY};
+use crate::system::archive::{self, zip};
+use crate::system::block::BlockDevice;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const READ_ONLY: &str = "Archives are read-only";
+
+/// Where a member's data is
+enum Contents {
+    /// Stored as is at an offset into the tar data
+    Tar(usize),
+    /// Compressed or stored in the zip
+    Zip(zip::Entry),
+}
+
+struct Member {
+    path: String,
+    is_dir: bool,
+    size: usize,
+    /// Unix seconds
+    modified: i64,
+    read_only: bool,
+    contents: Contents,
+}
+
+pub struct ArchiveFs {
+    /// "tar" or "zip"
+    kind: &'static str,
+    data: Vec<u8>,
+    members: Vec<Member>,
+}
+
+impl ArchiveFs {
+    /// Open a zip, tar or gzipped tar, told apart by their contents
+    pub fn new(data: Vec<u8>) -> Result<Self, &'static str> {
+        if zip::is_zip(&data) {
+            let members = zip::parse(&data)?.entries.into_iter().map(Self::zip_member).collect();
+            return Ok(ArchiveFs { kind: "zip", data, members });
+        }
+
+        let (data, archive) = archive::unpack_tar(data)?;
+        let members = archive
+            .entries
+            .into_iter()
+            .map(|entry| Member {
+                path: entry.path,
+                is_dir: entry.is_dir,
+                size: entry.size,
+                modified: entry.modified,
+                read_only: entry.mode & 0o222 == 0,
+                contents: Contents::Tar(entry.offset),
+            })
+            .collect();
+        Ok(ArchiveFs { kind: "tar", data, members })
+    }
+
+    fn zip_member(entry: zip::Entry) -> Member {
+        Member {
+            path: entry.path.clone(),
+            is_dir: entry.is_dir,
+            size: entry.size as usize,
+            // The DOS time is local time like FAT's; prefer the exact one
+            modified: entry.modified.unwrap_or_else(|| fat_to_unix(entry.dos_time.0, entry.dos_time.1)),
+            read_only: entry.mode.map_or(false, |mode| mode & 0o222 == 0),
+            contents: Contents::Zip(entry),
+        }
+    }
+
+    fn lookup(&self, path: &str) -> Option<&Member> {
+        self.members.iter().rev().find(|member| member.path == path)
+    }
+
+    fn is_dir(&self, path: &str) -> bool {
+        path.is_empty()
+            || self.members.iter().any(|member| {
+                member.path.strip_prefix(path).map_or(false, |rest| rest.starts_with('/') || (rest.is_empty() && member.is_dir))
+            })
+    }
+
+    fn info(name: &str, member: &Member) -> FileInfo {
+        let attributes = if member.read_only { ATTR_READ_ONLY } else { 0 };
+        FileInfo { modified: member.modified, attributes, ..FileInfo::new(String::from(name), member.size as u32, member.is_dir) }
+    }
+}
+
+impl FileSystem for ArchiveFs {
+    fn fs_type(&self) -> &'static str {
+        self.kind
+    }
+
+    fn list_dir(&self, _device: &mut dyn BlockDevice, path: &str) -> Result<Vec<FileInfo>, &'static str> {
+        let path = path.trim_matches('/');
+        if !self.is_dir(path) {
+            return Err(if self.lookup(path).is_some() { "Not a directory" } else { "Directory not found" });
+        }
+
+        let mut children: BTreeMap<&str, FileInfo> = BTreeMap::new();
+        for member in &self.members {
+            let rest = match (path.is_empty(), member.path.strip_prefix(path)) {
+                (true, _) => member.path.as_str(),
+                (false, Some(rest)) if rest.starts_with('/') => &rest[1..],
+                _ => continue,
+            };
+            match rest.find('/') {
+                Some(slash) => {
+                    let name = &rest[..slash];
+                    children.entry(name).or_insert_with(|| FileInfo::new(String::from(name), 0, true));
+                }
+                None => {
+                    children.insert(rest, Self::info(rest, member));
+                }
+            }
+        }
+        Ok(children.into_values().collect())
+    }
+
+    fn stat(&self, _device: &mut dyn BlockDevice, path: &str) -> Result<FileInfo, &'static str> {
+        let path = path.trim_matches('/');
+        let (_, name) = split_path(path);
+        match self.lookup(path) {
+            Some(member) => Ok(Self::info(name, member)),
+            None if self.is_dir(path) => Ok(FileInfo::new(String::from(name), 0, true)),
+            None => Err("File not found"),
+        }
+    }
+
+    fn read_file(&self, _device: &mut dyn BlockDevice, path: &str, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        let member = self.lookup(path.trim_matches('/')).ok_or("File not found")?;
+        if member.is_dir {
+            return Err("Is a directory");
+        }
+        if buffer.len() < member.size {
+            return Err("Buffer too small for file");
+        }
+        match &member.contents {
+            Contents::Tar(offset) => buffer[..member.size].copy_from_slice(&self.data[*offset..*offset + member.size]),
+            Contents::Zip(entry) => buffer[..member.size].copy_from_slice(&zip::read(&self.data, entry, member.size)?),
+        }
+        Ok(member.size)
+    }
+
+    fn create_file(&mut self, _device: &mut dyn BlockDevice, _path: &str, _size: u32) -> Result<(), &'static str> {
+        Err(READ_ONLY)
+    }
+
+    fn write_file(&mut self, _device: &mut dyn BlockDevice, _path: &str, _data: &[u8]) -> Result<(), &'static str> {
+        Err(READ_ONLY)
+    }
+
+    fn write_at(&mut self, _device: &mut dyn BlockDevice, _path: &str, _offset: u64, _data: &[u8]) -> Result<usize, &'static str> {
+        Err(READ_ONLY)
+    }
+
+    fn delete_file(&mut self, _device: &mut dyn BlockDevice, _path: &str) -> Result<(), &'static str> {
+        Err(READ_ONLY)
+    }
+
+    fn rename_file(&mut self, _device: &mut dyn BlockDevice, _old_path: &str, _new_path: &str) -> Result<(), &'static str> {
+        Err(READ_ONLY)
+    }
+
+    fn create_dir(&mut self, _device: &mut dyn BlockDevice, _path: &str) -> Result<(), &'static str> {
+        Err(READ_ONLY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::archive::tar;
+    use crate::system::block::RamDisk;
+
+    #[test]
+    fn test_browse_archive() {
+        let mut builder = tar::Builder::new();
+        builder.add_file("site/index.html", b"<p>old</p>", 0);
+        builder.add_file("site/css/style.css", b"p {}", 0);
+        builder.add_file("site/index.html", b"<p>new</p>", 0);
+        builder.add_file("readme", b"hi", 0);
+        let fs = ArchiveFs::new(builder.finish()).unwrap();
+        let mut device = RamDisk::new(1);
+
+        let mut names = |path: &str| fs.list_dir(&mut device, path).unwrap().into_iter().map(|f| (f.name, f.is_dir)).collect::<Vec<_>>();
+        assert_eq!(names(""), [(String::from("readme"), false), (String::from("site"), true)]);
+        assert_eq!(names("site"), [(String::from("css"), true), (String::from("index.html"), false)]);
+        assert!(fs.stat(&mut device, "site/css").unwrap().is_dir);
+        assert!(fs.list_dir(&mut device, "readme").is_err());
+
+        let mut buffer = [0u8; 16];
+        let size = fs.read_file(&mut device, "site/index.html", &mut buffer).unwrap();
+        assert_eq!(&buffer[..size], b"<p>new</p>");
+    }
+}
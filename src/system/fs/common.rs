@@ -0,0 +1,204 @@
+// Filesystem interface
+//
+// FileInfo, the ATTR_* bits and the FileSystem trait: what every filesystem
+// implements and the rest of the system uses. Nothing here depends on the
+// kernel, so the host tools (tools/rostfs) build it along with SimpleFS.
+
+use super::mime;
+use crate::system::block::BlockDevice;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// The file can't be written or deleted
+pub const ATTR_READ_ONLY: u8 = 0x01;
+/// Left out of normal listings
+pub const ATTR_HIDDEN: u8 = 0x02;
+/// Belongs to the system
+pub const ATTR_SYSTEM: u8 = 0x04;
+/// Attribute bits a caller may set
+pub const ATTR_MASK: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM;
+
+/// A directory entry as returned by `FileSystem::list_dir`
+#[derive(Clone, Debug)]
+pub struct FileInfo {
+    pub name: String,
+    pub size: u32,
+    pub is_dir: bool,
+    /// Unix times in seconds, 0 where the filesystem doesn't record one
+    pub created: i64,
+    pub modified: i64,
+    pub accessed: i64,
+    /// ATTR_* bits
+    pub attributes: u8,
+    /// Index into mime::FILE_TYPES
+    pub file_type: u8,
+}
+
+impl FileInfo {
+    /// An entry with no timestamps or attributes, typed by its name
+    pub fn new(name: String, size: u32, is_dir: bool) -> Self {
+        let file_type = if is_dir { mime::DIRECTORY } else { mime::from_name(&name) };
+        FileInfo { name, size, is_dir, created: 0, modified: 0, accessed: 0, attributes: 0, file_type }
+    }
+
+    pub fn attribute_string(&self) -> String {
+        format_attributes(self.attributes)
+    }
+}
+
+/// Space on a volume, as shown by `df`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Usage {
+    pub total_bytes: u64,
+    pub free_bytes: u64,
+    /// Free space as (separate runs, longest run in bytes), where tracked
+    pub free_runs: Option<(u64, u64)>,
+    /// Files stored in more than one piece, and files in all, where tracked
+    pub fragmented_files: Option<(u64, u64)>,
+}
+
+/// A named read-only copy of a volume, as listed by `snapshot list`
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    pub name: String,
+    /// Unix time in seconds
+    pub created: i64,
+}
+
+/// ATTR_* bits as "rhs" flags, '-' for each one not set
+pub fn format_attributes(attributes: u8) -> String {
+    let flag = |bit, c| if attributes & bit != 0 { c } else { '-' };
+    [flag(ATTR_READ_ONLY, 'r'), flag(ATTR_HIDDEN, 'h'), flag(ATTR_SYSTEM, 's')].iter().collect()
+}
+
+/// Operations every mounted filesystem supports
+pub trait FileSystem {
+    /// Name of the on-disk format ("SimpleFS", "FAT32", "ext4", ...)
+    fn fs_type(&self) -> &'static str;
+
+    /// Volume label, empty if the format has none or it wasn't set
+    fn label(&self) -> &str {
+        ""
+    }
+
+    /// Entries of the directory at `path` ("" for the root)
+    fn list_dir(&self, device: &mut dyn BlockDevice, path: &str) -> Result<Vec<FileInfo>, &'static str>;
+
+    /// Read a whole file into `buffer`, returning its size
+    fn read_file(&self, device: &mut dyn BlockDevice, path: &str, buffer: &mut [u8]) -> Result<usize, &'static str>;
+
+    /// Create a file of `size` zeroed bytes
+    fn create_file(&mut self, device: &mut dyn BlockDevice, path: &str, size: u32) -> Result<(), &'static str>;
+
+    /// Replace a file's contents with `data`
+    fn write_file(&mut self, device: &mut dyn BlockDevice, path: &str, data: &[u8]) -> Result<(), &'static str>;
+
+    fn delete_file(&mut self, device: &mut dyn BlockDevice, path: &str) -> Result<(), &'static str>;
+
+    fn rename_file(&mut self, device: &mut dyn BlockDevice, old_path: &str, new_path: &str) -> Result<(), &'static str>;
+
+    fn create_dir(&mut self, _device: &mut dyn BlockDevice, _path: &str) -> Result<(), &'static str> {
+        Err("Directories not supported on this filesystem")
+    }
+
+    /// Set a file's size, zero-filling any new space
+    fn truncate(&mut self, _device: &mut dyn BlockDevice, _path: &str, _size: u32) -> Result<(), &'static str> {
+        Err("Resizing files not supported on this filesystem")
+    }
+
+    /// Change a file's ATTR_* bits
+    fn set_attributes(&mut self, _device: &mut dyn BlockDevice, _path: &str, _attributes: u8) -> Result<(), &'static str> {
+        Err("Attributes not supported on this filesystem")
+    }
+
+    /// Read up to `buffer.len()` bytes starting `offset` bytes into a file,
+    /// returning how many were read (0 at the end)
+    fn read_at(&self, device: &mut dyn BlockDevice, path: &str, offset: u64, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        let mut data = alloc::vec![0u8; self.stat(device, path)?.size as usize];
+        let size = self.read_file(device, path, &mut data)?;
+        let start = core::cmp::min(offset, size as u64) as usize;
+        let count = core::cmp::min(buffer.len(), size - start);
+        buffer[..count].copy_from_slice(&data[start..start + count]);
+        Ok(count)
+    }
+
+    /// Write `data` starting `offset` bytes into a file, growing it as needed
+    fn write_at(&mut self, device: &mut dyn BlockDevice, path: &str, offset: u64, data: &[u8]) -> Result<usize, &'static str> {
+        let mut contents = alloc::vec![0u8; self.stat(device, path)?.size as usize];
+        let size = self.read_file(device, path, &mut contents)?;
+        contents.truncate(size);
+        let end = (offset as usize).checked_add(data.len()).filter(|&end| end <= u32::MAX as usize).ok_or("File too large")?;
+        if end > contents.len() {
+            contents.resize(end, 0);
+        }
+        contents[offset as usize..end].copy_from_slice(data);
+        self.write_file(device, path, &contents)?;
+        Ok(data.len())
+    }
+
+    /// Whether open handles should go through read_at/write_at for every
+    /// access instead of holding the whole file in memory. Device files can
+    /// be bigger than memory, and writes to them should land immediately.
+    fn unbuffered(&self) -> bool {
+        false
+    }
+
+    /// Check the volume for damage, returning one line per problem found.
+    /// With `repair` the problems are fixed as well.
+    fn check(&mut self, _device: &mut dyn BlockDevice, _repair: bool) -> Result<Vec<String>, &'static str> {
+        Err("Checking not supported on this filesystem")
+    }
+
+    /// Size and free space of the volume
+    fn usage(&self, _device: &mut dyn BlockDevice) -> Result<Usage, &'static str> {
+        Err("Usage not available on this filesystem")
+    }
+
+    /// Rearrange the volume so files and free space are contiguous,
+    /// returning how many files were moved
+    fn defrag(&mut self, _device: &mut dyn BlockDevice) -> Result<usize, &'static str> {
+        Err("Defragmenting not supported on this filesystem")
+    }
+
+    /// Snapshots of the volume, oldest first
+    fn snapshots(&self, _device: &mut dyn BlockDevice) -> Result<Vec<Snapshot>, &'static str> {
+        Err("Snapshots not supported on this filesystem")
+    }
+
+    fn create_snapshot(&mut self, _device: &mut dyn BlockDevice, _name: &str) -> Result<(), &'static str> {
+        Err("Snapshots not supported on this filesystem")
+    }
+
+    /// Put every file back as it was in a snapshot
+    fn restore_snapshot(&mut self, _device: &mut dyn BlockDevice, _name: &str) -> Result<(), &'static str> {
+        Err("Snapshots not supported on this filesystem")
+    }
+
+    fn delete_snapshot(&mut self, _device: &mut dyn BlockDevice, _name: &str) -> Result<(), &'static str> {
+        Err("Snapshots not supported on this filesystem")
+    }
+
+    /// Note that a file was read. Filesystems that keep access times may
+    /// update them here; failures are ignored by callers.
+    fn mark_accessed(&mut self, _device: &mut dyn BlockDevice, _path: &str) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    /// Look up a single file or directory
+    fn stat(&self, device: &mut dyn BlockDevice, path: &str) -> Result<FileInfo, &'static str> {
+        let (parent, name) = split_path(path);
+        self.list_dir(device, parent)?
+            .into_iter()
+            .find(|file| file.name == name)
+            .ok_or("File not found")
+    }
+}
+
+/// Split a path into its parent directory and final component
+pub fn split_path(path: &str) -> (&str, &str) {
+    let path = path.trim_end_matches('/');
+    match path.rfind('/') {
+        Some(slash) => (&path[..slash], &path[slash + 1..]),
+        None => ("", path),
+    }
+}
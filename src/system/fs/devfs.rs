@@ -0,0 +1,152 @@
+// devfs - devices as files
+//
+// /dev lists every registered block device (vda, vda1, ...) and the
+// framebuffer (fb0, its pixels as little-endian 32-bit values), plus null.
+// Handles on these files read the device in place rather than loading it, so
+// a disk bigger than memory can still be read with `cat` or `hexdump`.
+// Devices are read-only here; writes to null are accepted and dropped.
+
+use super::{FileInfo, FileSystem, ATTR_READ_ONLY, ATTR_SYSTEM};
+use crate::system::block::{self, BlockDevice, BLOCK_SIZE};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Largest read passed to a block device at once
+const CHUNK_BYTES: usize = 64 * 1024;
+
+enum Device {
+    Null,
+    Framebuffer,
+    /// Registry index
+    Block(usize),
+}
+
+pub struct DevFs;
+
+impl DevFs {
+    fn device(path: &str) -> Result<Device, &'static str> {
+        match path {
+            "null" => Ok(Device::Null),
+            "fb0" => Ok(Device::Framebuffer),
+            _ => block::registry::find(path).map(Device::Block).ok_or("File not found"),
+        }
+    }
+
+    fn size(device: &Device) -> u64 {
+        match device {
+            Device::Null => 0,
+            Device::Framebuffer => crate::gui::framebuffer::get_back_buffer().len() as u64 * 4,
+            Device::Block(index) => block::device(*index).map_or(0, |d| d.block_count() * BLOCK_SIZE as u64),
+        }
+    }
+
+    fn info(name: &str, device: &Device) -> FileInfo {
+        let attributes = match device {
+            Device::Null => ATTR_SYSTEM,
+            _ => ATTR_SYSTEM | ATTR_READ_ONLY,
+        };
+        // Sizes past 4 GiB don't fit in FileInfo; read_at still reaches the whole device
+        let size = core::cmp::min(Self::size(device), u32::MAX as u64) as u32;
+        FileInfo { attributes, ..FileInfo::new(String::from(name), size, false) }
+    }
+}
+
+impl FileSystem for DevFs {
+    fn fs_type(&self) -> &'static str {
+        "devfs"
+    }
+
+    fn list_dir(&self, _device: &mut dyn BlockDevice, path: &str) -> Result<Vec<FileInfo>, &'static str> {
+        if !path.is_empty() {
+            Self::device(path)?;
+            return Err("Not a directory");
+        }
+        let mut files = alloc::vec![Self::info("null", &Device::Null), Self::info("fb0", &Device::Framebuffer)];
+        for (index, entry) in block::registry::devices().iter().enumerate() {
+            files.push(Self::info(&entry.name, &Device::Block(index)));
+        }
+        Ok(files)
+    }
+
+    fn stat(&self, _device: &mut dyn BlockDevice, path: &str) -> Result<FileInfo, &'static str> {
+        if path.is_empty() {
+            return Ok(FileInfo { attributes: ATTR_SYSTEM, ..FileInfo::new(String::new(), 0, true) });
+        }
+        Ok(Self::info(path, &Self::device(path)?))
+    }
+
+    fn read_file(&self, device: &mut dyn BlockDevice, path: &str, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        self.read_at(device, path, 0, buffer)
+    }
+
+    fn read_at(&self, _device: &mut dyn BlockDevice, path: &str, offset: u64, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        let device = Self::device(path)?;
+        let size = Self::size(&device);
+        if offset >= size {
+            return Ok(0);
+        }
+        let count = core::cmp::min(buffer.len() as u64, size - offset) as usize;
+        let buffer = &mut buffer[..count];
+
+        match device {
+            Device::Null => {}
+            Device::Framebuffer => {
+                let pixels = crate::gui::framebuffer::get_back_buffer();
+                for (i, byte) in buffer.iter_mut().enumerate() {
+                    let at = offset as usize + i;
+                    *byte = pixels[at / 4].to_le_bytes()[at % 4];
+                }
+            }
+            Device::Block(index) => {
+                let disk = block::device(index).ok_or("Block device not available")?;
+                let mut chunk = alloc::vec![0u8; CHUNK_BYTES];
+                let mut done = 0;
+                while done < count {
+                    // Read whole blocks around the wanted bytes
+                    let at = offset + done as u64;
+                    let skip = (at % BLOCK_SIZE as u64) as usize;
+                    let want = core::cmp::min(count - done, CHUNK_BYTES - skip);
+                    let blocks = (skip + want + BLOCK_SIZE - 1) / BLOCK_SIZE;
+                    disk.read_blocks(at / BLOCK_SIZE as u64, &mut chunk[..blocks * BLOCK_SIZE])?;
+                    buffer[done..done + want].copy_from_slice(&chunk[skip..skip + want]);
+                    done += want;
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    fn write_at(&mut self, _device: &mut dyn BlockDevice, path: &str, _offset: u64, data: &[u8]) -> Result<usize, &'static str> {
+        match Self::device(path)? {
+            Device::Null => Ok(data.len()),
+            _ => Err("Device is read-only"),
+        }
+    }
+
+    fn unbuffered(&self) -> bool {
+        true
+    }
+
+    fn create_file(&mut self, _device: &mut dyn BlockDevice, _path: &str, _size: u32) -> Result<(), &'static str> {
+        Err("Can't create files in devfs")
+    }
+
+    fn write_file(&mut self, device: &mut dyn BlockDevice, path: &str, data: &[u8]) -> Result<(), &'static str> {
+        self.write_at(device, path, 0, data).map(|_| ())
+    }
+
+    fn truncate(&mut self, _device: &mut dyn BlockDevice, path: &str, _size: u32) -> Result<(), &'static str> {
+        match Self::device(path)? {
+            Device::Null => Ok(()),
+            _ => Err("Can't resize a device"),
+        }
+    }
+
+    fn delete_file(&mut self, _device: &mut dyn BlockDevice, _path: &str) -> Result<(), &'static str> {
+        Err("Can't delete a device")
+    }
+
+    fn rename_file(&mut self, _device: &mut dyn BlockDevice, _old_path: &str, _new_path: &str) -> Result<(), &'static str> {
+        Err("Can't rename a device")
+    }
+}
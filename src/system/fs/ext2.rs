@@ -0,0 +1,731 @@
+// ext2/ext4 filesystem (read-only)
+//
+// Reads volumes made by mkfs.ext2 and mkfs.ext4, so disk images built on Linux
+// can be browsed, edited-from and viewed without conversion. Files are mapped
+// through either classic indirect blocks or ext4 extent trees; 64-bit block
+// numbers and flex_bg layouts are handled. The journal is never replayed, so a
+// volume that still needs recovery is refused rather than read half-updated.
+// Symlinks are followed, both relative and volume-absolute.
+
+use super::{FileInfo, FileSystem, ATTR_HIDDEN, ATTR_READ_ONLY};
+use crate::system::block::{BlockDevice, BLOCK_SIZE};
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+const EXT_MAGIC: u16 = 0xEF53;
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const ROOT_INODE: u32 = 2;
+
+// Incompatible features
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+const INCOMPAT_RECOVER: u32 = 0x0004;
+const INCOMPAT_EXTENTS: u32 = 0x0040;
+const INCOMPAT_64BIT: u32 = 0x0080;
+const INCOMPAT_MMP: u32 = 0x0100;
+const INCOMPAT_FLEX_BG: u32 = 0x0200;
+const INCOMPAT_EA_INODE: u32 = 0x0400;
+const INCOMPAT_CSUM_SEED: u32 = 0x2000;
+const INCOMPAT_LARGEDIR: u32 = 0x4000;
+/// Features that don't change how files and directories are read
+const INCOMPAT_SUPPORTED: u32 = INCOMPAT_FILETYPE | INCOMPAT_RECOVER | INCOMPAT_EXTENTS | INCOMPAT_64BIT
+    | INCOMPAT_MMP | INCOMPAT_FLEX_BG | INCOMPAT_EA_INODE | INCOMPAT_CSUM_SEED | INCOMPAT_LARGEDIR;
+
+const MODE_TYPE_MASK: u16 = 0xF000;
+const MODE_DIR: u16 = 0x4000;
+const MODE_FILE: u16 = 0x8000;
+const MODE_SYMLINK: u16 = 0xA000;
+
+const INODE_FLAG_EXTENTS: u32 = 0x0008_0000;
+
+const EXTENT_MAGIC: u16 = 0xF30A;
+/// Extent lengths above this mark preallocated (unwritten) space
+const EXTENT_INIT_MAX_LEN: u16 = 32768;
+const MAX_EXTENT_DEPTH: u16 = 5;
+
+const MAX_SYMLINK_FOLLOWS: usize = 8;
+const READ_ONLY: &str = "ext2/ext4 volumes are read-only";
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// The fields of an on-disk inode we use
+struct Inode {
+    mode: u16,
+    size: u64,
+    flags: u32,
+    /// 512-byte sectors in use, including any extended attribute block
+    sectors: u32,
+    /// Extended attribute block (0 if none)
+    file_acl: u32,
+    /// i_block: block pointers, an extent tree root or a short symlink target
+    block: [u8; 60],
+    /// Unix times; `created` is the ext4 creation time, else the inode change time
+    created: i64,
+    modified: i64,
+    accessed: i64,
+}
+
+impl Inode {
+    fn kind(&self) -> u16 {
+        self.mode & MODE_TYPE_MASK
+    }
+
+    fn is_dir(&self) -> bool {
+        self.kind() == MODE_DIR
+    }
+}
+
+/// A run of file blocks; physical 0 is a hole (or unwritten space) that reads as zeros
+struct Run {
+    logical: u64,
+    physical: u64,
+    len: u64,
+}
+
+pub struct Ext2Filesystem {
+    block_size: u64,
+    blocks_count: u64,
+    inodes_count: u32,
+    inodes_per_group: u32,
+    inode_size: u64,
+    /// First block of each block group's inode table
+    inode_tables: Vec<u64>,
+    /// Directory entries carry a file type byte and an 8-bit name length
+    filetype: bool,
+    ext4: bool,
+    label: String,
+}
+
+impl Ext2Filesystem {
+    /// Whether `device` holds an ext2/ext3/ext4 superblock, mountable or not.
+    /// Only reads the superblock.
+    pub fn probe(device: &mut dyn BlockDevice) -> bool {
+        let mut sb = [0u8; 1024];
+        device.read_blocks(SUPERBLOCK_OFFSET / BLOCK_SIZE as u64, &mut sb).is_ok() && read_u16(&sb, 56) == EXT_MAGIC
+    }
+
+    pub fn mount(device: &mut dyn BlockDevice) -> Result<Self, &'static str> {
+        let mut sb = [0u8; 1024];
+        device.read_blocks(SUPERBLOCK_OFFSET / BLOCK_SIZE as u64, &mut sb)?;
+
+        if read_u16(&sb, 56) != EXT_MAGIC {
+            return Err("Not an ext2/ext4 volume");
+        }
+
+        let inodes_count = read_u32(&sb, 0);
+        let first_data_block = read_u32(&sb, 20) as u64;
+        let log_block_size = read_u32(&sb, 24);
+        let blocks_per_group = read_u32(&sb, 32) as u64;
+        let inodes_per_group = read_u32(&sb, 40);
+        let rev_level = read_u32(&sb, 76);
+        let incompat = if rev_level >= 1 { read_u32(&sb, 96) } else { 0 };
+        let inode_size = if rev_level >= 1 { read_u16(&sb, 88) as u64 } else { 128 };
+
+        if log_block_size > 6 || blocks_per_group == 0 || inodes_per_group == 0 {
+            return Err("Corrupt ext superblock");
+        }
+        let block_size = 1024u64 << log_block_size;
+        if inode_size < 128 || !inode_size.is_power_of_two() || inode_size > block_size {
+            return Err("Corrupt ext superblock");
+        }
+
+        if incompat & !INCOMPAT_SUPPORTED != 0 {
+            crate::kernel::uart_write_string(&alloc::format!(
+                "ext: unsupported incompatible features 0x{:x}\r\n",
+                incompat & !INCOMPAT_SUPPORTED
+            ));
+            return Err("Unsupported ext2/ext4 features");
+        }
+        if incompat & INCOMPAT_RECOVER != 0 {
+            return Err("ext4 journal needs recovery - mount it on Linux first");
+        }
+
+        let wide = incompat & INCOMPAT_64BIT != 0;
+        let mut blocks_count = read_u32(&sb, 4) as u64;
+        if wide {
+            blocks_count |= (read_u32(&sb, 336) as u64) << 32;
+        }
+        let sectors_per_block = block_size / BLOCK_SIZE as u64;
+        if blocks_count.checked_mul(sectors_per_block).map_or(true, |sectors| sectors > device.block_count()) {
+            return Err("ext volume is larger than the device");
+        }
+        if first_data_block >= blocks_count {
+            return Err("Corrupt ext superblock");
+        }
+
+        // Group descriptor table, in the block after the superblock
+        let desc_size = if wide { core::cmp::max(read_u16(&sb, 254) as u64, 32) } else { 32 };
+        let group_count = (blocks_count - first_data_block + blocks_per_group - 1) / blocks_per_group;
+        if (group_count - 1) * inodes_per_group as u64 >= inodes_count as u64 || desc_size > block_size {
+            return Err("Corrupt ext superblock");
+        }
+        let table_bytes = group_count * desc_size;
+        let table_blocks = (table_bytes + block_size - 1) / block_size;
+        let mut table = vec![0u8; (table_blocks * block_size) as usize];
+        device.read_blocks((first_data_block + 1) * sectors_per_block, &mut table)?;
+
+        let inode_table_blocks = (inodes_per_group as u64 * inode_size + block_size - 1) / block_size;
+        let mut inode_tables = Vec::with_capacity(group_count as usize);
+        for group in 0..group_count as usize {
+            let desc = &table[group * desc_size as usize..];
+            let mut start = read_u32(desc, 8) as u64;
+            if wide && desc_size >= 64 {
+                start |= (read_u32(desc, 0x28) as u64) << 32;
+            }
+            if start == 0 || start + inode_table_blocks > blocks_count {
+                return Err("Corrupt ext group descriptor");
+            }
+            inode_tables.push(start);
+        }
+
+        let label_bytes = &sb[120..136];
+        let label_len = label_bytes.iter().position(|&b| b == 0).unwrap_or(16);
+        let label = String::from_utf8_lossy(&label_bytes[..label_len]).into_owned();
+        let ext4 = incompat & (INCOMPAT_EXTENTS | INCOMPAT_64BIT | INCOMPAT_FLEX_BG) != 0;
+
+        crate::kernel::uart_write_string(&alloc::format!(
+            "{} volume '{}': {} blocks of {} bytes, {} groups (read-only)\r\n",
+            if ext4 { "ext4" } else { "ext2" }, label, blocks_count, block_size, group_count
+        ));
+
+        let fs = Ext2Filesystem {
+            block_size,
+            blocks_count,
+            inodes_count,
+            inodes_per_group,
+            inode_size,
+            inode_tables,
+            filetype: incompat & INCOMPAT_FILETYPE != 0,
+            ext4,
+            label,
+        };
+
+        if !fs.read_inode(device, ROOT_INODE)?.is_dir() {
+            return Err("Corrupt ext volume: root is not a directory");
+        }
+        Ok(fs)
+    }
+
+    fn read_block(&self, device: &mut dyn BlockDevice, block: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+        if block == 0 || block >= self.blocks_count {
+            return Err("Corrupt ext block number");
+        }
+        device.read_blocks(block * (self.block_size / BLOCK_SIZE as u64), buffer)
+    }
+
+    fn read_inode(&self, device: &mut dyn BlockDevice, inode: u32) -> Result<Inode, &'static str> {
+        if inode == 0 || inode > self.inodes_count {
+            return Err("Corrupt ext inode number");
+        }
+        let index = (inode - 1) as u64;
+        let group = (index / self.inodes_per_group as u64) as usize;
+        let offset = (index % self.inodes_per_group as u64) * self.inode_size;
+        let table = *self.inode_tables.get(group).ok_or("Corrupt ext inode number")?;
+
+        // Read the 512-byte sector holding the inode's first 128 bytes
+        let byte = table * self.block_size + offset;
+        let mut sector = [0u8; BLOCK_SIZE];
+        device.read_blocks(byte / BLOCK_SIZE as u64, &mut sector)?;
+        let raw = &sector[(byte % BLOCK_SIZE as u64) as usize..];
+
+        let mode = read_u16(raw, 0);
+        let mut size = read_u32(raw, 4) as u64;
+        if mode & MODE_TYPE_MASK == MODE_FILE {
+            size |= (read_u32(raw, 108) as u64) << 32;
+        }
+        let mut block = [0u8; 60];
+        block.copy_from_slice(&raw[40..100]);
+
+        // i_crtime sits in the extra fields of large inodes
+        let extra = if self.inode_size > 128 && raw.len() >= 132 { read_u16(raw, 128) as usize } else { 0 };
+        let created = if 128 + extra >= 148 && raw.len() >= 148 { read_u32(raw, 144) } else { read_u32(raw, 12) };
+        Ok(Inode {
+            mode,
+            size,
+            flags: read_u32(raw, 32),
+            sectors: read_u32(raw, 28),
+            file_acl: read_u32(raw, 104),
+            block,
+            created: created as i64,
+            modified: read_u32(raw, 16) as i64,
+            accessed: read_u32(raw, 8) as i64,
+        })
+    }
+
+    /// Block runs covering the first `size` bytes of a file
+    fn runs(&self, device: &mut dyn BlockDevice, inode: &Inode) -> Result<Vec<Run>, &'static str> {
+        let blocks = (inode.size + self.block_size - 1) / self.block_size;
+        let mut runs = Vec::new();
+        if inode.flags & INODE_FLAG_EXTENTS != 0 {
+            self.walk_extents(device, &inode.block, MAX_EXTENT_DEPTH, &mut runs)?;
+            runs.sort_unstable_by_key(|run| run.logical);
+        } else {
+            let mut pointers = [0u64; 15];
+            for (i, pointer) in pointers.iter_mut().enumerate() {
+                *pointer = read_u32(&inode.block, i * 4) as u64;
+            }
+            let mut logical = 0;
+            for (i, &pointer) in pointers.iter().enumerate() {
+                let depth = i.saturating_sub(11) as u32; // 12 direct, then single/double/triple indirect
+                if logical >= blocks {
+                    break;
+                }
+                self.walk_indirect(device, pointer, depth, &mut logical, blocks, &mut runs)?;
+            }
+        }
+        Ok(runs)
+    }
+
+    /// Map the blocks under one (possibly indirect) block pointer
+    fn walk_indirect(
+        &self,
+        device: &mut dyn BlockDevice,
+        pointer: u64,
+        depth: u32,
+        logical: &mut u64,
+        limit: u64,
+        runs: &mut Vec<Run>,
+    ) -> Result<(), &'static str> {
+        let per_block = self.block_size / 4;
+        if depth == 0 {
+            match runs.last_mut() {
+                Some(last) if last.logical + last.len == *logical
+                    && (last.physical + last.len == pointer || (last.physical == 0 && pointer == 0)) => last.len += 1,
+                _ => runs.push(Run { logical: *logical, physical: pointer, len: 1 }),
+            }
+            *logical += 1;
+            return Ok(());
+        }
+
+        let span = per_block.pow(depth);
+        if pointer == 0 {
+            // A hole the size of everything this pointer would map
+            let len = core::cmp::min(span, limit - *logical);
+            runs.push(Run { logical: *logical, physical: 0, len });
+            *logical += len;
+            return Ok(());
+        }
+
+        let mut table = vec![0u8; self.block_size as usize];
+        self.read_block(device, pointer, &mut table)?;
+        for i in 0..per_block as usize {
+            if *logical >= limit {
+                break;
+            }
+            self.walk_indirect(device, read_u32(&table, i * 4) as u64, depth - 1, logical, limit, runs)?;
+        }
+        Ok(())
+    }
+
+    /// Collect the leaf extents of an extent tree node
+    fn walk_extents(&self, device: &mut dyn BlockDevice, node: &[u8], max_depth: u16, runs: &mut Vec<Run>) -> Result<(), &'static str> {
+        let entries = read_u16(node, 2) as usize;
+        let depth = read_u16(node, 6);
+        if read_u16(node, 0) != EXTENT_MAGIC || depth > max_depth || 12 + entries * 12 > node.len() {
+            return Err("Corrupt ext extent tree");
+        }
+
+        for i in 0..entries {
+            let entry = &node[12 + i * 12..24 + i * 12];
+            let logical = read_u32(entry, 0) as u64;
+            if depth == 0 {
+                let raw_len = read_u16(entry, 4);
+                let physical = ((read_u16(entry, 6) as u64) << 32) | read_u32(entry, 8) as u64;
+                let (len, physical) = if raw_len > EXTENT_INIT_MAX_LEN {
+                    ((raw_len - EXTENT_INIT_MAX_LEN) as u64, 0) // Unwritten: reads as zeros
+                } else {
+                    (raw_len as u64, physical)
+                };
+                if physical != 0 && physical + len > self.blocks_count {
+                    return Err("Corrupt ext extent");
+                }
+                runs.push(Run { logical, physical, len });
+            } else {
+                let child = ((read_u16(entry, 8) as u64) << 32) | read_u32(entry, 4) as u64;
+                let mut block = vec![0u8; self.block_size as usize];
+                self.read_block(device, child, &mut block)?;
+                self.walk_extents(device, &block, depth - 1, runs)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the first `buffer.len()` bytes of a file
+    fn read_data(&self, device: &mut dyn BlockDevice, inode: &Inode, buffer: &mut [u8]) -> Result<(), &'static str> {
+        buffer.fill(0);
+        let sectors_per_block = self.block_size / BLOCK_SIZE as u64;
+        let mut bounce = [0u8; BLOCK_SIZE];
+
+        for run in self.runs(device, inode)? {
+            let start = run.logical * self.block_size;
+            if run.physical == 0 || start >= buffer.len() as u64 {
+                continue;
+            }
+            let end = core::cmp::min(start + run.len * self.block_size, buffer.len() as u64);
+            let first_sector = run.physical * sectors_per_block;
+
+            // Whole sectors straight into the buffer, the partial tail bounced
+            let whole = (end - start) / BLOCK_SIZE as u64 * BLOCK_SIZE as u64;
+            if whole > 0 {
+                device.read_blocks(first_sector, &mut buffer[start as usize..(start + whole) as usize])?;
+            }
+            if start + whole < end {
+                device.read_blocks(first_sector + whole / BLOCK_SIZE as u64, &mut bounce)?;
+                let tail = (end - start - whole) as usize;
+                buffer[(start + whole) as usize..end as usize].copy_from_slice(&bounce[..tail]);
+            }
+        }
+        Ok(())
+    }
+
+    /// (name, inode) pairs of a directory, without "." and ".."
+    fn read_dir(&self, device: &mut dyn BlockDevice, inode: &Inode) -> Result<Vec<(String, u32)>, &'static str> {
+        let mut data = vec![0u8; inode.size as usize];
+        self.read_data(device, inode, &mut data)?;
+
+        let mut entries = Vec::new();
+        let mut pos = 0;
+        while pos + 8 <= data.len() {
+            let child = read_u32(&data, pos);
+            let rec_len = read_u16(&data, pos + 4) as usize;
+            let name_len = if self.filetype { data[pos + 6] as usize } else { read_u16(&data, pos + 6) as usize };
+            if rec_len < 8 || pos + rec_len > data.len() || 8 + name_len > rec_len {
+                return Err("Corrupt ext directory");
+            }
+
+            // Inode 0 marks unused space (and htree index / checksum tail entries)
+            let name = &data[pos + 8..pos + 8 + name_len];
+            if child != 0 && name != b"." && name != b".." {
+                entries.push((String::from_utf8_lossy(name).into_owned(), child));
+            }
+            pos += rec_len;
+        }
+        Ok(entries)
+    }
+
+    fn symlink_target(&self, device: &mut dyn BlockDevice, inode: &Inode) -> Result<String, &'static str> {
+        let size = inode.size as usize;
+        if size > self.block_size as usize {
+            return Err("Corrupt ext symlink");
+        }
+        // Short targets live in the inode itself ("fast" symlinks): no data
+        // blocks beyond an extended attribute block
+        let xattr_sectors = if inode.file_acl != 0 { (self.block_size / BLOCK_SIZE as u64) as u32 } else { 0 };
+        let fast = inode.sectors == xattr_sectors && size < inode.block.len();
+        let mut target = vec![0u8; size];
+        if fast {
+            target.copy_from_slice(&inode.block[..size]);
+        } else {
+            self.read_data(device, inode, &mut target)?;
+        }
+        String::from_utf8(target).map_err(|_| "Corrupt ext symlink")
+    }
+
+    /// Look up a path, following symlinks on the way (and at the end if `follow`)
+    fn lookup(&self, device: &mut dyn BlockDevice, path: &str, follow: bool) -> Result<(u32, Inode), &'static str> {
+        let mut pending: VecDeque<String> = path.split('/').filter(|p| !p.is_empty()).map(String::from).collect();
+        // Directories from the root down to the current one
+        let mut dirs = vec![ROOT_INODE];
+        let mut current = (ROOT_INODE, self.read_inode(device, ROOT_INODE)?);
+        let mut follows = 0;
+
+        while let Some(part) = pending.pop_front() {
+            if !current.1.is_dir() {
+                return Err("Not a directory");
+            }
+            if part == "." {
+                continue;
+            }
+            if part == ".." {
+                if dirs.len() > 1 {
+                    dirs.pop();
+                }
+                let parent = *dirs.last().unwrap();
+                current = (parent, self.read_inode(device, parent)?);
+                continue;
+            }
+
+            let (_, child) = self
+                .read_dir(device, &current.1)?
+                .into_iter()
+                .find(|(name, _)| *name == part)
+                .ok_or("File not found")?;
+            let inode = self.read_inode(device, child)?;
+
+            if inode.kind() == MODE_SYMLINK && (follow || !pending.is_empty()) {
+                follows += 1;
+                if follows > MAX_SYMLINK_FOLLOWS {
+                    return Err("Too many levels of symbolic links");
+                }
+                let target = self.symlink_target(device, &inode)?;
+                // Absolute targets start again from the volume root
+                if target.starts_with('/') {
+                    dirs.truncate(1);
+                    current = (ROOT_INODE, self.read_inode(device, ROOT_INODE)?);
+                }
+                for part in target.split('/').filter(|p| !p.is_empty()).rev() {
+                    pending.push_front(String::from(part));
+                }
+                continue;
+            }
+
+            if inode.is_dir() {
+                dirs.push(child);
+            }
+            current = (child, inode);
+        }
+        Ok(current)
+    }
+
+    fn info(&self, name: String, inode: &Inode) -> FileInfo {
+        let is_dir = inode.is_dir();
+        let size = if is_dir { 0 } else { core::cmp::min(inode.size, u32::MAX as u64) as u32 };
+        // No write permission for anyone reads as read-only; dotfiles are hidden
+        let mut attributes = 0;
+        if inode.mode & 0o222 == 0 {
+            attributes |= ATTR_READ_ONLY;
+        }
+        if name.starts_with('.') {
+            attributes |= ATTR_HIDDEN;
+        }
+        FileInfo {
+            created: inode.created,
+            modified: inode.modified,
+            accessed: inode.accessed,
+            attributes,
+            ..FileInfo::new(name, size, is_dir)
+        }
+    }
+}
+
+impl FileSystem for Ext2Filesystem {
+    fn fs_type(&self) -> &'static str {
+        if self.ext4 { "ext4" } else { "ext2" }
+    }
+
+    /// Volume name from the superblock
+    fn label(&self) -> &str {
+        &self.label
+    }
+
+    fn list_dir(&self, device: &mut dyn BlockDevice, path: &str) -> Result<Vec<FileInfo>, &'static str> {
+        let (_, dir) = self.lookup(device, path, true)?;
+        if !dir.is_dir() {
+            return Err("Not a directory");
+        }
+
+        let mut files = Vec::new();
+        for (name, child) in self.read_dir(device, &dir)? {
+            let mut inode = self.read_inode(device, child)?;
+            if inode.kind() == MODE_SYMLINK {
+                // Show what the link points at; a dangling link shows as an empty file
+                let link = alloc::format!("{}/{}", path, name);
+                match self.lookup(device, &link, true) {
+                    Ok((_, target)) => inode = target,
+                    Err(_) => inode.size = 0,
+                }
+            }
+            files.push(self.info(name, &inode));
+        }
+        Ok(files)
+    }
+
+    fn stat(&self, device: &mut dyn BlockDevice, path: &str) -> Result<FileInfo, &'static str> {
+        let (_, inode) = self.lookup(device, path, true)?;
+        let (_, name) = super::split_path(path);
+        Ok(self.info(String::from(name), &inode))
+    }
+
+    fn read_file(&self, device: &mut dyn BlockDevice, path: &str, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        let (_, inode) = self.lookup(device, path, true)?;
+        if inode.is_dir() {
+            return Err("Is a directory");
+        }
+        let size = inode.size as usize;
+        if buffer.len() < size {
+            return Err("Buffer too small for file");
+        }
+        self.read_data(device, &inode, &mut buffer[..size])?;
+        Ok(size)
+    }
+
+    fn create_file(&mut self, _device: &mut dyn BlockDevice, _path: &str, _size: u32) -> Result<(), &'static str> {
+        Err(READ_ONLY)
+    }
+
+    fn write_file(&mut self, _device: &mut dyn BlockDevice, _path: &str, _data: &[u8]) -> Result<(), &'static str> {
+        Err(READ_ONLY)
+    }
+
+    fn delete_file(&mut self, _device: &mut dyn BlockDevice, _path: &str) -> Result<(), &'static str> {
+        Err(READ_ONLY)
+    }
+
+    fn rename_file(&mut self, _device: &mut dyn BlockDevice, _old_path: &str, _new_path: &str) -> Result<(), &'static str> {
+        Err(READ_ONLY)
+    }
+
+    fn create_dir(&mut self, _device: &mut dyn BlockDevice, _path: &str) -> Result<(), &'static str> {
+        Err(READ_ONLY)
+    }
+
+    fn truncate(&mut self, _device: &mut dyn BlockDevice, _path: &str, _size: u32) -> Result<(), &'static str> {
+        Err(READ_ONLY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::block::RamDisk;
+
+    const BS: usize = 1024;
+
+    fn put_u16(image: &mut [u8], offset: usize, value: u16) {
+        image[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_u32(image: &mut [u8], offset: usize, value: u32) {
+        image[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// Write inode `number` (128-byte inodes, table at block 5)
+    fn put_inode(image: &mut [u8], number: usize, mode: u16, size: u32, flags: u32, block: &[u8]) {
+        let at = 5 * BS + (number - 1) * 128;
+        put_u16(image, at, mode);
+        put_u32(image, at + 4, size);
+        put_u32(image, at + 32, flags);
+        image[at + 40..at + 40 + block.len()].copy_from_slice(block);
+    }
+
+    /// Write directory records filling one block
+    fn put_dir(image: &mut [u8], block: usize, entries: &[(&str, u32, u8)]) {
+        let mut pos = block * BS;
+        for (i, (name, inode, kind)) in entries.iter().enumerate() {
+            let rec_len = if i + 1 == entries.len() { block * BS + BS - pos } else { (8 + name.len() + 3) & !3 };
+            put_u32(image, pos, *inode);
+            put_u16(image, pos + 4, rec_len as u16);
+            image[pos + 6] = name.len() as u8;
+            image[pos + 7] = *kind;
+            image[pos + 8..pos + 8 + name.len()].copy_from_slice(name.as_bytes());
+            pos += rec_len;
+        }
+    }
+
+    /// A 64 KB volume with 1 KB blocks and one block group:
+    ///   /hello.txt       direct block
+    ///   /sub/inner.txt   extent-mapped, with a hole
+    ///   /sub/up          symlink to ../hello.txt
+    ///   /link            symlink to sub/inner.txt
+    fn make_volume(incompat: u32) -> RamDisk {
+        let mut image = vec![0u8; 64 * BS];
+
+        let sb = BS;
+        put_u32(&mut image, sb, 16); // inodes
+        put_u32(&mut image, sb + 4, 64); // blocks
+        put_u32(&mut image, sb + 20, 1); // first data block
+        put_u32(&mut image, sb + 32, 8192); // blocks per group
+        put_u32(&mut image, sb + 40, 16); // inodes per group
+        put_u16(&mut image, sb + 56, EXT_MAGIC);
+        put_u32(&mut image, sb + 76, 1); // dynamic revision
+        put_u16(&mut image, sb + 88, 128);
+        put_u32(&mut image, sb + 96, INCOMPAT_FILETYPE | incompat);
+        image[sb + 120..sb + 127].copy_from_slice(b"testvol");
+
+        // Group descriptor: inode table at block 5
+        put_u32(&mut image, 2 * BS + 8, 5);
+
+        let mut direct = [0u8; 60];
+        put_u32(&mut direct, 0, 7);
+        put_inode(&mut image, 2, MODE_DIR | 0o755, BS as u32, 0, &direct);
+        put_dir(&mut image, 7, &[(".", 2, 2), ("..", 2, 2), ("hello.txt", 12, 1), ("sub", 13, 2), ("link", 15, 7)]);
+
+        put_u32(&mut direct, 0, 8);
+        put_inode(&mut image, 12, MODE_FILE | 0o644, 13, 0, &direct);
+        image[8 * BS..8 * BS + 13].copy_from_slice(b"Hello, ext2!\n");
+
+        put_u32(&mut direct, 0, 9);
+        put_inode(&mut image, 13, MODE_DIR | 0o755, BS as u32, 0, &direct);
+        put_dir(&mut image, 9, &[(".", 13, 2), ("..", 2, 2), ("inner.txt", 14, 1), ("up", 16, 7)]);
+
+        // Blocks 0-1 at 20-21, block 2 a hole, block 3 at 30
+        let mut tree = [0u8; 60];
+        put_u16(&mut tree, 0, EXTENT_MAGIC);
+        put_u16(&mut tree, 2, 2);
+        put_u16(&mut tree, 4, 4);
+        put_u32(&mut tree, 12, 0);
+        put_u16(&mut tree, 16, 2);
+        put_u32(&mut tree, 20, 20);
+        put_u32(&mut tree, 24, 3);
+        put_u16(&mut tree, 28, 1);
+        put_u32(&mut tree, 32, 30);
+        put_inode(&mut image, 14, MODE_FILE | 0o644, (4 * BS - 100) as u32, INODE_FLAG_EXTENTS, &tree);
+        image[20 * BS..22 * BS].fill(0xAA);
+        image[30 * BS..31 * BS].fill(0xBB);
+
+        put_inode(&mut image, 15, MODE_SYMLINK | 0o777, 13, 0, b"sub/inner.txt");
+        put_inode(&mut image, 16, MODE_SYMLINK | 0o777, 12, 0, b"../hello.txt");
+
+        RamDisk::from_image(image)
+    }
+
+    #[test]
+    fn test_mount_and_read() {
+        let mut disk = make_volume(0);
+        let fs = Ext2Filesystem::mount(&mut disk).unwrap();
+        assert_eq!(fs.fs_type(), "ext2");
+        assert_eq!(fs.label(), "testvol");
+
+        let files = fs.list_dir(&mut disk, "").unwrap();
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["hello.txt", "sub", "link"]);
+        assert!(files[1].is_dir);
+        assert_eq!(files[2].size, (4 * BS - 100) as u32);
+
+        let mut buffer = [0u8; 64];
+        assert_eq!(fs.read_file(&mut disk, "hello.txt", &mut buffer).unwrap(), 13);
+        assert_eq!(&buffer[..13], b"Hello, ext2!\n");
+    }
+
+    #[test]
+    fn test_extents_and_symlinks() {
+        let mut disk = make_volume(INCOMPAT_EXTENTS);
+        let mut fs = Ext2Filesystem::mount(&mut disk).unwrap();
+        assert_eq!(fs.fs_type(), "ext4");
+
+        let mut buffer = vec![0xFFu8; 4 * BS];
+        let size = fs.read_file(&mut disk, "link", &mut buffer).unwrap();
+        assert_eq!(size, 4 * BS - 100);
+        assert!(buffer[..2 * BS].iter().all(|&b| b == 0xAA));
+        assert!(buffer[2 * BS..3 * BS].iter().all(|&b| b == 0));
+        assert!(buffer[3 * BS..size].iter().all(|&b| b == 0xBB));
+
+        let mut buffer = [0u8; 64];
+        assert_eq!(fs.read_file(&mut disk, "sub/up", &mut buffer).unwrap(), 13);
+        assert_eq!(fs.stat(&mut disk, "link/../hello.txt").map(|f| f.size), Err("Not a directory"));
+        assert!(fs.write_file(&mut disk, "hello.txt", b"x").is_err());
+    }
+
+    #[test]
+    fn test_rejects_unsupported_volumes() {
+        let mut blank = RamDisk::new(128);
+        assert!(!Ext2Filesystem::probe(&mut blank));
+        assert!(Ext2Filesystem::mount(&mut blank).is_err());
+
+        // Refused volumes are still recognised, so boot won't format them
+        const INCOMPAT_INLINE_DATA: u32 = 0x8000;
+        let mut disk = make_volume(INCOMPAT_INLINE_DATA);
+        assert!(Ext2Filesystem::probe(&mut disk));
+        assert_eq!(Ext2Filesystem::mount(&mut disk).err(), Some("Unsupported ext2/ext4 features"));
+        let mut disk = make_volume(INCOMPAT_RECOVER);
+        assert!(Ext2Filesystem::probe(&mut disk));
+        assert!(Ext2Filesystem::mount(&mut disk).is_err());
+    }
+}
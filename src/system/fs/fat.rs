@@ -0,0 +1,1349 @@
+// FAT12/16/32 filesystem
+//
+// Reads and writes volumes made by mkfs.vfat or mtools, so files can be shared
+// with the host. Long file names (VFAT LFN entries) are read and written; a new
+// name that doesn't fit 8.3 gets LFN entries plus a generated NAME~N short name.
+// The FAT is read and written a sector at a time through the block device (the
+// block cache keeps that cheap), and every FAT copy is updated on each change.
+
+use super::{FileInfo, FileSystem, Usage, ATTR_MASK};
+use crate::system::block::{BlockDevice, BLOCK_SIZE};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_HIDDEN: u8 = 0x02;
+const ATTR_SYSTEM: u8 = 0x04;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+
+const DIR_ENTRY_SIZE: usize = 32;
+const ENTRY_END: u8 = 0x00;
+const ENTRY_DELETED: u8 = 0xE5;
+/// A short name starting with 0xE5 is stored with 0x05 instead
+const ENTRY_KANJI_E5: u8 = 0x05;
+
+/// NT reserved byte: base name / extension are stored upper case but shown lower case
+const NTRES_LOWER_BASE: u8 = 0x08;
+const NTRES_LOWER_EXT: u8 = 0x10;
+
+const LFN_LAST_ENTRY: u8 = 0x40;
+const LFN_CHARS_PER_ENTRY: usize = 13;
+/// Byte offsets of the 13 UCS-2 characters inside an LFN entry
+const LFN_CHAR_OFFSETS: [usize; 13] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+const MAX_NAME_LEN: usize = 255;
+
+const FSINFO_LEAD_SIG: u32 = 0x41615252;
+const FSINFO_STRUC_SIG: u32 = 0x61417272;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+/// A directory: the fixed root region of FAT12/16, or a cluster chain
+#[derive(Clone, Copy, PartialEq)]
+enum Dir {
+    FixedRoot,
+    Clusters(u32),
+}
+
+/// Raw directory contents and the device block behind each BLOCK_SIZE chunk
+struct DirData {
+    bytes: Vec<u8>,
+    blocks: Vec<u64>,
+}
+
+/// A directory entry with its long name resolved
+struct DirEntry {
+    name: String,
+    short_name: [u8; 11],
+    attr: u8,
+    first_cluster: u32,
+    size: u32,
+    /// Unix times (0 if unset)
+    created: i64,
+    modified: i64,
+    accessed: i64,
+    /// Byte offset of the short entry within the directory
+    offset: usize,
+    /// Byte offset of the first LFN entry (== offset if there are none)
+    lfn_start: usize,
+}
+
+impl DirEntry {
+    fn is_dir(&self) -> bool {
+        self.attr & ATTR_DIRECTORY != 0
+    }
+
+    fn info(self) -> FileInfo {
+        let is_dir = self.is_dir();
+        FileInfo {
+            size: if is_dir { 0 } else { self.size },
+            created: self.created,
+            modified: self.modified,
+            accessed: self.accessed,
+            // The low three FAT attribute bits are the same as ATTR_*
+            attributes: self.attr & ATTR_MASK,
+            ..FileInfo::new(self.name, 0, is_dir)
+        }
+    }
+
+    /// The directory this entry points to (".." entries use 0 for the root)
+    fn as_dir(&self, root: Dir) -> Dir {
+        if self.first_cluster == 0 { root } else { Dir::Clusters(self.first_cluster) }
+    }
+}
+
+/// Boot signature plus the jump instruction every FAT boot sector starts with
+fn is_boot_sector(boot: &[u8]) -> bool {
+    boot[510] == 0x55 && boot[511] == 0xAA && (boot[0] == 0xEB || boot[0] == 0xE9)
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+fn write_u16(bytes: &mut [u8], offset: usize, value: u16) {
+    bytes[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Checksum of a short name, stored in each of its LFN entries
+fn lfn_checksum(short_name: &[u8; 11]) -> u8 {
+    short_name.iter().fold(0u8, |sum, &c| ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(c))
+}
+
+/// Characters allowed in a short name besides letters and digits
+fn is_short_name_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"$%'-_@~`!(){}^#&".contains(&c)
+}
+
+fn validate_name(name: &str) -> Result<(), &'static str> {
+    if name.is_empty() || name == "." || name == ".." {
+        return Err("Invalid filename");
+    }
+    if name.chars().count() > MAX_NAME_LEN {
+        return Err("Filename too long (max 255 chars)");
+    }
+    if name.ends_with('.') || name.ends_with(' ') {
+        return Err("Filename can't end with '.' or space");
+    }
+    if name.chars().any(|c| (c as u32) < 0x20 || "\"*/:<>?\\|".contains(c)) {
+        return Err("Filename contains invalid characters");
+    }
+    Ok(())
+}
+
+/// Display form of a short name, honouring the NT lower-case flags
+fn format_short_name(short_name: &[u8; 11], ntres: u8) -> String {
+    let mut raw = *short_name;
+    if raw[0] == ENTRY_KANJI_E5 {
+        raw[0] = ENTRY_DELETED;
+    }
+    let convert = |bytes: &[u8], lower: bool| -> String {
+        let text: String = bytes.iter().map(|&b| b as char).collect();
+        let text = String::from(text.trim_end());
+        if lower { text.to_ascii_lowercase() } else { text }
+    };
+    let base = convert(&raw[..8], ntres & NTRES_LOWER_BASE != 0);
+    let ext = convert(&raw[8..], ntres & NTRES_LOWER_EXT != 0);
+    if ext.is_empty() { base } else { alloc::format!("{}.{}", base, ext) }
+}
+
+/// `name` as a plain 8.3 short name (plus NT case flags), if it is one
+fn exact_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, ext) = match name.rfind('.') {
+        Some(dot) => (&name[..dot], &name[dot + 1..]),
+        None => (name, ""),
+    };
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 {
+        return None;
+    }
+    if !base.bytes().chain(ext.bytes()).all(is_short_name_char) {
+        return None;
+    }
+
+    // Each part must be all upper or all lower case to round-trip
+    let case_flag = |part: &str, flag: u8| -> Option<u8> {
+        let has_upper = part.bytes().any(|b| b.is_ascii_uppercase());
+        let has_lower = part.bytes().any(|b| b.is_ascii_lowercase());
+        match (has_upper, has_lower) {
+            (true, true) => None,
+            (false, true) => Some(flag),
+            _ => Some(0),
+        }
+    };
+    let ntres = case_flag(base, NTRES_LOWER_BASE)? | case_flag(ext, NTRES_LOWER_EXT)?;
+
+    let mut short = [b' '; 11];
+    for (i, b) in base.bytes().enumerate() {
+        short[i] = b.to_ascii_uppercase();
+    }
+    for (i, b) in ext.bytes().enumerate() {
+        short[8 + i] = b.to_ascii_uppercase();
+    }
+    Some((short, ntres))
+}
+
+/// Generate a unique NAME~N short name for a long name
+fn generate_short_name(name: &str, taken: &[[u8; 11]]) -> Result<[u8; 11], &'static str> {
+    let name = name.trim_start_matches('.');
+    let (base, ext) = match name.rfind('.') {
+        Some(dot) => (&name[..dot], &name[dot + 1..]),
+        None => (name, ""),
+    };
+    let clean = |part: &str, max: usize| -> Vec<u8> {
+        part.bytes()
+            .filter(|&b| b != b' ' && b != b'.')
+            .map(|b| {
+                let b = b.to_ascii_uppercase();
+                if is_short_name_char(b) { b } else { b'_' }
+            })
+            .take(max)
+            .collect()
+    };
+    let mut base = clean(base, 8);
+    if base.is_empty() {
+        base.push(b'_');
+    }
+    let ext = clean(ext, 3);
+
+    for n in 1..1_000_000u32 {
+        let tail = alloc::format!("~{}", n);
+        let keep = core::cmp::min(base.len(), 8 - tail.len());
+        let mut short = [b' '; 11];
+        short[..keep].copy_from_slice(&base[..keep]);
+        short[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+        short[8..8 + ext.len()].copy_from_slice(&ext);
+        if !taken.contains(&short) {
+            return Ok(short);
+        }
+    }
+    Err("No free short name")
+}
+
+/// Current local time as FAT (date, time)
+#[cfg(not(test))]
+fn fat_timestamp() -> (u16, u16) {
+    let now = crate::kernel::clock::now_local().datetime;
+    let year = (now.year - 1980).clamp(0, 127) as u16;
+    let date = year << 9 | (now.month as u16) << 5 | now.day as u16;
+    let time = (now.hour as u16) << 11 | (now.minute as u16) << 5 | (now.second as u16) / 2;
+    (date, time)
+}
+
+#[cfg(test)]
+fn fat_timestamp() -> (u16, u16) {
+    (1 << 5 | 1, 0) // 1980-01-01 00:00
+}
+
+/// Unix time of a FAT (date, time) pair, which is in local time
+pub fn fat_to_unix(date: u16, time: u16) -> i64 {
+    let (month, day) = (((date >> 5) & 0x0F) as u8, (date & 0x1F) as u8);
+    if month == 0 || day == 0 {
+        return 0;
+    }
+    let days = crate::kernel::clock::days_from_civil(1980 + (date >> 9) as i32, month, day);
+    let seconds = (time >> 11) as i64 * 3600 + ((time >> 5) & 0x3F) as i64 * 60 + (time & 0x1F) as i64 * 2;
+    days * 24 * 60 * 60 + seconds - local_offset()
+}
+
+/// Seconds the local timezone is ahead of UTC
+#[cfg(not(test))]
+fn local_offset() -> i64 {
+    crate::kernel::clock::now_local().utc_offset as i64
+}
+
+#[cfg(test)]
+fn local_offset() -> i64 {
+    0
+}
+
+/// Split a path into its non-empty components
+fn components(path: &str) -> Vec<&str> {
+    path.split('/').filter(|part| !part.is_empty()).collect()
+}
+
+pub struct FatFilesystem {
+    fat_type: FatType,
+    blocks_per_cluster: u64,
+    /// First block and size in blocks of the first FAT copy
+    fat_start: u64,
+    fat_blocks: u64,
+    num_fats: u64,
+    /// FAT12/16 fixed root directory region
+    root_dir_start: u64,
+    root_dir_blocks: u64,
+    /// FAT32 root directory cluster
+    root_cluster: u32,
+    data_start: u64,
+    cluster_count: u32,
+    fs_info_block: Option<u64>,
+    free_count: Option<u32>,
+    next_free: u32,
+    label: String,
+}
+
+impl FatFilesystem {
+    /// Whether `device` starts with a FAT boot sector. Only reads block 0.
+    pub fn probe(device: &mut dyn BlockDevice) -> bool {
+        let mut boot = [0u8; BLOCK_SIZE];
+        device.read_blocks(0, &mut boot).is_ok() && is_boot_sector(&boot)
+    }
+
+    /// Mount the FAT volume on `device`
+    pub fn mount(device: &mut dyn BlockDevice) -> Result<Self, &'static str> {
+        let mut boot = [0u8; BLOCK_SIZE];
+        device.read_blocks(0, &mut boot)?;
+
+        if !is_boot_sector(&boot) {
+            return Err("Not a FAT volume");
+        }
+
+        let bytes_per_sector = read_u16(&boot, 11) as u64;
+        let sectors_per_cluster = boot[13] as u64;
+        let reserved_sectors = read_u16(&boot, 14) as u64;
+        let num_fats = boot[16] as u64;
+        let root_entries = read_u16(&boot, 17) as u64;
+        let total_sectors = match read_u16(&boot, 19) {
+            0 => read_u32(&boot, 32) as u64,
+            n => n as u64,
+        };
+        let fat_sectors = match read_u16(&boot, 22) {
+            0 => read_u32(&boot, 36) as u64,
+            n => n as u64,
+        };
+
+        if !matches!(bytes_per_sector, 512 | 1024 | 2048 | 4096)
+            || !sectors_per_cluster.is_power_of_two()
+            || reserved_sectors == 0
+            || num_fats == 0
+            || fat_sectors == 0
+        {
+            return Err("Not a FAT volume");
+        }
+
+        let blocks_per_sector = bytes_per_sector / BLOCK_SIZE as u64;
+        if total_sectors * blocks_per_sector > device.block_count() {
+            return Err("FAT volume is larger than the device");
+        }
+
+        let root_dir_sectors = (root_entries * DIR_ENTRY_SIZE as u64 + bytes_per_sector - 1) / bytes_per_sector;
+        let data_start_sector = reserved_sectors + num_fats * fat_sectors + root_dir_sectors;
+        if data_start_sector >= total_sectors {
+            return Err("Corrupt FAT volume");
+        }
+        let cluster_count = ((total_sectors - data_start_sector) / sectors_per_cluster) as u32;
+
+        // The cluster count alone decides the FAT type
+        let fat_type = if cluster_count < 4085 {
+            FatType::Fat12
+        } else if cluster_count < 65525 {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        };
+
+        let (root_cluster, fs_info_sector, label_offset) = if fat_type == FatType::Fat32 {
+            (read_u32(&boot, 44), read_u16(&boot, 48) as u64, 71)
+        } else {
+            (0, 0, 43)
+        };
+
+        let mut fs = FatFilesystem {
+            fat_type,
+            blocks_per_cluster: sectors_per_cluster * blocks_per_sector,
+            fat_start: reserved_sectors * blocks_per_sector,
+            fat_blocks: fat_sectors * blocks_per_sector,
+            num_fats,
+            root_dir_start: (reserved_sectors + num_fats * fat_sectors) * blocks_per_sector,
+            root_dir_blocks: root_dir_sectors * blocks_per_sector,
+            root_cluster,
+            data_start: data_start_sector * blocks_per_sector,
+            cluster_count,
+            fs_info_block: None,
+            free_count: None,
+            next_free: 2,
+            label: format_short_name(boot[label_offset..label_offset + 11].try_into().unwrap(), 0),
+        };
+
+        if fat_type == FatType::Fat32 {
+            if !fs.is_valid_cluster(root_cluster) {
+                return Err("Corrupt FAT32 root cluster");
+            }
+            if fs_info_sector != 0 && fs_info_sector < reserved_sectors {
+                fs.read_fs_info(device, fs_info_sector * blocks_per_sector)?;
+            }
+        }
+
+        Ok(fs)
+    }
+
+
+    fn read_fs_info(&mut self, device: &mut dyn BlockDevice, block: u64) -> Result<(), &'static str> {
+        let mut info = [0u8; BLOCK_SIZE];
+        device.read_blocks(block, &mut info)?;
+        if read_u32(&info, 0) != FSINFO_LEAD_SIG || read_u32(&info, 484) != FSINFO_STRUC_SIG {
+            return Ok(()); // No usable FSInfo; the hints are optional
+        }
+        self.fs_info_block = Some(block);
+
+        let free_count = read_u32(&info, 488);
+        if free_count <= self.cluster_count {
+            self.free_count = Some(free_count);
+        }
+        let next_free = read_u32(&info, 492);
+        if self.is_valid_cluster(next_free) {
+            self.next_free = next_free;
+        }
+        Ok(())
+    }
+
+    /// Store the free cluster hints back in the FSInfo sector
+    fn write_fs_info(&self, device: &mut dyn BlockDevice) -> Result<(), &'static str> {
+        if let Some(block) = self.fs_info_block {
+            let mut info = [0u8; BLOCK_SIZE];
+            device.read_blocks(block, &mut info)?;
+            write_u32(&mut info, 488, self.free_count.unwrap_or(0xFFFF_FFFF));
+            write_u32(&mut info, 492, self.next_free);
+            device.write_blocks(block, &info)?;
+        }
+        Ok(())
+    }
+
+    /// Finish a modifying operation: update FSInfo and make it all durable
+    fn commit(&self, device: &mut dyn BlockDevice) -> Result<(), &'static str> {
+        self.write_fs_info(device)?;
+        device.flush()
+    }
+
+    fn root(&self) -> Dir {
+        match self.fat_type {
+            FatType::Fat32 => Dir::Clusters(self.root_cluster),
+            _ => Dir::FixedRoot,
+        }
+    }
+
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster < self.cluster_count + 2
+    }
+
+    fn bytes_per_cluster(&self) -> usize {
+        self.blocks_per_cluster as usize * BLOCK_SIZE
+    }
+
+    fn cluster_block(&self, cluster: u32) -> u64 {
+        self.data_start + (cluster as u64 - 2) * self.blocks_per_cluster
+    }
+
+    fn end_of_chain(&self) -> u32 {
+        match self.fat_type {
+            FatType::Fat12 => 0xFFF,
+            FatType::Fat16 => 0xFFFF,
+            FatType::Fat32 => 0x0FFF_FFFF,
+        }
+    }
+
+    fn is_end_of_chain(&self, value: u32) -> bool {
+        value >= self.end_of_chain() - 7
+    }
+
+    // ---- FAT access ----
+
+    /// Read `out.len()` bytes at byte `offset` of the first FAT
+    fn read_fat_bytes(&self, device: &mut dyn BlockDevice, offset: u64, out: &mut [u8]) -> Result<(), &'static str> {
+        let first = offset / BLOCK_SIZE as u64;
+        let last = (offset + out.len() as u64 - 1) / BLOCK_SIZE as u64;
+        let mut blocks = vec![0u8; (last - first + 1) as usize * BLOCK_SIZE];
+        device.read_blocks(self.fat_start + first, &mut blocks)?;
+        let start = (offset % BLOCK_SIZE as u64) as usize;
+        out.copy_from_slice(&blocks[start..start + out.len()]);
+        Ok(())
+    }
+
+    /// Write bytes at byte `offset` into every FAT copy
+    fn write_fat_bytes(&self, device: &mut dyn BlockDevice, offset: u64, data: &[u8]) -> Result<(), &'static str> {
+        let first = offset / BLOCK_SIZE as u64;
+        let last = (offset + data.len() as u64 - 1) / BLOCK_SIZE as u64;
+        let mut blocks = vec![0u8; (last - first + 1) as usize * BLOCK_SIZE];
+        let start = (offset % BLOCK_SIZE as u64) as usize;
+
+        for copy in 0..self.num_fats {
+            let block = self.fat_start + copy * self.fat_blocks + first;
+            device.read_blocks(block, &mut blocks)?;
+            blocks[start..start + data.len()].copy_from_slice(data);
+            device.write_blocks(block, &blocks)?;
+        }
+        Ok(())
+    }
+
+    fn fat_entry(&self, device: &mut dyn BlockDevice, cluster: u32) -> Result<u32, &'static str> {
+        match self.fat_type {
+            FatType::Fat12 => {
+                let mut bytes = [0u8; 2];
+                self.read_fat_bytes(device, cluster as u64 * 3 / 2, &mut bytes)?;
+                let value = u16::from_le_bytes(bytes) as u32;
+                Ok(if cluster & 1 == 1 { value >> 4 } else { value & 0xFFF })
+            }
+            FatType::Fat16 => {
+                let mut bytes = [0u8; 2];
+                self.read_fat_bytes(device, cluster as u64 * 2, &mut bytes)?;
+                Ok(u16::from_le_bytes(bytes) as u32)
+            }
+            FatType::Fat32 => {
+                let mut bytes = [0u8; 4];
+                self.read_fat_bytes(device, cluster as u64 * 4, &mut bytes)?;
+                Ok(u32::from_le_bytes(bytes) & 0x0FFF_FFFF)
+            }
+        }
+    }
+
+    fn set_fat_entry(&self, device: &mut dyn BlockDevice, cluster: u32, value: u32) -> Result<(), &'static str> {
+        match self.fat_type {
+            FatType::Fat12 => {
+                // Entries are 12 bits and share a byte with their neighbour
+                let offset = cluster as u64 * 3 / 2;
+                let mut bytes = [0u8; 2];
+                self.read_fat_bytes(device, offset, &mut bytes)?;
+                let old = u16::from_le_bytes(bytes);
+                let new = if cluster & 1 == 1 {
+                    (old & 0x000F) | ((value as u16 & 0xFFF) << 4)
+                } else {
+                    (old & 0xF000) | (value as u16 & 0xFFF)
+                };
+                self.write_fat_bytes(device, offset, &new.to_le_bytes())
+            }
+            FatType::Fat16 => self.write_fat_bytes(device, cluster as u64 * 2, &(value as u16).to_le_bytes()),
+            FatType::Fat32 => {
+                // The top 4 bits are reserved and must be preserved
+                let offset = cluster as u64 * 4;
+                let mut bytes = [0u8; 4];
+                self.read_fat_bytes(device, offset, &mut bytes)?;
+                let new = (u32::from_le_bytes(bytes) & 0xF000_0000) | (value & 0x0FFF_FFFF);
+                self.write_fat_bytes(device, offset, &new.to_le_bytes())
+            }
+        }
+    }
+
+    /// Clusters of the chain starting at `first` (empty for cluster 0)
+    fn chain(&self, device: &mut dyn BlockDevice, first: u32) -> Result<Vec<u32>, &'static str> {
+        let mut clusters = Vec::new();
+        let mut cluster = first;
+        while cluster != 0 {
+            if !self.is_valid_cluster(cluster) || clusters.len() > self.cluster_count as usize {
+                return Err("Corrupt cluster chain");
+            }
+            clusters.push(cluster);
+            let next = self.fat_entry(device, cluster)?;
+            if self.is_end_of_chain(next) {
+                break;
+            }
+            cluster = next;
+        }
+        Ok(clusters)
+    }
+
+    /// Find a free cluster and mark it as the end of a chain
+    fn alloc_cluster(&mut self, device: &mut dyn BlockDevice) -> Result<u32, &'static str> {
+        let start = if self.is_valid_cluster(self.next_free) { self.next_free } else { 2 };
+        for i in 0..self.cluster_count {
+            let cluster = 2 + (start - 2 + i) % self.cluster_count;
+            if self.fat_entry(device, cluster)? == 0 {
+                self.set_fat_entry(device, cluster, self.end_of_chain())?;
+                self.next_free = if cluster + 1 < self.cluster_count + 2 { cluster + 1 } else { 2 };
+                self.free_count = self.free_count.map(|n| n.saturating_sub(1));
+                return Ok(cluster);
+            }
+        }
+        Err("Disk full")
+    }
+
+    fn free_clusters(&mut self, device: &mut dyn BlockDevice, clusters: &[u32]) -> Result<(), &'static str> {
+        for &cluster in clusters {
+            self.set_fat_entry(device, cluster, 0)?;
+        }
+        self.free_count = self.free_count.map(|n| n + clusters.len() as u32);
+
+        // Freed space no longer holds anything the device needs to keep
+        for &cluster in clusters {
+            device.discard(self.cluster_block(cluster), self.blocks_per_cluster)?;
+        }
+        Ok(())
+    }
+
+    /// Grow or shrink the chain starting at `first` to `count` clusters.
+    /// Returns the (possibly new) first cluster and the resulting chain.
+    fn resize_chain(&mut self, device: &mut dyn BlockDevice, first: u32, count: usize) -> Result<(u32, Vec<u32>), &'static str> {
+        let mut chain = self.chain(device, first)?;
+
+        if chain.len() > count {
+            let freed = chain.split_off(count);
+            if let Some(&last) = chain.last() {
+                self.set_fat_entry(device, last, self.end_of_chain())?;
+            }
+            self.free_clusters(device, &freed)?;
+        }
+
+        while chain.len() < count {
+            let cluster = match self.alloc_cluster(device) {
+                Ok(cluster) => cluster,
+                Err(e) => {
+                    // Give back what this call allocated
+                    let old_len = self.chain(device, first)?.len();
+                    if old_len < chain.len() {
+                        let added = chain.split_off(old_len);
+                        if let Some(&last) = chain.last() {
+                            self.set_fat_entry(device, last, self.end_of_chain())?;
+                        }
+                        self.free_clusters(device, &added)?;
+                    }
+                    return Err(e);
+                }
+            };
+            if let Some(&last) = chain.last() {
+                self.set_fat_entry(device, last, cluster)?;
+            }
+            chain.push(cluster);
+        }
+
+        Ok((chain.first().copied().unwrap_or(0), chain))
+    }
+
+    // ---- File data ----
+
+    /// Read the first `buffer.len()` bytes stored in `chain`
+    fn read_chain_data(&self, device: &mut dyn BlockDevice, chain: &[u32], buffer: &mut [u8]) -> Result<(), &'static str> {
+        let cluster_bytes = self.bytes_per_cluster();
+        let mut i = 0;
+        let mut pos = 0;
+
+        while pos < buffer.len() {
+            // Read runs of adjacent clusters in one request
+            let mut run = 1;
+            while i + run < chain.len() && chain[i + run] == chain[i] + run as u32 {
+                run += 1;
+            }
+            let len = core::cmp::min(run * cluster_bytes, buffer.len() - pos);
+            let whole = len / BLOCK_SIZE * BLOCK_SIZE;
+            let block = self.cluster_block(chain[i]);
+
+            if whole > 0 {
+                device.read_blocks(block, &mut buffer[pos..pos + whole])?;
+            }
+            if whole < len {
+                let mut tail = [0u8; BLOCK_SIZE];
+                device.read_blocks(block + (whole / BLOCK_SIZE) as u64, &mut tail)?;
+                buffer[pos + whole..pos + len].copy_from_slice(&tail[..len - whole]);
+            }
+
+            pos += len;
+            i += run;
+        }
+        Ok(())
+    }
+
+    /// Write `data` at byte `offset` of the space covered by `chain`
+    fn write_chain_data(&self, device: &mut dyn BlockDevice, chain: &[u32], offset: usize, data: &[u8]) -> Result<(), &'static str> {
+        let cluster_bytes = self.bytes_per_cluster();
+        let mut pos = 0;
+
+        while pos < data.len() {
+            let at = offset + pos;
+            let within = at % cluster_bytes;
+            let block = self.cluster_block(chain[at / cluster_bytes]) + (within / BLOCK_SIZE) as u64;
+            let block_offset = within % BLOCK_SIZE;
+            let remaining = data.len() - pos;
+
+            if block_offset == 0 && remaining >= BLOCK_SIZE {
+                // Whole blocks up to the end of this cluster
+                let len = core::cmp::min(remaining / BLOCK_SIZE * BLOCK_SIZE, cluster_bytes - within);
+                device.write_blocks(block, &data[pos..pos + len])?;
+                pos += len;
+            } else {
+                let len = core::cmp::min(BLOCK_SIZE - block_offset, remaining);
+                let mut buffer = [0u8; BLOCK_SIZE];
+                device.read_blocks(block, &mut buffer)?;
+                buffer[block_offset..block_offset + len].copy_from_slice(&data[pos..pos + len]);
+                device.write_blocks(block, &buffer)?;
+                pos += len;
+            }
+        }
+        Ok(())
+    }
+
+    /// Zero bytes `from..to` of the space covered by `chain`
+    fn zero_chain_data(&self, device: &mut dyn BlockDevice, chain: &[u32], from: usize, to: usize) -> Result<(), &'static str> {
+        let zeroes = [0u8; 4096];
+        let mut at = from;
+        while at < to {
+            let len = core::cmp::min(zeroes.len(), to - at);
+            self.write_chain_data(device, chain, at, &zeroes[..len])?;
+            at += len;
+        }
+        Ok(())
+    }
+
+    fn clusters_for(&self, size: usize) -> usize {
+        (size + self.bytes_per_cluster() - 1) / self.bytes_per_cluster()
+    }
+
+    // ---- Directories ----
+
+    fn load_dir(&self, device: &mut dyn BlockDevice, dir: Dir) -> Result<DirData, &'static str> {
+        let blocks: Vec<u64> = match dir {
+            Dir::FixedRoot => (self.root_dir_start..self.root_dir_start + self.root_dir_blocks).collect(),
+            Dir::Clusters(first) => self
+                .chain(device, first)?
+                .iter()
+                .flat_map(|&cluster| {
+                    let start = self.cluster_block(cluster);
+                    start..start + self.blocks_per_cluster
+                })
+                .collect(),
+        };
+
+        let mut bytes = vec![0u8; blocks.len() * BLOCK_SIZE];
+        for (i, &block) in blocks.iter().enumerate() {
+            device.read_blocks(block, &mut bytes[i * BLOCK_SIZE..(i + 1) * BLOCK_SIZE])?;
+        }
+        Ok(DirData { bytes, blocks })
+    }
+
+    /// Write back the blocks of `data` covering bytes `from..to`
+    fn store_dir(&self, device: &mut dyn BlockDevice, data: &DirData, from: usize, to: usize) -> Result<(), &'static str> {
+        for chunk in from / BLOCK_SIZE..=(to - 1) / BLOCK_SIZE {
+            device.write_blocks(data.blocks[chunk], &data.bytes[chunk * BLOCK_SIZE..(chunk + 1) * BLOCK_SIZE])?;
+        }
+        Ok(())
+    }
+
+    fn parse_dir(data: &DirData) -> Vec<DirEntry> {
+        let mut entries = Vec::new();
+        // LFN entries seen so far for the next short entry: (checksum, count, chars, start)
+        let mut lfn: Option<(u8, usize, Vec<u16>, usize)> = None;
+
+        for offset in (0..data.bytes.len()).step_by(DIR_ENTRY_SIZE) {
+            let raw = &data.bytes[offset..offset + DIR_ENTRY_SIZE];
+            if raw[0] == ENTRY_END {
+                break;
+            }
+            if raw[0] == ENTRY_DELETED {
+                lfn = None;
+                continue;
+            }
+
+            let attr = raw[11];
+            if attr & 0x3F == ATTR_LONG_NAME {
+                let order = raw[0] & 0x1F;
+                let checksum = raw[13];
+                if raw[0] & LFN_LAST_ENTRY != 0 {
+                    lfn = Some((checksum, order as usize, vec![0xFFFF; order as usize * LFN_CHARS_PER_ENTRY], offset));
+                }
+                let valid = matches!(lfn, Some((sum, count, _, _)) if sum == checksum && order >= 1 && order as usize <= count);
+                if !valid {
+                    lfn = None;
+                    continue;
+                }
+                if let Some((_, _, ref mut chars, _)) = lfn {
+                    let base = (order as usize - 1) * LFN_CHARS_PER_ENTRY;
+                    for (i, &at) in LFN_CHAR_OFFSETS.iter().enumerate() {
+                        chars[base + i] = read_u16(raw, at);
+                    }
+                }
+                continue;
+            }
+
+            let pending = lfn.take();
+            if attr & ATTR_VOLUME_ID != 0 {
+                continue;
+            }
+
+            let short_name: [u8; 11] = raw[..11].try_into().unwrap();
+            if short_name[0] == b'.' {
+                continue; // "." and ".."
+            }
+
+            let (name, lfn_start) = match pending {
+                Some((checksum, _, chars, start)) if checksum == lfn_checksum(&short_name) => {
+                    let units = chars.iter().copied().take_while(|&c| c != 0 && c != 0xFFFF);
+                    let name: String = char::decode_utf16(units)
+                        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                        .collect();
+                    (name, start)
+                }
+                _ => (format_short_name(&short_name, raw[12]), offset),
+            };
+
+            entries.push(DirEntry {
+                name,
+                short_name,
+                attr,
+                first_cluster: (read_u16(raw, 20) as u32) << 16 | read_u16(raw, 26) as u32,
+                size: read_u32(raw, 28),
+                created: fat_to_unix(read_u16(raw, 16), read_u16(raw, 14)),
+                modified: fat_to_unix(read_u16(raw, 24), read_u16(raw, 22)),
+                accessed: fat_to_unix(read_u16(raw, 18), 0),
+                offset,
+                lfn_start,
+            });
+        }
+        entries
+    }
+
+    /// Entry named `name` (case-insensitive, long or short name) in `dir`
+    fn find_in(&self, device: &mut dyn BlockDevice, dir: Dir, name: &str) -> Result<Option<DirEntry>, &'static str> {
+        let data = self.load_dir(device, dir)?;
+        Ok(Self::parse_dir(&data).into_iter().find(|entry| {
+            entry.name.eq_ignore_ascii_case(name) || format_short_name(&entry.short_name, 0).eq_ignore_ascii_case(name)
+        }))
+    }
+
+    /// Walk `parts` from the root down to a directory
+    fn resolve_dir(&self, device: &mut dyn BlockDevice, parts: &[&str]) -> Result<Dir, &'static str> {
+        let mut dir = self.root();
+        for part in parts {
+            match self.find_in(device, dir, part)? {
+                Some(entry) if entry.is_dir() => dir = entry.as_dir(self.root()),
+                Some(_) => return Err("Not a directory"),
+                None => return Err("Directory not found"),
+            }
+        }
+        Ok(dir)
+    }
+
+    /// Parent directory and final name of `path`
+    fn split_parent<'a>(&self, device: &mut dyn BlockDevice, path: &'a str) -> Result<(Dir, &'a str), &'static str> {
+        let parts = components(path);
+        let (name, parents) = parts.split_last().ok_or("Invalid path")?;
+        Ok((self.resolve_dir(device, parents)?, name))
+    }
+
+    /// Look up the entry for `path`
+    fn lookup(&self, device: &mut dyn BlockDevice, path: &str) -> Result<(Dir, DirEntry), &'static str> {
+        let (dir, name) = self.split_parent(device, path)?;
+        let entry = self.find_in(device, dir, name)?.ok_or("File not found")?;
+        Ok((dir, entry))
+    }
+
+    /// Offset of `count` consecutive free entries in `data`
+    fn find_free_slots(data: &DirData, count: usize) -> Option<usize> {
+        let mut run_start = 0;
+        let mut run = 0;
+        for offset in (0..data.bytes.len()).step_by(DIR_ENTRY_SIZE) {
+            let first = data.bytes[offset];
+            if first == ENTRY_END || first == ENTRY_DELETED {
+                if run == 0 {
+                    run_start = offset;
+                }
+                run += 1;
+                if run == count {
+                    return Some(run_start);
+                }
+            } else {
+                run = 0;
+            }
+        }
+        None
+    }
+
+    /// Add a new entry (with LFN entries if needed) to `dir`
+    fn add_entry(&mut self, device: &mut dyn BlockDevice, dir: Dir, name: &str, attr: u8, first_cluster: u32, size: u32) -> Result<(), &'static str> {
+        validate_name(name)?;
+
+        let mut data = self.load_dir(device, dir)?;
+        let existing = Self::parse_dir(&data);
+        if existing.iter().any(|entry| entry.name.eq_ignore_ascii_case(name)) {
+            return Err("File already exists");
+        }
+        let taken: Vec<[u8; 11]> = existing.iter().map(|entry| entry.short_name).collect();
+
+        let (short_name, ntres, long_name) = match exact_short_name(name) {
+            Some((short, ntres)) if !taken.contains(&short) => (short, ntres, None),
+            _ => (generate_short_name(name, &taken)?, 0, Some(name)),
+        };
+
+        let units: Vec<u16> = long_name.map(|name| name.encode_utf16().collect()).unwrap_or_default();
+        let lfn_entries = (units.len() + LFN_CHARS_PER_ENTRY - 1) / LFN_CHARS_PER_ENTRY;
+        let slots = lfn_entries + 1;
+
+        let start = loop {
+            if let Some(start) = Self::find_free_slots(&data, slots) {
+                break start;
+            }
+            // Directory full: FAT12/16 roots can't grow, others get another cluster
+            let first = match dir {
+                Dir::FixedRoot => return Err("Root directory full"),
+                Dir::Clusters(first) => first,
+            };
+            let count = self.chain(device, first)?.len();
+            let (_, chain) = self.resize_chain(device, first, count + 1)?;
+            let new_cluster = *chain.last().unwrap();
+            device.write_zeroes(self.cluster_block(new_cluster), self.blocks_per_cluster)?;
+            data = self.load_dir(device, dir)?;
+        };
+
+        let checksum = lfn_checksum(&short_name);
+        for i in 0..lfn_entries {
+            // Stored last part first; part N carries the LAST_ENTRY flag
+            let order = lfn_entries - i;
+            let entry = &mut data.bytes[start + i * DIR_ENTRY_SIZE..start + (i + 1) * DIR_ENTRY_SIZE];
+            entry.fill(0);
+            entry[0] = order as u8 | if i == 0 { LFN_LAST_ENTRY } else { 0 };
+            entry[11] = ATTR_LONG_NAME;
+            entry[13] = checksum;
+            for (j, &at) in LFN_CHAR_OFFSETS.iter().enumerate() {
+                let index = (order - 1) * LFN_CHARS_PER_ENTRY + j;
+                let unit = match index.cmp(&units.len()) {
+                    core::cmp::Ordering::Less => units[index],
+                    core::cmp::Ordering::Equal => 0x0000,
+                    core::cmp::Ordering::Greater => 0xFFFF,
+                };
+                write_u16(entry, at, unit);
+            }
+        }
+
+        let (date, time) = fat_timestamp();
+        let offset = start + lfn_entries * DIR_ENTRY_SIZE;
+        let entry = &mut data.bytes[offset..offset + DIR_ENTRY_SIZE];
+        entry.fill(0);
+        entry[..11].copy_from_slice(&short_name);
+        entry[11] = attr;
+        entry[12] = ntres;
+        write_u16(entry, 14, time);
+        write_u16(entry, 16, date);
+        write_u16(entry, 18, date);
+        write_u16(entry, 20, (first_cluster >> 16) as u16);
+        write_u16(entry, 22, time);
+        write_u16(entry, 24, date);
+        write_u16(entry, 26, first_cluster as u16);
+        write_u32(entry, 28, size);
+
+        self.store_dir(device, &data, start, offset + DIR_ENTRY_SIZE)
+    }
+
+    /// Mark an entry and its LFN entries deleted
+    fn remove_entry(&self, device: &mut dyn BlockDevice, dir: Dir, entry: &DirEntry) -> Result<(), &'static str> {
+        let mut data = self.load_dir(device, dir)?;
+        for offset in (entry.lfn_start..=entry.offset).step_by(DIR_ENTRY_SIZE) {
+            data.bytes[offset] = ENTRY_DELETED;
+        }
+        self.store_dir(device, &data, entry.lfn_start, entry.offset + DIR_ENTRY_SIZE)
+    }
+
+    /// Update an entry's first cluster and size, and stamp its write time
+    fn update_entry(&self, device: &mut dyn BlockDevice, dir: Dir, entry: &DirEntry, first_cluster: u32, size: u32) -> Result<(), &'static str> {
+        let mut data = self.load_dir(device, dir)?;
+        let (date, time) = fat_timestamp();
+        let raw = &mut data.bytes[entry.offset..entry.offset + DIR_ENTRY_SIZE];
+        raw[11] |= ATTR_ARCHIVE;
+        write_u16(raw, 18, date);
+        write_u16(raw, 20, (first_cluster >> 16) as u16);
+        write_u16(raw, 22, time);
+        write_u16(raw, 24, date);
+        write_u16(raw, 26, first_cluster as u16);
+        write_u32(raw, 28, size);
+        self.store_dir(device, &data, entry.offset, entry.offset + DIR_ENTRY_SIZE)
+    }
+}
+
+impl FileSystem for FatFilesystem {
+    fn fs_type(&self) -> &'static str {
+        match self.fat_type {
+            FatType::Fat12 => "FAT12",
+            FatType::Fat16 => "FAT16",
+            FatType::Fat32 => "FAT32",
+        }
+    }
+
+    /// Volume label from the boot sector ("NO NAME" is how formatters leave it unset)
+    fn label(&self) -> &str {
+        if self.label == "NO NAME" { "" } else { &self.label }
+    }
+
+    fn stat(&self, device: &mut dyn BlockDevice, path: &str) -> Result<FileInfo, &'static str> {
+        if components(path).is_empty() {
+            return Ok(FileInfo::new(String::from("/"), 0, true));
+        }
+        let (_, entry) = self.lookup(device, path)?;
+        Ok(entry.info())
+    }
+
+    fn list_dir(&self, device: &mut dyn BlockDevice, path: &str) -> Result<Vec<FileInfo>, &'static str> {
+        let dir = self.resolve_dir(device, &components(path))?;
+        let data = self.load_dir(device, dir)?;
+        Ok(Self::parse_dir(&data).into_iter().map(DirEntry::info).collect())
+    }
+
+    fn read_file(&self, device: &mut dyn BlockDevice, path: &str, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        let (_, entry) = self.lookup(device, path)?;
+        if entry.is_dir() {
+            return Err("Is a directory");
+        }
+        let size = entry.size as usize;
+        if buffer.len() < size {
+            return Err("Buffer too small for file");
+        }
+        let chain = self.chain(device, entry.first_cluster)?;
+        if chain.len() < self.clusters_for(size) {
+            return Err("Corrupt cluster chain");
+        }
+        self.read_chain_data(device, &chain, &mut buffer[..size])?;
+        Ok(size)
+    }
+
+    fn create_file(&mut self, device: &mut dyn BlockDevice, path: &str, size: u32) -> Result<(), &'static str> {
+        let (dir, name) = self.split_parent(device, path)?;
+        self.add_entry(device, dir, name, ATTR_ARCHIVE, 0, 0)?;
+        if size > 0 {
+            self.truncate(device, path, size)
+        } else {
+            self.commit(device)
+        }
+    }
+
+    fn write_file(&mut self, device: &mut dyn BlockDevice, path: &str, data: &[u8]) -> Result<(), &'static str> {
+        let (dir, entry) = self.lookup(device, path)?;
+        if entry.is_dir() {
+            return Err("Is a directory");
+        }
+        if data.len() > u32::MAX as usize {
+            return Err("File too large for FAT");
+        }
+
+        let (first, chain) = self.resize_chain(device, entry.first_cluster, self.clusters_for(data.len()))?;
+        self.write_chain_data(device, &chain, 0, data)?;
+        self.update_entry(device, dir, &entry, first, data.len() as u32)?;
+        self.commit(device)
+    }
+
+    fn delete_file(&mut self, device: &mut dyn BlockDevice, path: &str) -> Result<(), &'static str> {
+        let (dir, entry) = self.lookup(device, path)?;
+        if entry.attr & ATTR_READ_ONLY != 0 {
+            return Err("File is read-only");
+        }
+        if entry.is_dir() {
+            let contents = self.load_dir(device, entry.as_dir(self.root()))?;
+            if !Self::parse_dir(&contents).is_empty() {
+                return Err("Directory not empty");
+            }
+        }
+
+        self.remove_entry(device, dir, &entry)?;
+        let chain = self.chain(device, entry.first_cluster)?;
+        self.free_clusters(device, &chain)?;
+        self.commit(device)
+    }
+
+    fn rename_file(&mut self, device: &mut dyn BlockDevice, old_path: &str, new_path: &str) -> Result<(), &'static str> {
+        let (old_dir, entry) = self.lookup(device, old_path)?;
+        let (new_dir, new_name) = self.split_parent(device, new_path)?;
+
+        if entry.is_dir() && new_dir != old_dir {
+            // A directory can't be moved inside itself
+            let mut check = new_dir;
+            while let Dir::Clusters(cluster) = check {
+                if cluster == entry.first_cluster {
+                    return Err("Can't move a directory into itself");
+                }
+                check = match self.find_in(device, check, "..")? {
+                    Some(parent) => parent.as_dir(self.root()),
+                    None => self.root(),
+                };
+                if check == self.root() {
+                    break;
+                }
+            }
+        }
+
+        // Case-only renames in place need the old entry out of the way first
+        let same_name = old_dir == new_dir && entry.name.eq_ignore_ascii_case(new_name);
+        if same_name {
+            self.remove_entry(device, old_dir, &entry)?;
+        }
+        self.add_entry(device, new_dir, new_name, entry.attr, entry.first_cluster, entry.size)?;
+        if !same_name {
+            let (_, old) = self.lookup(device, old_path)?;
+            self.remove_entry(device, old_dir, &old)?;
+        }
+
+        // A moved directory's ".." must point at its new parent
+        if entry.is_dir() && new_dir != old_dir {
+            let mut data = self.load_dir(device, entry.as_dir(self.root()))?;
+            let parent_cluster = match new_dir {
+                Dir::Clusters(cluster) if new_dir != self.root() => cluster,
+                _ => 0,
+            };
+            if data.bytes.len() >= 2 * DIR_ENTRY_SIZE && &data.bytes[32..34] == b".." {
+                write_u16(&mut data.bytes, 32 + 20, (parent_cluster >> 16) as u16);
+                write_u16(&mut data.bytes, 32 + 26, parent_cluster as u16);
+                self.store_dir(device, &data, 32, 64)?;
+            }
+        }
+
+        self.commit(device)
+    }
+
+    fn create_dir(&mut self, device: &mut dyn BlockDevice, path: &str) -> Result<(), &'static str> {
+        let (parent, name) = self.split_parent(device, path)?;
+        validate_name(name)?;
+        if self.find_in(device, parent, name)?.is_some() {
+            return Err("File already exists");
+        }
+
+        let cluster = self.alloc_cluster(device)?;
+        device.write_zeroes(self.cluster_block(cluster), self.blocks_per_cluster)?;
+
+        // "." and ".." entries; ".." uses cluster 0 for the root
+        let parent_cluster = match parent {
+            Dir::Clusters(cluster) if parent != self.root() => cluster,
+            _ => 0,
+        };
+        let (date, time) = fat_timestamp();
+        let mut block = [0u8; BLOCK_SIZE];
+        for (i, (dot_name, target)) in [(&b".          "[..], cluster), (&b"..         "[..], parent_cluster)].iter().enumerate() {
+            let entry = &mut block[i * DIR_ENTRY_SIZE..(i + 1) * DIR_ENTRY_SIZE];
+            entry[..11].copy_from_slice(dot_name);
+            entry[11] = ATTR_DIRECTORY;
+            write_u16(entry, 14, time);
+            write_u16(entry, 16, date);
+            write_u16(entry, 18, date);
+            write_u16(entry, 20, (target >> 16) as u16);
+            write_u16(entry, 22, time);
+            write_u16(entry, 24, date);
+            write_u16(entry, 26, *target as u16);
+        }
+        device.write_blocks(self.cluster_block(cluster), &block)?;
+
+        if let Err(e) = self.add_entry(device, parent, name, ATTR_DIRECTORY, cluster, 0) {
+            self.free_clusters(device, &[cluster])?;
+            return Err(e);
+        }
+        self.commit(device)
+    }
+
+    fn truncate(&mut self, device: &mut dyn BlockDevice, path: &str, size: u32) -> Result<(), &'static str> {
+        let (dir, entry) = self.lookup(device, path)?;
+        if entry.is_dir() {
+            return Err("Is a directory");
+        }
+        let (first, chain) = self.resize_chain(device, entry.first_cluster, self.clusters_for(size as usize))?;
+        if size > entry.size {
+            self.zero_chain_data(device, &chain, entry.size as usize, size as usize)?;
+        }
+        self.update_entry(device, dir, &entry, first, size)?;
+        self.commit(device)
+    }
+
+    fn set_attributes(&mut self, device: &mut dyn BlockDevice, path: &str, attributes: u8) -> Result<(), &'static str> {
+        let (dir, entry) = self.lookup(device, path)?;
+        let mut data = self.load_dir(device, dir)?;
+        let raw = &mut data.bytes[entry.offset..entry.offset + DIR_ENTRY_SIZE];
+        raw[11] = (raw[11] & !ATTR_MASK) | (attributes & ATTR_MASK);
+        self.store_dir(device, &data, entry.offset, entry.offset + DIR_ENTRY_SIZE)?;
+        self.commit(device)
+    }
+
+    fn usage(&self, device: &mut dyn BlockDevice) -> Result<Usage, &'static str> {
+        // Count the FAT when FSInfo had no usable free count
+        let free = match self.free_count {
+            Some(free) => free as u64,
+            None => {
+                let mut free = 0;
+                for cluster in 2..self.cluster_count + 2 {
+                    if self.fat_entry(device, cluster)? == 0 {
+                        free += 1;
+                    }
+                }
+                free
+            }
+        };
+        let cluster_bytes = self.blocks_per_cluster * BLOCK_SIZE as u64;
+        Ok(Usage {
+            total_bytes: self.cluster_count as u64 * cluster_bytes,
+            free_bytes: free * cluster_bytes,
+            ..Usage::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::block::RamDisk;
+    use crate::system::fs::mime;
+
+    /// Minimal mkfs: one reserved sector (two on FAT32), two FATs, 512-byte sectors
+    fn make_volume(blocks: u64, sectors_per_cluster: u8, fat32: bool) -> RamDisk {
+        let mut disk = RamDisk::new(blocks);
+        let fat12 = !fat32 && blocks / (sectors_per_cluster as u64) < 4085;
+        let mut boot = [0u8; BLOCK_SIZE];
+        boot[0] = 0xEB;
+        write_u16(&mut boot, 11, 512);
+        boot[13] = sectors_per_cluster;
+        boot[16] = 2;
+        boot[21] = 0xF8;
+        write_u32(&mut boot, 32, blocks as u32);
+        boot[510] = 0x55;
+        boot[511] = 0xAA;
+
+        let fat_sectors;
+        if fat32 {
+            let reserved = 32u64;
+            write_u16(&mut boot, 14, reserved as u16);
+            fat_sectors = (blocks / sectors_per_cluster as u64 * 4 + 511) / 512;
+            write_u32(&mut boot, 36, fat_sectors as u32);
+            write_u32(&mut boot, 44, 2);
+            boot[71..82].copy_from_slice(b"TESTVOL    ");
+        } else {
+            write_u16(&mut boot, 14, 1);
+            write_u16(&mut boot, 17, 64);
+            let bytes = if fat12 { blocks / sectors_per_cluster as u64 * 3 / 2 } else { blocks / sectors_per_cluster as u64 * 2 };
+            fat_sectors = (bytes + 511) / 512 + 1;
+            write_u16(&mut boot, 22, fat_sectors as u16);
+            boot[43..54].copy_from_slice(b"TESTVOL    ");
+        }
+        disk.write_blocks(0, &boot).unwrap();
+
+        // Reserved FAT entries 0 and 1, plus the FAT32 root directory cluster
+        let reserved = if fat32 { 32 } else { 1 };
+        let mut fat = [0u8; BLOCK_SIZE];
+        if fat32 {
+            write_u32(&mut fat, 0, 0x0FFF_FFF8);
+            write_u32(&mut fat, 4, 0x0FFF_FFFF);
+            write_u32(&mut fat, 8, 0x0FFF_FFFF);
+        } else if fat12 {
+            fat[..3].copy_from_slice(&[0xF8, 0xFF, 0xFF]);
+        } else {
+            fat[..4].copy_from_slice(&[0xF8, 0xFF, 0xFF, 0xFF]);
+        }
+        for copy in 0..2 {
+            disk.write_blocks(reserved + copy * fat_sectors, &fat).unwrap();
+        }
+        disk
+    }
+
+    #[test]
+    fn test_detects_fat_type() {
+        let mut disk = make_volume(2048, 1, false);
+        assert_eq!(FatFilesystem::mount(&mut disk).unwrap().fs_type(), "FAT12");
+        let mut disk = make_volume(16384, 1, false);
+        assert_eq!(FatFilesystem::mount(&mut disk).unwrap().fs_type(), "FAT16");
+        let mut disk = make_volume(70000, 1, true);
+        let fs = FatFilesystem::mount(&mut disk).unwrap();
+        assert_eq!(fs.fs_type(), "FAT32");
+        assert_eq!(fs.label(), "TESTVOL");
+    }
+
+    #[test]
+    fn test_long_names_and_data() {
+        for (blocks, fat32) in [(2048, false), (16384, false), (70000, true)] {
+            let mut disk = make_volume(blocks, 1, fat32);
+            let mut fs = FatFilesystem::mount(&mut disk).unwrap();
+
+            let data: Vec<u8> = (0..3000).map(|i| (i % 251) as u8).collect();
+            fs.create_file(&mut disk, "A rather long file name.txt", 0).unwrap();
+            fs.write_file(&mut disk, "A rather long file name.txt", &data).unwrap();
+            fs.create_file(&mut disk, "readme.md", 10).unwrap();
+
+            // Remount and read everything back through the on-disk structures
+            let fs = FatFilesystem::mount(&mut disk).unwrap();
+            let files = fs.list_dir(&mut disk, "").unwrap();
+            assert_eq!(files.len(), 2);
+            assert_eq!(files[0].name, "A rather long file name.txt");
+            assert_eq!(files[0].size, 3000);
+            assert_eq!(files[1].name, "readme.md");
+            assert_eq!(files[1].size, 10);
+
+            let mut buffer = vec![0u8; 3000];
+            assert_eq!(fs.read_file(&mut disk, "a rather LONG file name.txt", &mut buffer).unwrap(), 3000);
+            assert_eq!(buffer, data);
+            assert_eq!(fs.read_file(&mut disk, "ARATHE~1.TXT", &mut buffer).unwrap(), 3000);
+        }
+    }
+
+    #[test]
+    fn test_timestamps_and_attributes() {
+        let mut disk = make_volume(2048, 1, false);
+        let mut fs = FatFilesystem::mount(&mut disk).unwrap();
+        fs.create_file(&mut disk, "photo.png", 10).unwrap();
+
+        let file = fs.stat(&mut disk, "photo.png").unwrap();
+        assert_eq!((file.created, file.modified, file.accessed), (315_532_800, 315_532_800, 315_532_800));
+        assert_eq!(mime::file_type(file.file_type).mime, "image/png");
+
+        fs.set_attributes(&mut disk, "photo.png", crate::system::fs::ATTR_READ_ONLY).unwrap();
+        assert_eq!(fs.stat(&mut disk, "photo.png").unwrap().attribute_string(), "r--");
+        assert_eq!(fs.delete_file(&mut disk, "photo.png"), Err("File is read-only"));
+    }
+
+    #[test]
+    fn test_directories_rename_delete() {
+        let mut disk = make_volume(16384, 4, false);
+        let mut fs = FatFilesystem::mount(&mut disk).unwrap();
+
+        fs.create_dir(&mut disk, "docs").unwrap();
+        fs.create_dir(&mut disk, "docs/old").unwrap();
+        fs.create_file(&mut disk, "docs/notes.txt", 0).unwrap();
+        fs.write_file(&mut disk, "docs/notes.txt", b"hello").unwrap();
+        fs.rename_file(&mut disk, "docs/notes.txt", "docs/old/Notes From Monday.txt").unwrap();
+
+        assert_eq!(fs.list_dir(&mut disk, "docs").unwrap().len(), 1);
+        let moved = fs.list_dir(&mut disk, "docs/old").unwrap();
+        assert_eq!(moved[0].name, "Notes From Monday.txt");
+        let mut buffer = [0u8; 5];
+        fs.read_file(&mut disk, "docs/old/Notes From Monday.txt", &mut buffer).unwrap();
+        assert_eq!(&buffer, b"hello");
+
+        assert_eq!(fs.delete_file(&mut disk, "docs/old"), Err("Directory not empty"));
+        fs.delete_file(&mut disk, "docs/old/Notes From Monday.txt").unwrap();
+        fs.delete_file(&mut disk, "docs/old").unwrap();
+        assert!(fs.list_dir(&mut disk, "docs").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_truncate_and_cluster_reuse() {
+        let mut disk = make_volume(2048, 1, false);
+        let mut fs = FatFilesystem::mount(&mut disk).unwrap();
+
+        fs.create_file(&mut disk, "big.bin", 0).unwrap();
+        fs.write_file(&mut disk, "big.bin", &[0xAA; 2000]).unwrap();
+        fs.truncate(&mut disk, "big.bin", 100).unwrap();
+        fs.truncate(&mut disk, "big.bin", 1000).unwrap();
+
+        let mut buffer = [0xFFu8; 1000];
+        fs.read_file(&mut disk, "big.bin", &mut buffer).unwrap();
+        assert!(buffer[..100].iter().all(|&b| b == 0xAA));
+        assert!(buffer[100..].iter().all(|&b| b == 0));
+
+        // A write that can't fit gives back what it allocated, and deleting
+        // the file frees everything
+        let too_big = vec![1u8; (fs.cluster_count as usize + 1) * BLOCK_SIZE];
+        assert_eq!(fs.write_file(&mut disk, "big.bin", &too_big), Err("Disk full"));
+        fs.delete_file(&mut disk, "big.bin").unwrap();
+        let free = (0..fs.cluster_count).filter(|&c| fs.fat_entry(&mut disk, c + 2).unwrap() == 0).count();
+        assert_eq!(free, fs.cluster_count as usize);
+    }
+
+    #[test]
+    fn test_short_names() {
+        assert_eq!(exact_short_name("readme.txt"), Some((*b"README  TXT", NTRES_LOWER_BASE | NTRES_LOWER_EXT)));
+        assert_eq!(exact_short_name("Readme.txt"), None);
+        assert_eq!(exact_short_name("toolongname.txt"), None);
+        assert_eq!(&generate_short_name("My Document.html", &[]).unwrap(), b"MYDOCU~1HTM");
+        let taken = [*b"MYDOCU~1HTM"];
+        assert_eq!(&generate_short_name("My Document.html", &taken).unwrap(), b"MYDOCU~2HTM");
+    }
+}
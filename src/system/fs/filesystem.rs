@@ -1,38 +1,120 @@
 // Simple Custom Filesystem for rOSt
 //
-// Disk Layout:
-// - Sector 0: Superblock (filesystem metadata)
-// - Sector 1: File Table (up to 32 files)
-// - Sectors 2-9: Reserved for future use
-// - Sectors 10+: Data blocks
-
-use crate::kernel::drivers::virtio::blk::VirtioBlkDevice;
+// Disk Layout (version 4, 512-byte blocks):
+// - Block 0: Superblock (filesystem metadata)
+// - Block bitmap: one bit per block on the volume, set = in use
+// - Inode bitmap: one bit per inode
+// - Inode table: 128-byte inodes, 4 per block. Inode 0 is never used, inode 1
+//   is the root directory
+// - Data blocks, including a contiguous run reserved for the journal
+//
+// Inodes also hold created/modified/accessed times (Unix seconds), ATTR_*
+// bits and a file type hint (an index into mime::FILE_TYPES).
+//
+// A file's data is a list of extents (first block, block count). Six fit in the
+// inode; longer lists continue in a chain of extent blocks. Directories are
+// files of packed records: inode (u32), kind (u8), name length (u8), UTF-8 name.
+//
+// Metadata changes (superblock, bitmaps, inodes, extent blocks, directory
+// contents) are staged in memory and committed as one transaction: they are
+// written to the journal, a checksummed header is written as the commit
+// record, and only then are the blocks copied to their home locations. Mount
+// replays a committed transaction that didn't finish and ignores one whose
+// header never made it to disk. File contents are written directly and are
+// not journaled.
+//
+// Every mount also walks the directory tree and checks it against the bitmaps
+// and counters (see fsck()). Damage the journal can't prevent, such as a
+// crash in an unjournaled write or a bug, is repaired unless the device is
+// read-only.
+//
+// Free space is tracked in memory as a map of free runs (freemap.rs), built
+// from the block bitmap at mount. A growing file continues where it ends if it
+// can; otherwise new extents come from the shortest run that fits. defrag()
+// moves fragmented files into single runs and packs files towards the start.
+//
+// Snapshots are named, read-only copies of the whole tree. Taking one writes a
+// manifest (an inode of kind KIND_SNAPSHOT, linked into no directory) with
+// every file's and directory's metadata and every file's extent list, so no
+// data is copied: the blocks it lists are shared with the live files. Shared
+// blocks stay allocated when a live file lets go of them, and a live file
+// moves off its shared blocks before it is written (copy-on-write). The
+// superblock points at a table of snapshot names and manifests. Snapshots are
+// browsed read-only under /.snapshots/<name>/ and can be restored or deleted.
+//
+// Version 1 volumes (a flat 32-entry file table with 8-byte names) are
+// converted in place the first time they are mounted. Version 2 volumes are
+// the same layout without a journal; one is added when they are mounted.
+// Version 3 is the current layout without snapshots, and is still what a
+// volume without snapshots is written as, so older kernels can mount it.
+
+use super::freemap::FreeMap;
+use super::{mime, FileInfo, FileSystem, Snapshot, Usage, ATTR_MASK, ATTR_READ_ONLY};
+use crate::system::block::{BlockDevice, BLOCK_SIZE};
+use crate::system::crc32::crc32;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
 use core::ptr;
 extern crate alloc;
 
 const FS_MAGIC: u32 = 0x524F5354; // "ROST" in ASCII
-const FS_VERSION: u32 = 1;
-const DATA_START_SECTOR: u64 = 11; // Moved to 11 to make room for 2-sector file table
-const MAX_FILES: usize = 32;
-const SECTOR_SIZE: usize = 512;
-const FILE_TABLE_SECTORS: u64 = 2; // File table spans sectors 1-2 (640 bytes needs 2 sectors)
-
-/// Filesystem superblock (stored in sector 0)
-#[repr(C, packed)]
-#[derive(Clone, Copy)]
-pub struct Superblock {
-    magic: u32,              // Magic number for validation
-    version: u32,            // Filesystem version
-    total_sectors: u64,      // Total sectors on disk
-    data_start_sector: u64,  // First data sector (10)
-    file_count: u32,         // Number of files currently stored
-    reserved: [u8; 480],     // Padding to fill 512 bytes
-}
-
-/// File table entry (16 bytes each)
+const FS_VERSION: u32 = 4;
+const SECTOR_SIZE: usize = BLOCK_SIZE;
+
+const INODE_SIZE: usize = 128;
+const INODES_PER_BLOCK: u32 = (SECTOR_SIZE / INODE_SIZE) as u32;
+const ROOT_INODE: u32 = 1;
+const MIN_INODES: u32 = 64;
+const MAX_INODES: u32 = 1 << 20;
+const BITS_PER_BLOCK: u64 = SECTOR_SIZE as u64 * 8;
+
+/// Extents stored in the inode itself
+const INODE_EXTENTS: usize = 6;
+/// Extents per overflow block, after its 8-byte next pointer
+const BLOCK_EXTENTS: usize = (SECTOR_SIZE - 8) / 12;
+/// Longest extent (the on-disk length is 32 bits)
+const MAX_EXTENT_LEN: u64 = u32::MAX as u64;
+
+const KIND_FREE: u8 = 0;
+const KIND_FILE: u8 = 1;
+const KIND_DIR: u8 = 2;
+/// Snapshot table or manifest, never linked into a directory
+const KIND_SNAPSHOT: u8 = 3;
+
+const MAX_NAME_LEN: usize = 255;
+
+/// Root directory name that snapshots are browsed under
+const SNAPSHOT_DIR: &str = ".snapshots";
+const SNAPSHOTS_READ_ONLY: &str = "Snapshots are read-only";
+/// Fixed part of a manifest entry, before its extents and name
+const TREE_ENTRY_SIZE: usize = 44;
+
+/// Blocks copied at a time when defrag moves a file
+const COPY_BLOCKS: u64 = 128;
+
+// Journal: header block, then block numbers (tags), then the block images
+const JOURNAL_MAGIC: u32 = 0x4A524E4C; // "JRNL"
+const MIN_JOURNAL_BLOCKS: u64 = 64;
+const MAX_JOURNAL_BLOCKS: u64 = 8192;
+const TAGS_PER_BLOCK: usize = SECTOR_SIZE / 8;
+
+// Version 3: the current layout without snapshots
+const V3_VERSION: u32 = 3;
+
+// Version 2: version 3 without a journal
+const V2_VERSION: u32 = 2;
+
+// Version 1 layout, only needed to convert old volumes
+const V1_VERSION: u32 = 1;
+const V1_MAX_FILES: usize = 32;
+const V1_FILE_TABLE_SECTORS: u64 = 2; // File table spans sectors 1-2 (640 bytes needs 2 sectors)
+
+/// Version 1 file table entry (20 bytes each)
 #[repr(C, packed)]
 #[derive(Clone, Copy)]
-pub struct FileEntry {
+struct V1FileEntry {
     name: [u8; 8],       // 8-character filename (null-terminated)
     start_sector: u16,   // First data sector for this file
     size_sectors: u16,   // Size in sectors
@@ -41,125 +123,537 @@ pub struct FileEntry {
     reserved: [u8; 3],   // Future use
 }
 
-impl FileEntry {
-    const FLAG_USED: u8 = 0x01;
-    const FLAG_FREE: u8 = 0x00;
+/// Journal blocks a transaction of `count` blocks takes: header, tags, images
+fn journal_space(count: usize) -> u64 {
+    (1 + (count + TAGS_PER_BLOCK - 1) / TAGS_PER_BLOCK + count) as u64
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u64(bytes: &mut [u8], offset: usize, value: u64) {
+    bytes[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Current time for inode timestamps
+#[cfg(not(test))]
+fn now() -> i64 {
+    crate::kernel::clock::realtime_secs()
+}
+
+#[cfg(test)]
+fn now() -> i64 {
+    1_700_000_000
+}
+
+fn blocks_for(bytes: u64) -> u64 {
+    (bytes + SECTOR_SIZE as u64 - 1) / SECTOR_SIZE as u64
+}
+
+/// Filesystem superblock (stored in block 0)
+#[derive(Clone, Copy)]
+struct Superblock {
+    total_blocks: u64,
+    block_bitmap_start: u64,
+    inode_bitmap_start: u64,
+    inode_table_start: u64,
+    data_start: u64,
+    inode_count: u32,
+    free_blocks: u64,
+    free_inodes: u32,
+    /// Journal blocks (both 0 if the volume has none)
+    journal_start: u64,
+    journal_len: u64,
+    /// Inode of the snapshot table (0 if there are no snapshots)
+    snapshot_table: u32,
+}
+
+impl Superblock {
+    /// Lay out a volume of `total_blocks` blocks
+    fn for_size(total_blocks: u64) -> Result<Self, &'static str> {
+        let inode_count = ((total_blocks / 16) as u32).clamp(MIN_INODES, MAX_INODES) / INODES_PER_BLOCK * INODES_PER_BLOCK;
+        let block_bitmap_start = 1;
+        let inode_bitmap_start = block_bitmap_start + (total_blocks + BITS_PER_BLOCK - 1) / BITS_PER_BLOCK;
+        let inode_table_start = inode_bitmap_start + (inode_count as u64 + BITS_PER_BLOCK - 1) / BITS_PER_BLOCK;
+        let data_start = inode_table_start + (inode_count / INODES_PER_BLOCK) as u64;
+
+        if data_start + 16 > total_blocks {
+            return Err("Disk too small for SimpleFS");
+        }
+
+        Ok(Superblock {
+            total_blocks,
+            block_bitmap_start,
+            inode_bitmap_start,
+            inode_table_start,
+            data_start,
+            inode_count,
+            free_blocks: total_blocks - data_start,
+            free_inodes: inode_count - 2, // Inode 0 is reserved, 1 is the root
+            journal_start: 0,
+            journal_len: 0,
+            snapshot_table: 0,
+        })
+    }
+
+    fn read(sector: &[u8]) -> Self {
+        Superblock {
+            total_blocks: read_u64(sector, 8),
+            block_bitmap_start: read_u64(sector, 16),
+            inode_bitmap_start: read_u64(sector, 24),
+            inode_table_start: read_u64(sector, 32),
+            data_start: read_u64(sector, 40),
+            inode_count: read_u32(sector, 48),
+            free_inodes: read_u32(sector, 52),
+            free_blocks: read_u64(sector, 56),
+            journal_start: read_u64(sector, 64),
+            journal_len: read_u64(sector, 72),
+            snapshot_table: read_u32(sector, 80),
+        }
+    }
+
+    fn write(&self, sector: &mut [u8]) {
+        sector.fill(0);
+        write_u32(sector, 0, FS_MAGIC);
+        write_u32(sector, 4, if self.snapshot_table != 0 { FS_VERSION } else { V3_VERSION });
+        write_u64(sector, 8, self.total_blocks);
+        write_u64(sector, 16, self.block_bitmap_start);
+        write_u64(sector, 24, self.inode_bitmap_start);
+        write_u64(sector, 32, self.inode_table_start);
+        write_u64(sector, 40, self.data_start);
+        write_u32(sector, 48, self.inode_count);
+        write_u32(sector, 52, self.free_inodes);
+        write_u64(sector, 56, self.free_blocks);
+        write_u64(sector, 64, self.journal_start);
+        write_u64(sector, 72, self.journal_len);
+        write_u32(sector, 80, self.snapshot_table);
+    }
+
+    /// Check the layout is self-consistent and fits on the device
+    fn validate(&self, device_blocks: u64) -> Result<(), &'static str> {
+        let ok = self.total_blocks <= device_blocks
+            && self.block_bitmap_start == 1
+            && self.inode_bitmap_start > self.block_bitmap_start
+            && self.inode_table_start > self.inode_bitmap_start
+            && self.data_start == self.inode_table_start + (self.inode_count / INODES_PER_BLOCK) as u64
+            && self.data_start < self.total_blocks
+            && self.inode_count > ROOT_INODE
+            && (self.journal_len == 0
+                || (self.journal_start >= self.data_start && self.journal_start + self.journal_len <= self.total_blocks))
+            && (self.snapshot_table == 0 || (self.snapshot_table > ROOT_INODE && self.snapshot_table < self.inode_count));
+        if ok { Ok(()) } else { Err("Corrupt SimpleFS superblock") }
+    }
+}
+
+/// A run of consecutive blocks
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Extent {
+    start: u64,
+    len: u64,
+}
+
+/// An inode as held in memory, with its full extent list
+#[derive(Clone)]
+struct Inode {
+    kind: u8,
+    attributes: u8,
+    file_type: u8,
+    size: u64,
+    created: i64,
+    modified: i64,
+    accessed: i64,
+    extents: Vec<Extent>,
+    /// Blocks holding extents beyond INODE_EXTENTS, in chain order
+    overflow: Vec<u64>,
+}
+
+impl Inode {
+    fn new(kind: u8) -> Self {
+        let time = now();
+        let file_type = if kind == KIND_DIR { mime::DIRECTORY } else { mime::UNKNOWN };
+        Inode {
+            kind,
+            attributes: 0,
+            file_type,
+            size: 0,
+            created: time,
+            modified: time,
+            accessed: time,
+            extents: Vec::new(),
+            overflow: Vec::new(),
+        }
+    }
+
+    fn info(&self, name: String) -> FileInfo {
+        let is_dir = self.kind == KIND_DIR;
+        FileInfo {
+            name,
+            size: if is_dir { 0 } else { core::cmp::min(self.size, u32::MAX as u64) as u32 },
+            is_dir,
+            created: self.created,
+            modified: self.modified,
+            accessed: self.accessed,
+            attributes: self.attributes,
+            file_type: self.file_type,
+        }
+    }
+
+    fn check_writable(&self) -> Result<(), &'static str> {
+        if self.attributes & ATTR_READ_ONLY != 0 { Err("File is read-only") } else { Ok(()) }
+    }
+
+    fn block_count(&self) -> u64 {
+        self.extents.iter().map(|extent| extent.len).sum()
+    }
+
+    /// Device block holding block `index` of the file, plus how many blocks
+    /// after it are contiguous on disk
+    fn map(&self, mut index: u64) -> Option<(u64, u64)> {
+        for extent in &self.extents {
+            if index < extent.len {
+                return Some((extent.start + index, extent.len - index));
+            }
+            index -= extent.len;
+        }
+        None
+    }
+}
+
+/// A directory record
+#[derive(Clone)]
+struct DirRecord {
+    inode: u32,
+    kind: u8,
+    name: String,
+}
+
+fn parse_dir(data: &[u8]) -> Vec<DirRecord> {
+    let mut records = Vec::new();
+    let mut pos = 0;
+    while pos + 6 <= data.len() {
+        let inode = read_u32(data, pos);
+        let kind = data[pos + 4];
+        let len = data[pos + 5] as usize;
+        if inode == 0 || pos + 6 + len > data.len() {
+            break;
+        }
+        let name = String::from_utf8_lossy(&data[pos + 6..pos + 6 + len]).into_owned();
+        records.push(DirRecord { inode, kind, name });
+        pos += 6 + len;
+    }
+    records
+}
+
+fn serialize_dir(records: &[DirRecord]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for record in records {
+        data.extend_from_slice(&record.inode.to_le_bytes());
+        data.push(record.kind);
+        data.push(record.name.len() as u8);
+        data.extend_from_slice(record.name.as_bytes());
+    }
+    data
+}
 
-    pub fn is_used(&self) -> bool {
-        unsafe { ptr::read_volatile(ptr::addr_of!(self.flags)) == Self::FLAG_USED }
+/// A snapshot table record: manifest inode (u32), creation time (i64), name
+/// length (u8), UTF-8 name
+#[derive(Clone)]
+struct SnapshotRecord {
+    manifest: u32,
+    created: i64,
+    name: String,
+}
+
+fn parse_snapshot_table(data: &[u8]) -> Vec<SnapshotRecord> {
+    let mut records = Vec::new();
+    let mut pos = 0;
+    while pos + 13 <= data.len() {
+        let len = data[pos + 12] as usize;
+        if pos + 13 + len > data.len() {
+            break;
+        }
+        let name = String::from_utf8_lossy(&data[pos + 13..pos + 13 + len]).into_owned();
+        records.push(SnapshotRecord { manifest: read_u32(data, pos), created: read_u64(data, pos + 4) as i64, name });
+        pos += 13 + len;
+    }
+    records
+}
+
+fn serialize_snapshot_table(records: &[SnapshotRecord]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for record in records {
+        data.extend_from_slice(&record.manifest.to_le_bytes());
+        data.extend_from_slice(&record.created.to_le_bytes());
+        data.push(record.name.len() as u8);
+        data.extend_from_slice(record.name.as_bytes());
     }
+    data
+}
+
+/// A file or directory frozen in a snapshot. Entry 0 of a manifest is the
+/// root and every other entry comes after its parent. Directories keep no
+/// extents: their records are the entries that name them as parent.
+struct TreeEntry {
+    parent: usize,
+    name: String,
+    data: Inode,
+}
 
-    pub fn is_free(&self) -> bool {
-        !self.is_used()
+/// Manifest entries: parent index (u32), kind, attributes, file type and name
+/// length (u8 each), size, created, modified and accessed (u64 each), extent
+/// count (u32), the extents (u64 start, u32 length), then the UTF-8 name
+fn serialize_tree(tree: &[TreeEntry]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for entry in tree {
+        let mut fixed = [0u8; TREE_ENTRY_SIZE];
+        write_u32(&mut fixed, 0, entry.parent as u32);
+        fixed[4] = entry.data.kind;
+        fixed[5] = entry.data.attributes;
+        fixed[6] = entry.data.file_type;
+        fixed[7] = entry.name.len() as u8;
+        write_u64(&mut fixed, 8, entry.data.size);
+        write_u64(&mut fixed, 16, entry.data.created as u64);
+        write_u64(&mut fixed, 24, entry.data.modified as u64);
+        write_u64(&mut fixed, 32, entry.data.accessed as u64);
+        write_u32(&mut fixed, 40, entry.data.extents.len() as u32);
+        data.extend_from_slice(&fixed);
+        for extent in &entry.data.extents {
+            data.extend_from_slice(&extent.start.to_le_bytes());
+            data.extend_from_slice(&(extent.len as u32).to_le_bytes());
+        }
+        data.extend_from_slice(entry.name.as_bytes());
     }
+    data
+}
+
+/// Read a manifest, checking that it forms a tree and that its extents are
+/// in the data area
+fn parse_tree(data: &[u8], sb: &Superblock) -> Result<Vec<TreeEntry>, &'static str> {
+    let mut tree: Vec<TreeEntry> = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        if pos + TREE_ENTRY_SIZE > data.len() {
+            return Err("Corrupt snapshot");
+        }
+        let fixed = &data[pos..pos + TREE_ENTRY_SIZE];
+        let extent_count = read_u32(fixed, 40) as usize;
+        let name_start = pos + TREE_ENTRY_SIZE + extent_count * 12;
+        let end = name_start + fixed[7] as usize;
+        if end > data.len() {
+            return Err("Corrupt snapshot");
+        }
+
+        let extents = (0..extent_count)
+            .map(|i| pos + TREE_ENTRY_SIZE + i * 12)
+            .map(|at| Extent { start: read_u64(data, at), len: read_u32(data, at + 8) as u64 })
+            .collect();
+        let entry = TreeEntry {
+            parent: read_u32(fixed, 0) as usize,
+            name: String::from_utf8_lossy(&data[name_start..end]).into_owned(),
+            data: Inode {
+                kind: fixed[4],
+                attributes: fixed[5],
+                file_type: fixed[6],
+                size: read_u64(fixed, 8),
+                created: read_u64(fixed, 16) as i64,
+                modified: read_u64(fixed, 24) as i64,
+                accessed: read_u64(fixed, 32) as i64,
+                extents,
+                overflow: Vec::new(),
+            },
+        };
 
-    pub fn get_name(&self) -> &str {
-        unsafe {
-            let name_bytes = &*(ptr::addr_of!(self.name) as *const [u8; 8]);
-            // Find null terminator
-            let len = name_bytes.iter().position(|&b| b == 0).unwrap_or(8);
-            core::str::from_utf8(&name_bytes[..len]).unwrap_or("")
+        let placed = if tree.is_empty() {
+            entry.data.kind == KIND_DIR
+        } else {
+            entry.parent < tree.len() && tree[entry.parent].data.kind == KIND_DIR && matches!(entry.data.kind, KIND_FILE | KIND_DIR)
+        };
+        let in_bounds = |e: &Extent| e.len > 0 && e.start >= sb.data_start && e.start + e.len <= sb.total_blocks;
+        if !placed || !entry.data.extents.iter().all(in_bounds) || entry.data.size > entry.data.block_count() * SECTOR_SIZE as u64 {
+            return Err("Corrupt snapshot");
         }
+        tree.push(entry);
+        pos = end;
+    }
+    if tree.is_empty() {
+        return Err("Corrupt snapshot");
     }
+    Ok(tree)
+}
 
-    pub fn get_size_bytes(&self) -> u32 {
-        unsafe { ptr::read_volatile(ptr::addr_of!(self.size_bytes)) }
+/// Index of the manifest entry at `parts`
+fn tree_lookup(tree: &[TreeEntry], parts: &[&str]) -> Result<usize, &'static str> {
+    let mut index = 0;
+    for part in parts {
+        if tree[index].data.kind != KIND_DIR {
+            return Err("Not a directory");
+        }
+        index = (1..tree.len()).find(|&i| tree[i].parent == index && tree[i].name == *part).ok_or("File not found")?;
     }
+    Ok(index)
+}
+
+/// For a path under /.snapshots, the components after it
+fn snapshot_parts(path: &str) -> Option<Vec<&str>> {
+    let parts = components(path);
+    if parts.first() == Some(&SNAPSHOT_DIR) { Some(parts[1..].to_vec()) } else { None }
+}
+
+fn check_not_snapshot(path: &str) -> Result<(), &'static str> {
+    if snapshot_parts(path).is_some() { Err(SNAPSHOTS_READ_ONLY) } else { Ok(()) }
+}
+
+/// What a consistency check found, and the fixes that would repair it
+struct Scan {
+    /// One line per problem
+    problems: Vec<String>,
+    /// Block and inode bitmaps as the directory tree actually uses them
+    blocks: Vec<u8>,
+    inodes: Vec<u8>,
+    free_blocks: u64,
+    free_inodes: u32,
+    /// Inodes whose extent list or size has to be rewritten
+    inode_fixes: Vec<(u32, Inode)>,
+    /// Directories whose records change
+    dir_fixes: Vec<(u32, Vec<DirRecord>)>,
+    /// Allocated inodes that no directory refers to
+    orphans: Vec<u32>,
+    /// Blocks held by the snapshots that are kept
+    shared: Vec<u8>,
+    /// The snapshot table to write, if any snapshot has to go
+    snapshots: Option<Vec<SnapshotRecord>>,
+}
 
-    pub fn get_start_sector(&self) -> u16 {
-        unsafe { ptr::read_volatile(ptr::addr_of!(self.start_sector)) }
+/// A name for a directory record that is invalid or shared with another
+/// record: the old name with "~1", "~2", ... added, or "inodeN" if the old one
+/// can't be kept
+fn unique_name(name: &str, inode: u32, taken: &[String], kept: &[DirRecord]) -> String {
+    let base = if validate_name(name).is_ok() && name.len() <= MAX_NAME_LEN - 8 {
+        String::from(name)
+    } else {
+        alloc::format!("inode{}", inode)
+    };
+    let free = |candidate: &str| !taken.iter().any(|n| n == candidate) && !kept.iter().any(|r| r.name == candidate);
+    if free(&base) {
+        return base;
     }
+    (1..).map(|n| alloc::format!("{}~{}", base, n)).find(|candidate| free(candidate)).unwrap()
+}
 
-    pub fn get_size_sectors(&self) -> u16 {
-        unsafe { ptr::read_volatile(ptr::addr_of!(self.size_sectors)) }
+/// Overflow blocks needed to hold `extents` extents
+fn overflow_blocks(extents: usize) -> usize {
+    (extents.saturating_sub(INODE_EXTENTS) + BLOCK_EXTENTS - 1) / BLOCK_EXTENTS
+}
+
+fn validate_name(name: &str) -> Result<(), &'static str> {
+    if name.is_empty() || name == "." || name == ".." {
+        return Err("Invalid filename");
+    }
+    if name.len() > MAX_NAME_LEN {
+        return Err("Filename too long (max 255 bytes)");
+    }
+    if name.contains('/') || name.contains('\0') {
+        return Err("Filename contains invalid characters");
     }
+    Ok(())
+}
+
+/// Split a path into its non-empty components
+fn components(path: &str) -> Vec<&str> {
+    path.split('/').filter(|part| !part.is_empty()).collect()
 }
 
 /// Simple filesystem implementation
 pub struct SimpleFilesystem {
     superblock: Superblock,
-    file_table: [FileEntry; MAX_FILES],
-    next_free_sector: u64,  // Track next available data sector
+    block_bitmap: Vec<u8>,
+    inode_bitmap: Vec<u8>,
+    /// Bitmap blocks changed since the last commit
+    dirty_bitmap_blocks: Vec<u64>,
+    /// Free runs of the block bitmap, for best-fit allocation
+    free_map: FreeMap,
+    /// Metadata blocks written by the current transaction
+    staged: BTreeMap<u64, Vec<u8>>,
+    /// Extents freed by the current transaction, released when it commits
+    pending_free: Vec<Extent>,
+    /// Blocks held by a snapshot, laid out like the block bitmap. They stay
+    /// allocated when a live file lets go of them.
+    shared: Vec<u8>,
 }
 
 impl SimpleFilesystem {
-    /// Format a disk with the simple filesystem
-    pub fn format(device: &mut VirtioBlkDevice, total_sectors: u64) -> Result<(), &'static str> {
+    /// Format a disk with the simple filesystem, using all of it
+    pub fn format(device: &mut dyn BlockDevice) -> Result<(), &'static str> {
         crate::kernel::uart_write_string("Formatting disk with SimpleFS...\r\n");
 
-        // Create superblock
-        let mut superblock = Superblock {
-            magic: FS_MAGIC,
-            version: FS_VERSION,
-            total_sectors,
-            data_start_sector: DATA_START_SECTOR,
-            file_count: 0,
-            reserved: [0; 480],
-        };
+        let mut fs = Self::create_empty(device)?;
 
-        // Write superblock to sector 0
-        let mut sector_buffer = [0u8; SECTOR_SIZE];
-        unsafe {
-            ptr::copy_nonoverlapping(
-                &superblock as *const Superblock as *const u8,
-                sector_buffer.as_mut_ptr(),
-                core::mem::size_of::<Superblock>(),
-            );
-        }
-        device.write_sector(0, &sector_buffer)?;
-        crate::kernel::uart_write_string("Superblock written to sector 0\r\n");
-
-        // Create empty file table
-        let empty_entry = FileEntry {
-            name: [0; 8],
-            start_sector: 0,
-            size_sectors: 0,
-            size_bytes: 0,
-            flags: FileEntry::FLAG_FREE,
-            reserved: [0; 3],
-        };
+        // Nothing in the data area is live any more
+        let data_start = fs.superblock.data_start;
+        device.discard(data_start, fs.superblock.total_blocks - data_start)?;
+
+        fs.add_journal(device)?;
+        fs.commit(device)?;
+
+        crate::kernel::uart_write_string("Format complete!\r\n");
+        Ok(())
+    }
 
-        // Write file table to sectors 1-2 (640 bytes total across 2 sectors)
-        let entry_size = core::mem::size_of::<FileEntry>();
-        let entries_per_sector = SECTOR_SIZE / entry_size; // 512 / 20 = 25
+    /// Write a fresh inode table and stage the root directory. Bitmaps and
+    /// the superblock are only written by the following commit().
+    fn create_empty(device: &mut dyn BlockDevice) -> Result<Self, &'static str> {
+        let superblock = Superblock::for_size(device.block_count())?;
 
-        for sector in 0..FILE_TABLE_SECTORS {
-            sector_buffer = [0u8; SECTOR_SIZE];
-            let start_entry = (sector * entries_per_sector as u64) as usize;
-            let end_entry = core::cmp::min(start_entry + entries_per_sector, MAX_FILES);
+        let mut fs = SimpleFilesystem {
+            superblock,
+            block_bitmap: vec![0; ((superblock.inode_bitmap_start - superblock.block_bitmap_start) as usize) * SECTOR_SIZE],
+            inode_bitmap: vec![0; ((superblock.inode_table_start - superblock.inode_bitmap_start) as usize) * SECTOR_SIZE],
+            dirty_bitmap_blocks: Vec::new(),
+            free_map: FreeMap::new(),
+            staged: BTreeMap::new(),
+            pending_free: Vec::new(),
+            shared: vec![0; ((superblock.inode_bitmap_start - superblock.block_bitmap_start) as usize) * SECTOR_SIZE],
+        };
 
-            for i in start_entry..end_entry {
-                let offset = (i - start_entry) * entry_size;
-                unsafe {
-                    ptr::copy_nonoverlapping(
-                        &empty_entry as *const FileEntry as *const u8,
-                        sector_buffer.as_mut_ptr().add(offset),
-                        entry_size,
-                    );
-                }
-            }
-            device.write_sector(1 + sector, &sector_buffer)?;
+        // Metadata blocks and inodes 0/1 are permanently in use
+        for block in 0..superblock.data_start {
+            Self::set_bit(&mut fs.block_bitmap, block, true);
         }
-        crate::kernel::uart_write_string("File table initialized at sectors 1-2\r\n");
+        fs.free_map.insert(superblock.data_start, superblock.total_blocks - superblock.data_start);
+        Self::set_bit(&mut fs.inode_bitmap, 0, true);
+        Self::set_bit(&mut fs.inode_bitmap, ROOT_INODE as u64, true);
+        fs.dirty_bitmap_blocks.extend(superblock.block_bitmap_start..superblock.inode_table_start);
+
+        device.write_zeroes(superblock.inode_table_start, superblock.data_start - superblock.inode_table_start)?;
+        fs.write_inode(device, ROOT_INODE, &mut Inode::new(KIND_DIR))?;
+        Ok(fs)
+    }
 
-        crate::kernel::uart_write_string("Format complete!\r\n");
-        Ok(())
+    /// Whether `device` starts with a SimpleFS superblock. Only reads sector 0.
+    pub fn probe(device: &mut dyn BlockDevice) -> bool {
+        let mut sector_buffer = [0u8; SECTOR_SIZE];
+        device.read_blocks(0, &mut sector_buffer).is_ok() && read_u32(&sector_buffer, 0) == FS_MAGIC
     }
 
     /// Mount an existing filesystem
-    pub fn mount(device: &mut VirtioBlkDevice) -> Result<Self, &'static str> {
+    pub fn mount(device: &mut dyn BlockDevice) -> Result<Self, &'static str> {
         crate::kernel::uart_write_string("Mounting SimpleFS...\r\n");
 
         // Read superblock from sector 0
         let mut sector_buffer = [0u8; SECTOR_SIZE];
-        device.read_sector(0, &mut sector_buffer)?;
-
-        let superblock: Superblock = unsafe {
-            ptr::read_unaligned(sector_buffer.as_ptr() as *const Superblock)
-        };
+        device.read_blocks(0, &mut sector_buffer)?;
 
         // Validate magic number
-        let magic = unsafe { ptr::read_volatile(ptr::addr_of!(superblock.magic)) };
+        let magic = read_u32(&sector_buffer, 0);
         if magic != FS_MAGIC {
             crate::kernel::uart_write_string(&alloc::format!(
                 "ERROR: Invalid magic number: 0x{:x}, expected 0x{:x}\r\n",
@@ -168,8 +662,15 @@ impl SimpleFilesystem {
             return Err("Invalid filesystem magic number");
         }
 
-        let version = unsafe { ptr::read_volatile(ptr::addr_of!(superblock.version)) };
-        if version != FS_VERSION {
+        let version = read_u32(&sector_buffer, 4);
+        if version == V1_VERSION {
+            crate::kernel::uart_write_string("Found SimpleFS v1 volume, converting to v2...\r\n");
+            if device.is_read_only() {
+                return Err("SimpleFS v1 volume is read-only and can't be converted");
+            }
+            Self::migrate_v1(device)?;
+            device.read_blocks(0, &mut sector_buffer)?;
+        } else if version != FS_VERSION && version != V3_VERSION && version != V2_VERSION {
             crate::kernel::uart_write_string(&alloc::format!(
                 "ERROR: Unsupported version: {}, expected {}\r\n",
                 version, FS_VERSION
@@ -177,417 +678,2172 @@ impl SimpleFilesystem {
             return Err("Unsupported filesystem version");
         }
 
+        let mut superblock = Superblock::read(&sector_buffer);
+        superblock.validate(device.block_count())?;
         crate::kernel::uart_write_string("Superblock validated\r\n");
 
-        // Read file table from sectors 1-2
-        let mut file_table = [FileEntry {
-            name: [0; 8],
-            start_sector: 0,
-            size_sectors: 0,
-            size_bytes: 0,
-            flags: FileEntry::FLAG_FREE,
-            reserved: [0; 3],
-        }; MAX_FILES];
+        // The journal may hold a newer superblock
+        if superblock.journal_len > 0 {
+            Self::replay_journal(device, &superblock)?;
+            device.read_blocks(0, &mut sector_buffer)?;
+            superblock = Superblock::read(&sector_buffer);
+            superblock.validate(device.block_count())?;
+        }
+
+        let mut fs = Self::load(device, superblock)?;
+        let repair = !device.is_read_only();
+        let problems = fs.fsck(device, repair)?;
+        for problem in &problems {
+            crate::kernel::uart_write_string(&alloc::format!("SimpleFS: {}\r\n", problem));
+        }
+        if !problems.is_empty() && repair {
+            crate::kernel::uart_write_string(&alloc::format!("Repaired {} problem(s)\r\n", problems.len()));
+        } else if !problems.is_empty() {
+            crate::kernel::uart_write_string("WARNING: device is read-only, problems not repaired\r\n");
+        }
+
+        if superblock.journal_len == 0 && !device.is_read_only() {
+            fs.transaction(device, |fs, device| fs.add_journal(device))?;
+        }
+
+        crate::kernel::uart_write_string(&alloc::format!(
+            "Filesystem mounted! {} of {} blocks free, {} inodes free\r\n",
+            fs.superblock.free_blocks, fs.superblock.total_blocks, fs.superblock.free_inodes
+        ));
+        Ok(fs)
+    }
 
-        let entry_size = core::mem::size_of::<FileEntry>();
-        let entries_per_sector = SECTOR_SIZE / entry_size; // 25 entries per sector
+    /// In-memory state for a volume whose superblock is `superblock`
+    fn load(device: &mut dyn BlockDevice, superblock: Superblock) -> Result<Self, &'static str> {
+        let mut block_bitmap = vec![0u8; ((superblock.inode_bitmap_start - superblock.block_bitmap_start) as usize) * SECTOR_SIZE];
+        device.read_blocks(superblock.block_bitmap_start, &mut block_bitmap)?;
+        let mut inode_bitmap = vec![0u8; ((superblock.inode_table_start - superblock.inode_bitmap_start) as usize) * SECTOR_SIZE];
+        device.read_blocks(superblock.inode_bitmap_start, &mut inode_bitmap)?;
 
-        for sector in 0..FILE_TABLE_SECTORS {
-            device.read_sector(1 + sector, &mut sector_buffer)?;
-            let start_entry = (sector * entries_per_sector as u64) as usize;
-            let end_entry = core::cmp::min(start_entry + entries_per_sector, MAX_FILES);
+        let mut fs = SimpleFilesystem {
+            superblock,
+            free_map: FreeMap::from_bitmap(&block_bitmap, superblock.data_start, superblock.total_blocks),
+            shared: vec![0; block_bitmap.len()],
+            block_bitmap,
+            inode_bitmap,
+            dirty_bitmap_blocks: Vec::new(),
+            staged: BTreeMap::new(),
+            pending_free: Vec::new(),
+        };
+        fs.shared = fs.snapshot_blocks(device);
+        Ok(fs)
+    }
+
+    /// Convert a version 1 volume in place. Files lying where the v2 metadata
+    /// goes are read into memory and rewritten; all others keep their blocks.
+    fn migrate_v1(device: &mut dyn BlockDevice) -> Result<(), &'static str> {
+        let entry_size = core::mem::size_of::<V1FileEntry>();
+        let entries_per_sector = SECTOR_SIZE / entry_size;
+        let mut sector_buffer = [0u8; SECTOR_SIZE];
+        let mut files = Vec::new();
 
+        for sector in 0..V1_FILE_TABLE_SECTORS {
+            device.read_blocks(1 + sector, &mut sector_buffer)?;
+            let start_entry = sector as usize * entries_per_sector;
+            let end_entry = core::cmp::min(start_entry + entries_per_sector, V1_MAX_FILES);
             for i in start_entry..end_entry {
-                let offset = (i - start_entry) * entry_size;
-                file_table[i] = unsafe {
-                    ptr::read_unaligned(sector_buffer.as_ptr().add(offset) as *const FileEntry)
+                let entry: V1FileEntry = unsafe {
+                    ptr::read_unaligned(sector_buffer.as_ptr().add((i - start_entry) * entry_size) as *const V1FileEntry)
                 };
+                if entry.flags == 0x01 {
+                    files.push(entry);
+                }
             }
         }
 
-        crate::kernel::uart_write_string("File table loaded from sectors 1-2\r\n");
-
-        // Calculate next free sector
-        let mut next_free_sector = DATA_START_SECTOR;
-        for entry in &file_table {
-            if entry.is_used() {
-                let end_sector = entry.get_start_sector() as u64 + entry.get_size_sectors() as u64;
-                if end_sector > next_free_sector {
-                    next_free_sector = end_sector;
+        let layout = Superblock::for_size(device.block_count())?;
+        let mut moved = Vec::new();
+        let mut kept = Vec::new();
+        for entry in &files {
+            let name_len = entry.name.iter().position(|&b| b == 0).unwrap_or(8);
+            let name = String::from_utf8_lossy(&entry.name[..name_len]).into_owned();
+            let size = entry.size_bytes as u64;
+            let extent = Extent { start: entry.start_sector as u64, len: blocks_for(size) };
+
+            if extent.len > 0 && extent.start >= layout.data_start && extent.start + extent.len <= layout.total_blocks {
+                kept.push((name, size, extent));
+            } else {
+                let mut data = vec![0u8; (extent.len as usize) * SECTOR_SIZE];
+                if extent.len > 0 {
+                    device.read_blocks(extent.start, &mut data)?;
                 }
+                data.truncate(size as usize);
+                moved.push((name, data));
             }
         }
 
-        let file_count = unsafe { ptr::read_volatile(ptr::addr_of!(superblock.file_count)) };
-        crate::kernel::uart_write_string(&alloc::format!(
-            "Filesystem mounted! {} files, next free sector: {}\r\n",
-            file_count, next_free_sector
-        ));
+        let mut fs = Self::create_empty(device)?;
+        let mut root = Vec::new();
 
-        Ok(SimpleFilesystem {
-            superblock,
-            file_table,
-            next_free_sector,
-        })
+        for (name, size, extent) in kept {
+            fs.mark_blocks(extent, true);
+            let inode = fs.alloc_inode()?;
+            let mut file = Inode { size, extents: vec![extent], file_type: mime::from_name(&name), ..Inode::new(KIND_FILE) };
+            fs.write_inode(device, inode, &mut file)?;
+            root.push(DirRecord { inode, kind: KIND_FILE, name });
+        }
+        for (name, data) in moved {
+            let inode = fs.alloc_inode()?;
+            let mut file = Inode { file_type: mime::detect(&name, &data), ..Inode::new(KIND_FILE) };
+            fs.write_data(device, &mut file, &data)?;
+            fs.write_inode(device, inode, &mut file)?;
+            root.push(DirRecord { inode, kind: KIND_FILE, name });
+        }
+
+        let mut root_inode = fs.read_inode(device, ROOT_INODE)?;
+        fs.write_data(device, &mut root_inode, &serialize_dir(&root))?;
+        fs.write_inode(device, ROOT_INODE, &mut root_inode)?;
+
+        // There is no journal yet: commit writes in place with the superblock
+        // last, so a half-converted volume never mounts as v3
+        fs.commit(device)?;
+        crate::kernel::uart_write_string(&alloc::format!("Converted {} file(s) to SimpleFS v2\r\n", root.len()));
+        Ok(())
     }
 
-    /// Get the number of files in the filesystem
-    pub fn file_count(&self) -> u32 {
-        unsafe { ptr::read_volatile(ptr::addr_of!(self.superblock.file_count)) }
+    // ---- Bitmaps ----
+
+    fn bit(bitmap: &[u8], index: u64) -> bool {
+        bitmap[(index / 8) as usize] & (1 << (index % 8)) != 0
     }
 
-    /// List all files in the filesystem
-    pub fn list_files(&self) -> alloc::vec::Vec<&FileEntry> {
-        self.file_table
-            .iter()
-            .filter(|entry| entry.is_used())
-            .collect()
+    fn set_bit(bitmap: &mut [u8], index: u64, value: bool) {
+        let byte = &mut bitmap[(index / 8) as usize];
+        if value {
+            *byte |= 1 << (index % 8);
+        } else {
+            *byte &= !(1 << (index % 8));
+        }
     }
 
-    /// Create a new file in the filesystem
-    pub fn create_file(
-        &mut self,
-        device: &mut VirtioBlkDevice,
-        name: &str,
-        size_bytes: u32,
-    ) -> Result<(), &'static str> {
-        // Validate filename
-        if name.len() == 0 || name.len() > 8 {
-            return Err("Filename must be 1-8 characters");
+    fn set_blocks(bitmap: &mut [u8], extent: Extent, value: bool) {
+        for block in extent.start..extent.start + extent.len {
+            Self::set_bit(bitmap, block, value);
+        }
+    }
+
+    fn mark_dirty(&mut self, block: u64) {
+        if !self.dirty_bitmap_blocks.contains(&block) {
+            self.dirty_bitmap_blocks.push(block);
+        }
+    }
+
+    /// Mark an extent allocated or free
+    fn mark_blocks(&mut self, extent: Extent, used: bool) {
+        Self::set_blocks(&mut self.block_bitmap, extent, used);
+        if used {
+            self.free_map.remove(extent.start, extent.len);
+        } else {
+            self.free_map.insert(extent.start, extent.len);
+        }
+        let first = self.superblock.block_bitmap_start + extent.start / BITS_PER_BLOCK;
+        let last = self.superblock.block_bitmap_start + (extent.start + extent.len - 1) / BITS_PER_BLOCK;
+        for block in first..=last {
+            self.mark_dirty(block);
+        }
+        if used {
+            self.superblock.free_blocks -= extent.len;
+        } else {
+            self.superblock.free_blocks += extent.len;
         }
+    }
+
+    /// Allocate up to `max` contiguous blocks. Allocation continues at
+    /// `goal` if that block is free, so a growing file stays in one piece;
+    /// otherwise it takes the shortest free run that holds all `max` blocks,
+    /// or the longest run if none does.
+    fn alloc_extent(&mut self, goal: u64, max: u64) -> Result<Extent, &'static str> {
+        let max = core::cmp::min(max, MAX_EXTENT_LEN);
+        let (start, len) = match self.free_map.run_at(goal) {
+            Some((run, run_len)) => (goal, run + run_len - goal),
+            None => self.free_map.best_fit(max).ok_or("Not enough disk space")?,
+        };
+        let extent = Extent { start, len: core::cmp::min(len, max) };
+        self.mark_blocks(extent, true);
+        Ok(extent)
+    }
 
-        // Check if file already exists
-        for entry in &self.file_table {
-            if entry.is_used() && entry.get_name() == name {
-                return Err("File already exists");
+    fn alloc_inode(&mut self) -> Result<u32, &'static str> {
+        for inode in ROOT_INODE + 1..self.superblock.inode_count {
+            if !Self::bit(&self.inode_bitmap, inode as u64) {
+                Self::set_bit(&mut self.inode_bitmap, inode as u64, true);
+                self.mark_dirty(self.superblock.inode_bitmap_start + inode as u64 / BITS_PER_BLOCK);
+                self.superblock.free_inodes -= 1;
+                return Ok(inode);
             }
         }
+        Err("No free inodes")
+    }
 
-        // Find free entry
-        let mut entry_index = None;
-        for (i, entry) in self.file_table.iter().enumerate() {
-            if entry.is_free() {
-                entry_index = Some(i);
-                break;
+    fn free_inode(&mut self, inode: u32) {
+        Self::set_bit(&mut self.inode_bitmap, inode as u64, false);
+        self.mark_dirty(self.superblock.inode_bitmap_start + inode as u64 / BITS_PER_BLOCK);
+        self.superblock.free_inodes += 1;
+    }
+
+    /// Free an extent once the current transaction commits. Until then the
+    /// on-disk metadata may still point at it, so it must not be reused.
+    /// Blocks a snapshot holds are left allocated.
+    fn release_blocks(&mut self, extent: Extent) {
+        if self.superblock.snapshot_table == 0 {
+            self.pending_free.push(extent);
+            return;
+        }
+        let end = extent.start + extent.len;
+        let mut block = extent.start;
+        while block < end {
+            let start = block;
+            let shared = Self::bit(&self.shared, block);
+            while block < end && Self::bit(&self.shared, block) == shared {
+                block += 1;
+            }
+            if !shared {
+                self.pending_free.push(Extent { start, len: block - start });
             }
         }
+    }
 
-        let entry_index = entry_index.ok_or("File table full (max 32 files)")?;
+    /// Whether any of a file's blocks are held by a snapshot
+    fn is_shared(&self, data: &Inode) -> bool {
+        self.superblock.snapshot_table != 0
+            && data.extents.iter().any(|e| (e.start..e.start + e.len).any(|block| Self::bit(&self.shared, block)))
+    }
 
-        // Calculate sectors needed
-        let size_sectors = ((size_bytes + SECTOR_SIZE as u32 - 1) / SECTOR_SIZE as u32) as u16;
+    // ---- Transactions ----
 
-        // Check if we have enough space
-        let total_sectors = unsafe { ptr::read_volatile(ptr::addr_of!(self.superblock.total_sectors)) };
-        if self.next_free_sector + size_sectors as u64 > total_sectors {
-            return Err("Not enough disk space");
+    /// Run `op` as one atomic metadata update: committed if it succeeds,
+    /// rolled back if it fails
+    fn transaction<T>(
+        &mut self,
+        device: &mut dyn BlockDevice,
+        op: impl FnOnce(&mut Self, &mut dyn BlockDevice) -> Result<T, &'static str>,
+    ) -> Result<T, &'static str> {
+        let result = op(self, device).and_then(|value| self.commit(device).map(|_| value));
+        if result.is_err() {
+            if let Err(e) = self.abort(device) {
+                crate::kernel::uart_write_string(&alloc::format!("SimpleFS: rollback failed: {}\r\n", e));
+            }
         }
+        result
+    }
 
-        // Create file entry
-        let mut name_bytes = [0u8; 8];
-        for (i, byte) in name.bytes().take(8).enumerate() {
-            name_bytes[i] = byte;
+    /// Read blocks as the current transaction sees them
+    fn read_meta(&self, device: &mut dyn BlockDevice, start: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+        device.read_blocks(start, buffer)?;
+        let end = start + (buffer.len() / SECTOR_SIZE) as u64;
+        for (&block, data) in self.staged.range(start..end) {
+            let offset = ((block - start) as usize) * SECTOR_SIZE;
+            buffer[offset..offset + SECTOR_SIZE].copy_from_slice(data);
         }
+        Ok(())
+    }
 
-        let new_entry = FileEntry {
-            name: name_bytes,
-            start_sector: self.next_free_sector as u16,
-            size_sectors,
-            size_bytes,
-            flags: FileEntry::FLAG_USED,
-            reserved: [0; 3],
-        };
+    /// Stage metadata blocks for the current transaction
+    fn write_meta(&mut self, start: u64, data: &[u8]) {
+        for (i, block) in data.chunks(SECTOR_SIZE).enumerate() {
+            self.staged.insert(start + i as u64, block.to_vec());
+        }
+    }
 
-        // Update file table in memory
-        self.file_table[entry_index] = new_entry;
+    /// Make the current transaction durable: log every changed metadata block
+    /// to the journal, then write them home and flush
+    fn commit(&mut self, device: &mut dyn BlockDevice) -> Result<(), &'static str> {
+        let freed = core::mem::take(&mut self.pending_free);
+        for &extent in &freed {
+            self.mark_blocks(extent, false);
+        }
 
-        // Update superblock
-        let mut file_count = unsafe { ptr::read_volatile(ptr::addr_of!(self.superblock.file_count)) };
-        file_count += 1;
-        unsafe {
-            ptr::write_volatile(ptr::addr_of_mut!(self.superblock.file_count) as *mut u32, file_count);
+        let sb = self.superblock;
+        self.dirty_bitmap_blocks.sort_unstable();
+        for block in core::mem::take(&mut self.dirty_bitmap_blocks) {
+            let (bitmap, first) = if block < sb.inode_bitmap_start {
+                (&self.block_bitmap, sb.block_bitmap_start)
+            } else {
+                (&self.inode_bitmap, sb.inode_bitmap_start)
+            };
+            let offset = ((block - first) as usize) * SECTOR_SIZE;
+            self.staged.insert(block, bitmap[offset..offset + SECTOR_SIZE].to_vec());
         }
+        let mut sector_buffer = vec![0u8; SECTOR_SIZE];
+        sb.write(&mut sector_buffer);
+        self.staged.insert(0, sector_buffer);
 
-        // Update next free sector
-        self.next_free_sector += size_sectors as u64;
+        let blocks = core::mem::take(&mut self.staged);
+        let journaled = self.write_journal(device, &blocks)?;
 
-        // Write updated superblock to disk
-        // Use static buffer to avoid stack overflow
-        static mut CREATE_BUFFER: [u8; 512] = [0; 512];
+        // Superblock last, so an unjournaled update never looks complete early
+        for (&block, data) in blocks.range(1..).chain(blocks.range(..1)) {
+            device.write_blocks(block, data)?;
+        }
+        device.flush()?;
 
-        unsafe {
-            CREATE_BUFFER.fill(0);
-            ptr::copy_nonoverlapping(
-                &self.superblock as *const Superblock as *const u8,
-                CREATE_BUFFER.as_mut_ptr(),
-                core::mem::size_of::<Superblock>(),
-            );
+        if journaled {
+            device.write_zeroes(sb.journal_start, 1)?;
         }
-        device.write_sector(0, unsafe { &CREATE_BUFFER })?;
+        for extent in freed {
+            device.discard(extent.start, extent.len)?;
+        }
+        device.flush()
+    }
 
-        // Write updated file table to disk (sectors 1-2)
-        let entry_size = core::mem::size_of::<FileEntry>();
-        let entries_per_sector = SECTOR_SIZE / entry_size;
+    /// Drop the current transaction and reload the committed state
+    fn abort(&mut self, device: &mut dyn BlockDevice) -> Result<(), &'static str> {
+        self.staged.clear();
+        self.pending_free.clear();
+        self.dirty_bitmap_blocks.clear();
 
-        for sector in 0..FILE_TABLE_SECTORS {
-            unsafe {
-                CREATE_BUFFER.fill(0);
-                let start_entry = (sector * entries_per_sector as u64) as usize;
-                let end_entry = core::cmp::min(start_entry + entries_per_sector, MAX_FILES);
-
-                for i in start_entry..end_entry {
-                    let offset = (i - start_entry) * entry_size;
-                    ptr::copy_nonoverlapping(
-                        &self.file_table[i] as *const FileEntry as *const u8,
-                        CREATE_BUFFER.as_mut_ptr().add(offset),
-                        entry_size,
-                    );
-                }
-            }
-            device.write_sector(1 + sector, unsafe { &CREATE_BUFFER })?;
+        // Finishes a commit that failed after its header was written
+        if self.superblock.journal_len > 0 {
+            Self::replay_journal(device, &self.superblock)?;
         }
+        let mut sector_buffer = [0u8; SECTOR_SIZE];
+        device.read_blocks(0, &mut sector_buffer)?;
+        *self = Self::load(device, Superblock::read(&sector_buffer))?;
+        Ok(())
+    }
 
+    // ---- Journal ----
+
+    /// Reserve a contiguous run of blocks for the journal, if there is room.
+    /// It always holds a transaction that rewrites both bitmaps whole, plus
+    /// MIN_JOURNAL_BLOCKS of inodes and directories, so no operation short of
+    /// that is ever refused for being too large.
+    fn add_journal(&mut self, device: &mut dyn BlockDevice) -> Result<(), &'static str> {
+        let sb = self.superblock;
+        let bitmap_blocks = sb.inode_table_start - sb.block_bitmap_start;
+        let len = (sb.total_blocks / 64)
+            .clamp(MIN_JOURNAL_BLOCKS, MAX_JOURNAL_BLOCKS)
+            .max(journal_space((bitmap_blocks + MIN_JOURNAL_BLOCKS) as usize));
+
+        let room = if len <= sb.free_blocks / 4 { self.free_map.first_fit_below(len, sb.total_blocks) } else { None };
+        if let Some(start) = room {
+            // A stale header must not look like a committed transaction
+            device.write_zeroes(start, 1)?;
+            self.mark_blocks(Extent { start, len }, true);
+            self.superblock.journal_start = start;
+            self.superblock.journal_len = len;
+            crate::kernel::uart_write_string(&alloc::format!("Created a {}-block journal\r\n", len));
+            return Ok(());
+        }
+        crate::kernel::uart_write_string("WARNING: no room for a journal, metadata updates are not crash-safe\r\n");
         Ok(())
     }
 
-    /// Rename a file in the filesystem
-    pub fn rename_file(
-        &mut self,
-        device: &mut VirtioBlkDevice,
-        old_name: &str,
-        new_name: &str,
-    ) -> Result<(), &'static str> {
-        // Validate new name length
-        if new_name.len() > 8 {
-            return Err("New filename too long (max 8 chars)");
+    /// Log `blocks` to the journal. Returns false without writing anything
+    /// if the volume has no journal. A transaction that doesn't fit is
+    /// refused rather than written in place without its atomicity, and the
+    /// operation is rolled back.
+    fn write_journal(&self, device: &mut dyn BlockDevice, blocks: &BTreeMap<u64, Vec<u8>>) -> Result<bool, &'static str> {
+        let sb = &self.superblock;
+        let tag_blocks = (blocks.len() + TAGS_PER_BLOCK - 1) / TAGS_PER_BLOCK;
+        if sb.journal_len == 0 {
+            return Ok(false);
         }
-        if new_name.is_empty() {
-            return Err("New filename cannot be empty");
+        if journal_space(blocks.len()) > sb.journal_len {
+            crate::kernel::uart_write_string(&alloc::format!(
+                "SimpleFS: {}-block transaction doesn't fit the {}-block journal\r\n",
+                blocks.len(), sb.journal_len
+            ));
+            return Err("Transaction too large for the SimpleFS journal");
         }
 
-        // Check if new name already exists
-        for entry in self.file_table.iter() {
-            if entry.is_used() && entry.get_name() == new_name {
-                return Err("File with new name already exists");
-            }
+        let mut log = vec![0u8; (tag_blocks + blocks.len()) * SECTOR_SIZE];
+        for (i, (&block, data)) in blocks.iter().enumerate() {
+            write_u64(&mut log, i * 8, block);
+            let offset = (tag_blocks + i) * SECTOR_SIZE;
+            log[offset..offset + SECTOR_SIZE].copy_from_slice(data);
         }
+        device.write_blocks(sb.journal_start + 1, &log)?;
+        device.flush()?;
+
+        // The header is the commit record: once it is on disk, mount replays
+        // the transaction
+        let mut header = [0u8; SECTOR_SIZE];
+        write_u32(&mut header, 0, JOURNAL_MAGIC);
+        write_u32(&mut header, 4, blocks.len() as u32);
+        write_u32(&mut header, 8, crc32(&log));
+        device.write_blocks(sb.journal_start, &header)?;
+        device.flush()?;
+        Ok(true)
+    }
 
-        // Find the file to rename
-        let mut entry_index = None;
-        for (i, entry) in self.file_table.iter().enumerate() {
-            if entry.is_used() && entry.get_name() == old_name {
-                entry_index = Some(i);
-                break;
-            }
+    /// Finish writing a committed transaction left in the journal by a crash.
+    /// A transaction without a valid header never reached its home blocks
+    /// and is ignored.
+    fn replay_journal(device: &mut dyn BlockDevice, sb: &Superblock) -> Result<(), &'static str> {
+        let mut header = [0u8; SECTOR_SIZE];
+        device.read_blocks(sb.journal_start, &mut header)?;
+        let count = read_u32(&header, 4) as usize;
+        if read_u32(&header, 0) != JOURNAL_MAGIC || count == 0 {
+            return Ok(());
         }
 
-        let entry_index = entry_index.ok_or("File not found")?;
-
-        // Update the name in the file entry
-        let mut new_name_bytes = [0u8; 8];
-        let name_bytes = new_name.as_bytes();
-        new_name_bytes[..name_bytes.len()].copy_from_slice(name_bytes);
-
-        unsafe {
-            ptr::copy_nonoverlapping(
-                new_name_bytes.as_ptr(),
-                ptr::addr_of_mut!(self.file_table[entry_index].name) as *mut u8,
-                8,
-            );
+        let tag_blocks = (count + TAGS_PER_BLOCK - 1) / TAGS_PER_BLOCK;
+        if journal_space(count) > sb.journal_len {
+            return Err("Corrupt SimpleFS journal");
+        }
+        let mut log = vec![0u8; (tag_blocks + count) * SECTOR_SIZE];
+        device.read_blocks(sb.journal_start + 1, &mut log)?;
+        if crc32(&log) != read_u32(&header, 8) {
+            crate::kernel::uart_write_string("Ignoring incomplete journal transaction\r\n");
+            return Ok(());
         }
 
-        // Write updated file table to disk (sectors 1-2)
-        static mut RENAME_BUFFER: [u8; 512] = [0; 512];
-        let entry_size = core::mem::size_of::<FileEntry>();
-        let entries_per_sector = SECTOR_SIZE / entry_size;
-
-        for sector in 0..FILE_TABLE_SECTORS {
-            unsafe {
-                RENAME_BUFFER.fill(0);
-                let start_entry = (sector * entries_per_sector as u64) as usize;
-                let end_entry = core::cmp::min(start_entry + entries_per_sector, MAX_FILES);
-
-                for i in start_entry..end_entry {
-                    let offset = (i - start_entry) * entry_size;
-                    ptr::copy_nonoverlapping(
-                        &self.file_table[i] as *const FileEntry as *const u8,
-                        RENAME_BUFFER.as_mut_ptr().add(offset),
-                        entry_size,
-                    );
-                }
-            }
-            device.write_sector(1 + sector, unsafe { &RENAME_BUFFER })?;
+        let journal = sb.journal_start..sb.journal_start + sb.journal_len;
+        if (0..count).map(|i| read_u64(&log, i * 8)).any(|block| block >= sb.total_blocks || journal.contains(&block)) {
+            return Err("Corrupt SimpleFS journal");
+        }
+        if device.is_read_only() {
+            return Err("SimpleFS journal needs replaying but the device is read-only");
         }
 
+        for i in 0..count {
+            let offset = (tag_blocks + i) * SECTOR_SIZE;
+            device.write_blocks(read_u64(&log, i * 8), &log[offset..offset + SECTOR_SIZE])?;
+        }
+        device.flush()?;
+        device.write_zeroes(sb.journal_start, 1)?;
+        device.flush()?;
+        crate::kernel::uart_write_string(&alloc::format!("Replayed {} block(s) from the journal\r\n", count));
         Ok(())
     }
 
-    /// Delete a file from the filesystem
-    pub fn delete_file(
-        &mut self,
-        device: &mut VirtioBlkDevice,
-        name: &str,
-    ) -> Result<(), &'static str> {
-        // Find the file
-        let mut entry_index = None;
-        for (i, entry) in self.file_table.iter().enumerate() {
-            if entry.is_used() && entry.get_name() == name {
-                entry_index = Some(i);
-                break;
-            }
-        }
-
-        let entry_index = entry_index.ok_or("File not found")?;
+    // ---- Inodes ----
 
-        // Mark entry as free
-        unsafe {
-            ptr::write_volatile(
-                ptr::addr_of_mut!(self.file_table[entry_index].flags) as *mut u8,
-                FileEntry::FLAG_FREE
-            );
+    fn inode_location(&self, inode: u32) -> Result<(u64, usize), &'static str> {
+        if inode == 0 || inode >= self.superblock.inode_count {
+            return Err("Corrupt inode number");
         }
+        let block = self.superblock.inode_table_start + (inode / INODES_PER_BLOCK) as u64;
+        Ok((block, (inode % INODES_PER_BLOCK) as usize * INODE_SIZE))
+    }
 
-        // Update superblock
-        let mut file_count = unsafe { ptr::read_volatile(ptr::addr_of!(self.superblock.file_count)) };
-        file_count -= 1;
-        unsafe {
-            ptr::write_volatile(ptr::addr_of_mut!(self.superblock.file_count) as *mut u32, file_count);
+    fn read_inode(&self, device: &mut dyn BlockDevice, inode: u32) -> Result<Inode, &'static str> {
+        match self.decode_inode(device, inode)? {
+            (data, None) => Ok(data),
+            (_, Some(problem)) => Err(problem),
         }
+    }
 
-        // Write updated superblock to disk
-        // Use static buffer to avoid stack issues
-        static mut TEMP_BUFFER: [u8; 512] = [0; 512];
+    /// Read an inode, keeping its extent list up to the first bad extent or
+    /// chain block. The second value says what was wrong, if anything.
+    fn decode_inode(&self, device: &mut dyn BlockDevice, inode: u32) -> Result<(Inode, Option<&'static str>), &'static str> {
+        let (block, offset) = self.inode_location(inode)?;
+        let mut sector_buffer = [0u8; SECTOR_SIZE];
+        self.read_meta(device, block, &mut sector_buffer)?;
+        let raw = &sector_buffer[offset..offset + INODE_SIZE];
 
-        unsafe {
-            TEMP_BUFFER.fill(0);
-            ptr::copy_nonoverlapping(
-                &self.superblock as *const Superblock as *const u8,
-                TEMP_BUFFER.as_mut_ptr(),
-                core::mem::size_of::<Superblock>(),
-            );
-        }
-        if let Err(e) = device.write_sector(0, unsafe { &TEMP_BUFFER }) {
-            return Err(e);
+        let kind = raw[0];
+        if kind == KIND_FREE {
+            return Err("Corrupt directory entry (free inode)");
         }
+        let extent_count = read_u32(raw, 4) as usize;
+        let mut result = Inode {
+            kind,
+            attributes: raw[1],
+            file_type: raw[2],
+            size: read_u64(raw, 8),
+            created: read_u64(raw, 104) as i64,
+            modified: read_u64(raw, 112) as i64,
+            accessed: read_u64(raw, 120) as i64,
+            extents: Vec::with_capacity(extent_count),
+            overflow: Vec::new(),
+        };
 
-        // Write updated file table to disk (sectors 1-2)
-        let entry_size = core::mem::size_of::<FileEntry>();
-        let entries_per_sector = SECTOR_SIZE / entry_size;
+        let read_extent = |bytes: &[u8], at: usize| Extent { start: read_u64(bytes, at), len: read_u32(bytes, at + 8) as u64 };
+        for i in 0..core::cmp::min(extent_count, INODE_EXTENTS) {
+            result.extents.push(read_extent(raw, 32 + i * 12));
+        }
 
-        for sector in 0..FILE_TABLE_SECTORS {
-            unsafe {
-                TEMP_BUFFER.fill(0);
-                let start_entry = (sector * entries_per_sector as u64) as usize;
-                let end_entry = core::cmp::min(start_entry + entries_per_sector, MAX_FILES);
-
-                for i in start_entry..end_entry {
-                    let offset = (i - start_entry) * entry_size;
-                    ptr::copy_nonoverlapping(
-                        &self.file_table[i] as *const FileEntry as *const u8,
-                        TEMP_BUFFER.as_mut_ptr().add(offset),
-                        entry_size,
-                    );
-                }
+        let mut next = read_u64(raw, 16);
+        while result.extents.len() < extent_count {
+            if next < self.superblock.data_start || next >= self.superblock.total_blocks || result.overflow.contains(&next) {
+                return Ok((result, Some("Corrupt extent chain")));
             }
-            if let Err(e) = device.write_sector(1 + sector, unsafe { &TEMP_BUFFER }) {
-                return Err(e);
+            result.overflow.push(next);
+            self.read_meta(device, next, &mut sector_buffer)?;
+            let count = core::cmp::min(extent_count - result.extents.len(), BLOCK_EXTENTS);
+            for i in 0..count {
+                result.extents.push(read_extent(&sector_buffer, 8 + i * 12));
             }
+            next = read_u64(&sector_buffer, 0);
         }
 
-        Ok(())
+        let bad = |e: &Extent| e.len == 0 || e.start < self.superblock.data_start || e.start + e.len > self.superblock.total_blocks;
+        if let Some(index) = result.extents.iter().position(bad) {
+            result.extents.truncate(index);
+            return Ok((result, Some("Corrupt extent")));
+        }
+        Ok((result, None))
     }
 
-    /// Write data to a file
-    pub fn write_file(
-        &mut self,
-        device: &mut VirtioBlkDevice,
-        name: &str,
-        data: &[u8],
-    ) -> Result<(), &'static str> {
-        // Find the file
-        let mut file_entry = None;
-        for entry in &self.file_table {
-            if entry.is_used() && entry.get_name() == name {
-                file_entry = Some(entry.clone());
-                break;
-            }
+    /// Write an inode, growing or shrinking its overflow chain to fit
+    fn write_inode(&mut self, device: &mut dyn BlockDevice, inode: u32, data: &mut Inode) -> Result<(), &'static str> {
+        let overflow_needed = overflow_blocks(data.extents.len());
+        while data.overflow.len() > overflow_needed {
+            let block = data.overflow.pop().unwrap();
+            self.release_blocks(Extent { start: block, len: 1 });
+        }
+        while data.overflow.len() < overflow_needed {
+            let goal = data.overflow.last().copied().unwrap_or(0);
+            data.overflow.push(self.alloc_extent(goal, 1)?.start);
         }
 
-        let file_entry = file_entry.ok_or("File not found")?;
+        let write_extent = |bytes: &mut [u8], at: usize, extent: &Extent| {
+            write_u64(bytes, at, extent.start);
+            write_u32(bytes, at + 8, extent.len as u32);
+        };
+
+        let mut sector_buffer = [0u8; SECTOR_SIZE];
+        for (i, &block) in data.overflow.iter().enumerate() {
+            sector_buffer.fill(0);
+            write_u64(&mut sector_buffer, 0, data.overflow.get(i + 1).copied().unwrap_or(0));
+            let first = INODE_EXTENTS + i * BLOCK_EXTENTS;
+            let last = core::cmp::min(first + BLOCK_EXTENTS, data.extents.len());
+            for (j, extent) in data.extents[first..last].iter().enumerate() {
+                write_extent(&mut sector_buffer, 8 + j * 12, extent);
+            }
+            self.write_meta(block, &sector_buffer);
+        }
 
-        let size_bytes = file_entry.get_size_bytes();
-        if data.len() > size_bytes as usize {
-            return Err("Data too large for file");
+        let (block, offset) = self.inode_location(inode)?;
+        self.read_meta(device, block, &mut sector_buffer)?;
+        let raw = &mut sector_buffer[offset..offset + INODE_SIZE];
+        raw.fill(0);
+        raw[0] = data.kind;
+        raw[1] = data.attributes;
+        raw[2] = data.file_type;
+        write_u32(raw, 4, data.extents.len() as u32);
+        write_u64(raw, 8, data.size);
+        write_u64(raw, 16, data.overflow.first().copied().unwrap_or(0));
+        for (i, extent) in data.extents.iter().take(INODE_EXTENTS).enumerate() {
+            write_extent(raw, 32 + i * 12, extent);
         }
+        write_u64(raw, 104, data.created as u64);
+        write_u64(raw, 112, data.modified as u64);
+        write_u64(raw, 120, data.accessed as u64);
+        self.write_meta(block, &sector_buffer);
+        Ok(())
+    }
 
-        let start_sector = file_entry.get_start_sector();
-        let size_sectors = file_entry.get_size_sectors();
+    /// Clear an inode on disk and release it with all of its blocks
+    fn release_inode(&mut self, device: &mut dyn BlockDevice, inode: u32, data: &mut Inode) -> Result<(), &'static str> {
+        self.resize(device, data, 0)?;
+        for block in core::mem::take(&mut data.overflow) {
+            self.release_blocks(Extent { start: block, len: 1 });
+        }
+        self.erase_inode(device, inode)?;
+        self.free_inode(inode);
+        Ok(())
+    }
 
-        // Write data sector by sector
-        let mut bytes_written = 0;
-        for sector_idx in 0..size_sectors {
-            let mut sector_buffer = [0u8; SECTOR_SIZE];
+    /// Zero an inode's slot in the inode table
+    fn erase_inode(&mut self, device: &mut dyn BlockDevice, inode: u32) -> Result<(), &'static str> {
+        let (block, offset) = self.inode_location(inode)?;
+        let mut sector_buffer = [0u8; SECTOR_SIZE];
+        self.read_meta(device, block, &mut sector_buffer)?;
+        sector_buffer[offset..offset + INODE_SIZE].fill(0);
+        self.write_meta(block, &sector_buffer);
+        Ok(())
+    }
 
-            // Copy data for this sector
-            let bytes_to_write = core::cmp::min(SECTOR_SIZE, data.len() - bytes_written);
-            sector_buffer[..bytes_to_write].copy_from_slice(&data[bytes_written..bytes_written + bytes_to_write]);
+    // ---- File data ----
 
-            // Write sector
-            device.write_sector((start_sector + sector_idx) as u64, &sector_buffer)?;
+    /// Give a file exactly `blocks` data blocks. New blocks are not zeroed.
+    fn resize(&mut self, device: &mut dyn BlockDevice, data: &mut Inode, blocks: u64) -> Result<(), &'static str> {
+        let mut current = data.block_count();
 
-            bytes_written += bytes_to_write;
-            if bytes_written >= data.len() {
-                break;
+        while current > blocks {
+            let last = data.extents.last_mut().unwrap();
+            let cut = core::cmp::min(last.len, current - blocks);
+            let freed = Extent { start: last.start + last.len - cut, len: cut };
+            last.len -= cut;
+            if last.len == 0 {
+                data.extents.pop();
             }
+            self.release_blocks(freed);
+            current -= cut;
         }
 
+        let original = current;
+        while current < blocks {
+            let goal = data.extents.last().map(|e| e.start + e.len).unwrap_or(0);
+            let extent = match self.alloc_extent(goal, blocks - current) {
+                Ok(extent) => extent,
+                Err(e) => {
+                    // Give back what this call allocated
+                    self.resize(device, data, original)?;
+                    return Err(e);
+                }
+            };
+            match data.extents.last_mut() {
+                Some(last) if last.start + last.len == extent.start && last.len + extent.len <= MAX_EXTENT_LEN => {
+                    last.len += extent.len
+                }
+                _ => data.extents.push(extent),
+            }
+            current += extent.len;
+        }
         Ok(())
     }
 
-    /// Read data from a file
-    pub fn read_file(
-        &self,
-        device: &mut VirtioBlkDevice,
-        name: &str,
-        buffer: &mut [u8],
-    ) -> Result<usize, &'static str> {
-        // Find the file
-        let mut file_entry = None;
-        for entry in &self.file_table {
-            if entry.is_used() && entry.get_name() == name {
-                file_entry = Some(entry.clone());
+    /// Read the first `buffer.len()` bytes of a file
+    fn read_data(&self, device: &mut dyn BlockDevice, data: &Inode, buffer: &mut [u8]) -> Result<(), &'static str> {
+        let mut pos = 0;
+        for extent in &data.extents {
+            if pos >= buffer.len() {
                 break;
             }
+            // Whole blocks straight into the buffer, the partial tail bounced
+            let len = core::cmp::min(extent.len as usize * SECTOR_SIZE, buffer.len() - pos);
+            let whole = len / SECTOR_SIZE * SECTOR_SIZE;
+            if whole > 0 {
+                self.read_meta(device, extent.start, &mut buffer[pos..pos + whole])?;
+            }
+            if whole < len {
+                let mut sector_buffer = [0u8; SECTOR_SIZE];
+                self.read_meta(device, extent.start + (whole / SECTOR_SIZE) as u64, &mut sector_buffer)?;
+                buffer[pos + whole..pos + len].copy_from_slice(&sector_buffer[..len - whole]);
+            }
+            pos += len;
         }
-
-        let file_entry = file_entry.ok_or("File not found")?;
-
-        let size_bytes = file_entry.get_size_bytes() as usize;
-        if buffer.len() < size_bytes {
-            return Err("Buffer too small for file");
+        if pos < buffer.len() {
+            return Err("File data missing");
         }
+        Ok(())
+    }
 
-        let start_sector = file_entry.get_start_sector();
-        let size_sectors = file_entry.get_size_sectors();
-
-        // Read data sector by sector
-        let mut bytes_read = 0;
-        for sector_idx in 0..size_sectors {
-            let mut sector_buffer = [0u8; SECTOR_SIZE];
+    /// Replace a file's contents with `bytes`. Directory and snapshot
+    /// contents are metadata and go into the transaction; file data is
+    /// written directly, into new blocks if a snapshot holds the old ones.
+    fn write_data(&mut self, device: &mut dyn BlockDevice, data: &mut Inode, bytes: &[u8]) -> Result<(), &'static str> {
+        if data.kind == KIND_FILE && self.is_shared(data) {
+            self.resize(device, data, 0)?;
+        }
+        self.resize(device, data, blocks_for(bytes.len() as u64))?;
+
+        let journaled = data.kind != KIND_FILE;
+        let mut pos = 0;
+        for extent in &data.extents {
+            let len = core::cmp::min(extent.len as usize * SECTOR_SIZE, bytes.len() - pos);
+            let whole = len / SECTOR_SIZE * SECTOR_SIZE;
+            if whole > 0 {
+                if journaled {
+                    self.write_meta(extent.start, &bytes[pos..pos + whole]);
+                } else {
+                    device.write_blocks(extent.start, &bytes[pos..pos + whole])?;
+                }
+            }
+            // Zero-pad the tail into one last sector
+            if whole < len {
+                let mut sector_buffer = [0u8; SECTOR_SIZE];
+                sector_buffer[..len - whole].copy_from_slice(&bytes[pos + whole..pos + len]);
+                let block = extent.start + (whole / SECTOR_SIZE) as u64;
+                if journaled {
+                    self.write_meta(block, &sector_buffer);
+                } else {
+                    device.write_blocks(block, &sector_buffer)?;
+                }
+            }
+            pos += len;
+        }
+        data.size = bytes.len() as u64;
+        data.modified = now();
+        Ok(())
+    }
+
+    /// Set a file's size, zero-filling any new space
+    fn set_size(&mut self, device: &mut dyn BlockDevice, data: &mut Inode, size: u64) -> Result<(), &'static str> {
+        let old_blocks = data.block_count();
+        self.resize(device, data, blocks_for(size))?;
+
+        if size > data.size {
+            // Clear the rest of the old last block...
+            let tail = (data.size % SECTOR_SIZE as u64) as usize;
+            if tail != 0 {
+                let block = self.unshare_block(device, data, data.size / SECTOR_SIZE as u64)?;
+                let mut sector_buffer = [0u8; SECTOR_SIZE];
+                device.read_blocks(block, &mut sector_buffer)?;
+                sector_buffer[tail..].fill(0);
+                device.write_blocks(block, &sector_buffer)?;
+            }
+            // ...and all newly allocated blocks
+            let mut index = old_blocks;
+            while index < data.block_count() {
+                let (block, run) = data.map(index).unwrap();
+                let run = core::cmp::min(run, data.block_count() - index);
+                device.write_zeroes(block, run)?;
+                index += run;
+            }
+        }
+        data.size = size;
+        data.modified = now();
+        Ok(())
+    }
+
+    /// Device block holding block `index` of a file, first moving it to a
+    /// copy of its own if a snapshot holds it
+    fn unshare_block(&mut self, device: &mut dyn BlockDevice, data: &mut Inode, index: u64) -> Result<u64, &'static str> {
+        let (block, _) = data.map(index).ok_or("File data missing")?;
+        if !Self::bit(&self.shared, block) {
+            return Ok(block);
+        }
+        let copy = self.alloc_extent(0, 1)?.start;
+        let mut sector_buffer = [0u8; SECTOR_SIZE];
+        device.read_blocks(block, &mut sector_buffer)?;
+        device.write_blocks(copy, &sector_buffer)?;
+
+        // Split the extent around the copied block
+        let (mut position, mut first) = (0, index);
+        while first >= data.extents[position].len {
+            first -= data.extents[position].len;
+            position += 1;
+        }
+        let extent = data.extents[position];
+        let pieces = [
+            Extent { start: extent.start, len: first },
+            Extent { start: copy, len: 1 },
+            Extent { start: block + 1, len: extent.len - first - 1 },
+        ];
+        data.extents.splice(position..=position, pieces.into_iter().filter(|piece| piece.len > 0));
+        Ok(copy)
+    }
+
+    // ---- Directories ----
+
+    fn read_dir(&self, device: &mut dyn BlockDevice, inode: u32) -> Result<Vec<DirRecord>, &'static str> {
+        let data = self.read_inode(device, inode)?;
+        if data.kind != KIND_DIR {
+            return Err("Not a directory");
+        }
+        let mut bytes = vec![0u8; data.size as usize];
+        self.read_data(device, &data, &mut bytes)?;
+        Ok(parse_dir(&bytes))
+    }
+
+    fn write_dir(&mut self, device: &mut dyn BlockDevice, inode: u32, records: &[DirRecord]) -> Result<(), &'static str> {
+        let mut data = self.read_inode(device, inode)?;
+        self.write_data(device, &mut data, &serialize_dir(records))?;
+        self.write_inode(device, inode, &mut data)
+    }
+
+    /// Inode of the directory at `parts`
+    fn resolve_dir(&self, device: &mut dyn BlockDevice, parts: &[&str]) -> Result<u32, &'static str> {
+        let mut inode = ROOT_INODE;
+        for part in parts {
+            let record = self.read_dir(device, inode)?.into_iter().find(|r| r.name == *part).ok_or("Directory not found")?;
+            if record.kind != KIND_DIR {
+                return Err("Not a directory");
+            }
+            inode = record.inode;
+        }
+        Ok(inode)
+    }
+
+    /// Parent directory inode and final name of `path`
+    fn split_parent<'a>(&self, device: &mut dyn BlockDevice, path: &'a str) -> Result<(u32, &'a str), &'static str> {
+        let parts = components(path);
+        let (name, parents) = parts.split_last().ok_or("Invalid path")?;
+        Ok((self.resolve_dir(device, parents)?, name))
+    }
+
+    /// Parent directory, its records and the index of the record for `path`
+    fn lookup(&self, device: &mut dyn BlockDevice, path: &str) -> Result<(u32, Vec<DirRecord>, usize), &'static str> {
+        let (parent, name) = self.split_parent(device, path)?;
+        let records = self.read_dir(device, parent)?;
+        let index = records.iter().position(|r| r.name == name).ok_or("File not found")?;
+        Ok((parent, records, index))
+    }
 
-            // Read sector
-            device.read_sector((start_sector + sector_idx) as u64, &mut sector_buffer)?;
+    /// Create an empty file or directory at `path`
+    fn create_node(&mut self, device: &mut dyn BlockDevice, path: &str, kind: u8) -> Result<(u32, Inode), &'static str> {
+        let (parent, name) = self.split_parent(device, path)?;
+        validate_name(name)?;
+        let mut records = self.read_dir(device, parent)?;
+        if records.iter().any(|r| r.name == name) {
+            return Err("File already exists");
+        }
+
+        let inode = self.alloc_inode()?;
+        let mut data = Inode::new(kind);
+        if kind == KIND_FILE {
+            data.file_type = mime::from_name(name);
+        }
+        self.write_inode(device, inode, &mut data)?;
+        records.push(DirRecord { inode, kind, name: String::from(name) });
+        self.write_dir(device, parent, &records)?;
+        Ok((inode, data))
+    }
 
-            // Copy to output buffer
-            let bytes_to_copy = core::cmp::min(SECTOR_SIZE, size_bytes - bytes_read);
-            buffer[bytes_read..bytes_read + bytes_to_copy].copy_from_slice(&sector_buffer[..bytes_to_copy]);
+    /// Find a file or directory by path ("" is the root)
+    fn lookup_inode(&self, device: &mut dyn BlockDevice, path: &str) -> Result<(u32, Inode), &'static str> {
+        let inode = if components(path).is_empty() {
+            ROOT_INODE
+        } else {
+            let (_, records, index) = self.lookup(device, path)?;
+            records[index].inode
+        };
+        Ok((inode, self.read_inode(device, inode)?))
+    }
 
-            bytes_read += bytes_to_copy;
-            if bytes_read >= size_bytes {
+    /// Find a file (not a directory) by path
+    fn open_file(&self, device: &mut dyn BlockDevice, path: &str) -> Result<(u32, Inode), &'static str> {
+        let (inode, data) = self.lookup_inode(device, path)?;
+        if data.kind != KIND_FILE {
+            return Err("Is a directory");
+        }
+        Ok((inode, data))
+    }
+
+    /// Every file and directory on the volume, including the root
+    fn all_inodes(&self, device: &mut dyn BlockDevice) -> Result<Vec<(u32, Inode)>, &'static str> {
+        let mut found = vec![(ROOT_INODE, self.read_inode(device, ROOT_INODE)?)];
+        let mut pending = vec![ROOT_INODE];
+        while let Some(dir) = pending.pop() {
+            for record in self.read_dir(device, dir)? {
+                let data = self.read_inode(device, record.inode)?;
+                if data.kind == KIND_DIR {
+                    pending.push(record.inode);
+                }
+                found.push((record.inode, data));
+            }
+        }
+        Ok(found)
+    }
+
+    // ---- Defragmentation ----
+
+    /// Move each fragmented file into one free run, then pack files towards
+    /// the start of the volume so the free space joins up. Every move copies
+    /// the data first and then switches the inode over in its own
+    /// transaction, so after a crash a file is wholly at its old or its new
+    /// place. Files sharing blocks with a snapshot stay where they are.
+    /// Returns how many files were moved.
+    pub fn defrag(&mut self, device: &mut dyn BlockDevice) -> Result<usize, &'static str> {
+        if device.is_read_only() {
+            return Err("Device is read-only");
+        }
+        let mut moved = 0;
+        for (inode, data) in self.all_inodes(device)? {
+            let blocks = data.block_count();
+            if data.extents.len() > 1 && blocks <= MAX_EXTENT_LEN && !self.is_shared(&data) {
+                if let Some((start, len)) = self.free_map.best_fit(blocks) {
+                    if len >= blocks {
+                        self.relocate(device, inode, start)?;
+                        moved += 1;
+                    }
+                }
+            }
+        }
+
+        // Files only ever move down, so this ends
+        loop {
+            let mut files: Vec<(u32, Extent)> = self
+                .all_inodes(device)?
+                .into_iter()
+                .filter(|(_, data)| data.extents.len() == 1 && !self.is_shared(data))
+                .map(|(inode, data)| (inode, data.extents[0]))
+                .collect();
+            files.sort_unstable_by_key(|&(_, extent)| core::cmp::Reverse(extent.start));
+
+            let mut moved_this_pass = 0;
+            for (inode, extent) in files {
+                if let Some(start) = self.free_map.first_fit_below(extent.len, extent.start) {
+                    self.relocate(device, inode, start)?;
+                    moved_this_pass += 1;
+                }
+            }
+            if moved_this_pass == 0 {
                 break;
             }
+            moved += moved_this_pass;
+        }
+        Ok(moved)
+    }
+
+    /// Copy a file into the free run at `start` and point its inode there
+    fn relocate(&mut self, device: &mut dyn BlockDevice, inode: u32, start: u64) -> Result<(), &'static str> {
+        self.transaction(device, |fs, device| {
+            let mut data = fs.read_inode(device, inode)?;
+            let target = Extent { start, len: data.block_count() };
+            fs.mark_blocks(target, true);
+
+            let mut buffer = vec![0u8; COPY_BLOCKS as usize * SECTOR_SIZE];
+            let mut index = 0;
+            while index < target.len {
+                let (block, run) = data.map(index).ok_or("File data missing")?;
+                let count = core::cmp::min(core::cmp::min(run, COPY_BLOCKS), target.len - index);
+                let chunk = &mut buffer[..count as usize * SECTOR_SIZE];
+                fs.read_meta(device, block, chunk)?;
+                if data.kind == KIND_DIR {
+                    fs.write_meta(start + index, chunk);
+                } else {
+                    device.write_blocks(start + index, chunk)?;
+                }
+                index += count;
+            }
+            // The copy must be on disk before the inode points at it
+            device.flush()?;
+
+            for extent in core::mem::replace(&mut data.extents, vec![target]) {
+                fs.release_blocks(extent);
+            }
+            fs.write_inode(device, inode, &mut data)
+        })
+    }
+
+    // ---- Snapshots ----
+
+    // A snapshot is a manifest inode listing every file and directory as it
+    // was, with the files' extents. Those blocks are marked shared: freeing
+    // them is skipped, and a write to a shared file goes to new blocks. The
+    // snapshot table inode in the superblock lists the manifests by name.
+
+    fn read_snapshot_table(&self, device: &mut dyn BlockDevice) -> Result<Vec<SnapshotRecord>, &'static str> {
+        if self.superblock.snapshot_table == 0 {
+            return Ok(Vec::new());
+        }
+        let data = self.read_inode(device, self.superblock.snapshot_table)?;
+        if data.kind != KIND_SNAPSHOT {
+            return Err("Corrupt snapshot table");
+        }
+        let mut bytes = vec![0u8; data.size as usize];
+        self.read_data(device, &data, &mut bytes)?;
+        Ok(parse_snapshot_table(&bytes))
+    }
+
+    /// Store the snapshot list, dropping the table once it is empty
+    fn write_snapshot_table(&mut self, device: &mut dyn BlockDevice, records: &[SnapshotRecord]) -> Result<(), &'static str> {
+        let table = self.superblock.snapshot_table;
+        if records.is_empty() {
+            if table != 0 {
+                let mut data = self.read_inode(device, table)?;
+                self.release_inode(device, table, &mut data)?;
+                self.superblock.snapshot_table = 0;
+            }
+            return Ok(());
+        }
+        let (table, mut data) = if table == 0 {
+            (self.alloc_inode()?, Inode::new(KIND_SNAPSHOT))
+        } else {
+            (table, self.read_inode(device, table)?)
+        };
+        self.write_data(device, &mut data, &serialize_snapshot_table(records))?;
+        self.write_inode(device, table, &mut data)?;
+        self.superblock.snapshot_table = table;
+        Ok(())
+    }
+
+    fn read_tree(&self, device: &mut dyn BlockDevice, manifest: u32) -> Result<Vec<TreeEntry>, &'static str> {
+        let data = self.read_inode(device, manifest)?;
+        if data.kind != KIND_SNAPSHOT {
+            return Err("Corrupt snapshot");
+        }
+        let mut bytes = vec![0u8; data.size as usize];
+        self.read_data(device, &data, &mut bytes)?;
+        parse_tree(&bytes, &self.superblock)
+    }
+
+    /// The record and manifest of the snapshot called `name`
+    fn snapshot_tree(&self, device: &mut dyn BlockDevice, name: &str) -> Result<(SnapshotRecord, Vec<TreeEntry>), &'static str> {
+        let record = self.read_snapshot_table(device)?.into_iter().find(|r| r.name == name).ok_or("Snapshot not found")?;
+        let tree = self.read_tree(device, record.manifest)?;
+        Ok((record, tree))
+    }
+
+    /// The live directory tree as a manifest
+    fn capture_tree(&self, device: &mut dyn BlockDevice) -> Result<Vec<TreeEntry>, &'static str> {
+        let mut tree = vec![TreeEntry { parent: 0, name: String::new(), data: self.read_inode(device, ROOT_INODE)? }];
+        let mut pending = vec![(0, ROOT_INODE)];
+        while let Some((index, dir)) = pending.pop() {
+            for record in self.read_dir(device, dir)? {
+                let data = self.read_inode(device, record.inode)?;
+                if data.kind == KIND_DIR {
+                    pending.push((tree.len(), record.inode));
+                }
+                tree.push(TreeEntry { parent: index, name: record.name, data });
+            }
+        }
+        for entry in &mut tree {
+            entry.data.overflow.clear();
+            if entry.data.kind == KIND_DIR {
+                entry.data.extents.clear();
+                entry.data.size = 0;
+            }
+        }
+        Ok(tree)
+    }
+
+    /// Mark the blocks of every file in a manifest
+    fn mark_tree(bitmap: &mut [u8], tree: &[TreeEntry]) {
+        for entry in tree {
+            for &extent in &entry.data.extents {
+                Self::set_blocks(bitmap, extent, true);
+            }
+        }
+    }
+
+    /// The shared bitmap as the snapshots on disk make it. Snapshots that
+    /// can't be read are left for fsck.
+    fn snapshot_blocks(&self, device: &mut dyn BlockDevice) -> Vec<u8> {
+        let mut shared = vec![0; self.block_bitmap.len()];
+        for record in self.read_snapshot_table(device).unwrap_or_default() {
+            if let Ok(tree) = self.read_tree(device, record.manifest) {
+                Self::mark_tree(&mut shared, &tree);
+            }
+        }
+        shared
+    }
+
+    /// Every snapshot on the volume, oldest first
+    pub fn snapshots(&self, device: &mut dyn BlockDevice) -> Result<Vec<Snapshot>, &'static str> {
+        let records = self.read_snapshot_table(device)?;
+        Ok(records.into_iter().map(|r| Snapshot { name: r.name, created: r.created }).collect())
+    }
+
+    /// Take a read-only snapshot of the whole volume. Only the directory
+    /// tree is written; file data stays shared until it changes.
+    pub fn create_snapshot(&mut self, device: &mut dyn BlockDevice, name: &str) -> Result<(), &'static str> {
+        if device.is_read_only() {
+            return Err("Device is read-only");
+        }
+        validate_name(name)?;
+        self.transaction(device, |fs, device| {
+            let mut records = fs.read_snapshot_table(device)?;
+            if records.iter().any(|r| r.name == name) {
+                return Err("Snapshot already exists");
+            }
+            let tree = fs.capture_tree(device)?;
+            let manifest = fs.alloc_inode()?;
+            let mut data = Inode::new(KIND_SNAPSHOT);
+            fs.write_data(device, &mut data, &serialize_tree(&tree))?;
+            fs.write_inode(device, manifest, &mut data)?;
+
+            records.push(SnapshotRecord { manifest, created: data.created, name: String::from(name) });
+            fs.write_snapshot_table(device, &records)?;
+            Self::mark_tree(&mut fs.shared, &tree);
+            Ok(())
+        })
+    }
+
+    /// Delete a snapshot, freeing the blocks that only it held
+    pub fn delete_snapshot(&mut self, device: &mut dyn BlockDevice, name: &str) -> Result<(), &'static str> {
+        if device.is_read_only() {
+            return Err("Device is read-only");
+        }
+        self.transaction(device, |fs, device| {
+            let (record, tree) = fs.snapshot_tree(device, name)?;
+            let mut records = fs.read_snapshot_table(device)?;
+            records.retain(|r| r.manifest != record.manifest);
+            let mut data = fs.read_inode(device, record.manifest)?;
+            fs.release_inode(device, record.manifest, &mut data)?;
+            fs.write_snapshot_table(device, &records)?;
+
+            let mut shared = vec![0; fs.block_bitmap.len()];
+            for other in &records {
+                Self::mark_tree(&mut shared, &fs.read_tree(device, other.manifest)?);
+            }
+            fs.shared = shared;
+
+            // What neither the live files nor another snapshot still use
+            let mut used = fs.shared.clone();
+            for (_, data) in fs.all_inodes(device)? {
+                for &extent in &data.extents {
+                    Self::set_blocks(&mut used, extent, true);
+                }
+            }
+            for entry in &tree {
+                for extent in &entry.data.extents {
+                    let end = extent.start + extent.len;
+                    let mut block = extent.start;
+                    while block < end {
+                        let start = block;
+                        let in_use = Self::bit(&used, block);
+                        while block < end && Self::bit(&used, block) == in_use {
+                            block += 1;
+                        }
+                        if !in_use {
+                            fs.release_blocks(Extent { start, len: block - start });
+                        }
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Replace every file and directory with the ones in a snapshot. The
+    /// snapshot itself is kept, so files come back sharing its blocks.
+    pub fn restore_snapshot(&mut self, device: &mut dyn BlockDevice, name: &str) -> Result<(), &'static str> {
+        if device.is_read_only() {
+            return Err("Device is read-only");
+        }
+        self.transaction(device, |fs, device| {
+            let (_, tree) = fs.snapshot_tree(device, name)?;
+            for (inode, mut data) in fs.all_inodes(device)? {
+                if inode != ROOT_INODE {
+                    fs.release_inode(device, inode, &mut data)?;
+                }
+            }
+
+            let mut inodes = vec![ROOT_INODE];
+            for _ in 1..tree.len() {
+                inodes.push(fs.alloc_inode()?);
+            }
+            let mut children: Vec<Vec<DirRecord>> = vec![Vec::new(); tree.len()];
+            for (i, entry) in tree.iter().enumerate().skip(1) {
+                children[entry.parent].push(DirRecord { inode: inodes[i], kind: entry.data.kind, name: entry.name.clone() });
+            }
+
+            for (i, entry) in tree.iter().enumerate() {
+                let mut data = entry.data.clone();
+                if i == 0 {
+                    // The root keeps its inode and blocks
+                    let root = fs.read_inode(device, ROOT_INODE)?;
+                    data.extents = root.extents;
+                    data.overflow = root.overflow;
+                }
+                if data.kind == KIND_DIR {
+                    fs.write_data(device, &mut data, &serialize_dir(&children[i]))?;
+                    data.modified = entry.data.modified;
+                }
+                fs.write_inode(device, inodes[i], &mut data)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// What a path under /.snapshots refers to, for list_dir() and stat()
+    fn snapshot_info(&self, device: &mut dyn BlockDevice, parts: &[&str]) -> Result<FileInfo, &'static str> {
+        let (record, tree) = self.snapshot_tree(device, parts[0])?;
+        let index = tree_lookup(&tree, &parts[1..])?;
+        let mut info = tree[index].data.info(String::from(*parts.last().unwrap()));
+        if index == 0 {
+            info.created = record.created;
+            info.modified = record.created;
+            info.accessed = record.created;
         }
+        info.attributes |= ATTR_READ_ONLY;
+        Ok(info)
+    }
+
+    /// The components after /.snapshots, if `path` is in there and the
+    /// volume has snapshots
+    fn snapshot_path<'a>(&self, path: &'a str) -> Option<Vec<&'a str>> {
+        if self.superblock.snapshot_table == 0 { None } else { snapshot_parts(path) }
+    }
+
+    fn snapshot_dir_info(&self, device: &mut dyn BlockDevice) -> Result<FileInfo, &'static str> {
+        let mut info = self.read_inode(device, ROOT_INODE)?.info(String::from(SNAPSHOT_DIR));
+        info.attributes |= ATTR_READ_ONLY;
+        Ok(info)
+    }
+
+    // ---- Consistency check ----
+
+    /// Check the metadata for damage left by an interrupted write or a bad
+    /// disk, returning one line per problem. With `repair` everything found
+    /// is fixed in one transaction; otherwise nothing is written.
+    pub fn fsck(&mut self, device: &mut dyn BlockDevice, repair: bool) -> Result<Vec<String>, &'static str> {
+        if repair && device.is_read_only() {
+            return Err("Device is read-only");
+        }
+        let mut scan = self.scan(device)?;
+        let problems = core::mem::take(&mut scan.problems);
+        if repair && !problems.is_empty() {
+            self.transaction(device, |fs, device| fs.apply_fixes(device, scan))?;
+        }
+        Ok(problems)
+    }
+
+    /// Walk the directory tree, working out which inodes and blocks are
+    /// really in use and what has to change to make the volume consistent
+    fn scan(&self, device: &mut dyn BlockDevice) -> Result<Scan, &'static str> {
+        let sb = self.superblock;
+        let mut scan = Scan {
+            problems: Vec::new(),
+            blocks: vec![0; self.block_bitmap.len()],
+            inodes: vec![0; self.inode_bitmap.len()],
+            free_blocks: 0,
+            free_inodes: 0,
+            inode_fixes: Vec::new(),
+            dir_fixes: Vec::new(),
+            orphans: Vec::new(),
+            shared: vec![0; self.block_bitmap.len()],
+            snapshots: None,
+        };
+        for block in (0..sb.data_start).chain(sb.journal_start..sb.journal_start + sb.journal_len) {
+            Self::set_bit(&mut scan.blocks, block, true);
+        }
+        Self::set_bit(&mut scan.inodes, 0, true);
+
+        // Snapshot files may share blocks with live ones, so their blocks go
+        // in a bitmap of their own until the live tree has been claimed
+        if sb.snapshot_table != 0 {
+            let table = match self.decode_inode(device, sb.snapshot_table) {
+                Ok((data, None)) if data.kind == KIND_SNAPSHOT => Some(data),
+                _ => None,
+            };
+            let mut records = Vec::new();
+            if let Some(data) = &table {
+                let mut bytes = vec![0u8; data.size as usize];
+                if data.size <= data.block_count() * SECTOR_SIZE as u64 && self.read_data(device, data, &mut bytes).is_ok() {
+                    records = parse_snapshot_table(&bytes);
+                }
+            }
+            if records.is_empty() {
+                scan.problems.push(String::from("snapshot table is damaged; dropping all snapshots"));
+                scan.snapshots = Some(Vec::new());
+            }
+
+            let mut kept = Vec::new();
+            for record in &records {
+                let manifest = match self.decode_inode(device, record.manifest) {
+                    Ok((data, None)) if data.kind == KIND_SNAPSHOT && !Self::bit(&scan.inodes, record.manifest as u64) => Some(data),
+                    _ => None,
+                };
+                let tree = manifest.as_ref().and_then(|data| {
+                    let mut bytes = vec![0u8; data.size as usize];
+                    if data.size > data.block_count() * SECTOR_SIZE as u64 || self.read_data(device, data, &mut bytes).is_err() {
+                        return None;
+                    }
+                    parse_tree(&bytes, &sb).ok()
+                });
+                match (manifest, tree) {
+                    (Some(data), Some(tree)) => {
+                        let path = alloc::format!("snapshot {}", record.name);
+                        self.claim(&mut scan, record.manifest, &path, data, None);
+                        Self::mark_tree(&mut scan.shared, &tree);
+                        kept.push(record.clone());
+                    }
+                    _ => scan.problems.push(alloc::format!("snapshot {}: damaged; deleting it", record.name)),
+                }
+            }
+            if !kept.is_empty() {
+                self.claim(&mut scan, sb.snapshot_table, "snapshot table", table.unwrap(), None);
+            }
+            if kept.len() < records.len() {
+                scan.snapshots = Some(kept);
+            }
+        }
+
+        let root = match self.decode_inode(device, ROOT_INODE) {
+            Ok((data, problem)) if data.kind == KIND_DIR => self.claim(&mut scan, ROOT_INODE, "/", data, problem),
+            _ => return Err("Root directory is corrupt"),
+        };
+        let mut pending = vec![(ROOT_INODE, String::new(), root)];
+        while let Some((dir, path, data)) = pending.pop() {
+            let mut bytes = vec![0u8; data.size as usize];
+            self.read_data(device, &data, &mut bytes)?;
+            let records = parse_dir(&bytes);
+            let names: Vec<String> = records.iter().map(|r| r.name.clone()).collect();
+            let mut kept: Vec<DirRecord> = Vec::new();
+            let mut changed = false;
+
+            for mut record in records {
+                let child = alloc::format!("{}/{}", path, record.name);
+                let problem = if record.inode == 0 || record.inode >= sb.inode_count {
+                    Some("bad inode number")
+                } else if Self::bit(&scan.inodes, record.inode as u64) {
+                    Some("inode is already linked elsewhere")
+                } else {
+                    match self.inode_kind(device, record.inode)? {
+                        KIND_FREE => Some("points to a free inode"),
+                        KIND_FILE | KIND_DIR => None,
+                        _ => Some("inode has an unknown type"),
+                    }
+                };
+                if let Some(problem) = problem {
+                    scan.problems.push(alloc::format!("{}: {}; removing entry", child, problem));
+                    changed = true;
+                    continue;
+                }
+
+                let (data, problem) = self.decode_inode(device, record.inode)?;
+                if record.kind != data.kind {
+                    scan.problems.push(alloc::format!("{}: entry has the wrong type; fixing", child));
+                    record.kind = data.kind;
+                    changed = true;
+                }
+                let duplicate = kept.iter().any(|r| r.name == record.name);
+                if duplicate || validate_name(&record.name).is_err() {
+                    let name = unique_name(&record.name, record.inode, &names, &kept);
+                    let what = if duplicate { "duplicate" } else { "invalid" };
+                    scan.problems.push(alloc::format!("{}: {} name; renaming to {}", child, what, name));
+                    record.name = name;
+                    changed = true;
+                }
+
+                let child = alloc::format!("{}/{}", path, record.name);
+                let data = self.claim(&mut scan, record.inode, &child, data, problem);
+                if data.kind == KIND_DIR {
+                    pending.push((record.inode, child, data));
+                }
+                kept.push(record);
+            }
+            if changed {
+                scan.dir_fixes.push((dir, kept));
+            }
+        }
+
+        for (block, shared) in scan.blocks.iter_mut().zip(&scan.shared) {
+            *block |= shared;
+        }
+
+        // Compare what the tree uses with the bitmaps and counters
+        let (mut used_inodes, mut bitmap_inodes, mut unmarked_inodes) = (0, 0, 0);
+        for inode in 0..sb.inode_count as u64 {
+            let (marked, used) = (Self::bit(&self.inode_bitmap, inode), Self::bit(&scan.inodes, inode));
+            used_inodes += used as u32;
+            bitmap_inodes += marked as u32;
+            if marked && !used {
+                scan.orphans.push(inode as u32);
+            } else if used && !marked {
+                unmarked_inodes += 1;
+            }
+        }
+        let (mut used_blocks, mut bitmap_blocks, mut leaked, mut unmarked) = (0, 0, 0, 0);
+        for block in 0..sb.total_blocks {
+            let (marked, used) = (Self::bit(&self.block_bitmap, block), Self::bit(&scan.blocks, block));
+            used_blocks += used as u64;
+            bitmap_blocks += marked as u64;
+            if marked && !used {
+                leaked += 1;
+            } else if used && !marked {
+                unmarked += 1;
+            }
+        }
+        scan.free_blocks = sb.total_blocks - used_blocks;
+        scan.free_inodes = sb.inode_count - used_inodes;
+
+        if !scan.orphans.is_empty() {
+            scan.problems.push(alloc::format!("{} allocated inode(s) are in no directory; freeing", scan.orphans.len()));
+        }
+        if unmarked_inodes > 0 {
+            scan.problems.push(alloc::format!("{} inode(s) in use are marked free; marking", unmarked_inodes));
+        }
+        if leaked > 0 {
+            scan.problems.push(alloc::format!("{} block(s) marked in use belong to no file; freeing", leaked));
+        }
+        if unmarked > 0 {
+            scan.problems.push(alloc::format!("{} block(s) in use are marked free; marking", unmarked));
+        }
+        if sb.free_blocks != sb.total_blocks - bitmap_blocks {
+            scan.problems.push(alloc::format!(
+                "free block count is {} but the bitmap has {}; fixing",
+                sb.free_blocks, sb.total_blocks - bitmap_blocks
+            ));
+        }
+        if sb.free_inodes != sb.inode_count - bitmap_inodes {
+            scan.problems.push(alloc::format!(
+                "free inode count is {} but the bitmap has {}; fixing",
+                sb.free_inodes, sb.inode_count - bitmap_inodes
+            ));
+        }
+        Ok(scan)
+    }
+
+    fn inode_kind(&self, device: &mut dyn BlockDevice, inode: u32) -> Result<u8, &'static str> {
+        let (block, offset) = self.inode_location(inode)?;
+        let mut sector_buffer = [0u8; SECTOR_SIZE];
+        self.read_meta(device, block, &mut sector_buffer)?;
+        Ok(sector_buffer[offset])
+    }
+
+    /// Mark an inode and its blocks in use. Extents that are damaged or
+    /// already used by another file are cut off, as are blocks past the end
+    /// of the file; the inode is returned as it will be after repair.
+    fn claim(&self, scan: &mut Scan, inode: u32, path: &str, mut data: Inode, problem: Option<&'static str>) -> Inode {
+        Self::set_bit(&mut scan.inodes, inode as u64, true);
+        let mut changed = false;
+        if let Some(problem) = problem {
+            scan.problems.push(alloc::format!("{}: {}; truncating", path, problem));
+            changed = true;
+        }
+
+        let mut keep = data.extents.len();
+        for (i, extent) in data.extents.iter().enumerate() {
+            let blocks = extent.start..extent.start + extent.len;
+            if blocks.clone().any(|block| Self::bit(&scan.blocks, block)) {
+                scan.problems.push(alloc::format!(
+                    "{}: blocks {}-{} are also used elsewhere; truncating",
+                    path, blocks.start, blocks.end - 1
+                ));
+                keep = i;
+                break;
+            }
+            for block in blocks {
+                Self::set_bit(&mut scan.blocks, block, true);
+            }
+        }
+        if keep < data.extents.len() {
+            data.extents.truncate(keep);
+            changed = true;
+        }
+
+        let capacity = data.block_count() * SECTOR_SIZE as u64;
+        if data.size > capacity {
+            if !changed {
+                scan.problems.push(alloc::format!("{}: size {} is past its last block; truncating", path, data.size));
+            }
+            data.size = capacity;
+            changed = true;
+        } else if blocks_for(data.size) < data.block_count() {
+            let blocks = blocks_for(data.size);
+            let extra = data.block_count() - blocks;
+            scan.problems.push(alloc::format!("{}: {} block(s) past the end of the file; freeing", path, extra));
+            for extent in Self::cut_extents(&mut data, blocks) {
+                Self::set_blocks(&mut scan.blocks, extent, false);
+            }
+            changed = true;
+        }
+
+        // Extent blocks no longer needed are simply not claimed
+        let needed = overflow_blocks(data.extents.len());
+        if data.overflow.len() > needed {
+            data.overflow.truncate(needed);
+            changed = true;
+        }
+        for i in 0..data.overflow.len() {
+            let block = data.overflow[i];
+            if Self::bit(&scan.blocks, block) {
+                scan.problems.push(alloc::format!("{}: extent block {} is also used elsewhere; truncating", path, block));
+                let blocks = data.extents[..INODE_EXTENTS + i * BLOCK_EXTENTS].iter().map(|e| e.len).sum();
+                for extent in Self::cut_extents(&mut data, blocks) {
+                    Self::set_blocks(&mut scan.blocks, extent, false);
+                }
+                data.overflow.truncate(i);
+                data.size = core::cmp::min(data.size, blocks * SECTOR_SIZE as u64);
+                changed = true;
+                break;
+            }
+            Self::set_bit(&mut scan.blocks, block, true);
+        }
+
+        if changed {
+            scan.inode_fixes.push((inode, data.clone()));
+        }
+        data
+    }
+
+    /// Shorten a file's extent list to `blocks` blocks, returning the extents
+    /// cut off. Nothing is freed; the caller accounts for them.
+    fn cut_extents(data: &mut Inode, blocks: u64) -> Vec<Extent> {
+        let mut remaining = blocks;
+        let mut removed = Vec::new();
+        data.extents.retain_mut(|extent| {
+            if remaining >= extent.len {
+                remaining -= extent.len;
+                return true;
+            }
+            removed.push(Extent { start: extent.start + remaining, len: extent.len - remaining });
+            extent.len = remaining;
+            remaining = 0;
+            extent.len > 0
+        });
+        removed
+    }
+
+    /// Apply what scan() worked out: bitmaps and counters rebuilt from the
+    /// tree, damaged inodes and directories rewritten, orphans erased
+    fn apply_fixes(&mut self, device: &mut dyn BlockDevice, scan: Scan) -> Result<(), &'static str> {
+        let sb = self.superblock;
+        self.block_bitmap = scan.blocks;
+        self.inode_bitmap = scan.inodes;
+        self.free_map = FreeMap::from_bitmap(&self.block_bitmap, sb.data_start, sb.total_blocks);
+        self.dirty_bitmap_blocks.extend(sb.block_bitmap_start..sb.inode_table_start);
+        self.superblock.free_blocks = scan.free_blocks;
+        self.superblock.free_inodes = scan.free_inodes;
+        self.shared = scan.shared;
+        if scan.snapshots.as_ref().is_some_and(|kept| kept.is_empty()) {
+            self.superblock.snapshot_table = 0;
+        }
+
+        for (inode, mut data) in scan.inode_fixes {
+            self.write_inode(device, inode, &mut data)?;
+        }
+        for inode in scan.orphans {
+            self.erase_inode(device, inode)?;
+        }
+        // Directories last: rewriting one may allocate, which needs the
+        // rebuilt bitmap
+        for (dir, records) in scan.dir_fixes {
+            self.write_dir(device, dir, &records)?;
+        }
+        if let Some(kept) = scan.snapshots {
+            self.write_snapshot_table(device, &kept)?;
+        }
+        Ok(())
+    }
+}
+
+impl FileSystem for SimpleFilesystem {
+    fn fs_type(&self) -> &'static str {
+        "SimpleFS"
+    }
+
+    fn list_dir(&self, device: &mut dyn BlockDevice, path: &str) -> Result<Vec<FileInfo>, &'static str> {
+        if let Some(parts) = self.snapshot_path(path) {
+            let mut files = Vec::new();
+            if parts.is_empty() {
+                for record in self.read_snapshot_table(device)? {
+                    files.push(self.snapshot_info(device, &[&record.name])?);
+                }
+                return Ok(files);
+            }
+            let (_, tree) = self.snapshot_tree(device, parts[0])?;
+            let dir = tree_lookup(&tree, &parts[1..])?;
+            if tree[dir].data.kind != KIND_DIR {
+                return Err("Not a directory");
+            }
+            for entry in tree.iter().skip(1).filter(|entry| entry.parent == dir) {
+                let mut info = entry.data.info(entry.name.clone());
+                info.attributes |= ATTR_READ_ONLY;
+                files.push(info);
+            }
+            return Ok(files);
+        }
+
+        let parts = components(path);
+        let dir = self.resolve_dir(device, &parts)?;
+        let mut files = Vec::new();
+        for record in self.read_dir(device, dir)? {
+            files.push(self.read_inode(device, record.inode)?.info(record.name));
+        }
+        if parts.is_empty() && self.superblock.snapshot_table != 0 {
+            files.push(self.snapshot_dir_info(device)?);
+        }
+        Ok(files)
+    }
+
+    fn stat(&self, device: &mut dyn BlockDevice, path: &str) -> Result<FileInfo, &'static str> {
+        match self.snapshot_path(path) {
+            Some(parts) if parts.is_empty() => return self.snapshot_dir_info(device),
+            Some(parts) => return self.snapshot_info(device, &parts),
+            None => {}
+        }
+        let (_, data) = self.lookup_inode(device, path)?;
+        let name = components(path).last().copied().unwrap_or("/");
+        Ok(data.info(String::from(name)))
+    }
+
+    fn read_file(&self, device: &mut dyn BlockDevice, path: &str, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        let data = match self.snapshot_path(path) {
+            Some(parts) if parts.len() > 1 => {
+                let (_, tree) = self.snapshot_tree(device, parts[0])?;
+                tree[tree_lookup(&tree, &parts[1..])?].data.clone()
+            }
+            Some(_) => return Err("Is a directory"),
+            None => self.open_file(device, path)?.1,
+        };
+        if data.kind != KIND_FILE {
+            return Err("Is a directory");
+        }
+        let size = data.size as usize;
+        if buffer.len() < size {
+            return Err("Buffer too small for file");
+        }
+        self.read_data(device, &data, &mut buffer[..size])?;
+        Ok(size)
+    }
+
+    fn create_file(&mut self, device: &mut dyn BlockDevice, path: &str, size: u32) -> Result<(), &'static str> {
+        check_not_snapshot(path)?;
+        self.transaction(device, |fs, device| {
+            let (inode, mut data) = fs.create_node(device, path, KIND_FILE)?;
+            if size > 0 {
+                fs.set_size(device, &mut data, size as u64)?;
+                fs.write_inode(device, inode, &mut data)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn write_file(&mut self, device: &mut dyn BlockDevice, path: &str, bytes: &[u8]) -> Result<(), &'static str> {
+        check_not_snapshot(path)?;
+        self.transaction(device, |fs, device| {
+            let (inode, mut data) = fs.open_file(device, path)?;
+            data.check_writable()?;
+            fs.write_data(device, &mut data, bytes)?;
+            data.file_type = mime::detect(super::split_path(path).1, bytes);
+            fs.write_inode(device, inode, &mut data)
+        })
+    }
+
+    fn delete_file(&mut self, device: &mut dyn BlockDevice, path: &str) -> Result<(), &'static str> {
+        check_not_snapshot(path)?;
+        self.transaction(device, |fs, device| {
+            let (parent, mut records, index) = fs.lookup(device, path)?;
+            let record = records.remove(index);
+            let mut data = fs.read_inode(device, record.inode)?;
+            data.check_writable()?;
+            if data.kind == KIND_DIR && !fs.read_dir(device, record.inode)?.is_empty() {
+                return Err("Directory not empty");
+            }
+
+            fs.write_dir(device, parent, &records)?;
+            fs.release_inode(device, record.inode, &mut data)
+        })
+    }
+
+    fn rename_file(&mut self, device: &mut dyn BlockDevice, old_path: &str, new_path: &str) -> Result<(), &'static str> {
+        check_not_snapshot(old_path)?;
+        check_not_snapshot(new_path)?;
+        self.transaction(device, |fs, device| {
+            let (old_parent, mut old_records, index) = fs.lookup(device, old_path)?;
+            let (new_parent, new_name) = fs.split_parent(device, new_path)?;
+            validate_name(new_name)?;
+
+            let record = old_records[index].clone();
+            if record.kind == KIND_FILE {
+                // A type guessed from the old name follows the new one
+                let mut data = fs.read_inode(device, record.inode)?;
+                if data.file_type == mime::UNKNOWN || data.file_type == mime::from_name(&record.name) {
+                    data.file_type = mime::from_name(new_name);
+                    fs.write_inode(device, record.inode, &mut data)?;
+                }
+            }
+            if record.kind == KIND_DIR {
+                // A directory can't be moved inside itself
+                let old_parts = components(old_path);
+                if components(new_path).starts_with(&old_parts) {
+                    return Err("Can't move a directory into itself");
+                }
+            }
+
+            if new_parent == old_parent {
+                if old_records.iter().any(|r| r.name == new_name) {
+                    return Err("File with new name already exists");
+                }
+                old_records[index].name = String::from(new_name);
+                fs.write_dir(device, old_parent, &old_records)
+            } else {
+                let mut new_records = fs.read_dir(device, new_parent)?;
+                if new_records.iter().any(|r| r.name == new_name) {
+                    return Err("File with new name already exists");
+                }
+                new_records.push(DirRecord { name: String::from(new_name), ..record });
+                fs.write_dir(device, new_parent, &new_records)?;
+                old_records.remove(index);
+                fs.write_dir(device, old_parent, &old_records)
+            }
+        })
+    }
+
+    fn create_dir(&mut self, device: &mut dyn BlockDevice, path: &str) -> Result<(), &'static str> {
+        check_not_snapshot(path)?;
+        self.transaction(device, |fs, device| fs.create_node(device, path, KIND_DIR).map(|_| ()))
+    }
+
+    fn truncate(&mut self, device: &mut dyn BlockDevice, path: &str, size: u32) -> Result<(), &'static str> {
+        check_not_snapshot(path)?;
+        self.transaction(device, |fs, device| {
+            let (inode, mut data) = fs.open_file(device, path)?;
+            data.check_writable()?;
+            fs.set_size(device, &mut data, size as u64)?;
+            fs.write_inode(device, inode, &mut data)
+        })
+    }
+
+    fn set_attributes(&mut self, device: &mut dyn BlockDevice, path: &str, attributes: u8) -> Result<(), &'static str> {
+        check_not_snapshot(path)?;
+        self.transaction(device, |fs, device| {
+            let (inode, mut data) = fs.lookup_inode(device, path)?;
+            data.attributes = attributes & ATTR_MASK;
+            fs.write_inode(device, inode, &mut data)
+        })
+    }
+
+    fn check(&mut self, device: &mut dyn BlockDevice, repair: bool) -> Result<Vec<String>, &'static str> {
+        self.fsck(device, repair)
+    }
+
+    fn usage(&self, device: &mut dyn BlockDevice) -> Result<Usage, &'static str> {
+        let files: Vec<Inode> = self.all_inodes(device)?.into_iter().map(|(_, data)| data).filter(|data| data.kind == KIND_FILE).collect();
+        let fragmented = files.iter().filter(|data| data.extents.len() > 1).count();
+        let block_bytes = SECTOR_SIZE as u64;
+        Ok(Usage {
+            total_bytes: self.superblock.total_blocks * block_bytes,
+            free_bytes: self.superblock.free_blocks * block_bytes,
+            free_runs: Some((self.free_map.run_count() as u64, self.free_map.largest() * block_bytes)),
+            fragmented_files: Some((fragmented as u64, files.len() as u64)),
+        })
+    }
+
+    fn defrag(&mut self, device: &mut dyn BlockDevice) -> Result<usize, &'static str> {
+        SimpleFilesystem::defrag(self, device)
+    }
+
+    fn snapshots(&self, device: &mut dyn BlockDevice) -> Result<Vec<Snapshot>, &'static str> {
+        SimpleFilesystem::snapshots(self, device)
+    }
+
+    fn create_snapshot(&mut self, device: &mut dyn BlockDevice, name: &str) -> Result<(), &'static str> {
+        SimpleFilesystem::create_snapshot(self, device, name)
+    }
+
+    fn restore_snapshot(&mut self, device: &mut dyn BlockDevice, name: &str) -> Result<(), &'static str> {
+        SimpleFilesystem::restore_snapshot(self, device, name)
+    }
+
+    fn delete_snapshot(&mut self, device: &mut dyn BlockDevice, name: &str) -> Result<(), &'static str> {
+        SimpleFilesystem::delete_snapshot(self, device, name)
+    }
+
+    fn mark_accessed(&mut self, device: &mut dyn BlockDevice, path: &str) -> Result<(), &'static str> {
+        // Like relatime: only write when the access time is older than the
+        // last change or a day old, so reads rarely cost a transaction
+        if snapshot_parts(path).is_some() {
+            return Ok(());
+        }
+        let (inode, mut data) = self.open_file(device, path)?;
+        let time = now();
+        if data.accessed >= data.modified && time - data.accessed < 24 * 60 * 60 {
+            return Ok(());
+        }
+        self.transaction(device, |fs, device| {
+            data.accessed = time;
+            fs.write_inode(device, inode, &mut data)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::block::RamDisk;
+
+    #[test]
+    fn test_format_mount_roundtrip() {
+        let mut disk = RamDisk::new(2048);
+        SimpleFilesystem::format(&mut disk).unwrap();
+
+        let mut fs = SimpleFilesystem::mount(&mut disk).unwrap();
+        fs.create_dir(&mut disk, "documents").unwrap();
+        fs.create_file(&mut disk, "documents/a rather long file name.txt", 600).unwrap();
+        fs.write_file(&mut disk, "documents/a rather long file name.txt", &[7u8; 1500]).unwrap();
+        fs.rename_file(&mut disk, "documents/a rather long file name.txt", "moved.txt").unwrap();
+
+        // Remount so everything comes back from the device
+        let fs = SimpleFilesystem::mount(&mut disk).unwrap();
+        assert!(fs.list_dir(&mut disk, "documents").unwrap().is_empty());
+        let files = fs.list_dir(&mut disk, "").unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[1].name, "moved.txt");
+        assert_eq!(files[1].size, 1500);
+        let mut buffer = [0u8; 1500];
+        assert_eq!(fs.read_file(&mut disk, "moved.txt", &mut buffer).unwrap(), 1500);
+        assert!(buffer.iter().all(|&b| b == 7));
+    }
+
+    #[test]
+    fn test_grow_shrink_and_fragmentation() {
+        let mut disk = RamDisk::new(4096);
+        SimpleFilesystem::format(&mut disk).unwrap();
+        let mut fs = SimpleFilesystem::mount(&mut disk).unwrap();
+        let free_at_start = fs.superblock.free_blocks;
+
+        // Interleave two growing files so "big" ends up with many extents
+        fs.create_file(&mut disk, "big", 0).unwrap();
+        fs.create_file(&mut disk, "gaps", 0).unwrap();
+        let mut expected = Vec::new();
+        for i in 1..=40u8 {
+            expected.extend_from_slice(&[i; 512]);
+            fs.write_file(&mut disk, "big", &expected).unwrap();
+            fs.write_file(&mut disk, "gaps", &vec![0xEE; i as usize * 512]).unwrap();
+        }
+
+        let fs = SimpleFilesystem::mount(&mut disk).unwrap();
+        let (_, big) = fs.open_file(&mut disk, "big").unwrap();
+        assert!(big.extents.len() > INODE_EXTENTS);
+        let mut buffer = vec![0u8; expected.len()];
+        fs.read_file(&mut disk, "big", &mut buffer).unwrap();
+        assert_eq!(buffer, expected);
+
+        // Shrinking, zero-extending and deleting give every block back
+        let mut fs = fs;
+        fs.truncate(&mut disk, "big", 100).unwrap();
+        fs.truncate(&mut disk, "big", 2000).unwrap();
+        let mut buffer = vec![0xFFu8; 2000];
+        fs.read_file(&mut disk, "big", &mut buffer).unwrap();
+        assert!(buffer[..100].iter().all(|&b| b == 1));
+        assert!(buffer[100..].iter().all(|&b| b == 0));
+        fs.delete_file(&mut disk, "big").unwrap();
+        fs.delete_file(&mut disk, "gaps").unwrap();
+        assert_eq!(fs.superblock.free_blocks, free_at_start);
+    }
+
+    #[test]
+    fn test_migrates_v1_volume() {
+        // Hand-built v1 volume: superblock, file table at sectors 1-2, data from 11
+        let mut disk = RamDisk::new(4096);
+        let mut sector = [0u8; SECTOR_SIZE];
+        write_u32(&mut sector, 0, FS_MAGIC);
+        write_u32(&mut sector, 4, V1_VERSION);
+        write_u64(&mut sector, 8, 4096);
+        write_u64(&mut sector, 16, 11);
+        write_u32(&mut sector, 24, 2);
+        disk.write_blocks(0, &sector).unwrap();
+
+        let mut table = [0u8; 2 * SECTOR_SIZE];
+        for (i, (name, start, size)) in [(&b"early"[..], 11u16, 700u32), (&b"late"[..], 300, 100)].iter().enumerate() {
+            let entry = &mut table[i * 20..(i + 1) * 20];
+            entry[..name.len()].copy_from_slice(name);
+            entry[8..10].copy_from_slice(&start.to_le_bytes());
+            entry[10..12].copy_from_slice(&(((size + 511) / 512) as u16).to_le_bytes());
+            entry[12..16].copy_from_slice(&size.to_le_bytes());
+            entry[16] = 0x01;
+        }
+        disk.write_blocks(1, &table).unwrap();
+        disk.write_blocks(11, &[0xAB; 1024]).unwrap();
+        disk.write_blocks(300, &[0xCD; 512]).unwrap();
+
+        let fs = SimpleFilesystem::mount(&mut disk).unwrap();
+        let files = fs.list_dir(&mut disk, "").unwrap();
+        assert_eq!(files.len(), 2);
+        let mut buffer = [0u8; 700];
+        assert_eq!(fs.read_file(&mut disk, "early", &mut buffer).unwrap(), 700);
+        assert!(buffer.iter().all(|&b| b == 0xAB));
+        assert_eq!(fs.read_file(&mut disk, "late", &mut buffer).unwrap(), 100);
+        assert!(buffer[..100].iter().all(|&b| b == 0xCD));
+
+        // "late" kept its blocks in place
+        let (_, late) = fs.open_file(&mut disk, "late").unwrap();
+        assert_eq!(late.extents, vec![Extent { start: 300, len: 1 }]);
+    }
+
+    #[test]
+    fn test_metadata() {
+        let mut disk = RamDisk::new(2048);
+        SimpleFilesystem::format(&mut disk).unwrap();
+        let mut fs = SimpleFilesystem::mount(&mut disk).unwrap();
+        fs.create_file(&mut disk, "notes", 0).unwrap();
+        fs.create_file(&mut disk, "picture.txt", 0).unwrap();
+        fs.write_file(&mut disk, "picture.txt", b"\x89PNG\r\n\x1a\n").unwrap();
+        fs.rename_file(&mut disk, "notes", "notes.md").unwrap();
+        fs.rename_file(&mut disk, "picture.txt", "picture").unwrap();
+        fs.set_attributes(&mut disk, "notes.md", ATTR_READ_ONLY | crate::system::fs::ATTR_HIDDEN | 0x80).unwrap();
+
+        let fs = SimpleFilesystem::mount(&mut disk).unwrap();
+        let files = fs.list_dir(&mut disk, "").unwrap();
+        assert_eq!(mime::file_type(files[0].file_type).mime, "text/markdown");
+        assert_eq!(files[0].attribute_string(), "rh-");
+        // Detected from the contents, so it survives the rename
+        assert_eq!(mime::file_type(files[1].file_type).mime, "image/png");
+        assert!(files.iter().all(|f| f.created == now() && f.modified == now() && f.accessed == now()));
+        assert_eq!(mime::file_type(fs.stat(&mut disk, "").unwrap().file_type).mime, "inode/directory");
+
+        let mut fs = fs;
+        assert_eq!(fs.write_file(&mut disk, "notes.md", b"x"), Err("File is read-only"));
+        assert_eq!(fs.delete_file(&mut disk, "notes.md"), Err("File is read-only"));
+        fs.set_attributes(&mut disk, "notes.md", 0).unwrap();
+        fs.delete_file(&mut disk, "notes.md").unwrap();
+    }
+
+    /// A disk that loses power after a set number of writes: every later
+    /// write, zeroing or discard fails and never reaches the medium
+    struct CrashDisk {
+        disk: RamDisk,
+        writes_left: usize,
+    }
+
+    impl CrashDisk {
+        fn write(&mut self) -> Result<(), &'static str> {
+            if self.writes_left == 0 {
+                return Err("Power lost");
+            }
+            self.writes_left -= 1;
+            Ok(())
+        }
+    }
+
+    impl BlockDevice for CrashDisk {
+        fn block_count(&self) -> u64 {
+            self.disk.block_count()
+        }
+
+        fn read_blocks(&mut self, start: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+            self.disk.read_blocks(start, buffer)
+        }
+
+        fn write_blocks(&mut self, start: u64, buffer: &[u8]) -> Result<(), &'static str> {
+            self.write()?;
+            self.disk.write_blocks(start, buffer)
+        }
+
+        fn discard(&mut self, start: u64, count: u64) -> Result<(), &'static str> {
+            self.write()?;
+            self.disk.discard(start, count)
+        }
+
+        fn write_zeroes(&mut self, start: u64, count: u64) -> Result<(), &'static str> {
+            self.write()?;
+            self.disk.write_zeroes(start, count)
+        }
+    }
+
+    /// Check that the bitmaps and free counts match what the directory tree uses
+    fn check_consistent(fs: &SimpleFilesystem, disk: &mut RamDisk) {
+        let sb = fs.superblock;
+        let mut used_blocks = vec![false; sb.total_blocks as usize];
+        let mut used_inodes = 0;
+        let mut pending = vec![ROOT_INODE];
+        while let Some(inode) = pending.pop() {
+            used_inodes += 1;
+            let data = fs.read_inode(disk, inode).unwrap();
+            let blocks = data.extents.iter().flat_map(|e| e.start..e.start + e.len);
+            for block in blocks.chain(data.overflow.iter().copied()) {
+                assert!(!used_blocks[block as usize], "block {} used twice", block);
+                used_blocks[block as usize] = true;
+            }
+            if data.kind == KIND_DIR {
+                pending.extend(fs.read_dir(disk, inode).unwrap().iter().map(|r| r.inode));
+            }
+        }
+        for block in sb.journal_start..sb.journal_start + sb.journal_len {
+            used_blocks[block as usize] = true;
+        }
+        for block in sb.data_start..sb.total_blocks {
+            assert_eq!(SimpleFilesystem::bit(&fs.block_bitmap, block), used_blocks[block as usize], "block {}", block);
+        }
+        let data_used = used_blocks[sb.data_start as usize..].iter().filter(|&&used| used).count() as u64;
+        assert_eq!(sb.free_blocks, sb.total_blocks - sb.data_start - data_used);
+        assert_eq!(sb.free_inodes, sb.inode_count - 1 - used_inodes);
+    }
+
+    #[test]
+    fn test_survives_power_loss_at_every_write() {
+        let mut base = RamDisk::new(2048);
+        SimpleFilesystem::format(&mut base).unwrap();
+        let mut fs = SimpleFilesystem::mount(&mut base).unwrap();
+        fs.create_dir(&mut base, "docs").unwrap();
+        // A failed operation is rolled back
+        fs.write_file(&mut base, "docs/a.txt", &[1u8; 3000]).unwrap_err();
+        fs.create_file(&mut base, "docs/a.txt", 3000).unwrap();
+        fs.create_file(&mut base, "b.txt", 700).unwrap();
+        check_consistent(&fs, &mut base);
+
+        type Op = fn(&mut SimpleFilesystem, &mut dyn BlockDevice) -> Result<(), &'static str>;
+        let ops: [(&str, Op); 4] = [
+            ("create", |fs, d| fs.create_file(d, "docs/new.txt", 5000)),
+            ("rename", |fs, d| fs.rename_file(d, "docs/a.txt", "a.txt")),
+            ("delete", |fs, d| fs.delete_file(d, "b.txt")),
+            ("mkdir", |fs, d| fs.create_dir(d, "docs/sub")),
+        ];
+
+        for (name, op) in ops {
+            let (mut crashes, mut replayed) = (0, 0);
+            for writes in 0.. {
+                let mut disk = CrashDisk { disk: RamDisk::from_image(base.as_bytes().to_vec()), writes_left: writes };
+                let mut fs = SimpleFilesystem::mount(&mut disk).unwrap();
+                let finished = op(&mut fs, &mut disk).is_ok();
+
+                // Power back on: the volume holds either the old or the new state
+                let mut disk = disk.disk;
+                let fs = SimpleFilesystem::mount(&mut disk).unwrap();
+                check_consistent(&fs, &mut disk);
+                let exists = |path: &str, disk: &mut RamDisk| fs.stat(disk, path).is_ok();
+                let applied = match name {
+                    "create" => exists("docs/new.txt", &mut disk),
+                    "rename" => exists("a.txt", &mut disk),
+                    "delete" => !exists("b.txt", &mut disk),
+                    _ => exists("docs/sub", &mut disk),
+                };
+                if name == "rename" {
+                    assert_ne!(applied, exists("docs/a.txt", &mut disk));
+                }
+                if finished {
+                    assert!(applied, "{}", name);
+                    break;
+                }
+                crashes += 1;
+                if applied {
+                    replayed += 1;
+                }
+            }
+            assert!(crashes > 3, "{} never crashed mid-transaction", name);
+            assert!(replayed > 0, "{} was never replayed from the journal", name);
+        }
+    }
+
+    #[test]
+    fn test_fsck_repairs_damage() {
+        let mut disk = RamDisk::new(2048);
+        SimpleFilesystem::format(&mut disk).unwrap();
+        let mut fs = SimpleFilesystem::mount(&mut disk).unwrap();
+        fs.create_dir(&mut disk, "docs").unwrap();
+        fs.create_file(&mut disk, "a", 1000).unwrap();
+        fs.create_file(&mut disk, "b", 0).unwrap();
+        fs.create_file(&mut disk, "docs/c", 2000).unwrap();
+        assert!(fs.fsck(&mut disk, false).unwrap().is_empty());
+
+        // Damage it the way a buggy driver or torn write might
+        fs.transaction(&mut disk, |fs, d| {
+            let mut root = fs.read_dir(d, ROOT_INODE)?;
+            root.iter_mut().find(|r| r.name == "b").unwrap().name = String::from("a");
+            root.push(DirRecord { inode: 50, kind: KIND_FILE, name: String::from("ghost") });
+            fs.write_dir(d, ROOT_INODE, &root)?;
+
+            // docs/c claims a's blocks
+            let (_, a) = fs.open_file(d, "a")?;
+            let (c, mut c_data) = fs.open_file(d, "docs/c")?;
+            c_data.extents = a.extents.clone();
+            fs.write_inode(d, c, &mut c_data)?;
+
+            // An inode nobody links to, and blocks nobody owns
+            let orphan = fs.alloc_inode()?;
+            fs.write_inode(d, orphan, &mut Inode::new(KIND_FILE))?;
+            fs.alloc_extent(1500, 3)?;
+            fs.superblock.free_blocks += 7;
+            Ok(())
+        })
+        .unwrap();
+
+        // A dry run reports everything and writes nothing
+        let image = disk.as_bytes().to_vec();
+        let problems = fs.fsck(&mut disk, false).unwrap();
+        assert_eq!(disk.as_bytes(), &image[..]);
+        let report = problems.join("\n");
+        for expected in ["/a: duplicate name; renaming to a~1", "/ghost: points to a free inode", "/docs/c: blocks", "1 allocated inode", "block(s) marked in use belong to no file", "free block count"] {
+            assert!(report.contains(expected), "missing {:?} in:\n{}", expected, report);
+        }
+
+        // The check at mount repairs it, on a copy so this one can be checked too
+        let mut copy = RamDisk::from_image(image);
+        let mut mounted = SimpleFilesystem::mount(&mut copy).unwrap();
+        assert!(mounted.fsck(&mut copy, false).unwrap().is_empty());
+
+        assert_eq!(fs.fsck(&mut disk, true).unwrap().len(), problems.len());
+        assert!(fs.fsck(&mut disk, false).unwrap().is_empty());
+        check_consistent(&fs, &mut disk);
+        let names: Vec<String> = fs.list_dir(&mut disk, "").unwrap().into_iter().map(|f| f.name).collect();
+        assert_eq!(names, ["docs", "a", "a~1"]);
+        assert_eq!(fs.stat(&mut disk, "a").unwrap().size, 1000);
+        assert_eq!(fs.stat(&mut disk, "docs/c").unwrap().size, 0);
+    }
+
+    #[test]
+    fn test_defrag() {
+        let mut disk = RamDisk::new(4096);
+        SimpleFilesystem::format(&mut disk).unwrap();
+        let mut fs = SimpleFilesystem::mount(&mut disk).unwrap();
+
+        // Grow three files in turn so each is in many pieces, then leave a hole
+        let names = ["x", "y", "z"];
+        for name in names {
+            fs.create_file(&mut disk, name, 0).unwrap();
+        }
+        for i in 1..=30u8 {
+            for (n, name) in names.iter().enumerate() {
+                fs.write_file(&mut disk, name, &vec![i + n as u8 * 50; i as usize * 512]).unwrap();
+            }
+        }
+        fs.delete_file(&mut disk, "y").unwrap();
+        let before = fs.usage(&mut disk).unwrap();
+        assert_eq!(before.fragmented_files, Some((2, 2)));
+        assert!(before.free_runs.unwrap().0 > 2);
+
+        assert!(fs.defrag(&mut disk).unwrap() >= 2);
+        let after = fs.usage(&mut disk).unwrap();
+        assert_eq!(after.fragmented_files, Some((0, 2)));
+        assert_eq!(after.free_runs.unwrap().0, 1);
+        // Single-extent files no longer need their extent blocks
+        assert!(after.free_bytes > before.free_bytes);
+        check_consistent(&fs, &mut disk);
+
+        let fs = SimpleFilesystem::mount(&mut disk).unwrap();
+        let mut buffer = vec![0u8; 30 * 512];
+        fs.read_file(&mut disk, "z", &mut buffer).unwrap();
+        assert!(buffer.iter().all(|&b| b == 130));
+    }
+
+    #[test]
+    fn test_snapshots() {
+        let mut disk = RamDisk::new(4096);
+        SimpleFilesystem::format(&mut disk).unwrap();
+        let mut fs = SimpleFilesystem::mount(&mut disk).unwrap();
+        fs.create_dir(&mut disk, "docs").unwrap();
+        fs.create_file(&mut disk, "docs/a.txt", 0).unwrap();
+        fs.write_file(&mut disk, "docs/a.txt", &[1u8; 3000]).unwrap();
+        fs.create_file(&mut disk, "b.txt", 0).unwrap();
+        fs.write_file(&mut disk, "b.txt", &[2u8; 700]).unwrap();
+        let free_blocks = fs.superblock.free_blocks;
+
+        fs.create_snapshot(&mut disk, "before").unwrap();
+        assert_eq!(fs.create_snapshot(&mut disk, "before"), Err("Snapshot already exists"));
+        fs.write_file(&mut disk, "docs/a.txt", &[3u8; 3000]).unwrap();
+        fs.truncate(&mut disk, "b.txt", 1000).unwrap();
+        fs.create_file(&mut disk, "c.txt", 10).unwrap();
+
+        // The snapshot still sees the old contents, and can't be changed
+        let names: Vec<String> = fs.list_dir(&mut disk, SNAPSHOT_DIR).unwrap().into_iter().map(|f| f.name).collect();
+        assert_eq!(names, ["before"]);
+        let mut buffer = vec![0u8; 3000];
+        fs.read_file(&mut disk, ".snapshots/before/docs/a.txt", &mut buffer).unwrap();
+        assert!(buffer.iter().all(|&b| b == 1));
+        assert_eq!(fs.read_file(&mut disk, ".snapshots/before/b.txt", &mut buffer), Ok(700));
+        assert!(buffer[..700].iter().all(|&b| b == 2));
+        assert!(fs.stat(&mut disk, ".snapshots/before/c.txt").is_err());
+        assert_ne!(fs.stat(&mut disk, ".snapshots/before/b.txt").unwrap().attributes & ATTR_READ_ONLY, 0);
+        assert_eq!(fs.write_file(&mut disk, ".snapshots/before/b.txt", b"x"), Err(SNAPSHOTS_READ_ONLY));
+        assert_eq!(fs.delete_file(&mut disk, ".snapshots/before/docs"), Err(SNAPSHOTS_READ_ONLY));
+
+        // The live file only had its last block copied
+        assert_eq!(fs.read_file(&mut disk, "b.txt", &mut buffer), Ok(1000));
+        assert!(buffer[..700].iter().all(|&b| b == 2) && buffer[700..1000].iter().all(|&b| b == 0));
+        assert!(fs.fsck(&mut disk, false).unwrap().is_empty());
+
+        let mut fs = SimpleFilesystem::mount(&mut disk).unwrap();
+        assert!(fs.fsck(&mut disk, false).unwrap().is_empty());
+        fs.restore_snapshot(&mut disk, "before").unwrap();
+        assert!(fs.stat(&mut disk, "c.txt").is_err());
+        assert_eq!(fs.read_file(&mut disk, "b.txt", &mut buffer), Ok(700));
+        fs.read_file(&mut disk, "docs/a.txt", &mut buffer).unwrap();
+        assert!(buffer.iter().all(|&b| b == 1));
+        fs.write_file(&mut disk, "docs/a.txt", &[4u8; 3000]).unwrap();
+        fs.read_file(&mut disk, ".snapshots/before/docs/a.txt", &mut buffer).unwrap();
+        assert!(buffer.iter().all(|&b| b == 1));
+        assert!(fs.fsck(&mut disk, false).unwrap().is_empty());
+
+        // Deleting the last snapshot frees everything it held
+        fs.delete_snapshot(&mut disk, "before").unwrap();
+        assert_eq!(fs.delete_snapshot(&mut disk, "before"), Err("Snapshot not found"));
+        assert!(fs.snapshots(&mut disk).unwrap().is_empty());
+        assert!(fs.list_dir(&mut disk, "").unwrap().iter().all(|f| f.name != SNAPSHOT_DIR));
+        assert_eq!(fs.superblock.free_blocks, free_blocks);
+        check_consistent(&fs, &mut disk);
+        let mut fs = SimpleFilesystem::mount(&mut disk).unwrap();
+        assert!(fs.fsck(&mut disk, false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_refuses_transaction_larger_than_journal() {
+        let mut disk = RamDisk::new(2048);
+        SimpleFilesystem::format(&mut disk).unwrap();
+        let mut fs = SimpleFilesystem::mount(&mut disk).unwrap();
+        let bitmap_blocks = fs.superblock.inode_table_start - fs.superblock.block_bitmap_start;
+        assert!(fs.superblock.journal_len >= journal_space((bitmap_blocks + MIN_JOURNAL_BLOCKS) as usize));
+
+        // Too small for superblock, bitmap, inode and directory together
+        fs.superblock.journal_len = journal_space(2);
+        assert_eq!(fs.create_file(&mut disk, "a.txt", 5000), Err("Transaction too large for the SimpleFS journal"));
+
+        // Nothing was written in place, and the rollback restored the journal
+        assert!(fs.stat(&mut disk, "a.txt").is_err());
+        let mut fs = SimpleFilesystem::mount(&mut disk).unwrap();
+        assert!(fs.stat(&mut disk, "a.txt").is_err());
+        check_consistent(&fs, &mut disk);
+        fs.create_file(&mut disk, "a.txt", 5000).unwrap();
+    }
 
-        Ok(bytes_read)
+    #[test]
+    fn test_mount_rejects_blank_disk() {
+        let mut disk = RamDisk::new(64);
+        assert!(SimpleFilesystem::mount(&mut disk).is_err());
     }
 }
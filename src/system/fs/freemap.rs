@@ -0,0 +1,161 @@
+// Free extent map
+//
+// Free space as runs of consecutive blocks, indexed by start block (to merge
+// neighbours when blocks are freed) and by length (for best-fit allocation).
+// A block filesystem keeps one next to its allocation bitmap, which stays the
+// on-disk record; the map is rebuilt from the bitmap at mount.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+
+#[derive(Default)]
+pub struct FreeMap {
+    /// Start block -> run length
+    by_start: BTreeMap<u64, u64>,
+    /// (run length, start block)
+    by_len: BTreeSet<(u64, u64)>,
+}
+
+impl FreeMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Map of the clear bits of `bitmap` (set = in use) from block `first`
+    /// up to `end`
+    pub fn from_bitmap(bitmap: &[u8], first: u64, end: u64) -> Self {
+        let mut map = Self::new();
+        let mut block = first;
+        while block < end {
+            // Skip whole bytes of allocated blocks quickly
+            if block % 8 == 0 && block + 8 <= end && bitmap[(block / 8) as usize] == 0xFF {
+                block += 8;
+                continue;
+            }
+            if bitmap[(block / 8) as usize] & (1 << (block % 8)) != 0 {
+                block += 1;
+                continue;
+            }
+            let start = block;
+            while block < end && bitmap[(block / 8) as usize] & (1 << (block % 8)) == 0 {
+                block += 1;
+            }
+            map.add(start, block - start);
+        }
+        map
+    }
+
+    /// Number of separate free runs
+    pub fn run_count(&self) -> usize {
+        self.by_start.len()
+    }
+
+    /// Length of the longest free run
+    pub fn largest(&self) -> u64 {
+        self.by_len.iter().next_back().map_or(0, |&(len, _)| len)
+    }
+
+    /// The free run containing `block`, as (start, length)
+    pub fn run_at(&self, block: u64) -> Option<(u64, u64)> {
+        let (&start, &len) = self.by_start.range(..=block).next_back()?;
+        if block < start + len { Some((start, len)) } else { None }
+    }
+
+    /// The shortest run of at least `len` blocks, or the longest run if none
+    /// is long enough
+    pub fn best_fit(&self, len: u64) -> Option<(u64, u64)> {
+        self.by_len
+            .range((len, 0)..)
+            .next()
+            .or_else(|| self.by_len.iter().next_back())
+            .map(|&(len, start)| (start, len))
+    }
+
+    /// Start of the lowest run of at least `len` blocks that begins before
+    /// block `limit`
+    pub fn first_fit_below(&self, len: u64, limit: u64) -> Option<u64> {
+        self.by_start.range(..limit).find(|(_, &run)| run >= len).map(|(&start, _)| start)
+    }
+
+    /// Mark blocks free, merging them with the runs on either side
+    pub fn insert(&mut self, start: u64, len: u64) {
+        if len == 0 {
+            return;
+        }
+        self.remove(start, len);
+        let (mut start, mut len) = (start, len);
+        if let Some((before, before_len)) = self.by_start.range(..start).next_back().map(|(&s, &l)| (s, l)) {
+            if before + before_len == start {
+                self.take(before, before_len);
+                start = before;
+                len += before_len;
+            }
+        }
+        if let Some(after_len) = self.by_start.get(&(start + len)).copied() {
+            self.take(start + len, after_len);
+            len += after_len;
+        }
+        self.add(start, len);
+    }
+
+    /// Mark blocks in use, splitting any runs they fall in
+    pub fn remove(&mut self, start: u64, len: u64) {
+        let end = start + len;
+        let overlapping: alloc::vec::Vec<(u64, u64)> = self
+            .by_start
+            .range(..end)
+            .rev()
+            .take_while(|(&run, &run_len)| run + run_len > start)
+            .map(|(&run, &run_len)| (run, run_len))
+            .collect();
+        for (run, run_len) in overlapping {
+            self.take(run, run_len);
+            if run < start {
+                self.add(run, start - run);
+            }
+            if run + run_len > end {
+                self.add(end, run + run_len - end);
+            }
+        }
+    }
+
+    fn add(&mut self, start: u64, len: u64) {
+        self.by_start.insert(start, len);
+        self.by_len.insert((len, start));
+    }
+
+    fn take(&mut self, start: u64, len: u64) {
+        self.by_start.remove(&start);
+        self.by_len.remove(&(len, start));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_split_and_fit() {
+        // Blocks 0-3 and 10-11 in use, 16 blocks in all
+        let bitmap = [0b0000_1111, 0b0000_1100];
+        let mut map = FreeMap::from_bitmap(&bitmap, 0, 16);
+        assert_eq!(map.run_count(), 2);
+        assert_eq!(map.run_at(5), Some((4, 6)));
+        assert_eq!(map.run_at(10), None);
+
+        assert_eq!(map.best_fit(3), Some((12, 4)));
+        assert_eq!(map.best_fit(5), Some((4, 6)));
+        assert_eq!(map.best_fit(9), Some((4, 6)));
+        assert_eq!(map.first_fit_below(4, 12), Some(4));
+        assert_eq!(map.first_fit_below(4, 4), None);
+
+        map.remove(6, 2);
+        assert_eq!(map.run_count(), 3);
+        assert_eq!(map.best_fit(2), Some((4, 2)));
+
+        // Freeing the gaps joins everything from block 4 up
+        map.insert(6, 2);
+        map.insert(10, 2);
+        assert_eq!((map.run_count(), map.largest()), (1, 12));
+        assert_eq!(map.run_at(15), Some((4, 12)));
+    }
+}
@@ -0,0 +1,733 @@
+// ISO 9660 filesystem (read-only)
+//
+// Reads CD-ROM images made by mkisofs/genisoimage/xorriso, attached as a
+// virtio-blk disk. The volume descriptors start at byte 32768; everything
+// after that is addressed in the volume's logical blocks (2048 bytes on
+// every real disc), which are read through the 512-byte block layer.
+//
+// Names come from Rock Ridge when the primary tree carries it (long POSIX
+// names, modes and times), else from the Joliet tree (UCS-2 names), else the
+// plain 8.3 names, shown lowercased without their ";1" version suffix.
+// Files split into several extents are read as one.
+
+use super::{FileInfo, FileSystem, ATTR_HIDDEN, ATTR_READ_ONLY};
+use crate::system::block::{BlockDevice, BLOCK_SIZE};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Volume descriptors are 2048 bytes from byte 16 * 2048, whatever the block size
+const DESCRIPTOR_SIZE: u64 = 2048;
+const FIRST_DESCRIPTOR: u64 = 16;
+const MAX_DESCRIPTORS: u64 = 32;
+const TYPE_PRIMARY: u8 = 1;
+const TYPE_SUPPLEMENTARY: u8 = 2;
+const TYPE_TERMINATOR: u8 = 255;
+
+// Directory record flags
+const FLAG_HIDDEN: u8 = 0x01;
+const FLAG_DIR: u8 = 0x02;
+const FLAG_MULTI_EXTENT: u8 = 0x80;
+
+// Rock Ridge NM flags
+const NM_CURRENT: u8 = 0x02;
+const NM_PARENT: u8 = 0x04;
+
+// Rock Ridge TF flags: which stamps are present, and their format
+const TF_CREATION: u8 = 0x01;
+const TF_MODIFY: u8 = 0x02;
+const TF_ACCESS: u8 = 0x04;
+const TF_LONG_FORM: u8 = 0x80;
+
+const MAX_CONTINUATIONS: usize = 16;
+/// Largest directory read (bigger ones are taken as corruption)
+const MAX_DIR_BYTES: u64 = 16 * 1024 * 1024;
+/// Largest read passed to the device at once
+const CHUNK_BYTES: u64 = 64 * 1024;
+const READ_ONLY: &str = "ISO 9660 volumes are read-only";
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// Where entry names come from
+#[derive(Clone, Copy, PartialEq)]
+enum Names {
+    Plain,
+    Joliet,
+    /// Rock Ridge, with `skip` bytes before each record's SUSP entries
+    RockRidge { skip: usize },
+}
+
+/// A directory entry, merged across the records of a multi-extent file
+#[derive(Clone)]
+struct Entry {
+    name: String,
+    /// (first logical block, length in bytes) of each extent, in file order
+    extents: Vec<(u64, u64)>,
+    size: u64,
+    is_dir: bool,
+    hidden: bool,
+    /// POSIX mode from Rock Ridge
+    mode: Option<u32>,
+    created: i64,
+    modified: i64,
+    accessed: i64,
+    /// Rock Ridge moved this directory here to keep the tree shallow; it is
+    /// listed at its original place instead
+    relocated: bool,
+}
+
+pub struct IsoFilesystem {
+    block_size: u64,
+    volume_blocks: u64,
+    names: Names,
+    root: Entry,
+    label: String,
+}
+
+/// Read `buffer.len()` bytes at byte `offset` of the device
+fn read_bytes(device: &mut dyn BlockDevice, offset: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+    let first = offset / BLOCK_SIZE as u64;
+    let skip = (offset % BLOCK_SIZE as u64) as usize;
+    if skip == 0 && buffer.len() % BLOCK_SIZE == 0 {
+        return device.read_blocks(first, buffer);
+    }
+    let blocks = (skip + buffer.len() + BLOCK_SIZE - 1) / BLOCK_SIZE;
+    let mut whole = vec![0u8; blocks * BLOCK_SIZE];
+    device.read_blocks(first, &mut whole)?;
+    buffer.copy_from_slice(&whole[skip..skip + buffer.len()]);
+    Ok(())
+}
+
+/// A Joliet supplementary descriptor has a UCS-2 escape sequence
+fn is_joliet(descriptor: &[u8]) -> bool {
+    descriptor[88] == b'%' && descriptor[89] == b'/' && matches!(descriptor[90], b'@' | b'C' | b'E')
+}
+
+fn decode_ucs2(bytes: &[u8]) -> String {
+    let units = bytes.chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]]));
+    char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)).collect()
+}
+
+/// A descriptor identifier without its space (or NUL) padding
+fn trim_id(id: &str) -> &str {
+    id.trim_end_matches(|c| c == ' ' || c == '\0')
+}
+
+/// Drop the ";1" version and the '.' left on names without an extension
+fn strip_version(name: &str) -> &str {
+    let name = name.split(';').next().unwrap_or(name);
+    match name.strip_suffix('.') {
+        Some(stem) if !stem.is_empty() => stem,
+        _ => name,
+    }
+}
+
+/// Unix time of a 7-byte directory record date (years since 1900, month,
+/// day, hour, minute, second, offset from UTC in 15-minute steps)
+fn record_time(stamp: &[u8]) -> i64 {
+    if stamp[1] == 0 || stamp[2] == 0 {
+        return 0;
+    }
+    let days = crate::kernel::clock::days_from_civil(1900 + stamp[0] as i32, stamp[1], stamp[2]);
+    let seconds = stamp[3] as i64 * 3600 + stamp[4] as i64 * 60 + stamp[5] as i64;
+    days * 24 * 60 * 60 + seconds - stamp[6] as i8 as i64 * 15 * 60
+}
+
+/// Unix time of a 17-byte descriptor-style date ("YYYYMMDDHHMMSScc" and an offset)
+fn long_time(stamp: &[u8]) -> i64 {
+    let number = |range: core::ops::Range<usize>| {
+        stamp[range].iter().fold(0i64, |n, &digit| n * 10 + digit.wrapping_sub(b'0') as i64 % 10)
+    };
+    let (month, day) = (number(4..6) as u8, number(6..8) as u8);
+    if month == 0 || day == 0 {
+        return 0;
+    }
+    let days = crate::kernel::clock::days_from_civil(number(0..4) as i32, month, day);
+    let seconds = number(8..10) * 3600 + number(10..12) * 60 + number(12..14);
+    days * 24 * 60 * 60 + seconds - stamp[16] as i8 as i64 * 15 * 60
+}
+
+/// Apply a Rock Ridge TF entry's creation, modification and access times
+fn apply_times(field: &[u8], entry: &mut Entry) {
+    let flags = field[4];
+    let width = if flags & TF_LONG_FORM != 0 { 17 } else { 7 };
+    let mut pos = 5;
+    // Stamps appear in flag bit order: creation, modify, access, attributes, backup, expiration, effective
+    for bit in 0..7 {
+        if flags & (1 << bit) == 0 {
+            continue;
+        }
+        if pos + width > field.len() {
+            break;
+        }
+        let stamp = &field[pos..pos + width];
+        let time = if width == 17 { long_time(stamp) } else { record_time(stamp) };
+        match 1 << bit {
+            TF_CREATION => entry.created = time,
+            TF_MODIFY => entry.modified = time,
+            TF_ACCESS => entry.accessed = time,
+            _ => {}
+        }
+        pos += width;
+    }
+}
+
+impl IsoFilesystem {
+    /// Whether `device` has an ISO 9660 volume descriptor. Only reads the
+    /// first descriptor.
+    pub fn probe(device: &mut dyn BlockDevice) -> bool {
+        let mut descriptor = vec![0u8; DESCRIPTOR_SIZE as usize];
+        read_bytes(device, FIRST_DESCRIPTOR * DESCRIPTOR_SIZE, &mut descriptor).is_ok() && &descriptor[1..6] == b"CD001"
+    }
+
+    pub fn mount(device: &mut dyn BlockDevice) -> Result<Self, &'static str> {
+        let mut primary = None;
+        let mut joliet = None;
+        for index in 0..MAX_DESCRIPTORS {
+            let mut descriptor = vec![0u8; DESCRIPTOR_SIZE as usize];
+            if read_bytes(device, (FIRST_DESCRIPTOR + index) * DESCRIPTOR_SIZE, &mut descriptor).is_err()
+                || &descriptor[1..6] != b"CD001"
+            {
+                if index == 0 {
+                    return Err("Not an ISO 9660 volume");
+                }
+                break;
+            }
+            match descriptor[0] {
+                TYPE_PRIMARY if primary.is_none() => primary = Some(descriptor),
+                TYPE_SUPPLEMENTARY if joliet.is_none() && is_joliet(&descriptor) => joliet = Some(descriptor),
+                TYPE_TERMINATOR => break,
+                _ => {}
+            }
+        }
+        let primary = primary.ok_or("ISO 9660 volume has no primary descriptor")?;
+
+        let block_size = read_u16(&primary, 128) as u64;
+        let volume_blocks = read_u32(&primary, 80) as u64;
+        if !matches!(block_size, 512 | 1024 | 2048) {
+            return Err("Corrupt ISO 9660 descriptor");
+        }
+        if volume_blocks * block_size > device.block_count() * BLOCK_SIZE as u64 {
+            return Err("ISO 9660 volume is larger than the device");
+        }
+
+        let mut fs = IsoFilesystem {
+            block_size,
+            volume_blocks,
+            names: Names::Plain,
+            root: Self::root_entry(&primary)?,
+            label: String::from(trim_id(&String::from_utf8_lossy(&primary[40..72]))),
+        };
+
+        // Rock Ridge announces itself with an SP entry in the root's "." record
+        let first = fs.read_all(device, &fs.root)?;
+        let dot_len = first[0] as usize;
+        if dot_len >= 34 && dot_len <= first.len() {
+            let area = system_use(&first[..dot_len], 0);
+            if area.len() >= 7 && &area[..2] == b"SP" && area[4] == 0xBE && area[5] == 0xEF {
+                fs.names = Names::RockRidge { skip: area[6] as usize };
+            }
+        }
+        if let (Names::Plain, Some(joliet)) = (fs.names, &joliet) {
+            fs.names = Names::Joliet;
+            fs.root = Self::root_entry(joliet)?;
+        }
+        if let Some(joliet) = &joliet {
+            let label = decode_ucs2(&joliet[40..72]);
+            if !trim_id(&label).is_empty() {
+                fs.label = String::from(trim_id(&label));
+            }
+        }
+
+        crate::kernel::uart_write_string(&alloc::format!(
+            "ISO 9660 volume '{}': {} blocks of {} bytes, {} (read-only)\r\n",
+            fs.label, volume_blocks, block_size, fs.fs_type()
+        ));
+        Ok(fs)
+    }
+
+    fn root_entry(descriptor: &[u8]) -> Result<Entry, &'static str> {
+        let record = &descriptor[156..190];
+        if record[0] < 34 || record[25] & FLAG_DIR == 0 {
+            return Err("Corrupt ISO 9660 root directory");
+        }
+        Ok(Self::plain_entry(record, String::new()))
+    }
+
+    /// An entry from the fields of a directory record alone
+    fn plain_entry(record: &[u8], name: String) -> Entry {
+        let flags = record[25];
+        let start = read_u32(record, 2) as u64 + record[1] as u64; // Skip any extended attribute record
+        let size = read_u32(record, 10) as u64;
+        let time = record_time(&record[18..25]);
+        Entry {
+            name,
+            extents: vec![(start, size)],
+            size,
+            is_dir: flags & FLAG_DIR != 0,
+            hidden: flags & FLAG_HIDDEN != 0,
+            mode: None,
+            created: time,
+            modified: time,
+            accessed: time,
+            relocated: false,
+        }
+    }
+
+    /// Read `buffer.len()` bytes of `entry` starting `offset` bytes in,
+    /// stopping at its end. Returns how many bytes were read.
+    fn read_range(&self, device: &mut dyn BlockDevice, entry: &Entry, offset: u64, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        let end = core::cmp::min(offset.saturating_add(buffer.len() as u64), entry.size);
+        let mut at = offset;
+        let mut extent_start = 0;
+        for &(block, len) in &entry.extents {
+            let extent_end = extent_start + len;
+            if block.saturating_mul(self.block_size).saturating_add(len) > self.volume_blocks * self.block_size {
+                return Err("Corrupt ISO 9660 extent");
+            }
+            while at < core::cmp::min(extent_end, end) {
+                let count = core::cmp::min(core::cmp::min(extent_end, end) - at, CHUNK_BYTES);
+                let done = (at - offset) as usize;
+                let byte = block * self.block_size + (at - extent_start);
+                read_bytes(device, byte, &mut buffer[done..done + count as usize])?;
+                at += count;
+            }
+            extent_start = extent_end;
+        }
+        Ok(at.saturating_sub(offset) as usize)
+    }
+
+    fn read_all(&self, device: &mut dyn BlockDevice, entry: &Entry) -> Result<Vec<u8>, &'static str> {
+        if entry.size > MAX_DIR_BYTES {
+            return Err("Corrupt ISO 9660 directory");
+        }
+        let mut data = vec![0u8; entry.size as usize];
+        let size = self.read_range(device, entry, 0, &mut data)?;
+        data.truncate(size);
+        Ok(data)
+    }
+
+    fn decode_name(&self, raw: &[u8]) -> String {
+        match self.names {
+            Names::Joliet => String::from(strip_version(&decode_ucs2(raw))),
+            _ => strip_version(&String::from_utf8_lossy(raw)).to_ascii_lowercase(),
+        }
+    }
+
+    fn read_dir(&self, device: &mut dyn BlockDevice, dir: &Entry) -> Result<Vec<Entry>, &'static str> {
+        let data = self.read_all(device, dir)?;
+        let mut entries: Vec<Entry> = Vec::new();
+        let mut continuing = false;
+        let mut pos = 0;
+        while pos < data.len() {
+            // Records don't cross block boundaries; a zero length pads to the next block
+            let len = data[pos] as usize;
+            if len == 0 {
+                pos = (pos / self.block_size as usize + 1) * self.block_size as usize;
+                continue;
+            }
+            if len < 34 || pos + len > data.len() || 33 + data[pos + 32] as usize > len {
+                return Err("Corrupt ISO 9660 directory");
+            }
+            let record = &data[pos..pos + len];
+            pos += len;
+
+            let raw_name = &record[33..33 + record[32] as usize];
+            if raw_name == [0] || raw_name == [1] {
+                continue; // "." and ".."
+            }
+
+            let flags = record[25];
+            if continuing {
+                // Later extents of a multi-extent file
+                if let Some(last) = entries.last_mut() {
+                    let start = read_u32(record, 2) as u64 + record[1] as u64;
+                    let size = read_u32(record, 10) as u64;
+                    last.extents.push((start, size));
+                    last.size += size;
+                }
+                continuing = flags & FLAG_MULTI_EXTENT != 0;
+                continue;
+            }
+            continuing = flags & FLAG_MULTI_EXTENT != 0;
+
+            let mut entry = Self::plain_entry(record, self.decode_name(raw_name));
+            if let Names::RockRidge { skip } = self.names {
+                self.apply_rock_ridge(device, &mut entry, system_use(record, skip))?;
+            }
+            if !entry.relocated {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Update an entry from the Rock Ridge entries in a record's system use area
+    fn apply_rock_ridge(&self, device: &mut dyn BlockDevice, entry: &mut Entry, area: &[u8]) -> Result<(), &'static str> {
+        let mut name = String::new();
+        let mut child_link = None;
+        let mut area = Vec::from(area);
+        let mut continuations = 0;
+        loop {
+            let mut next = None;
+            let mut pos = 0;
+            while pos + 4 <= area.len() {
+                let len = area[pos + 2] as usize;
+                if len < 4 || pos + len > area.len() {
+                    break;
+                }
+                let field = &area[pos..pos + len];
+                match &field[..2] {
+                    b"NM" if len >= 5 && field[4] & (NM_CURRENT | NM_PARENT) == 0 => {
+                        name.push_str(&String::from_utf8_lossy(&field[5..]));
+                    }
+                    b"PX" if len >= 12 => entry.mode = Some(read_u32(field, 4)),
+                    b"TF" if len >= 5 => apply_times(field, entry),
+                    b"RE" => entry.relocated = true,
+                    b"CL" if len >= 12 => child_link = Some(read_u32(field, 4) as u64),
+                    b"CE" if len >= 28 => {
+                        let offset = read_u32(field, 4) as u64 * self.block_size + read_u32(field, 12) as u64;
+                        next = Some((offset, read_u32(field, 20) as usize));
+                    }
+                    b"ST" => break,
+                    _ => {}
+                }
+                pos += len;
+            }
+
+            // Entries that didn't fit in the record continue in another block
+            match next {
+                Some((offset, len)) if continuations < MAX_CONTINUATIONS && len as u64 <= self.block_size => {
+                    area = vec![0u8; len];
+                    read_bytes(device, offset, &mut area)?;
+                    continuations += 1;
+                }
+                _ => break,
+            }
+        }
+
+        if !name.is_empty() {
+            entry.name = name;
+        }
+        if let Some(block) = child_link {
+            // A placeholder file standing for a relocated directory; its size
+            // is in the "." record at the start of the directory
+            let mut first = vec![0u8; 34];
+            read_bytes(device, block * self.block_size, &mut first)?;
+            let size = read_u32(&first, 10) as u64;
+            entry.extents = vec![(block, size)];
+            entry.size = size;
+            entry.is_dir = true;
+        }
+        Ok(())
+    }
+
+    fn lookup(&self, device: &mut dyn BlockDevice, path: &str) -> Result<Entry, &'static str> {
+        let mut dirs = vec![self.root.clone()];
+        for part in path.split('/').filter(|p| !p.is_empty() && *p != ".") {
+            let current = dirs.last().unwrap();
+            if !current.is_dir {
+                return Err("Not a directory");
+            }
+            if part == ".." {
+                if dirs.len() > 1 {
+                    dirs.pop();
+                }
+                continue;
+            }
+            // Plain names are shown lowercased, so match them in any case
+            let child = self
+                .read_dir(device, current)?
+                .into_iter()
+                .find(|e| e.name == part || (self.names == Names::Plain && e.name.eq_ignore_ascii_case(part)))
+                .ok_or("File not found")?;
+            dirs.push(child);
+        }
+        Ok(dirs.pop().unwrap())
+    }
+
+    fn info(&self, entry: &Entry) -> FileInfo {
+        let size = if entry.is_dir { 0 } else { core::cmp::min(entry.size, u32::MAX as u64) as u32 };
+        let mut attributes = 0;
+        if entry.hidden || (matches!(self.names, Names::RockRidge { .. }) && entry.name.starts_with('.')) {
+            attributes |= ATTR_HIDDEN;
+        }
+        if entry.mode.map_or(false, |mode| mode & 0o222 == 0) {
+            attributes |= ATTR_READ_ONLY;
+        }
+        FileInfo {
+            created: entry.created,
+            modified: entry.modified,
+            accessed: entry.accessed,
+            attributes,
+            ..FileInfo::new(entry.name.clone(), size, entry.is_dir)
+        }
+    }
+}
+
+/// The system use area of a directory record, after `skip` bytes
+fn system_use(record: &[u8], skip: usize) -> &[u8] {
+    let name_len = record[32] as usize;
+    // The name is padded to an even length
+    let start = 33 + name_len + (name_len + 1) % 2 + skip;
+    record.get(start..).unwrap_or(&[])
+}
+
+impl FileSystem for IsoFilesystem {
+    fn fs_type(&self) -> &'static str {
+        match self.names {
+            Names::Plain => "ISO9660",
+            Names::Joliet => "ISO9660 (Joliet)",
+            Names::RockRidge { .. } => "ISO9660 (Rock Ridge)",
+        }
+    }
+
+    /// Volume identifier, from the Joliet descriptor when there is one
+    fn label(&self) -> &str {
+        &self.label
+    }
+
+    fn list_dir(&self, device: &mut dyn BlockDevice, path: &str) -> Result<Vec<FileInfo>, &'static str> {
+        let dir = self.lookup(device, path)?;
+        if !dir.is_dir {
+            return Err("Not a directory");
+        }
+        Ok(self.read_dir(device, &dir)?.iter().map(|entry| self.info(entry)).collect())
+    }
+
+    fn stat(&self, device: &mut dyn BlockDevice, path: &str) -> Result<FileInfo, &'static str> {
+        Ok(self.info(&self.lookup(device, path)?))
+    }
+
+    fn read_file(&self, device: &mut dyn BlockDevice, path: &str, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        let entry = self.lookup(device, path)?;
+        if entry.is_dir {
+            return Err("Is a directory");
+        }
+        let size = entry.size as usize;
+        if buffer.len() < size {
+            return Err("Buffer too small for file");
+        }
+        self.read_range(device, &entry, 0, &mut buffer[..size])
+    }
+
+    fn read_at(&self, device: &mut dyn BlockDevice, path: &str, offset: u64, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        let entry = self.lookup(device, path)?;
+        if entry.is_dir {
+            return Err("Is a directory");
+        }
+        self.read_range(device, &entry, offset, buffer)
+    }
+
+    fn create_file(&mut self, _device: &mut dyn BlockDevice, _path: &str, _size: u32) -> Result<(), &'static str> {
+        Err(READ_ONLY)
+    }
+
+    fn write_file(&mut self, _device: &mut dyn BlockDevice, _path: &str, _data: &[u8]) -> Result<(), &'static str> {
+        Err(READ_ONLY)
+    }
+
+    fn write_at(&mut self, _device: &mut dyn BlockDevice, _path: &str, _offset: u64, _data: &[u8]) -> Result<usize, &'static str> {
+        Err(READ_ONLY)
+    }
+
+    fn delete_file(&mut self, _device: &mut dyn BlockDevice, _path: &str) -> Result<(), &'static str> {
+        Err(READ_ONLY)
+    }
+
+    fn rename_file(&mut self, _device: &mut dyn BlockDevice, _old_path: &str, _new_path: &str) -> Result<(), &'static str> {
+        Err(READ_ONLY)
+    }
+
+    fn create_dir(&mut self, _device: &mut dyn BlockDevice, _path: &str) -> Result<(), &'static str> {
+        Err(READ_ONLY)
+    }
+
+    fn truncate(&mut self, _device: &mut dyn BlockDevice, _path: &str, _size: u32) -> Result<(), &'static str> {
+        Err(READ_ONLY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::block::RamDisk;
+    use crate::system::fs::mime;
+
+    const SS: usize = 2048;
+
+    fn put_both_u16(bytes: &mut [u8], at: usize, value: u16) {
+        bytes[at..at + 2].copy_from_slice(&value.to_le_bytes());
+        bytes[at + 2..at + 4].copy_from_slice(&value.to_be_bytes());
+    }
+
+    fn put_both_u32(bytes: &mut [u8], at: usize, value: u32) {
+        bytes[at..at + 4].copy_from_slice(&value.to_le_bytes());
+        bytes[at + 4..at + 8].copy_from_slice(&value.to_be_bytes());
+    }
+
+    /// A directory record dated 2026-01-02 03:04:05 UTC
+    fn record(block: u32, size: u32, flags: u8, name: &[u8], system_use: &[u8]) -> Vec<u8> {
+        let pad = (name.len() + 1) % 2;
+        let len = 33 + name.len() + pad + system_use.len();
+        let mut r = vec![0u8; len + len % 2];
+        r[0] = r.len() as u8;
+        put_both_u32(&mut r, 2, block);
+        put_both_u32(&mut r, 10, size);
+        r[18..25].copy_from_slice(&[126, 1, 2, 3, 4, 5, 0]);
+        r[25] = flags;
+        put_both_u16(&mut r, 28, 1);
+        r[32] = name.len() as u8;
+        r[33..33 + name.len()].copy_from_slice(name);
+        r[33 + name.len() + pad..len].copy_from_slice(system_use);
+        r
+    }
+
+    fn put_dir(image: &mut [u8], sector: usize, records: &[Vec<u8>]) {
+        let mut at = sector * SS;
+        for r in records {
+            image[at..at + r.len()].copy_from_slice(r);
+            at += r.len();
+        }
+    }
+
+    fn put_descriptor(image: &mut [u8], sector: usize, kind: u8, root: u32, volume_id: &[u8]) {
+        let d = &mut image[sector * SS..(sector + 1) * SS];
+        d[0] = kind;
+        d[1..6].copy_from_slice(b"CD001");
+        d[6] = 1;
+        d[40..72].copy_from_slice(volume_id);
+        put_both_u32(d, 80, 40);
+        put_both_u16(d, 128, SS as u16);
+        d[156..190].copy_from_slice(&record(root, SS as u32, FLAG_DIR, &[0], &[]));
+    }
+
+    fn ucs2(s: &str) -> Vec<u8> {
+        s.encode_utf16().flat_map(|unit| unit.to_be_bytes()).collect()
+    }
+
+    fn susp(signature: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut field = vec![signature[0], signature[1], (4 + body.len()) as u8, 1];
+        field.extend_from_slice(body);
+        field
+    }
+
+    /// A 40-sector image:
+    ///   /HELLO.TXT;1     sector 25, 12 bytes ("Hello World.txt" in Rock Ridge and Joliet)
+    ///   /SUBDIR/DEEP.BIN 3000 bytes in two extents, sectors 30 and 33
+    fn make_image(rock_ridge: bool, joliet: bool) -> RamDisk {
+        let mut image = vec![0u8; 40 * SS];
+        put_descriptor(&mut image, 16, TYPE_PRIMARY, 20, alloc::format!("{:<32}", "TESTISO").as_bytes());
+        let mut next = 17;
+        if joliet {
+            put_descriptor(&mut image, 17, TYPE_SUPPLEMENTARY, 22, &ucs2(&alloc::format!("{:<16}", "Joliet Vol")));
+            image[17 * SS + 88..17 * SS + 91].copy_from_slice(b"%/E");
+            next = 18;
+        }
+        image[next * SS] = TYPE_TERMINATOR;
+        image[next * SS + 1..next * SS + 6].copy_from_slice(b"CD001");
+
+        // Rock Ridge: SP in the root's ".", the file's NM in a continuation area
+        let (sp, hello_su, subdir_su) = if rock_ridge {
+            let nm = susp(b"NM", b"\0Hello World.txt");
+            image[26 * SS + 100..26 * SS + 100 + nm.len()].copy_from_slice(&nm);
+            let mut ce = [0u8; 24];
+            put_both_u32(&mut ce, 0, 26);
+            put_both_u32(&mut ce, 8, 100);
+            put_both_u32(&mut ce, 16, nm.len() as u32);
+            let mut px = [0u8; 32];
+            put_both_u32(&mut px, 0, 0o100444);
+            let mut hello = susp(b"PX", &px);
+            hello.extend(susp(b"CE", &ce));
+            (susp(b"SP", &[0xBE, 0xEF, 0]), hello, susp(b"NM", b"\0Sub Dir"))
+        } else {
+            (Vec::new(), Vec::new(), Vec::new())
+        };
+
+        put_dir(&mut image, 20, &[
+            record(20, SS as u32, FLAG_DIR, &[0], &sp),
+            record(20, SS as u32, FLAG_DIR, &[1], &[]),
+            record(25, 12, 0, b"HELLO.TXT;1", &hello_su),
+            record(21, SS as u32, FLAG_DIR, b"SUBDIR", &subdir_su),
+        ]);
+        put_dir(&mut image, 21, &[
+            record(21, SS as u32, FLAG_DIR, &[0], &[]),
+            record(20, SS as u32, FLAG_DIR, &[1], &[]),
+            record(30, SS as u32, FLAG_MULTI_EXTENT, b"DEEP.BIN;1", &[]),
+            record(33, 952, 0, b"DEEP.BIN;1", &[]),
+        ]);
+        put_dir(&mut image, 22, &[
+            record(22, SS as u32, FLAG_DIR, &[0], &[]),
+            record(22, SS as u32, FLAG_DIR, &[1], &[]),
+            record(25, 12, 0, &ucs2("Hello World.txt;1"), &[]),
+            record(23, SS as u32, FLAG_DIR, &ucs2("Sub Dir"), &[]),
+        ]);
+        put_dir(&mut image, 23, &[
+            record(23, SS as u32, FLAG_DIR, &[0], &[]),
+            record(22, SS as u32, FLAG_DIR, &[1], &[]),
+            record(30, SS as u32, FLAG_MULTI_EXTENT, &ucs2("deep.bin;1"), &[]),
+            record(33, 952, 0, &ucs2("deep.bin;1"), &[]),
+        ]);
+
+        image[25 * SS..25 * SS + 12].copy_from_slice(b"Hello, ISO!\n");
+        image[30 * SS..31 * SS].fill(0xAA);
+        image[33 * SS..34 * SS].fill(0xBB);
+        RamDisk::from_image(image)
+    }
+
+    fn names(fs: &IsoFilesystem, disk: &mut RamDisk, path: &str) -> Vec<String> {
+        fs.list_dir(disk, path).unwrap().into_iter().map(|f| f.name).collect()
+    }
+
+    #[test]
+    fn test_rock_ridge() {
+        let mut disk = make_image(true, true);
+        let mut fs = IsoFilesystem::mount(&mut disk).unwrap();
+        assert_eq!(fs.fs_type(), "ISO9660 (Rock Ridge)");
+        assert_eq!(fs.label(), "Joliet Vol");
+        assert_eq!(names(&fs, &mut disk, ""), ["Hello World.txt", "Sub Dir"]);
+
+        let hello = fs.stat(&mut disk, "Hello World.txt").unwrap();
+        assert_eq!((hello.size, hello.attribute_string().as_str()), (12, "r--"));
+        assert_eq!(hello.modified, 1_767_323_045);
+        let mut buffer = [0u8; 16];
+        assert_eq!(fs.read_file(&mut disk, "Hello World.txt", &mut buffer).unwrap(), 12);
+        assert_eq!(&buffer[..12], b"Hello, ISO!\n");
+
+        // Two extents read as one file
+        let mut data = vec![0u8; 3000];
+        assert_eq!(fs.read_file(&mut disk, "Sub Dir/deep.bin", &mut data).unwrap(), 3000);
+        assert!(data[..SS].iter().all(|&b| b == 0xAA) && data[SS..].iter().all(|&b| b == 0xBB));
+        assert_eq!(fs.read_at(&mut disk, "Sub Dir/deep.bin", 2040, &mut buffer).unwrap(), 16);
+        assert_eq!(buffer, [0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xBB, 0xBB, 0xBB, 0xBB, 0xBB, 0xBB, 0xBB, 0xBB]);
+        assert_eq!(fs.read_at(&mut disk, "Sub Dir/deep.bin", 2990, &mut buffer).unwrap(), 10);
+        assert!(fs.write_file(&mut disk, "Hello World.txt", b"x").is_err());
+    }
+
+    #[test]
+    fn test_joliet_and_plain_names() {
+        let mut disk = make_image(false, true);
+        let fs = IsoFilesystem::mount(&mut disk).unwrap();
+        assert_eq!(fs.fs_type(), "ISO9660 (Joliet)");
+        assert_eq!(names(&fs, &mut disk, ""), ["Hello World.txt", "Sub Dir"]);
+        assert_eq!(fs.stat(&mut disk, "Sub Dir/deep.bin").unwrap().size, 3000);
+
+        let mut disk = make_image(false, false);
+        let fs = IsoFilesystem::mount(&mut disk).unwrap();
+        assert_eq!((fs.fs_type(), fs.label()), ("ISO9660", "TESTISO"));
+        assert_eq!(names(&fs, &mut disk, ""), ["hello.txt", "subdir"]);
+        assert_eq!(names(&fs, &mut disk, "SUBDIR"), ["deep.bin"]);
+        let file = fs.stat(&mut disk, "HELLO.TXT").unwrap();
+        assert_eq!(mime::file_type(file.file_type).mime, "text/plain");
+        assert_eq!(fs.stat(&mut disk, "hello.txt/x").map(|f| f.size), Err("Not a directory"));
+
+        let mut blank = RamDisk::new(128);
+        assert!(IsoFilesystem::mount(&mut blank).is_err());
+    }
+}
@@ -0,0 +1,83 @@
+// File type hints
+//
+// A small table of MIME types. Files are matched by extension, or by their
+// first bytes for formats with a signature. SimpleFS records the table index
+// in each inode; other filesystems derive the type from the name when listed.
+
+/// A known file type
+pub struct FileType {
+    pub mime: &'static str,
+    /// Short description for listings
+    pub label: &'static str,
+    extensions: &'static [&'static str],
+    signature: &'static [u8],
+}
+
+/// Every known type. Index 0 is the fallback; indices are stored on disk,
+/// so new types go at the end.
+pub const FILE_TYPES: &[FileType] = &[
+    FileType { mime: "application/octet-stream", label: "File", extensions: &[], signature: &[] },
+    FileType { mime: "inode/directory", label: "Folder", extensions: &[], signature: &[] },
+    FileType { mime: "text/plain", label: "Text", extensions: &["txt", "log", "cfg", "ini", "conf"], signature: &[] },
+    FileType { mime: "text/markdown", label: "Markdown", extensions: &["md"], signature: &[] },
+    FileType { mime: "text/html", label: "HTML", extensions: &["html", "htm"], signature: &[] },
+    FileType { mime: "text/css", label: "CSS", extensions: &["css"], signature: &[] },
+    FileType { mime: "application/json", label: "JSON", extensions: &["json"], signature: &[] },
+    FileType { mime: "text/x-rust", label: "Rust", extensions: &["rs"], signature: &[] },
+    FileType { mime: "image/png", label: "PNG image", extensions: &["png"], signature: b"\x89PNG\r\n\x1a\n" },
+    FileType { mime: "image/bmp", label: "BMP image", extensions: &["bmp"], signature: &[] },
+    FileType { mime: "image/jpeg", label: "JPEG image", extensions: &["jpg", "jpeg"], signature: b"\xFF\xD8\xFF" },
+    FileType { mime: "image/gif", label: "GIF image", extensions: &["gif"], signature: b"GIF8" },
+    FileType { mime: "font/ttf", label: "Font", extensions: &["ttf"], signature: b"\x00\x01\x00\x00" },
+    FileType { mime: "application/pdf", label: "PDF", extensions: &["pdf"], signature: b"%PDF-" },
+    FileType { mime: "application/zip", label: "Zip archive", extensions: &["zip"], signature: b"PK\x03\x04" },
+    FileType { mime: "application/gzip", label: "Gzip archive", extensions: &["gz", "tgz"], signature: b"\x1f\x8b" },
+    FileType { mime: "application/x-tar", label: "Tar archive", extensions: &["tar"], signature: &[] },
+    FileType { mime: "application/x-elf", label: "Program", extensions: &["elf"], signature: b"\x7fELF" },
+];
+
+/// Index of the fallback type
+pub const UNKNOWN: u8 = 0;
+/// Index of "inode/directory"
+pub const DIRECTORY: u8 = 1;
+
+/// Type of a file named `name`, by extension
+pub fn from_name(name: &str) -> u8 {
+    let extension = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => extension,
+        _ => return UNKNOWN,
+    };
+    FILE_TYPES
+        .iter()
+        .position(|t| t.extensions.iter().any(|e| e.eq_ignore_ascii_case(extension)))
+        .map_or(UNKNOWN, |index| index as u8)
+}
+
+/// Type of a file from its contents, falling back to its name
+pub fn detect(name: &str, data: &[u8]) -> u8 {
+    FILE_TYPES
+        .iter()
+        .position(|t| !t.signature.is_empty() && data.starts_with(t.signature))
+        .map_or_else(|| from_name(name), |index| index as u8)
+}
+
+/// Table entry for a stored index (unknown indices map to the fallback)
+pub fn file_type(index: u8) -> &'static FileType {
+    FILE_TYPES.get(index as usize).unwrap_or(&FILE_TYPES[UNKNOWN as usize])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detection() {
+        assert_eq!(file_type(from_name("notes.TXT")).mime, "text/plain");
+        assert_eq!(from_name(".txt"), UNKNOWN);
+        assert_eq!(from_name("Makefile"), UNKNOWN);
+        // Contents win over a misleading extension
+        assert_eq!(file_type(detect("photo.txt", b"\x89PNG\r\n\x1a\n....")).mime, "image/png");
+        assert_eq!(file_type(detect("a.jpg", b"hello")).mime, "image/jpeg");
+        assert_eq!(file_type(200).mime, "application/octet-stream");
+    }
+}
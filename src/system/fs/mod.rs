@@ -1,6 +1,90 @@
 // Filesystem module
+//
+// Each on-disk format implements the FileSystem trait, with paths relative to
+// the volume root and '/' between directory names. The rest of the system goes
+// through the vfs module, which mounts volumes once and addresses them by
+// absolute path (/disk0/docs/a.txt). tmpfs, procfs and devfs implement the
+// same trait without a disk behind them and are mounted at /tmp, /proc and /dev,
+// as are host folders shared over 9P (ninep.rs), at /<mount tag>.
+// The trait and its types live in common.rs, which the host tools share.
 
+mod common;
+pub mod archivefs;
+pub mod devfs;
+pub mod ext2;
+pub mod fat;
 pub mod filesystem;
+pub mod freemap;
+pub mod iso9660;
+pub mod mime;
+pub mod ninep;
+pub mod procfs;
+pub mod tmpfs;
+pub mod vfs;
+
+use crate::system::block::BlockDevice;
+use alloc::boxed::Box;
+
+pub use common::*;
 
 // Re-export commonly used types
+pub use archivefs::ArchiveFs;
+pub use ext2::Ext2Filesystem;
+pub use fat::FatFilesystem;
 pub use filesystem::SimpleFilesystem;
+pub use iso9660::IsoFilesystem;
+pub use ninep::NinePFs;
+pub use tmpfs::TmpFs;
+
+/// Blocks at the start of a device that must be zero for it to count as blank
+const BLANK_CHECK_BLOCKS: u64 = 128;
+
+/// Mount whichever supported filesystem is on `device`. Only formats whose
+/// signature is present are tried, so a volume we recognise but refuse (an
+/// ext4 feature we lack, a journal that needs replaying on a read-only disk)
+/// reports why rather than "no filesystem".
+pub fn mount(device: &mut dyn BlockDevice) -> Result<Box<dyn FileSystem>, &'static str> {
+    let mut error = None;
+    if SimpleFilesystem::probe(device) {
+        match SimpleFilesystem::mount(device) {
+            Ok(fs) => return Ok(Box::new(fs)),
+            Err(e) => error = error.or(Some(e)),
+        }
+    }
+    if FatFilesystem::probe(device) {
+        match FatFilesystem::mount(device) {
+            Ok(fs) => return Ok(Box::new(fs)),
+            Err(e) => error = error.or(Some(e)),
+        }
+    }
+    if Ext2Filesystem::probe(device) {
+        match Ext2Filesystem::mount(device) {
+            Ok(fs) => return Ok(Box::new(fs)),
+            Err(e) => error = error.or(Some(e)),
+        }
+    }
+    if IsoFilesystem::probe(device) {
+        match IsoFilesystem::mount(device) {
+            Ok(fs) => return Ok(Box::new(fs)),
+            Err(e) => error = error.or(Some(e)),
+        }
+    }
+    Err(error.unwrap_or("No supported filesystem found"))
+}
+
+/// Whether `device` carries the signature of a filesystem we know, whether or
+/// not it would mount. Only reads superblocks.
+pub fn probe(device: &mut dyn BlockDevice) -> bool {
+    SimpleFilesystem::probe(device)
+        || FatFilesystem::probe(device)
+        || Ext2Filesystem::probe(device)
+        || IsoFilesystem::probe(device)
+}
+
+/// Whether the start of `device`, where every format we know keeps its
+/// superblock, is all zeros. Only a blank device is formatted without asking.
+pub fn is_blank(device: &mut dyn BlockDevice) -> bool {
+    let blocks = device.block_count().min(BLANK_CHECK_BLOCKS);
+    let mut buffer = alloc::vec![0u8; blocks as usize * crate::system::block::BLOCK_SIZE];
+    device.read_blocks(0, &mut buffer).is_ok() && buffer.iter().all(|&b| b == 0)
+}
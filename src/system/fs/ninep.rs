@@ -0,0 +1,807 @@
+// 9P2000.L client and host folder filesystem
+//
+// A host directory shared over 9P (QEMU's -virtfs) mounted like any other
+// volume. The client speaks 9P2000.L, the Linux dialect, over a Transport that
+// carries one request and its reply at a time; the virtio-9p driver is one.
+//
+// Every operation walks a new fid from the root fid to the path it needs and
+// clunks it afterwards, so nothing is held open between calls and files
+// changed on the host are seen straight away. Reads and writes go to the host
+// as they happen (the filesystem is unbuffered). Names starting with '.' are
+// reported hidden, and files without write permission read-only.
+
+use super::{split_path, FileInfo, FileSystem, Usage, ATTR_HIDDEN, ATTR_READ_ONLY};
+use crate::system::block::BlockDevice;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+/// Carries 9P messages to a server and back
+pub trait Transport {
+    /// Send `request` and receive the reply into `reply`, returning its length
+    fn transact(&mut self, request: &[u8], reply: &mut [u8]) -> Result<usize, &'static str>;
+
+    /// Largest message the transport can carry either way
+    fn max_message(&self) -> usize;
+}
+
+// Message types (T = request, R = reply)
+const RLERROR: u8 = 7;
+const TSTATFS: u8 = 8;
+const TLOPEN: u8 = 12;
+const TLCREATE: u8 = 14;
+const TGETATTR: u8 = 24;
+const TSETATTR: u8 = 26;
+const TREADDIR: u8 = 40;
+const TMKDIR: u8 = 72;
+const TRENAMEAT: u8 = 74;
+const TVERSION: u8 = 100;
+const TATTACH: u8 = 104;
+const TWALK: u8 = 110;
+const TREAD: u8 = 116;
+const TWRITE: u8 = 118;
+const TCLUNK: u8 = 120;
+const TREMOVE: u8 = 122;
+
+const VERSION: &str = "9P2000.L";
+const NOTAG: u16 = 0xffff;
+const NOFID: u32 = 0xffff_ffff;
+/// Only one request is ever outstanding, so one tag does
+const TAG: u16 = 1;
+const ROOT_FID: u32 = 0;
+/// Most names one Twalk may carry
+const MAX_WALK: usize = 16;
+/// Largest message asked for when connecting
+const MAX_MSIZE: usize = 64 * 1024;
+/// Bytes of a Tread/Twrite/Rread around the data
+const IO_HEADER: usize = 24;
+
+// Linux open flags and modes, as 9P2000.L uses them
+const O_RDONLY: u32 = 0;
+const O_WRONLY: u32 = 1;
+const O_CREAT: u32 = 0o100;
+const O_EXCL: u32 = 0o200;
+const O_TRUNC: u32 = 0o1000;
+const FILE_MODE: u32 = 0o644;
+const DIR_MODE: u32 = 0o755;
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+
+/// Tgetattr request mask: the basic fields and the birth time
+const GETATTR_ALL: u64 = 0x3fff;
+const GETATTR_BTIME: u64 = 0x800;
+// Tsetattr valid bits
+const SETATTR_MODE: u32 = 0x1;
+const SETATTR_SIZE: u32 = 0x8;
+
+/// File attributes from Rgetattr
+#[derive(Clone, Copy, Debug)]
+pub struct Attr {
+    pub mode: u32,
+    pub size: u64,
+    pub atime: i64,
+    pub mtime: i64,
+    /// Creation time, 0 if the server doesn't know it
+    pub btime: i64,
+}
+
+impl Attr {
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+}
+
+/// Message text for a Linux errno from Rlerror
+fn errno_message(errno: u32) -> &'static str {
+    match errno {
+        1 | 13 => "Permission denied",
+        2 => "File not found",
+        17 => "File already exists",
+        20 => "Not a directory",
+        21 => "Is a directory",
+        27 => "File too large",
+        28 => "Disk full",
+        30 => "Read-only filesystem",
+        36 => "Filename too long (max 255 bytes)",
+        39 => "Directory not empty",
+        _ => "I/O error on the host",
+    }
+}
+
+/// A T-message being built
+struct Message(Vec<u8>);
+
+impl Message {
+    fn new(kind: u8, tag: u16) -> Self {
+        let mut bytes = vec![0u8; 4];
+        bytes.push(kind);
+        bytes.extend_from_slice(&tag.to_le_bytes());
+        Message(bytes)
+    }
+
+    fn u16(mut self, value: u16) -> Self {
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn u32(mut self, value: u32) -> Self {
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn u64(mut self, value: u64) -> Self {
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn str(self, value: &str) -> Self {
+        let mut message = self.u16(value.len() as u16);
+        message.0.extend_from_slice(value.as_bytes());
+        message
+    }
+
+    fn data(self, data: &[u8]) -> Self {
+        let mut message = self.u32(data.len() as u32);
+        message.0.extend_from_slice(data);
+        message
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let size = self.0.len() as u32;
+        self.0[..4].copy_from_slice(&size.to_le_bytes());
+        self.0
+    }
+}
+
+/// Reads the fields of an R-message in order
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], &'static str> {
+        let bytes = self.data.get(self.pos..self.pos + count).ok_or("Malformed 9P reply")?;
+        self.pos += count;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, &'static str> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, &'static str> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, &'static str> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, &'static str> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn str(&mut self) -> Result<String, &'static str> {
+        let len = self.u16()? as usize;
+        Ok(String::from(core::str::from_utf8(self.bytes(len)?).map_err(|_| "Bad filename from host")?))
+    }
+
+    /// A qid, which only matters here for telling directories apart
+    fn qid(&mut self) -> Result<(), &'static str> {
+        self.bytes(13).map(|_| ())
+    }
+}
+
+/// A connection to a 9P2000.L server, attached to the root of its tree
+pub struct Client {
+    transport: Box<dyn Transport>,
+    msize: usize,
+    reply: Vec<u8>,
+    next_fid: u32,
+    free_fids: Vec<u32>,
+}
+
+impl Client {
+    /// Agree on a version and message size and attach to the server's tree
+    pub fn connect(mut transport: Box<dyn Transport>) -> Result<Self, &'static str> {
+        let msize = core::cmp::min(transport.max_message(), MAX_MSIZE);
+        let mut reply = vec![0u8; msize];
+        let request = Message::new(TVERSION, NOTAG).u32(msize as u32).str(VERSION).finish();
+        let len = transport.transact(&request, &mut reply)?;
+        let mut reader = Reader { data: &reply[..len], pos: 7 };
+        if reply.get(4) != Some(&(TVERSION + 1)) {
+            return Err("9P version negotiation failed");
+        }
+        let server_msize = reader.u32()? as usize;
+        if reader.str()? != VERSION {
+            return Err("Server doesn't speak 9P2000.L");
+        }
+        let msize = core::cmp::min(msize, server_msize);
+        if msize < 4096 {
+            return Err("9P message size too small");
+        }
+
+        let mut client = Client { transport, msize, reply, next_fid: ROOT_FID + 1, free_fids: Vec::new() };
+        let request = Message::new(TATTACH, TAG).u32(ROOT_FID).u32(NOFID).str("root").str("").u32(0);
+        client.rpc(request, TATTACH)?.qid()?;
+        Ok(client)
+    }
+
+    /// Send a request and check the reply is the one expected, returning a
+    /// reader positioned after the header
+    fn rpc(&mut self, request: Message, kind: u8) -> Result<Reader<'_>, &'static str> {
+        let len = self.transport.transact(&request.finish(), &mut self.reply)?;
+        let mut reader = Reader { data: &self.reply[..len], pos: 4 };
+        let reply_kind = reader.u8()?;
+        reader.u16()?;
+        if reply_kind == RLERROR {
+            return Err(errno_message(reader.u32()?));
+        }
+        if reply_kind != kind + 1 {
+            return Err("Unexpected 9P reply");
+        }
+        Ok(reader)
+    }
+
+    fn allocate_fid(&mut self) -> u32 {
+        self.free_fids.pop().unwrap_or_else(|| {
+            self.next_fid += 1;
+            self.next_fid - 1
+        })
+    }
+
+    /// Walk from `fid` through `names` to a new fid
+    pub fn walk(&mut self, fid: u32, names: &[&str]) -> Result<u32, &'static str> {
+        let new_fid = self.allocate_fid();
+        let mut from = fid;
+        // A Twalk takes at most MAX_WALK names; longer paths continue from the
+        // new fid. The first walk always happens, to clone the fid for "".
+        let mut chunks = names.chunks(MAX_WALK);
+        let mut chunk = chunks.next().unwrap_or(&[]);
+        loop {
+            let mut request = Message::new(TWALK, TAG).u32(from).u32(new_fid).u16(chunk.len() as u16);
+            for name in chunk {
+                request = request.str(name);
+            }
+            let walked = match self.rpc(request, TWALK) {
+                Ok(mut reader) => reader.u16().map(|count| count as usize == chunk.len()),
+                Err(e) => Err(e),
+            };
+            match walked {
+                Ok(true) => {}
+                // Only some names walked: the next one doesn't exist
+                result => {
+                    if from == new_fid {
+                        self.clunk(new_fid);
+                    } else {
+                        self.free_fids.push(new_fid);
+                    }
+                    return Err(result.err().unwrap_or("File not found"));
+                }
+            }
+            from = new_fid;
+            chunk = match chunks.next() {
+                Some(chunk) => chunk,
+                None => return Ok(new_fid),
+            };
+        }
+    }
+
+    /// Walk from the root to `path` ("" for the root itself)
+    pub fn walk_path(&mut self, path: &str) -> Result<u32, &'static str> {
+        let names: Vec<&str> = path.split('/').filter(|name| !name.is_empty()).collect();
+        self.walk(ROOT_FID, &names)
+    }
+
+    /// Release a fid. The server forgets it even if this fails.
+    pub fn clunk(&mut self, fid: u32) {
+        let _ = self.rpc(Message::new(TCLUNK, TAG).u32(fid), TCLUNK);
+        self.free_fids.push(fid);
+    }
+
+    /// Run `f` on a fid walked to `path`, clunking it afterwards
+    fn with_path<R>(&mut self, path: &str, f: impl FnOnce(&mut Self, u32) -> Result<R, &'static str>) -> Result<R, &'static str> {
+        let fid = self.walk_path(path)?;
+        let result = f(self, fid);
+        self.clunk(fid);
+        result
+    }
+
+    /// Open a walked fid for I/O with Linux `flags`
+    pub fn open(&mut self, fid: u32, flags: u32) -> Result<(), &'static str> {
+        self.rpc(Message::new(TLOPEN, TAG).u32(fid).u32(flags), TLOPEN)?.qid()
+    }
+
+    /// Create and open file `name` in directory `fid`, which then refers to it
+    pub fn create(&mut self, fid: u32, name: &str, flags: u32, mode: u32) -> Result<(), &'static str> {
+        let request = Message::new(TLCREATE, TAG).u32(fid).str(name).u32(flags).u32(mode).u32(0);
+        self.rpc(request, TLCREATE)?.qid()
+    }
+
+    pub fn mkdir(&mut self, dir_fid: u32, name: &str, mode: u32) -> Result<(), &'static str> {
+        let request = Message::new(TMKDIR, TAG).u32(dir_fid).str(name).u32(mode).u32(0);
+        self.rpc(request, TMKDIR)?.qid()
+    }
+
+    /// Delete the file or empty directory `fid` refers to, releasing the fid
+    pub fn remove(&mut self, fid: u32) -> Result<(), &'static str> {
+        let result = self.rpc(Message::new(TREMOVE, TAG).u32(fid), TREMOVE).map(|_| ());
+        self.free_fids.push(fid);
+        result
+    }
+
+    pub fn rename(&mut self, old_dir: u32, old_name: &str, new_dir: u32, new_name: &str) -> Result<(), &'static str> {
+        let request = Message::new(TRENAMEAT, TAG).u32(old_dir).str(old_name).u32(new_dir).str(new_name);
+        self.rpc(request, TRENAMEAT).map(|_| ())
+    }
+
+    /// Largest read or write one message can carry
+    fn io_size(&self) -> usize {
+        self.msize - IO_HEADER
+    }
+
+    /// Read from an open fid at `offset`, filling as much of `buffer` as the
+    /// file has. Returns the number of bytes read.
+    pub fn read(&mut self, fid: u32, offset: u64, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        let mut done = 0;
+        while done < buffer.len() {
+            let count = core::cmp::min(buffer.len() - done, self.io_size());
+            let request = Message::new(TREAD, TAG).u32(fid).u64(offset + done as u64).u32(count as u32);
+            let mut reader = self.rpc(request, TREAD)?;
+            let len = reader.u32()? as usize;
+            let data = reader.bytes(len)?;
+            if len == 0 || len > count {
+                break;
+            }
+            buffer[done..done + len].copy_from_slice(data);
+            done += len;
+        }
+        Ok(done)
+    }
+
+    /// Write all of `data` to an open fid at `offset`
+    pub fn write(&mut self, fid: u32, offset: u64, data: &[u8]) -> Result<(), &'static str> {
+        let mut done = 0;
+        while done < data.len() {
+            let count = core::cmp::min(data.len() - done, self.io_size());
+            let request = Message::new(TWRITE, TAG).u32(fid).u64(offset + done as u64).data(&data[done..done + count]);
+            let written = self.rpc(request, TWRITE)?.u32()? as usize;
+            if written == 0 {
+                return Err("Host accepted no data");
+            }
+            done += core::cmp::min(written, count);
+        }
+        Ok(())
+    }
+
+    /// Names in an open directory, without "." and ".."
+    pub fn read_dir(&mut self, fid: u32) -> Result<Vec<String>, &'static str> {
+        let mut names = Vec::new();
+        let mut offset = 0;
+        loop {
+            let count = self.io_size() as u32;
+            let mut reader = self.rpc(Message::new(TREADDIR, TAG).u32(fid).u64(offset).u32(count), TREADDIR)?;
+            let len = reader.u32()? as usize;
+            if len == 0 {
+                return Ok(names);
+            }
+            let mut entries = Reader { data: reader.bytes(len)?, pos: 0 };
+            while entries.pos < len {
+                entries.qid()?;
+                offset = entries.u64()?;
+                entries.u8()?;
+                let name = entries.str()?;
+                if name != "." && name != ".." {
+                    names.push(name);
+                }
+            }
+        }
+    }
+
+    pub fn getattr(&mut self, fid: u32) -> Result<Attr, &'static str> {
+        let mut reader = self.rpc(Message::new(TGETATTR, TAG).u32(fid).u64(GETATTR_ALL), TGETATTR)?;
+        let valid = reader.u64()?;
+        reader.qid()?;
+        let mode = reader.u32()?;
+        reader.bytes(4 + 4 + 8 + 8)?; // uid, gid, nlink, rdev
+        let size = reader.u64()?;
+        reader.bytes(8 + 8)?; // blksize, blocks
+        let mut time = || -> Result<i64, &'static str> {
+            let secs = reader.u64()? as i64;
+            reader.u64()?;
+            Ok(secs)
+        };
+        let atime = time()?;
+        let mtime = time()?;
+        time()?; // ctime
+        let btime = time()?;
+        let btime = if valid & GETATTR_BTIME != 0 { btime } else { 0 };
+        Ok(Attr { mode, size, atime, mtime, btime })
+    }
+
+    fn setattr(&mut self, fid: u32, valid: u32, mode: u32, size: u64) -> Result<(), &'static str> {
+        let request = Message::new(TSETATTR, TAG)
+            .u32(fid).u32(valid).u32(mode).u32(0).u32(0).u64(size)
+            .u64(0).u64(0).u64(0).u64(0);
+        self.rpc(request, TSETATTR).map(|_| ())
+    }
+
+    /// Size and free space of the filesystem holding `fid`
+    pub fn statfs(&mut self, fid: u32) -> Result<Usage, &'static str> {
+        let mut reader = self.rpc(Message::new(TSTATFS, TAG).u32(fid), TSTATFS)?;
+        reader.u32()?; // type
+        let block_size = reader.u32()? as u64;
+        let blocks = reader.u64()?;
+        reader.u64()?; // free, including blocks reserved for root
+        let available = reader.u64()?;
+        Ok(Usage { total_bytes: blocks * block_size, free_bytes: available * block_size, ..Usage::default() })
+    }
+}
+
+/// A host folder reached over 9P
+pub struct NinePFs {
+    client: RefCell<Client>,
+}
+
+impl NinePFs {
+    pub fn mount(transport: Box<dyn Transport>) -> Result<Self, &'static str> {
+        Ok(NinePFs { client: RefCell::new(Client::connect(transport)?) })
+    }
+
+    fn file_info(name: &str, attr: &Attr) -> FileInfo {
+        let size = core::cmp::min(attr.size, u32::MAX as u64) as u32;
+        let mut info = FileInfo::new(String::from(name), size, attr.is_dir());
+        info.created = attr.btime;
+        info.modified = attr.mtime;
+        info.accessed = attr.atime;
+        if name.starts_with('.') {
+            info.attributes |= ATTR_HIDDEN;
+        }
+        if attr.mode & 0o200 == 0 {
+            info.attributes |= ATTR_READ_ONLY;
+        }
+        info
+    }
+}
+
+impl FileSystem for NinePFs {
+    fn fs_type(&self) -> &'static str {
+        "9p"
+    }
+
+    fn list_dir(&self, _device: &mut dyn BlockDevice, path: &str) -> Result<Vec<FileInfo>, &'static str> {
+        self.client.borrow_mut().with_path(path, |client, dir| {
+            // An open fid can't be walked from, so list through a copy
+            let listing = client.walk(dir, &[])?;
+            let names = client.open(listing, O_RDONLY).and_then(|()| client.read_dir(listing));
+            client.clunk(listing);
+
+            let mut files = Vec::new();
+            for name in names? {
+                let fid = match client.walk(dir, &[&name]) {
+                    Ok(fid) => fid,
+                    // Deleted on the host since the listing
+                    Err(_) => continue,
+                };
+                let attr = client.getattr(fid);
+                client.clunk(fid);
+                files.push(Self::file_info(&name, &attr?));
+            }
+            Ok(files)
+        })
+    }
+
+    fn stat(&self, _device: &mut dyn BlockDevice, path: &str) -> Result<FileInfo, &'static str> {
+        let attr = self.client.borrow_mut().with_path(path, |client, fid| client.getattr(fid))?;
+        Ok(Self::file_info(split_path(path).1, &attr))
+    }
+
+    fn read_file(&self, device: &mut dyn BlockDevice, path: &str, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        self.read_at(device, path, 0, buffer)
+    }
+
+    fn read_at(&self, _device: &mut dyn BlockDevice, path: &str, offset: u64, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        self.client.borrow_mut().with_path(path, |client, fid| {
+            client.open(fid, O_RDONLY)?;
+            client.read(fid, offset, buffer)
+        })
+    }
+
+    fn write_at(&mut self, _device: &mut dyn BlockDevice, path: &str, offset: u64, data: &[u8]) -> Result<usize, &'static str> {
+        self.client.get_mut().with_path(path, |client, fid| {
+            client.open(fid, O_WRONLY)?;
+            client.write(fid, offset, data)
+        })?;
+        Ok(data.len())
+    }
+
+    fn unbuffered(&self) -> bool {
+        true
+    }
+
+    fn create_file(&mut self, _device: &mut dyn BlockDevice, path: &str, size: u32) -> Result<(), &'static str> {
+        let (parent, name) = split_path(path);
+        self.client.get_mut().with_path(parent, |client, fid| {
+            client.create(fid, name, O_WRONLY | O_CREAT | O_EXCL, FILE_MODE)?;
+            if size > 0 {
+                client.setattr(fid, SETATTR_SIZE, 0, size as u64)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn write_file(&mut self, _device: &mut dyn BlockDevice, path: &str, data: &[u8]) -> Result<(), &'static str> {
+        self.client.get_mut().with_path(path, |client, fid| {
+            client.open(fid, O_WRONLY | O_TRUNC)?;
+            client.write(fid, 0, data)
+        })
+    }
+
+    fn delete_file(&mut self, _device: &mut dyn BlockDevice, path: &str) -> Result<(), &'static str> {
+        if split_path(path).1.is_empty() {
+            return Err("Can't delete the root directory");
+        }
+        let client = self.client.get_mut();
+        let fid = client.walk_path(path)?;
+        client.remove(fid)
+    }
+
+    fn rename_file(&mut self, _device: &mut dyn BlockDevice, old_path: &str, new_path: &str) -> Result<(), &'static str> {
+        let (old_parent, old_name) = split_path(old_path);
+        let (new_parent, new_name) = split_path(new_path);
+        if old_name.is_empty() {
+            return Err("Can't rename the root directory");
+        }
+        let client = self.client.get_mut();
+        // The host would replace an existing file; other filesystems refuse
+        if let Ok(fid) = client.walk_path(new_path) {
+            client.clunk(fid);
+            return Err("File with new name already exists");
+        }
+        client.with_path(old_parent, |client, old_dir| {
+            client.with_path(new_parent, |client, new_dir| client.rename(old_dir, old_name, new_dir, new_name))
+        })
+    }
+
+    fn create_dir(&mut self, _device: &mut dyn BlockDevice, path: &str) -> Result<(), &'static str> {
+        let (parent, name) = split_path(path);
+        self.client.get_mut().with_path(parent, |client, fid| client.mkdir(fid, name, DIR_MODE))
+    }
+
+    fn truncate(&mut self, _device: &mut dyn BlockDevice, path: &str, size: u32) -> Result<(), &'static str> {
+        self.client.get_mut().with_path(path, |client, fid| client.setattr(fid, SETATTR_SIZE, 0, size as u64))
+    }
+
+    /// Only read-only can be changed, as the owner's write permission.
+    /// Hidden follows the name.
+    fn set_attributes(&mut self, _device: &mut dyn BlockDevice, path: &str, attributes: u8) -> Result<(), &'static str> {
+        let hidden = split_path(path).1.starts_with('.');
+        if attributes & !(ATTR_READ_ONLY | ATTR_HIDDEN) != 0 || (attributes & ATTR_HIDDEN != 0) != hidden {
+            return Err("Only the read-only attribute can be changed on a host folder");
+        }
+        self.client.get_mut().with_path(path, |client, fid| {
+            let mode = client.getattr(fid)?.mode & 0o7777;
+            let mode = if attributes & ATTR_READ_ONLY != 0 { mode & !0o222 } else { mode | 0o200 };
+            client.setattr(fid, SETATTR_MODE, mode, 0)
+        })
+    }
+
+    fn usage(&self, _device: &mut dyn BlockDevice) -> Result<Usage, &'static str> {
+        self.client.borrow_mut().statfs(ROOT_FID)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::collections::BTreeMap;
+    use alloc::string::ToString;
+
+    /// A minimal in-memory 9P2000.L server: just enough of the protocol
+    /// for the client, with a small message size so I/O is split up
+    struct TestServer {
+        /// Path to contents, None for directories
+        tree: BTreeMap<String, Option<Vec<u8>>>,
+        fids: BTreeMap<u32, String>,
+    }
+
+    impl TestServer {
+        fn reply(kind: u8) -> Message {
+            Message::new(kind + 1, TAG)
+        }
+
+        fn error(errno: u32) -> Message {
+            Message::new(RLERROR, TAG).u32(errno)
+        }
+
+        fn child(dir: &str, name: &str) -> String {
+            if dir.is_empty() { name.to_string() } else { alloc::format!("{}/{}", dir, name) }
+        }
+
+        fn handle(&mut self, request: &[u8]) -> Message {
+            let mut r = Reader { data: request, pos: 4 };
+            let kind = r.u8().unwrap();
+            r.u16().unwrap();
+            let qid = |m: Message| m.u8(0).u32(0).u64(0);
+            match kind {
+                TVERSION => Message::new(TVERSION + 1, NOTAG).u32(4096).str(VERSION),
+                TATTACH => {
+                    self.fids.insert(r.u32().unwrap(), String::new());
+                    qid(Self::reply(kind))
+                }
+                TWALK => {
+                    let (fid, new_fid) = (r.u32().unwrap(), r.u32().unwrap());
+                    let mut path = self.fids[&fid].clone();
+                    let count = r.u16().unwrap();
+                    let mut walked = 0;
+                    for _ in 0..count {
+                        let next = Self::child(&path, &r.str().unwrap());
+                        if !self.tree.contains_key(&next) {
+                            break;
+                        }
+                        path = next;
+                        walked += 1;
+                    }
+                    if walked == 0 && count > 0 {
+                        return Self::error(2);
+                    }
+                    if walked == count {
+                        self.fids.insert(new_fid, path);
+                    }
+                    let mut reply = Self::reply(kind).u16(walked);
+                    for _ in 0..walked {
+                        reply = qid(reply);
+                    }
+                    reply
+                }
+                TCLUNK | TREMOVE => {
+                    let path = self.fids.remove(&r.u32().unwrap()).unwrap();
+                    if kind == TREMOVE {
+                        self.tree.remove(&path);
+                    }
+                    Self::reply(kind)
+                }
+                TLOPEN => {
+                    let path = &self.fids[&r.u32().unwrap()];
+                    if r.u32().unwrap() & O_TRUNC != 0 {
+                        self.tree.insert(path.clone(), Some(Vec::new()));
+                    }
+                    qid(Self::reply(kind)).u32(0)
+                }
+                TLCREATE => {
+                    let fid = r.u32().unwrap();
+                    let path = Self::child(&self.fids[&fid], &r.str().unwrap());
+                    if self.tree.contains_key(&path) {
+                        return Self::error(17);
+                    }
+                    self.tree.insert(path.clone(), Some(Vec::new()));
+                    self.fids.insert(fid, path);
+                    qid(Self::reply(kind)).u32(0)
+                }
+                TMKDIR => {
+                    let path = Self::child(&self.fids[&r.u32().unwrap()], &r.str().unwrap());
+                    self.tree.insert(path, None);
+                    qid(Self::reply(kind))
+                }
+                TREAD => {
+                    let data = self.tree[&self.fids[&r.u32().unwrap()]].clone().unwrap();
+                    let offset = (r.u64().unwrap() as usize).min(data.len());
+                    let count = (r.u32().unwrap() as usize).min(data.len() - offset);
+                    Self::reply(kind).data(&data[offset..offset + count])
+                }
+                TWRITE => {
+                    let path = self.fids[&r.u32().unwrap()].clone();
+                    let offset = r.u64().unwrap() as usize;
+                    let count = r.u32().unwrap() as usize;
+                    let data = self.tree.get_mut(&path).unwrap().as_mut().unwrap();
+                    let end = offset + count;
+                    if data.len() < end {
+                        data.resize(end, 0);
+                    }
+                    data[offset..end].copy_from_slice(r.bytes(count).unwrap());
+                    Self::reply(kind).u32(count as u32)
+                }
+                TREADDIR => {
+                    let dir = self.fids[&r.u32().unwrap()].clone();
+                    let offset = r.u64().unwrap() as usize;
+                    let names: Vec<String> = [".".to_string(), "..".to_string()]
+                        .into_iter()
+                        .chain(self.tree.keys().filter(|path| split_path(path).0 == dir && !path.is_empty()).map(|path| split_path(path).1.to_string()))
+                        .collect();
+                    // One entry per reply, to exercise continuing at an offset
+                    let mut entries = Message(Vec::new());
+                    if let Some(name) = names.get(offset) {
+                        entries = qid(entries).u64(offset as u64 + 1).u8(0).str(name);
+                    }
+                    Self::reply(kind).data(&entries.0)
+                }
+                TGETATTR => {
+                    let node = &self.tree[&self.fids[&r.u32().unwrap()]];
+                    let (mode, size) = match node {
+                        Some(data) => (0o100644, data.len() as u64),
+                        None => (S_IFDIR | 0o755, 0),
+                    };
+                    let reply = qid(Self::reply(kind).u64(GETATTR_ALL)).u32(mode).u32(0).u32(0).u64(1).u64(0).u64(size).u64(4096).u64(0);
+                    // atime, mtime, ctime, btime, gen, data_version
+                    reply.u64(1).u64(0).u64(2).u64(0).u64(3).u64(0).u64(4).u64(0).u64(0).u64(0)
+                }
+                TSETATTR => {
+                    let path = self.fids[&r.u32().unwrap()].clone();
+                    if r.u32().unwrap() & SETATTR_SIZE != 0 {
+                        r.bytes(12).unwrap();
+                        let size = r.u64().unwrap() as usize;
+                        self.tree.get_mut(&path).unwrap().as_mut().unwrap().resize(size, 0);
+                    }
+                    Self::reply(kind)
+                }
+                _ => Self::error(95),
+            }
+        }
+    }
+
+    impl Message {
+        fn u8(mut self, value: u8) -> Self {
+            self.0.push(value);
+            self
+        }
+    }
+
+    impl Transport for TestServer {
+        fn transact(&mut self, request: &[u8], reply: &mut [u8]) -> Result<usize, &'static str> {
+            let message = self.handle(request).finish();
+            reply[..message.len()].copy_from_slice(&message);
+            Ok(message.len())
+        }
+
+        fn max_message(&self) -> usize {
+            MAX_MSIZE
+        }
+    }
+
+    #[test]
+    fn test_host_folder() {
+        let mut tree = BTreeMap::new();
+        tree.insert(String::new(), None);
+        tree.insert("docs".to_string(), None);
+        tree.insert("docs/readme.txt".to_string(), Some(b"hello from the host".to_vec()));
+        tree.insert(".hidden".to_string(), Some(Vec::new()));
+        let server = TestServer { tree, fids: BTreeMap::new() };
+        let mut fs = NinePFs::mount(Box::new(server)).unwrap();
+        let device = &mut crate::system::block::RamDisk::new(0);
+
+        let mut root: Vec<(String, bool, u8)> =
+            fs.list_dir(device, "").unwrap().into_iter().map(|f| (f.name, f.is_dir, f.attributes)).collect();
+        root.sort();
+        assert_eq!(root, [(".hidden".to_string(), false, ATTR_HIDDEN), ("docs".to_string(), true, 0)]);
+
+        let info = fs.stat(device, "docs/readme.txt").unwrap();
+        assert_eq!((info.size, info.modified, info.created), (19, 2, 4));
+        let mut buffer = [0u8; 32];
+        assert_eq!(fs.read_at(device, "docs/readme.txt", 11, &mut buffer).unwrap(), 8);
+        assert_eq!(&buffer[..8], b"the host");
+        assert_eq!(fs.stat(device, "docs/missing").err(), Some("File not found"));
+
+        // Bigger than one message, so reads and writes are split
+        let data: Vec<u8> = (0..10_000).map(|i| (i % 253) as u8).collect();
+        fs.create_file(device, "docs/big.bin", 0).unwrap();
+        assert_eq!(fs.create_file(device, "docs/big.bin", 0).err(), Some("File already exists"));
+        fs.write_file(device, "docs/big.bin", &data).unwrap();
+        let mut buffer = vec![0u8; 12_000];
+        assert_eq!(fs.read_file(device, "docs/big.bin", &mut buffer).unwrap(), data.len());
+        assert_eq!(&buffer[..data.len()], &data[..]);
+        fs.write_at(device, "docs/big.bin", 9_998, b"end").unwrap();
+        fs.truncate(device, "docs/big.bin", 5).unwrap();
+        assert_eq!(fs.stat(device, "docs/big.bin").unwrap().size, 5);
+
+        fs.create_dir(device, "docs/sub").unwrap();
+        fs.delete_file(device, "docs/readme.txt").unwrap();
+        let mut names: Vec<String> = fs.list_dir(device, "docs").unwrap().into_iter().map(|f| f.name).collect();
+        names.sort();
+        assert_eq!(names, ["big.bin", "sub"]);
+        // Every fid walked was clunked again
+        assert_eq!(fs.client.borrow().free_fids.len() as u32, fs.client.borrow().next_fid - 1);
+    }
+}
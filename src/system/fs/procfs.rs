@@ -0,0 +1,188 @@
+// procfs - kernel state as read-only text files
+//
+// Each file is generated from live kernel data when it is read, so `cat
+// /proc/threads` always shows the current thread list. Sizes are those of the
+// text as it would be generated now. Handles take a snapshot when opened.
+
+use super::{FileInfo, FileSystem, ATTR_READ_ONLY};
+use crate::system::block::BlockDevice;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+/// File name and the function generating its contents
+const FILES: &[(&str, fn() -> Vec<u8>)] = &[
+    ("threads", threads),
+    ("memmap", memmap),
+    ("pci", pci),
+    ("net", net),
+    ("log", log),
+    ("mounts", mounts),
+    ("uptime", uptime),
+];
+
+pub struct ProcFs;
+
+impl ProcFs {
+    fn generator(path: &str) -> Result<fn() -> Vec<u8>, &'static str> {
+        FILES.iter().find(|(name, _)| *name == path).map(|(_, generate)| *generate).ok_or("File not found")
+    }
+
+    fn info(name: &str, size: usize) -> FileInfo {
+        let now = crate::kernel::clock::realtime_secs();
+        FileInfo {
+            created: now,
+            modified: now,
+            accessed: now,
+            attributes: ATTR_READ_ONLY,
+            ..FileInfo::new(String::from(name), size as u32, false)
+        }
+    }
+}
+
+impl FileSystem for ProcFs {
+    fn fs_type(&self) -> &'static str {
+        "procfs"
+    }
+
+    fn list_dir(&self, _device: &mut dyn BlockDevice, path: &str) -> Result<Vec<FileInfo>, &'static str> {
+        if !path.is_empty() {
+            return Err(if Self::generator(path).is_ok() { "Not a directory" } else { "File not found" });
+        }
+        Ok(FILES.iter().map(|(name, generate)| Self::info(name, generate().len())).collect())
+    }
+
+    fn stat(&self, _device: &mut dyn BlockDevice, path: &str) -> Result<FileInfo, &'static str> {
+        if path.is_empty() {
+            return Ok(FileInfo { attributes: ATTR_READ_ONLY, ..FileInfo::new(String::new(), 0, true) });
+        }
+        Ok(Self::info(path, Self::generator(path)?().len()))
+    }
+
+    fn read_file(&self, _device: &mut dyn BlockDevice, path: &str, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        let data = Self::generator(path)?();
+        // The text may have grown since the caller sized the buffer
+        let count = core::cmp::min(buffer.len(), data.len());
+        buffer[..count].copy_from_slice(&data[..count]);
+        Ok(count)
+    }
+
+    fn create_file(&mut self, _device: &mut dyn BlockDevice, _path: &str, _size: u32) -> Result<(), &'static str> {
+        Err("Read-only filesystem")
+    }
+
+    fn write_file(&mut self, _device: &mut dyn BlockDevice, _path: &str, _data: &[u8]) -> Result<(), &'static str> {
+        Err("Read-only filesystem")
+    }
+
+    fn delete_file(&mut self, _device: &mut dyn BlockDevice, _path: &str) -> Result<(), &'static str> {
+        Err("Read-only filesystem")
+    }
+
+    fn rename_file(&mut self, _device: &mut dyn BlockDevice, _old_path: &str, _new_path: &str) -> Result<(), &'static str> {
+        Err("Read-only filesystem")
+    }
+}
+
+fn threads() -> Vec<u8> {
+    use crate::kernel::thread::ThreadState;
+
+    let mut text = String::from("ID    STATE       STACK\n");
+    // The timer interrupt takes this lock to preempt, so never wait for it here
+    let scheduler = match crate::kernel::scheduler::SCHEDULER.try_lock() {
+        Some(scheduler) => scheduler,
+        None => return Vec::from("(scheduler busy, try again)\n"),
+    };
+    for thread in &scheduler.threads {
+        let state = match thread.state {
+            _ if scheduler.current_thread == Some(thread.id) => "running",
+            ThreadState::Ready | ThreadState::Running => "ready",
+            ThreadState::Blocked => "blocked",
+            ThreadState::Terminated => "terminated",
+        };
+        let _ = writeln!(text, "{:<5} {:<11} {} KB", thread.id, state, thread.stack.len() / 1024);
+    }
+    text.into_bytes()
+}
+
+fn memmap() -> Vec<u8> {
+    use crate::kernel::memory::{self, MemoryType};
+
+    let mut text = String::new();
+    let (heap_start, heap_size, heap_used) = crate::heap_usage();
+    let _ = writeln!(
+        text,
+        "heap      {:#012x}-{:#012x}  {} KB used of {} KB",
+        heap_start, heap_start + heap_size, heap_used / 1024, heap_size / 1024
+    );
+    let (next_free, end) = memory::free_physical_range();
+    let _ = writeln!(text, "pages     {:#012x}-{:#012x}  {} KB free", next_free, end, end.saturating_sub(next_free) / 1024);
+
+    let map = memory::memory_map();
+    if !map.is_empty() {
+        text.push_str("\nSTART          END            TYPE\n");
+    }
+    for region in map {
+        let end = region.physical_start + region.number_of_pages * 4096;
+        let _ = writeln!(text, "{:#012x}-{:#012x}  {}", region.physical_start, end, MemoryType::name(region.typ));
+    }
+    text.into_bytes()
+}
+
+fn pci() -> Vec<u8> {
+    let mut text = String::from("SLOT     VENDOR:DEVICE  CLASS     BAR0\n");
+    for device in crate::kernel::drivers::pci::scan_pci_bus() {
+        let info = &device.device_info;
+        let _ = writeln!(
+            text,
+            "{:02x}:{:02x}.{}  {:04x}:{:04x}      {:02x}.{:02x}.{:02x}  {:#010x}",
+            device.bus, device.device, device.function, device.vendor_id, device.device_id,
+            info.class_code, info.subclass, info.prog_if, device.bar0
+        );
+    }
+    text.into_bytes()
+}
+
+fn net() -> Vec<u8> {
+    use crate::system::net::network::{format_ip, format_mac};
+
+    let mut text = String::from("NAME  MAC                IP               GATEWAY\n");
+    unsafe {
+        // The first device drives the network stack; any others are idle
+        let mut index = 0;
+        if let Some(ref stack) = crate::kernel::NETWORK_STACK {
+            let ip = stack.ip_address().map_or(String::from("-"), format_ip);
+            let _ = writeln!(
+                text,
+                "eth0  {}  {:<15}  {}",
+                format_mac(stack.mac_address()), ip, format_ip(crate::kernel::GATEWAY_IP)
+            );
+            index = 1;
+        }
+        if let Some(ref devices) = crate::kernel::NET_DEVICES {
+            for device in devices {
+                let _ = writeln!(text, "eth{}  {}  {:<15}  -", index, format_mac(device.mac_address()), "-");
+                index += 1;
+            }
+        }
+    }
+    text.into_bytes()
+}
+
+fn log() -> Vec<u8> {
+    crate::kernel::log::contents()
+}
+
+fn mounts() -> Vec<u8> {
+    let mut text = String::new();
+    for mount in super::vfs::mounts() {
+        let _ = writeln!(text, "/{:<8} {:<8} {}", mount.name, mount.source(), mount.fs_type());
+    }
+    text.into_bytes()
+}
+
+fn uptime() -> Vec<u8> {
+    use crate::kernel::clock::{clock_gettime, ClockId};
+    let time = clock_gettime(ClockId::Monotonic);
+    alloc::format!("{}.{:02}\n", time.sec, time.nsec / 10_000_000).into_bytes()
+}
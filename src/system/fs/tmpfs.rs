@@ -0,0 +1,326 @@
+// tmpfs - files kept in memory
+//
+// Scratch space that never touches a disk and is gone after a reboot. Nodes
+// live in one map keyed by their path, so a directory's entries are the keys
+// that start with its path and a '/'. File data counts against a fixed
+// capacity so a runaway program fills the tmpfs instead of the kernel heap.
+
+use super::{mime, split_path, FileInfo, FileSystem, Usage, ATTR_MASK, ATTR_READ_ONLY};
+use crate::system::block::BlockDevice;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+struct Node {
+    is_dir: bool,
+    data: Vec<u8>,
+    created: i64,
+    modified: i64,
+    accessed: i64,
+    attributes: u8,
+    file_type: u8,
+}
+
+impl Node {
+    fn new(is_dir: bool, name: &str) -> Self {
+        let time = now();
+        Node {
+            is_dir,
+            data: Vec::new(),
+            created: time,
+            modified: time,
+            accessed: time,
+            attributes: 0,
+            file_type: if is_dir { mime::DIRECTORY } else { mime::from_name(name) },
+        }
+    }
+
+    fn info(&self, name: &str) -> FileInfo {
+        FileInfo {
+            name: String::from(name),
+            size: self.data.len() as u32,
+            is_dir: self.is_dir,
+            created: self.created,
+            modified: self.modified,
+            accessed: self.accessed,
+            attributes: self.attributes,
+            file_type: self.file_type,
+        }
+    }
+}
+
+pub struct TmpFs {
+    nodes: BTreeMap<String, Node>,
+    /// Bytes of file data allowed, and in use
+    capacity: usize,
+    used: usize,
+}
+
+/// Current time for node timestamps
+#[cfg(not(test))]
+fn now() -> i64 {
+    crate::kernel::clock::realtime_secs()
+}
+
+#[cfg(test)]
+fn now() -> i64 {
+    1_700_000_000
+}
+
+impl TmpFs {
+    /// An empty tmpfs holding at most `capacity` bytes of file data
+    pub fn new(capacity: usize) -> Self {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(String::new(), Node::new(true, ""));
+        TmpFs { nodes, capacity, used: 0 }
+    }
+
+    fn node(&self, path: &str) -> Result<&Node, &'static str> {
+        self.nodes.get(path).ok_or("File not found")
+    }
+
+    fn file_mut(&mut self, path: &str) -> Result<&mut Node, &'static str> {
+        match self.nodes.get_mut(path) {
+            Some(node) if node.is_dir => Err("Is a directory"),
+            Some(node) => Ok(node),
+            None => Err("File not found"),
+        }
+    }
+
+    /// Keys of everything below directory `path`
+    fn descendants(&self, path: &str) -> Vec<String> {
+        let prefix = child_prefix(path);
+        self.nodes
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Check that `path` is free and its parent is a directory
+    fn check_new(&self, path: &str) -> Result<(), &'static str> {
+        let (parent, name) = split_path(path);
+        if name.is_empty() || name == "." || name == ".." {
+            return Err("Invalid name");
+        }
+        match self.nodes.get(parent) {
+            Some(node) if node.is_dir => {}
+            Some(_) => return Err("Not a directory"),
+            None => return Err("Directory not found"),
+        }
+        if self.nodes.contains_key(path) {
+            return Err("File already exists");
+        }
+        Ok(())
+    }
+
+    /// Change a file's size from `old` to `new` bytes against the capacity
+    fn reserve(&mut self, old: usize, new: usize) -> Result<(), &'static str> {
+        let used = self.used - old + new;
+        if new > old && used > self.capacity {
+            return Err("No space left on tmpfs");
+        }
+        self.used = used;
+        Ok(())
+    }
+
+    fn check_writable(node: &Node) -> Result<(), &'static str> {
+        if node.attributes & ATTR_READ_ONLY != 0 {
+            return Err("File is read-only");
+        }
+        Ok(())
+    }
+}
+
+/// Prefix shared by the keys of a directory's entries
+fn child_prefix(path: &str) -> String {
+    if path.is_empty() {
+        String::new()
+    } else {
+        alloc::format!("{}/", path)
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn fs_type(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn list_dir(&self, _device: &mut dyn BlockDevice, path: &str) -> Result<Vec<FileInfo>, &'static str> {
+        if !self.node(path)?.is_dir {
+            return Err("Not a directory");
+        }
+        let prefix = child_prefix(path);
+        Ok(self
+            .nodes
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .filter(|(key, _)| !key.is_empty() && !key[prefix.len()..].contains('/'))
+            .map(|(key, node)| node.info(&key[prefix.len()..]))
+            .collect())
+    }
+
+    fn stat(&self, _device: &mut dyn BlockDevice, path: &str) -> Result<FileInfo, &'static str> {
+        Ok(self.node(path)?.info(split_path(path).1))
+    }
+
+    fn read_file(&self, _device: &mut dyn BlockDevice, path: &str, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        let node = self.node(path)?;
+        if node.is_dir {
+            return Err("Is a directory");
+        }
+        let count = core::cmp::min(buffer.len(), node.data.len());
+        buffer[..count].copy_from_slice(&node.data[..count]);
+        Ok(node.data.len())
+    }
+
+    fn create_file(&mut self, _device: &mut dyn BlockDevice, path: &str, size: u32) -> Result<(), &'static str> {
+        self.check_new(path)?;
+        self.reserve(0, size as usize)?;
+        let mut node = Node::new(false, split_path(path).1);
+        node.data.resize(size as usize, 0);
+        self.nodes.insert(String::from(path), node);
+        Ok(())
+    }
+
+    fn write_file(&mut self, _device: &mut dyn BlockDevice, path: &str, data: &[u8]) -> Result<(), &'static str> {
+        let node = self.file_mut(path)?;
+        Self::check_writable(node)?;
+        let old = node.data.len();
+        self.reserve(old, data.len())?;
+
+        let node = self.file_mut(path)?;
+        node.data = Vec::from(data);
+        node.modified = now();
+        node.file_type = mime::detect(split_path(path).1, data);
+        Ok(())
+    }
+
+    fn delete_file(&mut self, _device: &mut dyn BlockDevice, path: &str) -> Result<(), &'static str> {
+        if path.is_empty() {
+            return Err("Can't delete the root directory");
+        }
+        let node = self.node(path)?;
+        Self::check_writable(node)?;
+        if node.is_dir && !self.descendants(path).is_empty() {
+            return Err("Directory not empty");
+        }
+        if let Some(node) = self.nodes.remove(path) {
+            self.used -= node.data.len();
+        }
+        Ok(())
+    }
+
+    fn rename_file(&mut self, _device: &mut dyn BlockDevice, old_path: &str, new_path: &str) -> Result<(), &'static str> {
+        if old_path.is_empty() {
+            return Err("Can't rename the root directory");
+        }
+        self.node(old_path)?;
+        self.check_new(new_path)?;
+        if new_path.starts_with(&child_prefix(old_path)) {
+            return Err("Can't move a directory into itself");
+        }
+
+        // Move the node and everything under it
+        for key in self.descendants(old_path) {
+            if let Some(node) = self.nodes.remove(&key) {
+                let moved = alloc::format!("{}{}", new_path, &key[old_path.len()..]);
+                self.nodes.insert(moved, node);
+            }
+        }
+        if let Some(mut node) = self.nodes.remove(old_path) {
+            // Files keep a content-detected type, but not one that came from the old name
+            if !node.is_dir && node.file_type == mime::from_name(split_path(old_path).1) {
+                node.file_type = mime::detect(split_path(new_path).1, &node.data);
+            }
+            self.nodes.insert(String::from(new_path), node);
+        }
+        Ok(())
+    }
+
+    fn create_dir(&mut self, _device: &mut dyn BlockDevice, path: &str) -> Result<(), &'static str> {
+        self.check_new(path)?;
+        self.nodes.insert(String::from(path), Node::new(true, split_path(path).1));
+        Ok(())
+    }
+
+    fn truncate(&mut self, _device: &mut dyn BlockDevice, path: &str, size: u32) -> Result<(), &'static str> {
+        let node = self.file_mut(path)?;
+        Self::check_writable(node)?;
+        let old = node.data.len();
+        self.reserve(old, size as usize)?;
+
+        let node = self.file_mut(path)?;
+        node.data.resize(size as usize, 0);
+        node.modified = now();
+        Ok(())
+    }
+
+    fn set_attributes(&mut self, _device: &mut dyn BlockDevice, path: &str, attributes: u8) -> Result<(), &'static str> {
+        let node = self.nodes.get_mut(path).ok_or("File not found")?;
+        node.attributes = attributes & ATTR_MASK;
+        Ok(())
+    }
+
+    fn mark_accessed(&mut self, _device: &mut dyn BlockDevice, path: &str) -> Result<(), &'static str> {
+        let node = self.nodes.get_mut(path).ok_or("File not found")?;
+        node.accessed = now();
+        Ok(())
+    }
+
+    fn usage(&self, _device: &mut dyn BlockDevice) -> Result<Usage, &'static str> {
+        Ok(Usage {
+            total_bytes: self.capacity as u64,
+            free_bytes: (self.capacity - self.used) as u64,
+            ..Usage::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::block::RamDisk;
+
+    #[test]
+    fn test_tree_and_capacity() {
+        let mut device = RamDisk::new(0);
+        let mut fs = TmpFs::new(64);
+
+        fs.create_dir(&mut device, "docs").unwrap();
+        fs.create_file(&mut device, "docs/a.txt", 0).unwrap();
+        fs.write_file(&mut device, "docs/a.txt", b"hello").unwrap();
+        fs.create_dir(&mut device, "docs/sub").unwrap();
+        fs.create_file(&mut device, "docs/sub/b", 10).unwrap();
+        assert_eq!(fs.create_file(&mut device, "missing/c", 0), Err("Directory not found"));
+        assert_eq!(fs.create_file(&mut device, "docs/a.txt", 0), Err("File already exists"));
+
+        let names: Vec<String> = fs.list_dir(&mut device, "docs").unwrap().into_iter().map(|f| f.name).collect();
+        assert_eq!(names, ["a.txt", "sub"]);
+        assert_eq!(fs.list_dir(&mut device, "").unwrap().len(), 1);
+        let file = fs.stat(&mut device, "docs/a.txt").unwrap();
+        assert_eq!(mime::file_type(file.file_type).mime, "text/plain");
+
+        // Moving a directory carries its contents along
+        assert_eq!(fs.rename_file(&mut device, "docs", "docs/sub/x"), Err("Can't move a directory into itself"));
+        fs.rename_file(&mut device, "docs", "papers").unwrap();
+        let mut buffer = [0u8; 16];
+        assert_eq!(fs.read_file(&mut device, "papers/a.txt", &mut buffer).unwrap(), 5);
+        assert_eq!(&buffer[..5], b"hello");
+        assert_eq!(fs.stat(&mut device, "papers/sub/b").unwrap().size, 10);
+        assert!(fs.stat(&mut device, "docs/sub/b").is_err());
+
+        // 15 of 64 bytes used
+        assert_eq!(fs.usage(&mut device).unwrap().free_bytes, 49);
+        assert_eq!(fs.truncate(&mut device, "papers/sub/b", 60), Err("No space left on tmpfs"));
+        fs.truncate(&mut device, "papers/sub/b", 59).unwrap();
+        assert_eq!(fs.write_at(&mut device, "papers/a.txt", 3, b"p!").unwrap(), 2);
+        assert_eq!(fs.read_at(&mut device, "papers/a.txt", 1, &mut buffer).unwrap(), 4);
+        assert_eq!(&buffer[..4], b"elp!");
+
+        assert_eq!(fs.delete_file(&mut device, "papers/sub"), Err("Directory not empty"));
+        fs.delete_file(&mut device, "papers/sub/b").unwrap();
+        fs.delete_file(&mut device, "papers/sub").unwrap();
+        assert_eq!(fs.usage(&mut device).unwrap().free_bytes, 59);
+    }
+}
@@ -0,0 +1,784 @@
+// Virtual filesystem
+//
+// Every mounted volume appears as a top-level directory (/disk0, /disk1, ...),
+// so /disk0/docs/a.txt is "docs/a.txt" on the first volume mounted. There is
+// one mounted FileSystem per volume, shared by the shell, file explorer,
+// editor and everything else, so their views of a disk never disagree.
+//
+// Filesystems without a disk (tmpfs, procfs, devfs) are mounted under a fixed
+// name with mount_virtual() and get a stand-in device that holds no blocks.
+//
+// Files can also be opened as handles for read/write/seek. A handle holds the
+// file's contents in memory and writes them back when it is closed, unless
+// the filesystem is unbuffered, in which case every read and write goes
+// straight to it.
+//
+// A .tar, .tar.gz, .tgz or .zip file also works as a read-only directory in
+// paths, e.g. /disk0/site.zip/css/style.css, for listing and reading.
+
+use super::{ArchiveFs, FileInfo, FileSystem, Snapshot, Usage, ATTR_READ_ONLY};
+use crate::system::archive;
+use crate::system::block::BlockDevice;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+extern crate alloc;
+
+/// Open for reading
+pub const O_READ: u32 = 0x01;
+/// Open for writing
+pub const O_WRITE: u32 = 0x02;
+/// Create the file if it doesn't exist
+pub const O_CREATE: u32 = 0x04;
+/// Start from an empty file
+pub const O_TRUNC: u32 = 0x08;
+/// Every write goes to the end of the file
+pub const O_APPEND: u32 = 0x10;
+
+/// Handle to an open file
+pub type Fd = usize;
+
+pub enum SeekFrom {
+    Start(u64),
+    End(i64),
+}
+
+pub struct Mount {
+    /// Mount point name, without the leading '/'
+    pub name: String,
+    /// Registry index of the block device, None for virtual filesystems
+    pub device: Option<usize>,
+    fs: Box<dyn FileSystem>,
+}
+
+impl Mount {
+    pub fn fs_type(&self) -> &'static str {
+        self.fs.fs_type()
+    }
+
+    pub fn label(&self) -> &str {
+        self.fs.label()
+    }
+
+    /// Name of the block device, or the filesystem type for virtual ones
+    pub fn source(&self) -> &'static str {
+        match self.device {
+            Some(device) => crate::system::block::device_name(device).unwrap_or("?"),
+            None => self.fs.fs_type(),
+        }
+    }
+}
+
+/// Device handed to filesystems that don't use one
+struct NoDevice;
+
+impl BlockDevice for NoDevice {
+    fn block_count(&self) -> u64 {
+        0
+    }
+
+    fn read_blocks(&mut self, _start: u64, _buffer: &mut [u8]) -> Result<(), &'static str> {
+        Err("No block device")
+    }
+
+    fn write_blocks(&mut self, _start: u64, _buffer: &[u8]) -> Result<(), &'static str> {
+        Err("No block device")
+    }
+}
+
+struct OpenFile {
+    mount: String,
+    path: String, // Relative to the volume root
+    flags: u32,
+    pos: usize,
+    data: Vec<u8>,
+    dirty: bool,
+    /// Reads and writes go to the filesystem and `data` is unused
+    unbuffered: bool,
+}
+
+/// An archive file being browsed. The last one used is kept loaded until
+/// another is needed or the file changes.
+struct OpenArchive {
+    path: String,
+    size: u32,
+    modified: i64,
+    fs: ArchiveFs,
+}
+
+static mut MOUNTS: Vec<Mount> = Vec::new();
+static mut OPEN_FILES: Vec<Option<OpenFile>> = Vec::new();
+static mut NO_DEVICE: NoDevice = NoDevice;
+static mut ARCHIVE: Option<OpenArchive> = None;
+
+/// Mount the filesystem on block device `device` at the first free /diskN.
+/// Returns the mount point name.
+pub fn mount(device: usize) -> Result<String, &'static str> {
+    if mounts().iter().any(|m| m.device == Some(device)) {
+        return Err("Device already mounted");
+    }
+    let block_device = crate::system::block::device(device).ok_or("No such block device")?;
+    if crate::system::block::crypt::is_encrypted(block_device) {
+        return Err("Device is encrypted - unlock it first");
+    }
+    let fs = super::mount(block_device)?;
+
+    let mut n = 0;
+    let name = loop {
+        let name = alloc::format!("disk{}", n);
+        if !mounts().iter().any(|m| m.name == name) {
+            break name;
+        }
+        n += 1;
+    };
+
+    crate::kernel::uart_write_string(&alloc::format!(
+        "VFS: mounted {} ({}) at /{}\r\n",
+        crate::system::block::device_name(device).unwrap_or("?"), fs.fs_type(), name
+    ));
+    unsafe { MOUNTS.push(Mount { name: name.clone(), device: Some(device), fs }) };
+    Ok(name)
+}
+
+/// Mount a filesystem that has no block device (tmpfs, procfs, ...) at /`name`
+pub fn mount_virtual(name: &str, fs: Box<dyn FileSystem>) -> Result<(), &'static str> {
+    if name.is_empty() || name.contains('/') {
+        return Err("Invalid mount point");
+    }
+    if mounts().iter().any(|m| m.name == name) {
+        return Err("Mount point in use");
+    }
+
+    crate::kernel::uart_write_string(&alloc::format!("VFS: mounted {} at /{}\r\n", fs.fs_type(), name));
+    unsafe { MOUNTS.push(Mount { name: String::from(name), device: None, fs }) };
+    Ok(())
+}
+
+/// Unmount a volume by mount point name ("disk1" or "/disk1")
+pub fn unmount(name: &str) -> Result<(), &'static str> {
+    let name = name.trim_matches('/');
+    let index = mounts().iter().position(|m| m.name == name).ok_or("Not mounted")?;
+    if open_files().any(|file| file.mount == name) {
+        return Err("Volume has open files");
+    }
+
+    unsafe {
+        let mount = MOUNTS.remove(index);
+        if let Some(device) = mount.device.and_then(crate::system::block::device) {
+            device.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// All mounted volumes, in mount order
+pub fn mounts() -> &'static [Mount] {
+    unsafe { &MOUNTS }
+}
+
+/// Path of the root volume's mount point ("/" if it isn't mounted)
+pub fn home() -> String {
+    let root = crate::system::block::root_volume();
+    match mounts().iter().find(|m| m.device.is_some() && m.device == root) {
+        Some(mount) => alloc::format!("/{}", mount.name),
+        None => String::from("/"),
+    }
+}
+
+/// Resolve `path` against directory `dir`, folding "." and ".." away.
+/// The result is absolute, e.g. join("/disk0/docs", "../a.txt") is "/disk0/a.txt".
+pub fn join(dir: &str, path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    let full = if path.starts_with('/') { [path, ""] } else { [dir, path] };
+    for part in full.iter().flat_map(|p| p.split('/')) {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+
+    let mut result = String::new();
+    for part in &parts {
+        result.push('/');
+        result.push_str(part);
+    }
+    if result.is_empty() {
+        result.push('/');
+    }
+    result
+}
+
+/// Find the volume holding an absolute path. Returns the mount and the path
+/// relative to its root, or None for "/" itself.
+fn resolve(path: &str) -> Result<Option<(&'static mut Mount, String)>, &'static str> {
+    let path = join("/", path);
+    let rest = &path[1..];
+    if rest.is_empty() {
+        return Ok(None);
+    }
+
+    let (name, relative) = match rest.find('/') {
+        Some(slash) => (&rest[..slash], &rest[slash + 1..]),
+        None => (rest, ""),
+    };
+    let mount = unsafe { MOUNTS.iter_mut().find(|m| m.name == name) }.ok_or("No such volume")?;
+    Ok(Some((mount, String::from(relative))))
+}
+
+/// Like resolve(), for operations that need a path on a volume
+fn resolve_on_volume(path: &str) -> Result<(&'static mut Mount, String), &'static str> {
+    resolve(path)?.ok_or("Not allowed on /")
+}
+
+/// Block device behind a mount
+fn device_of(mount: &Mount) -> Result<&'static mut dyn BlockDevice, &'static str> {
+    match mount.device {
+        Some(device) => crate::system::block::device(device).ok_or("Block device not available"),
+        None => Ok(unsafe { &mut NO_DEVICE }),
+    }
+}
+
+/// Split a path that runs through an archive file into the archive's path
+/// and the path inside it ("" for the archive's root). None for other paths.
+fn split_archive(path: &str) -> Option<(String, String)> {
+    let path = join("/", path);
+    let parts: Vec<&str> = path[1..].split('/').collect();
+    // parts[0] is the mount point; only names that look like archives are checked
+    for end in 2..=parts.len() {
+        if !archive::is_archive_name(parts[end - 1]) {
+            continue;
+        }
+        let file = alloc::format!("/{}", parts[..end].join("/"));
+        let (mount, relative) = resolve_on_volume(&file).ok()?;
+        if matches!(mount.fs.stat(device_of(mount).ok()?, &relative), Ok(info) if !info.is_dir) {
+            return Some((file, parts[end..].join("/")));
+        }
+    }
+    None
+}
+
+/// The archive at `path`, loading it unless it is the one already open
+fn open_archive(path: &str) -> Result<&'static ArchiveFs, &'static str> {
+    let (mount, relative) = resolve_on_volume(path)?;
+    let info = mount.fs.stat(device_of(mount)?, &relative)?;
+    unsafe {
+        let current = ARCHIVE.as_ref().map_or(false, |open| {
+            open.path == path && open.size == info.size && open.modified == info.modified
+        });
+        if !current {
+            // Drop the old one first; archives can be large
+            ARCHIVE = None;
+            let fs = ArchiveFs::new(read_volume_file(mount, &relative)?)?;
+            ARCHIVE = Some(OpenArchive { path: String::from(path), size: info.size, modified: info.modified, fs });
+        }
+        Ok(&ARCHIVE.as_ref().unwrap().fs)
+    }
+}
+
+/// For a path to something inside an archive, the archive and the path in it
+fn archive_member(path: &str) -> Result<Option<(&'static ArchiveFs, String)>, &'static str> {
+    match split_archive(path) {
+        Some((file, inner)) if !inner.is_empty() => Ok(Some((open_archive(&file)?, inner))),
+        _ => Ok(None),
+    }
+}
+
+pub fn list_dir(path: &str) -> Result<Vec<FileInfo>, &'static str> {
+    if let Some((file, inner)) = split_archive(path) {
+        return open_archive(&file)?.list_dir(unsafe { &mut NO_DEVICE }, &inner);
+    }
+    match resolve(path)? {
+        None => Ok(mounts()
+            .iter()
+            .map(|m| FileInfo::new(m.name.clone(), 0, true))
+            .collect()),
+        Some((mount, relative)) => mount.fs.list_dir(device_of(mount)?, &relative),
+    }
+}
+
+pub fn stat(path: &str) -> Result<FileInfo, &'static str> {
+    if let Some((archive, inner)) = archive_member(path)? {
+        return archive.stat(unsafe { &mut NO_DEVICE }, &inner);
+    }
+    match resolve(path)? {
+        None => Ok(FileInfo::new(String::from("/"), 0, true)),
+        Some((mount, relative)) if relative.is_empty() => {
+            Ok(FileInfo { name: mount.name.clone(), ..mount.fs.stat(device_of(mount)?, "")? })
+        }
+        Some((mount, relative)) => mount.fs.stat(device_of(mount)?, &relative),
+    }
+}
+
+/// Read a whole file
+pub fn read_file(path: &str) -> Result<Vec<u8>, &'static str> {
+    if let Some((archive, inner)) = archive_member(path)? {
+        return read_archive_file(archive, &inner);
+    }
+    let (mount, relative) = resolve_on_volume(path)?;
+    let buffer = read_volume_file(mount, &relative)?;
+    let _ = mount.fs.mark_accessed(device_of(mount)?, &relative);
+    Ok(buffer)
+}
+
+fn read_volume_file(mount: &mut Mount, relative: &str) -> Result<Vec<u8>, &'static str> {
+    let device = device_of(mount)?;
+    let file = mount.fs.stat(device, relative)?;
+    if file.is_dir {
+        return Err("Is a directory");
+    }
+
+    let mut buffer = alloc::vec![0u8; file.size as usize];
+    let size = mount.fs.read_file(device, relative, &mut buffer)?;
+    buffer.truncate(size);
+    Ok(buffer)
+}
+
+fn read_archive_file(archive: &ArchiveFs, inner: &str) -> Result<Vec<u8>, &'static str> {
+    let device = unsafe { &mut NO_DEVICE };
+    let mut buffer = alloc::vec![0u8; archive.stat(device, inner)?.size as usize];
+    let size = archive.read_file(device, inner, &mut buffer)?;
+    buffer.truncate(size);
+    Ok(buffer)
+}
+
+/// Replace a file's contents, creating it if needed
+pub fn write_file(path: &str, data: &[u8]) -> Result<(), &'static str> {
+    let (mount, relative) = resolve_on_volume(path)?;
+    let device = device_of(mount)?;
+    if mount.fs.stat(device, &relative).is_err() {
+        mount.fs.create_file(device, &relative, 0)?;
+    }
+    mount.fs.write_file(device, &relative, data)
+}
+
+/// Create a file of `size` zeroed bytes
+pub fn create_file(path: &str, size: u32) -> Result<(), &'static str> {
+    let (mount, relative) = resolve_on_volume(path)?;
+    mount.fs.create_file(device_of(mount)?, &relative, size)
+}
+
+pub fn create_dir(path: &str) -> Result<(), &'static str> {
+    let (mount, relative) = resolve_on_volume(path)?;
+    mount.fs.create_dir(device_of(mount)?, &relative)
+}
+
+/// Create a directory and any missing parents
+pub fn create_dir_all(path: &str) -> Result<(), &'static str> {
+    match stat(path) {
+        Ok(info) if info.is_dir => Ok(()),
+        Ok(_) => Err("Not a directory"),
+        Err(_) => {
+            create_dir_all(&join(path, ".."))?;
+            create_dir(path)
+        }
+    }
+}
+
+/// The archive file that `path` is, or is inside of
+pub fn archive_of(path: &str) -> Option<String> {
+    split_archive(path).map(|(file, _)| file)
+}
+
+/// Copy an archive's contents, or a file or directory inside an archive, out
+/// to `dest`. Returns how many files were written.
+pub fn extract(path: &str, dest: &str) -> Result<usize, &'static str> {
+    let is_dir = match split_archive(path) {
+        Some((_, inner)) if inner.is_empty() => true,
+        Some(_) => stat(path)?.is_dir,
+        None => return Err("Not an archive"),
+    };
+    if !is_dir {
+        create_dir_all(&join(dest, ".."))?;
+        write_file(dest, &read_file(path)?)?;
+        return Ok(1);
+    }
+
+    create_dir_all(dest)?;
+    let mut files = 0;
+    for child in list_dir(path)? {
+        files += extract(&join(path, &child.name), &join(dest, &child.name))?;
+    }
+    Ok(files)
+}
+
+/// Delete a file or empty directory
+pub fn delete(path: &str) -> Result<(), &'static str> {
+    let (mount, relative) = resolve_on_volume(path)?;
+    if relative.is_empty() {
+        return Err("Can't delete a mount point");
+    }
+    if open_files().any(|file| file.mount == mount.name && file.path == relative) {
+        return Err("File is open");
+    }
+    mount.fs.delete_file(device_of(mount)?, &relative)
+}
+
+/// Rename or move within one volume
+pub fn rename(old_path: &str, new_path: &str) -> Result<(), &'static str> {
+    let (mount, old_relative) = resolve_on_volume(old_path)?;
+    let (new_mount, new_relative) = resolve_on_volume(new_path)?;
+    if mount.name != new_mount.name {
+        return Err("Can't move between volumes");
+    }
+    if old_relative.is_empty() || new_relative.is_empty() {
+        return Err("Can't rename a mount point");
+    }
+    mount.fs.rename_file(device_of(mount)?, &old_relative, &new_relative)
+}
+
+/// Set a file's size, zero-filling any new space
+pub fn truncate(path: &str, size: u32) -> Result<(), &'static str> {
+    let (mount, relative) = resolve_on_volume(path)?;
+    mount.fs.truncate(device_of(mount)?, &relative, size)
+}
+
+/// Replace a file's ATTR_* bits
+pub fn set_attributes(path: &str, attributes: u8) -> Result<(), &'static str> {
+    let (mount, relative) = resolve_on_volume(path)?;
+    if relative.is_empty() {
+        return Err("Can't change a mount point");
+    }
+    mount.fs.set_attributes(device_of(mount)?, &relative, attributes)
+}
+
+/// Check a volume by mount point name, repairing it unless `repair` is false
+pub fn check(name: &str, repair: bool) -> Result<Vec<String>, &'static str> {
+    let name = name.trim_matches('/');
+    let mount = unsafe { MOUNTS.iter_mut().find(|m| m.name == name) }.ok_or("Not mounted")?;
+    // Handles hold paths and buffered data a repair could invalidate
+    if repair && open_files().any(|file| file.mount == name) {
+        return Err("Volume has open files");
+    }
+    mount.fs.check(device_of(mount)?, repair)
+}
+
+/// Size and free space of a volume, by mount point name
+pub fn usage(name: &str) -> Result<Usage, &'static str> {
+    let name = name.trim_matches('/');
+    let mount = unsafe { MOUNTS.iter_mut().find(|m| m.name == name) }.ok_or("Not mounted")?;
+    mount.fs.usage(device_of(mount)?)
+}
+
+/// Defragment a volume by mount point name, returning how many files moved
+pub fn defrag(name: &str) -> Result<usize, &'static str> {
+    let name = name.trim_matches('/');
+    let mount = unsafe { MOUNTS.iter_mut().find(|m| m.name == name) }.ok_or("Not mounted")?;
+    mount.fs.defrag(device_of(mount)?)
+}
+
+/// Snapshots of a volume, by mount point name
+pub fn snapshots(name: &str) -> Result<Vec<Snapshot>, &'static str> {
+    let name = name.trim_matches('/');
+    let mount = unsafe { MOUNTS.iter_mut().find(|m| m.name == name) }.ok_or("Not mounted")?;
+    mount.fs.snapshots(device_of(mount)?)
+}
+
+/// Take a snapshot of a volume, readable afterwards under /<name>/.snapshots
+pub fn create_snapshot(name: &str, snapshot: &str) -> Result<(), &'static str> {
+    let name = name.trim_matches('/');
+    let mount = unsafe { MOUNTS.iter_mut().find(|m| m.name == name) }.ok_or("Not mounted")?;
+    mount.fs.create_snapshot(device_of(mount)?, snapshot)
+}
+
+/// Put a volume's files back as they were in a snapshot
+pub fn restore_snapshot(name: &str, snapshot: &str) -> Result<(), &'static str> {
+    let name = name.trim_matches('/');
+    let mount = unsafe { MOUNTS.iter_mut().find(|m| m.name == name) }.ok_or("Not mounted")?;
+    // Open handles would point at files that no longer exist
+    if open_files().any(|file| file.mount == name) {
+        return Err("Volume has open files");
+    }
+    mount.fs.restore_snapshot(device_of(mount)?, snapshot)
+}
+
+pub fn delete_snapshot(name: &str, snapshot: &str) -> Result<(), &'static str> {
+    let name = name.trim_matches('/');
+    let mount = unsafe { MOUNTS.iter_mut().find(|m| m.name == name) }.ok_or("Not mounted")?;
+    mount.fs.delete_snapshot(device_of(mount)?, snapshot)
+}
+
+// ---- File handles ----
+
+fn open_files() -> impl Iterator<Item = &'static OpenFile> {
+    unsafe { OPEN_FILES.iter().flatten() }
+}
+
+fn handle(fd: Fd) -> Result<&'static mut OpenFile, &'static str> {
+    unsafe { OPEN_FILES.get_mut(fd).and_then(|file| file.as_mut()) }.ok_or("Bad file descriptor")
+}
+
+/// Open a file with O_* `flags`, returning a handle to it
+pub fn open(path: &str, flags: u32) -> Result<Fd, &'static str> {
+    if flags & (O_READ | O_WRITE) == 0 {
+        return Err("Open needs O_READ or O_WRITE");
+    }
+    if flags & O_WRITE == 0 && flags & (O_CREATE | O_TRUNC | O_APPEND) != 0 {
+        return Err("Flags need O_WRITE");
+    }
+
+    let (mount, relative) = resolve_on_volume(path)?;
+    if let Some((archive, inner)) = archive_member(path)? {
+        if flags & O_WRITE != 0 {
+            return Err("Archives are read-only");
+        }
+        let data = read_archive_file(archive, &inner)?;
+        return Ok(add_handle(OpenFile { mount: mount.name.clone(), path: relative, flags, pos: 0, data, dirty: false, unbuffered: false }));
+    }
+    let device = device_of(mount)?;
+    let unbuffered = mount.fs.unbuffered();
+    let data = match mount.fs.stat(device, &relative) {
+        Ok(file) if file.is_dir => return Err("Is a directory"),
+        Ok(file) if flags & O_WRITE != 0 && file.attributes & ATTR_READ_ONLY != 0 => return Err("File is read-only"),
+        Ok(_) if unbuffered => {
+            if flags & O_TRUNC != 0 {
+                mount.fs.truncate(device, &relative, 0)?;
+            }
+            Vec::new()
+        }
+        Ok(_) if flags & O_TRUNC != 0 => Vec::new(),
+        Ok(file) => {
+            let mut data = alloc::vec![0u8; file.size as usize];
+            let size = mount.fs.read_file(device, &relative, &mut data)?;
+            data.truncate(size);
+            if flags & O_READ != 0 {
+                let _ = mount.fs.mark_accessed(device, &relative);
+            }
+            data
+        }
+        Err(_) if flags & O_CREATE != 0 => {
+            mount.fs.create_file(device, &relative, 0)?;
+            Vec::new()
+        }
+        Err(e) => return Err(e),
+    };
+
+    let file = OpenFile {
+        mount: mount.name.clone(),
+        path: relative,
+        flags,
+        pos: 0,
+        data,
+        dirty: flags & O_TRUNC != 0 && !unbuffered,
+        unbuffered,
+    };
+    Ok(add_handle(file))
+}
+
+fn add_handle(file: OpenFile) -> Fd {
+    unsafe {
+        // Reuse the lowest free slot
+        match OPEN_FILES.iter().position(|slot| slot.is_none()) {
+            Some(fd) => {
+                OPEN_FILES[fd] = Some(file);
+                fd
+            }
+            None => {
+                OPEN_FILES.push(Some(file));
+                OPEN_FILES.len() - 1
+            }
+        }
+    }
+}
+
+/// Read from the current position, returning how many bytes were read (0 at end of file)
+pub fn read(fd: Fd, buffer: &mut [u8]) -> Result<usize, &'static str> {
+    let file = handle(fd)?;
+    if file.flags & O_READ == 0 {
+        return Err("File not open for reading");
+    }
+    if file.unbuffered {
+        let (mount, relative) = handle_target(file)?;
+        let count = mount.fs.read_at(device_of(mount)?, &relative, file.pos as u64, buffer)?;
+        file.pos += count;
+        return Ok(count);
+    }
+    let start = core::cmp::min(file.pos, file.data.len());
+    let count = core::cmp::min(buffer.len(), file.data.len() - start);
+    buffer[..count].copy_from_slice(&file.data[start..start + count]);
+    file.pos = start + count;
+    Ok(count)
+}
+
+/// Write at the current position (or the end with O_APPEND), growing the file as needed
+pub fn write(fd: Fd, data: &[u8]) -> Result<usize, &'static str> {
+    let file = handle(fd)?;
+    if file.flags & O_WRITE == 0 {
+        return Err("File not open for writing");
+    }
+    if file.unbuffered {
+        let (mount, relative) = handle_target(file)?;
+        let device = device_of(mount)?;
+        if file.flags & O_APPEND != 0 {
+            file.pos = mount.fs.stat(device, &relative)?.size as usize;
+        }
+        let count = mount.fs.write_at(device, &relative, file.pos as u64, data)?;
+        file.pos += count;
+        return Ok(count);
+    }
+    if file.flags & O_APPEND != 0 {
+        file.pos = file.data.len();
+    }
+    let end = file.pos.checked_add(data.len()).filter(|&end| end <= u32::MAX as usize).ok_or("File too large")?;
+    if end > file.data.len() {
+        // Writing past the end leaves a zero-filled gap
+        file.data.resize(end, 0);
+    }
+    file.data[file.pos..end].copy_from_slice(data);
+    file.pos = end;
+    file.dirty = true;
+    Ok(data.len())
+}
+
+/// Move the file position, returning the new one
+pub fn seek(fd: Fd, from: SeekFrom) -> Result<u64, &'static str> {
+    let file = handle(fd)?;
+    let (base, offset) = match from {
+        SeekFrom::Start(offset) => (0, offset as i64),
+        SeekFrom::End(offset) if file.unbuffered => {
+            let (mount, relative) = handle_target(file)?;
+            (mount.fs.stat(device_of(mount)?, &relative)?.size as i64, offset)
+        }
+        SeekFrom::End(offset) => (file.data.len() as i64, offset),
+    };
+    let pos = base.checked_add(offset).filter(|&pos| pos >= 0).ok_or("Invalid seek")?;
+    file.pos = pos as usize;
+    Ok(pos as u64)
+}
+
+/// Information about an open file, including unsaved size changes
+pub fn fstat(fd: Fd) -> Result<FileInfo, &'static str> {
+    let file = handle(fd)?;
+    if file.unbuffered {
+        let (mount, relative) = handle_target(file)?;
+        return mount.fs.stat(device_of(mount)?, &relative);
+    }
+    // By full path, as the file may be inside an archive
+    let info = stat(&alloc::format!("/{}/{}", file.mount, file.path))?;
+    Ok(FileInfo { size: file.data.len() as u32, ..info })
+}
+
+/// Mount and relative path of an open file
+fn handle_target(file: &OpenFile) -> Result<(&'static mut Mount, String), &'static str> {
+    resolve_on_volume(&alloc::format!("/{}/{}", file.mount, file.path))
+}
+
+/// Close a handle, writing back its contents if they changed
+pub fn close(fd: Fd) -> Result<(), &'static str> {
+    let file = handle(fd)?;
+    let result = if file.dirty {
+        let relative = file.path.clone();
+        let data = core::mem::take(&mut file.data);
+        match resolve_on_volume(&alloc::format!("/{}", file.mount)) {
+            Ok((mount, _)) => device_of(mount).and_then(|device| mount.fs.write_file(device, &relative, &data)),
+            Err(e) => Err(e),
+        }
+    } else {
+        Ok(())
+    };
+    unsafe { OPEN_FILES[fd] = None };
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::block::{registry, RamDisk};
+    use crate::system::fs::{SimpleFilesystem, TmpFs};
+
+    /// The mount table and handles are global, so tests that use them take turns
+    static VFS_LOCK: spin::Mutex<()> = spin::Mutex::new(());
+
+    fn mount_tmpfs(name: &str) {
+        mount_virtual(name, Box::new(TmpFs::new(64 * 1024))).unwrap();
+    }
+
+    #[test]
+    fn test_mount_table_lookup() {
+        let _lock = VFS_LOCK.lock();
+        mount_tmpfs("vt_lookup");
+        create_dir("/vt_lookup/docs").unwrap();
+        write_file("/vt_lookup/docs/a.txt", b"hi").unwrap();
+
+        assert_eq!(read_file("/vt_lookup/./docs/../docs/a.txt").unwrap(), b"hi");
+        assert_eq!(stat("/vt_lookup/docs/a.txt").unwrap().size, 2);
+        assert!(list_dir("/").unwrap().iter().any(|info| info.name == "vt_lookup" && info.is_dir));
+        assert_eq!(stat("/vt_nowhere/a.txt").err(), Some("No such volume"));
+        assert_eq!(create_dir("/").err(), Some("Not allowed on /"));
+        assert_eq!(mount_virtual("vt_lookup", Box::new(TmpFs::new(1024))).err(), Some("Mount point in use"));
+
+        // A block device goes at the first free /diskN and is found by index
+        let mut disk = RamDisk::new(2048);
+        SimpleFilesystem::format(&mut disk).unwrap();
+        let index = registry::register_disk("vt0", Box::new(disk));
+        let name = mount(index).unwrap();
+        assert!(name.starts_with("disk"));
+        assert!(mounts().iter().any(|m| m.name == name && m.device == Some(index) && m.fs_type() == "SimpleFS"));
+        assert_eq!(mount(index).err(), Some("Device already mounted"));
+        registry::set_root_volume(index);
+        assert_eq!(home(), alloc::format!("/{}", name));
+
+        unmount(&name).unwrap();
+        assert_eq!(home(), "/");
+        unmount("/vt_lookup").unwrap();
+        assert_eq!(stat("/vt_lookup").err(), Some("No such volume"));
+    }
+
+    #[test]
+    fn test_fd_lifetime() {
+        let _lock = VFS_LOCK.lock();
+        mount_tmpfs("vt_fd");
+        let fd = open("/vt_fd/a.txt", O_WRITE | O_CREATE).unwrap();
+        assert_eq!(write(fd, b"hello").unwrap(), 5);
+
+        // Buffered until close, and the file and volume stay put meanwhile
+        assert_eq!(fstat(fd).unwrap().size, 5);
+        assert_eq!(stat("/vt_fd/a.txt").unwrap().size, 0);
+        assert_eq!(delete("/vt_fd/a.txt").err(), Some("File is open"));
+        assert_eq!(unmount("vt_fd").err(), Some("Volume has open files"));
+        close(fd).unwrap();
+        assert_eq!(close(fd).err(), Some("Bad file descriptor"));
+        assert_eq!(write(fd, b"x").err(), Some("Bad file descriptor"));
+        assert_eq!(read_file("/vt_fd/a.txt").unwrap(), b"hello");
+
+        let fd = open("/vt_fd/a.txt", O_READ).unwrap();
+        assert_eq!(seek(fd, SeekFrom::End(-2)).unwrap(), 3);
+        let mut buffer = [0u8; 8];
+        assert_eq!(read(fd, &mut buffer).unwrap(), 2);
+        assert_eq!(&buffer[..2], b"lo");
+        assert_eq!(write(fd, b"x").err(), Some("File not open for writing"));
+        close(fd).unwrap();
+
+        delete("/vt_fd/a.txt").unwrap();
+        unmount("vt_fd").unwrap();
+    }
+
+    #[test]
+    fn test_rename_stays_on_one_volume() {
+        let _lock = VFS_LOCK.lock();
+        mount_tmpfs("vt_src");
+        mount_tmpfs("vt_dst");
+        write_file("/vt_src/a.txt", b"x").unwrap();
+
+        assert_eq!(rename("/vt_src/a.txt", "/vt_dst/a.txt").err(), Some("Can't move between volumes"));
+        assert!(stat("/vt_src/a.txt").is_ok());
+        assert!(stat("/vt_dst/a.txt").is_err());
+        assert_eq!(rename("/vt_src/a.txt", "/vt_src").err(), Some("Can't rename a mount point"));
+        rename("/vt_src/a.txt", "/vt_src/b.txt").unwrap();
+        assert_eq!(read_file("/vt_src/b.txt").unwrap(), b"x");
+
+        unmount("vt_src").unwrap();
+        unmount("vt_dst").unwrap();
+    }
+
+    #[test]
+    fn test_join() {
+        assert_eq!(join("/disk0", "a.txt"), "/disk0/a.txt");
+        assert_eq!(join("/disk0/docs", "../a.txt"), "/disk0/a.txt");
+        assert_eq!(join("/disk0/docs", "/disk1/./b"), "/disk1/b");
+        assert_eq!(join("/disk0", "../.."), "/");
+        assert_eq!(join("/", ""), "/");
+    }
+}
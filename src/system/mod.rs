@@ -1,4 +1,10 @@
 // System services module
 
+pub mod aes;
+pub mod archive;
+pub mod block;
+pub mod crc32;
 pub mod fs;
 pub mod net;
+pub mod sha256;
+pub mod transfer;
@@ -245,3 +245,49 @@ pub fn parse_dns_response(data: &[u8]) -> Option<Vec<[u8; 4]>> {
 
     Some(addresses)
 }
+
+/// Upstream resolver used for all lookups (Google DNS)
+pub const DNS_SERVER: [u8; 4] = [8, 8, 8, 8];
+const DNS_ATTEMPTS: usize = 3;
+const DNS_ATTEMPT_TIMEOUT_MS: u64 = 2000;
+
+/// Resolve a host name to its IPv4 addresses (async, for executor tasks).
+/// IP literals are returned as-is without a query.
+pub async fn resolve(host: &str) -> Result<Vec<[u8; 4]>, &'static str> {
+    use crate::kernel::executor::timeout;
+    use crate::system::net::sockets::UdpSocket;
+
+    if let Some(ip) = crate::system::net::network::parse_ip(host) {
+        return Ok(alloc::vec![ip]);
+    }
+
+    let socket = UdpSocket::bind(0)?;
+    let mut buf = [0u8; 512];
+
+    for _ in 0..DNS_ATTEMPTS {
+        // Fresh random ID per attempt so a late reply can't be mistaken for this one
+        let query_id = crate::kernel::random::random_u16();
+        let query = build_dns_query(host, DNS_TYPE_A, query_id);
+        socket.send_to(&query, DNS_SERVER, 53).await?;
+
+        let reply = timeout(DNS_ATTEMPT_TIMEOUT_MS, async {
+            loop {
+                let (len, _, port) = socket.recv_from(&mut buf).await?;
+                let reply_id = if len >= 2 { u16::from_be_bytes([buf[0], buf[1]]) } else { 0 };
+                if port == 53 && reply_id == query_id {
+                    return Ok::<_, &'static str>(parse_dns_response(&buf[..len]));
+                }
+            }
+        })
+        .await;
+
+        match reply {
+            Ok(Ok(Some(addresses))) if !addresses.is_empty() => return Ok(addresses),
+            Ok(Ok(_)) => return Err("No A records found"),
+            Ok(Err(e)) => return Err(e),
+            Err(_) => continue, // Retry
+        }
+    }
+
+    Err("DNS timeout")
+}
@@ -0,0 +1,88 @@
+// Async HTTP/1.0 client
+//
+// Runs inside executor tasks on top of net::sockets, so any number of requests
+// can be in flight without blocking the main loop.
+
+use crate::kernel::executor::timeout;
+use crate::system::net::dns;
+use crate::system::net::sockets::TcpStream;
+use alloc::vec::Vec;
+
+const CONNECT_TIMEOUT_MS: u64 = 10000;
+/// Give up if the server sends nothing for this long
+const IDLE_TIMEOUT_MS: u64 = 30000;
+
+/// Offset of the blank line that ends the headers, and its length
+fn find_header_end(data: &[u8]) -> Option<(usize, usize)> {
+    if let Some(pos) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+        return Some((pos, 4));
+    }
+    data.windows(2).position(|w| w == b"\n\n").map(|pos| (pos, 2))
+}
+
+/// Content-Length from a response header block, if present
+fn content_length(headers: &[u8]) -> Option<usize> {
+    let headers = core::str::from_utf8(headers).ok()?;
+    headers.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        if name.trim().eq_ignore_ascii_case("content-length") {
+            value.trim().parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
+/// Strip the status line and headers from a raw response
+pub fn body(response: &[u8]) -> &[u8] {
+    match find_header_end(response) {
+        Some((pos, len)) => &response[pos + len..],
+        None => response,
+    }
+}
+
+/// GET `path` from `host` and return the raw response (headers included)
+pub async fn get(host: &str, port: u16, path: &str) -> Result<Vec<u8>, &'static str> {
+    let addresses = dns::resolve(host).await?;
+    let mut stream = timeout(CONNECT_TIMEOUT_MS, TcpStream::connect(addresses[0], port))
+        .await
+        .map_err(|_| "TCP connection timeout")??;
+
+    let request = alloc::format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, host
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut response = Vec::new();
+    let mut expected_len: Option<usize> = None;
+    let mut chunk = [0u8; 4096];
+
+    loop {
+        let n = timeout(IDLE_TIMEOUT_MS, stream.read(&mut chunk))
+            .await
+            .map_err(|_| "HTTP response timeout")??;
+        if n == 0 {
+            break; // Server closed the connection
+        }
+        response.extend_from_slice(&chunk[..n]);
+
+        // Stop as soon as Content-Length bytes of body have arrived
+        if let Some((pos, len)) = find_header_end(&response) {
+            if expected_len.is_none() {
+                expected_len = content_length(&response[..pos]);
+            }
+            if let Some(expected) = expected_len {
+                if response.len() - (pos + len) >= expected {
+                    break;
+                }
+            }
+        }
+    }
+
+    if response.is_empty() {
+        Err("No HTTP response received")
+    } else {
+        Ok(response)
+    }
+}
@@ -5,6 +5,8 @@ pub mod dns;
 pub mod smoltcp_device;
 pub mod stack;
 pub mod helpers;
+pub mod sockets;
+pub mod http;
 
 // Re-export commonly used types
 pub use smoltcp_device::SmoltcpVirtioNetDevice;
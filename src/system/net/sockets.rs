@@ -0,0 +1,198 @@
+// Async socket API on top of the smoltcp stack
+//
+// Socket handles wrapped in futures that park on smoltcp's socket wakers, so
+// kernel tasks (see kernel::executor) can connect, read and write without
+// blocking the main loop. The sockets live in the global NETWORK_STACK; the
+// main loop's NetworkStack::poll() is what fires the wakers.
+// Dropping a TcpStream or UdpSocket removes its smoltcp socket.
+
+use crate::system::net::NetworkStack;
+use crate::system::net::helpers::ephemeral_port;
+use smoltcp::iface::SocketHandle;
+use smoltcp::wire::{IpAddress, IpEndpoint, Ipv4Address};
+use core::future::poll_fn;
+use core::task::Poll;
+
+fn stack() -> Result<&'static mut NetworkStack, &'static str> {
+    unsafe { crate::kernel::NETWORK_STACK.as_mut().ok_or("Network stack not initialized") }
+}
+
+fn ipv4(addr: [u8; 4]) -> IpAddress {
+    IpAddress::Ipv4(Ipv4Address::new(addr[0], addr[1], addr[2], addr[3]))
+}
+
+/// Async TCP connection
+pub struct TcpStream {
+    handle: SocketHandle,
+}
+
+impl TcpStream {
+    /// Open a connection and wait until it is established
+    pub async fn connect(addr: [u8; 4], port: u16) -> Result<TcpStream, &'static str> {
+        let handle = {
+            let stack = stack()?;
+            let handle = stack.create_tcp_socket();
+            if stack.tcp_connect(handle, IpEndpoint::new(ipv4(addr), port), ephemeral_port()).is_err() {
+                stack.remove_socket(handle);
+                return Err("Failed to initiate TCP connection");
+            }
+            handle
+        };
+        // From here on Drop cleans up the socket if we bail out or get cancelled
+        let stream = TcpStream { handle };
+
+        poll_fn(|cx| {
+            let stack = match stack() {
+                Ok(stack) => stack,
+                Err(e) => return Poll::Ready(Err(e)),
+            };
+            stack.with_tcp_socket(handle, |socket| {
+                if socket.may_send() {
+                    Poll::Ready(Ok(()))
+                } else if !socket.is_open() {
+                    Poll::Ready(Err("Connection refused"))
+                } else {
+                    socket.register_send_waker(cx.waker());
+                    Poll::Pending
+                }
+            })
+        })
+        .await?;
+
+        Ok(stream)
+    }
+
+    /// Send all of `data`, waiting for transmit buffer space as needed
+    pub async fn write_all(&mut self, data: &[u8]) -> Result<(), &'static str> {
+        let handle = self.handle;
+        let mut sent = 0;
+        poll_fn(|cx| {
+            let stack = match stack() {
+                Ok(stack) => stack,
+                Err(e) => return Poll::Ready(Err(e)),
+            };
+            stack.with_tcp_socket(handle, |socket| {
+                while sent < data.len() {
+                    if !socket.may_send() {
+                        return Poll::Ready(Err("Connection closed"));
+                    }
+                    match socket.send_slice(&data[sent..]) {
+                        Ok(0) => {
+                            socket.register_send_waker(cx.waker());
+                            return Poll::Pending;
+                        }
+                        Ok(n) => sent += n,
+                        Err(_) => return Poll::Ready(Err("TCP send failed")),
+                    }
+                }
+                Poll::Ready(Ok(()))
+            })
+        })
+        .await
+    }
+
+    /// Receive into `buf`. Returns 0 once the peer has closed the connection.
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, &'static str> {
+        let handle = self.handle;
+        poll_fn(|cx| {
+            let stack = match stack() {
+                Ok(stack) => stack,
+                Err(e) => return Poll::Ready(Err(e)),
+            };
+            stack.with_tcp_socket(handle, |socket| {
+                if socket.can_recv() {
+                    Poll::Ready(socket.recv_slice(buf).map_err(|_| "TCP receive failed"))
+                } else if !socket.may_recv() {
+                    Poll::Ready(Ok(0))
+                } else {
+                    socket.register_recv_waker(cx.waker());
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        if let Ok(stack) = stack() {
+            stack.remove_socket(self.handle);
+        }
+    }
+}
+
+/// Async UDP socket
+pub struct UdpSocket {
+    handle: SocketHandle,
+}
+
+impl UdpSocket {
+    /// Bind a new socket to `port` (0 picks a random ephemeral port)
+    pub fn bind(port: u16) -> Result<UdpSocket, &'static str> {
+        let stack = stack()?;
+        let handle = stack.create_udp_socket();
+        let port = if port == 0 { ephemeral_port() } else { port };
+        if stack.with_udp_socket(handle, |socket| socket.bind(port)).is_err() {
+            stack.remove_socket(handle);
+            return Err("Failed to bind UDP socket");
+        }
+        Ok(UdpSocket { handle })
+    }
+
+    /// Send one datagram, waiting for buffer space if necessary
+    pub async fn send_to(&self, data: &[u8], addr: [u8; 4], port: u16) -> Result<(), &'static str> {
+        let handle = self.handle;
+        poll_fn(|cx| {
+            let stack = match stack() {
+                Ok(stack) => stack,
+                Err(e) => return Poll::Ready(Err(e)),
+            };
+            stack.with_udp_socket(handle, |socket| {
+                if socket.can_send() {
+                    Poll::Ready(socket.send_slice(data, (ipv4(addr), port)).map_err(|_| "UDP send failed"))
+                } else {
+                    socket.register_send_waker(cx.waker());
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+
+    /// Receive one datagram. Returns (length, source address, source port).
+    pub async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, [u8; 4], u16), &'static str> {
+        let handle = self.handle;
+        poll_fn(|cx| {
+            let stack = match stack() {
+                Ok(stack) => stack,
+                Err(e) => return Poll::Ready(Err(e)),
+            };
+            stack.with_udp_socket(handle, |socket| {
+                if !socket.can_recv() {
+                    socket.register_recv_waker(cx.waker());
+                    return Poll::Pending;
+                }
+                match socket.recv_slice(buf) {
+                    Ok((len, meta)) => {
+                        let addr = match meta.endpoint.addr {
+                            IpAddress::Ipv4(ip) => ip.octets(),
+                            _ => return Poll::Ready(Err("IPv6 datagrams are not supported")),
+                        };
+                        Poll::Ready(Ok((len, addr, meta.endpoint.port)))
+                    }
+                    Err(_) => Poll::Ready(Err("UDP receive failed")),
+                }
+            })
+        })
+        .await
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        if let Ok(stack) = stack() {
+            stack.remove_socket(self.handle);
+        }
+    }
+}
@@ -31,7 +31,9 @@ impl NetworkStack {
         let ethernet_addr = EthernetAddress::from_bytes(&mac);
 
         // Create interface configuration
-        let config = Config::new(ethernet_addr.into());
+        let mut config = Config::new(ethernet_addr.into());
+        // Seeds TCP initial sequence numbers and other protocol randomness
+        config.random_seed = crate::kernel::random::random_u64();
 
         // Create interface
         let mut interface = Interface::new(config, &mut device, Instant::ZERO);
@@ -169,11 +171,6 @@ impl NetworkStack {
         self.device.mac_address()
     }
 
-    /// Get the interface for socket operations
-    pub fn interface_mut(&mut self) -> &mut Interface {
-        &mut self.interface
-    }
-
     /// Connect a TCP socket (helper that manages Context)
     pub fn tcp_connect(
         &mut self,
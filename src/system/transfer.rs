@@ -0,0 +1,214 @@
+// Host file transfer protocol
+//
+// A byte stream protocol for copying files between the host and the guest,
+// spoken over a virtio-console port. The host sends one request at a time and
+// the guest answers each before reading the next. Requests are a text line,
+// followed for PUT by the file contents:
+//
+//   PUT <size> <path>\n<size bytes>   write a file, replacing it if it exists
+//   GET <path>\n                      read a file
+//   LS <path>\n                       list a directory
+//
+// Paths are absolute VFS paths and come last, so they may contain spaces. Every
+// reply is either "OK <size>\n" followed by that many bytes (the file for GET,
+// one "<d|-> <size> <name>" line per entry for LS, nothing for PUT) or
+// "ERR <message>\n". The parser only splits the stream; the kernel carries the
+// requests out.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Longest request line accepted
+const MAX_LINE: usize = 1024;
+/// Largest file a PUT may carry; it is held in memory until complete
+pub const MAX_PUT_SIZE: usize = 32 * 1024 * 1024;
+
+/// A complete request from the host
+#[derive(Debug, PartialEq)]
+pub enum Request {
+    Put { path: String, data: Vec<u8> },
+    Get { path: String },
+    List { path: String },
+}
+
+enum State {
+    /// Reading a request line
+    Line,
+    /// Reading the data of a PUT until the buffer holds `size` bytes
+    Data { path: String, size: usize },
+    /// Dropping the data of a refused PUT
+    Skip { remaining: usize },
+    /// Dropping the rest of an overlong line
+    DropLine,
+}
+
+/// Splits the incoming byte stream into requests
+pub struct Parser {
+    state: State,
+    buffer: Vec<u8>,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Parser { state: State::Line, buffer: Vec::new() }
+    }
+
+    /// Consume received bytes, returning the requests they complete in order,
+    /// or the error to reply with for a request that can't be carried out
+    pub fn feed(&mut self, mut bytes: &[u8]) -> Vec<Result<Request, &'static str>> {
+        let mut requests = Vec::new();
+        while !bytes.is_empty() {
+            match &mut self.state {
+                State::Line => {
+                    let newline = bytes.iter().position(|&b| b == b'\n');
+                    let end = newline.unwrap_or(bytes.len());
+                    self.buffer.extend_from_slice(&bytes[..end]);
+                    bytes = &bytes[(end + 1).min(bytes.len())..];
+                    if newline.is_some() {
+                        let line = core::mem::take(&mut self.buffer);
+                        if let Some(result) = self.parse_line(&line) {
+                            requests.push(result);
+                        }
+                    } else if self.buffer.len() > MAX_LINE {
+                        // Drop the rest of the line and answer it
+                        self.buffer.clear();
+                        self.state = State::DropLine;
+                        requests.push(Err("Request line too long"));
+                    }
+                }
+                State::Data { path, size } => {
+                    let take = (*size - self.buffer.len()).min(bytes.len());
+                    self.buffer.extend_from_slice(&bytes[..take]);
+                    bytes = &bytes[take..];
+                    if self.buffer.len() == *size {
+                        let path = core::mem::take(path);
+                        requests.push(Ok(Request::Put { path, data: core::mem::take(&mut self.buffer) }));
+                        self.state = State::Line;
+                    }
+                }
+                State::DropLine => {
+                    match bytes.iter().position(|&b| b == b'\n') {
+                        Some(newline) => {
+                            bytes = &bytes[newline + 1..];
+                            self.state = State::Line;
+                        }
+                        None => bytes = &[],
+                    }
+                }
+                State::Skip { remaining } => {
+                    let take = (*remaining).min(bytes.len());
+                    *remaining -= take;
+                    bytes = &bytes[take..];
+                    if *remaining == 0 {
+                        self.state = State::Line;
+                    }
+                }
+            }
+        }
+        // A PUT with no data completes as soon as its line does
+        if let State::Data { path, size: 0 } = &mut self.state {
+            requests.push(Ok(Request::Put { path: core::mem::take(path), data: Vec::new() }));
+            self.state = State::Line;
+        }
+        requests
+    }
+
+    /// Parse a request line. Returns None for blank lines, and for a PUT,
+    /// whose data comes next.
+    fn parse_line(&mut self, line: &[u8]) -> Option<Result<Request, &'static str>> {
+        let line = match core::str::from_utf8(line) {
+            Ok(line) => line.trim_end_matches('\r'),
+            Err(_) => return Some(Err("Request is not UTF-8")),
+        };
+        if line.trim().is_empty() {
+            return None;
+        }
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let path = |path: &str| -> Result<String, &'static str> {
+            if path.starts_with('/') { Ok(String::from(path)) } else { Err("Path must be absolute") }
+        };
+        match command {
+            "GET" => Some(path(rest).map(|path| Request::Get { path })),
+            "LS" => Some(path(rest).map(|path| Request::List { path })),
+            "PUT" => {
+                let (size, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+                let size: usize = match size.parse() {
+                    Ok(size) => size,
+                    Err(_) => return Some(Err("Usage: PUT <size> <path>")),
+                };
+                let error = match path(rest) {
+                    Ok(_) if size > MAX_PUT_SIZE => "File too large",
+                    Ok(path) => {
+                        self.state = State::Data { path, size };
+                        return None;
+                    }
+                    Err(e) => e,
+                };
+                // The data still follows; skip it so the next request lines up
+                if size > 0 {
+                    self.state = State::Skip { remaining: size };
+                }
+                Some(Err(error))
+            }
+            _ => Some(Err("Unknown request")),
+        }
+    }
+}
+
+/// Encode the reply to a request
+pub fn reply(result: Result<Vec<u8>, &str>) -> Vec<u8> {
+    match result {
+        Ok(data) => {
+            let mut reply = format!("OK {}\n", data.len()).into_bytes();
+            reply.extend_from_slice(&data);
+            reply
+        }
+        Err(e) => format!("ERR {}\n", e).into_bytes(),
+    }
+}
+
+/// One line of an LS reply
+pub fn list_entry(name: &str, size: u32, is_dir: bool) -> String {
+    format!("{} {} {}\n", if is_dir { 'd' } else { '-' }, size, name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put(path: &str, data: &[u8]) -> Result<Request, &'static str> {
+        Ok(Request::Put { path: String::from(path), data: data.to_vec() })
+    }
+
+    #[test]
+    fn test_parse_requests() {
+        let mut parser = Parser::new();
+        // Split at awkward points, as port buffers would
+        assert_eq!(parser.feed(b"GET /host/a b.txt\nPUT 5 /tmp/x\nhel"), [Ok(Request::Get { path: "/host/a b.txt".into() })]);
+        assert_eq!(parser.feed(b"lo"), [put("/tmp/x", b"hello")]);
+        assert_eq!(parser.feed(b"PUT 0 /tmp/empty\n"), [put("/tmp/empty", b"")]);
+        assert_eq!(parser.feed(b"\r\nLS /\r\nLS tmp\n"), [Ok(Request::List { path: "/".into() }), Err("Path must be absolute")]);
+
+        // A refused PUT still consumes its data
+        assert_eq!(parser.feed(b"PUT 3 rel\nabcGET /a\n"), [Err("Path must be absolute"), Ok(Request::Get { path: "/a".into() })]);
+        assert_eq!(parser.feed(b"PUT x /a\nFOO\n"), [Err("Usage: PUT <size> <path>"), Err("Unknown request")]);
+
+        let long = alloc::vec![b'a'; MAX_LINE + 10];
+        assert_eq!(parser.feed(&long), [Err("Request line too long")]);
+        assert_eq!(parser.feed(b"aaa\nGET /b\n"), [Ok(Request::Get { path: "/b".into() })]);
+    }
+
+    #[test]
+    fn test_reply() {
+        assert_eq!(reply(Ok(b"abc".to_vec())), b"OK 3\nabc");
+        assert_eq!(reply(Err("File not found")), b"ERR File not found\n");
+        assert_eq!(list_entry("docs", 0, true), "d 0 docs\n");
+    }
+}
@@ -17,6 +17,12 @@ use raw_uefi::*;
 #[global_allocator]
 static ALLOCATOR: LockedHeap = LockedHeap::empty();
 
+/// Kernel heap as (start address, size, bytes in use)
+pub fn heap_usage() -> (usize, usize, usize) {
+    let heap = ALLOCATOR.lock();
+    (heap.bottom() as usize, heap.size(), heap.used())
+}
+
 // UEFI entry point
 #[no_mangle]
 pub extern "efiapi" fn efi_main(
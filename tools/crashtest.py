@@ -0,0 +1,226 @@
+#!/usr/bin/env python3
+"""Kill QEMU at random points while rOSt writes to its disk, and check the
+SimpleFS volume after every kill.
+
+    crashtest.py --rostfs PATH --image IMG [--runs N] [--seed S] -- QEMU ARGS...
+
+`make crashtest` runs it with the right QEMU command line. The command must
+attach IMG as a virtio disk, send the serial console to stdout and create
+the rost.shell port socket under target/ (see PORTS in the Makefile).
+
+Each run boots the guest and drives its remote shell with a random mix of
+write, append, create, truncate, rename, rm, mkdir and sync in /<volume>/crash.
+After a random delay it SIGKILLs QEMU, which loses whatever was in flight and
+whatever the block cache hadn't written back. Then, on the host:
+
+  1. `rostfs fsck -n IMG` must find nothing wrong. A journal transaction
+     that committed but wasn't replayed is fine: a read-only check can't
+     replay it, so the check is repeated after step 2.
+  2. Remounting writable (`rostfs -v ls`) replays the journal and must not
+     report anything to repair.
+  3. `rostfs fsck -n IMG` must now be clean.
+
+The next run's boot mounts the volume in the guest again. A failed run leaves
+the image as it was and the guest's serial output in target/crashtest.log.
+Only the Python standard library is needed.
+"""
+
+import argparse
+import os
+import random
+import re
+import socket
+import subprocess
+import sys
+import threading
+import time
+
+TARGET_DIR = os.path.normpath(os.path.join(os.path.dirname(__file__), "..", "target"))
+SHELL_SOCKET = os.path.join(TARGET_DIR, "rost.shell.sock")
+LOG = os.path.join(TARGET_DIR, "crashtest.log")
+BOOT_TIMEOUT = 180
+COMMAND_TIMEOUT = 60
+# What the prompt looks like at the end of a command's output
+PROMPT = re.compile(rb"\r\n(/[^\r\n]*)> $")
+
+
+class Failure(Exception):
+    pass
+
+
+class Shell:
+    """The guest's remote shell, one command at a time"""
+
+    def __init__(self, sock):
+        self.sock = sock
+        self.buffer = b""
+
+    def _read_until(self, done, timeout):
+        deadline = time.monotonic() + timeout
+        while not done():
+            left = deadline - time.monotonic()
+            if left <= 0:
+                raise Failure(f"guest stopped answering; last output: {self.buffer[-200:]!r}")
+            self.sock.settimeout(left)
+            try:
+                chunk = self.sock.recv(4096)
+            except socket.timeout:
+                continue
+            if not chunk:
+                raise EOFError
+            self.buffer += chunk
+
+    def wait_for_banner(self):
+        self._read_until(lambda: PROMPT.search(self.buffer), BOOT_TIMEOUT)
+        self.buffer = b""
+
+    def run(self, command):
+        """Run a command and return its output and the directory it left the shell in"""
+        self.sock.sendall(command.encode() + b"\r")
+        self._read_until(lambda: PROMPT.search(self.buffer), COMMAND_TIMEOUT)
+        output, self.buffer = self.buffer, b""
+        prompt = PROMPT.search(output)
+        # Drop the echoed command line and the prompt
+        lines = output[: prompt.start()].decode(errors="replace").split("\r\n", 1)
+        return lines[1] if len(lines) > 1 else "", prompt.group(1).decode()
+
+
+def random_command(rng):
+    name = lambda prefix: f"{prefix}{rng.randrange(12)}"
+    text = lambda: "".join(rng.choice("abcdefghijklmnopqrstuvwxyz0123456789") for _ in range(rng.randrange(1, 90)))
+    choice = rng.random()
+    if choice < 0.30:
+        return f"write {name('f')} {text()}"
+    if choice < 0.45:
+        return f"append {name('f')} {text()}"
+    if choice < 0.55:
+        return f"create {name('b')} {rng.randrange(1, 512 * 1024)}"
+    if choice < 0.62:
+        return f"truncate {name(rng.choice('fb'))} {rng.randrange(0, 64 * 1024)}"
+    if choice < 0.72:
+        prefix = rng.choice("fb")
+        return f"rename {name(prefix)} {name(prefix)}"
+    if choice < 0.87:
+        return f"rm {name(rng.choice('fb'))}"
+    if choice < 0.92:
+        return f"mkdir {name('d')}"
+    return "sync"
+
+
+def connect(qemu):
+    """Connect to the shell port once QEMU has created its socket"""
+    deadline = time.monotonic() + 30
+    while True:
+        if qemu.poll() is not None:
+            raise Failure(f"QEMU exited with status {qemu.returncode} before creating {SHELL_SOCKET}")
+        sock = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+        try:
+            sock.connect(SHELL_SOCKET)
+            return sock
+        except OSError:
+            sock.close()
+            if time.monotonic() > deadline:
+                raise Failure(f"can't connect to {SHELL_SOCKET}")
+            time.sleep(0.1)
+
+
+def crash_run(qemu_args, rng, max_seconds):
+    """Boot, write until killed, and return the number of commands completed"""
+    with open(LOG, "wb") as log:
+        qemu = subprocess.Popen(qemu_args, stdin=subprocess.DEVNULL, stdout=log, stderr=subprocess.STDOUT)
+    commands = 0
+    try:
+        shell = Shell(connect(qemu))
+        shell.wait_for_banner()
+        _, home = shell.run("cd")
+        if home == "/":
+            raise Failure("the guest didn't mount the volume")
+        shell.run("mkdir crash")
+        output, cwd = shell.run("cd crash")
+        if cwd != home.rstrip("/") + "/crash":
+            raise Failure(f"can't enter the test directory: {output.strip()}")
+
+        # From here on the guest is killed wherever it happens to be
+        killed = threading.Event()
+
+        def kill():
+            killed.set()
+            qemu.kill()
+
+        timer = threading.Timer(rng.uniform(0, max_seconds), kill)
+        timer.start()
+        try:
+            while True:
+                shell.run(random_command(rng))
+                commands += 1
+        except (EOFError, OSError, Failure) as e:
+            if not killed.is_set():
+                timer.cancel()
+                raise Failure(f"the guest went away before it was killed: {str(e) or 'connection closed'}")
+    finally:
+        if qemu.poll() is None:
+            qemu.kill()
+        qemu.wait()
+    return commands
+
+
+def check_volume(rostfs, image):
+    """Check the image after a kill; returns whether the journal needed replaying"""
+    def run(*args):
+        return subprocess.run([rostfs, *args], capture_output=True, text=True)
+
+    first = run("fsck", "-n", image)
+    replay = "needs replaying" in first.stderr
+    if first.returncode != 0 and not replay:
+        raise Failure(f"fsck -n after the kill:\n{first.stdout}{first.stderr}")
+
+    # Mounting writable replays the journal; mount also repairs, so any
+    # repair it logs means the crash left the volume damaged
+    remount = run("-v", "ls", image, "/")
+    problems = [line for line in remount.stderr.splitlines() if line.startswith(("SimpleFS: ", "Repaired"))]
+    if remount.returncode != 0 or problems:
+        raise Failure(f"remount:\n{remount.stderr}")
+
+    second = run("fsck", "-n", image)
+    if second.returncode != 0:
+        raise Failure(f"fsck -n after remounting:\n{second.stdout}{second.stderr}")
+    return replay
+
+
+def main(argv):
+    parser = argparse.ArgumentParser(description="Kill QEMU mid-write and check the SimpleFS volume.")
+    parser.add_argument("--rostfs", required=True, help="path to the rostfs binary")
+    parser.add_argument("--image", required=True, help="disk image the QEMU command attaches")
+    parser.add_argument("--runs", type=int, default=20, help="number of kills (default 20)")
+    parser.add_argument("--seed", type=int, help="repeat the commands and kill delays of an earlier run")
+    parser.add_argument("--max-seconds", type=float, default=12.0,
+                        help="latest kill after the writes start (default 12, past the 5 s cache write-back)")
+    parser.add_argument("qemu", nargs=argparse.REMAINDER, help="-- and the QEMU command line")
+    args = parser.parse_args(argv)
+    qemu_args = args.qemu[1:] if args.qemu[:1] == ["--"] else args.qemu
+    if not qemu_args:
+        parser.error("no QEMU command line given")
+
+    seed = args.seed if args.seed is not None else random.randrange(1 << 32)
+    rng = random.Random(seed)
+    print(f"crashtest: {args.runs} run(s), seed {seed}")
+
+    check_volume(args.rostfs, args.image)
+    replays = 0
+    for run in range(1, args.runs + 1):
+        try:
+            commands = crash_run(qemu_args, rng, args.max_seconds)
+            replayed = check_volume(args.rostfs, args.image)
+        except Failure as e:
+            print(f"run {run}: FAILED: {e}")
+            print(f"crashtest: repeat with --seed {seed}; guest output is in {LOG}")
+            return 1
+        replays += replayed
+        print(f"run {run}: killed after {commands} command(s), volume consistent"
+              + (" (journal replayed)" if replayed else ""))
+    print(f"crashtest: {args.runs} run(s) passed, {replays} needed the journal replayed")
+    return 0
+
+
+if __name__ == "__main__":
+    sys.exit(main(sys.argv[1:]))
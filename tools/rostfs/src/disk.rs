@@ -0,0 +1,63 @@
+// Image files as block devices
+
+use crate::system::block::{check_request, BlockDevice, BLOCK_SIZE};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+pub struct FileDisk {
+    file: File,
+    blocks: u64,
+    writable: bool,
+}
+
+impl FileDisk {
+    pub fn open(path: &str, writable: bool) -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(writable)
+            .open(path)
+            .map_err(|e| format!("{}: {}", path, e))?;
+        let size = file.metadata().map_err(|e| format!("{}: {}", path, e))?.len();
+        Ok(FileDisk { file, blocks: size / BLOCK_SIZE as u64, writable })
+    }
+
+    /// Make everything written so far durable
+    pub fn close(self) -> Result<(), String> {
+        if self.writable {
+            self.file.sync_all().map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn seek(&mut self, block: u64) -> Result<(), &'static str> {
+        self.file.seek(SeekFrom::Start(block * BLOCK_SIZE as u64)).map(|_| ()).map_err(|_| "Seek failed")
+    }
+}
+
+impl BlockDevice for FileDisk {
+    fn block_count(&self) -> u64 {
+        self.blocks
+    }
+
+    fn read_blocks(&mut self, start: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+        check_request(self.blocks, start, buffer.len())?;
+        self.seek(start)?;
+        self.file.read_exact(buffer).map_err(|_| "Read failed")
+    }
+
+    fn write_blocks(&mut self, start: u64, buffer: &[u8]) -> Result<(), &'static str> {
+        if !self.writable {
+            return Err("Device is read-only");
+        }
+        check_request(self.blocks, start, buffer.len())?;
+        self.seek(start)?;
+        self.file.write_all(buffer).map_err(|_| "Write failed")
+    }
+
+    // Writes reach the file straight away. A power cut while the tool runs
+    // isn't worth an fsync per commit; close() syncs once at the end.
+
+    fn is_read_only(&self) -> bool {
+        !self.writable
+    }
+}
@@ -0,0 +1,73 @@
+// Stand-ins for the kernel services the shared filesystem code calls
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by -v: show the filesystem's log messages
+pub static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+/// The kernel log goes to stderr, and only with -v
+pub fn uart_write_string(s: &str) {
+    if VERBOSE.load(Ordering::Relaxed) {
+        eprint!("{}", s.replace("\r\n", "\n"));
+    }
+}
+
+// Calendar and timezone code, with the host clock standing in for the RTC
+#[path = "../../../src/kernel/clock/mod.rs"]
+#[allow(dead_code, clippy::all)]
+pub mod clock;
+
+pub mod drivers {
+    pub mod rtc {
+        /// Seconds since the Unix epoch. SOURCE_DATE_EPOCH overrides the host
+        /// clock so that images built from the same files are identical.
+        pub fn read_time() -> u32 {
+            if let Some(secs) = std::env::var("SOURCE_DATE_EPOCH").ok().and_then(|value| value.parse().ok()) {
+                return secs;
+            }
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs() as u32)
+        }
+
+        pub fn set_time(_seconds: u32) {}
+
+        pub fn init() {}
+    }
+
+    pub mod timer {
+        use std::sync::OnceLock;
+        use std::time::Instant;
+
+        static START: OnceLock<Instant> = OnceLock::new();
+
+        /// Nanoseconds since the first call
+        pub fn read_counter() -> u64 {
+            START.get_or_init(Instant::now).elapsed().as_nanos() as u64
+        }
+
+        pub fn counter_frequency() -> u64 {
+            1_000_000_000
+        }
+    }
+}
+
+// The kernel CSPRNG, reseeded from the host instead of virtio-rng
+#[path = "../../../src/kernel/random.rs"]
+#[allow(dead_code, static_mut_refs, clippy::all)]
+pub mod random;
+
+/// Stands in for the virtio-rng device random.rs reseeds from
+pub struct HostRng;
+
+impl HostRng {
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, &'static str> {
+        use std::io::Read;
+        std::fs::File::open("/dev/urandom")
+            .and_then(|mut urandom| urandom.read_exact(buf))
+            .map_err(|_| "Can't read /dev/urandom")?;
+        Ok(buf.len())
+    }
+}
+
+pub static mut RNG_DEVICE: Option<HostRng> = Some(HostRng);
@@ -0,0 +1,245 @@
+// rostfs - build and inspect rOSt disk images on the host
+//
+// Works on image files holding a bare SimpleFS volume (no partition table),
+// using the kernel's own SimpleFS code, so an image made here mounts in the
+// guest exactly like one formatted there. The repository's .cargo/config.toml
+// targets aarch64, so build it for the host with `make rostfs`.
+//
+// Set SOURCE_DATE_EPOCH to stamp every file with that time; the same files
+// then always give the same image.
+
+extern crate alloc;
+
+mod disk;
+mod kernel;
+mod system;
+
+use disk::FileDisk;
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::atomic::Ordering;
+use system::fs::{FileSystem, SimpleFilesystem};
+
+const USAGE: &str = "\
+usage: rostfs [-v] <command> <image> [args]
+
+  mkfs <image> [size]            format an image, first creating it at size
+                                 bytes (K, M and G suffixes work) if given
+  ls <image> [path]              list a directory
+  put <image> <file|dir> [path]  copy host files in, directories recursively
+  get <image> <path> [dest]      copy a file or directory out (- for stdout)
+  mkdir <image> <path>           create a directory
+  rm [-r] <image> <path>         delete a file, or a directory with -r
+  fsck [-n] <image>              check and repair (-n: check only)
+
+  -v shows the filesystem's log messages";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("rostfs: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let (flags, args): (Vec<&str>, Vec<&str>) =
+        args.iter().map(String::as_str).partition(|arg| arg.len() > 1 && arg.starts_with('-'));
+    let flag = |name: &str| flags.contains(&name);
+    if let Some(unknown) = flags.iter().find(|f| !["-v", "-r", "-n"].contains(f)) {
+        return Err(format!("unknown option {}\n{}", unknown, USAGE));
+    }
+    kernel::VERBOSE.store(flag("-v"), Ordering::Relaxed);
+
+    let (command, image, rest) = match args.as_slice() {
+        [command, image, rest @ ..] => (*command, *image, rest),
+        _ => return Err(String::from(USAGE)),
+    };
+    match (command, rest) {
+        ("mkfs", size) if size.len() <= 1 => mkfs(image, size.first().copied()),
+        ("fsck", []) => fsck(image, flag("-n")),
+        (_, _) => {
+            let mut disk = FileDisk::open(image, true)?;
+            let mut fs = SimpleFilesystem::mount(&mut disk)?;
+            match (command, rest) {
+                ("ls", path) if path.len() <= 1 => ls(&fs, &mut disk, path.first().copied().unwrap_or("/"))?,
+                ("put", [source]) => put(&mut fs, &mut disk, Path::new(source), "/")?,
+                ("put", [source, dest]) => put(&mut fs, &mut disk, Path::new(source), dest)?,
+                ("get", [path]) => get(&fs, &mut disk, path, None)?,
+                ("get", [path, dest]) => get(&fs, &mut disk, path, Some(dest))?,
+                ("mkdir", [path]) => fs.create_dir(&mut disk, volume_path(path))?,
+                ("rm", [path]) => remove(&mut fs, &mut disk, volume_path(path), flag("-r"))?,
+                _ => return Err(String::from(USAGE)),
+            }
+            disk.close()
+        }
+    }
+}
+
+/// Path on the volume for a path given on the command line ("/" is the root)
+fn volume_path(path: &str) -> &str {
+    path.trim_matches('/')
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() { String::from(name) } else { format!("{}/{}", dir, name) }
+}
+
+/// Parse a size such as 1048576, 512K, 64M or 2G
+fn parse_size(text: &str) -> Result<u64, String> {
+    let (digits, unit) = match text.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((at, _)) => text.split_at(at),
+        None => (text, ""),
+    };
+    let shift = match unit.to_ascii_uppercase().as_str() {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        _ => return Err(format!("bad size {}", text)),
+    };
+    digits.parse::<u64>().map(|n| n << shift).map_err(|_| format!("bad size {}", text))
+}
+
+fn mkfs(image: &str, size: Option<&str>) -> Result<(), String> {
+    if let Some(size) = size {
+        // A fresh, zeroed file, so nothing from an old image is left behind
+        let bytes = parse_size(size)?;
+        let file = std::fs::File::create(image).map_err(|e| format!("{}: {}", image, e))?;
+        file.set_len(bytes).map_err(|e| format!("{}: {}", image, e))?;
+    }
+    let mut disk = FileDisk::open(image, true)?;
+    SimpleFilesystem::format(&mut disk)?;
+    disk.close()
+}
+
+fn ls(fs: &SimpleFilesystem, disk: &mut FileDisk, path: &str) -> Result<(), String> {
+    for file in fs.list_dir(disk, volume_path(path))? {
+        let (kind, suffix) = if file.is_dir { ('d', "/") } else { ('-', "") };
+        println!("{}{} {:>10}  {}{}", kind, file.attribute_string(), file.size, file.name, suffix);
+    }
+    Ok(())
+}
+
+/// Copy `source` to `dest` on the volume, or into it if it's a directory
+fn put(fs: &mut SimpleFilesystem, disk: &mut FileDisk, source: &Path, dest: &str) -> Result<(), String> {
+    let dest = volume_path(dest);
+    let target = match fs.stat(disk, dest) {
+        Ok(info) if info.is_dir => join(dest, host_name(source)?),
+        _ => String::from(dest),
+    };
+    copy_in(fs, disk, source, &target)
+}
+
+fn host_name(path: &Path) -> Result<&str, String> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| format!("{}: no usable file name", path.display()))
+}
+
+fn copy_in(fs: &mut SimpleFilesystem, disk: &mut FileDisk, source: &Path, target: &str) -> Result<(), String> {
+    let exists = fs.stat(disk, target).is_ok();
+    if source.is_dir() {
+        if !exists {
+            fs.create_dir(disk, target)?;
+        }
+        // Sorted, so the same tree always gives the same image
+        let mut entries = std::fs::read_dir(source)
+            .and_then(|dir| dir.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| format!("{}: {}", source.display(), e))?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let path = entry.path();
+            copy_in(fs, disk, &path, &join(target, host_name(&path)?))?;
+        }
+        return Ok(());
+    }
+
+    let data = std::fs::read(source).map_err(|e| format!("{}: {}", source.display(), e))?;
+    if data.len() > u32::MAX as usize {
+        return Err(format!("{}: too large for SimpleFS", source.display()));
+    }
+    if !exists {
+        fs.create_file(disk, target, 0)?;
+    }
+    fs.write_file(disk, target, &data).map_err(|e| format!("{}: {}", target, e))
+}
+
+fn get(fs: &SimpleFilesystem, disk: &mut FileDisk, path: &str, dest: Option<&str>) -> Result<(), String> {
+    let path = volume_path(path);
+    if dest == Some("-") {
+        use std::io::Write;
+        let data = read_file(fs, disk, path)?;
+        return std::io::stdout().write_all(&data).map_err(|e| e.to_string());
+    }
+
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let dest = match dest {
+        Some(dest) if Path::new(dest).is_dir() && !name.is_empty() => Path::new(dest).join(name),
+        Some(dest) => Path::new(dest).to_path_buf(),
+        None if !name.is_empty() => Path::new(name).to_path_buf(),
+        None => return Err(String::from("give a destination for the root directory")),
+    };
+    copy_out(fs, disk, path, &dest)
+}
+
+fn read_file(fs: &SimpleFilesystem, disk: &mut FileDisk, path: &str) -> Result<Vec<u8>, String> {
+    let mut data = vec![0u8; fs.stat(disk, path)?.size as usize];
+    let size = fs.read_file(disk, path, &mut data)?;
+    data.truncate(size);
+    Ok(data)
+}
+
+fn copy_out(fs: &SimpleFilesystem, disk: &mut FileDisk, path: &str, dest: &Path) -> Result<(), String> {
+    let host_error = |e: std::io::Error| format!("{}: {}", dest.display(), e);
+    if !fs.stat(disk, path)?.is_dir {
+        return std::fs::write(dest, read_file(fs, disk, path)?).map_err(host_error);
+    }
+    std::fs::create_dir_all(dest).map_err(host_error)?;
+    for file in fs.list_dir(disk, path)? {
+        copy_out(fs, disk, &join(path, &file.name), &dest.join(&file.name))?;
+    }
+    Ok(())
+}
+
+fn remove(fs: &mut SimpleFilesystem, disk: &mut FileDisk, path: &str, recursive: bool) -> Result<(), String> {
+    if path.is_empty() {
+        return Err(String::from("can't remove the root directory"));
+    }
+    if recursive && fs.stat(disk, path)?.is_dir {
+        for file in fs.list_dir(disk, path)? {
+            remove(fs, disk, &join(path, &file.name), true)?;
+        }
+    }
+    fs.delete_file(disk, path).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn fsck(image: &str, dry_run: bool) -> Result<(), String> {
+    // Mounting writable would repair the volume before we could list what
+    // was wrong, so look read-only first
+    let mut disk = FileDisk::open(image, false)?;
+    let problems = SimpleFilesystem::mount(&mut disk).and_then(|mut fs| fs.fsck(&mut disk, false))?;
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    if problems.is_empty() {
+        println!("{}: clean", image);
+        return Ok(());
+    }
+    if dry_run {
+        return Err(format!("{} problem(s) found", problems.len()));
+    }
+
+    let mut disk = FileDisk::open(image, true)?;
+    let mut fs = SimpleFilesystem::mount(&mut disk)?;
+    let left = fs.fsck(&mut disk, false)?;
+    disk.close()?;
+    if !left.is_empty() {
+        return Err(format!("{} problem(s) could not be repaired", left.len()));
+    }
+    println!("{} problem(s) repaired", problems.len());
+    Ok(())
+}
@@ -0,0 +1,22 @@
+// The kernel's storage code, built for the host. Modules sit at the same
+// paths as in the kernel (crate::system::...) so the shared files build
+// unchanged. Only part of it is used here, and the kernel's own lint
+// settings apply to it rather than this crate's. The cfg(test) modules are
+// here only so `make test-tools` runs their unit tests.
+#![allow(dead_code, unused_imports, static_mut_refs, clippy::all)]
+
+#[path = "../../../src/system/aes.rs"]
+pub mod aes;
+#[cfg(test)]
+#[path = "../../../src/system/archive/mod.rs"]
+pub mod archive;
+#[path = "../../../src/system/block/mod.rs"]
+pub mod block;
+#[path = "../../../src/system/crc32.rs"]
+pub mod crc32;
+pub mod fs;
+#[path = "../../../src/system/sha256.rs"]
+pub mod sha256;
+#[cfg(test)]
+#[path = "../../../src/system/transfer.rs"]
+pub mod transfer;
@@ -0,0 +1,46 @@
+// SimpleFS and the FileSystem interface it implements, shared with the kernel.
+// The other filesystems and the VFS are only built for their unit tests.
+
+#[cfg(test)]
+#[path = "../../../../src/system/fs/archivefs.rs"]
+pub mod archivefs;
+#[path = "../../../../src/system/fs/common.rs"]
+mod common;
+#[cfg(test)]
+#[path = "../../../../src/system/fs/ext2.rs"]
+pub mod ext2;
+#[cfg(test)]
+#[path = "../../../../src/system/fs/fat.rs"]
+pub mod fat;
+#[path = "../../../../src/system/fs/filesystem.rs"]
+pub mod filesystem;
+#[path = "../../../../src/system/fs/freemap.rs"]
+pub mod freemap;
+#[cfg(test)]
+#[path = "../../../../src/system/fs/iso9660.rs"]
+pub mod iso9660;
+#[path = "../../../../src/system/fs/mime.rs"]
+pub mod mime;
+#[cfg(test)]
+#[path = "../../../../src/system/fs/ninep.rs"]
+pub mod ninep;
+#[cfg(test)]
+#[path = "../../../../src/system/fs/tmpfs.rs"]
+pub mod tmpfs;
+#[cfg(test)]
+#[path = "../../../../src/system/fs/vfs.rs"]
+pub mod vfs;
+
+pub use common::*;
+pub use filesystem::SimpleFilesystem;
+#[cfg(test)]
+pub use archivefs::ArchiveFs;
+#[cfg(test)]
+pub use tmpfs::TmpFs;
+
+/// The kernel's fs::mount, as far as the VFS tests need it: they only mount
+/// SimpleFS volumes
+#[cfg(test)]
+pub fn mount(device: &mut dyn crate::system::block::BlockDevice) -> Result<Box<dyn FileSystem>, &'static str> {
+    Ok(Box::new(SimpleFilesystem::mount(device)?))
+}
@@ -0,0 +1,144 @@
+#!/usr/bin/env python3
+"""Talk to a running rOSt over its virtio-console ports.
+
+`make run` connects each port to a Unix socket under target/:
+
+    rostlink.py shell                  interactive shell (Ctrl-] to quit)
+    rostlink.py log [--json]           follow the kernel log
+    rostlink.py push LOCAL REMOTE      copy a host file into the guest
+    rostlink.py pull REMOTE [LOCAL]    copy a guest file to the host
+    rostlink.py ls REMOTE              list a guest directory
+
+REMOTE paths are absolute guest paths such as /disk0/notes.txt or /tmp/x.
+Only the Python standard library is needed.
+"""
+
+import json
+import os
+import select
+import socket
+import sys
+
+SOCKET_DIR = os.environ.get("ROSTLINK_DIR", os.path.join(os.path.dirname(__file__), "..", "target"))
+QUIT = b"\x1d"  # Ctrl-]
+
+
+def connect(port):
+    path = os.path.join(SOCKET_DIR, port + ".sock")
+    sock = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+    try:
+        sock.connect(path)
+    except OSError as e:
+        sys.exit(f"rostlink: can't connect to {path}: {e.strerror} (is rOSt running under `make run`?)")
+    return sock
+
+
+class Files:
+    """The rost.files protocol: one request at a time, each answered with
+    "OK <size>\\n<data>" or "ERR <message>\\n"."""
+
+    def __init__(self):
+        self.sock = connect("rost.files")
+        self.buffer = b""
+
+    def _read_line(self):
+        while b"\n" not in self.buffer:
+            self._fill()
+        line, self.buffer = self.buffer.split(b"\n", 1)
+        return line.decode()
+
+    def _read_exact(self, size):
+        while len(self.buffer) < size:
+            self._fill()
+        data, self.buffer = self.buffer[:size], self.buffer[size:]
+        return data
+
+    def _fill(self):
+        chunk = self.sock.recv(65536)
+        if not chunk:
+            sys.exit("rostlink: guest closed the connection")
+        self.buffer += chunk
+
+    def request(self, line, data=b""):
+        self.sock.sendall(line.encode() + b"\n" + data)
+        status, _, rest = self._read_line().partition(" ")
+        if status != "OK":
+            sys.exit(f"rostlink: {rest}")
+        return self._read_exact(int(rest))
+
+
+def shell():
+    import termios
+    import tty
+
+    sock = connect("rost.shell")
+    stdin = sys.stdin.fileno()
+    saved = termios.tcgetattr(stdin)
+    print("Connected to rOSt; Ctrl-] to quit.\r")
+    tty.setraw(stdin)
+    try:
+        while True:
+            ready, _, _ = select.select([sock, stdin], [], [])
+            if sock in ready:
+                data = sock.recv(4096)
+                if not data:
+                    break
+                os.write(sys.stdout.fileno(), data)
+            if stdin in ready:
+                data = os.read(stdin, 1024)
+                if QUIT in data:
+                    break
+                sock.sendall(data)
+    finally:
+        termios.tcsetattr(stdin, termios.TCSADRAIN, saved)
+        print()
+
+
+def log(as_json):
+    sock = connect("rost.log")
+    buffer = b""
+    while True:
+        data = sock.recv(4096)
+        if not data:
+            break
+        buffer += data
+        *lines, buffer = buffer.split(b"\n")
+        for line in lines:
+            if as_json:
+                print(line.decode(errors="replace"), flush=True)
+                continue
+            record = json.loads(line)
+            print(f"[{record['ms'] / 1000:10.3f}] {record['level']:5} {record['msg']}", flush=True)
+
+
+def main(args):
+    if not args or args[0] in ("-h", "--help"):
+        print(__doc__.strip())
+        return
+    command, args = args[0], args[1:]
+    if command == "shell" and not args:
+        shell()
+    elif command == "log" and args in ([], ["--json"]):
+        try:
+            log(args == ["--json"])
+        except KeyboardInterrupt:
+            pass
+    elif command == "push" and len(args) == 2:
+        with open(args[0], "rb") as f:
+            data = f.read()
+        Files().request(f"PUT {len(data)} {args[1]}", data)
+        print(f"{args[0]} -> {args[1]} ({len(data)} bytes)")
+    elif command == "pull" and len(args) in (1, 2):
+        local = args[1] if len(args) == 2 else os.path.basename(args[0])
+        data = Files().request(f"GET {args[0]}")
+        with open(local, "wb") as f:
+            f.write(data)
+        print(f"{args[0]} -> {local} ({len(data)} bytes)")
+    elif command == "ls" and len(args) == 1:
+        sys.stdout.write(Files().request(f"LS {args[0]}").decode())
+    else:
+        sys.exit(f"rostlink: bad arguments; see {sys.argv[0]} --help")
+
+
+if __name__ == "__main__":
+    main(sys.argv[1:])